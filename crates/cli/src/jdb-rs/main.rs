@@ -0,0 +1,278 @@
+//
+// jdb-rs stacks [--out DIR] [--collapse] [--depth N] [--include PATTERN]... [--exclude PATTERN]...
+//                [--links TEMPLATE [--link-package PKG]...] [--label THREAD=LABEL]... [--json]
+//                HOST:PORT...
+//
+// Attaches to every target at once, takes a thread dump of each (see
+// JdwpJavaVirtualMachine::capture_thread_dump()) and writes it to DIR (by default the working
+// directory) as stacks-HOST-PORT.txt. Targets which can't be reached are reported, and don't stop
+// the others being written. The exit status is 1 if any target failed. --collapse leaves out the
+// frames of lambdas' generated classes, method handles and reflection (see FrameInfo::is_hidden()).
+// --depth only reads the top N frames of each thread (see capture_shallow_thread_dump()), which
+// is much quicker for targets with thousands of threads. --include and --exclude, which can be
+// given more than once, pick threads by name with '*' globs (see ThreadFilter), e.g.
+// --include 'http-nio-*'; the threads left out aren't walked at all. --links follows each frame
+// with a link to its code, made from TEMPLATE (see source_link.rs), e.g.
+// 'https://github.com/example/app/blob/main/src/main/java/{package}/{file}#L{line}', and with
+// --link-package, which can be given more than once, only links frames in that package or the
+// packages under it, e.g. --link-package com.example, leaving out the JDK and libraries. --label,
+// which can be given more than once, writes LABEL in brackets after the name of each thread
+// called THREAD (see annotation.rs), e.g. --label 'main=stuck since 10:02'. --json
+// saves each dump as stacks-HOST-PORT.json instead (see libjdb::saved), to be loaded and looked at
+// somewhere else later.
+//
+// jdb-rs histogram [--json FILE] DUMP|HOST:PORT
+//
+// Prints the classes taking the most space in a heap dump, or in a live target (see
+// JavaVirtualMachine::class_histogram()). --json saves the whole histogram to FILE instead (see
+// libjdb::saved), e.g. to compare with one taken later.
+//
+// jdb-rs timeline [--json FILE] DUMP...
+//
+// Compares heap dumps of the same process, oldest first (see hprof::heap_timeline()), and prints
+// the classes which grew in every one and when the largest objects in the last one appeared.
+// --json saves the timeline to FILE instead.
+//
+// jdb-rs validate DUMP...
+//
+// Checks each heap dump's structure without parsing it (see HprofJavaVirtualMachine::validate()),
+// which is much quicker, and says whether it's intact and if not how much of it is usable. The
+// exit status is 1 if any dump isn't intact.
+//
+// jdb-rs repl HOST:PORT
+//
+// An interactive prompt for looking at threads, frames and variables and setting breakpoints
+// (see repl.rs).
+//
+
+mod repl;
+
+use std::env;
+use std::path::Path;
+use std::process;
+
+use libjdb::annotation::Annotations;
+use libjdb::fleet::{capture_filtered_thread_dumps, write_thread_dumps};
+use libjdb::hprof::heap_timeline;
+use libjdb::model::JavaVirtualMachine;
+use libjdb::report::{self, DirectorySink};
+use libjdb::saved;
+use libjdb::source_link::SourceLinks;
+use libjdb::thread_filter::ThreadFilter;
+use libjdb::{attach_live, open_hprof, open_hprofs, validate_hprof};
+
+const USAGE: &str = "usage: jdb-rs stacks [--out DIR] [--collapse] [--depth N]
+                     [--include PATTERN]... [--exclude PATTERN]...
+                     [--links TEMPLATE [--link-package PKG]...] [--label THREAD=LABEL]...
+                     [--json] HOST:PORT...
+       jdb-rs histogram [--json FILE] DUMP|HOST:PORT
+       jdb-rs timeline [--json FILE] DUMP...
+       jdb-rs validate DUMP...
+       jdb-rs repl HOST:PORT";
+
+// How many of the largest objects in the last dump the timeline follows
+const TIMELINE_SUSPECTS: usize = 20;
+
+// How many classes a printed histogram has
+const HISTOGRAM_CLASSES: usize = 50;
+
+fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+    let ok = match args.first().map(String::as_str) {
+        Some("stacks") => stacks(&args[1..]),
+        Some("histogram") => histogram(&args[1..]),
+        Some("timeline") => timeline(&args[1..]),
+        Some("validate") if args.len() > 1 => validate(&args[1..]),
+        Some("repl") if args.len() == 2 => repl::repl(&args[1]),
+        _ => {
+            eprintln!("{}", USAGE);
+            process::exit(2);
+        }
+    };
+    if !ok {
+        process::exit(1);
+    }
+}
+
+fn stacks(args: &[String]) -> bool {
+    let mut dir = ".".to_string();
+    let mut collapse = false;
+    let mut json = false;
+    let mut depth = None;
+    let mut filter = ThreadFilter::all();
+    let mut annotations = Annotations::new();
+    let mut links: Option<SourceLinks> = None;
+    let mut link_packages = vec![];
+    let mut addresses = vec![];
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--out" => match args.next() {
+                Some(out) => dir = out.clone(),
+                None => {
+                    eprintln!("{}", USAGE);
+                    process::exit(2);
+                }
+            },
+            "--collapse" => collapse = true,
+            "--json" => json = true,
+            "--depth" => match args.next().and_then(|n| n.parse().ok()) {
+                Some(n) => depth = Some(n),
+                None => {
+                    eprintln!("{}", USAGE);
+                    process::exit(2);
+                }
+            },
+            "--links" => match args.next() {
+                Some(template) => links = Some(SourceLinks::new(template)),
+                None => {
+                    eprintln!("{}", USAGE);
+                    process::exit(2);
+                }
+            },
+            "--link-package" => match args.next() {
+                Some(package) => link_packages.push(package.clone()),
+                None => {
+                    eprintln!("{}", USAGE);
+                    process::exit(2);
+                }
+            },
+            "--label" => match args.next().and_then(|label| label.split_once('=')) {
+                Some((thread, label)) => annotations.label_thread(thread, label),
+                None => {
+                    eprintln!("{}", USAGE);
+                    process::exit(2);
+                }
+            },
+            "--include" | "--exclude" => match args.next() {
+                Some(pattern) if arg == "--include" => filter.include.push(pattern.clone()),
+                Some(pattern) => filter.exclude.push(pattern.clone()),
+                None => {
+                    eprintln!("{}", USAGE);
+                    process::exit(2);
+                }
+            },
+            _ => addresses.push(arg.clone()),
+        }
+    }
+    if addresses.is_empty() || (links.is_none() && !link_packages.is_empty()) {
+        eprintln!("{}", USAGE);
+        process::exit(2);
+    }
+    if let Some(mut links) = links {
+        links.packages = link_packages;
+        annotations.set_source_links(Some(links));
+    }
+
+    let sink = DirectorySink::new(dir);
+    let targets = capture_filtered_thread_dumps(&addresses, depth, &filter);
+    let extension = if json { "json" } else { "txt" };
+    let written = write_thread_dumps(targets, &sink, extension, |dump, out| {
+        let collapsed;
+        let dump = if collapse {
+            collapsed = dump.without_hidden_frames();
+            &collapsed
+        } else {
+            dump
+        };
+        if json {
+            saved::save(dump, out)
+        } else {
+            report::write_captured_thread_dump_annotated(dump, &annotations, out)
+        }
+    });
+    let mut ok = true;
+    for (address, written) in written {
+        match written {
+            Ok(path) => println!("{}: {}", address, path),
+            Err(e) => {
+                eprintln!("{}: {}", address, e);
+                ok = false;
+            }
+        }
+    }
+    ok
+}
+
+// The path after --json, if it's first, and the rest of the arguments
+fn json_option(args: &[String]) -> (Option<&str>, &[String]) {
+    match args {
+        [flag, rest @ ..] if flag == "--json" => match rest.split_first() {
+            Some((path, rest)) => (Some(path.as_str()), rest),
+            None => {
+                eprintln!("{}", USAGE);
+                process::exit(2);
+            }
+        },
+        _ => (None, args),
+    }
+}
+
+fn histogram(args: &[String]) -> bool {
+    let (json, target) = match json_option(args) {
+        (json, [target]) => (json, target),
+        _ => {
+            eprintln!("{}", USAGE);
+            process::exit(2);
+        }
+    };
+    let histogram = if Path::new(target).is_file() {
+        open_hprof(target).and_then(|jvm| jvm.class_histogram())
+    } else {
+        attach_live(target.as_str()).and_then(|jvm| jvm.class_histogram())
+    };
+    let written = histogram.and_then(|histogram| match json {
+        Some(path) => saved::save_file(&histogram, path),
+        None => report::write_histogram(
+            &histogram,
+            Some(HISTOGRAM_CLASSES),
+            &mut std::io::stdout().lock(),
+        ),
+    });
+    if let Err(e) = written {
+        eprintln!("{}: {}", target, e);
+        return false;
+    }
+    true
+}
+
+fn timeline(args: &[String]) -> bool {
+    let (json, paths) = json_option(args);
+    if paths.is_empty() {
+        eprintln!("{}", USAGE);
+        process::exit(2);
+    }
+    // The dumps are of the same process, so they share their strings
+    let dumps = match open_hprofs(paths) {
+        Ok(dumps) => dumps,
+        Err(e) => {
+            eprintln!("{}", e);
+            return false;
+        }
+    };
+    let written = heap_timeline(&dumps, TIMELINE_SUSPECTS).and_then(|timeline| match json {
+        Some(path) => saved::save_file(&timeline, path),
+        None => report::write_heap_timeline(&timeline, None, &mut std::io::stdout().lock()),
+    });
+    if let Err(e) = written {
+        eprintln!("{}", e);
+        return false;
+    }
+    true
+}
+
+fn validate(paths: &[String]) -> bool {
+    let mut ok = true;
+    for path in paths {
+        let written = validate_hprof(path).and_then(|validation| {
+            ok &= validation.is_intact();
+            print!("{}: ", path);
+            report::write_validation_report(&validation, &mut std::io::stdout().lock())
+        });
+        if let Err(e) = written {
+            eprintln!("{}: {}", path, e);
+            ok = false;
+        }
+    }
+    ok
+}
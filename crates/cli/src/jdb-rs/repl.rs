@@ -0,0 +1,383 @@
+//
+// jdb-rs repl HOST:PORT
+//
+// An interactive prompt for poking at a live target, like jdb's:
+//
+//   threads              list the threads, numbered
+//   frames [THREAD]      show a thread's stack, by number or name, and make it the current one
+//   frame N              make frame N of the current thread the current frame
+//   locals               the current frame's local variables
+//   print EXPR           evaluate an expression in the current frame (see expr.rs)
+//   break CLASS:LINE     set a breakpoint, e.g. com.example.Worker:42 or Worker.java:42
+//   suspend              suspend every thread, so that their stacks can be looked at
+//   cont                 resume every thread and wait for the next breakpoint
+//   quit
+//
+// Tab completes commands, class names after break, and thread names after frames. Lines are
+// kept in ~/.jdb_rs_history between runs.
+//
+
+use std::env;
+use std::io::{Error, ErrorKind, Result};
+use std::path::PathBuf;
+
+use rustyline::completion::Completer;
+use rustyline::error::ReadlineError;
+use rustyline::highlight::Highlighter;
+use rustyline::hint::Hinter;
+use rustyline::history::DefaultHistory;
+use rustyline::validate::Validator;
+use rustyline::{Context, Editor, Helper};
+
+use libjdb::jdwp::{BreakpointSpec, JdwpStackFrame, JdwpThreadReference, Session, SessionEvent};
+use libjdb::model::{JavaVirtualMachine, Location, ObjectReference, ReferenceType};
+use libjdb::model::{StackFrame, ThreadReference, TypeComponent};
+
+const COMMANDS: &[&str] = &[
+    "break", "cont", "frame", "frames", "help", "locals", "print", "quit", "suspend", "threads",
+];
+
+const HELP: &str = "threads              list the threads, numbered
+frames [THREAD]      show a thread's stack, by number or name, and make it the current one
+frame N              make frame N of the current thread the current frame
+locals               the current frame's local variables
+print EXPR           evaluate an expression in the current frame
+break CLASS:LINE     set a breakpoint, e.g. com.example.Worker:42 or Worker.java:42
+suspend              suspend every thread
+cont                 resume every thread and wait for the next breakpoint
+quit";
+
+// What tab completes, refreshed whenever the target may have changed
+#[derive(Default)]
+struct Completions {
+    classes: Vec<String>,
+    threads: Vec<String>,
+}
+
+impl Completer for Completions {
+    type Candidate = String;
+
+    fn complete(
+        &self,
+        line: &str,
+        pos: usize,
+        _ctx: &Context<'_>,
+    ) -> rustyline::Result<(usize, Vec<String>)> {
+        let line = &line[..pos];
+        let (start, word) = match line.rfind(' ') {
+            Some(i) => (i + 1, &line[i + 1..]),
+            None => (0, line),
+        };
+        let command = line.split_whitespace().next().unwrap_or("");
+        let candidates: Vec<String> = if start == 0 {
+            COMMANDS.iter().map(|c| c.to_string()).collect()
+        } else if command == "break" {
+            self.classes.clone()
+        } else if command == "frames" {
+            self.threads.clone()
+        } else {
+            vec![]
+        };
+        Ok((
+            start,
+            candidates
+                .into_iter()
+                .filter(|c| c.starts_with(word))
+                .collect(),
+        ))
+    }
+}
+
+impl Hinter for Completions {
+    type Hint = String;
+}
+
+impl Highlighter for Completions {}
+
+impl Validator for Completions {}
+
+impl Helper for Completions {}
+
+struct Repl {
+    session: Session,
+    // The thread and frame which locals and print look at, by thread ID and depth
+    thread: Option<u64>,
+    frame: usize,
+}
+
+// Runs until the user quits, or the input ends. False if attaching failed.
+pub fn repl(address: &str) -> bool {
+    let session = match Session::attach(address) {
+        Ok(session) => session,
+        Err(e) => {
+            eprintln!("{}: {}", address, e);
+            return false;
+        }
+    };
+    let mut editor: Editor<Completions, DefaultHistory> = match Editor::new() {
+        Ok(editor) => editor,
+        Err(e) => {
+            eprintln!("{}", e);
+            return false;
+        }
+    };
+    editor.set_helper(Some(Completions::default()));
+    let history = env::var_os("HOME").map(|home| PathBuf::from(home).join(".jdb_rs_history"));
+    if let Some(history) = &history {
+        // There's none the first time
+        let _ = editor.load_history(history);
+    }
+
+    let mut repl = Repl {
+        session,
+        thread: None,
+        frame: 0,
+    };
+    repl.refresh_completions(&mut editor);
+    loop {
+        let line = match editor.readline("> ") {
+            Ok(line) => line,
+            // ^C abandons the line, as in a shell
+            Err(ReadlineError::Interrupted) => continue,
+            Err(ReadlineError::Eof) => break,
+            Err(e) => {
+                eprintln!("{}", e);
+                break;
+            }
+        };
+        let line = line.trim();
+        if line.is_empty() {
+            continue;
+        }
+        let _ = editor.add_history_entry(line);
+        let (command, arg) = match line.split_once(' ') {
+            Some((command, arg)) => (command, arg.trim()),
+            None => (line, ""),
+        };
+        if command == "quit" || command == "exit" {
+            break;
+        }
+        if let Err(e) = repl.run(command, arg) {
+            eprintln!("{}", e);
+        }
+        if !repl.session.is_attached() {
+            break;
+        }
+        if command == "threads" || command == "cont" {
+            repl.refresh_completions(&mut editor);
+        }
+    }
+
+    if let Some(history) = &history {
+        let _ = editor.save_history(history);
+    }
+    let _ = repl.session.detach();
+    true
+}
+
+impl Repl {
+    fn run(&mut self, command: &str, arg: &str) -> Result<()> {
+        match command {
+            "threads" => self.threads(),
+            "frames" => self.frames(arg),
+            "frame" => {
+                let depth = arg.parse().map_err(|_| usage("frame N"))?;
+                self.frame(depth)?;
+                self.frame = depth;
+                Ok(())
+            }
+            "locals" => self.locals(),
+            "print" if !arg.is_empty() => self.print(arg),
+            "print" => Err(usage("print EXPR")),
+            "break" => self.set_breakpoint(arg),
+            "suspend" => self.session.jvm()?.suspend(),
+            "cont" => self.cont(),
+            "help" => {
+                println!("{}", HELP);
+                Ok(())
+            }
+            _ => Err(Error::new(
+                ErrorKind::InvalidInput,
+                format!("Unknown command {:?} (try help)", command),
+            )),
+        }
+    }
+
+    fn threads(&self) -> Result<()> {
+        for (i, thread) in self.session.jvm()?.all_threads_vec()?.iter().enumerate() {
+            let current = if Some(thread.unique_id()?) == self.thread {
+                "*"
+            } else {
+                " "
+            };
+            println!("{}{:>3} {}", current, i, thread.name()?);
+        }
+        Ok(())
+    }
+
+    fn frames(&mut self, arg: &str) -> Result<()> {
+        let thread = if arg.is_empty() {
+            self.current_thread()?
+        } else {
+            let mut threads = self.session.jvm()?.all_threads_vec()?;
+            let i = match arg.parse::<usize>() {
+                Ok(i) if i < threads.len() => i,
+                _ => threads
+                    .iter()
+                    .position(|t| t.name().is_ok_and(|name| name == arg))
+                    .ok_or_else(|| not_found(&format!("No thread {:?}", arg)))?,
+            };
+            threads.swap_remove(i)
+        };
+        let frames = thread.frames_vec()?;
+        self.thread = Some(thread.unique_id()?);
+        self.frame = 0;
+        for (i, frame) in frames.iter().enumerate() {
+            println!("{:>3} {}", i, describe_location(&frame.location()?)?);
+        }
+        Ok(())
+    }
+
+    fn locals(&self) -> Result<()> {
+        let frame = self.frame(self.frame)?;
+        let variables = frame.visible_variables()?;
+        let values = variables
+            .iter()
+            .map(|v| v.value())
+            .collect::<Result<Vec<_>>>()?;
+        for (variable, value) in variables.iter().zip(self.session.render_all(&values)?) {
+            println!("{} = {}", variable.name(), value);
+        }
+        Ok(())
+    }
+
+    fn print(&self, expr: &str) -> Result<()> {
+        let value = self.frame(self.frame)?.evaluate(expr)?;
+        println!("{}", self.session.render(&value)?);
+        Ok(())
+    }
+
+    fn set_breakpoint(&mut self, arg: &str) -> Result<()> {
+        let spec = parse_breakpoint(arg)?;
+        let description = format!("{}:{}", spec.source_file, spec.line);
+        self.session.add_breakpoint(spec)?;
+        println!("Breakpoint set at {}", description);
+        Ok(())
+    }
+
+    fn cont(&mut self) -> Result<()> {
+        self.session.jvm()?.resume()?;
+        self.thread = None;
+        self.frame = 0;
+        // Session::next_event() only returns breakpoints here, since there are no watches
+        match self.session.next_event(None)? {
+            Some(SessionEvent::Breakpoint {
+                thread, location, ..
+            }) => {
+                println!(
+                    "Breakpoint hit in thread {:?} at {}",
+                    thread.name()?,
+                    describe_location(&location)?
+                );
+                self.thread = Some(thread.unique_id()?);
+            }
+            Some(SessionEvent::VmDeath) => println!("The target has exited"),
+            Some(SessionEvent::Watch { .. }) | None => {}
+        }
+        Ok(())
+    }
+
+    fn current_thread(&self) -> Result<JdwpThreadReference> {
+        let thread_id = self
+            .thread
+            .ok_or_else(|| not_found("No current thread (see frames)"))?;
+        for thread in self.session.jvm()?.all_threads() {
+            let thread = thread?;
+            if thread.unique_id()? == thread_id {
+                return Ok(thread);
+            }
+        }
+        Err(not_found("The current thread has finished"))
+    }
+
+    // Frames are only valid until their thread is resumed, so they're fetched afresh each time
+    fn frame(&self, depth: usize) -> Result<JdwpStackFrame> {
+        let mut frames = self.current_thread()?.frames_vec()?;
+        if depth >= frames.len() {
+            return Err(not_found(&format!(
+                "No frame {} (the thread has {})",
+                depth,
+                frames.len()
+            )));
+        }
+        Ok(frames.swap_remove(depth))
+    }
+
+    fn refresh_completions(&self, editor: &mut Editor<Completions, DefaultHistory>) {
+        let jvm = match self.session.jvm() {
+            Ok(jvm) => jvm,
+            Err(_) => return,
+        };
+        let threads = jvm
+            .all_threads_vec()
+            .and_then(|threads| threads.iter().map(|t| t.name()).collect());
+        if let Some(completions) = editor.helper_mut() {
+            completions.classes = jvm.class_names().unwrap_or_default();
+            completions.threads = threads.unwrap_or_default();
+        }
+    }
+}
+
+// CLASS:LINE or FILE:LINE. A class covers its inner classes, and anything else in its source
+// file, since that's where the line is looked for.
+fn parse_breakpoint(arg: &str) -> Result<BreakpointSpec> {
+    let (place, line) = arg
+        .rsplit_once(':')
+        .ok_or_else(|| usage("break CLASS:LINE"))?;
+    let line = line.parse().map_err(|_| usage("break CLASS:LINE"))?;
+    let (class_pattern, source_file) = if place.ends_with(".java") || place.ends_with(".kt") {
+        ("*".to_string(), place.to_string())
+    } else {
+        let outer = place.split('$').next().unwrap_or(place);
+        match outer.rsplit_once('.') {
+            Some((package, name)) => (format!("{}.*", package), format!("{}.java", name)),
+            None => ("*".to_string(), format!("{}.java", outer)),
+        }
+    };
+    Ok(BreakpointSpec {
+        class_pattern: class_pattern
+            .parse()
+            .map_err(|e| Error::new(ErrorKind::InvalidInput, format!("{}", e)))?,
+        source_file,
+        line,
+    })
+}
+
+// e.g. com.example.Worker.run(Worker.java:42)
+fn describe_location<L: Location<libjdb::jdwp::JdwpJavaVirtualMachine>>(
+    location: &L,
+) -> Result<String> {
+    let class = location.declaring_type()?;
+    let file = class
+        .source_name()?
+        .unwrap_or_else(|| "Unknown Source".to_string());
+    let line = match location.line_number()? {
+        Some(line) => format!(":{}", line),
+        None => String::new(),
+    };
+    Ok(format!(
+        "{}.{}({}{})",
+        class.name()?,
+        location.method()?.name()?,
+        file,
+        line
+    ))
+}
+
+fn usage(usage: &str) -> Error {
+    Error::new(ErrorKind::InvalidInput, format!("usage: {}", usage))
+}
+
+fn not_found(msg: &str) -> Error {
+    Error::new(ErrorKind::NotFound, msg.to_string())
+}
@@ -0,0 +1,3282 @@
+//
+// HPROF Reference Sources:
+//
+// [1] There is actual documentation on the HPROF format in the
+//     docs of OpenJDK version 6 to 7:
+//     http://hg.openjdk.java.net/jdk6/jdk6/jdk/raw-file/tip/src/share/demo/jvmti/hprof/manual.html
+//
+// [2] For OpenJDK 8 there is a header file provider under
+//     src/share/demo/jvmti/hprof/hprof_b_spec.h
+//
+// [3] Since the above can get ouf of date we look for updates
+//     in the format from the actual source code of the latest
+//     OpenJDK (version 9 to 14):
+//     https://github.com/openjdk/jdk/blob/master/src/hotspot/share/services/heapDumper.cpp
+//
+// Assumptions:
+// - For now we assume that all identifier sizes are 8 bytes (u64). Dumps with any other size,
+//   i.e. those from 32-bit JVMs, are rejected when they're opened.
+//
+// XXX - Add other resources JVM and JNI spec.
+//
+
+use byteorder::{BigEndian, ReadBytesExt};
+use num_traits::cast::FromPrimitive;
+
+use std::cell::{Cell, OnceCell, RefCell};
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{BufRead, BufReader, Error, ErrorKind, Read, Result, Seek, SeekFrom};
+use std::mem;
+use std::rc::Rc;
+
+use graph::HeapGraph;
+use heap::{HeapObject, ObjectHeader};
+use symbols::{Symbol, Symbols};
+
+use crate::model::{
+    DeclaredField, Field, Items, JavaVirtualMachine, Location, Method, Modifiers, ObjectReference,
+    ReferenceType, StackFrame, ThreadReference, TypeComponent, Value,
+};
+use crate::mutf8;
+use crate::names;
+use crate::pattern::ClassPattern;
+use crate::snapshot::Histogram;
+
+mod class_loaders;
+mod collections;
+mod direct_buffers;
+mod dominators;
+mod file_descriptors;
+mod finalization;
+mod graph;
+mod heap;
+mod incremental;
+mod index;
+mod object_set;
+mod overhead;
+mod referrers;
+mod root_path;
+mod size;
+mod strings;
+mod symbols;
+mod thread_locals;
+mod timeline;
+mod validate;
+mod views;
+
+pub use class_loaders::{ClassLoaderReport, ClassLoaderReportEntry, LoaderRetention};
+pub use collections::{CollectionWaste, CollectionWasteReport, CollectionWasteSummary};
+pub use direct_buffers::{
+    DirectBufferEntry, DirectBufferGroup, DirectBufferKind, DirectBufferReport,
+};
+pub use dominators::{DominatorRow, DominatorTree};
+pub use file_descriptors::{
+    FileDescriptorEntry, FileDescriptorGroup, FileDescriptorReport, ResourceKind,
+};
+pub use finalization::{FinalizationEntry, FinalizationKind, FinalizationReport};
+pub use incremental::{HprofCheckpoint, IncrementalHprof};
+pub use object_set::ObjectSet;
+pub use overhead::{ClassOverhead, OverheadReport};
+pub use referrers::ReferrerIndex;
+pub use root_path::{PathEdge, PathObject, Reference, RootPath};
+pub use size::SizeModel;
+pub use strings::{StringMatch, StringSearchReport, SymbolMatch};
+pub use symbols::StringCache;
+pub use thread_locals::{ThreadLocalReport, ThreadLocalReportEntry, ThreadLocalValueClass};
+pub use timeline::{heap_timeline, ClassCurve, HeapTimeline, SuspectHistory, TimelineDump};
+pub use validate::{ValidationProblem, ValidationReport};
+pub use views::{ClassView, ObjectView, Summary};
+
+#[derive(Debug, Eq, PartialEq, FromPrimitive)]
+#[repr(u8)]
+enum RecordTag {
+    Utf8String = 0x01,
+    LoadClass = 0x02,
+    UnloadClass = 0x03,
+    StackFrame = 0x04,
+    StackTrace = 0x05,
+    AllocSites = 0x06,
+    HeapSummary = 0x07,
+    StartThread = 0x0A,
+    EndThread = 0x0B,
+    HeapDump = 0x0C,
+    CpuSamples = 0x0D,
+    ControlSettings = 0x0E,
+
+    // 1.0.2 Record Tags
+    HeapDumpSegment = 0x1C,
+    HeapDumpEnd = 0x2C,
+}
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash, FromPrimitive)]
+#[repr(u8)]
+enum FieldTag {
+    ArrayObject = 0x01,
+    NormalObject = 0x02,
+    Boolean = 0x04,
+    Char = 0x05,
+    Float = 0x06,
+    Double = 0x07,
+    Byte = 0x08,
+    Short = 0x09,
+    Int = 0x0A,
+    Long = 0x0B,
+}
+
+#[derive(Debug, Eq, PartialEq, FromPrimitive)]
+#[repr(u8)]
+enum DataDumpSubRecordTag {
+    RootUnknown = 0xFF,
+    JniGlobal = 0x01,
+    JniLocal = 0x02,
+    JavaFrame = 0x03,
+    NativeStack = 0x04,
+    StickyClass = 0x05,
+    ThreadBlock = 0x06,
+    MonitorUsed = 0x07,
+    ThreadObject = 0x08,
+    ClassDump = 0x20,
+    InstanceDump = 0x21,
+    ObjectArrayDump = 0x22,
+    PrimitiveArrayDump = 0x23,
+}
+
+#[allow(dead_code)]
+#[derive(Debug)]
+struct Header {
+    format: String,
+    identifier_size: u32,
+    high_word_ms: u32,
+    low_word_ms: u32,
+}
+
+// The format string is assumed to be one of the 19 byte 'JAVA PROFILE 1.0.x' ones
+const HEADER_SIZE: u64 = 19 + 3 * 4;
+
+fn parse_header<R: BufRead>(reader: &mut R) -> Result<Header> {
+    let mut format_buf = [0u8; 19];
+    let mut u32_buf = [0u8; 4];
+
+    reader.read_exact(&mut format_buf)?;
+    let format = String::from_utf8_lossy(&format_buf).to_string();
+    reader.read_exact(&mut u32_buf)?;
+    let identifier_size = u32::from_be_bytes(u32_buf);
+    // Everything after the header assumes eight byte IDs
+    if identifier_size != 8 {
+        return Err(Error::new(
+            ErrorKind::InvalidData,
+            format!("{}-byte identifiers aren't supported", identifier_size),
+        ));
+    }
+    reader.read_exact(&mut u32_buf)?;
+    let high_word_ms = u32::from_be_bytes(u32_buf);
+    reader.read_exact(&mut u32_buf)?;
+    let low_word_ms = u32::from_be_bytes(u32_buf);
+
+    Ok(Header {
+        format,
+        identifier_size,
+        high_word_ms,
+        low_word_ms,
+    })
+}
+
+#[allow(dead_code)]
+#[derive(Debug)]
+struct Record {
+    // None if it's one we don't know about, which is skipped
+    tag: Option<RecordTag>,
+    time: u32,
+    bytes: u32,
+}
+
+fn parse_record<R: Read + Seek>(parser: &mut HprofParser<R>) -> Result<Record> {
+    let record_start = parser.position;
+    let raw_tag = parser.parse_u8()?;
+    let tag: Option<RecordTag> = FromPrimitive::from_u8(raw_tag);
+    let time = parser.parse_u32()?;
+    let bytes = parser.parse_u32()?;
+
+    let tag = match tag {
+        Some(tag) => tag,
+        None => {
+            // Probably from a newer JVM. Every record has a length, so we can carry on.
+            parser.warn(false, raw_tag, false, record_start, u64::from(bytes));
+            parser.skip(u64::from(bytes))?;
+            return Ok(Record {
+                tag: None,
+                time,
+                bytes,
+            });
+        }
+    };
+    match tag {
+        RecordTag::Utf8String => {
+            // A string record starts with its ID, so one which is shorter than that is damaged
+            let length = match bytes.checked_sub(mem::size_of::<u64>() as u32) {
+                Some(length) => length,
+                None => {
+                    parser.warn(false, raw_tag, true, record_start, u64::from(bytes));
+                    parser.skip(u64::from(bytes))?;
+                    return Ok(Record {
+                        tag: None,
+                        time,
+                        bytes,
+                    });
+                }
+            };
+            let r: Utf8StringRecord = parser.parse_utf8_string_record(bytes as usize)?;
+            let offset = parser.position - length as u64;
+            parser
+                .symbols
+                .insert(r.identifier, &r.value, offset, length);
+        }
+        RecordTag::LoadClass => {
+            let r: LoadClassRecord = parser.parse_load_class_record()?;
+            parser.class_serials.insert(r.object_id, r.serial_num);
+            parser.class_tab.insert(r.serial_num, r);
+        }
+        RecordTag::UnloadClass => {
+            // TODO:
+            // These currently seem to be non-existent. Once you finish
+            // reading the rest of the dump data, if you still don't see
+            // such entries then check the C++ Dumper code to see if they
+            // are mentioned at all. You probably still want to leave the
+            // parsing code here for completeness but should be ok to
+            // leave things simplified.
+            let _r: UnloadClassRecord = parser.parse_unload_class_record()?;
+        }
+        RecordTag::StackFrame => {
+            let r: StackFrameRecord = parser.parse_stack_frame_record()?;
+            parser.frame_tab.insert(r.frame_id, r); // XXX
+        }
+        RecordTag::StackTrace => {
+            let r: StackTraceRecord = parser.parse_stack_trace_record()?;
+            parser.trace_tab.insert(r.serial_num, r);
+        }
+        RecordTag::StartThread => {
+            let r: StartThreadRecord = parser.parse_start_thread_record()?;
+            parser.thread_tab.insert(r.thread_serial_num, r);
+        }
+        RecordTag::EndThread => {
+            // The thread had exited by the time the dump was taken
+            let serial_num = parser.parse_u32()?;
+            parser.thread_tab.remove(&serial_num);
+        }
+        RecordTag::HeapDump | RecordTag::HeapDumpSegment => {
+            parse_heap_dump_records(parser, bytes)?;
+        }
+        _ => {
+            parser.skip(u64::from(bytes))?;
+        }
+    }
+    // XXX: For Testing
+    Ok(Record {
+        tag: Some(tag),
+        time,
+        bytes,
+    })
+}
+
+#[derive(Debug)]
+struct Utf8StringRecord {
+    // XXX: Assumption
+    identifier: u64,
+    value: String,
+}
+
+#[allow(dead_code)]
+#[derive(Debug)]
+struct LoadClassRecord {
+    serial_num: u32,
+    // XXX: Assumption?
+    object_id: u64,
+    strace_num: u32,
+    // XXX: Assumption?
+    strname_id: u64,
+}
+
+#[allow(dead_code)]
+#[derive(Debug)]
+struct UnloadClassRecord {
+    serial_num: u32,
+}
+
+#[allow(dead_code)]
+#[derive(Debug)]
+struct StackFrameRecord {
+    frame_id: u64,       // XXX: Assumption
+    method_name_id: u64, // XXX: Assumption
+    method_sign_id: u64, // XXX: Assumption
+    source_name_id: u64, // XXX: Assumption
+    class_serial_num: u32,
+    line_num: i32,
+}
+
+#[allow(dead_code)]
+#[derive(Debug)]
+struct StartThreadRecord {
+    thread_serial_num: u32,
+    thread_object_id: u64, // XXX: Assumption
+    strace_num: u32,
+    thread_name_id: u64,       // XXX: Assumption
+    group_name_id: u64,        // XXX: Assumption
+    parent_group_name_id: u64, // XXX: Assumption
+}
+
+#[allow(dead_code)]
+#[derive(Debug)]
+struct StackTraceRecord {
+    serial_num: u32,
+    thread_serial_num: u32,
+    nframes: u32,
+    frame_ids: Vec<u64>, // XXX: Assumption
+}
+
+// Which kind of GC root an object is
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum RootKind {
+    Unknown,
+    JniGlobal,
+    JniLocal,
+    JavaFrame,
+    NativeStack,
+    StickyClass,
+    ThreadBlock,
+    MonitorUsed,
+    ThreadObject,
+}
+
+// Records (or heap dump subrecords) with a tag we don't know, which were skipped. These are
+// usually from a JVM newer than this code. There's one of these for each tag, however many
+// records had it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParseWarning {
+    pub subrecord: bool,
+    pub tag: u8,
+    // The tag was known, but the record was too short to be one, so it was skipped instead
+    pub malformed: bool,
+    // Where the first one was in the dump
+    pub offset: u64,
+    pub count: u64,
+    // Subrecords don't have a length, so skipping one means skipping the rest of the heap dump
+    // segment it's in, which is included here
+    pub skipped_bytes: u64,
+}
+
+#[allow(dead_code)]
+#[derive(Debug)]
+struct GcRootRecord {
+    kind: RootKind,
+    object_id: u64, // XXX: Assumption
+    // For the roots which belong to a thread
+    thread_serial_num: Option<u32>,
+    // For JniLocal and JavaFrame roots, the depth of the frame in the thread's stack
+    frame_num: Option<u32>,
+}
+
+#[allow(dead_code)]
+#[derive(Debug)]
+struct ThreadObjectRecord {
+    thread_object_id: u64, // XXX: Assumption
+    strace_num: u32,
+}
+
+#[allow(dead_code)]
+#[derive(Debug)]
+struct ClassDumpRecord {
+    class_object_id: u64, // XXX: Assumption
+    strace_num: u32,
+    superclass_object_id: u64,   // XXX: Assumption
+    class_loader_object_id: u64, // XXX: Assumption
+    instance_size_bytes: u32,
+    // (name id, value)
+    static_fields: Vec<(u64, Value)>,
+    // (name id, type), in the order their values appear in instance dumps
+    instance_fields: Vec<(u64, FieldTag)>,
+}
+
+// Index the contents of a HeapDump or HeapDumpSegment record. We keep the roots and classes, but
+// only the locations of the objects, which are read when they're needed.
+fn parse_heap_dump_records<R: Read + Seek>(
+    parser: &mut HprofParser<R>,
+    dump_segment_size: u32,
+) -> Result<()> {
+    let dump_segment_end = parser.position + u64::from(dump_segment_size);
+
+    while parser.position < dump_segment_end {
+        let subrecord_start = parser.position;
+        let raw_subtag = parser.parse_u8()?;
+        let subtag = match FromPrimitive::from_u8(raw_subtag) {
+            Some(subtag) => subtag,
+            None => {
+                // Subrecords don't have a length, so there's no way to find the next one
+                let rest = dump_segment_end - subrecord_start;
+                parser.warn(true, raw_subtag, false, subrecord_start, rest);
+                parser.skip(rest - 1)?;
+                break;
+            }
+        };
+        match subtag {
+            DataDumpSubRecordTag::RootUnknown => parse_root(parser, RootKind::Unknown, 0)?,
+            DataDumpSubRecordTag::JniGlobal => {
+                parse_root(parser, RootKind::JniGlobal, 0)?;
+                let _jni_global_ref_id = parser.parse_u64()?;
+            }
+            DataDumpSubRecordTag::JniLocal => parse_root(parser, RootKind::JniLocal, 2)?,
+            DataDumpSubRecordTag::JavaFrame => parse_root(parser, RootKind::JavaFrame, 2)?,
+            DataDumpSubRecordTag::NativeStack => parse_root(parser, RootKind::NativeStack, 1)?,
+            DataDumpSubRecordTag::StickyClass => parse_root(parser, RootKind::StickyClass, 0)?,
+            DataDumpSubRecordTag::ThreadBlock => parse_root(parser, RootKind::ThreadBlock, 1)?,
+            DataDumpSubRecordTag::MonitorUsed => parse_root(parser, RootKind::MonitorUsed, 0)?,
+            DataDumpSubRecordTag::ThreadObject => {
+                let thread_object_id = parser.parse_u64()?;
+                let thread_serial_num = parser.parse_u32()?;
+                let strace_num = parser.parse_u32()?;
+                parser.thread_object_tab.insert(
+                    thread_serial_num,
+                    ThreadObjectRecord {
+                        thread_object_id,
+                        strace_num,
+                    },
+                );
+                parser.roots.push(GcRootRecord {
+                    kind: RootKind::ThreadObject,
+                    object_id: thread_object_id,
+                    thread_serial_num: Some(thread_serial_num),
+                    frame_num: None,
+                });
+            }
+            DataDumpSubRecordTag::ClassDump => {
+                let r = parse_class_subrecord(parser)?;
+                parser.class_dump_tab.insert(r.class_object_id, r);
+            }
+            DataDumpSubRecordTag::InstanceDump => {
+                let object_id = parse_instance_subrecord(parser)?;
+                parser.object_offsets.insert(object_id, subrecord_start);
+            }
+            DataDumpSubRecordTag::ObjectArrayDump => {
+                let object_id = parse_object_array_subrecord(parser)?;
+                parser.object_offsets.insert(object_id, subrecord_start);
+            }
+            DataDumpSubRecordTag::PrimitiveArrayDump => {
+                let object_id = parse_primitive_array_subrecord(parser)?;
+                parser.object_offsets.insert(object_id, subrecord_start);
+            }
+        }
+    }
+    Ok(())
+}
+
+// Roots are an object ID followed by 'serials' u32s, the first of which is the thread serial
+// number and the second the frame number
+fn parse_root<R: Read + Seek>(
+    parser: &mut HprofParser<R>,
+    kind: RootKind,
+    serials: u8,
+) -> Result<()> {
+    let object_id = parser.parse_u64()?;
+    let thread_serial_num = if serials > 0 {
+        Some(parser.parse_u32()?)
+    } else {
+        None
+    };
+    let frame_num = if serials > 1 {
+        Some(parser.parse_u32()?)
+    } else {
+        None
+    };
+    parser.roots.push(GcRootRecord {
+        kind,
+        object_id,
+        thread_serial_num,
+        frame_num,
+    });
+    Ok(())
+}
+
+// The above is super slow as is...
+//
+// sub tag: ThreadObject
+// current_pos (3116808688) vs segment_end (3117518676)
+// sub-entries: 32542 class 45628477 instance 2202270 obj array 3739074 p array
+// entries: 394711 string 34189 load 0 unload 25359 frame 1317 trace 1 heapdump
+//
+// real 19m56.328s
+// user 1m8.314s
+// sys  4m41.148s
+//
+// XXX - Most of that was seeking, which no longer throws away the read buffer. Measure again.
+//
+
+fn read_value<R: Read>(reader: &mut R, field_type: FieldTag) -> Result<Value> {
+    Ok(match field_type {
+        FieldTag::Boolean => Value::Boolean(reader.read_u8()? != 0),
+        FieldTag::Byte => Value::Byte(reader.read_i8()?),
+        FieldTag::Char => Value::Char(reader.read_u16::<BigEndian>()?),
+        FieldTag::Short => Value::Short(reader.read_i16::<BigEndian>()?),
+        FieldTag::Int => Value::Integer(reader.read_i32::<BigEndian>()?),
+        FieldTag::Long => Value::Long(reader.read_i64::<BigEndian>()?),
+        FieldTag::Float => Value::Float(reader.read_f32::<BigEndian>()?),
+        FieldTag::Double => Value::Double(reader.read_f64::<BigEndian>()?),
+        // XXX: Assume
+        FieldTag::ArrayObject | FieldTag::NormalObject => match reader.read_u64::<BigEndian>()? {
+            0 => Value::Null,
+            id => Value::Object(id),
+        },
+    })
+}
+
+// XXX - Mention Reference Here For Sizes
+fn field_size(field_type: FieldTag) -> u64 {
+    match field_type {
+        FieldTag::Boolean | FieldTag::Byte => 1,
+        FieldTag::Char | FieldTag::Short => 2,
+        FieldTag::Float | FieldTag::Int => 4,
+        FieldTag::Double | FieldTag::Long => 8,
+        FieldTag::ArrayObject | FieldTag::NormalObject => 8, // XXX: Assume
+    }
+}
+
+fn parse_primitive_array_subrecord<R: Read + Seek>(parser: &mut HprofParser<R>) -> Result<u64> {
+    let array_object_id = parser.parse_u64()?; // XXX: Assume
+    let _strace_serial_num = parser.parse_u32()?;
+    let n_elements = parser.parse_u32()?;
+    let element_type = parser.parse_field_type_tag()?;
+    parser.skip(u64::from(n_elements) * field_size(element_type))?;
+    Ok(array_object_id)
+}
+
+fn parse_object_array_subrecord<R: Read + Seek>(parser: &mut HprofParser<R>) -> Result<u64> {
+    let array_object_id = parser.parse_u64()?; // XXX: Assume
+    let _strace_serial_num = parser.parse_u32()?;
+    let n_elements = parser.parse_u32()?;
+    let _array_class_object_id = parser.parse_u64()?; // XXX: Assume
+    parser.skip(u64::from(n_elements) * 8)?; // XXX: Assume
+    Ok(array_object_id)
+}
+
+fn parse_instance_subrecord<R: Read + Seek>(parser: &mut HprofParser<R>) -> Result<u64> {
+    let object_id = parser.parse_u64()?; // XXX: Assume
+    let _strace_serial_num = parser.parse_u32()?;
+    let _class_object_id = parser.parse_u64()?; // XXX: Assume
+    let bytes_left = parser.parse_u32()?;
+    parser.skip(u64::from(bytes_left))?;
+    Ok(object_id)
+}
+
+fn parse_class_subrecord<R: Read + Seek>(parser: &mut HprofParser<R>) -> Result<ClassDumpRecord> {
+    let class_object_id = parser.parse_u64()?;
+    let strace_num = parser.parse_u32()?;
+    let superclass_object_id = parser.parse_u64()?;
+    let class_loader_object_id = parser.parse_u64()?;
+    let _signers_object_id = parser.parse_u64()?;
+    let _pdomain_object_id = parser.parse_u64()?;
+
+    let _reserved0 = parser.parse_u64()?;
+    let _reserved1 = parser.parse_u64()?;
+
+    let instance_size_bytes = parser.parse_u32()?;
+
+    // HotSpot never writes any constant pool entries, but the format allows them
+    let constant_pool_size = parser.parse_u16()?;
+    for _ in 0..constant_pool_size {
+        let _index = parser.parse_u16()?;
+        let field_type = parser.parse_field_type_tag()?;
+        parser.skip(field_size(field_type))?;
+    }
+
+    let static_field_num = parser.parse_u16()?;
+    let mut static_fields = Vec::with_capacity(usize::from(static_field_num));
+    for _ in 0..static_field_num {
+        let field_name_id = parser.parse_u64()?;
+        let field_type = parser.parse_field_type_tag()?;
+        static_fields.push((field_name_id, parser.parse_value(field_type)?));
+    }
+
+    let instance_field_num = parser.parse_u16()?;
+    let mut instance_fields = Vec::with_capacity(usize::from(instance_field_num));
+    for _ in 0..instance_field_num {
+        let field_name_id = parser.parse_u64()?;
+        let field_type = parser.parse_field_type_tag()?;
+        instance_fields.push((field_name_id, field_type));
+    }
+
+    Ok(ClassDumpRecord {
+        class_object_id,
+        strace_num,
+        superclass_object_id,
+        class_loader_object_id,
+        instance_size_bytes,
+        static_fields,
+        instance_fields,
+    })
+}
+
+// The input only needs to be seekable so that we can skip over the parts of the dump we aren't
+// interested in, so dumps can come from anywhere but a pipe.
+#[allow(dead_code)]
+#[derive(Debug)]
+struct HprofParser<R: Read + Seek> {
+    // Objects are read lazily, after parsing, through a shared reference
+    reader: RefCell<BufReader<R>>,
+    position: u64,
+    // Where the reader is after the last lazy read (see read_at()), or None if we don't know
+    read_position: Cell<Option<u64>>,
+    header: Header,
+    symbols: Symbols,
+    frame_tab: HashMap<u64, StackFrameRecord>,
+    class_tab: HashMap<u32, LoadClassRecord>,
+    // Class object ID -> class serial number
+    class_serials: HashMap<u64, u32>,
+    trace_tab: HashMap<u32, StackTraceRecord>,
+    // Only threads which were alive when the dump was taken. HotSpot doesn't write these records
+    // at all.
+    thread_tab: HashMap<u32, StartThreadRecord>,
+    // From the heap dump records
+    roots: Vec<GcRootRecord>,
+    thread_object_tab: HashMap<u32, ThreadObjectRecord>,
+    class_dump_tab: HashMap<u64, ClassDumpRecord>,
+    object_offsets: index::ObjectIndex,
+    // Detected the first time it's needed, unless it's been set
+    size_model: Cell<Option<SizeModel>>,
+    // Built the first time it's needed, since it means reading every object in the dump. The
+    // sizes in it depend on the size model, so it's thrown away if that changes.
+    graph: RefCell<Option<Rc<HeapGraph>>>,
+    // Whether a java.lang.ref.Reference's referent counts as a reference in the graph (see
+    // graph.rs)
+    follow_referents: Cell<bool>,
+    // Reference's class object and the index of its referent field, found the first time it's
+    // needed
+    referent_field: OnceCell<Option<(u64, usize)>>,
+    warnings: Vec<ParseWarning>,
+}
+
+impl HprofParser<File> {
+    fn open(path: &str) -> Result<HprofParser<File>> {
+        HprofParser::new(File::open(path)?)
+    }
+}
+
+impl<R: Read + Seek> HprofParser<R> {
+    fn new(reader: R) -> Result<HprofParser<R>> {
+        let mut r = BufReader::new(reader);
+        let h = parse_header(&mut r)?;
+        Ok(HprofParser {
+            reader: RefCell::new(r),
+            position: HEADER_SIZE,
+            read_position: Cell::new(None),
+            header: h,
+            symbols: Symbols::new(symbols::DEFAULT_BUDGET, StringCache::new()),
+            frame_tab: HashMap::new(),
+            class_tab: HashMap::new(),
+            class_serials: HashMap::new(),
+            trace_tab: HashMap::new(),
+            thread_tab: HashMap::new(),
+            roots: vec![],
+            thread_object_tab: HashMap::new(),
+            class_dump_tab: HashMap::new(),
+            object_offsets: Default::default(),
+            size_model: Cell::new(None),
+            graph: RefCell::new(None),
+            follow_referents: Cell::new(false),
+            referent_field: OnceCell::new(),
+            warnings: vec![],
+        })
+    }
+
+    fn read_exact(&mut self, buf: &mut [u8]) -> Result<()> {
+        self.reader.get_mut().read_exact(buf)?;
+        self.position += buf.len() as u64;
+        Ok(())
+    }
+
+    fn skip(&mut self, bytes: u64) -> Result<()> {
+        self.reader.get_mut().seek_relative(bytes as i64)?;
+        self.position += bytes;
+        Ok(())
+    }
+
+    // Skipping past the end of the dump isn't an error in itself, so a dump which was cut off in
+    // the middle of something which was skipped is only noticed here
+    fn done_parsing(&mut self) -> Result<bool> {
+        let reader = self.reader.get_mut();
+        if !reader.fill_buf()?.is_empty() {
+            return Ok(false);
+        }
+        let end = reader.seek(SeekFrom::End(0))?;
+        if end < self.position {
+            return Err(Error::new(
+                ErrorKind::UnexpectedEof,
+                format!(
+                    "The dump ends at {}, before the end of its last record at {}",
+                    end, self.position
+                ),
+            ));
+        }
+        Ok(true)
+    }
+
+    fn warn(&mut self, subrecord: bool, tag: u8, malformed: bool, offset: u64, skipped_bytes: u64) {
+        match self
+            .warnings
+            .iter_mut()
+            .find(|w| w.subrecord == subrecord && w.tag == tag && w.malformed == malformed)
+        {
+            Some(warning) => {
+                warning.count += 1;
+                warning.skipped_bytes += skipped_bytes;
+            }
+            None => self.warnings.push(ParseWarning {
+                subrecord,
+                tag,
+                malformed,
+                offset,
+                count: 1,
+                skipped_bytes,
+            }),
+        }
+    }
+
+    // Unlike an unknown record or subrecord tag, there's no way to carry on after an unknown field
+    // type, since it's what says how long the value after it is
+    fn parse_field_type_tag(&mut self) -> Result<FieldTag> {
+        let offset = self.position;
+        let raw = self.parse_u8()?;
+        FromPrimitive::from_u8(raw).ok_or_else(|| {
+            Error::new(
+                ErrorKind::InvalidData,
+                format!("Unknown field type {:#x} at {}", raw, offset),
+            )
+        })
+    }
+
+    #[allow(dead_code)]
+    fn parse_i8(&mut self) -> Result<i8> {
+        let mut u8_buf = [0u8; 1];
+        self.read_exact(&mut u8_buf)?;
+        // TODO - XXX - double check below
+        Ok(i8::from_be(u8_buf[0] as i8))
+    }
+
+    #[allow(dead_code)]
+    fn parse_u8(&mut self) -> Result<u8> {
+        let mut u8_buf = [0u8; 1];
+        self.read_exact(&mut u8_buf)?;
+        Ok(u8_buf[0])
+    }
+
+    #[allow(dead_code)]
+    fn parse_i16(&mut self) -> Result<i16> {
+        let mut u16_buf = [0u8; 2];
+        self.read_exact(&mut u16_buf)?;
+        Ok(i16::from_be_bytes(u16_buf))
+    }
+
+    #[allow(dead_code)]
+    fn parse_u16(&mut self) -> Result<u16> {
+        let mut u16_buf = [0u8; 2];
+        self.read_exact(&mut u16_buf)?;
+        Ok(u16::from_be_bytes(u16_buf))
+    }
+
+    fn parse_i32(&mut self) -> Result<i32> {
+        let mut u32_buf = [0u8; 4];
+        self.read_exact(&mut u32_buf)?;
+        Ok(i32::from_be_bytes(u32_buf))
+    }
+
+    fn parse_u32(&mut self) -> Result<u32> {
+        let mut u32_buf = [0u8; 4];
+        self.read_exact(&mut u32_buf)?;
+        Ok(u32::from_be_bytes(u32_buf))
+    }
+
+    #[allow(dead_code)]
+    fn parse_i64(&mut self) -> Result<i64> {
+        let mut u64_buf = [0u8; 8];
+        self.read_exact(&mut u64_buf)?;
+        Ok(i64::from_be_bytes(u64_buf))
+    }
+
+    fn parse_u64(&mut self) -> Result<u64> {
+        let mut u64_buf = [0u8; 8];
+        self.read_exact(&mut u64_buf)?;
+        Ok(u64::from_be_bytes(u64_buf))
+    }
+
+    fn parse_value(&mut self, field_type: FieldTag) -> Result<Value> {
+        let offset = self.position;
+        let value = read_value(self.reader.get_mut(), field_type).map_err(|e| {
+            Error::new(
+                ErrorKind::InvalidData,
+                format!(
+                    "Couldn't read a {:?} value at {}: {}",
+                    field_type, offset, e
+                ),
+            )
+        })?;
+        self.position += field_size(field_type);
+        Ok(value)
+    }
+
+    fn parse_utf8_string(&mut self, bytes: usize) -> Result<String> {
+        let mut value_buf = vec![0u8; bytes];
+        self.read_exact(&mut value_buf)?;
+        Ok(mutf8::decode(&value_buf))
+    }
+
+    fn parse_utf8_string_record(&mut self, bytes: usize) -> Result<Utf8StringRecord> {
+        let identifier = self.parse_u64()?;
+        let value = self.parse_utf8_string(bytes - mem::size_of::<u64>())?;
+        Ok(Utf8StringRecord { identifier, value })
+    }
+
+    fn parse_load_class_record(&mut self) -> Result<LoadClassRecord> {
+        let serial_num = self.parse_u32()?;
+        let object_id = self.parse_u64()?;
+        let strace_num = self.parse_u32()?;
+        let strname_id = self.parse_u64()?;
+        Ok(LoadClassRecord {
+            serial_num,
+            object_id,
+            strace_num,
+            strname_id,
+        })
+    }
+    fn parse_unload_class_record(&mut self) -> Result<UnloadClassRecord> {
+        Ok(UnloadClassRecord {
+            serial_num: self.parse_u32()?,
+        })
+    }
+
+    fn parse_stack_frame_record(&mut self) -> Result<StackFrameRecord> {
+        let frame_id = self.parse_u64()?;
+        let method_name_id = self.parse_u64()?;
+        let method_sign_id = self.parse_u64()?;
+        let source_name_id = self.parse_u64()?;
+        let class_serial_num = self.parse_u32()?;
+        let line_num = self.parse_i32()?;
+
+        Ok(StackFrameRecord {
+            frame_id,
+            method_name_id,
+            method_sign_id,
+            source_name_id,
+            class_serial_num,
+            line_num,
+        })
+    }
+
+    fn parse_start_thread_record(&mut self) -> Result<StartThreadRecord> {
+        Ok(StartThreadRecord {
+            thread_serial_num: self.parse_u32()?,
+            thread_object_id: self.parse_u64()?,
+            strace_num: self.parse_u32()?,
+            thread_name_id: self.parse_u64()?,
+            group_name_id: self.parse_u64()?,
+            parent_group_name_id: self.parse_u64()?,
+        })
+    }
+
+    fn parse_stack_trace_record(&mut self) -> Result<StackTraceRecord> {
+        let serial_num = self.parse_u32()?;
+        let thread_serial_num = self.parse_u32()?;
+        let nframes = self.parse_u32()?;
+
+        // Not allocated up front from nframes, which a damaged record could make anything
+        let mut frame_ids = vec![];
+        for _ in 0..nframes {
+            frame_ids.push(self.parse_u64()?);
+        }
+
+        Ok(StackTraceRecord {
+            serial_num,
+            thread_serial_num,
+            nframes,
+            frame_ids,
+        })
+    }
+}
+
+//
+// The model backend for heap dumps. A dump is a snapshot of a JVM which is permanently suspended,
+// and which can't be modified.
+//
+
+trait ReadSeek: Read + Seek {}
+impl<T: Read + Seek> ReadSeek for T {}
+
+type Dump = HprofParser<Box<dyn ReadSeek>>;
+
+// How many bytes of a dump's strings are kept in memory, unless it's opened with another budget
+pub const DEFAULT_STRING_BUDGET: usize = symbols::DEFAULT_BUDGET;
+
+pub struct HprofJavaVirtualMachine {
+    dump: Rc<Dump>,
+}
+
+impl HprofJavaVirtualMachine {
+    pub fn new<R: Read + Seek + 'static>(reader: R) -> Result<Self> {
+        Self::with_string_budget(reader, symbols::DEFAULT_BUDGET)
+    }
+
+    // Keeps at most 'budget' bytes of the dump's strings in memory, and reads the rest from the
+    // dump when they're needed
+    pub fn with_string_budget<R: Read + Seek + 'static>(reader: R, budget: usize) -> Result<Self> {
+        Self::with_string_cache(reader, budget, &StringCache::new())
+    }
+
+    // Like with_string_budget(), keeping the strings in 'cache', which other dumps can share.
+    // Only the strings which aren't in it already count towards the budget.
+    pub fn with_string_cache<R: Read + Seek + 'static>(
+        reader: R,
+        budget: usize,
+        cache: &StringCache,
+    ) -> Result<Self> {
+        let reader: Box<dyn ReadSeek> = Box::new(reader);
+        let mut parser: Dump = HprofParser::new(reader)?;
+        parser.symbols = Symbols::new(budget, cache.clone());
+        while !parser.done_parsing()? {
+            parse_record(&mut parser)?;
+        }
+        parser.object_offsets.finish();
+        Ok(HprofJavaVirtualMachine {
+            dump: Rc::new(parser),
+        })
+    }
+}
+
+impl HprofJavaVirtualMachine {
+    // Where the dump's strings are kept, for opening another dump of the same application with
+    pub fn string_cache(&self) -> &StringCache {
+        self.dump.symbols.cache()
+    }
+
+    // How object sizes are worked out. Unless it's been set, this is a default which is right for
+    // most HotSpot heaps under 32GB (see SizeModel::default_for()).
+    pub fn size_model(&self) -> SizeModel {
+        self.dump.size_model()
+    }
+
+    // For when the guess is wrong, e.g. for a JVM with a heap over 32GB, which doesn't use
+    // compressed references
+    pub fn set_size_model(&self, model: SizeModel) {
+        self.dump.size_model.set(Some(model));
+        self.dump.graph.borrow_mut().take();
+    }
+
+    // Whether the referents of weak, soft, phantom and finalizer references are followed when
+    // working out retained sizes, dominators and paths from GC roots. They aren't by default.
+    pub fn follows_referents(&self) -> bool {
+        self.dump.follow_referents.get()
+    }
+
+    // Follow referents as if they were ordinary fields, e.g. to see what a cache of soft
+    // references is holding on to. A ReferrerIndex keeps the setting it was built with.
+    pub fn set_follow_referents(&self, follow: bool) {
+        self.dump.follow_referents.set(follow);
+        self.dump.graph.borrow_mut().take();
+    }
+
+    // What couldn't be understood when the dump was parsed, and was skipped
+    pub fn parse_warnings(&self) -> &[ParseWarning] {
+        &self.dump.warnings
+    }
+
+    // How many records and subrecords were skipped
+    pub fn skipped_records(&self) -> u64 {
+        self.dump.warnings.iter().map(|w| w.count).sum()
+    }
+
+    fn thread_serials(&self) -> Vec<u32> {
+        // Dumps from HotSpot don't have StartThread records, but they do have a ThreadObject root
+        // and a stack trace for each thread
+        let mut serials: Vec<u32> = self
+            .dump
+            .thread_tab
+            .keys()
+            .chain(self.dump.thread_object_tab.keys())
+            .copied()
+            .chain(
+                self.dump
+                    .trace_tab
+                    .values()
+                    .map(|trace| trace.thread_serial_num)
+                    .filter(|&serial| serial != 0),
+            )
+            .collect();
+        serials.sort_unstable();
+        serials.dedup();
+        serials
+    }
+}
+
+impl JavaVirtualMachine for HprofJavaVirtualMachine {
+    type Field = HprofField;
+    type Location = HprofLocation;
+    type Method = HprofMethod;
+    type ObjectReference = HprofObjectReference;
+    type ReferenceType = HprofReferenceType;
+    type StackFrame = HprofStackFrame;
+    type ThreadReference = HprofThreadReference;
+
+    fn all_threads(&self) -> Items<'_, HprofThreadReference> {
+        Box::new(self.thread_serials().into_iter().map(move |serial_num| {
+            Ok(HprofThreadReference {
+                dump: self.dump.clone(),
+                serial_num,
+            })
+        }))
+    }
+
+    fn can_be_modified(&self) -> bool {
+        false
+    }
+
+    // The threads in a dump are always suspended, so there's nothing to do
+    fn suspend(&self) -> Result<()> {
+        Ok(())
+    }
+
+    fn resume(&self) -> Result<()> {
+        Ok(())
+    }
+
+    fn classes_by_name(&self, name: &str) -> Result<Vec<HprofReferenceType>> {
+        let mut serials: Vec<u32> = self
+            .dump
+            .class_tab
+            .values()
+            .filter(|class| self.dump.class_name(class) == name)
+            .map(|class| class.serial_num)
+            .collect();
+        serials.sort_unstable();
+        Ok(serials
+            .into_iter()
+            .map(|serial_num| HprofReferenceType {
+                dump: self.dump.clone(),
+                serial_num,
+            })
+            .collect())
+    }
+
+    fn all_classes(&self) -> Items<'_, HprofReferenceType> {
+        let mut serials: Vec<u32> = self.dump.class_tab.keys().copied().collect();
+        serials.sort_unstable();
+        Box::new(serials.into_iter().map(move |serial_num| {
+            Ok(HprofReferenceType {
+                dump: self.dump.clone(),
+                serial_num,
+            })
+        }))
+    }
+
+    fn object(&self, id: u64) -> Result<HprofObjectReference> {
+        if !self.dump.object_offsets.contains(id) && !self.dump.class_serials.contains_key(&id) {
+            return Err(Error::new(
+                ErrorKind::NotFound,
+                format!("There's no object {:x} in the heap dump", id),
+            ));
+        }
+        Ok(HprofObjectReference {
+            dump: self.dump.clone(),
+            object_id: id,
+        })
+    }
+
+    fn array_values(&self, array: u64, first: usize, count: usize) -> Result<Vec<Value>> {
+        match self.dump.read_object(array)? {
+            Some(HeapObject::ObjectArray { elements, .. }) => Ok(elements
+                .into_iter()
+                .skip(first)
+                .take(count)
+                .map(|id| match id {
+                    0 => Value::Null,
+                    id => self.dump.model_value(Value::Object(id)),
+                })
+                .collect()),
+            Some(HeapObject::PrimitiveArray { element_type, data }) => {
+                let size = field_size(element_type) as usize;
+                data.chunks_exact(size)
+                    .skip(first)
+                    .take(count)
+                    .map(|mut element| read_value(&mut element, element_type))
+                    .collect()
+            }
+            _ => Err(Error::new(
+                ErrorKind::InvalidInput,
+                format!("Object {:x} isn't an array in the heap dump", array),
+            )),
+        }
+    }
+
+    // See summary()
+    fn class_histogram(&self) -> Result<Histogram> {
+        Ok(self.summary()?.histogram)
+    }
+
+    // There are no line tables in a dump, so the only locations we know about are the ones in the
+    // stack traces
+    fn locations_of_line(
+        &self,
+        class_pattern: &ClassPattern,
+        source_file: &str,
+        line: u32,
+    ) -> Result<Vec<HprofLocation>> {
+        let file_name = source_file
+            .rsplit(['/', '\\'])
+            .next()
+            .unwrap_or(source_file);
+        let mut frame_ids: Vec<u64> = self
+            .dump
+            .frame_tab
+            .values()
+            .filter(|frame| {
+                frame.line_num == line as i32
+                    && self.dump.string(frame.source_name_id).as_deref() == Some(file_name)
+                    && self
+                        .dump
+                        .class_tab
+                        .get(&frame.class_serial_num)
+                        .is_some_and(|class| class_pattern.matches(&self.dump.class_name(class)))
+            })
+            .map(|frame| frame.frame_id)
+            .collect();
+        frame_ids.sort_unstable();
+        Ok(frame_ids
+            .into_iter()
+            .map(|frame_id| HprofLocation {
+                dump: self.dump.clone(),
+                frame_id,
+            })
+            .collect())
+    }
+}
+
+impl<R: Read + Seek> HprofParser<R> {
+    // None if there's no such string, or it couldn't be read from the dump
+    fn string(&self, id: u64) -> Option<Rc<str>> {
+        match self.symbols.get(id)? {
+            Symbol::Interned(index) => Some(self.symbols.interned(index)),
+            Symbol::InFile { offset, length } => self
+                .read_at(offset, |reader| {
+                    let mut bytes = vec![0u8; length as usize];
+                    reader.read_exact(&mut bytes)?;
+                    Ok((mutf8::decode(&bytes).into(), length as u64))
+                })
+                .ok(),
+        }
+    }
+
+    // The IDs of the strings with the given value (usually just one)
+    fn string_ids(&self, value: &str) -> Vec<u64> {
+        self.symbols
+            .candidates(value)
+            .iter()
+            .copied()
+            .filter(|&id| self.string(id).as_deref() == Some(value))
+            .collect()
+    }
+
+    // Class names are in the internal form in dumps, e.g. java/lang/String or [I
+    fn class_name(&self, class: &LoadClassRecord) -> String {
+        match self.symbols.get(class.strname_id) {
+            Some(Symbol::Interned(index)) => self.symbols.class_name(index).to_string(),
+            _ => names::source_name(
+                self.string(class.strname_id)
+                    .as_deref()
+                    .unwrap_or("<unknown>"),
+            ),
+        }
+    }
+
+    fn graph(&self) -> Result<Rc<HeapGraph>> {
+        if let Some(graph) = &*self.graph.borrow() {
+            return Ok(graph.clone());
+        }
+        let graph = Rc::new(HeapGraph::build(self, self.follow_referents.get())?);
+        *self.graph.borrow_mut() = Some(graph.clone());
+        Ok(graph)
+    }
+
+    // Like graph(), but following referents or not whatever the setting is. Only the graph for
+    // the setting is kept, so the other one is built again every time.
+    fn graph_with_referents(&self, follow_referents: bool) -> Result<Rc<HeapGraph>> {
+        if follow_referents == self.follow_referents.get() {
+            return self.graph();
+        }
+        Ok(Rc::new(HeapGraph::build(self, follow_referents)?))
+    }
+}
+
+// For the bulk methods which fail before they've found anything
+fn failed<'a, T: 'a>(e: Error) -> Items<'a, T> {
+    Box::new(std::iter::once(Err(e)))
+}
+
+fn not_found(what: &str, id: u64) -> Error {
+    Error::new(
+        ErrorKind::InvalidData,
+        format!("Heap dump refers to a missing {} ({})", what, id),
+    )
+}
+
+impl<R: Read + Seek> HprofParser<R> {
+    // A value as the model hands it out. The dump doesn't tag references to class objects, but
+    // their IDs are those of the classes' LoadClass records, which is also how reflected_type()
+    // finds the class.
+    fn model_value(&self, value: Value) -> Value {
+        match value {
+            Value::Object(id) if self.class_serials.contains_key(&id) => Value::ClassObject(id),
+            value => value,
+        }
+    }
+
+    fn thread_object_id(&self, serial_num: u32) -> Option<u64> {
+        match self.thread_tab.get(&serial_num) {
+            Some(thread) => Some(thread.thread_object_id),
+            None => self
+                .thread_object_tab
+                .get(&serial_num)
+                .map(|thread| thread.thread_object_id),
+        }
+    }
+
+    fn thread_name(&self, serial_num: u32) -> Result<String> {
+        let start_thread_name = self
+            .thread_tab
+            .get(&serial_num)
+            .and_then(|thread| self.string(thread.thread_name_id));
+        if let Some(name) = start_thread_name {
+            return Ok(name.to_string());
+        }
+        if let Some(thread_object_id) = self.thread_object_id(serial_num) {
+            if let Some(name_id) = self.object_field(thread_object_id, "name")? {
+                if let Some(name) = self.read_string(name_id)? {
+                    return Ok(name);
+                }
+            }
+        }
+        Ok(format!("Thread {}", serial_num))
+    }
+
+    // The stack trace of a thread as it was when the dump was taken
+    fn thread_trace(&self, serial_num: u32) -> Option<&StackTraceRecord> {
+        let strace_num = match (
+            self.thread_tab.get(&serial_num),
+            self.thread_object_tab.get(&serial_num),
+        ) {
+            (Some(thread), _) => Some(thread.strace_num),
+            (None, Some(thread)) => Some(thread.strace_num),
+            (None, None) => None,
+        };
+        match strace_num {
+            Some(strace_num) => self.trace_tab.get(&strace_num),
+            None => self
+                .trace_tab
+                .values()
+                .find(|trace| trace.thread_serial_num == serial_num),
+        }
+    }
+}
+
+pub struct HprofThreadReference {
+    dump: Rc<Dump>,
+    serial_num: u32,
+}
+
+impl HprofThreadReference {
+    // The java.lang.Thread instance
+    fn thread_object_id(&self) -> Option<u64> {
+        self.dump.thread_object_id(self.serial_num)
+    }
+
+    // The thread's ThreadLocal values, including inheritable ones. Empty if the Thread instance
+    // isn't in the dump.
+    pub fn thread_locals(&self) -> Result<Vec<ThreadLocalEntry>> {
+        let thread_object_id = match self.thread_object_id() {
+            Some(id) => id,
+            None => return Ok(vec![]),
+        };
+        let mut entries = vec![];
+        for &(map_field, inheritable) in
+            &[("threadLocals", false), ("inheritableThreadLocals", true)]
+        {
+            // A ThreadLocal.ThreadLocalMap, which is an open addressed hash table of entries
+            // which refer to their ThreadLocal weakly
+            let table = match self.dump.object_field(thread_object_id, map_field)? {
+                Some(map) => {
+                    self.dump
+                        .read_object(match self.dump.object_field(map, "table")? {
+                            Some(table) => table,
+                            None => continue,
+                        })?
+                }
+                None => continue,
+            };
+            let elements = match table {
+                Some(HeapObject::ObjectArray { elements, .. }) => elements,
+                _ => continue,
+            };
+            for entry in elements.into_iter().filter(|&id| id != 0) {
+                entries.push(ThreadLocalEntry {
+                    entry,
+                    thread_local: self.dump.object_field(entry, "referent")?,
+                    value: self.dump.object_field(entry, "value")?,
+                    inheritable,
+                });
+            }
+        }
+        Ok(entries)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ThreadLocalEntry {
+    // The ThreadLocalMap.Entry
+    pub entry: u64,
+    // None if the ThreadLocal itself has been collected, but the entry hasn't been cleaned up
+    // yet (or if the dump is missing the object)
+    pub thread_local: Option<u64>,
+    pub value: Option<u64>,
+    pub inheritable: bool,
+}
+
+impl ObjectReference<HprofJavaVirtualMachine> for HprofThreadReference {
+    // The ID of the Thread object, or the thread's serial number if it isn't in the dump
+    fn unique_id(&self) -> Result<u64> {
+        Ok(self
+            .thread_object_id()
+            .unwrap_or_else(|| u64::from(self.serial_num)))
+    }
+
+    fn reference_type(&self) -> Result<Box<dyn ReferenceType<HprofJavaVirtualMachine>>> {
+        let not_in_dump = || {
+            Error::new(
+                ErrorKind::NotFound,
+                format!("Thread {} isn't in the heap dump", self.serial_num),
+            )
+        };
+        let thread_object_id = self.thread_object_id().ok_or_else(not_in_dump)?;
+        let class_object_id = match self.dump.read_object(thread_object_id)? {
+            Some(HeapObject::Instance {
+                class_object_id, ..
+            }) => class_object_id,
+            _ => return Err(not_in_dump()),
+        };
+        let serial_num = *self
+            .dump
+            .class_serials
+            .get(&class_object_id)
+            .ok_or_else(|| not_found("class", class_object_id))?;
+        Ok(Box::new(HprofReferenceType {
+            dump: self.dump.clone(),
+            serial_num,
+        }))
+    }
+
+    fn get_value(&self, field: &HprofField) -> Result<Value> {
+        let thread_object_id = self.thread_object_id().ok_or_else(|| {
+            Error::new(
+                ErrorKind::NotFound,
+                format!("Thread {} isn't in the heap dump", self.serial_num),
+            )
+        })?;
+        instance_field_value(&self.dump, thread_object_id, field)
+    }
+    fn reflected_type(&self) -> Result<Option<HprofReferenceType>> {
+        Ok(None)
+    }
+}
+
+impl ThreadReference<HprofJavaVirtualMachine> for HprofThreadReference {
+    fn name(&self) -> Result<String> {
+        self.dump.thread_name(self.serial_num)
+    }
+
+    // Dumps only list the threads which were mounted when the dump was taken
+    fn is_virtual(&self) -> Result<bool> {
+        Ok(match self.thread_object_id() {
+            Some(id) => {
+                self.dump.object_class_name(id)?.as_deref() == Some("java.lang.VirtualThread")
+            }
+            None => false,
+        })
+    }
+
+    fn frames(&self) -> Items<'_, HprofStackFrame> {
+        let frame_ids = match self.dump.thread_trace(self.serial_num) {
+            Some(trace) => &trace.frame_ids[..],
+            None => &[],
+        };
+        Box::new(frame_ids.iter().map(move |&frame_id| {
+            Ok(HprofStackFrame {
+                dump: self.dump.clone(),
+                frame_id,
+            })
+        }))
+    }
+}
+
+pub struct HprofStackFrame {
+    dump: Rc<Dump>,
+    frame_id: u64,
+}
+
+impl StackFrame<HprofJavaVirtualMachine> for HprofStackFrame {
+    fn location(&self) -> Result<HprofLocation> {
+        Ok(HprofLocation {
+            dump: self.dump.clone(),
+            frame_id: self.frame_id,
+        })
+    }
+}
+
+pub struct HprofLocation {
+    dump: Rc<Dump>,
+    frame_id: u64,
+}
+
+impl HprofLocation {
+    fn frame(&self) -> Result<&StackFrameRecord> {
+        self.dump
+            .frame_tab
+            .get(&self.frame_id)
+            .ok_or_else(|| not_found("stack frame", self.frame_id))
+    }
+}
+
+impl Location<HprofJavaVirtualMachine> for HprofLocation {
+    fn line_number(&self) -> Result<Option<u32>> {
+        // Zero or negative if unknown (-1), compiled (-2), or native (-3)
+        let line_num = self.frame()?.line_num;
+        Ok(if line_num > 0 {
+            Some(line_num as u32)
+        } else {
+            None
+        })
+    }
+
+    fn method(&self) -> Result<HprofMethod> {
+        let frame = self.frame()?;
+        let name = self
+            .dump
+            .string(frame.method_name_id)
+            .ok_or_else(|| not_found("method name", frame.method_name_id))?;
+        Ok(HprofMethod {
+            name: name.to_string(),
+        })
+    }
+
+    fn declaring_type(&self) -> Result<HprofReferenceType> {
+        Ok(HprofReferenceType {
+            dump: self.dump.clone(),
+            serial_num: self.frame()?.class_serial_num,
+        })
+    }
+}
+
+// Any object in the dump
+pub struct HprofObjectReference {
+    dump: Rc<Dump>,
+    object_id: u64,
+}
+
+impl ObjectReference<HprofJavaVirtualMachine> for HprofObjectReference {
+    fn unique_id(&self) -> Result<u64> {
+        Ok(self.object_id)
+    }
+
+    fn reference_type(&self) -> Result<Box<dyn ReferenceType<HprofJavaVirtualMachine>>> {
+        let serial_num = self
+            .dump
+            .class_of(self.object_id)?
+            .and_then(|class_object_id| self.dump.class_serials.get(&class_object_id))
+            .ok_or_else(|| not_found("class of object", self.object_id))?;
+        Ok(Box::new(HprofReferenceType {
+            dump: self.dump.clone(),
+            serial_num: *serial_num,
+        }))
+    }
+
+    fn get_value(&self, field: &HprofField) -> Result<Value> {
+        instance_field_value(&self.dump, self.object_id, field)
+    }
+    // Class objects aren't in the dump as objects, but their IDs are the IDs of the classes
+    fn reflected_type(&self) -> Result<Option<HprofReferenceType>> {
+        Ok(self
+            .dump
+            .class_serials
+            .get(&self.object_id)
+            .map(|&serial_num| HprofReferenceType {
+                dump: self.dump.clone(),
+                serial_num,
+            }))
+    }
+}
+
+fn instance_field_value(dump: &Dump, object_id: u64, field: &HprofField) -> Result<Value> {
+    let value = if field.is_static {
+        None
+    } else {
+        dump.declared_field_value(object_id, field.class_object_id, field.name_id)?
+    };
+    let value = value.map(|value| dump.model_value(value));
+    value.ok_or_else(|| {
+        Error::new(
+            ErrorKind::InvalidInput,
+            format!(
+                "{} isn't an instance field of object {:x}",
+                field.name, object_id
+            ),
+        )
+    })
+}
+
+pub struct HprofReferenceType {
+    dump: Rc<Dump>,
+    serial_num: u32,
+}
+
+impl HprofReferenceType {
+    fn class_dump(&self) -> Result<&ClassDumpRecord> {
+        let class = self
+            .dump
+            .class_tab
+            .get(&self.serial_num)
+            .ok_or_else(|| not_found("class", u64::from(self.serial_num)))?;
+        self.dump
+            .class_dump_tab
+            .get(&class.object_id)
+            .ok_or_else(|| not_found("class dump", class.object_id))
+    }
+}
+
+impl ReferenceType<HprofJavaVirtualMachine> for HprofReferenceType {
+    fn name(&self) -> Result<String> {
+        let class = self
+            .dump
+            .class_tab
+            .get(&self.serial_num)
+            .ok_or_else(|| not_found("class", u64::from(self.serial_num)))?;
+        Ok(self.dump.class_name(class))
+    }
+
+    // Dumps don't record the source file of classes, but the stack frames do
+    fn source_name(&self) -> Result<Option<String>> {
+        Ok(self
+            .dump
+            .frame_tab
+            .values()
+            .filter(|frame| frame.class_serial_num == self.serial_num)
+            .find_map(|frame| self.dump.string(frame.source_name_id))
+            .map(|name| name.to_string()))
+    }
+
+    // Static fields first, then instance fields
+    fn fields(&self) -> Items<'_, HprofField> {
+        let class = match self.class_dump() {
+            Ok(class) => class,
+            Err(e) => return failed(e),
+        };
+        let static_fields = class
+            .static_fields
+            .iter()
+            .map(|&(name_id, _)| (name_id, true));
+        let instance_fields = class
+            .instance_fields
+            .iter()
+            .map(|&(name_id, _)| (name_id, false));
+        Box::new(
+            static_fields
+                .chain(instance_fields)
+                .map(move |(name_id, is_static)| {
+                    let name = self
+                        .dump
+                        .string(name_id)
+                        .ok_or_else(|| not_found("field name", name_id))?;
+                    Ok(HprofField {
+                        name: name.to_string(),
+                        name_id,
+                        class_object_id: class.class_object_id,
+                        is_static,
+                    })
+                }),
+        )
+    }
+
+    fn all_fields(&self) -> Result<Vec<DeclaredField<HprofJavaVirtualMachine>>> {
+        let mut all_fields = vec![];
+        let mut class = Some(HprofReferenceType {
+            dump: self.dump.clone(),
+            serial_num: self.serial_num,
+        });
+        while let Some(declaring_type) = class {
+            for field in declaring_type.fields() {
+                let field = field?;
+                all_fields.push(DeclaredField {
+                    declaring_type: HprofReferenceType {
+                        dump: self.dump.clone(),
+                        serial_num: declaring_type.serial_num,
+                    },
+                    field,
+                });
+            }
+            class = declaring_type.superclass()?;
+        }
+        Ok(all_fields)
+    }
+
+    // Dumps don't distinguish interfaces from classes, but record Object as their superclass
+    fn superclass(&self) -> Result<Option<HprofReferenceType>> {
+        let superclass_object_id = self.class_dump()?.superclass_object_id;
+        Ok(self
+            .dump
+            .class_serials
+            .get(&superclass_object_id)
+            .map(|&serial_num| HprofReferenceType {
+                dump: self.dump.clone(),
+                serial_num,
+            }))
+    }
+
+    // In the order they're in the dump. Objects are read as the iterator goes, so asking for
+    // the first few of a common class doesn't mean reading the whole dump.
+    fn instances(&self, max: usize) -> Items<'_, u64> {
+        let class_object_id = match self.class_dump() {
+            Ok(class) => class.class_object_id,
+            Err(e) => return failed(e),
+        };
+        let max = if max == 0 { usize::MAX } else { max };
+        Box::new(
+            self.dump
+                .object_headers()
+                .filter_map(move |object| match object {
+                    Ok((object_id, ObjectHeader::Instance { class_object_id: c }))
+                    | Ok((
+                        object_id,
+                        ObjectHeader::ObjectArray {
+                            class_object_id: c, ..
+                        },
+                    )) if c == class_object_id => Some(Ok(object_id)),
+                    Ok(_) => None,
+                    Err(e) => Some(Err(e)),
+                })
+                .take(max),
+        )
+    }
+
+    fn get_value(&self, field: &HprofField) -> Result<Value> {
+        self.class_dump()?
+            .static_fields
+            .iter()
+            .find(|&&(name_id, _)| field.is_static && name_id == field.name_id)
+            .map(|(_, value)| self.dump.model_value(value.clone()))
+            .ok_or_else(|| {
+                Error::new(
+                    ErrorKind::InvalidInput,
+                    format!("{} isn't a static field of this class", field.name),
+                )
+            })
+    }
+}
+
+pub struct HprofField {
+    name: String,
+    name_id: u64,
+    // The class which declares it
+    class_object_id: u64,
+    is_static: bool,
+}
+
+impl TypeComponent for HprofField {
+    fn name(&self) -> Result<String> {
+        Ok(self.name.clone())
+    }
+}
+
+impl Field for HprofField {
+    // Whether the field is static is all a dump records
+    fn modifiers(&self) -> Result<Modifiers> {
+        Ok(Modifiers(if self.is_static {
+            Modifiers::STATIC
+        } else {
+            0
+        }))
+    }
+}
+
+pub struct HprofMethod {
+    name: String,
+}
+
+impl TypeComponent for HprofMethod {
+    fn name(&self) -> Result<String> {
+        Ok(self.name.clone())
+    }
+}
+
+impl Method<HprofJavaVirtualMachine> for HprofMethod {}
+
+fn parse_hprof_file(filename: &str) -> Result<()> {
+    let mut parser = HprofParser::open(filename)?;
+
+    // XXX: Debug
+    let mut i: u64 = 0;
+    let mut j: u64 = 0;
+    let mut k: u64 = 0;
+    let mut l: u64 = 0;
+    let mut m: u64 = 0;
+    let mut n: u64 = 0;
+
+    loop {
+        if parser.done_parsing()? {
+            break;
+        }
+        let record: Record = parse_record(&mut parser)?;
+        match record.tag {
+            Some(RecordTag::Utf8String) => {
+                i += 1;
+            }
+            Some(RecordTag::LoadClass) => {
+                j += 1;
+            }
+            Some(RecordTag::UnloadClass) => {
+                k += 1;
+            }
+            Some(RecordTag::StackFrame) => {
+                l += 1;
+            }
+            Some(RecordTag::StackTrace) => {
+                m += 1;
+            }
+            Some(RecordTag::HeapDump) => {
+                n += 1;
+                break;
+            }
+            _ => {
+                break;
+            }
+        }
+    }
+
+    // XXX: Debug
+    println!(
+        "entries: {} string {} load {} unload {} frame {} trace {} heapdump",
+        i, j, k, l, m, n
+    );
+    Ok(())
+}
+
+pub fn sample_fn() {
+    let args: Vec<String> = std::env::args().collect();
+    match args.len() {
+        1 => {
+            println!("usage: {} <hprof dump>", args[0]);
+        }
+        2 => {
+            println!("Analyzing {} ...", args[1]);
+            if let Err(e) = parse_hprof_file(&args[1]) {
+                println!("Couldn't parse {}: {}", args[1], e);
+            }
+        }
+        _ => {
+            println!("usage: {} <hprof dump>", args[0]);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+    use std::time::{Duration, UNIX_EPOCH};
+
+    fn record(tag: u8, body: &[u8]) -> Vec<u8> {
+        let mut buf = vec![tag];
+        buf.extend_from_slice(&0u32.to_be_bytes());
+        buf.extend_from_slice(&(body.len() as u32).to_be_bytes());
+        buf.extend_from_slice(body);
+        buf
+    }
+
+    fn header() -> Vec<u8> {
+        header_at(0)
+    }
+
+    // Taken 'ms' milliseconds after the epoch
+    fn header_at(ms: u64) -> Vec<u8> {
+        let mut dump = b"JAVA PROFILE 1.0.2\0".to_vec();
+        dump.extend_from_slice(&8u32.to_be_bytes());
+        dump.extend_from_slice(&((ms >> 32) as u32).to_be_bytes());
+        dump.extend_from_slice(&(ms as u32).to_be_bytes());
+        dump
+    }
+
+    fn string(id: u64, value: &str) -> Vec<u8> {
+        let mut body = id.to_be_bytes().to_vec();
+        body.extend_from_slice(value.as_bytes());
+        record(0x01, &body)
+    }
+
+    fn start_thread(serial: u32, object_id: u64, name_id: u64) -> Vec<u8> {
+        let mut body = serial.to_be_bytes().to_vec();
+        body.extend_from_slice(&object_id.to_be_bytes());
+        body.extend_from_slice(&0u32.to_be_bytes());
+        body.extend_from_slice(&name_id.to_be_bytes());
+        body.extend_from_slice(&[0u8; 16]);
+        record(0x0A, &body)
+    }
+
+    #[test]
+    fn parse_from_memory() {
+        let mut dump = header();
+        dump.extend(string(7, "java/lang/Object"));
+
+        let mut load_class = 1u32.to_be_bytes().to_vec();
+        load_class.extend_from_slice(&0x1000u64.to_be_bytes());
+        load_class.extend_from_slice(&0u32.to_be_bytes());
+        load_class.extend_from_slice(&7u64.to_be_bytes());
+        dump.extend(record(0x02, &load_class));
+
+        let mut parser = HprofParser::new(Cursor::new(dump)).unwrap();
+        assert_eq!(parser.header.format, "JAVA PROFILE 1.0.2\0");
+        assert_eq!(parser.header.identifier_size, 8);
+        while !parser.done_parsing().unwrap() {
+            parse_record(&mut parser).unwrap();
+        }
+        assert_eq!(parser.string(7).as_deref(), Some("java/lang/Object"));
+        assert_eq!(parser.string_ids("java/lang/Object"), vec![7]);
+        assert_eq!(parser.class_tab[&1].object_id, 0x1000);
+        assert_eq!(parser.class_tab[&1].strname_id, 7);
+    }
+
+    #[test]
+    fn thread_names() {
+        let mut dump = header();
+        dump.extend(string(1, "main"));
+        dump.extend(string(2, "worker"));
+        dump.extend(start_thread(1, 0x100, 1));
+        dump.extend(start_thread(2, 0x200, 2));
+        // Records we don't care about are skipped
+        dump.extend(record(0x0E, &[0u8; 6]));
+        dump.extend(record(0x0B, &2u32.to_be_bytes()));
+
+        let jvm = HprofJavaVirtualMachine::new(Cursor::new(dump)).unwrap();
+        let threads = jvm.all_threads_vec().unwrap();
+        assert_eq!(threads.len(), 1);
+        assert_eq!(threads[0].name().unwrap(), "main");
+        assert_eq!(threads[0].unique_id().unwrap(), 0x100);
+    }
+
+    #[test]
+    fn strings_over_budget() {
+        let mut dump = header();
+        dump.extend(string(1, "main"));
+        dump.extend(string(2, "worker"));
+        dump.extend(string(3, "main"));
+
+        let mut parser = HprofParser::new(Cursor::new(dump)).unwrap();
+        parser.symbols = Symbols::new(4, StringCache::new());
+        while !parser.done_parsing().unwrap() {
+            parse_record(&mut parser).unwrap();
+        }
+        // "worker" doesn't fit, so it's read from the dump
+        assert!(matches!(parser.symbols.get(2), Some(Symbol::InFile { .. })));
+        assert_eq!(parser.string(2).as_deref(), Some("worker"));
+        assert_eq!(parser.string(1).as_deref(), Some("main"));
+        let mut ids = parser.string_ids("main");
+        ids.sort_unstable();
+        assert_eq!(ids, vec![1, 3]);
+        assert_eq!(parser.string_ids("worker"), vec![2]);
+        assert!(parser.string_ids("idle").is_empty());
+    }
+
+    #[test]
+    fn modified_utf8_strings() {
+        // "a\0\u{1f600}", with NUL and the surrogate pair in modified UTF-8
+        let modified = [b'a', 0xc0, 0x80, 0xed, 0xa0, 0xbd, 0xed, 0xb8, 0x80];
+        let mut dump = header();
+        dump.extend(string(1, "main"));
+        let mut body = 2u64.to_be_bytes().to_vec();
+        body.extend_from_slice(&modified);
+        dump.extend(record(0x01, &body));
+
+        let mut parser = HprofParser::new(Cursor::new(dump)).unwrap();
+        parser.symbols = Symbols::new(4, StringCache::new());
+        while !parser.done_parsing().unwrap() {
+            parse_record(&mut parser).unwrap();
+        }
+        // Decoded as it's read from the dump, as well as when it's parsed
+        assert!(matches!(parser.symbols.get(2), Some(Symbol::InFile { .. })));
+        assert_eq!(parser.string(2).as_deref(), Some("a\0\u{1f600}"));
+        assert_eq!(parser.string_ids("a\0\u{1f600}"), vec![2]);
+    }
+
+    #[test]
+    fn shared_string_cache() {
+        let dump = |name: &str| {
+            let mut dump = header();
+            dump.extend(string(7, "java/lang/Object"));
+            dump.extend(string(8, name));
+            let mut load_class = 1u32.to_be_bytes().to_vec();
+            load_class.extend_from_slice(&0x1000u64.to_be_bytes());
+            load_class.extend_from_slice(&0u32.to_be_bytes());
+            load_class.extend_from_slice(&7u64.to_be_bytes());
+            dump.extend(record(0x02, &load_class));
+            dump
+        };
+        let cache = StringCache::new();
+        let first =
+            HprofJavaVirtualMachine::with_string_cache(Cursor::new(dump("main")), 20, &cache)
+                .unwrap();
+        // "java/lang/Object" is already in the cache, so only "worker" counts towards the budget
+        let second =
+            HprofJavaVirtualMachine::with_string_cache(Cursor::new(dump("worker")), 6, &cache)
+                .unwrap();
+        assert_eq!(cache.len(), 3);
+        assert_eq!(
+            first.string_cache().bytes(),
+            "java/lang/Objectmainworker".len()
+        );
+        assert!(matches!(
+            second.dump.symbols.get(8),
+            Some(Symbol::Interned(_))
+        ));
+        assert_eq!(second.dump.string(8).as_deref(), Some("worker"));
+        let class = &second.dump.class_tab[&1];
+        assert_eq!(first.dump.class_name(class), "java.lang.Object");
+        assert_eq!(second.dump.class_name(class), "java.lang.Object");
+    }
+
+    // A dump which is still being written
+    struct Growing {
+        data: Rc<RefCell<Vec<u8>>>,
+        position: u64,
+    }
+
+    impl Read for Growing {
+        fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+            let data = self.data.borrow();
+            let start = (self.position as usize).min(data.len());
+            let n = buf.len().min(data.len() - start);
+            buf[..n].copy_from_slice(&data[start..start + n]);
+            self.position += n as u64;
+            Ok(n)
+        }
+    }
+
+    impl Seek for Growing {
+        fn seek(&mut self, pos: std::io::SeekFrom) -> std::io::Result<u64> {
+            self.position = match pos {
+                std::io::SeekFrom::Start(offset) => offset,
+                std::io::SeekFrom::End(offset) => (self.data.borrow().len() as i64 + offset) as u64,
+                std::io::SeekFrom::Current(offset) => (self.position as i64 + offset) as u64,
+            };
+            Ok(self.position)
+        }
+    }
+
+    #[test]
+    fn incremental() {
+        let data = Rc::new(RefCell::new(header()));
+        let growing = || Growing {
+            data: data.clone(),
+            position: 0,
+        };
+        data.borrow_mut().truncate(10);
+        assert!(IncrementalHprof::new(growing()).is_err());
+        *data.borrow_mut() = header();
+
+        let mut dump = IncrementalHprof::new(growing()).unwrap();
+        let first = string(1, "main");
+        let second = string(2, "worker");
+        data.borrow_mut().extend(&first);
+        // Half of the second record has been written
+        data.borrow_mut().extend(&second[..second.len() / 2]);
+        let checkpoint = dump.parse_available().unwrap();
+        assert_eq!(checkpoint.records, 1);
+        assert_eq!(checkpoint.offset, HEADER_SIZE + first.len() as u64);
+        assert!(!checkpoint.complete);
+
+        data.borrow_mut().extend(&second[second.len() / 2..]);
+        data.borrow_mut().extend(record(0x2C, &[]));
+        let checkpoint = dump
+            .parse_until_complete(Duration::from_millis(1), Duration::from_secs(1))
+            .unwrap();
+        assert_eq!(checkpoint.records, 3);
+        assert!(checkpoint.complete);
+        let jvm = dump.finish();
+        assert_eq!(jvm.dump.string(2).as_deref(), Some("worker"));
+    }
+
+    #[test]
+    fn incremental_unsegmented() {
+        // A 1.0.1 dump, with a single HEAP DUMP record and no HEAP DUMP END after it
+        let mut header = header();
+        header[17] = b'1';
+        let data = Rc::new(RefCell::new(header));
+        let mut dump = IncrementalHprof::new(Growing {
+            data: data.clone(),
+            position: 0,
+        })
+        .unwrap();
+        data.borrow_mut().extend(string(1, "main"));
+        let mut heap = vec![0x05];
+        heap.extend_from_slice(&0x10u64.to_be_bytes());
+        heap.extend(object_array(0x10, &[]));
+        data.borrow_mut().extend(record(0x0C, &heap));
+        let checkpoint = dump
+            .parse_until_complete(Duration::from_millis(1), Duration::from_millis(200))
+            .unwrap();
+        assert_eq!(checkpoint.records, 2);
+        assert!(checkpoint.complete);
+        assert!(dump.is_complete());
+    }
+
+    #[test]
+    fn incremental_placeholder_lengths() {
+        // JDK 8 writes 0 for a heap dump record's length until the record's finished
+        let mut segment = vec![0x05];
+        segment.extend_from_slice(&0x10u64.to_be_bytes());
+        segment.extend(object_array(0x10, &[]));
+        let data = Rc::new(RefCell::new(header()));
+        let mut dump = IncrementalHprof::new(Growing {
+            data: data.clone(),
+            position: 0,
+        })
+        .unwrap();
+        let start = data.borrow().len();
+        data.borrow_mut().extend(record(0x1C, &[]));
+        data.borrow_mut().extend(&segment);
+        assert_eq!(dump.parse_available().unwrap().records, 0);
+
+        // Finished, but there could be another segment to come
+        let length = (segment.len() as u32).to_be_bytes();
+        data.borrow_mut()[start + 5..start + 9].copy_from_slice(&length);
+        assert_eq!(dump.parse_available().unwrap().records, 0);
+        data.borrow_mut().extend(record(0x2C, &[]));
+        let checkpoint = dump.parse_available().unwrap();
+        assert_eq!(checkpoint.records, 2);
+        assert!(checkpoint.complete);
+        assert!(dump.finish().dump.object_offsets.get(0x10).is_some());
+
+        // The same for a 1.0.1 dump's HEAP DUMP, which nothing comes after
+        let mut header = header();
+        header[17] = b'1';
+        let start = header.len();
+        let data = Rc::new(RefCell::new(header));
+        let mut dump = IncrementalHprof::new(Growing {
+            data: data.clone(),
+            position: 0,
+        })
+        .unwrap();
+        data.borrow_mut().extend(record(0x0C, &[]));
+        assert!(!dump.parse_available().unwrap().complete);
+        data.borrow_mut().extend(&segment);
+        assert!(!dump.parse_available().unwrap().complete);
+        data.borrow_mut()[start + 5..start + 9].copy_from_slice(&length);
+        let checkpoint = dump.parse_available().unwrap();
+        assert_eq!(checkpoint.records, 1);
+        assert!(checkpoint.complete);
+    }
+
+    #[test]
+    fn validate() {
+        let mut segment = vec![0x05];
+        segment.extend_from_slice(&0x10u64.to_be_bytes());
+        segment.extend(object_array(0x10, &[0x20]));
+        segment.extend(object_array(0x20, &[]));
+        let mut dump = header();
+        dump.extend(string(1, "main"));
+        dump.extend(record(0x1C, &segment));
+        let end_of_segment = dump.len() as u64;
+        dump.extend(record(0x2C, &[]));
+
+        let report = HprofJavaVirtualMachine::validate(Cursor::new(dump.clone())).unwrap();
+        assert!(report.is_intact(), "{:?}", report.problems);
+        assert_eq!((report.records, report.strings), (3, 1));
+        assert_eq!((report.objects, report.gc_roots), (2, 1));
+
+        // Cut off in the middle of the segment
+        let truncated = &dump[..dump.len() - 20];
+        let report = HprofJavaVirtualMachine::validate(Cursor::new(truncated.to_vec())).unwrap();
+        assert!(!report.is_intact());
+        assert!(report.is_recoverable());
+        assert_eq!(
+            report.valid_bytes,
+            HEADER_SIZE + string(1, "main").len() as u64
+        );
+        assert_eq!((report.records, report.objects), (1, 0));
+        assert!(report.problems[0].message.starts_with("Truncated"));
+
+        // Cut off after a whole segment, but before the end
+        let report = HprofJavaVirtualMachine::validate(Cursor::new(
+            dump[..end_of_segment as usize].to_vec(),
+        ))
+        .unwrap();
+        assert_eq!(report.valid_bytes, end_of_segment);
+        assert_eq!(report.problems.len(), 1);
+
+        // A segment whose last subrecord runs past its end
+        let mut bad = header();
+        bad.extend(record(0x1C, &segment[..segment.len() - 1]));
+        let report = HprofJavaVirtualMachine::validate(Cursor::new(bad)).unwrap();
+        assert_eq!(report.valid_bytes, HEADER_SIZE);
+        assert!(report.problems[0]
+            .message
+            .contains("past the end of its segment"));
+    }
+
+    #[test]
+    fn find_symbols() {
+        let mut dump = header();
+        dump.extend(string(1, "SELECT * FROM users"));
+        dump.extend(string(2, "main"));
+        dump.extend(string(3, "select id from orders"));
+
+        let jvm = HprofJavaVirtualMachine::new(Cursor::new(dump)).unwrap();
+        let report = jvm
+            .find_strings_where(|s| s.to_lowercase().contains("select"))
+            .unwrap();
+        let ids: Vec<u64> = report.symbols.iter().map(|m| m.id).collect();
+        assert_eq!(ids, vec![1, 3]);
+        assert!(report.strings.is_empty());
+    }
+
+    #[test]
+    fn unknown_records() {
+        let mut dump = header();
+        dump.extend(string(1, "main"));
+        dump.extend(record(0x7F, &[1, 2, 3]));
+        dump.extend(record(0x7F, &[4]));
+        // A root, then a subrecord from the future, which takes the rest of the segment with it
+        let mut segment = vec![0x05];
+        segment.extend_from_slice(&0x1000u64.to_be_bytes());
+        segment.extend_from_slice(&[0x77, 0, 0, 0]);
+        dump.extend(record(0x1C, &segment));
+        dump.extend(start_thread(1, 0x100, 1));
+
+        let jvm = HprofJavaVirtualMachine::new(Cursor::new(dump)).unwrap();
+        assert_eq!(jvm.all_threads_vec().unwrap()[0].name().unwrap(), "main");
+        assert_eq!(jvm.dump.roots.len(), 1);
+        let warnings = jvm.parse_warnings();
+        assert_eq!(warnings.len(), 2);
+        assert_eq!((warnings[0].subrecord, warnings[0].tag), (false, 0x7F));
+        assert_eq!((warnings[0].count, warnings[0].skipped_bytes), (2, 4));
+        assert_eq!((warnings[1].subrecord, warnings[1].tag), (true, 0x77));
+        assert_eq!(warnings[1].skipped_bytes, 4);
+        assert_eq!(jvm.skipped_records(), 3);
+    }
+
+    #[test]
+    fn short_string_record() {
+        let mut dump = header();
+        dump.extend(record(0x01, &[0, 0, 0, 1]));
+        dump.extend(string(1, "main"));
+        dump.extend(start_thread(1, 0x100, 1));
+
+        let jvm = HprofJavaVirtualMachine::new(Cursor::new(dump.clone())).unwrap();
+        assert_eq!(jvm.all_threads_vec().unwrap()[0].name().unwrap(), "main");
+        let warnings = jvm.parse_warnings();
+        assert_eq!(warnings.len(), 1);
+        assert_eq!((warnings[0].tag, warnings[0].malformed), (0x01, true));
+        assert_eq!(
+            (warnings[0].offset, warnings[0].skipped_bytes),
+            (HEADER_SIZE, 4)
+        );
+
+        let report = HprofJavaVirtualMachine::validate(Cursor::new(dump)).unwrap();
+        assert_eq!(report.valid_bytes, HEADER_SIZE);
+        assert!(report.problems[0].message.contains("too short"));
+    }
+
+    #[test]
+    fn truncated() {
+        let mut dump = header();
+        let mut boundaries = vec![dump.len()];
+        dump.extend(string(1, "main"));
+        boundaries.push(dump.len());
+        dump.extend(start_thread(1, 0x100, 1));
+        boundaries.push(dump.len());
+        // Skipped over rather than read, so being cut off inside it is only noticed at the end
+        dump.extend(record(0x1C, &raw_instance_dump(0x100, 0x200, &[0; 16])));
+        boundaries.push(dump.len());
+
+        for length in 0..=dump.len() {
+            let jvm = HprofJavaVirtualMachine::new(Cursor::new(dump[..length].to_vec()));
+            match jvm {
+                Ok(_) => assert!(boundaries.contains(&length), "cut at {}", length),
+                Err(e) => {
+                    assert!(!boundaries.contains(&length), "cut at {}: {}", length, e);
+                    assert_eq!(e.kind(), ErrorKind::UnexpectedEof);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn four_byte_identifiers() {
+        let mut dump = header();
+        dump[19..23].copy_from_slice(&4u32.to_be_bytes());
+
+        let e = HprofJavaVirtualMachine::new(Cursor::new(dump))
+            .err()
+            .unwrap();
+        assert_eq!(e.kind(), ErrorKind::InvalidData);
+        assert_eq!(e.to_string(), "4-byte identifiers aren't supported");
+    }
+
+    #[test]
+    fn unknown_field_type() {
+        let mut dump = header();
+        let segment = typed_class_dump(0x100, 0, &[(1, 0x55)]);
+        dump.extend(record(0x1C, &segment));
+
+        let e = HprofJavaVirtualMachine::new(Cursor::new(dump))
+            .err()
+            .unwrap();
+        assert_eq!(e.kind(), ErrorKind::InvalidData);
+        // The field's type is the last byte of the class dump
+        let offset = HEADER_SIZE + 9 + segment.len() as u64 - 1;
+        assert_eq!(
+            e.to_string(),
+            format!("Unknown field type 0x55 at {}", offset)
+        );
+    }
+
+    fn object_array(id: u64, elements: &[u64]) -> Vec<u8> {
+        let mut body = vec![0x22];
+        body.extend_from_slice(&id.to_be_bytes());
+        body.extend_from_slice(&0u32.to_be_bytes());
+        body.extend_from_slice(&(elements.len() as u32).to_be_bytes());
+        body.extend_from_slice(&0u64.to_be_bytes());
+        for element in elements {
+            body.extend_from_slice(&element.to_be_bytes());
+        }
+        body
+    }
+
+    #[test]
+    fn referrers() {
+        // A sticky class root holding A, which refers to B and C, and B to C again
+        let mut segment = vec![0x05];
+        segment.extend_from_slice(&0x10u64.to_be_bytes());
+        segment.extend(object_array(0x10, &[0x20, 0x30]));
+        segment.extend(object_array(0x20, &[0x30, 0x30]));
+        segment.extend(object_array(0x30, &[]));
+        segment.extend(object_array(0x40, &[0x30]));
+        let mut dump = header();
+        dump.extend(record(0x1C, &segment));
+        let jvm = HprofJavaVirtualMachine::new(Cursor::new(dump)).unwrap();
+
+        let index = ReferrerIndex::build(&jvm, 1 << 20).unwrap();
+        assert!(!index.is_banded());
+        assert_eq!(index.passes(), 2);
+        assert_eq!(index.referrers(0x30).unwrap(), vec![0x10, 0x20, 0x40]);
+        assert_eq!(index.referrer_count(0x30), Some(4));
+        assert_eq!(index.referrers(0x10).unwrap(), Vec::<u64>::new());
+        assert_eq!(index.path_to_root(0x30).unwrap(), Some(vec![0x10, 0x30]));
+        assert_eq!(index.path_to_root(0x40).unwrap(), None);
+        assert_eq!(index.passes(), 2);
+
+        // One object per band, so following referrers reads the dump again
+        let banded = ReferrerIndex::build(&jvm, 1).unwrap();
+        assert!(banded.is_banded());
+        assert_eq!(banded.passes(), 1);
+        assert_eq!(banded.referrers(0x30).unwrap(), vec![0x10, 0x20, 0x40]);
+        assert_eq!(banded.referrers(0x20).unwrap(), vec![0x10]);
+        assert_eq!(banded.passes(), 3);
+        assert_eq!(banded.path_to_root(0x30).unwrap(), Some(vec![0x10, 0x30]));
+        let view = jvm.object(0x30).unwrap();
+        let path: Vec<u64> = view
+            .path_to_root(&banded)
+            .unwrap()
+            .unwrap()
+            .iter()
+            .map(|object| object.id())
+            .collect();
+        assert_eq!(path, vec![0x10, 0x30]);
+    }
+
+    #[test]
+    fn dominator_tree() {
+        // As above: A refers to B and C, and B to C, so A dominates both. D is garbage.
+        let mut segment = vec![0x05];
+        segment.extend_from_slice(&0x10u64.to_be_bytes());
+        segment.extend(object_array(0x10, &[0x20, 0x30]));
+        segment.extend(object_array(0x20, &[0x30, 0x30]));
+        segment.extend(object_array(0x30, &[]));
+        segment.extend(object_array(0x40, &[0x30]));
+        let mut dump = header();
+        dump.extend(record(0x1C, &segment));
+        let jvm = HprofJavaVirtualMachine::new(Cursor::new(dump)).unwrap();
+
+        let tree = jvm.dominator_tree().unwrap();
+        assert_eq!(tree.roots(), vec![0x10]);
+        // B is bigger than C, having two elements
+        assert_eq!(tree.children(0x10), vec![0x20, 0x30]);
+        assert!(tree.children(0x40).is_empty());
+        assert_eq!(tree.dominator(0x30), Some(0x10));
+        assert_eq!(tree.dominator(0x10), None);
+        let shallow = |id| jvm.object(id).unwrap().shallow_size().unwrap();
+        assert_eq!(tree.shallow(0x10), Some(shallow(0x10)));
+        assert_eq!(
+            tree.retained(0x10),
+            Some(shallow(0x10) + shallow(0x20) + shallow(0x30))
+        );
+        assert_eq!(tree.retained(0x40), Some(0));
+
+        let rows = tree.rows(0).unwrap();
+        let parents: Vec<(u64, Option<u64>)> =
+            rows.iter().map(|row| (row.object_id, row.parent)).collect();
+        assert_eq!(
+            parents,
+            vec![(0x10, None), (0x20, Some(0x10)), (0x30, Some(0x10))]
+        );
+        let rows = tree.rows(shallow(0x20)).unwrap();
+        assert_eq!(rows.len(), 2);
+        assert_eq!(rows[1].shallow_bytes, rows[1].retained_bytes);
+    }
+
+    #[test]
+    fn object_sets() {
+        // A refers to B and C, and B to C. D refers to C too, but is garbage.
+        let mut segment = vec![0x05];
+        segment.extend_from_slice(&0x10u64.to_be_bytes());
+        segment.extend(object_array(0x10, &[0x20, 0x30]));
+        segment.extend(object_array(0x20, &[0x30, 0x30]));
+        segment.extend(object_array(0x30, &[]));
+        segment.extend(object_array(0x40, &[0x30]));
+        let mut dump = header();
+        dump.extend(record(0x1C, &segment));
+        let jvm = HprofJavaVirtualMachine::new(Cursor::new(dump)).unwrap();
+
+        let a = jvm.set_of(&[0x10, 0x99]).unwrap();
+        let b = jvm.set_of(&[0x20]).unwrap();
+        assert_eq!(a.ids(), vec![0x10]);
+        assert_eq!(jvm.gc_root_set().unwrap().ids(), vec![0x10]);
+        assert_eq!(a.retained().ids(), vec![0x10, 0x20, 0x30]);
+        // C is reachable without B, so B only retains itself
+        assert_eq!(b.retained().ids(), vec![0x20]);
+        assert_eq!(jvm.reachable_from(&b).unwrap().ids(), vec![0x20, 0x30]);
+        let roots = jvm.gc_root_set().unwrap();
+        assert_eq!(
+            jvm.reachable_avoiding(&roots, &b).unwrap().ids(),
+            vec![0x10, 0x30]
+        );
+        assert_eq!((&a | &b).len(), 2);
+        assert!((&a & &b).is_empty());
+        assert_eq!((&a.retained() - &b).ids(), vec![0x10, 0x30]);
+        assert_eq!(
+            a.retained_size(),
+            jvm.object(0x10).unwrap().retained_size().unwrap()
+        );
+
+        let arrays = jvm.instance_set(&ClassPattern::any()).unwrap();
+        assert_eq!(arrays.len(), 4);
+        assert!(arrays.contains(0x40));
+    }
+
+    fn load_class(serial: u32, class_object_id: u64, name_id: u64) -> Vec<u8> {
+        let mut body = serial.to_be_bytes().to_vec();
+        body.extend_from_slice(&class_object_id.to_be_bytes());
+        body.extend_from_slice(&0u32.to_be_bytes());
+        body.extend_from_slice(&name_id.to_be_bytes());
+        record(0x02, &body)
+    }
+
+    // A class dump subrecord whose instance fields are all objects
+    fn class_dump(class_object_id: u64, superclass_object_id: u64, fields: &[u64]) -> Vec<u8> {
+        let fields: Vec<(u64, u8)> = fields.iter().map(|&name_id| (name_id, 0x02)).collect();
+        typed_class_dump(class_object_id, superclass_object_id, &fields)
+    }
+
+    // With the instance fields as (name ID, type tag)
+    fn typed_class_dump(
+        class_object_id: u64,
+        superclass_object_id: u64,
+        fields: &[(u64, u8)],
+    ) -> Vec<u8> {
+        let mut body = vec![0x20];
+        body.extend_from_slice(&class_object_id.to_be_bytes());
+        body.extend_from_slice(&0u32.to_be_bytes());
+        body.extend_from_slice(&superclass_object_id.to_be_bytes());
+        body.extend_from_slice(&[0u8; 40]);
+        body.extend_from_slice(&(fields.len() as u32 * 8).to_be_bytes());
+        body.extend_from_slice(&[0u8; 4]);
+        body.extend_from_slice(&(fields.len() as u16).to_be_bytes());
+        for &(name_id, field_type) in fields {
+            body.extend_from_slice(&name_id.to_be_bytes());
+            body.push(field_type);
+        }
+        body
+    }
+
+    // An instance dump subrecord whose fields are all objects
+    fn instance_dump(object_id: u64, class_object_id: u64, values: &[u64]) -> Vec<u8> {
+        let values: Vec<u8> = values.iter().flat_map(|v| v.to_be_bytes()).collect();
+        raw_instance_dump(object_id, class_object_id, &values)
+    }
+
+    fn raw_instance_dump(object_id: u64, class_object_id: u64, values: &[u8]) -> Vec<u8> {
+        let mut body = vec![0x21];
+        body.extend_from_slice(&object_id.to_be_bytes());
+        body.extend_from_slice(&0u32.to_be_bytes());
+        body.extend_from_slice(&class_object_id.to_be_bytes());
+        body.extend_from_slice(&(values.len() as u32).to_be_bytes());
+        body.extend_from_slice(values);
+        body
+    }
+
+    #[test]
+    fn records() {
+        let mut dump = header();
+        dump.extend(string(1, "java/lang/Record"));
+        dump.extend(string(2, "Point"));
+        dump.extend(string(3, "x"));
+        dump.extend(string(4, "y"));
+        dump.extend(load_class(1, 0x100, 1));
+        dump.extend(load_class(2, 0x200, 2));
+        let mut segment = class_dump(0x100, 0, &[]);
+        segment.extend(typed_class_dump(0x200, 0x100, &[(3, 0x0a), (4, 0x0a)]));
+        dump.extend(record(0x1C, &segment));
+        let jvm = HprofJavaVirtualMachine::new(Cursor::new(dump)).unwrap();
+
+        let point = jvm.classes_by_name("Point").unwrap().remove(0);
+        assert!(point.is_record().unwrap());
+        assert_eq!(point.record_components().unwrap(), ["x", "y"]);
+        assert_eq!(point.permitted_subclasses().unwrap(), None);
+        let record = jvm.classes_by_name("java.lang.Record").unwrap().remove(0);
+        assert!(!record.is_record().unwrap());
+        assert!(record.record_components().unwrap().is_empty());
+    }
+
+    #[test]
+    fn class_objects() {
+        let mut dump = header();
+        dump.extend(string(1, "Holder"));
+        dump.extend(string(2, "type"));
+        dump.extend(string(3, "java/lang/String"));
+        dump.extend(load_class(1, 0x100, 1));
+        dump.extend(load_class(2, 0x200, 3));
+        let mut segment = class_dump(0x100, 0, &[2]);
+        segment.extend(class_dump(0x200, 0, &[]));
+        segment.extend(instance_dump(0x1000, 0x100, &[0x200]));
+        segment.extend(instance_dump(0x1100, 0x100, &[0x1000]));
+        segment.extend(object_array(0x2000, &[0x200, 0x1000, 0]));
+        dump.extend(record(0x1C, &segment));
+        let jvm = HprofJavaVirtualMachine::new(Cursor::new(dump)).unwrap();
+
+        // Not the inherent object(), which gives a view of the object
+        let object = |id| JavaVirtualMachine::object(&jvm, id).unwrap();
+        let holder = jvm.classes_by_name("Holder").unwrap().remove(0);
+        let field = holder.fields_vec().unwrap().remove(0);
+        let value = object(0x1000).get_value(&field).unwrap();
+        assert_eq!(value, Value::ClassObject(0x200));
+        let class = object(0x200).reflected_type().unwrap().unwrap();
+        assert_eq!(class.name().unwrap(), "java.lang.String");
+        let value = object(0x1100).get_value(&field).unwrap();
+        assert_eq!(value, Value::Object(0x1000));
+        assert_eq!(
+            jvm.array_values(0x2000, 0, 3).unwrap(),
+            [
+                Value::ClassObject(0x200),
+                Value::Object(0x1000),
+                Value::Null
+            ]
+        );
+    }
+
+    #[test]
+    fn enums() {
+        let mut dump = header();
+        for (id, name) in [
+            (1, "java/lang/Enum"),
+            (2, "Color"),
+            (3, "name"),
+            (4, "ordinal"),
+            (5, "java/lang/String"),
+            (6, "value"),
+            (7, "Color$1"),
+        ] {
+            dump.extend(string(id, name));
+        }
+        dump.extend(load_class(1, 0x100, 1));
+        dump.extend(load_class(2, 0x200, 2));
+        dump.extend(load_class(3, 0x300, 5));
+        dump.extend(load_class(4, 0x400, 7));
+        let mut segment = typed_class_dump(0x100, 0, &[(3, 0x02), (4, 0x0a)]);
+        // Color has a field called name of its own
+        segment.extend(class_dump(0x200, 0x100, &[3]));
+        segment.extend(class_dump(0x300, 0, &[6]));
+        segment.extend(class_dump(0x400, 0x200, &[]));
+        let constant = |object_id, class_object_id, own_name: u64, name: u64, ordinal: u32| {
+            let mut values = own_name.to_be_bytes().to_vec();
+            values.extend_from_slice(&name.to_be_bytes());
+            values.extend_from_slice(&ordinal.to_be_bytes());
+            raw_instance_dump(object_id, class_object_id, &values)
+        };
+        segment.extend(constant(0x1000, 0x200, 0x3001, 0x2001, 0));
+        segment.extend(constant(0x1100, 0x400, 0, 0x2002, 1));
+        for (object_id, text) in [(0x2001u64, "RED"), (0x2002, "BLUE"), (0x3001, "Red")] {
+            segment.extend(instance_dump(object_id, 0x300, &[object_id + 0x100]));
+            segment.push(0x23);
+            segment.extend_from_slice(&(object_id + 0x100).to_be_bytes());
+            segment.extend_from_slice(&0u32.to_be_bytes());
+            segment.extend_from_slice(&(text.len() as u32).to_be_bytes());
+            segment.push(0x08);
+            segment.extend_from_slice(text.as_bytes());
+        }
+        dump.extend(record(0x1C, &segment));
+        let jvm = HprofJavaVirtualMachine::new(Cursor::new(dump)).unwrap();
+
+        let red = jvm.object(0x1000).unwrap();
+        assert_eq!(red.as_enum().unwrap().as_deref(), Some("Color.RED"));
+        assert_eq!(red.render().unwrap(), "Color.RED");
+        // A constant with a body
+        let blue = jvm.object(0x1100).unwrap();
+        assert_eq!(blue.as_enum().unwrap().as_deref(), Some("Color.BLUE"));
+        let string = jvm.object(0x3001).unwrap();
+        assert_eq!(string.as_enum().unwrap(), None);
+        assert_eq!(string.render().unwrap(), "\"Red\"");
+
+        let color = jvm.classes_by_name("Color").unwrap().remove(0);
+        assert!(color.is_enum().unwrap());
+        let body = jvm.classes_by_name("Color$1").unwrap().remove(0);
+        assert!(!body.is_enum().unwrap());
+    }
+
+    #[test]
+    fn referents() {
+        let mut dump = header();
+        dump.extend(string(1, "java/lang/ref/Reference"));
+        dump.extend(string(2, "java/lang/ref/WeakReference"));
+        dump.extend(string(3, "referent"));
+        dump.extend(load_class(1, 0x100, 1));
+        dump.extend(load_class(2, 0x200, 2));
+        // A WeakReference held by a GC root, and what it refers to, which nothing else does
+        let mut segment = vec![];
+        for root in [0x100u64, 0x200, 0x1000] {
+            segment.push(0x05);
+            segment.extend_from_slice(&root.to_be_bytes());
+        }
+        segment.extend(class_dump(0x100, 0, &[3]));
+        segment.extend(class_dump(0x200, 0x100, &[]));
+        segment.extend(instance_dump(0x1000, 0x200, &[0x2000]));
+        segment.extend(object_array(0x2000, &[0x2000; 100]));
+        dump.extend(record(0x1C, &segment));
+        let jvm = HprofJavaVirtualMachine::new(Cursor::new(dump)).unwrap();
+        let weak = jvm.object(0x1000).unwrap();
+        let referent = jvm.object(0x2000).unwrap();
+
+        assert!(!jvm.follows_referents());
+        assert!(referent.path_from_root().unwrap().is_none());
+        assert_eq!(referent.retained_size().unwrap(), 0);
+        let weak_size = weak.shallow_size().unwrap();
+        assert_eq!(weak.retained_size().unwrap(), weak_size);
+        let index = ReferrerIndex::build(&jvm, 1 << 20).unwrap();
+        assert_eq!(index.referrers(0x2000).unwrap(), vec![0x2000]);
+
+        jvm.set_follow_referents(true);
+        let path: Vec<u64> = referent
+            .path_from_root()
+            .unwrap()
+            .unwrap()
+            .iter()
+            .map(|object| object.id())
+            .collect();
+        assert_eq!(path, vec![0x1000, 0x2000]);
+        assert_eq!(
+            weak.retained_size().unwrap(),
+            weak_size + referent.shallow_size().unwrap()
+        );
+        // The index built before keeps to what it saw then
+        assert_eq!(index.path_to_root(0x2000).unwrap(), None);
+    }
+
+    #[test]
+    fn finalization() {
+        let mut dump = header();
+        dump.extend(string(1, "java/lang/ref/Reference"));
+        dump.extend(string(2, "java/lang/ref/Finalizer"));
+        dump.extend(string(3, "referent"));
+        dump.extend(string(4, "Resource"));
+        dump.extend(string(5, "buffer"));
+        dump.extend(load_class(1, 0x100, 1));
+        dump.extend(load_class(2, 0x200, 2));
+        dump.extend(load_class(3, 0x300, 4));
+        // Two Finalizers, one of them for a Resource which a GC root still holds
+        let mut segment = vec![];
+        for root in [0x100u64, 0x200, 0x300, 0x1000, 0x1001, 0x2001] {
+            segment.push(0x05);
+            segment.extend_from_slice(&root.to_be_bytes());
+        }
+        segment.extend(class_dump(0x100, 0, &[3]));
+        segment.extend(class_dump(0x200, 0x100, &[]));
+        segment.extend(class_dump(0x300, 0, &[5]));
+        segment.extend(instance_dump(0x1000, 0x200, &[0x2000]));
+        segment.extend(instance_dump(0x1001, 0x200, &[0x2001]));
+        segment.extend(instance_dump(0x2000, 0x300, &[0x3000]));
+        segment.extend(instance_dump(0x2001, 0x300, &[0]));
+        segment.extend(object_array(0x3000, &[0; 10]));
+        dump.extend(record(0x1C, &segment));
+        let jvm = HprofJavaVirtualMachine::new(Cursor::new(dump)).unwrap();
+
+        let report = jvm.finalization_report().unwrap();
+        assert_eq!(report.entries.len(), 1);
+        let entry = &report.entries[0];
+        assert_eq!(entry.kind, FinalizationKind::Finalizer);
+        assert_eq!(entry.class_name, "Resource");
+        assert_eq!((entry.pending, entry.live), (1, 1));
+        let resource = jvm.object(0x2000).unwrap().shallow_size().unwrap();
+        let buffer = jvm.object(0x3000).unwrap().shallow_size().unwrap();
+        assert_eq!(entry.pending_shallow_bytes, resource);
+        assert_eq!(entry.pending_retained_bytes, resource + buffer);
+        assert_eq!(report.pending_retained_bytes(), resource + buffer);
+    }
+
+    #[test]
+    fn direct_buffers() {
+        let mut dump = header();
+        dump.extend(string(1, "java/nio/DirectByteBuffer"));
+        dump.extend(string(2, "Pool"));
+        for (id, name) in [(3, "capacity"), (4, "att"), (5, "cleaner"), (6, "fd")] {
+            dump.extend(string(id, name));
+        }
+        dump.extend(load_class(1, 0x100, 1));
+        dump.extend(load_class(2, 0x200, 2));
+        let buffer = |id: u64, capacity: i32, att: u64, cleaner: u64, fd: u64| {
+            let mut values = capacity.to_be_bytes().to_vec();
+            for value in [att, cleaner, fd] {
+                values.extend_from_slice(&value.to_be_bytes());
+            }
+            raw_instance_dump(id, 0x100, &values)
+        };
+        let mut segment = vec![];
+        for root in [0x100u64, 0x200, 0x1002, 0x3000] {
+            segment.push(0x05);
+            segment.extend_from_slice(&root.to_be_bytes());
+        }
+        segment.extend(typed_class_dump(
+            0x100,
+            0,
+            &[(3, 0x0A), (4, 0x02), (5, 0x02), (6, 0x02)],
+        ));
+        segment.extend(class_dump(0x200, 0, &[4, 5]));
+        // A Pool holding a buffer and a slice of it, a mapped file held by a root, and a buffer
+        // nothing refers to any more
+        segment.extend(instance_dump(0x3000, 0x200, &[0x1000, 0x1001]));
+        segment.extend(buffer(0x1000, 4096, 0, 0x5000, 0));
+        segment.extend(buffer(0x1001, 100, 0x1000, 0, 0));
+        segment.extend(buffer(0x1002, 1 << 20, 0, 0x5001, 0x6000));
+        segment.extend(buffer(0x1003, 10, 0, 0x5002, 0));
+        for id in [0x5000, 0x5001, 0x5002, 0x6000] {
+            segment.extend(object_array(id, &[]));
+        }
+        dump.extend(record(0x1C, &segment));
+        let jvm = HprofJavaVirtualMachine::new(Cursor::new(dump)).unwrap();
+
+        let report = jvm.direct_buffer_report().unwrap();
+        assert_eq!(report.views, 1);
+        let buffers: Vec<_> = report
+            .entries
+            .iter()
+            .map(|e| (e.object, e.kind, e.capacity, e.reachable, e.holder))
+            .collect();
+        assert_eq!(
+            buffers,
+            vec![
+                (0x1002, DirectBufferKind::Mapped, 1 << 20, true, None),
+                (
+                    0x1000,
+                    DirectBufferKind::Allocated,
+                    4096,
+                    true,
+                    Some(0x3000)
+                ),
+                (0x1003, DirectBufferKind::Allocated, 10, false, None),
+            ]
+        );
+        assert_eq!(report.entries[1].holder_class.as_deref(), Some("Pool"));
+        assert_eq!(report.total_capacity(), (1 << 20) + 4096 + 10);
+        let groups = report.groups();
+        assert_eq!(groups.len(), 3);
+        assert_eq!(groups[1].holder_class.as_deref(), Some("Pool"));
+        assert!(!groups[2].reachable);
+    }
+
+    #[test]
+    fn file_descriptors() {
+        let mut dump = header();
+        let names = [
+            (1, "java/io/FileDescriptor"),
+            (2, "java/net/SocketImpl"),
+            (3, "sun/nio/ch/NioSocketImpl"),
+            (4, "java/nio/channels/spi/AbstractInterruptibleChannel"),
+            (5, "sun/nio/ch/FileChannelImpl"),
+            (6, "Pool"),
+            (7, "fd"),
+            (8, "first"),
+            (9, "second"),
+        ];
+        for (id, name) in names {
+            dump.extend(string(id, name));
+        }
+        for (serial, class) in [
+            (1, 0x100u64),
+            (2, 0x200),
+            (3, 0x210),
+            (4, 0x300),
+            (5, 0x310),
+        ] {
+            dump.extend(load_class(serial, class, u64::from(serial)));
+        }
+        dump.extend(load_class(6, 0x400, 6));
+        let descriptor = |id: u64, fd: i32| raw_instance_dump(id, 0x100, &fd.to_be_bytes());
+        let mut segment = vec![];
+        for root in [
+            0x100u64, 0x200, 0x210, 0x300, 0x310, 0x400, 0x3000, 0x1001, 0x1002,
+        ] {
+            segment.push(0x05);
+            segment.extend_from_slice(&root.to_be_bytes());
+        }
+        segment.extend(typed_class_dump(0x100, 0, &[(7, 0x0A)]));
+        segment.extend(class_dump(0x200, 0, &[7]));
+        segment.extend(class_dump(0x210, 0x200, &[]));
+        segment.extend(class_dump(0x300, 0, &[7]));
+        segment.extend(class_dump(0x310, 0x300, &[]));
+        segment.extend(class_dump(0x400, 0, &[8, 9]));
+        // A Pool holding an open socket and a descriptor of its own, a socket which was never
+        // opened, and a closed file channel
+        segment.extend(instance_dump(0x3000, 0x400, &[0x1000, 0x2002]));
+        segment.extend(instance_dump(0x1000, 0x210, &[0x2000]));
+        segment.extend(instance_dump(0x1001, 0x210, &[0]));
+        segment.extend(instance_dump(0x1002, 0x310, &[0x2001]));
+        segment.extend(descriptor(0x2000, 5));
+        segment.extend(descriptor(0x2001, -1));
+        segment.extend(descriptor(0x2002, 0));
+        dump.extend(record(0x1C, &segment));
+        let jvm = HprofJavaVirtualMachine::new(Cursor::new(dump)).unwrap();
+
+        let report = jvm.file_descriptor_report().unwrap();
+        // The descriptors the socket and channel own aren't reported on their own
+        let expected = [
+            (
+                0x1000,
+                "sun.nio.ch.NioSocketImpl",
+                ResourceKind::Socket,
+                Some(5),
+                true,
+                Some("Pool"),
+            ),
+            (
+                0x1001,
+                "sun.nio.ch.NioSocketImpl",
+                ResourceKind::Socket,
+                None,
+                false,
+                None,
+            ),
+            (
+                0x1002,
+                "sun.nio.ch.FileChannelImpl",
+                ResourceKind::Channel,
+                Some(-1),
+                false,
+                None,
+            ),
+            (
+                0x2002,
+                "java.io.FileDescriptor",
+                ResourceKind::FileDescriptor,
+                Some(0),
+                true,
+                Some("Pool"),
+            ),
+        ];
+        assert_eq!(report.entries.len(), expected.len());
+        for (entry, &(object, class, kind, fd, open, holder)) in
+            report.entries.iter().zip(expected.iter())
+        {
+            assert_eq!(entry.object, object);
+            assert_eq!(entry.class, class, "{:#x}", object);
+            assert_eq!(entry.kind, kind, "{:#x}", object);
+            assert_eq!(entry.fd, fd, "{:#x}", object);
+            assert_eq!(entry.is_open(), open, "{:#x}", object);
+            assert_eq!(entry.holder_class.as_deref(), holder, "{:#x}", object);
+            assert!(entry.allocation_stack.is_empty());
+        }
+        assert_eq!(report.open().count(), 2);
+        assert_eq!(report.count(ResourceKind::Socket), 2);
+        assert_eq!(report.count(ResourceKind::FileDescriptor), 1);
+
+        let groups: Vec<_> = report
+            .groups()
+            .iter()
+            .map(|g| (g.kind, g.holder_class.clone(), g.count, g.open))
+            .collect();
+        let pool = Some("Pool".to_string());
+        assert_eq!(
+            groups,
+            vec![
+                (ResourceKind::Socket, pool.clone(), 1, 1),
+                (ResourceKind::FileDescriptor, pool, 1, 1),
+                (ResourceKind::Socket, None, 1, 0),
+                (ResourceKind::Channel, None, 1, 0),
+            ]
+        );
+    }
+
+    #[test]
+    fn timeline() {
+        // A Cache whose map grows, and a Session which only the second dump has. The objects move
+        // between the dumps.
+        let dump = |ms: u64, base: u64, entries: usize, session: bool| {
+            let mut dump = header_at(ms);
+            dump.extend(string(1, "Cache"));
+            dump.extend(string(2, "Session"));
+            dump.extend(string(3, "map"));
+            dump.extend(load_class(1, 0x100, 1));
+            dump.extend(load_class(2, 0x200, 2));
+            let mut segment = vec![];
+            let roots = if session { 2 } else { 1 };
+            for root in [base, base + 0x10].iter().take(roots) {
+                segment.push(0x05);
+                segment.extend_from_slice(&root.to_be_bytes());
+            }
+            segment.extend(class_dump(0x100, 0, &[3]));
+            segment.extend(class_dump(0x200, 0, &[3]));
+            segment.extend(instance_dump(base, 0x100, &[base + 1]));
+            segment.extend(object_array(base + 1, &vec![0; entries]));
+            if session {
+                segment.extend(instance_dump(base + 0x10, 0x200, &[base + 0x11]));
+                segment.extend(object_array(base + 0x11, &[0; 1000]));
+            }
+            dump.extend(record(0x1C, &segment));
+            HprofJavaVirtualMachine::new(Cursor::new(dump)).unwrap()
+        };
+        let dumps = [
+            dump(1_000, 0x1000, 10, false),
+            dump(3_601_000, 0x8000, 100, true),
+        ];
+        let timeline = heap_timeline(&dumps, 5).unwrap();
+
+        assert_eq!(
+            timeline.dumps[1].time,
+            UNIX_EPOCH + Duration::from_millis(3_601_000)
+        );
+        let cache = timeline
+            .classes
+            .iter()
+            .find(|c| c.class_name == "Cache")
+            .unwrap();
+        assert_eq!(cache.instances, vec![1, 1]);
+        assert!(!cache.grows_steadily());
+        assert!(timeline.classes[0].grows_steadily());
+
+        let suspects: Vec<(&str, usize)> = timeline
+            .suspects
+            .iter()
+            .map(|s| (s.path.as_str(), s.first_seen()))
+            .collect();
+        assert_eq!(suspects, vec![("Session", 1), ("Cache", 0)]);
+        let cache = &timeline.suspects[1].retained_bytes;
+        assert!(cache[1].unwrap() > cache[0].unwrap());
+        assert_eq!(
+            timeline.first_seen_time(&timeline.suspects[0]),
+            Some(timeline.dumps[1].time)
+        );
+    }
+
+    #[test]
+    fn held_by_thread() {
+        let mut dump = header();
+        for (id, value) in [
+            (1, "main"),
+            (2, "run"),
+            (3, "()V"),
+            (4, "Worker.java"),
+            (5, "com/example/Worker"),
+        ] {
+            dump.extend(string(id, value));
+        }
+        dump.extend(load_class(1, 0x500, 5));
+        let mut frame = 0x77u64.to_be_bytes().to_vec();
+        for id in [2u64, 3, 4] {
+            frame.extend_from_slice(&id.to_be_bytes());
+        }
+        frame.extend_from_slice(&1u32.to_be_bytes());
+        frame.extend_from_slice(&42i32.to_be_bytes());
+        dump.extend(record(0x04, &frame));
+        let mut trace = vec![0u8; 4];
+        trace.extend_from_slice(&1u32.to_be_bytes());
+        trace.extend_from_slice(&1u32.to_be_bytes());
+        trace.extend_from_slice(&0x77u64.to_be_bytes());
+        dump.extend(record(0x05, &trace));
+        dump.extend(start_thread(1, 0x100, 1));
+        // A local variable in main's only frame, which refers to another array
+        let mut segment = vec![0x03];
+        segment.extend_from_slice(&0x1000u64.to_be_bytes());
+        segment.extend_from_slice(&1u32.to_be_bytes());
+        segment.extend_from_slice(&0u32.to_be_bytes());
+        segment.extend(object_array(0x1000, &[0x2000]));
+        segment.extend(object_array(0x2000, &[]));
+        dump.extend(record(0x1C, &segment));
+        let jvm = HprofJavaVirtualMachine::new(Cursor::new(dump)).unwrap();
+
+        let holder = "thread \"main\" at com.example.Worker.run(Worker.java:42)";
+        assert_eq!(
+            jvm.object(0x1000).unwrap().held_by().unwrap().as_deref(),
+            Some(holder)
+        );
+        assert_eq!(jvm.object(0x2000).unwrap().held_by().unwrap(), None);
+        let chain = jvm.dump.reference_chain(&[0x1000, 0x2000]).unwrap();
+        assert_eq!(chain[0], format!("<unknown>@1000 (held by {})", holder));
+        assert_eq!(chain[1], "[0] <unknown>@2000");
+
+        let path = jvm.object(0x2000).unwrap().root_path().unwrap().unwrap();
+        assert_eq!(path.root.object_id, 0x1000);
+        assert_eq!(path.root_kind, RootKind::JavaFrame);
+        assert_eq!(path.held_by.as_deref(), Some(holder));
+        assert_eq!(path.edges.len(), 1);
+        assert_eq!(path.edges[0].reference, Reference::ArrayElement(0));
+        assert_eq!(path.target().object_id, 0x2000);
+        assert!(path.root.retained_bytes.unwrap() > path.target().shallow_bytes);
+        let index = ReferrerIndex::build(&jvm, 1 << 20).unwrap();
+        let found = jvm.object(0x2000).unwrap().root_path_with(&index).unwrap();
+        assert_eq!(found.unwrap().edges[0].to.retained_bytes, None);
+        let text = path.to_string();
+        assert!(text.starts_with(&format!(
+            "<unknown>@1000 (JavaFrame root, held by {}), ",
+            holder
+        )));
+        assert!(text.contains("\n  [0] <unknown>@2000, "));
+    }
+
+    // A class dump subrecord for a class defined by 'loader', with static object fields as
+    // (name ID, value) and no instance fields
+    fn loaded_class_dump(class_object_id: u64, loader: u64, statics: &[(u64, u64)]) -> Vec<u8> {
+        let mut body = class_dump(class_object_id, 0, &[]);
+        body[21..29].copy_from_slice(&loader.to_be_bytes());
+        let fields = body.split_off(67);
+        body.extend_from_slice(&(statics.len() as u16).to_be_bytes());
+        for &(name_id, value) in statics {
+            body.extend_from_slice(&name_id.to_be_bytes());
+            body.push(0x02);
+            body.extend_from_slice(&value.to_be_bytes());
+        }
+        body.extend_from_slice(&fields[2..]);
+        body
+    }
+
+    #[test]
+    fn class_loaders() {
+        let mut dump = header();
+        for (id, name) in [
+            (1, "com/example/PluginLoader"),
+            (2, "com/example/Plugin"),
+            (3, "com/example/Registry"),
+            (4, "sun/misc/Launcher$AppClassLoader"),
+            (5, "com/example/Orphan"),
+            (6, "plugin"),
+        ] {
+            dump.extend(string(id, name));
+            dump.extend(load_class(id as u32, id * 0x100, id));
+        }
+        // The application loader (0x2000) defined the registry, whose static field holds a
+        // plugin, whose class was defined by a plugin loader (0x1000). Another plugin loader
+        // (0x3000) is garbage.
+        let mut segment = vec![];
+        for root in [0x300u64, 0x400] {
+            segment.push(0x05);
+            segment.extend_from_slice(&root.to_be_bytes());
+        }
+        segment.extend(loaded_class_dump(0x100, 0x2000, &[]));
+        segment.extend(loaded_class_dump(0x200, 0x1000, &[]));
+        segment.extend(loaded_class_dump(0x300, 0x2000, &[(6, 0x4000)]));
+        segment.extend(loaded_class_dump(0x400, 0, &[]));
+        segment.extend(loaded_class_dump(0x500, 0x3000, &[]));
+        segment.extend(instance_dump(0x1000, 0x100, &[]));
+        segment.extend(instance_dump(0x2000, 0x400, &[]));
+        segment.extend(instance_dump(0x3000, 0x100, &[]));
+        segment.extend(instance_dump(0x4000, 0x200, &[]));
+        dump.extend(record(0x1C, &segment));
+        let jvm = HprofJavaVirtualMachine::new(Cursor::new(dump)).unwrap();
+
+        let report = jvm.class_loader_report().unwrap();
+        let rows: Vec<_> = report
+            .loaders
+            .iter()
+            .map(|l| {
+                (
+                    l.loader,
+                    l.loader_class.as_str(),
+                    l.retained_by,
+                    l.root_kind,
+                )
+            })
+            .collect();
+        let sticky = Some(RootKind::StickyClass);
+        assert_eq!(
+            rows,
+            vec![
+                (
+                    Some(0x2000),
+                    "sun.misc.Launcher$AppClassLoader",
+                    LoaderRetention::Other,
+                    sticky
+                ),
+                (
+                    Some(0x1000),
+                    "com.example.PluginLoader",
+                    LoaderRetention::StaticField,
+                    sticky
+                ),
+                (
+                    Some(0x3000),
+                    "com.example.PluginLoader",
+                    LoaderRetention::Unreachable,
+                    None
+                ),
+                (None, "<bootstrap>", LoaderRetention::Other, None),
+            ]
+        );
+        let classes: Vec<_> = report
+            .loaders
+            .iter()
+            .map(|l| l.classes.join(", "))
+            .collect();
+        assert_eq!(
+            classes,
+            vec![
+                "com.example.PluginLoader, com.example.Registry",
+                "com.example.Plugin",
+                "com.example.Orphan",
+                "sun.misc.Launcher$AppClassLoader",
+            ]
+        );
+
+        // The plugin loader takes the plugin class with it, and its own class, which nothing
+        // else live has an instance of, but not the plugin the registry holds
+        let plugins = &report.loaders[1];
+        let loader = jvm.object(0x1000).unwrap().shallow_size().unwrap();
+        let class = |id| jvm.dump.class_object_size(&jvm.dump.class_dump_tab[&id]);
+        assert_eq!(plugins.retained_bytes, loader + class(0x100) + class(0x200));
+        assert_eq!(
+            plugins.reference_chain,
+            vec![
+                "java.lang.Class@300",
+                "static plugin com.example.Plugin@4000",
+                "<class> java.lang.Class@200",
+                "<classloader> com.example.PluginLoader@1000",
+            ]
+        );
+        assert_eq!(report.loaders[2].retained_bytes, 0);
+        let suspects: Vec<_> = report.leak_suspects().map(|l| l.loader).collect();
+        assert_eq!(suspects, vec![Some(0x1000)]);
+    }
+
+    #[test]
+    fn collection_waste() {
+        let mut dump = header();
+        for (id, name) in [
+            (1, "java/util/ArrayList"),
+            (2, "java/util/HashMap"),
+            (3, "java/util/HashSet"),
+            (4, "Cache"),
+        ] {
+            dump.extend(string(id, name));
+            dump.extend(load_class(id as u32, id * 0x100, id));
+        }
+        for (id, name) in [(5, "size"), (6, "elementData"), (7, "table"), (8, "map")] {
+            dump.extend(string(id, name));
+        }
+        let collection = |id: u64, class: u64, size: i32, array: u64| {
+            let mut values = size.to_be_bytes().to_vec();
+            values.extend_from_slice(&array.to_be_bytes());
+            raw_instance_dump(id, class, &values)
+        };
+        let mut segment = vec![];
+        for root in [0x1001u64, 0x1002, 0x1003, 0x3000, 0x4000] {
+            segment.push(0xFF);
+            segment.extend_from_slice(&root.to_be_bytes());
+        }
+        segment.extend(typed_class_dump(0x100, 0, &[(5, 0x0A), (6, 0x02)]));
+        segment.extend(typed_class_dump(0x200, 0, &[(5, 0x0A), (7, 0x02)]));
+        segment.extend(class_dump(0x300, 0, &[8]));
+        segment.extend(class_dump(0x400, 0, &[6, 7]));
+        // A Cache holding an empty list and an empty map, a list with one element in ten slots,
+        // one half full, one which hasn't allocated its array yet, and an empty set
+        segment.extend(instance_dump(0x4000, 0x400, &[0x1000, 0x2000]));
+        segment.extend(collection(0x1000, 0x100, 0, 0x5000));
+        segment.extend(collection(0x1001, 0x100, 1, 0x5001));
+        segment.extend(collection(0x1002, 0x100, 5, 0x5002));
+        segment.extend(collection(0x1003, 0x100, 0, 0));
+        segment.extend(collection(0x2000, 0x200, 0, 0x6000));
+        segment.extend(instance_dump(0x3000, 0x300, &[0x2001]));
+        segment.extend(collection(0x2001, 0x200, 0, 0x6001));
+        segment.extend(object_array(0x5000, &[0; 10]));
+        segment.extend(object_array(0x5001, &[0x4000, 0, 0, 0, 0, 0, 0, 0, 0, 0]));
+        segment.extend(object_array(0x5002, &[0x4000; 10]));
+        segment.extend(object_array(0x6000, &[0; 16]));
+        segment.extend(object_array(0x6001, &[0; 16]));
+        dump.extend(record(0x1C, &segment));
+        let jvm = HprofJavaVirtualMachine::new(Cursor::new(dump)).unwrap();
+
+        let report = jvm.collection_waste_report().unwrap();
+        let shallow = |id| jvm.object(id).unwrap().shallow_size().unwrap();
+        let reference_size = jvm.dump.size_model().reference_size;
+        let rows: Vec<_> = report
+            .collections
+            .iter()
+            .map(|c| {
+                let holder = c.holder_class.as_deref();
+                (
+                    c.collection,
+                    c.collection_class.as_str(),
+                    holder,
+                    c.size,
+                    c.capacity,
+                )
+            })
+            .collect();
+        // The set's map is reported as the set
+        assert_eq!(
+            rows,
+            vec![
+                (0x2000, "java.util.HashMap", Some("Cache"), 0, 16),
+                (0x3000, "java.util.HashSet", None, 0, 16),
+                (0x1000, "java.util.ArrayList", Some("Cache"), 0, 10),
+                (0x1001, "java.util.ArrayList", None, 1, 10),
+            ]
+        );
+        let wasted: Vec<_> = report.collections.iter().map(|c| c.wasted_bytes).collect();
+        let (table, array) = (shallow(0x6000), shallow(0x5000));
+        assert_eq!(wasted, vec![table, table, array, 9 * reference_size]);
+        assert_eq!(report.total_wasted_bytes(), wasted.iter().sum::<u64>());
+
+        let summaries: Vec<_> = report
+            .by_class()
+            .into_iter()
+            .map(|s| {
+                let holder = s.holder_class;
+                (
+                    s.collection_class,
+                    holder,
+                    s.collections,
+                    s.empty_collections,
+                    s.wasted_bytes,
+                )
+            })
+            .collect();
+        let summary = |class: &str, holder: Option<&str>, collections, empty, wasted| {
+            (
+                class.to_string(),
+                holder.map(str::to_string),
+                collections,
+                empty,
+                wasted,
+            )
+        };
+        assert_eq!(
+            summaries,
+            vec![
+                summary("java.util.HashMap", Some("Cache"), 1, 1, table),
+                summary("java.util.HashSet", None, 1, 1, table),
+                summary("java.util.ArrayList", Some("Cache"), 1, 1, array),
+                summary("java.util.ArrayList", None, 1, 0, 9 * reference_size),
+            ]
+        );
+    }
+
+    #[test]
+    fn overhead() {
+        let mut dump = header();
+        for (id, name) in [
+            (1, "java/lang/Integer"),
+            (2, "java/lang/Integer$IntegerCache"),
+            (3, "Holder"),
+            (4, "[Ljava/lang/Integer;"),
+            (5, "[Ljava/lang/Object;"),
+        ] {
+            dump.extend(string(id, name));
+            dump.extend(load_class(id as u32, id * 0x100, id));
+        }
+        for (id, name) in [(6, "value"), (7, "cache"), (8, "values")] {
+            dump.extend(string(id, name));
+        }
+        let integer = |id: u64, value: i32| raw_instance_dump(id, 0x100, &value.to_be_bytes());
+        let array = |id: u64, class: u64, elements: &[u64]| {
+            let mut body = object_array(id, elements);
+            body[17..25].copy_from_slice(&class.to_be_bytes());
+            body
+        };
+        let mut segment = vec![];
+        for root in [0x200u64, 0x3000] {
+            segment.push(0x05);
+            segment.extend_from_slice(&root.to_be_bytes());
+        }
+        segment.extend(typed_class_dump(0x100, 0, &[(6, 0x0A)]));
+        segment.extend(loaded_class_dump(0x200, 0, &[(7, 0x5000)]));
+        segment.extend(class_dump(0x300, 0, &[8]));
+        segment.extend(class_dump(0x400, 0, &[]));
+        segment.extend(class_dump(0x500, 0, &[]));
+        // A holder with an array of two Integers of its own, one from the cache, and an empty
+        // and a one element array
+        segment.extend(instance_dump(0x3000, 0x300, &[0x5001]));
+        segment.extend(integer(0x1000, 1000));
+        segment.extend(integer(0x1001, 1001));
+        segment.extend(integer(0x1002, 1));
+        segment.extend(array(0x5000, 0x400, &[0x1002]));
+        segment.extend(array(
+            0x5001,
+            0x500,
+            &[0x1000, 0x1001, 0x1002, 0x5002, 0x5003],
+        ));
+        segment.extend(array(0x5002, 0x400, &[]));
+        segment.extend(array(0x5003, 0x400, &[0x1000]));
+        dump.extend(record(0x1C, &segment));
+        let jvm = HprofJavaVirtualMachine::new(Cursor::new(dump)).unwrap();
+
+        // With compressed oops: 12 byte object headers, 16 byte array headers, 4 byte references
+        // and everything padded to 8 bytes
+        let report = jvm.overhead_report().unwrap();
+        let rows = |overheads: &[ClassOverhead]| -> Vec<(String, u64, u64, u64)> {
+            overheads
+                .iter()
+                .map(|c| {
+                    let name = c.class_name.clone();
+                    (name, c.instances, c.shallow_bytes, c.overhead_bytes)
+                })
+                .collect()
+        };
+        let row = |name: &str, instances, shallow, overhead| {
+            (name.to_string(), instances, shallow, overhead)
+        };
+        // The cached Integer doesn't count
+        assert_eq!(
+            rows(&report.boxed),
+            vec![row("java.lang.Integer", 2, 32, 24)]
+        );
+        // 16 + 4 padded to 24 twice, and 16 for the empty one, of which only the two references
+        // are data
+        assert_eq!(
+            rows(&report.tiny_arrays),
+            vec![row("java.lang.Integer[]", 3, 64, 56)]
+        );
+        assert_eq!(
+            rows(&report.classes),
+            vec![
+                row("java.lang.Integer[]", 3, 64, 56),
+                row("java.lang.Integer", 3, 48, 36),
+                row("java.lang.Object[]", 1, 40, 20),
+                row("Holder", 1, 16, 12),
+            ]
+        );
+        assert_eq!(report.total_overhead_bytes(), 56 + 36 + 20 + 12);
+    }
+}
@@ -0,0 +1,178 @@
+//
+// Class loader leaks. A class loader can only be collected once nothing refers to it, any of its
+// classes, or any instance of them, so a single stray reference (typically a static field of a
+// class from another loader, a ThreadLocal value or a JNI global) keeps every class it loaded
+// alive. This is the usual reason redeploying an application leaks memory.
+//
+
+use std::collections::BTreeMap;
+use std::io::Result;
+
+use super::{HprofJavaVirtualMachine, RootKind};
+
+// The loaders which come with the JDK, and live as long as the JVM does
+const BUILTIN_LOADERS: &[&str] = &[
+    "jdk.internal.loader.ClassLoaders$AppClassLoader",
+    "jdk.internal.loader.ClassLoaders$PlatformClassLoader",
+    "jdk.internal.loader.ClassLoaders$BootClassLoader",
+    "sun.misc.Launcher$AppClassLoader",
+    "sun.misc.Launcher$ExtClassLoader",
+];
+
+// What keeps a class loader alive, judging by the shortest path to it from a GC root
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LoaderRetention {
+    JniGlobal,
+    ThreadLocal,
+    StaticField,
+    // Some other kind of root, e.g. a local variable
+    Other,
+    // It's garbage which hadn't been collected when the dump was taken
+    Unreachable,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ClassLoaderReportEntry {
+    // None for the bootstrap loader
+    pub loader: Option<u64>,
+    pub loader_class: String,
+    // The names of the classes it defined, sorted
+    pub classes: Vec<String>,
+    // What would be freed if the loader and its classes were collected. A class is often only
+    // reachable through its instances rather than its loader, so this is usually more than the
+    // retained size of the loader object alone. Zero for the bootstrap loader.
+    pub retained_bytes: u64,
+    pub retained_by: LoaderRetention,
+    pub root_kind: Option<RootKind>,
+    // The shortest chain of references from a GC root to the loader, one step per object,
+    // starting with the root, e.g. ["java.lang.Thread@7f0012", ".threadLocals
+    // java.lang.ThreadLocal$ThreadLocalMap@7f0040", ...]. If the root is a local variable, it
+    // says whose, e.g. "java.lang.Object[]@7f0012 (held by thread \"main\" at
+    // com.example.Worker.run(Worker.java:42))".
+    pub reference_chain: Vec<String>,
+}
+
+impl ClassLoaderReportEntry {
+    // Whether this looks like a leaked application loader: one which isn't part of the JDK, and
+    // is only being kept alive by one of the usual culprits
+    pub fn is_leak_suspect(&self) -> bool {
+        self.loader.is_some()
+            && !BUILTIN_LOADERS.contains(&self.loader_class.as_str())
+            && matches!(
+                self.retained_by,
+                LoaderRetention::JniGlobal
+                    | LoaderRetention::ThreadLocal
+                    | LoaderRetention::StaticField
+            )
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ClassLoaderReport {
+    // Largest retained size first, with the bootstrap loader last
+    pub loaders: Vec<ClassLoaderReportEntry>,
+}
+
+impl ClassLoaderReport {
+    pub fn leak_suspects(&self) -> impl Iterator<Item = &ClassLoaderReportEntry> {
+        self.loaders.iter().filter(|l| l.is_leak_suspect())
+    }
+}
+
+impl HprofJavaVirtualMachine {
+    // Like thread_local_report(), the first call reads every object in the dump
+    pub fn class_loader_report(&self) -> Result<ClassLoaderReport> {
+        let dump = &self.dump;
+        let graph = dump.graph()?;
+
+        // Loader -> ([class names], [class object IDs])
+        let mut classes_by_loader: BTreeMap<u64, (Vec<String>, Vec<u64>)> = BTreeMap::new();
+        for class in dump.class_dump_tab.values() {
+            let name = dump
+                .class_serials
+                .get(&class.class_object_id)
+                .and_then(|serial_num| dump.class_tab.get(serial_num))
+                .map(|class| dump.class_name(class))
+                .unwrap_or_else(|| format!("<unknown class {:x}>", class.class_object_id));
+            let (names, ids) = classes_by_loader
+                .entry(class.class_loader_object_id)
+                .or_default();
+            names.push(name);
+            ids.push(class.class_object_id);
+        }
+
+        let mut loaders = vec![];
+        for (loader_id, (mut classes, mut class_ids)) in classes_by_loader {
+            classes.sort();
+            if loader_id == 0 {
+                loaders.push(ClassLoaderReportEntry {
+                    loader: None,
+                    loader_class: "<bootstrap>".to_string(),
+                    classes,
+                    retained_bytes: 0,
+                    retained_by: LoaderRetention::Other,
+                    root_kind: None,
+                    reference_chain: vec![],
+                });
+                continue;
+            }
+
+            let path = graph.path_from_root(loader_id).unwrap_or_default();
+            let root_kind = path.first().and_then(|&root| {
+                dump.roots
+                    .iter()
+                    .find(|r| r.object_id == root)
+                    .map(|r| r.kind)
+            });
+            let mut reference_chain = vec![];
+            let mut through_thread_local = false;
+            let mut through_static = false;
+            for (i, &object_id) in path.iter().enumerate() {
+                let class_name = dump
+                    .object_class_name(object_id)?
+                    .unwrap_or_else(|| "<unknown>".to_string());
+                through_thread_local |= class_name == "java.lang.ThreadLocal$ThreadLocalMap$Entry";
+                let step = if i == 0 {
+                    dump.root_step(object_id, &class_name)?
+                } else {
+                    let reference = dump.reference_name(path[i - 1], object_id)?;
+                    through_static |= reference.starts_with("static ");
+                    format!("{} {}@{:x}", reference, class_name, object_id)
+                };
+                reference_chain.push(step);
+            }
+            let retained_by = if path.is_empty() {
+                LoaderRetention::Unreachable
+            } else if root_kind == Some(RootKind::JniGlobal) {
+                LoaderRetention::JniGlobal
+            } else if through_thread_local {
+                LoaderRetention::ThreadLocal
+            } else if through_static {
+                LoaderRetention::StaticField
+            } else {
+                LoaderRetention::Other
+            };
+
+            class_ids.push(loader_id);
+            loaders.push(ClassLoaderReportEntry {
+                loader: Some(loader_id),
+                loader_class: dump
+                    .object_class_name(loader_id)?
+                    .unwrap_or_else(|| "<unknown>".to_string()),
+                classes,
+                retained_bytes: graph.retained_size_of_all(&class_ids),
+                retained_by,
+                root_kind,
+                reference_chain,
+            });
+        }
+        loaders.sort_by(|a, b| {
+            (a.loader.is_none(), b.retained_bytes, a.loader).cmp(&(
+                b.loader.is_none(),
+                a.retained_bytes,
+                b.loader,
+            ))
+        });
+        Ok(ClassLoaderReport { loaders })
+    }
+}
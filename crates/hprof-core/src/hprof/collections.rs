@@ -0,0 +1,165 @@
+//
+// Wasted space in collections. An empty ArrayList or HashMap which has already allocated its
+// backing array, or one which grew large and then had most of its elements removed, holds on to
+// memory it isn't using. One of these is nothing, but there are often thousands of them.
+//
+
+use std::cmp::Reverse;
+use std::collections::{BTreeMap, HashSet};
+use std::io::Result;
+
+use super::heap::HeapObject;
+use super::HprofJavaVirtualMachine;
+use crate::model::Value;
+
+// Collections with fewer elements than this fraction of their capacity are reported. HashMaps
+// resize when they're 75% full, so a map which has only been added to never gets below 37.5%.
+const LOW_FILL_RATIO: f64 = 0.25;
+
+const LISTS: &[&str] = &["java.util.ArrayList"];
+const MAPS: &[&str] = &["java.util.HashMap", "java.util.LinkedHashMap"];
+// These are wrappers around a HashMap in the 'map' field
+const SETS: &[&str] = &["java.util.HashSet", "java.util.LinkedHashSet"];
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CollectionWaste {
+    pub collection: u64,
+    pub collection_class: String,
+    // The class of the object which dominates the collection (usually the one with the field
+    // which refers to it), or None if it's only held by GC roots
+    pub holder_class: Option<String>,
+    pub size: u64,
+    // The length of the backing array
+    pub capacity: u64,
+    // For empty collections, the whole backing array, otherwise its unused slots
+    pub wasted_bytes: u64,
+}
+
+impl CollectionWaste {
+    pub fn is_empty(&self) -> bool {
+        self.size == 0
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CollectionWasteSummary {
+    pub collection_class: String,
+    pub holder_class: Option<String>,
+    pub collections: u64,
+    pub empty_collections: u64,
+    pub wasted_bytes: u64,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct CollectionWasteReport {
+    // Most wasted bytes first
+    pub collections: Vec<CollectionWaste>,
+}
+
+impl CollectionWasteReport {
+    // Totalled up by collection class and holder class, most wasted bytes first
+    pub fn by_class(&self) -> Vec<CollectionWasteSummary> {
+        let mut summaries: BTreeMap<(&str, Option<&str>), CollectionWasteSummary> = BTreeMap::new();
+        for c in &self.collections {
+            let summary = summaries
+                .entry((&c.collection_class, c.holder_class.as_deref()))
+                .or_insert_with(|| CollectionWasteSummary {
+                    collection_class: c.collection_class.clone(),
+                    holder_class: c.holder_class.clone(),
+                    collections: 0,
+                    empty_collections: 0,
+                    wasted_bytes: 0,
+                });
+            summary.collections += 1;
+            summary.empty_collections += c.is_empty() as u64;
+            summary.wasted_bytes += c.wasted_bytes;
+        }
+        let mut summaries: Vec<_> = summaries.into_values().collect();
+        // Stable, so ties stay in name order
+        summaries.sort_by_key(|s| Reverse(s.wasted_bytes));
+        summaries
+    }
+
+    pub fn total_wasted_bytes(&self) -> u64 {
+        self.collections.iter().map(|c| c.wasted_bytes).sum()
+    }
+}
+
+impl HprofJavaVirtualMachine {
+    // Empty collections which have allocated a backing array, and ones which are mostly empty.
+    // Finding the holders needs the dominator tree, so the first call reads every object in the
+    // dump.
+    pub fn collection_waste_report(&self) -> Result<CollectionWasteReport> {
+        let dump = &self.dump;
+        let graph = dump.graph()?;
+        let class_ids = |names: &[&str]| -> Vec<u64> {
+            names
+                .iter()
+                .flat_map(|name| dump.class_object_ids(name))
+                .collect()
+        };
+
+        // (collection, the object with its size and array fields, the name of the array field)
+        let mut candidates = vec![];
+        let mut set_maps = HashSet::new();
+        for set in dump.instances_of(&class_ids(SETS))? {
+            if let Some(map) = dump.object_field(set, "map")? {
+                set_maps.insert(map);
+                candidates.push((set, map, "table"));
+            }
+        }
+        for map in dump.instances_of(&class_ids(MAPS))? {
+            if !set_maps.contains(&map) {
+                candidates.push((map, map, "table"));
+            }
+        }
+        for list in dump.instances_of(&class_ids(LISTS))? {
+            candidates.push((list, list, "elementData"));
+        }
+
+        let mut collections = vec![];
+        for (collection, inner, array_field) in candidates {
+            let size = match dump.field_value(inner, "size")? {
+                Some(Value::Integer(size)) => size.max(0) as u64,
+                _ => continue,
+            };
+            let array = match dump.object_field(inner, array_field)? {
+                Some(array) => dump.read_object(array)?,
+                None => None,
+            };
+            let (capacity, array_bytes) = match &array {
+                Some(object @ HeapObject::ObjectArray { elements, .. }) => {
+                    (elements.len() as u64, dump.shallow_size(object))
+                }
+                _ => (0, 0),
+            };
+            if capacity == 0 {
+                continue;
+            }
+            let wasted_bytes = if size == 0 {
+                array_bytes
+            } else if (size as f64) < LOW_FILL_RATIO * capacity as f64 {
+                (capacity - size) * dump.size_model().reference_size
+            } else {
+                continue;
+            };
+            let holder_class = match graph.immediate_dominator(collection) {
+                Some(holder) => dump.object_class_name(holder)?,
+                None => None,
+            };
+            collections.push(CollectionWaste {
+                collection,
+                collection_class: dump
+                    .object_class_name(collection)?
+                    .unwrap_or_else(|| "<unknown>".to_string()),
+                holder_class,
+                size,
+                capacity,
+                wasted_bytes,
+            });
+        }
+        collections
+            .sort_by(|a, b| (b.wasted_bytes, a.collection).cmp(&(a.wasted_bytes, b.collection)));
+        Ok(CollectionWasteReport { collections })
+    }
+}
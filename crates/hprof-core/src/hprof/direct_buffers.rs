@@ -0,0 +1,149 @@
+//
+// Native memory held by NIO buffers. A DirectByteBuffer is a small object in the heap, but the
+// memory it points at is outside it, so a histogram shows a few thousand 64 byte objects while
+// the process is using gigabytes more than its heap, and the container it's in gets killed for
+// it. The capacities in the dump say how much each buffer holds, and the dominator tree says
+// who's holding the buffer.
+//
+// Slices and duplicates share their parent's memory (their 'att' field points at it), so only
+// the buffers which own their memory are counted. A buffer which nothing refers to any more still
+// holds its memory until its Cleaner runs.
+//
+
+use std::cmp::Reverse;
+use std::collections::BTreeMap;
+use std::io::Result;
+
+use super::HprofJavaVirtualMachine;
+use crate::model::Value;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum DirectBufferKind {
+    // From ByteBuffer.allocateDirect(), freed by its Cleaner
+    Allocated,
+    // A memory mapped file, from FileChannel.map()
+    Mapped,
+    // Memory which Java didn't allocate and won't free, e.g. from JNI's NewDirectByteBuffer()
+    Foreign,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DirectBufferEntry {
+    pub object: u64,
+    pub kind: DirectBufferKind,
+    pub capacity: u64,
+    // Whether anything but the buffer's Cleaner refers to it. If not, it's waiting to be cleaned.
+    pub reachable: bool,
+    // The object which dominates the buffer, or None if it's only held by GC roots (or nothing)
+    pub holder: Option<u64>,
+    pub holder_class: Option<String>,
+    // The class of the loader of the holder's class, or None for the bootstrap loader
+    pub holder_class_loader: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DirectBufferGroup {
+    pub kind: DirectBufferKind,
+    pub reachable: bool,
+    pub holder_class: Option<String>,
+    pub holder_class_loader: Option<String>,
+    pub buffers: u64,
+    pub capacity: u64,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct DirectBufferReport {
+    // The buffers which own their memory, largest first
+    pub entries: Vec<DirectBufferEntry>,
+    // How many slices and duplicates there were, which aren't counted
+    pub views: u64,
+}
+
+impl DirectBufferReport {
+    pub fn total_capacity(&self) -> u64 {
+        self.entries.iter().map(|e| e.capacity).sum()
+    }
+
+    // The buffers totalled up by kind, holder class and its loader, largest first
+    pub fn groups(&self) -> Vec<DirectBufferGroup> {
+        let mut groups: BTreeMap<_, DirectBufferGroup> = BTreeMap::new();
+        for entry in &self.entries {
+            let key = (
+                entry.kind,
+                !entry.reachable,
+                &entry.holder_class,
+                &entry.holder_class_loader,
+            );
+            let group = groups.entry(key).or_insert_with(|| DirectBufferGroup {
+                kind: entry.kind,
+                reachable: entry.reachable,
+                holder_class: entry.holder_class.clone(),
+                holder_class_loader: entry.holder_class_loader.clone(),
+                buffers: 0,
+                capacity: 0,
+            });
+            group.buffers += 1;
+            group.capacity += entry.capacity;
+        }
+        let mut groups: Vec<_> = groups.into_values().collect();
+        // Stable, so ties stay in kind and holder order
+        groups.sort_by_key(|g| Reverse(g.capacity));
+        groups
+    }
+}
+
+impl HprofJavaVirtualMachine {
+    // Every DirectByteBuffer in the dump. Finding the holders needs the dominator tree, so the
+    // first call reads every object in the dump.
+    pub fn direct_buffer_report(&self) -> Result<DirectBufferReport> {
+        let dump = &self.dump;
+        let class_ids = dump.class_object_ids_with_subclasses("java.nio.DirectByteBuffer");
+        if class_ids.is_empty() {
+            return Ok(DirectBufferReport::default());
+        }
+        let graph = dump.graph_with_referents(false)?;
+
+        let mut report = DirectBufferReport::default();
+        for object in dump.instances_of(&class_ids)? {
+            let fields = dump.instance_fields(object)?.unwrap_or_default();
+            let field = |name: &str| fields.iter().find(|(n, _)| n == name).map(|(_, v)| v);
+            let is_set = |name: &str| matches!(field(name), Some(Value::Object(_)));
+            if is_set("att") {
+                report.views += 1;
+                continue;
+            }
+            let kind = if is_set("fd") {
+                DirectBufferKind::Mapped
+            } else if is_set("cleaner") {
+                DirectBufferKind::Allocated
+            } else {
+                DirectBufferKind::Foreign
+            };
+            let capacity = match field("capacity") {
+                Some(Value::Integer(capacity)) => (*capacity).max(0) as u64,
+                _ => 0,
+            };
+            let holder = graph.immediate_dominator(object);
+            let (holder_class, holder_class_loader) = match holder {
+                Some(holder) => (
+                    dump.object_class_name(holder)?,
+                    dump.class_loader_name(holder)?,
+                ),
+                None => (None, None),
+            };
+            report.entries.push(DirectBufferEntry {
+                object,
+                kind,
+                capacity,
+                reachable: graph.is_reachable(object),
+                holder,
+                holder_class,
+                holder_class_loader,
+            });
+        }
+        report
+            .entries
+            .sort_by_key(|e| (Reverse(e.capacity), e.object));
+        Ok(report)
+    }
+}
@@ -0,0 +1,168 @@
+//
+// The dominator tree itself, for front ends which draw it (as a treemap or an icicle chart) or let
+// the user walk down it, rather than only asking for one object's retained size. Each object's
+// children are the objects it immediately dominates, and the tree's roots are the objects which
+// only the GC roots dominate. Building it is building the graph (see graph.rs), which is kept, so
+// the dump's ObjectViews share it. A tree keeps the sizes it was built with, even if
+// set_size_model() or set_follow_referents() changes them afterwards.
+//
+// An object's shallow size is its retained size less its children's, so rows() gives a treemap
+// everything it needs without reading the objects again, apart from their classes.
+//
+
+use std::cmp::Reverse;
+use std::io::Result;
+use std::rc::Rc;
+
+use super::graph::HeapGraph;
+use super::{Dump, HprofJavaVirtualMachine};
+
+pub struct DominatorTree {
+    dump: Rc<Dump>,
+    graph: Rc<HeapGraph>,
+    // The children of each node are children[child_starts[n]..child_starts[n + 1]], and the
+    // tree's roots are under the extra node at the end. Largest retained size first.
+    child_starts: Vec<usize>,
+    children: Vec<u32>,
+}
+
+// One object in the tree, for drawing it without going back to the dump
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct DominatorRow {
+    pub object_id: u64,
+    // None for the tree's roots
+    pub parent: Option<u64>,
+    // "class X" for a class
+    pub class_name: String,
+    pub shallow_bytes: u64,
+    pub retained_bytes: u64,
+}
+
+impl HprofJavaVirtualMachine {
+    // Builds the dominator tree if it hasn't been already, which means reading every object
+    pub fn dominator_tree(&self) -> Result<DominatorTree> {
+        let graph = self.dump.graph()?;
+        let nodes = graph.len();
+        let parent_of = |n: usize| match graph.dominator_node(n) {
+            Some(d) if d == n => Some(nodes),
+            d => d,
+        };
+
+        let mut child_starts = vec![0; nodes + 2];
+        for n in 0..nodes {
+            if let Some(parent) = parent_of(n) {
+                child_starts[parent + 1] += 1;
+            }
+        }
+        for i in 1..child_starts.len() {
+            child_starts[i] += child_starts[i - 1];
+        }
+        let mut next = child_starts.clone();
+        let mut children = vec![0; child_starts[nodes + 1]];
+        for n in 0..nodes {
+            if let Some(parent) = parent_of(n) {
+                children[next[parent]] = n as u32;
+                next[parent] += 1;
+            }
+        }
+        for parent in 0..=nodes {
+            children[child_starts[parent]..child_starts[parent + 1]]
+                .sort_by_key(|&n| (Reverse(graph.retained_node(n as usize)), n));
+        }
+
+        Ok(DominatorTree {
+            dump: self.dump.clone(),
+            graph,
+            child_starts,
+            children,
+        })
+    }
+}
+
+impl DominatorTree {
+    // The objects which only GC roots dominate, largest first
+    pub fn roots(&self) -> Vec<u64> {
+        self.child_nodes(self.graph.len())
+    }
+
+    // The objects 'object_id' immediately dominates, largest first. Empty if there's no such
+    // object, or it isn't reachable.
+    pub fn children(&self, object_id: u64) -> Vec<u64> {
+        match self.graph.node(object_id) {
+            Some(n) => self.child_nodes(n),
+            None => vec![],
+        }
+    }
+
+    // The object's immediate dominator, or None if only GC roots dominate it
+    pub fn dominator(&self, object_id: u64) -> Option<u64> {
+        self.graph.immediate_dominator(object_id)
+    }
+
+    // None if there's no such object. Zero if it isn't reachable.
+    pub fn retained(&self, object_id: u64) -> Option<u64> {
+        self.graph.retained_size(object_id)
+    }
+
+    // The object's own size, as the part of its retained size its children don't account for
+    pub fn shallow(&self, object_id: u64) -> Option<u64> {
+        let n = self.graph.node(object_id)?;
+        Some(self.shallow_node(n))
+    }
+
+    // Every object retaining at least 'min_retained' bytes, parents before their children, with
+    // each object's children largest first. The objects which retain less are left out along
+    // with everything under them, but are still counted in their parents' retained sizes.
+    pub fn rows(&self, min_retained: u64) -> Result<Vec<DominatorRow>> {
+        let mut rows = vec![];
+        let root = self.graph.len();
+        let mut stack: Vec<(usize, Option<u64>)> = self
+            .children_of(root)
+            .iter()
+            .rev()
+            .map(|&n| (n as usize, None))
+            .collect();
+        while let Some((n, parent)) = stack.pop() {
+            let retained_bytes = self.graph.retained_node(n);
+            if retained_bytes < min_retained {
+                continue;
+            }
+            let object_id = self.graph.object_id(n);
+            rows.push(DominatorRow {
+                object_id,
+                parent,
+                class_name: self.dump.describe(object_id)?,
+                shallow_bytes: self.shallow_node(n),
+                retained_bytes,
+            });
+            stack.extend(
+                self.children_of(n)
+                    .iter()
+                    .rev()
+                    .map(|&child| (child as usize, Some(object_id))),
+            );
+        }
+        Ok(rows)
+    }
+
+    fn children_of(&self, n: usize) -> &[u32] {
+        &self.children[self.child_starts[n]..self.child_starts[n + 1]]
+    }
+
+    fn child_nodes(&self, n: usize) -> Vec<u64> {
+        self.children_of(n)
+            .iter()
+            .map(|&child| self.graph.object_id(child as usize))
+            .collect()
+    }
+
+    fn shallow_node(&self, n: usize) -> u64 {
+        let children: u64 = self
+            .children_of(n)
+            .iter()
+            .map(|&child| self.graph.retained_node(child as usize))
+            .sum();
+        self.graph.retained_node(n) - children
+    }
+}
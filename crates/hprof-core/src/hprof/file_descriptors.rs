@@ -0,0 +1,206 @@
+//
+// File descriptor leaks. Every open file, socket and channel has a java.io.FileDescriptor, and
+// the OS only gives a process so many, so a program which forgets to close them eventually fails
+// with "Too many open files". The dump usually has everything needed to see where they came from:
+// the sockets and channels which own the descriptors, and whatever is holding on to them.
+//
+
+use std::cmp::Reverse;
+use std::collections::BTreeMap;
+use std::io::{Read, Result, Seek};
+
+use super::{HprofJavaVirtualMachine, HprofParser};
+use crate::model::Value;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum ResourceKind {
+    // A java.net.SocketImpl, which is what a Socket or ServerSocket uses underneath
+    Socket,
+    // A java.nio.channels.spi.AbstractInterruptibleChannel, e.g. a FileChannel or SocketChannel
+    Channel,
+    // A java.io.FileDescriptor which isn't owned by any of the above, e.g. one from a
+    // FileInputStream
+    FileDescriptor,
+}
+
+const RESOURCE_CLASSES: &[(&str, ResourceKind)] = &[
+    ("java.net.SocketImpl", ResourceKind::Socket),
+    (
+        "java.nio.channels.spi.AbstractInterruptibleChannel",
+        ResourceKind::Channel,
+    ),
+    ("java.io.FileDescriptor", ResourceKind::FileDescriptor),
+];
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FileDescriptorEntry {
+    pub object: u64,
+    pub class: String,
+    pub kind: ResourceKind,
+    // The descriptor number, or None if there isn't one (which for a FileDescriptor means it's
+    // been closed, and for sockets and channels usually means it was never opened)
+    pub fd: Option<i32>,
+    // Where it was allocated, innermost frame first, e.g. "java.net.Socket.<init>(Socket.java:
+    // 120)". Empty unless the dump has allocation sites.
+    pub allocation_stack: Vec<String>,
+    // The class of the object which dominates it, or None if it's only held by GC roots
+    pub holder_class: Option<String>,
+}
+
+impl FileDescriptorEntry {
+    pub fn is_open(&self) -> bool {
+        matches!(self.fd, Some(fd) if fd >= 0)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FileDescriptorGroup {
+    pub kind: ResourceKind,
+    // The allocation stack the group shares, if the dump has them, otherwise empty
+    pub allocation_stack: Vec<String>,
+    // The holder class the group shares, if the dump doesn't have allocation stacks
+    pub holder_class: Option<String>,
+    pub count: u64,
+    pub open: u64,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct FileDescriptorReport {
+    // Sorted by kind, then ID
+    pub entries: Vec<FileDescriptorEntry>,
+}
+
+impl FileDescriptorReport {
+    pub fn open(&self) -> impl Iterator<Item = &FileDescriptorEntry> {
+        self.entries.iter().filter(|e| e.is_open())
+    }
+
+    // The entries grouped by where they were allocated, or by their holder class for the ones
+    // the dump has no allocation stack for, most open descriptors first
+    pub fn groups(&self) -> Vec<FileDescriptorGroup> {
+        let mut groups: BTreeMap<_, FileDescriptorGroup> = BTreeMap::new();
+        for entry in &self.entries {
+            let holder_class = if entry.allocation_stack.is_empty() {
+                entry.holder_class.as_deref()
+            } else {
+                None
+            };
+            let group = groups
+                .entry((entry.kind, &entry.allocation_stack, holder_class))
+                .or_insert_with(|| FileDescriptorGroup {
+                    kind: entry.kind,
+                    allocation_stack: entry.allocation_stack.clone(),
+                    holder_class: holder_class.map(str::to_string),
+                    count: 0,
+                    open: 0,
+                });
+            group.count += 1;
+            group.open += entry.is_open() as u64;
+        }
+        let mut groups: Vec<_> = groups.into_values().collect();
+        // Stable, so ties stay in kind and holder order
+        groups.sort_by_key(|g| (Reverse(g.open), Reverse(g.count)));
+        groups
+    }
+
+    pub fn count(&self, kind: ResourceKind) -> u64 {
+        self.entries.iter().filter(|e| e.kind == kind).count() as u64
+    }
+}
+
+impl HprofJavaVirtualMachine {
+    // Every socket, channel and file descriptor in the dump. A FileDescriptor which belongs to a
+    // socket or channel is only reported as part of it. Finding the holders needs the dominator
+    // tree, so the first call reads every object in the dump.
+    pub fn file_descriptor_report(&self) -> Result<FileDescriptorReport> {
+        let dump = &self.dump;
+        let graph = dump.graph()?;
+
+        let mut resources = vec![];
+        for &(name, kind) in RESOURCE_CLASSES {
+            let class_ids = dump.class_object_ids_with_subclasses(name);
+            for object in dump.instances_of(&class_ids)? {
+                resources.push((kind, object));
+            }
+        }
+        // The descriptors of sockets and channels, which aren't reported separately
+        let mut owned = vec![];
+        for &(kind, object) in &resources {
+            if kind != ResourceKind::FileDescriptor {
+                if let Some(fd) = dump.object_field(object, "fd")? {
+                    owned.push(fd);
+                }
+            }
+        }
+        owned.sort_unstable();
+
+        let mut entries = vec![];
+        for (kind, object) in resources {
+            let descriptor = match kind {
+                ResourceKind::FileDescriptor if owned.binary_search(&object).is_ok() => continue,
+                ResourceKind::FileDescriptor => Some(object),
+                _ => dump.object_field(object, "fd")?,
+            };
+            let fd = match descriptor {
+                Some(descriptor) => match dump.field_value(descriptor, "fd")? {
+                    Some(Value::Integer(fd)) => Some(fd),
+                    _ => None,
+                },
+                None => None,
+            };
+            let allocation_stack = match dump.allocation_trace(object)? {
+                Some(serial_num) => dump.trace_frames(serial_num),
+                None => vec![],
+            };
+            let holder_class = match graph.immediate_dominator(object) {
+                Some(holder) => dump.object_class_name(holder)?,
+                None => None,
+            };
+            entries.push(FileDescriptorEntry {
+                object,
+                class: dump
+                    .object_class_name(object)?
+                    .unwrap_or_else(|| "<unknown>".to_string()),
+                kind,
+                fd,
+                allocation_stack,
+                holder_class,
+            });
+        }
+        entries.sort_by_key(|e| (e.kind, e.object));
+        Ok(FileDescriptorReport { entries })
+    }
+}
+
+impl<R: Read + Seek> HprofParser<R> {
+    // The frames of a stack trace, innermost first, in the usual Java form
+    pub(super) fn trace_frames(&self, serial_num: u32) -> Vec<String> {
+        let frame_ids = match self.trace_tab.get(&serial_num) {
+            Some(trace) => &trace.frame_ids[..],
+            None => return vec![],
+        };
+        frame_ids
+            .iter()
+            .map(|frame_id| {
+                let frame = match self.frame_tab.get(frame_id) {
+                    Some(frame) => frame,
+                    None => return "<unknown frame>".to_string(),
+                };
+                let class = self
+                    .class_tab
+                    .get(&frame.class_serial_num)
+                    .map(|class| self.class_name(class))
+                    .unwrap_or_else(|| "<unknown>".to_string());
+                let method = self.string(frame.method_name_id);
+                let method = method.as_deref().unwrap_or("<unknown>");
+                let source = self.string(frame.source_name_id);
+                let source = source.as_deref().unwrap_or("Unknown Source");
+                if frame.line_num > 0 {
+                    format!("{}.{}({}:{})", class, method, source, frame.line_num)
+                } else {
+                    format!("{}.{}({})", class, method, source)
+                }
+            })
+            .collect()
+    }
+}
@@ -0,0 +1,143 @@
+//
+// Objects waiting to be finalized or cleaned. An object with a finalize() method, or registered
+// with a Cleaner, isn't freed when the last reference to it goes: its Finalizer or Cleaner is
+// queued, and it (and everything it refers to) stays in the heap until the finalizer thread or
+// the cleaner's thread gets round to it. If those threads are slow or stuck the queue only grows,
+// and the heap fills up with objects nothing uses, which no path from a GC root explains since
+// the graph doesn't follow referents.
+//
+// An object counts as pending if nothing but its Finalizer or Cleaner refers to it. What it would
+// free is worked out with referents followed, where the Finalizer or Cleaner dominates it.
+//
+
+use std::cmp::Reverse;
+use std::collections::BTreeMap;
+use std::io::{Read, Result, Seek};
+
+use super::{HprofJavaVirtualMachine, HprofParser};
+use crate::model::Value;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum FinalizationKind {
+    // An object with a finalize() method, registered by a java.lang.ref.Finalizer
+    Finalizer,
+    // An object registered with a java.lang.ref.Cleaner, or a sun.misc/jdk.internal.ref Cleaner
+    // such as the one every DirectByteBuffer has
+    Cleaner,
+}
+
+const REFERENCE_CLASSES: &[(&str, FinalizationKind)] = &[
+    ("java.lang.ref.Finalizer", FinalizationKind::Finalizer),
+    ("jdk.internal.ref.Cleaner", FinalizationKind::Cleaner),
+    ("sun.misc.Cleaner", FinalizationKind::Cleaner),
+    (
+        "jdk.internal.ref.PhantomCleanable",
+        FinalizationKind::Cleaner,
+    ),
+];
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FinalizationEntry {
+    pub kind: FinalizationKind,
+    // The class of the objects being finalized or cleaned
+    pub class_name: String,
+    // Objects which nothing else refers to, so are only waiting for their turn
+    pub pending: u64,
+    pub pending_shallow_bytes: u64,
+    // What would be freed once all the pending objects have been dealt with
+    pub pending_retained_bytes: u64,
+    // Objects which are still in use, and are only registered in case they stop being
+    pub live: u64,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct FinalizationReport {
+    // Most retained by pending objects first, then most pending, then by kind and class
+    pub entries: Vec<FinalizationEntry>,
+}
+
+impl FinalizationReport {
+    pub fn pending(&self) -> u64 {
+        self.entries.iter().map(|e| e.pending).sum()
+    }
+
+    pub fn pending_retained_bytes(&self) -> u64 {
+        self.entries.iter().map(|e| e.pending_retained_bytes).sum()
+    }
+}
+
+#[derive(Default)]
+struct Totals {
+    pending: Vec<u64>,
+    pending_shallow_bytes: u64,
+    live: u64,
+}
+
+impl HprofJavaVirtualMachine {
+    // Every object registered with a Finalizer or Cleaner, by class. This needs the dominator tree
+    // both with and without referents followed, so it reads every object in the dump at least
+    // twice, whatever set_follow_referents() says.
+    pub fn finalization_report(&self) -> Result<FinalizationReport> {
+        let dump = &self.dump;
+        let strong = dump.graph_with_referents(false)?;
+        let followed = dump.graph_with_referents(true)?;
+
+        let mut totals: BTreeMap<(FinalizationKind, String), Totals> = BTreeMap::new();
+        for &(name, kind) in REFERENCE_CLASSES {
+            let class_ids = dump.class_object_ids_with_subclasses(name);
+            if class_ids.is_empty() {
+                continue;
+            }
+            for reference in dump.instances_of(&class_ids)? {
+                // Cleared, or already dealt with
+                let referent = match dump.referent(reference)? {
+                    Some(referent) => referent,
+                    None => continue,
+                };
+                let class_name = dump
+                    .object_class_name(referent)?
+                    .unwrap_or_else(|| "<unknown>".to_string());
+                let class = totals.entry((kind, class_name)).or_default();
+                if strong.is_reachable(referent) {
+                    class.live += 1;
+                } else if let Some(object) = dump.read_object(referent)? {
+                    class.pending.push(referent);
+                    class.pending_shallow_bytes += dump.shallow_size(&object);
+                }
+            }
+        }
+
+        let mut entries: Vec<FinalizationEntry> = totals
+            .into_iter()
+            .map(|((kind, class_name), totals)| FinalizationEntry {
+                kind,
+                class_name,
+                pending: totals.pending.len() as u64,
+                pending_shallow_bytes: totals.pending_shallow_bytes,
+                pending_retained_bytes: followed.retained_size_of_all(&totals.pending),
+                live: totals.live,
+            })
+            .collect();
+        // Stable, so ties stay in kind and class order
+        entries.sort_by_key(|e| (Reverse(e.pending_retained_bytes), Reverse(e.pending)));
+        Ok(FinalizationReport { entries })
+    }
+}
+
+impl<R: Read + Seek> HprofParser<R> {
+    // What a java.lang.ref.Reference refers to. Looked up by where Reference declares it, since
+    // subclasses are free to have fields called referent too.
+    fn referent(&self, reference: u64) -> Result<Option<u64>> {
+        let (class_id, i) = match self.referent_field() {
+            Some(field) => field,
+            None => return Ok(None),
+        };
+        let name_id = self.class_dump_tab[&class_id].instance_fields[i].0;
+        Ok(
+            match self.declared_field_value(reference, class_id, name_id)? {
+                Some(Value::Object(referent)) => Some(referent),
+                _ => None,
+            },
+        )
+    }
+}
@@ -0,0 +1,411 @@
+//
+// The object graph of a heap dump and its dominator tree. An object dominates another if every
+// path from the GC roots to the other object goes through it, so an object's retained size (how
+// much memory would be freed if it were collected) is the total shallow size of the objects it
+// dominates.
+//
+// Dominators are computed with the iterative algorithm from Cooper, Harvey and Kennedy, "A Simple,
+// Fast Dominance Algorithm", which is simpler than Lengauer-Tarjan and converges in a few passes
+// on the shallow, wide graphs heaps tend to be.
+//
+// The referent of a java.lang.ref.Reference (a WeakReference, SoftReference, PhantomReference or
+// the Finalizer of an object with a finalize() method) doesn't keep it alive, so by default it
+// isn't an edge in the graph. Otherwise a cache of weak references would seem to retain
+// everything in it, and the shortest path to a leaked object would often be through a
+// WeakHashMap which isn't what's keeping it alive at all. set_follow_referents() puts them back.
+//
+
+use std::collections::{HashSet, VecDeque};
+use std::io::{Read, Result, Seek};
+
+use super::heap::HeapObject;
+use super::{field_size, ClassDumpRecord, FieldTag, HprofParser};
+use crate::model::Value;
+
+// Nodes are indexes into HeapGraph::ids
+type Node = u32;
+
+const UNREACHABLE: Node = Node::MAX;
+
+#[derive(Debug)]
+pub(super) struct HeapGraph {
+    // All the objects (including classes) in the dump, sorted
+    ids: Vec<u64>,
+    // The immediate dominator of each node. Nodes which are only dominated by the (virtual) root
+    // which all the GC roots hang off are their own dominator, and garbage which hadn't been
+    // collected yet when the dump was taken is UNREACHABLE.
+    dominators: Vec<Node>,
+    retained_sizes: Vec<u64>,
+    // The previous object on the shortest path to each object from a GC root, for explaining why
+    // things are still alive. GC roots are their own parent.
+    parents: Vec<Node>,
+}
+
+impl HeapGraph {
+    pub(super) fn build<R: Read + Seek>(
+        dump: &HprofParser<R>,
+        follow_referents: bool,
+    ) -> Result<HeapGraph> {
+        let mut ids = Vec::with_capacity(dump.object_offsets.len() + dump.class_dump_tab.len());
+        ids.extend(dump.object_offsets.ids());
+        ids.extend(dump.class_dump_tab.keys());
+        ids.sort_unstable();
+        ids.dedup();
+        let node = |id: u64| ids.binary_search(&id).ok().map(|i| i as Node);
+
+        // Outgoing references, in compressed sparse row form
+        let mut edge_starts = Vec::with_capacity(ids.len() + 1);
+        let mut edges = vec![];
+        let mut shallow_sizes = Vec::with_capacity(ids.len());
+        for &id in &ids {
+            edge_starts.push(edges.len());
+            let (size, references) = dump.outgoing_references(id, follow_referents)?;
+            shallow_sizes.push(size);
+            edges.extend(references.into_iter().filter_map(node));
+        }
+        edge_starts.push(edges.len());
+
+        let mut roots: Vec<Node> = dump
+            .roots
+            .iter()
+            .filter_map(|root| node(root.object_id))
+            .collect();
+        roots.sort_unstable();
+        roots.dedup();
+
+        let successors = |n: Node| &edges[edge_starts[n as usize]..edge_starts[n as usize + 1]];
+        let (dominators, postorder) = compute_dominators(ids.len(), &roots, successors);
+
+        let mut parents = vec![UNREACHABLE; ids.len()];
+        let mut queue: VecDeque<Node> = roots.iter().copied().collect();
+        for &root in &roots {
+            parents[root as usize] = root;
+        }
+        while let Some(n) = queue.pop_front() {
+            for &s in successors(n) {
+                if parents[s as usize] == UNREACHABLE {
+                    parents[s as usize] = n;
+                    queue.push_back(s);
+                }
+            }
+        }
+
+        // An object's dominators are all its ancestors in the depth first search, so they come
+        // after it in postorder, and each subtree is totalled before it's added to its parent
+        let mut retained_sizes = shallow_sizes;
+        for &n in &postorder {
+            let dominator = dominators[n as usize];
+            if dominator != n {
+                retained_sizes[dominator as usize] += retained_sizes[n as usize];
+            }
+        }
+        for (n, retained) in retained_sizes.iter_mut().enumerate() {
+            if dominators[n] == UNREACHABLE {
+                *retained = 0;
+            }
+        }
+
+        Ok(HeapGraph {
+            ids,
+            dominators,
+            retained_sizes,
+            parents,
+        })
+    }
+
+    pub(super) fn node(&self, object_id: u64) -> Option<usize> {
+        self.ids.binary_search(&object_id).ok()
+    }
+
+    // How many nodes there are, reachable or not
+    pub(super) fn len(&self) -> usize {
+        self.ids.len()
+    }
+
+    pub(super) fn object_id(&self, n: usize) -> u64 {
+        self.ids[n]
+    }
+
+    // The node's immediate dominator (itself if only the GC roots dominate it), or None if it
+    // isn't reachable
+    pub(super) fn dominator_node(&self, n: usize) -> Option<usize> {
+        match self.dominators[n] {
+            UNREACHABLE => None,
+            d => Some(d as usize),
+        }
+    }
+
+    pub(super) fn retained_node(&self, n: usize) -> u64 {
+        self.retained_sizes[n]
+    }
+
+    // Zero for objects which aren't reachable from any GC root
+    pub(super) fn retained_size(&self, object_id: u64) -> Option<u64> {
+        self.node(object_id).map(|n| self.retained_sizes[n])
+    }
+
+    // What would be freed if all the given objects were collected: the objects dominated by any
+    // of them, counting objects dominated by several of them once
+    pub(super) fn retained_size_of_all(&self, object_ids: &[u64]) -> u64 {
+        let nodes: HashSet<usize> = object_ids.iter().filter_map(|&id| self.node(id)).collect();
+        let dominated_by_another = |mut n: usize| {
+            while self.dominators[n] as usize != n {
+                n = self.dominators[n] as usize;
+                if nodes.contains(&n) {
+                    return true;
+                }
+            }
+            false
+        };
+        nodes
+            .iter()
+            .filter(|&&n| self.dominators[n] != UNREACHABLE && !dominated_by_another(n))
+            .map(|&n| self.retained_sizes[n])
+            .sum()
+    }
+
+    // The objects on the shortest path from a GC root to an object, starting with the root and
+    // ending with the object itself. None if the object isn't reachable.
+    pub(super) fn path_from_root(&self, object_id: u64) -> Option<Vec<u64>> {
+        let mut n = self.node(object_id)?;
+        if self.parents[n] == UNREACHABLE {
+            return None;
+        }
+        let mut path = vec![object_id];
+        while self.parents[n] as usize != n {
+            n = self.parents[n] as usize;
+            path.push(self.ids[n]);
+        }
+        path.reverse();
+        Some(path)
+    }
+
+    // The objects which only the GC roots dominate, with their retained sizes
+    pub(super) fn top_level_dominators(&self) -> Vec<(u64, u64)> {
+        (0..self.ids.len())
+            .filter(|&n| self.dominators[n] as usize == n)
+            .map(|n| (self.ids[n], self.retained_sizes[n]))
+            .collect()
+    }
+
+    pub(super) fn is_reachable(&self, object_id: u64) -> bool {
+        matches!(self.node(object_id), Some(n) if self.parents[n] != UNREACHABLE)
+    }
+
+    // None if the object is only dominated by the GC roots, or isn't reachable at all
+    pub(super) fn immediate_dominator(&self, object_id: u64) -> Option<u64> {
+        let n = self.node(object_id)?;
+        match self.dominators[n] {
+            UNREACHABLE => None,
+            d if d as usize == n => None,
+            d => Some(self.ids[d as usize]),
+        }
+    }
+}
+
+// Returns the immediate dominator of each node, and the reachable nodes in postorder. Nodes which
+// are dominated only by the virtual root are made their own dominator.
+fn compute_dominators<'a, F>(
+    n_nodes: usize,
+    roots: &[Node],
+    successors: F,
+) -> (Vec<Node>, Vec<Node>)
+where
+    F: Fn(Node) -> &'a [Node],
+{
+    let root = n_nodes as Node;
+
+    // Postorder numbering of everything reachable from the virtual root, iteratively since heap
+    // graphs can be very deep (e.g. long linked lists)
+    let mut postorder_num = vec![UNREACHABLE; n_nodes + 1];
+    let mut postorder = Vec::with_capacity(n_nodes + 1);
+    let mut visited = vec![false; n_nodes + 1];
+    let mut stack: Vec<(Node, usize)> = vec![(root, 0)];
+    visited[root as usize] = true;
+    let children = |n: Node| if n == root { roots } else { successors(n) };
+    while let Some(&mut (n, ref mut next)) = stack.last_mut() {
+        let out = children(n);
+        if let Some(&child) = out.get(*next) {
+            *next += 1;
+            if !visited[child as usize] {
+                visited[child as usize] = true;
+                stack.push((child, 0));
+            }
+        } else {
+            postorder_num[n as usize] = postorder.len() as Node;
+            postorder.push(n);
+            stack.pop();
+        }
+    }
+
+    // Predecessors of each reachable node
+    let mut pred_starts = vec![0usize; n_nodes + 2];
+    for &n in &postorder {
+        for &s in children(n) {
+            pred_starts[s as usize + 1] += 1;
+        }
+    }
+    for i in 1..pred_starts.len() {
+        pred_starts[i] += pred_starts[i - 1];
+    }
+    let mut preds = vec![0 as Node; pred_starts[n_nodes + 1]];
+    let mut fill = pred_starts.clone();
+    for &n in &postorder {
+        for &s in children(n) {
+            preds[fill[s as usize]] = n;
+            fill[s as usize] += 1;
+        }
+    }
+
+    let mut idom = vec![UNREACHABLE; n_nodes + 1];
+    idom[root as usize] = root;
+    let intersect = |idom: &[Node], mut a: Node, mut b: Node| {
+        while a != b {
+            while postorder_num[a as usize] < postorder_num[b as usize] {
+                a = idom[a as usize];
+            }
+            while postorder_num[b as usize] < postorder_num[a as usize] {
+                b = idom[b as usize];
+            }
+        }
+        a
+    };
+    let mut changed = true;
+    while changed {
+        changed = false;
+        for &n in postorder.iter().rev().skip(1) {
+            let mut new_idom = UNREACHABLE;
+            for &p in &preds[pred_starts[n as usize]..pred_starts[n as usize + 1]] {
+                if idom[p as usize] == UNREACHABLE {
+                    continue;
+                }
+                new_idom = if new_idom == UNREACHABLE {
+                    p
+                } else {
+                    intersect(&idom, p, new_idom)
+                };
+            }
+            if idom[n as usize] != new_idom {
+                idom[n as usize] = new_idom;
+                changed = true;
+            }
+        }
+    }
+
+    idom.truncate(n_nodes);
+    for (n, d) in idom.iter_mut().enumerate() {
+        if *d == root {
+            *d = n as Node;
+        }
+    }
+    postorder.pop(); // The virtual root
+    (idom, postorder)
+}
+
+impl<R: Read + Seek> HprofParser<R> {
+    // The shallow size of an object, and the objects it refers to (including its class)
+    pub(super) fn outgoing_references(
+        &self,
+        object_id: u64,
+        follow_referents: bool,
+    ) -> Result<(u64, Vec<u64>)> {
+        if let Some(class) = self.class_dump_tab.get(&object_id) {
+            return Ok((self.class_object_size(class), class_references(class)));
+        }
+        let object = match self.read_object(object_id)? {
+            Some(object) => object,
+            None => return Ok((0, vec![])),
+        };
+        let size = self.shallow_size(&object);
+        Ok((size, self.object_references(object, follow_referents)))
+    }
+
+    pub(super) fn object_references(&self, object: HeapObject, follow_referents: bool) -> Vec<u64> {
+        let references = match object {
+            HeapObject::Instance {
+                class_object_id,
+                field_values,
+            } => {
+                let mut references = vec![class_object_id];
+                let mut offset = 0;
+                let mut class_id = class_object_id;
+                let referent = match follow_referents {
+                    true => None,
+                    false => self.referent_field(),
+                };
+                while let Some(class) = self.class_dump_tab.get(&class_id) {
+                    for (i, &(_, field_type)) in class.instance_fields.iter().enumerate() {
+                        let size = field_size(field_type) as usize;
+                        if referent == Some((class_id, i)) {
+                            offset += size;
+                            continue;
+                        }
+                        if let FieldTag::NormalObject | FieldTag::ArrayObject = field_type {
+                            if let Some(bytes) = field_values.get(offset..offset + 8) {
+                                let mut id = [0u8; 8];
+                                id.copy_from_slice(bytes);
+                                references.push(u64::from_be_bytes(id));
+                            }
+                        }
+                        offset += size;
+                    }
+                    class_id = class.superclass_object_id;
+                }
+                references
+            }
+            HeapObject::ObjectArray {
+                class_object_id,
+                mut elements,
+            } => {
+                elements.push(class_object_id);
+                elements
+            }
+            HeapObject::PrimitiveArray { .. } => vec![],
+        };
+        references.into_iter().filter(|&id| id != 0).collect()
+    }
+
+    // java.lang.ref.Reference's class object and which of its instance fields is the referent
+    pub(super) fn referent_field(&self) -> Option<(u64, usize)> {
+        *self.referent_field.get_or_init(|| {
+            let class_id = *self.class_object_ids("java.lang.ref.Reference").first()?;
+            let class = self.class_dump_tab.get(&class_id)?;
+            let i = class
+                .instance_fields
+                .iter()
+                .position(|&(name_id, _)| self.string(name_id).as_deref() == Some("referent"))?;
+            Some((class_id, i))
+        })
+    }
+}
+
+// A class's superclass, loader, and the objects its static fields refer to
+pub(super) fn class_references(class: &ClassDumpRecord) -> Vec<u64> {
+    let mut references = vec![class.superclass_object_id, class.class_loader_object_id];
+    references.extend(
+        class
+            .static_fields
+            .iter()
+            .filter_map(|(_, value)| match value {
+                Value::Object(id) => Some(*id),
+                _ => None,
+            }),
+    );
+    references.retain(|&id| id != 0);
+    references
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn dominators() {
+        // 0 -> 1 -> 3, 0 -> 2 -> 3, 3 -> 4, and 5 is garbage which points at 4
+        let edges: Vec<Vec<Node>> = vec![vec![1, 2], vec![3], vec![3], vec![4], vec![], vec![4]];
+        let (idom, postorder) = compute_dominators(edges.len(), &[0], |n| &edges[n as usize]);
+        assert_eq!(idom, vec![0, 0, 0, 0, 3, UNREACHABLE]);
+        assert_eq!(postorder.len(), 5);
+        assert_eq!(postorder.last(), Some(&0));
+    }
+}
@@ -0,0 +1,653 @@
+//
+// Reading objects out of a heap dump. Indexing a dump only keeps the location of each object in
+// memory, so the objects themselves are read from the dump when they're asked for.
+//
+
+use byteorder::{BigEndian, ReadBytesExt};
+use num_traits::cast::FromPrimitive;
+use std::io::{BufReader, Cursor, Error, ErrorKind, Read, Result, Seek, SeekFrom};
+
+use super::root_path::Reference;
+use super::{field_size, read_value, DataDumpSubRecordTag, FieldTag, HprofParser, RootKind};
+use crate::model::Value;
+use crate::names;
+
+#[allow(dead_code)]
+#[derive(Debug)]
+pub(super) enum HeapObject {
+    Instance {
+        class_object_id: u64,
+        // The raw field values, as described by the ClassDump records of the class and its
+        // superclasses
+        field_values: Vec<u8>,
+    },
+    ObjectArray {
+        class_object_id: u64,
+        elements: Vec<u64>,
+    },
+    PrimitiveArray {
+        element_type: FieldTag,
+        data: Vec<u8>,
+    },
+}
+
+pub(super) fn primitive_type_name(element_type: FieldTag) -> &'static str {
+    match element_type {
+        FieldTag::Boolean => "boolean",
+        FieldTag::Byte => "byte",
+        FieldTag::Char => "char",
+        FieldTag::Short => "short",
+        FieldTag::Int => "int",
+        FieldTag::Long => "long",
+        FieldTag::Float => "float",
+        FieldTag::Double => "double",
+        FieldTag::ArrayObject | FieldTag::NormalObject => "java.lang.Object",
+    }
+}
+
+impl HeapObject {
+    pub(super) fn class_key(&self) -> ClassKey {
+        match self {
+            HeapObject::Instance {
+                class_object_id, ..
+            }
+            | HeapObject::ObjectArray {
+                class_object_id, ..
+            } => ClassKey::Class(*class_object_id),
+            HeapObject::PrimitiveArray { element_type, .. } => {
+                ClassKey::PrimitiveArray(*element_type)
+            }
+        }
+    }
+}
+
+// What kind of object something is, without its contents
+#[derive(Debug, Clone, Copy)]
+pub(super) enum ObjectHeader {
+    Instance { class_object_id: u64 },
+    ObjectArray { class_object_id: u64, length: u32 },
+    PrimitiveArray { element_type: FieldTag, length: u32 },
+}
+
+impl ObjectHeader {
+    pub(super) fn class_key(&self) -> ClassKey {
+        match *self {
+            ObjectHeader::Instance { class_object_id }
+            | ObjectHeader::ObjectArray {
+                class_object_id, ..
+            } => ClassKey::Class(class_object_id),
+            ObjectHeader::PrimitiveArray { element_type, .. } => {
+                ClassKey::PrimitiveArray(element_type)
+            }
+        }
+    }
+}
+
+// Primitive arrays don't have a class in dumps, just an element type
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub(super) enum ClassKey {
+    Class(u64),
+    PrimitiveArray(FieldTag),
+}
+
+// Reads an InstanceDump, ObjectArrayDump or PrimitiveArrayDump subrecord
+fn read_object_record<R: Read>(reader: &mut R, object_id: u64) -> Result<HeapObject> {
+    let subtag = reader.read_u8()?;
+    let _object_id = reader.read_u64::<BigEndian>()?; // XXX: Assume
+    let _strace_serial_num = reader.read_u32::<BigEndian>()?;
+    Ok(match FromPrimitive::from_u8(subtag) {
+        Some(DataDumpSubRecordTag::InstanceDump) => {
+            let class_object_id = reader.read_u64::<BigEndian>()?; // XXX: Assume
+            let n_bytes = reader.read_u32::<BigEndian>()?;
+            let mut field_values = vec![0u8; n_bytes as usize];
+            reader.read_exact(&mut field_values)?;
+            HeapObject::Instance {
+                class_object_id,
+                field_values,
+            }
+        }
+        Some(DataDumpSubRecordTag::ObjectArrayDump) => {
+            let n_elements = reader.read_u32::<BigEndian>()?;
+            let class_object_id = reader.read_u64::<BigEndian>()?; // XXX: Assume
+            let mut elements = vec![0u64; n_elements as usize];
+            reader.read_u64_into::<BigEndian>(&mut elements)?;
+            HeapObject::ObjectArray {
+                class_object_id,
+                elements,
+            }
+        }
+        Some(DataDumpSubRecordTag::PrimitiveArrayDump) => {
+            let n_elements = reader.read_u32::<BigEndian>()?;
+            let element_type: FieldTag = FromPrimitive::from_u8(reader.read_u8()?)
+                .ok_or_else(|| corrupt(format!("Bad array type in object {}", object_id)))?;
+            let mut data = vec![0u8; (u64::from(n_elements) * field_size(element_type)) as usize];
+            reader.read_exact(&mut data)?;
+            HeapObject::PrimitiveArray { element_type, data }
+        }
+        _ => {
+            return Err(corrupt(format!(
+                "Object {} points at subrecord type {}",
+                object_id, subtag
+            )))
+        }
+    })
+}
+
+// Reads the start of an InstanceDump, ObjectArrayDump or PrimitiveArrayDump subrecord, and says
+// how much it read
+fn read_object_header<R: Read>(reader: &mut R, object_id: u64) -> Result<(ObjectHeader, u64)> {
+    let subtag = reader.read_u8()?;
+    let _object_id = reader.read_u64::<BigEndian>()?; // XXX: Assume
+    let _strace_serial_num = reader.read_u32::<BigEndian>()?;
+    let common = 1 + 8 + 4;
+    Ok(match FromPrimitive::from_u8(subtag) {
+        Some(DataDumpSubRecordTag::InstanceDump) => {
+            let class_object_id = reader.read_u64::<BigEndian>()?; // XXX: Assume
+            (ObjectHeader::Instance { class_object_id }, common + 8)
+        }
+        Some(DataDumpSubRecordTag::ObjectArrayDump) => {
+            let length = reader.read_u32::<BigEndian>()?;
+            let class_object_id = reader.read_u64::<BigEndian>()?; // XXX: Assume
+            (
+                ObjectHeader::ObjectArray {
+                    class_object_id,
+                    length,
+                },
+                common + 4 + 8,
+            )
+        }
+        Some(DataDumpSubRecordTag::PrimitiveArrayDump) => {
+            let length = reader.read_u32::<BigEndian>()?;
+            let element_type = FromPrimitive::from_u8(reader.read_u8()?)
+                .ok_or_else(|| corrupt(format!("Bad array type in object {}", object_id)))?;
+            (
+                ObjectHeader::PrimitiveArray {
+                    element_type,
+                    length,
+                },
+                common + 4 + 1,
+            )
+        }
+        _ => {
+            return Err(corrupt(format!(
+                "Object {} points at subrecord type {}",
+                object_id, subtag
+            )))
+        }
+    })
+}
+
+// The length of the subrecord an object was read from
+fn record_size(object: &HeapObject) -> u64 {
+    // Tag, object ID and stack trace serial number
+    let common = 1 + 8 + 4;
+    common
+        + match object {
+            HeapObject::Instance { field_values, .. } => 8 + 4 + field_values.len() as u64,
+            HeapObject::ObjectArray { elements, .. } => 4 + 8 + 8 * elements.len() as u64,
+            HeapObject::PrimitiveArray { data, .. } => 4 + 1 + data.len() as u64,
+        }
+}
+
+fn corrupt(msg: String) -> Error {
+    Error::new(ErrorKind::InvalidData, msg)
+}
+
+impl<R: Read + Seek> HprofParser<R> {
+    // Reads from the dump at 'offset'. 'f' returns what it read and how many bytes that took, so
+    // that the next read can seek relative to where this one finished, which keeps whatever's
+    // been buffered if it's close by.
+    pub(super) fn read_at<T, F>(&self, offset: u64, f: F) -> Result<T>
+    where
+        F: FnOnce(&mut BufReader<R>) -> Result<(T, u64)>,
+    {
+        let mut reader = self.reader.borrow_mut();
+        let seek = match self.read_position.get() {
+            Some(position) => reader.seek_relative(offset as i64 - position as i64),
+            None => reader.seek(SeekFrom::Start(offset)).map(|_| ()),
+        };
+        match seek.and_then(|_| f(&mut reader)) {
+            Ok((value, bytes)) => {
+                self.read_position.set(Some(offset + bytes));
+                Ok(value)
+            }
+            Err(e) => {
+                self.read_position.set(None);
+                Err(e)
+            }
+        }
+    }
+
+    pub(super) fn read_object(&self, object_id: u64) -> Result<Option<HeapObject>> {
+        let offset = match self.object_offsets.get(object_id) {
+            Some(offset) => offset,
+            None => return Ok(None),
+        };
+        self.read_at(offset, |reader| {
+            let object = read_object_record(reader, object_id)?;
+            let bytes = record_size(&object);
+            Ok((Some(object), bytes))
+        })
+    }
+
+    // Objects sorted by where they are in the dump, for reading them all
+    fn objects_in_file_order(&self) -> Vec<(u64, u64)> {
+        self.object_offsets.in_file_order()
+    }
+
+    // Calls 'f' with every object (but not class) in the dump, in the order they appear in it,
+    // which is much faster than reading them one at a time
+    pub(super) fn for_each_object<F>(&self, mut f: F) -> Result<()>
+    where
+        F: FnMut(u64, HeapObject) -> Result<()>,
+    {
+        for (offset, object_id) in self.objects_in_file_order() {
+            let object = self.read_at(offset, |reader| {
+                let object = read_object_record(reader, object_id)?;
+                let bytes = record_size(&object);
+                Ok((object, bytes))
+            })?;
+            f(object_id, object)?;
+        }
+        Ok(())
+    }
+
+    // All the instances of the given classes (but not of their subclasses), sorted by ID. This
+    // reads the header of every object in the dump.
+    pub(super) fn instances_of(&self, class_object_ids: &[u64]) -> Result<Vec<u64>> {
+        let mut instances = vec![];
+        self.for_each_object_header(|object_id, header| {
+            if let ObjectHeader::Instance { class_object_id }
+            | ObjectHeader::ObjectArray {
+                class_object_id, ..
+            } = header
+            {
+                if class_object_ids.contains(&class_object_id) {
+                    instances.push(object_id);
+                }
+            }
+        })?;
+        instances.sort_unstable();
+        Ok(instances)
+    }
+
+    // Like for_each_object(), but only reads enough of each object to know what it is, which is
+    // much cheaper for big arrays
+    pub(super) fn for_each_object_header<F>(&self, mut f: F) -> Result<()>
+    where
+        F: FnMut(u64, ObjectHeader),
+    {
+        for object in self.object_headers() {
+            let (object_id, header) = object?;
+            f(object_id, header);
+        }
+        Ok(())
+    }
+
+    // The same as an iterator, for callers which may not need to look at every object. Each
+    // header is only read when it's asked for.
+    pub(super) fn object_headers(&self) -> impl Iterator<Item = Result<(u64, ObjectHeader)>> + '_ {
+        self.objects_in_file_order()
+            .into_iter()
+            .map(move |(offset, object_id)| {
+                let header =
+                    self.read_at(offset, |reader| read_object_header(reader, object_id))?;
+                Ok((object_id, header))
+            })
+    }
+
+    pub(super) fn class_key_name(&self, key: ClassKey) -> String {
+        match key {
+            ClassKey::Class(class_object_id) => self
+                .class_serials
+                .get(&class_object_id)
+                .and_then(|serial_num| self.class_tab.get(serial_num))
+                .map(|class| self.class_name(class))
+                .unwrap_or_else(|| format!("<unknown class {:x}>", class_object_id)),
+            ClassKey::PrimitiveArray(element_type) => {
+                format!("{}[]", primitive_type_name(element_type))
+            }
+        }
+    }
+
+    // The class objects of all the classes with the given (dotted) name
+    pub(super) fn class_object_ids(&self, name: &str) -> Vec<u64> {
+        // A hidden class's name can be in any of three forms (see names.rs), so those are
+        // compared in the source form
+        if names::is_hidden_class(name) {
+            let source = names::source_name(name);
+            return self
+                .class_tab
+                .values()
+                .filter(|class| self.class_name(class) == source)
+                .map(|class| class.object_id)
+                .collect();
+        }
+        let name_ids = self.string_ids(&names::internal_name(name));
+        self.class_tab
+            .values()
+            .filter(|class| name_ids.contains(&class.strname_id))
+            .map(|class| class.object_id)
+            .collect()
+    }
+
+    // Like class_object_ids(), but with every class which extends one of those as well. Dumps
+    // don't record which interfaces a class implements, so 'name' has to be a class.
+    pub(super) fn class_object_ids_with_subclasses(&self, name: &str) -> Vec<u64> {
+        let bases = self.class_object_ids(name);
+        self.class_dump_tab
+            .values()
+            .filter(|class| {
+                let mut class_object_id = class.class_object_id;
+                while class_object_id != 0 {
+                    if bases.contains(&class_object_id) {
+                        return true;
+                    }
+                    class_object_id = match self.class_dump_tab.get(&class_object_id) {
+                        Some(class) => class.superclass_object_id,
+                        None => 0,
+                    };
+                }
+                false
+            })
+            .map(|class| class.class_object_id)
+            .collect()
+    }
+
+    // The serial number of the stack trace of where an object was allocated. Dumps only have
+    // these when allocation sites were being recorded (e.g. by the old hprof agent with
+    // depth > 0), so it's usually None.
+    pub(super) fn allocation_trace(&self, object_id: u64) -> Result<Option<u32>> {
+        let offset = match self.object_offsets.get(object_id) {
+            Some(offset) => offset,
+            None => return Ok(None),
+        };
+        let serial_num = self.read_at(offset, |reader| {
+            let _subtag = reader.read_u8()?;
+            let _object_id = reader.read_u64::<BigEndian>()?; // XXX: Assume
+            Ok((reader.read_u32::<BigEndian>()?, 1 + 8 + 4))
+        })?;
+        Ok(match self.trace_tab.get(&serial_num) {
+            Some(trace) if !trace.frame_ids.is_empty() => Some(serial_num),
+            _ => None,
+        })
+    }
+
+    // The name of the class of an object. Classes themselves are java.lang.Class instances.
+    pub(super) fn object_class_name(&self, object_id: u64) -> Result<Option<String>> {
+        if self.class_dump_tab.contains_key(&object_id) {
+            return Ok(Some("java.lang.Class".to_string()));
+        }
+        let class_object_id = match self.read_object(object_id)? {
+            Some(HeapObject::Instance {
+                class_object_id, ..
+            })
+            | Some(HeapObject::ObjectArray {
+                class_object_id, ..
+            }) => class_object_id,
+            Some(HeapObject::PrimitiveArray { element_type, .. }) => {
+                return Ok(Some(format!("{}[]", primitive_type_name(element_type))))
+            }
+            None => return Ok(None),
+        };
+        Ok(self
+            .class_serials
+            .get(&class_object_id)
+            .and_then(|serial_num| self.class_tab.get(serial_num))
+            .map(|class| self.class_name(class)))
+    }
+
+    // How one object refers to another, e.g. ".next", "[3]" or "static INSTANCE"
+    pub(super) fn reference_name(&self, from: u64, to: u64) -> Result<String> {
+        Ok(self.reference(from, to)?.to_string())
+    }
+
+    pub(super) fn reference(&self, from: u64, to: u64) -> Result<Reference> {
+        if let Some(class) = self.class_dump_tab.get(&from) {
+            let static_field = class.static_fields.iter().find(|(_, value)| match value {
+                Value::Object(id) => *id == to,
+                _ => false,
+            });
+            return Ok(match static_field {
+                Some(&(name_id, _)) => Reference::StaticField(
+                    self.string(name_id)
+                        .as_deref()
+                        .unwrap_or("<unknown>")
+                        .to_string(),
+                ),
+                None if class.class_loader_object_id == to => Reference::ClassLoader,
+                None if class.superclass_object_id == to => Reference::Superclass,
+                None => Reference::Unknown,
+            });
+        }
+        Ok(match self.read_object(from)? {
+            Some(HeapObject::Instance {
+                class_object_id, ..
+            }) => {
+                let fields = self.instance_fields(from)?.unwrap_or_default();
+                match fields
+                    .into_iter()
+                    .find(|(_, value)| *value == Value::Object(to))
+                {
+                    Some((name, _)) => Reference::Field(name),
+                    None if class_object_id == to => Reference::Class,
+                    None => Reference::Unknown,
+                }
+            }
+            Some(HeapObject::ObjectArray {
+                class_object_id,
+                elements,
+            }) => match elements.iter().position(|&id| id == to) {
+                Some(i) => Reference::ArrayElement(i),
+                None if class_object_id == to => Reference::Class,
+                None => Reference::Unknown,
+            },
+            _ => Reference::Unknown,
+        })
+    }
+
+    // The shallow size of an object or class, or 0 if there's no such object
+    pub(super) fn object_size(&self, object_id: u64) -> Result<u64> {
+        if let Some(class) = self.class_dump_tab.get(&object_id) {
+            return Ok(self.class_object_size(class));
+        }
+        Ok(match self.read_object(object_id)? {
+            Some(object) => self.shallow_size(&object),
+            None => 0,
+        })
+    }
+
+    // A path through the heap (e.g. from HeapGraph::path_from_root()), one step per object,
+    // e.g. ["java.lang.Thread@7f0012", ".threadLocals java.lang.ThreadLocal$ThreadLocalMap@7f0040"].
+    // The root says which thread holds it, if a thread does (see root_step()).
+    pub(super) fn reference_chain(&self, path: &[u64]) -> Result<Vec<String>> {
+        let mut chain = vec![];
+        for (i, &object_id) in path.iter().enumerate() {
+            let class_name = self
+                .object_class_name(object_id)?
+                .unwrap_or_else(|| "<unknown>".to_string());
+            chain.push(if i == 0 {
+                self.root_step(object_id, &class_name)?
+            } else {
+                let reference = self.reference_name(path[i - 1], object_id)?;
+                format!("{} {}@{:x}", reference, class_name, object_id)
+            });
+        }
+        Ok(chain)
+    }
+
+    // The first step of a reference chain, e.g. "java.lang.Object[]@7f0012 (held by thread
+    // \"main\" at com.example.Worker.run(Worker.java:42))"
+    pub(super) fn root_step(&self, object_id: u64, class_name: &str) -> Result<String> {
+        Ok(match self.root_holder(object_id)? {
+            Some(holder) => format!("{}@{:x} (held by {})", class_name, object_id, holder),
+            None => format!("{}@{:x}", class_name, object_id),
+        })
+    }
+
+    // Which thread holds a GC root, and where, e.g. "thread \"main\" at com.example.Worker.run(
+    // Worker.java:42)" for a local variable. None if the object isn't a root which belongs to a
+    // thread. If it's a local in several frames, this is the first the dump lists.
+    pub(super) fn root_holder(&self, object_id: u64) -> Result<Option<String>> {
+        let thread_roots = self.roots.iter().filter(|root| {
+            root.object_id == object_id
+                && root.thread_serial_num.is_some()
+                && root.kind != RootKind::ThreadObject
+        });
+        // The roots which say which frame they're in come first
+        let root = match thread_roots.min_by_key(|root| root.frame_num.is_none()) {
+            Some(root) => root,
+            None => return Ok(None),
+        };
+        let serial_num = root.thread_serial_num.unwrap_or_default();
+        let thread = format!("thread \"{}\"", self.thread_name(serial_num)?);
+        let frame = match (root.frame_num, self.thread_trace(serial_num)) {
+            (Some(frame_num), Some(trace)) => self
+                .trace_frames(trace.serial_num)
+                .into_iter()
+                .nth(frame_num as usize),
+            _ => None,
+        };
+        Ok(Some(match frame {
+            Some(frame) => format!("{} at {}", thread, frame),
+            None => thread,
+        }))
+    }
+
+    // The fields of an instance as (name, value), starting with those declared by its class and
+    // followed by those of each superclass in turn. None if there's no such instance.
+    pub(super) fn instance_fields(&self, object_id: u64) -> Result<Option<Vec<(String, Value)>>> {
+        let (mut class_object_id, field_values) = match self.read_object(object_id)? {
+            Some(HeapObject::Instance {
+                class_object_id,
+                field_values,
+            }) => (class_object_id, field_values),
+            _ => return Ok(None),
+        };
+        let mut reader = Cursor::new(field_values);
+        let mut fields = vec![];
+        while class_object_id != 0 {
+            let class = self
+                .class_dump_tab
+                .get(&class_object_id)
+                .ok_or_else(|| corrupt(format!("Missing class dump for {}", class_object_id)))?;
+            for &(name_id, field_type) in &class.instance_fields {
+                let name = self
+                    .string(name_id)
+                    .as_deref()
+                    .unwrap_or("<unknown>")
+                    .to_string();
+                fields.push((name, read_value(&mut reader, field_type)?));
+            }
+            class_object_id = class.superclass_object_id;
+        }
+        Ok(Some(fields))
+    }
+
+    // The class of an object, as the object ID of the class. None if there's no such object, or
+    // it's an array of a primitive type whose class isn't in the dump. Classes themselves are
+    // instances of java.lang.Class.
+    pub(super) fn class_of(&self, object_id: u64) -> Result<Option<u64>> {
+        if self.class_serials.contains_key(&object_id) {
+            return Ok(self
+                .class_tab
+                .values()
+                .find(|class| self.class_name(class) == "java.lang.Class")
+                .map(|class| class.object_id));
+        }
+        Ok(match self.read_object(object_id)? {
+            Some(HeapObject::Instance {
+                class_object_id, ..
+            })
+            | Some(HeapObject::ObjectArray {
+                class_object_id, ..
+            }) => Some(class_object_id),
+            // Primitive array records don't refer to their class, but it's usually loaded
+            Some(HeapObject::PrimitiveArray { element_type, .. }) => {
+                let name = format!("{}[]", primitive_type_name(element_type));
+                self.class_tab
+                    .values()
+                    .find(|class| self.class_name(class) == name)
+                    .map(|class| class.object_id)
+            }
+            None => None,
+        })
+    }
+
+    // The value of the instance field 'name_id' declared by the class 'declaring_class', which
+    // unlike field_value() can tell apart fields hidden by a subclass. None if the object isn't
+    // an instance, or its class has no such field.
+    pub(super) fn declared_field_value(
+        &self,
+        object_id: u64,
+        declaring_class: u64,
+        name_id: u64,
+    ) -> Result<Option<Value>> {
+        let (mut class_object_id, field_values) = match self.read_object(object_id)? {
+            Some(HeapObject::Instance {
+                class_object_id,
+                field_values,
+            }) => (class_object_id, field_values),
+            _ => return Ok(None),
+        };
+        let mut reader = Cursor::new(field_values);
+        while class_object_id != 0 {
+            let class = self
+                .class_dump_tab
+                .get(&class_object_id)
+                .ok_or_else(|| corrupt(format!("Missing class dump for {}", class_object_id)))?;
+            for &(field_name_id, field_type) in &class.instance_fields {
+                let value = read_value(&mut reader, field_type)?;
+                if class_object_id == declaring_class && field_name_id == name_id {
+                    return Ok(Some(value));
+                }
+            }
+            class_object_id = class.superclass_object_id;
+        }
+        Ok(None)
+    }
+
+    // The value of the named field of an instance, if it has one. If a subclass hides a field of
+    // a superclass, this is the subclass's field.
+    pub(super) fn field_value(&self, object_id: u64, name: &str) -> Result<Option<Value>> {
+        let fields = self.instance_fields(object_id)?.unwrap_or_default();
+        Ok(fields.into_iter().find(|(n, _)| n == name).map(|(_, v)| v))
+    }
+
+    pub(super) fn object_field(&self, object_id: u64, name: &str) -> Result<Option<u64>> {
+        Ok(match self.field_value(object_id, name)? {
+            Some(Value::Object(id)) => Some(id),
+            _ => None,
+        })
+    }
+
+    // The contents of a java.lang.String. Handles both the char[] representation (Java 8 and
+    // earlier) and the byte[] plus coder one of compact strings (Java 9+).
+    pub(super) fn read_string(&self, object_id: u64) -> Result<Option<String>> {
+        let value_id = match self.object_field(object_id, "value")? {
+            Some(id) => id,
+            None => return Ok(None),
+        };
+        let (element_type, data) = match self.read_object(value_id)? {
+            Some(HeapObject::PrimitiveArray { element_type, data }) => (element_type, data),
+            _ => return Ok(None),
+        };
+        let utf16: Vec<u16> = match element_type {
+            FieldTag::Char => data
+                .chunks_exact(2)
+                .map(|c| u16::from_be_bytes([c[0], c[1]]))
+                .collect(),
+            FieldTag::Byte => match self.field_value(object_id, "coder")? {
+                // UTF-16 in the byte order of the machine the dump came from
+                // XXX: Assumes little endian
+                Some(Value::Byte(1)) => data
+                    .chunks_exact(2)
+                    .map(|c| u16::from_le_bytes([c[0], c[1]]))
+                    .collect(),
+                _ => data.iter().map(|&b| u16::from(b)).collect(),
+            },
+            _ => return Ok(None),
+        };
+        Ok(Some(String::from_utf16_lossy(&utf16)))
+    }
+}
@@ -0,0 +1,182 @@
+//
+// Indexing a dump while the JVM is still writing it. A dump of a big heap takes minutes to write,
+// and indexing it takes about as long again, so it's quicker to do both at once: parse_available()
+// parses the records which have been written completely so far, and says where it got to, and
+// the next call carries on from there. A record is only parsed once all the bytes its length
+// promises are in the file, so a half-written one is left for next time.
+//
+// JDK 8 writes each heap dump record's length as 0 to begin with, and only fills it in once the
+// record is finished, so until then the record looks empty, with its subrecords after it. A
+// segment is held back until the next segment or the HEAP DUMP END appears after it, and a 1.0.1
+// dump's HEAP DUMP until its length reaches the end of the dump.
+//
+// The dump is complete once its HEAP DUMP END record has been parsed, or for a 1.0.1 dump, which
+// has one HEAP DUMP record and no end, once that has. finish() makes an
+// HprofJavaVirtualMachine of what's been parsed, which for a dump that isn't complete is missing
+// whatever hadn't been written.
+//
+
+use num_traits::cast::FromPrimitive;
+use std::io::{Error, ErrorKind, Read, Result, Seek, SeekFrom};
+use std::rc::Rc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use super::symbols::{self, StringCache, Symbols};
+use super::{
+    parse_record, Dump, HprofJavaVirtualMachine, HprofParser, ReadSeek, RecordTag, HEADER_SIZE,
+};
+
+// How far parsing has got
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct HprofCheckpoint {
+    // Where the next record starts in the dump, and so how much of it has been parsed
+    pub offset: u64,
+    // Records parsed so far, not counting the header
+    pub records: u64,
+    // Whether the HEAP DUMP END record (or a 1.0.1 dump's HEAP DUMP) has been parsed, so there's
+    // nothing more to come
+    pub complete: bool,
+}
+
+pub struct IncrementalHprof {
+    parser: Dump,
+    records: u64,
+    complete: bool,
+}
+
+// A record's tag, time and length
+const RECORD_HEADER_SIZE: u64 = 9;
+
+impl IncrementalHprof {
+    // Fails with ErrorKind::UnexpectedEof if not even the dump's header has been written yet
+    pub fn new<R: Read + Seek + 'static>(reader: R) -> Result<IncrementalHprof> {
+        Self::with_string_cache(reader, symbols::DEFAULT_BUDGET, &StringCache::new())
+    }
+
+    // As with HprofJavaVirtualMachine::with_string_cache()
+    pub fn with_string_cache<R: Read + Seek + 'static>(
+        mut reader: R,
+        budget: usize,
+        cache: &StringCache,
+    ) -> Result<IncrementalHprof> {
+        let length = reader.seek(SeekFrom::End(0))?;
+        if length < HEADER_SIZE {
+            return Err(Error::new(
+                ErrorKind::UnexpectedEof,
+                "The heap dump's header hasn't been written yet",
+            ));
+        }
+        reader.seek(SeekFrom::Start(0))?;
+        let reader: Box<dyn ReadSeek> = Box::new(reader);
+        let mut parser: Dump = HprofParser::new(reader)?;
+        parser.symbols = Symbols::new(budget, cache.clone());
+        Ok(IncrementalHprof {
+            parser,
+            records: 0,
+            complete: false,
+        })
+    }
+
+    // Parse every record which has been written completely since the last call
+    pub fn parse_available(&mut self) -> Result<HprofCheckpoint> {
+        let position = self.parser.position;
+        let reader = self.parser.reader.get_mut();
+        let end = reader.seek(SeekFrom::End(0))?;
+        reader.seek(SeekFrom::Start(position))?;
+        self.parser.read_position.set(None);
+
+        while !self.complete && self.parser.position + RECORD_HEADER_SIZE <= end {
+            // Peek at the record's length
+            let reader = self.parser.reader.get_mut();
+            let mut header = [0u8; RECORD_HEADER_SIZE as usize];
+            reader.read_exact(&mut header)?;
+            reader.seek_relative(-(RECORD_HEADER_SIZE as i64))?;
+            let length = u32::from_be_bytes([header[5], header[6], header[7], header[8]]);
+            let next = self.parser.position + RECORD_HEADER_SIZE + u64::from(length);
+            if next > end {
+                break;
+            }
+            match FromPrimitive::from_u8(header[0]) {
+                Some(RecordTag::HeapDumpSegment) if !self.heap_dump_record_at(next, end)? => break,
+                Some(RecordTag::HeapDump) if length == 0 || next != end => break,
+                _ => {}
+            }
+            let record = parse_record(&mut self.parser)?;
+            self.records += 1;
+            self.complete = matches!(
+                record.tag,
+                Some(RecordTag::HeapDumpEnd | RecordTag::HeapDump)
+            );
+        }
+        Ok(self.checkpoint())
+    }
+
+    // Whether a whole HEAP DUMP SEGMENT or HEAP DUMP END record header has been written at
+    // 'offset'. Leaves the reader where the current record starts.
+    fn heap_dump_record_at(&mut self, offset: u64, end: u64) -> Result<bool> {
+        if offset + RECORD_HEADER_SIZE > end {
+            return Ok(false);
+        }
+        let position = self.parser.position;
+        let reader = self.parser.reader.get_mut();
+        reader.seek(SeekFrom::Start(offset))?;
+        let mut tag = [0u8];
+        reader.read_exact(&mut tag)?;
+        reader.seek(SeekFrom::Start(position))?;
+        Ok(matches!(
+            FromPrimitive::from_u8(tag[0]),
+            Some(RecordTag::HeapDumpSegment | RecordTag::HeapDumpEnd)
+        ))
+    }
+
+    // Keep parsing as the dump is written, checking for more every 'poll', until it's complete.
+    // Fails with ErrorKind::TimedOut if no more complete records have been written for
+    // 'idle_timeout', e.g. because the JVM died while writing it.
+    pub fn parse_until_complete(
+        &mut self,
+        poll: Duration,
+        idle_timeout: Duration,
+    ) -> Result<HprofCheckpoint> {
+        let mut last_progress = Instant::now();
+        loop {
+            let before = self.parser.position;
+            let checkpoint = self.parse_available()?;
+            if checkpoint.complete {
+                return Ok(checkpoint);
+            }
+            if checkpoint.offset != before {
+                last_progress = Instant::now();
+            } else if last_progress.elapsed() >= idle_timeout {
+                return Err(Error::new(
+                    ErrorKind::TimedOut,
+                    format!(
+                        "No more of the heap dump was written for {:?}, after {} bytes",
+                        idle_timeout, checkpoint.offset
+                    ),
+                ));
+            }
+            thread::sleep(poll);
+        }
+    }
+
+    pub fn checkpoint(&self) -> HprofCheckpoint {
+        HprofCheckpoint {
+            offset: self.parser.position,
+            records: self.records,
+            complete: self.complete,
+        }
+    }
+
+    pub fn is_complete(&self) -> bool {
+        self.complete
+    }
+
+    // Stop parsing, and look at what's been parsed so far
+    pub fn finish(mut self) -> HprofJavaVirtualMachine {
+        self.parser.object_offsets.finish();
+        HprofJavaVirtualMachine {
+            dump: Rc::new(self.parser),
+        }
+    }
+}
@@ -0,0 +1,148 @@
+//
+// Where each object is in the dump, by ID. Everything which follows references (paths to GC
+// roots, referrers, the dominator tree) turns object IDs into offsets millions of times, and a
+// HashMap of every object in a big heap costs more memory than the rest of the index put
+// together. So the (ID, offset) pairs are kept in one sorted Vec, with a directory of where each
+// range of IDs starts in it.
+//
+// Object IDs are addresses, so they're spread fairly evenly between the lowest and the highest,
+// and the directory has a slot for every few objects' worth of that range. Finding an ID is a
+// lookup in the directory, then a search of the handful of entries in its slot, so it takes the
+// same time however big the heap is. Gaps between the heap's regions just mean some slots are
+// empty, and a slot that's crowded is only slower by a binary search.
+//
+
+// About how many objects share each directory slot
+const OBJECTS_PER_SLOT: usize = 2;
+
+#[derive(Debug, Default)]
+pub(super) struct ObjectIndex {
+    // Sorted by ID once finish() has been called, and in the order they were added until then
+    entries: Vec<(u64, u64)>,
+    lowest: u64,
+    // How many bits of (ID - lowest) to drop to find an ID's slot
+    shift: u32,
+    // Where each slot starts in 'entries', with one more at the end for where the last one ends
+    slots: Vec<usize>,
+}
+
+impl ObjectIndex {
+    pub(super) fn insert(&mut self, object_id: u64, offset: u64) {
+        self.entries.push((object_id, offset));
+    }
+
+    // Called once everything has been inserted, before anything is looked up
+    pub(super) fn finish(&mut self) {
+        // An object written twice is where it was last written, as it would be in a HashMap
+        self.entries
+            .sort_unstable_by_key(|&(id, offset)| (id, std::cmp::Reverse(offset)));
+        self.entries.dedup_by_key(|&mut (id, _)| id);
+        self.entries.shrink_to_fit();
+
+        let (lowest, highest) = match (self.entries.first(), self.entries.last()) {
+            (Some(&(lowest, _)), Some(&(highest, _))) => (lowest, highest),
+            _ => return,
+        };
+        let wanted_slots = (self.entries.len() / OBJECTS_PER_SLOT).max(1) as u64;
+        let range = highest - lowest;
+        let mut shift = 0;
+        while shift < 63 && (range >> shift) >= wanted_slots {
+            shift += 1;
+        }
+        let slot_count = (range >> shift) as usize + 1;
+        let mut slots = Vec::with_capacity(slot_count + 1);
+        for (i, &(id, _)) in self.entries.iter().enumerate() {
+            let slot = ((id - lowest) >> shift) as usize;
+            while slots.len() <= slot {
+                slots.push(i);
+            }
+        }
+        slots.push(self.entries.len());
+        self.lowest = lowest;
+        self.shift = shift;
+        self.slots = slots;
+    }
+
+    // The object's offset in the dump
+    pub(super) fn get(&self, object_id: u64) -> Option<u64> {
+        if object_id < self.lowest {
+            return None;
+        }
+        let slot = ((object_id - self.lowest) >> self.shift) as usize;
+        let (&start, &end) = (self.slots.get(slot)?, self.slots.get(slot + 1)?);
+        let entries = &self.entries[start..end];
+        entries
+            .binary_search_by_key(&object_id, |&(id, _)| id)
+            .ok()
+            .map(|i| entries[i].1)
+    }
+
+    pub(super) fn contains(&self, object_id: u64) -> bool {
+        self.get(object_id).is_some()
+    }
+
+    pub(super) fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    // Every object ID, in order
+    pub(super) fn ids(&self) -> impl Iterator<Item = u64> + '_ {
+        self.entries.iter().map(|&(id, _)| id)
+    }
+
+    // (offset, ID) for every object, sorted by where they are in the dump, for reading them all
+    pub(super) fn in_file_order(&self) -> Vec<(u64, u64)> {
+        let mut objects: Vec<(u64, u64)> = self
+            .entries
+            .iter()
+            .map(|&(id, offset)| (offset, id))
+            .collect();
+        objects.sort_unstable();
+        objects
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::ObjectIndex;
+
+    fn index(entries: &[(u64, u64)]) -> ObjectIndex {
+        let mut index = ObjectIndex::default();
+        for &(id, offset) in entries {
+            index.insert(id, offset);
+        }
+        index.finish();
+        index
+    }
+
+    #[test]
+    fn lookups() {
+        // Two heap regions far apart, added out of order
+        let mut entries = vec![];
+        for i in 0..1000 {
+            entries.push((0x7_0000_0000 + i * 24, 100 + i));
+            entries.push((0x1000 + i * 16, 5000 + i));
+        }
+        let index = index(&entries);
+        assert_eq!(index.len(), 2000);
+        for &(id, offset) in &entries {
+            assert_eq!(index.get(id), Some(offset));
+        }
+        for id in [0, 0x1008, 0xFFF, 0x7_0000_0001, u64::MAX] {
+            assert_eq!(index.get(id), None);
+        }
+        let ids: Vec<u64> = index.ids().collect();
+        assert!(ids.windows(2).all(|w| w[0] < w[1]));
+        assert_eq!(index.in_file_order()[0], (100, 0x7_0000_0000));
+    }
+
+    #[test]
+    fn duplicates_and_empty() {
+        assert_eq!(index(&[]).get(0), None);
+        let index = index(&[(8, 1), (8, 2), (u64::MAX, 3)]);
+        assert_eq!(index.len(), 2);
+        assert_eq!(index.get(8), Some(2));
+        assert_eq!(index.get(u64::MAX), Some(3));
+        assert!(!index.contains(16));
+    }
+}
@@ -0,0 +1,237 @@
+//
+// Sets of objects, for the questions no one report answers, e.g. "which byte[]s are only kept
+// alive by the cache":
+//
+//   let cache = jvm.set_of(&[cache_id])?;
+//   let bytes = jvm.instance_set(&ClassPattern::new("byte[]")?)?;
+//   let only_cached = &bytes & &cache.retained();
+//
+// or, following references rather than dominators, the byte[]s the cache can reach which nothing
+// else can:
+//
+//   let elsewhere = jvm.reachable_avoiding(&jvm.gc_root_set()?, &cache)?;
+//   let only_cached = &(&bytes & &jvm.reachable_from(&cache)?) - &elsewhere;
+//
+// A set is a bit per object in the dump, so they're cheap to combine. Making one builds the
+// dominator tree if it hasn't been already, and the reachability ones read every object they
+// reach, as the dominator tree doesn't keep the references. Sets from different dumps (or from
+// before the size model or follow_referents was changed) can't be combined.
+//
+
+use std::collections::HashMap;
+use std::io::Result;
+use std::ops::{BitAnd, BitOr, Sub};
+use std::rc::Rc;
+
+use super::graph::HeapGraph;
+use super::HprofJavaVirtualMachine;
+use crate::pattern::ClassPattern;
+
+#[derive(Clone)]
+pub struct ObjectSet {
+    graph: Rc<HeapGraph>,
+    // A bit for each node in the graph
+    bits: Vec<u64>,
+}
+
+impl ObjectSet {
+    fn empty(graph: Rc<HeapGraph>) -> ObjectSet {
+        let words = graph.len().div_ceil(64);
+        ObjectSet {
+            graph,
+            bits: vec![0; words],
+        }
+    }
+
+    fn insert_node(&mut self, n: usize) -> bool {
+        let (word, bit) = (n / 64, 1 << (n % 64));
+        let inserted = self.bits[word] & bit == 0;
+        self.bits[word] |= bit;
+        inserted
+    }
+
+    fn has_node(&self, n: usize) -> bool {
+        self.bits[n / 64] & (1 << (n % 64)) != 0
+    }
+
+    fn nodes(&self) -> impl Iterator<Item = usize> + '_ {
+        self.bits.iter().enumerate().flat_map(|(word, &bits)| {
+            (0..64)
+                .filter(move |bit| bits & (1 << bit) != 0)
+                .map(move |bit| word * 64 + bit)
+        })
+    }
+
+    pub fn len(&self) -> usize {
+        self.bits
+            .iter()
+            .map(|bits| bits.count_ones() as usize)
+            .sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.bits.iter().all(|&bits| bits == 0)
+    }
+
+    pub fn contains(&self, object_id: u64) -> bool {
+        matches!(self.graph.node(object_id), Some(n) if self.has_node(n))
+    }
+
+    // The objects' IDs, in order
+    pub fn ids(&self) -> Vec<u64> {
+        self.nodes().map(|n| self.graph.object_id(n)).collect()
+    }
+
+    // What would be freed if every object in the set were collected
+    pub fn retained_size(&self) -> u64 {
+        self.graph.retained_size_of_all(&self.ids())
+    }
+
+    // The objects in the set and everything they dominate, which would all be freed if they were
+    pub fn retained(&self) -> ObjectSet {
+        let graph = self.graph.clone();
+        let mut retained = ObjectSet::empty(graph.clone());
+        // Whether each node is known to be under one in the set, filled in a dominator chain at
+        // a time
+        let mut known: Vec<Option<bool>> = vec![None; graph.len()];
+        for n in 0..graph.len() {
+            let mut chain = vec![];
+            let mut m = n;
+            let under = loop {
+                if let Some(under) = known[m] {
+                    break under;
+                }
+                if self.has_node(m) {
+                    break true;
+                }
+                chain.push(m);
+                match graph.dominator_node(m) {
+                    Some(d) if d != m => m = d,
+                    _ => break false,
+                }
+            };
+            for m in chain {
+                known[m] = Some(under);
+            }
+            known[n] = Some(under);
+            if under {
+                retained.insert_node(n);
+            }
+        }
+        retained
+    }
+
+    pub fn union(&self, other: &ObjectSet) -> ObjectSet {
+        self.combine(other, |a, b| a | b)
+    }
+
+    pub fn intersection(&self, other: &ObjectSet) -> ObjectSet {
+        self.combine(other, |a, b| a & b)
+    }
+
+    // The objects in this set which aren't in 'other'
+    pub fn difference(&self, other: &ObjectSet) -> ObjectSet {
+        self.combine(other, |a, b| a & !b)
+    }
+
+    fn combine<F: Fn(u64, u64) -> u64>(&self, other: &ObjectSet, f: F) -> ObjectSet {
+        assert!(
+            Rc::ptr_eq(&self.graph, &other.graph),
+            "Object sets from different heap graphs can't be combined"
+        );
+        ObjectSet {
+            graph: self.graph.clone(),
+            bits: self
+                .bits
+                .iter()
+                .zip(&other.bits)
+                .map(|(&a, &b)| f(a, b))
+                .collect(),
+        }
+    }
+}
+
+impl BitOr for &ObjectSet {
+    type Output = ObjectSet;
+
+    fn bitor(self, other: &ObjectSet) -> ObjectSet {
+        self.union(other)
+    }
+}
+
+impl BitAnd for &ObjectSet {
+    type Output = ObjectSet;
+
+    fn bitand(self, other: &ObjectSet) -> ObjectSet {
+        self.intersection(other)
+    }
+}
+
+impl Sub for &ObjectSet {
+    type Output = ObjectSet;
+
+    fn sub(self, other: &ObjectSet) -> ObjectSet {
+        self.difference(other)
+    }
+}
+
+impl HprofJavaVirtualMachine {
+    // The given objects, leaving out any which aren't in the dump
+    pub fn set_of(&self, object_ids: &[u64]) -> Result<ObjectSet> {
+        let mut set = ObjectSet::empty(self.dump.graph()?);
+        for &object_id in object_ids {
+            if let Some(n) = set.graph.node(object_id) {
+                set.insert_node(n);
+            }
+        }
+        Ok(set)
+    }
+
+    pub fn gc_root_set(&self) -> Result<ObjectSet> {
+        let roots: Vec<u64> = self.dump.roots.iter().map(|root| root.object_id).collect();
+        self.set_of(&roots)
+    }
+
+    // The instances (and arrays) of the classes matching 'classes', not counting subclasses
+    pub fn instance_set(&self, classes: &ClassPattern) -> Result<ObjectSet> {
+        let mut set = ObjectSet::empty(self.dump.graph()?);
+        let mut matches = HashMap::new();
+        self.dump.for_each_object_header(|object_id, header| {
+            let key = header.class_key();
+            let matched = *matches
+                .entry(key)
+                .or_insert_with(|| classes.matches(&self.dump.class_key_name(key)));
+            if let (true, Some(n)) = (matched, set.graph.node(object_id)) {
+                set.insert_node(n);
+            }
+        })?;
+        Ok(set)
+    }
+
+    // The objects in 'set' and everything they refer to, directly or not
+    pub fn reachable_from(&self, set: &ObjectSet) -> Result<ObjectSet> {
+        self.reachable_avoiding(set, &ObjectSet::empty(set.graph.clone()))
+    }
+
+    // Like reachable_from(), but without going through the objects in 'avoid', e.g. what the GC
+    // roots keep alive other than through a cache
+    pub fn reachable_avoiding(&self, from: &ObjectSet, avoid: &ObjectSet) -> Result<ObjectSet> {
+        let mut reached = ObjectSet::empty(from.graph.clone());
+        let follow_referents = self.dump.follow_referents.get();
+        let mut stack: Vec<usize> = (from - avoid).nodes().collect();
+        for &n in &stack {
+            reached.insert_node(n);
+        }
+        while let Some(n) = stack.pop() {
+            let object_id = reached.graph.object_id(n);
+            let (_, references) = self.dump.outgoing_references(object_id, follow_referents)?;
+            for reference in references {
+                match reached.graph.node(reference) {
+                    Some(m) if !avoid.has_node(m) && reached.insert_node(m) => stack.push(m),
+                    _ => {}
+                }
+            }
+        }
+        Ok(reached)
+    }
+}
@@ -0,0 +1,174 @@
+//
+// Memory spent on object headers and padding rather than data. This is mostly a problem for
+// small objects: a boxed Integer is four bytes of value in a sixteen byte object, and a two
+// element array is mostly header. Replacing these (with primitive collections, or by flattening
+// them into their owner) is often an easy win.
+//
+
+use std::cmp::Reverse;
+use std::collections::{HashMap, HashSet};
+use std::io::{Read, Result, Seek};
+
+use super::heap::{ClassKey, HeapObject};
+use super::{HprofJavaVirtualMachine, HprofParser};
+use crate::model::Value;
+
+const BOXES: &[&str] = &[
+    "java.lang.Boolean",
+    "java.lang.Byte",
+    "java.lang.Character",
+    "java.lang.Short",
+    "java.lang.Integer",
+    "java.lang.Long",
+    "java.lang.Float",
+    "java.lang.Double",
+];
+
+// The static fields holding the instances valueOf() hands out, which are shared and so aren't
+// overhead anyone can do anything about
+const BOX_CACHES: &[(&str, &str)] = &[
+    ("java.lang.Byte$ByteCache", "cache"),
+    ("java.lang.Character$CharacterCache", "cache"),
+    ("java.lang.Short$ShortCache", "cache"),
+    ("java.lang.Integer$IntegerCache", "cache"),
+    ("java.lang.Long$LongCache", "cache"),
+];
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ClassOverhead {
+    pub class_name: String,
+    pub instances: u64,
+    pub shallow_bytes: u64,
+    // The part of the shallow size which isn't field values or array elements, i.e. headers and
+    // padding
+    pub overhead_bytes: u64,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct OverheadReport {
+    // Each of these is sorted by overhead, largest first
+    // Boxed primitives, apart from the cached ones
+    pub boxed: Vec<ClassOverhead>,
+    // Arrays whose contents are smaller than their header, by array class
+    pub tiny_arrays: Vec<ClassOverhead>,
+    // Every class
+    pub classes: Vec<ClassOverhead>,
+}
+
+impl OverheadReport {
+    pub fn total_overhead_bytes(&self) -> u64 {
+        self.classes.iter().map(|c| c.overhead_bytes).sum()
+    }
+}
+
+#[derive(Default)]
+struct Totals {
+    instances: u64,
+    shallow_bytes: u64,
+    overhead_bytes: u64,
+}
+
+impl Totals {
+    fn add(&mut self, shallow_bytes: u64, payload_bytes: u64) {
+        self.instances += 1;
+        self.shallow_bytes += shallow_bytes;
+        self.overhead_bytes += shallow_bytes - payload_bytes;
+    }
+}
+
+impl HprofJavaVirtualMachine {
+    // Reads every object in the dump, but doesn't need the dominator tree
+    pub fn overhead_report(&self) -> Result<OverheadReport> {
+        let dump = &self.dump;
+        let box_classes: HashSet<u64> = BOXES
+            .iter()
+            .flat_map(|name| dump.class_object_ids(name))
+            .collect();
+        let cached = dump.cached_boxes()?;
+        let array_header = dump.size_model().array_header;
+
+        // By class object ID, or element type for primitive arrays
+        let mut classes: HashMap<ClassKey, Totals> = HashMap::new();
+        let mut boxed: HashMap<ClassKey, Totals> = HashMap::new();
+        let mut tiny_arrays: HashMap<ClassKey, Totals> = HashMap::new();
+        dump.for_each_object(|object_id, object| {
+            let key = object.class_key();
+            let (shallow, payload) = (dump.shallow_size(&object), dump.payload_size(&object));
+            classes.entry(key).or_default().add(shallow, payload);
+            match &object {
+                HeapObject::Instance {
+                    class_object_id, ..
+                } => {
+                    if box_classes.contains(class_object_id) && !cached.contains(&object_id) {
+                        boxed.entry(key).or_default().add(shallow, payload);
+                    }
+                }
+                _ => {
+                    if payload < array_header {
+                        tiny_arrays.entry(key).or_default().add(shallow, payload);
+                    }
+                }
+            }
+            Ok(())
+        })?;
+
+        let summarize = |totals: HashMap<ClassKey, Totals>| {
+            let mut overheads: Vec<ClassOverhead> = totals
+                .into_iter()
+                .map(|(key, totals)| ClassOverhead {
+                    class_name: dump.class_key_name(key),
+                    instances: totals.instances,
+                    shallow_bytes: totals.shallow_bytes,
+                    overhead_bytes: totals.overhead_bytes,
+                })
+                .collect();
+            overheads.sort_by(|a, b| a.class_name.cmp(&b.class_name));
+            overheads.sort_by_key(|c| Reverse(c.overhead_bytes));
+            overheads
+        };
+        Ok(OverheadReport {
+            boxed: summarize(boxed),
+            tiny_arrays: summarize(tiny_arrays),
+            classes: summarize(classes),
+        })
+    }
+}
+
+impl<R: Read + Seek> HprofParser<R> {
+    fn cached_boxes(&self) -> Result<HashSet<u64>> {
+        let mut cached = HashSet::new();
+        for &(class_name, field) in BOX_CACHES {
+            for class_object_id in self.class_object_ids(class_name) {
+                let cache = self.class_dump_tab.get(&class_object_id).and_then(|class| {
+                    class.static_fields.iter().find_map(|(name_id, value)| {
+                        match (self.string(*name_id), value) {
+                            (Some(name), Value::Object(id)) if &*name == field => Some(*id),
+                            _ => None,
+                        }
+                    })
+                });
+                if let Some(HeapObject::ObjectArray { elements, .. }) = match cache {
+                    Some(cache) => self.read_object(cache)?,
+                    None => None,
+                } {
+                    cached.extend(elements);
+                }
+            }
+        }
+        // Boolean.TRUE and Boolean.FALSE
+        for class_object_id in self.class_object_ids("java.lang.Boolean") {
+            if let Some(class) = self.class_dump_tab.get(&class_object_id) {
+                cached.extend(
+                    class
+                        .static_fields
+                        .iter()
+                        .filter_map(|(_, value)| match value {
+                            Value::Object(id) => Some(*id),
+                            _ => None,
+                        }),
+                );
+            }
+        }
+        Ok(cached)
+    }
+}
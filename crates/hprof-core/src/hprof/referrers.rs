@@ -0,0 +1,242 @@
+//
+// Who refers to whom: the reverse of the references in the dump, for asking what's keeping an
+// object alive. The obvious way to build it, collecting every (from, to) pair and sorting them by
+// 'to', needs several times the memory of the index it produces, which on a big heap is more
+// than there is. So it's built in passes over the dump instead: one to count each object's
+// referrers, which says exactly how big each list will be, then one to fill the lists in.
+//
+// If even the lists don't fit in the budget they're split into bands of objects (by ID), and only
+// one band is kept in memory at a time. Asking for the referrers of an object in another band
+// reads the whole dump again to fill that band in, so a small budget trades memory for time.
+// path_to_root() works a level at a time so that each band is read at most once per level.
+//
+
+use std::cell::{Cell, RefCell};
+use std::collections::hash_map::{Entry, HashMap};
+use std::io::Result;
+use std::rc::Rc;
+
+use super::graph::class_references;
+use super::{Dump, HprofJavaVirtualMachine};
+
+// Nodes are indexes into ReferrerIndex::ids
+type Node = u32;
+
+// What each reference costs in a loaded band, and what each object in the band costs while it's
+// being filled in
+const BYTES_PER_REFERRER: usize = std::mem::size_of::<Node>();
+const BYTES_PER_FILLING: usize = std::mem::size_of::<usize>();
+
+pub struct ReferrerIndex {
+    dump: Rc<Dump>,
+    // Every object and class in the dump, sorted
+    ids: Vec<u64>,
+    // Where each node's referrers start, counting from the first node's, with one more at the end
+    starts: Vec<usize>,
+    // The first node of each band, with one more at the end
+    bands: Vec<usize>,
+    loaded: RefCell<Option<Band>>,
+    // The GC roots, sorted
+    roots: Vec<Node>,
+    // HprofJavaVirtualMachine::follows_referents() as it was when the index was built, since
+    // every pass has to see the same references
+    follow_referents: bool,
+    passes: Cell<usize>,
+}
+
+struct Band {
+    index: usize,
+    referrers: Vec<Node>,
+}
+
+impl ReferrerIndex {
+    // Index the referrers of every object in the heap, using at most about 'budget' bytes beyond
+    // what every object costs (16 bytes each). With enough budget for all of them this reads the
+    // dump twice; with less, the referrers are kept in bands (see above).
+    pub fn build(heap: &HprofJavaVirtualMachine, budget: usize) -> Result<ReferrerIndex> {
+        let dump = heap.dump.clone();
+        let mut ids = Vec::with_capacity(dump.object_offsets.len() + dump.class_dump_tab.len());
+        ids.extend(dump.object_offsets.ids());
+        ids.extend(dump.class_dump_tab.keys());
+        ids.sort_unstable();
+        ids.dedup();
+
+        let mut roots: Vec<Node> = dump
+            .roots
+            .iter()
+            .filter_map(|root| node(&ids, root.object_id))
+            .collect();
+        roots.sort_unstable();
+        roots.dedup();
+
+        let mut index = ReferrerIndex {
+            dump,
+            starts: vec![0; ids.len() + 1],
+            ids,
+            bands: vec![],
+            loaded: RefCell::new(None),
+            roots,
+            follow_referents: heap.follows_referents(),
+            passes: Cell::new(0),
+        };
+
+        let mut starts = std::mem::take(&mut index.starts);
+        index.for_each_reference(|_, to| starts[to as usize + 1] += 1)?;
+        for i in 1..starts.len() {
+            starts[i] += starts[i - 1];
+        }
+        index.starts = starts;
+
+        // As many objects in each band as fit in the budget, and at least one
+        let mut bands = vec![0];
+        let mut start = 0;
+        for n in 0..index.ids.len() {
+            let referrers = index.starts[n + 1] - index.starts[start];
+            let objects = n + 1 - start;
+            if n > start && referrers * BYTES_PER_REFERRER + objects * BYTES_PER_FILLING > budget {
+                bands.push(n);
+                start = n;
+            }
+        }
+        bands.push(index.ids.len());
+        index.bands = bands;
+        if index.bands.len() == 2 {
+            index.load(0)?;
+        }
+        Ok(index)
+    }
+
+    // Whether the referrers didn't all fit in the budget
+    pub fn is_banded(&self) -> bool {
+        self.bands.len() > 2
+    }
+
+    // How many times the dump has been read, to build the index and since
+    pub fn passes(&self) -> usize {
+        self.passes.get()
+    }
+
+    // How many references there are to an object, without reading anything. None if there's no
+    // such object.
+    pub fn referrer_count(&self, object_id: u64) -> Option<usize> {
+        let n = node(&self.ids, object_id)? as usize;
+        Some(self.starts[n + 1] - self.starts[n])
+    }
+
+    // The objects (and classes) which refer to an object, sorted, each once however many
+    // references it has to it. Classes refer to their superclasses, loaders and the values of
+    // their static fields, and every object refers to its class.
+    pub fn referrers(&self, object_id: u64) -> Result<Vec<u64>> {
+        let n = match node(&self.ids, object_id) {
+            Some(n) => n,
+            None => return Ok(vec![]),
+        };
+        let mut referrers: Vec<u64> = self
+            .referrers_of(n)?
+            .into_iter()
+            .map(|r| self.ids[r as usize])
+            .collect();
+        referrers.sort_unstable();
+        referrers.dedup();
+        Ok(referrers)
+    }
+
+    // The objects on the shortest path from a GC root to an object, like ObjectView::path_from_root(),
+    // but found by following referrers back from the object, so without building the dominator
+    // tree. None if the object isn't reachable.
+    pub fn path_to_root(&self, object_id: u64) -> Result<Option<Vec<u64>>> {
+        let target = match node(&self.ids, object_id) {
+            Some(n) => n,
+            None => return Ok(None),
+        };
+        // The next step towards the object from each node we've seen
+        let mut towards: HashMap<Node, Node> = HashMap::new();
+        towards.insert(target, target);
+        let mut level = vec![target];
+        while !level.is_empty() {
+            // Sorted, so that each band is loaded once per level
+            level.sort_unstable();
+            let mut next = vec![];
+            for &n in &level {
+                if self.roots.binary_search(&n).is_ok() {
+                    let mut path = vec![self.ids[n as usize]];
+                    let mut step = n;
+                    while step != target {
+                        step = towards[&step];
+                        path.push(self.ids[step as usize]);
+                    }
+                    return Ok(Some(path));
+                }
+                for referrer in self.referrers_of(n)? {
+                    if let Entry::Vacant(e) = towards.entry(referrer) {
+                        e.insert(n);
+                        next.push(referrer);
+                    }
+                }
+            }
+            level = next;
+        }
+        Ok(None)
+    }
+
+    fn referrers_of(&self, n: Node) -> Result<Vec<Node>> {
+        let n = n as usize;
+        let band = self.bands.partition_point(|&start| start <= n) - 1;
+        if self.loaded.borrow().as_ref().map(|b| b.index) != Some(band) {
+            self.load(band)?;
+        }
+        let loaded = self.loaded.borrow();
+        let loaded = loaded.as_ref().expect("band was just loaded");
+        let base = self.starts[self.bands[band]];
+        Ok(loaded.referrers[self.starts[n] - base..self.starts[n + 1] - base].to_vec())
+    }
+
+    // Fill in the referrers of one band's objects, replacing whichever band was loaded
+    fn load(&self, band: usize) -> Result<()> {
+        // Let the old band go first, so that there aren't two in memory at once
+        self.loaded.borrow_mut().take();
+        let (first, end) = (self.bands[band], self.bands[band + 1]);
+        let base = self.starts[first];
+        let mut referrers = vec![0; self.starts[end] - base];
+        let mut filled: Vec<usize> = self.starts[first..end].iter().map(|s| s - base).collect();
+        self.for_each_reference(|from, to| {
+            let to = to as usize;
+            if (first..end).contains(&to) {
+                referrers[filled[to - first]] = from;
+                filled[to - first] += 1;
+            }
+        })?;
+        *self.loaded.borrow_mut() = Some(Band {
+            index: band,
+            referrers,
+        });
+        Ok(())
+    }
+
+    // Calls 'f' with every reference in the dump, as (from, to), reading every object
+    fn for_each_reference<F: FnMut(Node, Node)>(&self, mut f: F) -> Result<()> {
+        self.passes.set(self.passes.get() + 1);
+        let ids = &self.ids;
+        let mut add = |from: u64, references: Vec<u64>| {
+            if let Some(from) = node(ids, from) {
+                for to in references {
+                    if let Some(to) = node(ids, to) {
+                        f(from, to);
+                    }
+                }
+            }
+        };
+        for (&id, class) in &self.dump.class_dump_tab {
+            add(id, class_references(class));
+        }
+        let dump = &self.dump;
+        dump.for_each_object(|id, object| {
+            add(id, dump.object_references(object, self.follow_referents));
+            Ok(())
+        })
+    }
+}
+
+fn node(ids: &[u64], object_id: u64) -> Option<Node> {
+    ids.binary_search(&object_id).ok().map(|i| i as Node)
+}
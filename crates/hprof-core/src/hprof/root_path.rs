@@ -0,0 +1,172 @@
+//
+// Paths from GC roots as data rather than text, for front ends which show them as something to
+// expand and click through: the root, what kind of root it is, and each reference after it, with
+// the field (or array index) it goes through and the size of the object it gets to. With the
+// "serde" feature they can be serialized as they are.
+//
+// The Display impl is the text form, one step per line:
+//
+//   java.lang.Object[]@7f0012 (JavaFrame root, held by thread "main" at ...), 24 bytes
+//     [3] com.example.Node@7f0040, 32 bytes, retains 1024 bytes
+//
+
+use std::fmt;
+use std::io::{Read, Result, Seek};
+
+use super::referrers::ReferrerIndex;
+use super::views::ObjectView;
+use super::{HprofParser, RootKind};
+
+// How one object refers to the next on a path
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Reference {
+    Field(String),
+    StaticField(String),
+    ArrayElement(usize),
+    ClassLoader,
+    Superclass,
+    // From an object to its class
+    Class,
+    // The dump doesn't say, e.g. for references from JNI code
+    Unknown,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct PathObject {
+    pub object_id: u64,
+    // "class X" for a class
+    pub class_name: String,
+    pub shallow_bytes: u64,
+    // None when the path was found without the dominator tree (see ObjectView::root_path_with())
+    pub retained_bytes: Option<u64>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct PathEdge {
+    pub reference: Reference,
+    pub to: PathObject,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct RootPath {
+    pub root: PathObject,
+    // The first the dump lists, if it's more than one kind of root
+    pub root_kind: RootKind,
+    // Which thread holds the root and where, as in ObjectView::held_by()
+    pub held_by: Option<String>,
+    // From the root to the object, in order
+    pub edges: Vec<PathEdge>,
+}
+
+impl RootPath {
+    // The object the path leads to
+    pub fn target(&self) -> &PathObject {
+        self.edges.last().map_or(&self.root, |edge| &edge.to)
+    }
+}
+
+impl ObjectView {
+    // The shortest path to this from a GC root, as in path_from_root(), with retained sizes.
+    // None if it's garbage.
+    pub fn root_path(&self) -> Result<Option<RootPath>> {
+        let graph = self.dump.graph()?;
+        match graph.path_from_root(self.object_id) {
+            Some(path) => Ok(Some(
+                self.dump.root_path(&path, |id| graph.retained_size(id))?,
+            )),
+            None => Ok(None),
+        }
+    }
+
+    // Like root_path(), but found with a ReferrerIndex as in path_to_root(), so without
+    // retained sizes
+    pub fn root_path_with(&self, index: &ReferrerIndex) -> Result<Option<RootPath>> {
+        match index.path_to_root(self.object_id)? {
+            Some(path) => Ok(Some(self.dump.root_path(&path, |_| None)?)),
+            None => Ok(None),
+        }
+    }
+}
+
+impl<R: Read + Seek> HprofParser<R> {
+    pub(super) fn root_path<F>(&self, path: &[u64], retained_bytes: F) -> Result<RootPath>
+    where
+        F: Fn(u64) -> Option<u64>,
+    {
+        let object = |object_id: u64| -> Result<PathObject> {
+            Ok(PathObject {
+                object_id,
+                class_name: self.describe(object_id)?,
+                shallow_bytes: self.object_size(object_id)?,
+                retained_bytes: retained_bytes(object_id),
+            })
+        };
+        let root_id = path.first().copied().unwrap_or_default();
+        let mut edges = vec![];
+        for pair in path.windows(2) {
+            edges.push(PathEdge {
+                reference: self.reference(pair[0], pair[1])?,
+                to: object(pair[1])?,
+            });
+        }
+        Ok(RootPath {
+            root: object(root_id)?,
+            root_kind: self
+                .roots
+                .iter()
+                .find(|root| root.object_id == root_id)
+                .map_or(RootKind::Unknown, |root| root.kind),
+            held_by: self.root_holder(root_id)?,
+            edges,
+        })
+    }
+}
+
+impl fmt::Display for Reference {
+    // As in reference chains, e.g. ".next", "[3]" or "static INSTANCE"
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Reference::Field(name) => write!(f, ".{}", name),
+            Reference::StaticField(name) => write!(f, "static {}", name),
+            Reference::ArrayElement(i) => write!(f, "[{}]", i),
+            Reference::ClassLoader => write!(f, "<classloader>"),
+            Reference::Superclass => write!(f, "<superclass>"),
+            Reference::Class => write!(f, "<class>"),
+            Reference::Unknown => write!(f, "?"),
+        }
+    }
+}
+
+impl fmt::Display for PathObject {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}@{:x}", self.class_name, self.object_id)
+    }
+}
+
+impl fmt::Display for RootPath {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} ({:?} root", self.root, self.root_kind)?;
+        if let Some(holder) = &self.held_by {
+            write!(f, ", held by {}", holder)?;
+        }
+        write!(f, ")")?;
+        write_sizes(f, &self.root)?;
+        for edge in &self.edges {
+            write!(f, "\n  {} {}", edge.reference, edge.to)?;
+            write_sizes(f, &edge.to)?;
+        }
+        Ok(())
+    }
+}
+
+fn write_sizes(f: &mut fmt::Formatter, object: &PathObject) -> fmt::Result {
+    write!(f, ", {} bytes", object.shallow_bytes)?;
+    match object.retained_bytes {
+        Some(retained) => write!(f, ", retains {} bytes", retained),
+        None => Ok(()),
+    }
+}
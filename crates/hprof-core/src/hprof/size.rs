@@ -0,0 +1,202 @@
+//
+// How big objects are in the JVM. Dumps don't say: they write references as identifiers, which
+// are usually eight bytes, and they don't include headers. So sizes are worked out from the
+// fields and elements of each object, with the header and reference sizes of the JVM it came
+// from. That depends on the JVM's flags, and the dump doesn't record those either, so there's a
+// default (see SizeModel::default_for()) which the caller overrides if it knows better.
+//
+
+use std::io::{Read, Seek};
+
+use super::heap::{HeapObject, ObjectHeader};
+use super::{field_size, ClassDumpRecord, FieldTag, HprofParser};
+use crate::model::Value;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SizeModel {
+    pub object_header: u64,
+    // Including the length, and any padding before the first element
+    pub array_header: u64,
+    pub reference_size: u64,
+    // Objects start on a multiple of this (-XX:ObjectAlignmentInBytes)
+    pub alignment: u64,
+}
+
+impl SizeModel {
+    // 64 bit JVMs with heaps under 32GB, which is HotSpot's default
+    pub const COMPRESSED_OOPS: SizeModel = SizeModel {
+        object_header: 12,
+        array_header: 16,
+        reference_size: 4,
+        alignment: 8,
+    };
+
+    // 64 bit JVMs with larger heaps (or -XX:-UseCompressedOops). Since Java 15 class pointers are
+    // still compressed.
+    pub const LARGE_HEAP: SizeModel = SizeModel {
+        object_header: 12,
+        array_header: 16,
+        reference_size: 8,
+        alignment: 8,
+    };
+
+    // 64 bit JVMs before Java 15 with large heaps, which have neither compressed references nor
+    // class pointers
+    pub const UNCOMPRESSED: SizeModel = SizeModel {
+        object_header: 16,
+        array_header: 24,
+        reference_size: 8,
+        alignment: 8,
+    };
+
+    pub fn instance_size(&self, primitive_bytes: u64, references: u64) -> u64 {
+        self.align(self.object_header + primitive_bytes + references * self.reference_size)
+    }
+
+    pub fn array_size(&self, length: u64, element_size: u64) -> u64 {
+        self.align(self.array_header + length * element_size)
+    }
+
+    fn align(&self, size: u64) -> u64 {
+        size.div_ceil(self.alignment) * self.alignment
+    }
+
+    // This isn't detected so much as assumed. Most dump writers only count the fields in the
+    // instance size of a class, so java.lang.Object's is zero, but if it isn't then it tells us the
+    // header size. HotSpot's is always zero, so for a HotSpot dump this is COMPRESSED_OOPS
+    // whatever the heap was, and a dump of a heap over 32GB needs set_size_model(). (Dumps with
+    // four byte identifiers, from 32 bit JVMs, aren't parsed at all.)
+    fn default_for<R: Read + Seek>(dump: &HprofParser<R>) -> SizeModel {
+        let object_size = dump
+            .class_object_ids("java.lang.Object")
+            .first()
+            .and_then(|id| dump.class_dump_tab.get(id))
+            .map(|class| class.instance_size_bytes);
+        match object_size {
+            Some(16) => SizeModel::UNCOMPRESSED,
+            _ => SizeModel::COMPRESSED_OOPS,
+        }
+    }
+}
+
+impl<R: Read + Seek> HprofParser<R> {
+    pub(super) fn size_model(&self) -> SizeModel {
+        match self.size_model.get() {
+            Some(model) => model,
+            None => {
+                let model = SizeModel::default_for(self);
+                self.size_model.set(Some(model));
+                model
+            }
+        }
+    }
+
+    // How much memory an object takes up in the JVM
+    pub(super) fn shallow_size(&self, object: &HeapObject) -> u64 {
+        let model = self.size_model();
+        match object {
+            HeapObject::Instance {
+                class_object_id, ..
+            } => {
+                let (primitive_bytes, references) = self.instance_layout(*class_object_id);
+                model.instance_size(primitive_bytes, references)
+            }
+            HeapObject::ObjectArray { elements, .. } => {
+                model.array_size(elements.len() as u64, model.reference_size)
+            }
+            HeapObject::PrimitiveArray { element_type, data } => model.array_size(
+                data.len() as u64 / field_size(*element_type),
+                field_size(*element_type),
+            ),
+        }
+    }
+
+    // The same, from just the object's header
+    pub(super) fn header_shallow_size(&self, header: &ObjectHeader) -> u64 {
+        let model = self.size_model();
+        match *header {
+            ObjectHeader::Instance { class_object_id } => {
+                let (primitive_bytes, references) = self.instance_layout(class_object_id);
+                model.instance_size(primitive_bytes, references)
+            }
+            ObjectHeader::ObjectArray { length, .. } => {
+                model.array_size(u64::from(length), model.reference_size)
+            }
+            ObjectHeader::PrimitiveArray {
+                element_type,
+                length,
+            } => model.array_size(u64::from(length), field_size(element_type)),
+        }
+    }
+
+    // The bytes of an object which hold its field values or elements, as opposed to its header and
+    // padding
+    pub(super) fn payload_size(&self, object: &HeapObject) -> u64 {
+        let model = self.size_model();
+        match object {
+            HeapObject::Instance {
+                class_object_id, ..
+            } => {
+                let (primitive_bytes, references) = self.instance_layout(*class_object_id);
+                primitive_bytes + references * model.reference_size
+            }
+            HeapObject::ObjectArray { elements, .. } => {
+                elements.len() as u64 * model.reference_size
+            }
+            HeapObject::PrimitiveArray { data, .. } => data.len() as u64,
+        }
+    }
+
+    // The java.lang.Class instance, which is where the static fields live
+    pub(super) fn class_object_size(&self, class: &ClassDumpRecord) -> u64 {
+        let model = self.size_model();
+        let (primitive_bytes, references) =
+            class
+                .static_fields
+                .iter()
+                .fold(
+                    (0, 0),
+                    |(primitive_bytes, references), (_, value)| match value {
+                        Value::Object(_) | Value::Null => (primitive_bytes, references + 1),
+                        Value::Boolean(_) | Value::Byte(_) => (primitive_bytes + 1, references),
+                        Value::Char(_) | Value::Short(_) => (primitive_bytes + 2, references),
+                        Value::Integer(_) | Value::Float(_) => (primitive_bytes + 4, references),
+                        _ => (primitive_bytes + 8, references),
+                    },
+                );
+        model.instance_size(primitive_bytes, references)
+    }
+
+    // The total size of the primitive fields of instances of a class (including inherited ones),
+    // and how many reference fields they have
+    fn instance_layout(&self, mut class_object_id: u64) -> (u64, u64) {
+        let (mut primitive_bytes, mut references) = (0, 0);
+        while let Some(class) = self.class_dump_tab.get(&class_object_id) {
+            for &(_, field_type) in &class.instance_fields {
+                match field_type {
+                    FieldTag::NormalObject | FieldTag::ArrayObject => references += 1,
+                    _ => primitive_bytes += field_size(field_type),
+                }
+            }
+            class_object_id = class.superclass_object_id;
+        }
+        (primitive_bytes, references)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sizes() {
+        // java.lang.String: byte[] value, int hash, byte coder, boolean hashIsZero
+        assert_eq!(SizeModel::COMPRESSED_OOPS.instance_size(6, 1), 24);
+        assert_eq!(SizeModel::UNCOMPRESSED.instance_size(6, 1), 32);
+        assert_eq!(SizeModel::COMPRESSED_OOPS.instance_size(0, 0), 16);
+        assert_eq!(SizeModel::COMPRESSED_OOPS.array_size(0, 1), 16);
+        assert_eq!(SizeModel::COMPRESSED_OOPS.array_size(10, 1), 32);
+        assert_eq!(SizeModel::COMPRESSED_OOPS.array_size(3, 4), 32);
+        assert_eq!(SizeModel::LARGE_HEAP.array_size(3, 8), 40);
+    }
+}
@@ -0,0 +1,106 @@
+//
+// Searching a dump for strings, for when all you know is that something suspicious (an SQL
+// statement, a URL, a customer's name) is in there and you want to know who's holding on to it.
+// Both kinds of string are searched: the symbols in the UTF8 records (names of classes, methods,
+// fields and so on, and interned literals on some JVMs), and java.lang.String instances.
+//
+
+use std::io::Result;
+
+use super::HprofJavaVirtualMachine;
+
+// One of the dump's UTF8 records
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SymbolMatch {
+    pub id: u64,
+    pub value: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StringMatch {
+    // The java.lang.String
+    pub object: u64,
+    pub value: String,
+    // The class of the object which dominates the string, or None if it's only held by GC roots
+    pub holder_class: Option<String>,
+    // The shortest chain of references from a GC root to the string, in the same form as
+    // ClassLoaderReportEntry::reference_chain. Empty if it's garbage.
+    pub reference_chain: Vec<String>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct StringSearchReport {
+    // Sorted by value
+    pub symbols: Vec<SymbolMatch>,
+    // Sorted by value, then ID
+    pub strings: Vec<StringMatch>,
+}
+
+impl HprofJavaVirtualMachine {
+    // The strings which match a regular expression anywhere in them. Finding the holders needs
+    // the dominator tree, so the first call reads every object in the dump.
+    #[cfg(feature = "regex")]
+    pub fn find_strings(&self, pattern: &str) -> Result<StringSearchReport> {
+        let regex = regex::Regex::new(pattern).map_err(|e| {
+            std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                format!("Invalid pattern '{}': {}", pattern, e),
+            )
+        })?;
+        self.find_strings_where(|s| regex.is_match(s))
+    }
+
+    // Like find_strings(), with any test of a string
+    pub fn find_strings_where<F>(&self, mut matches: F) -> Result<StringSearchReport>
+    where
+        F: FnMut(&str) -> bool,
+    {
+        let dump = &self.dump;
+
+        let mut symbols = vec![];
+        for id in dump.symbols.ids() {
+            if let Some(value) = dump.string(id) {
+                if matches(&value) {
+                    symbols.push(SymbolMatch {
+                        id,
+                        value: value.to_string(),
+                    });
+                }
+            }
+        }
+        symbols.sort_by(|a, b| (&a.value, a.id).cmp(&(&b.value, b.id)));
+
+        let mut found = vec![];
+        for object in dump.instances_of(&dump.class_object_ids("java.lang.String"))? {
+            if let Some(value) = dump.read_string(object)? {
+                if matches(&value) {
+                    found.push((object, value));
+                }
+            }
+        }
+        if found.is_empty() {
+            return Ok(StringSearchReport {
+                symbols,
+                strings: vec![],
+            });
+        }
+
+        let graph = dump.graph()?;
+        let mut strings = vec![];
+        for (object, value) in found {
+            let holder_class = match graph.immediate_dominator(object) {
+                Some(holder) => dump.object_class_name(holder)?,
+                None => None,
+            };
+            let path = graph.path_from_root(object).unwrap_or_default();
+            strings.push(StringMatch {
+                object,
+                value,
+                holder_class,
+                reference_chain: dump.reference_chain(&path)?,
+            });
+        }
+        strings.sort_by(|a, b| (&a.value, a.object).cmp(&(&b.value, b.object)));
+        Ok(StringSearchReport { symbols, strings })
+    }
+}
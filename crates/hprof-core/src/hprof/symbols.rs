@@ -0,0 +1,169 @@
+//
+// The strings in a dump (class, method, field and file names, and so on), by ID. There's one
+// UTF8 record for every symbol the JVM knows about, which in a big dump can be gigabytes, and
+// most of them (e.g. the names of local variables and methods which aren't on any stack) are
+// never looked at. So only the first DEFAULT_BUDGET bytes worth of them are kept in memory, and
+// for the rest we just remember where they are in the dump and read them when they're asked for.
+// Strings which are kept are interned, so duplicates only cost an index.
+//
+// Dumps of the same application (e.g. for heap_timeline()) have nearly all of their strings in
+// common, so they can share a StringCache: a string which is already in it costs nothing, and
+// doesn't count towards the dump's budget. The source form of class names, which are looked up
+// over and over, is kept in the cache too.
+//
+
+use std::cell::RefCell;
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashMap;
+use std::hash::{Hash, Hasher};
+use std::rc::Rc;
+
+use crate::names;
+
+pub(super) const DEFAULT_BUDGET: usize = 256 * 1024 * 1024;
+
+#[derive(Debug, Clone, Copy)]
+pub(super) enum Symbol {
+    // An index into Symbols::interned
+    Interned(u32),
+    // Where the string's bytes are in the dump, and how many of them there are
+    InFile { offset: u64, length: u32 },
+}
+
+#[derive(Debug, Default)]
+struct Interner {
+    strings: Vec<Rc<str>>,
+    index: HashMap<Rc<str>, u32>,
+    bytes: usize,
+    // Interned internal class name -> source name, e.g. java/lang/String -> java.lang.String
+    class_names: HashMap<u32, Rc<str>>,
+}
+
+// Interned strings, which can be shared by the dumps opened with it. Cloning it shares it.
+#[derive(Debug, Clone, Default)]
+pub struct StringCache {
+    interner: Rc<RefCell<Interner>>,
+}
+
+impl StringCache {
+    pub fn new() -> StringCache {
+        StringCache::default()
+    }
+
+    // How many different strings are kept, and how many bytes they take up
+    pub fn len(&self) -> usize {
+        self.interner.borrow().strings.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn bytes(&self) -> usize {
+        self.interner.borrow().bytes
+    }
+
+    fn find(&self, value: &str) -> Option<u32> {
+        self.interner.borrow().index.get(value).copied()
+    }
+
+    fn insert(&self, value: &str) -> u32 {
+        let mut interner = self.interner.borrow_mut();
+        let index = interner.strings.len() as u32;
+        let value: Rc<str> = Rc::from(value);
+        interner.bytes += value.len();
+        interner.strings.push(value.clone());
+        interner.index.insert(value, index);
+        index
+    }
+
+    fn get(&self, index: u32) -> Rc<str> {
+        self.interner.borrow().strings[index as usize].clone()
+    }
+
+    fn class_name(&self, index: u32) -> Rc<str> {
+        if let Some(name) = self.interner.borrow().class_names.get(&index) {
+            return name.clone();
+        }
+        let name: Rc<str> = Rc::from(names::source_name(&self.get(index)));
+        let mut interner = self.interner.borrow_mut();
+        interner.class_names.insert(index, name.clone());
+        name
+    }
+}
+
+#[derive(Debug)]
+pub(super) struct Symbols {
+    // How many bytes of strings to keep in memory, not counting the ones which were already in
+    // the cache
+    budget: usize,
+    used: usize,
+    entries: HashMap<u64, Symbol>,
+    cache: StringCache,
+    // For finding IDs by name. Since the strings themselves aren't all in memory, this only
+    // narrows things down to the IDs of strings with the same hash, which then need checking.
+    by_hash: HashMap<u64, Vec<u64>>,
+}
+
+impl Symbols {
+    pub(super) fn new(budget: usize, cache: StringCache) -> Symbols {
+        Symbols {
+            budget,
+            used: 0,
+            entries: HashMap::new(),
+            cache,
+            by_hash: HashMap::new(),
+        }
+    }
+
+    // 'offset' and 'length' are where the string is in the dump, in case it isn't kept. The
+    // length is of the bytes in the dump, which are in modified UTF-8 (see mutf8.rs).
+    pub(super) fn insert(&mut self, id: u64, value: &str, offset: u64, length: u32) {
+        self.by_hash.entry(hash(value)).or_default().push(id);
+        let symbol = if let Some(index) = self.cache.find(value) {
+            Symbol::Interned(index)
+        } else if self.used + value.len() <= self.budget {
+            self.used += value.len();
+            Symbol::Interned(self.cache.insert(value))
+        } else {
+            Symbol::InFile { offset, length }
+        };
+        self.entries.insert(id, symbol);
+    }
+
+    pub(super) fn get(&self, id: u64) -> Option<Symbol> {
+        self.entries.get(&id).copied()
+    }
+
+    pub(super) fn interned(&self, index: u32) -> Rc<str> {
+        self.cache.get(index)
+    }
+
+    // The source form of an interned class name
+    pub(super) fn class_name(&self, index: u32) -> Rc<str> {
+        self.cache.class_name(index)
+    }
+
+    pub(super) fn cache(&self) -> &StringCache {
+        &self.cache
+    }
+
+    // Every string's ID, in no particular order
+    pub(super) fn ids(&self) -> impl Iterator<Item = u64> + '_ {
+        self.entries.keys().copied()
+    }
+
+    // The IDs which might be for 'value'
+    pub(super) fn candidates(&self, value: &str) -> &[u64] {
+        self.by_hash
+            .get(&hash(value))
+            .map(Vec::as_slice)
+            .unwrap_or(&[])
+    }
+}
+
+fn hash(value: &str) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    value.hash(&mut hasher);
+    hasher.finish()
+}
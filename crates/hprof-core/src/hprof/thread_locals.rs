@@ -0,0 +1,148 @@
+//
+// ThreadLocal leaks. Each thread keeps its ThreadLocal values in a ThreadLocalMap whose entries
+// refer to the ThreadLocal weakly but to the value strongly, so a value outlives its ThreadLocal
+// until the map gets around to expunging the stale entry, which may be never if the thread is
+// pooled and doesn't use ThreadLocals much. If the value's class came from a class loader which
+// has otherwise gone away (e.g. an undeployed web application), the value keeps the whole loader
+// and all its classes alive.
+//
+
+use std::collections::HashMap;
+use std::io::{Read, Result, Seek};
+
+use super::{HeapObject, HprofJavaVirtualMachine, HprofParser};
+use crate::model::{JavaVirtualMachine, ThreadReference};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ThreadLocalReportEntry {
+    pub thread_name: String,
+    // None if the ThreadLocal has been collected, i.e. the entry is stale
+    pub thread_local: Option<u64>,
+    pub thread_local_class: Option<String>,
+    pub value: u64,
+    pub value_class: String,
+    // The class of the loader which defined the value's class, or None for the bootstrap loader
+    pub value_class_loader: Option<String>,
+    pub retained_bytes: u64,
+    pub inheritable: bool,
+}
+
+impl ThreadLocalReportEntry {
+    pub fn is_stale(&self) -> bool {
+        self.thread_local.is_none()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ThreadLocalValueClass {
+    pub value_class: String,
+    pub entries: u64,
+    pub stale_entries: u64,
+    pub retained_bytes: u64,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ThreadLocalReport {
+    // Every entry with a non-null value, largest retained size first
+    pub entries: Vec<ThreadLocalReportEntry>,
+}
+
+impl ThreadLocalReport {
+    pub fn stale_entries(&self) -> impl Iterator<Item = &ThreadLocalReportEntry> {
+        self.entries.iter().filter(|e| e.is_stale())
+    }
+
+    // The entries totalled up by the class of their value, largest retained size first
+    pub fn by_value_class(&self) -> Vec<ThreadLocalValueClass> {
+        let mut classes: HashMap<&str, ThreadLocalValueClass> = HashMap::new();
+        for entry in &self.entries {
+            let class =
+                classes
+                    .entry(&entry.value_class)
+                    .or_insert_with(|| ThreadLocalValueClass {
+                        value_class: entry.value_class.clone(),
+                        entries: 0,
+                        stale_entries: 0,
+                        retained_bytes: 0,
+                    });
+            class.entries += 1;
+            class.stale_entries += entry.is_stale() as u64;
+            class.retained_bytes += entry.retained_bytes;
+        }
+        let mut classes: Vec<_> = classes.into_values().collect();
+        classes.sort_by(|a, b| {
+            (b.retained_bytes, &a.value_class).cmp(&(a.retained_bytes, &b.value_class))
+        });
+        classes
+    }
+}
+
+impl HprofJavaVirtualMachine {
+    // Retained sizes need the dominator tree of the whole heap, so the first call reads every
+    // object in the dump
+    pub fn thread_local_report(&self) -> Result<ThreadLocalReport> {
+        let graph = self.dump.graph()?;
+        let mut entries = vec![];
+        for thread in self.all_threads() {
+            let thread = thread?;
+            let locals = thread.thread_locals()?;
+            if locals.is_empty() {
+                continue;
+            }
+            let thread_name = thread.name()?;
+            for local in locals {
+                let value = match local.value {
+                    Some(value) => value,
+                    None => continue,
+                };
+                let thread_local_class = match local.thread_local {
+                    Some(id) => self.dump.object_class_name(id)?,
+                    None => None,
+                };
+                entries.push(ThreadLocalReportEntry {
+                    thread_name: thread_name.clone(),
+                    thread_local: local.thread_local,
+                    thread_local_class,
+                    value,
+                    value_class: self
+                        .dump
+                        .object_class_name(value)?
+                        .unwrap_or_else(|| "<unknown>".to_string()),
+                    value_class_loader: self.dump.class_loader_name(value)?,
+                    retained_bytes: graph.retained_size(value).unwrap_or(0),
+                    inheritable: local.inheritable,
+                });
+            }
+        }
+        entries.sort_by(|a, b| {
+            (b.retained_bytes, &a.thread_name, a.value).cmp(&(
+                a.retained_bytes,
+                &b.thread_name,
+                b.value,
+            ))
+        });
+        Ok(ThreadLocalReport { entries })
+    }
+}
+
+impl<R: Read + Seek> HprofParser<R> {
+    // The class of the loader of an object's class. None for the bootstrap loader, and for
+    // primitive arrays.
+    pub(super) fn class_loader_name(&self, object_id: u64) -> Result<Option<String>> {
+        let class_object_id = match self.read_object(object_id)? {
+            Some(HeapObject::Instance {
+                class_object_id, ..
+            })
+            | Some(HeapObject::ObjectArray {
+                class_object_id, ..
+            }) => class_object_id,
+            _ => return Ok(None),
+        };
+        match self.class_dump_tab.get(&class_object_id) {
+            Some(class) if class.class_loader_object_id != 0 => {
+                self.object_class_name(class.class_loader_object_id)
+            }
+            _ => Ok(None),
+        }
+    }
+}
@@ -0,0 +1,226 @@
+//
+// Several dumps of the same process, taken a while apart. The usual way of finding a slow leak is
+// to take a dump every hour or so and compare them: the classes which grow in every one, and the
+// big objects which weren't there at the start, are the leak. This does the comparing.
+//
+// Object IDs are addresses, and the GC moves objects around, so the same object usually has a
+// different ID in each dump. The large objects (the ones which are only dominated by GC roots,
+// which is where leaks end up) are matched up by how they're reached instead: the classes and
+// fields on the shortest path to them from a GC root, e.g. "class com.example.Cache static
+// INSTANCE com.example.Cache .map java.util.HashMap". Objects reached the same way are counted
+// together.
+//
+
+use std::cmp::Reverse;
+use std::collections::HashMap;
+use std::io::{Read, Result, Seek};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use super::{HprofJavaVirtualMachine, HprofParser};
+use crate::model::JavaVirtualMachine;
+
+// How many of the largest objects in each dump are matched up with the suspects. Something which
+// isn't among these in a dump counts as not being there yet.
+const TRACKED: usize = 1000;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct TimelineDump {
+    // When the dump was taken, from its header
+    pub time: SystemTime,
+    pub objects: u64,
+    pub shallow_bytes: u64,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ClassCurve {
+    pub class_name: String,
+    // One for each dump, oldest first
+    pub instances: Vec<u64>,
+    pub shallow_bytes: Vec<u64>,
+}
+
+impl ClassCurve {
+    // Between the first dump and the last. Negative if it's shrunk.
+    pub fn byte_growth(&self) -> i64 {
+        let first = self.shallow_bytes.first().copied().unwrap_or(0);
+        let last = self.shallow_bytes.last().copied().unwrap_or(0);
+        last as i64 - first as i64
+    }
+
+    // Whether it grew from each dump to the next, which is what a leak looks like. Something
+    // which is only busy goes up and down.
+    pub fn grows_steadily(&self) -> bool {
+        self.shallow_bytes.len() > 1 && self.shallow_bytes.windows(2).all(|w| w[1] > w[0])
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct SuspectHistory {
+    // The classes and fields on the path from a GC root, as described above
+    pub path: String,
+    pub class_name: String,
+    // The total retained size of the objects reached this way in each dump, oldest first. None
+    // if there weren't any among that dump's largest objects.
+    pub retained_bytes: Vec<Option<u64>>,
+}
+
+impl SuspectHistory {
+    // The index of the first dump it was in
+    pub fn first_seen(&self) -> usize {
+        self.retained_bytes
+            .iter()
+            .position(Option::is_some)
+            .unwrap_or(self.retained_bytes.len())
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct HeapTimeline {
+    pub dumps: Vec<TimelineDump>,
+    // Every class in any of the dumps, biggest growth first
+    pub classes: Vec<ClassCurve>,
+    // The largest objects in the last dump, largest first
+    pub suspects: Vec<SuspectHistory>,
+}
+
+impl HeapTimeline {
+    // The classes which grew from every dump to the next
+    pub fn steadily_growing(&self) -> impl Iterator<Item = &ClassCurve> {
+        self.classes.iter().filter(|c| c.grows_steadily())
+    }
+
+    // When a suspect was first seen
+    pub fn first_seen_time(&self, suspect: &SuspectHistory) -> Option<SystemTime> {
+        self.dumps.get(suspect.first_seen()).map(|dump| dump.time)
+    }
+}
+
+impl HprofJavaVirtualMachine {
+    // When the dump was taken, from its header
+    pub fn dump_time(&self) -> SystemTime {
+        let header = &self.dump.header;
+        let ms = (u64::from(header.high_word_ms) << 32) | u64::from(header.low_word_ms);
+        UNIX_EPOCH + Duration::from_millis(ms)
+    }
+}
+
+// Compare dumps of the same process, oldest first, following every class and the 'suspects'
+// largest objects in the last dump back through the others. This builds the dominator tree of
+// every dump, so it reads every object in each of them.
+pub fn heap_timeline(dumps: &[HprofJavaVirtualMachine], suspects: usize) -> Result<HeapTimeline> {
+    let mut timeline = HeapTimeline::default();
+    // Class name -> (instances, bytes) in each dump
+    let mut classes: HashMap<String, (Vec<u64>, Vec<u64>)> = HashMap::new();
+    // The largest objects in each dump, by path (see largest_by_path())
+    let mut largest: Vec<Vec<(String, String, u64)>> = vec![];
+    for (i, heap) in dumps.iter().enumerate() {
+        let histogram = heap.class_histogram()?;
+        for entry in &histogram.entries {
+            let (instances, bytes) = classes
+                .entry(entry.class_name.clone())
+                .or_insert_with(|| (vec![0; dumps.len()], vec![0; dumps.len()]));
+            instances[i] = entry.instances;
+            bytes[i] = entry.shallow_bytes.unwrap_or(0);
+        }
+        timeline.dumps.push(TimelineDump {
+            time: heap.dump_time(),
+            objects: histogram.total_instances(),
+            shallow_bytes: histogram.total_bytes().unwrap_or(0),
+        });
+        largest.push(heap.dump.largest_by_path(TRACKED)?);
+    }
+
+    timeline.classes = classes
+        .into_iter()
+        .map(|(class_name, (instances, shallow_bytes))| ClassCurve {
+            class_name,
+            instances,
+            shallow_bytes,
+        })
+        .collect();
+    timeline
+        .classes
+        .sort_by(|a, b| (b.byte_growth(), &a.class_name).cmp(&(a.byte_growth(), &b.class_name)));
+
+    let last = largest.last().cloned().unwrap_or_default();
+    for (path, class_name, _) in last.into_iter().take(suspects) {
+        let retained_bytes = largest
+            .iter()
+            .map(|objects| {
+                objects
+                    .iter()
+                    .find(|(p, _, _)| *p == path)
+                    .map(|&(_, _, bytes)| bytes)
+            })
+            .collect();
+        timeline.suspects.push(SuspectHistory {
+            path,
+            class_name,
+            retained_bytes,
+        });
+    }
+    Ok(timeline)
+}
+
+impl<R: Read + Seek> HprofParser<R> {
+    // The 'limit' largest objects which only GC roots dominate, totalled up by the path to them
+    // (see above), as (path, class name, retained bytes), largest first
+    fn largest_by_path(&self, limit: usize) -> Result<Vec<(String, String, u64)>> {
+        let graph = self.graph()?;
+        let mut objects = graph.top_level_dominators();
+        objects.sort_unstable_by_key(|&(object_id, retained)| (Reverse(retained), object_id));
+        objects.truncate(limit);
+
+        let mut totals: Vec<(String, String, u64)> = vec![];
+        // Path -> index in 'totals'
+        let mut indexes: HashMap<String, usize> = HashMap::new();
+        for (object_id, retained) in objects {
+            let path = graph.path_from_root(object_id).unwrap_or_default();
+            let mut steps = vec![];
+            for (i, &step) in path.iter().enumerate() {
+                let name = self.describe(step)?;
+                if i == 0 {
+                    steps.push(name);
+                    continue;
+                }
+                let mut reference = self.reference_name(path[i - 1], step)?;
+                // Array indexes aren't worth telling apart
+                if reference.starts_with('[') {
+                    reference = "[]".to_string();
+                }
+                steps.push(format!("{} {}", reference, name));
+            }
+            let path = steps.join(" ");
+            match indexes.get(&path) {
+                Some(&i) => totals[i].2 += retained,
+                None => {
+                    indexes.insert(path.clone(), totals.len());
+                    totals.push((path, self.describe(object_id)?, retained));
+                }
+            }
+        }
+        // Stable, so ties stay in the order of their largest object
+        totals.sort_by_key(|&(_, _, retained)| Reverse(retained));
+        Ok(totals)
+    }
+
+    // The class of an object, or "class X" for a class
+    pub(super) fn describe(&self, object_id: u64) -> Result<String> {
+        if self.class_dump_tab.contains_key(&object_id) {
+            if let Some(class) = self
+                .class_serials
+                .get(&object_id)
+                .and_then(|serial_num| self.class_tab.get(serial_num))
+            {
+                return Ok(format!("class {}", self.class_name(class)));
+            }
+        }
+        Ok(self
+            .object_class_name(object_id)?
+            .unwrap_or_else(|| "<unknown>".to_string()))
+    }
+}
@@ -0,0 +1,327 @@
+//
+// Checking a dump is sound before spending an hour analysing it. Dumps are often truncated (the
+// disk filled up, the JVM was killed while writing it, the copy off the server was cut short),
+// and parsing one which is can fail a long way in. validate() only walks the dump's structure:
+// each record's length, and within heap dump segments each subrecord's, checking they all line up
+// with where the next one starts and with the end of the file. Nothing is kept, so it runs in
+// constant memory, at about the speed the dump can be read.
+//
+// The report says where the last sound record ends, and what's before that, which is what can be
+// recovered: IncrementalHprof parses a dump up to its last complete record, and finish() makes a
+// usable HprofJavaVirtualMachine of that.
+//
+
+use std::io::{BufReader, Error, ErrorKind, Read, Result, Seek, SeekFrom};
+
+use num_traits::cast::FromPrimitive;
+
+use super::HEADER_SIZE;
+use super::{field_size, DataDumpSubRecordTag, FieldTag, HprofJavaVirtualMachine, RecordTag};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ValidationProblem {
+    // Where in the dump
+    pub offset: u64,
+    pub message: String,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ValidationReport {
+    pub file_bytes: u64,
+    // Where the last record before the first problem ends. Everything before this is sound.
+    pub valid_bytes: u64,
+    // In the sound part of the dump
+    pub records: u64,
+    pub strings: u64,
+    pub classes: u64,
+    pub objects: u64,
+    pub gc_roots: u64,
+    // Records with tags we don't know, probably from a newer JVM. Parsing skips them.
+    pub unknown_records: u64,
+    // Whether the dump ends with a HEAP DUMP END record, as a finished 1.0.2 dump does
+    pub has_end: bool,
+    pub problems: Vec<ValidationProblem>,
+}
+
+impl ValidationReport {
+    // Nothing wrong, and nothing missing from the end
+    pub fn is_intact(&self) -> bool {
+        self.problems.is_empty() && self.valid_bytes == self.file_bytes
+    }
+
+    // Whether there's anything worth recovering: the header, and at least one record
+    pub fn is_recoverable(&self) -> bool {
+        self.valid_bytes > HEADER_SIZE
+    }
+}
+
+impl HprofJavaVirtualMachine {
+    // Check the dump's structure from end to end, without parsing it. Only I/O errors are
+    // errors: what's wrong with the dump is in the report.
+    pub fn validate<R: Read + Seek>(reader: R) -> Result<ValidationReport> {
+        let mut walker = Walker {
+            reader: BufReader::new(reader),
+            position: 0,
+            segments: false,
+        };
+        walker.validate()
+    }
+}
+
+struct Walker<R: Read + Seek> {
+    reader: BufReader<R>,
+    position: u64,
+    // Whether there were any HEAP DUMP SEGMENT records, which need a HEAP DUMP END after them
+    segments: bool,
+}
+
+// Why a walk stopped early
+enum Stop {
+    // The record runs past the end of the file
+    Truncated,
+    Problem(String),
+    Io(Error),
+}
+
+type Walked = std::result::Result<(), Stop>;
+
+impl From<Error> for Stop {
+    fn from(e: Error) -> Stop {
+        if e.kind() == ErrorKind::UnexpectedEof {
+            Stop::Truncated
+        } else {
+            Stop::Io(e)
+        }
+    }
+}
+
+impl<R: Read + Seek> Walker<R> {
+    fn validate(&mut self) -> Result<ValidationReport> {
+        let mut report = ValidationReport {
+            file_bytes: self.reader.seek(SeekFrom::End(0))?,
+            ..Default::default()
+        };
+        self.reader.seek(SeekFrom::Start(0))?;
+        let problem = |report: &mut ValidationReport, offset, message: String| {
+            report.problems.push(ValidationProblem { offset, message });
+        };
+
+        if report.file_bytes < HEADER_SIZE {
+            problem(&mut report, 0, "Too short to have a header".to_string());
+            return Ok(report);
+        }
+        let mut format = [0u8; 19];
+        self.read(&mut format)?;
+        if !format.starts_with(b"JAVA PROFILE 1.0.") || format[18] != 0 {
+            let format = String::from_utf8_lossy(&format).to_string();
+            problem(&mut report, 0, format!("Not a heap dump ({:?})", format));
+            return Ok(report);
+        }
+        let identifier_size = self.u32()?;
+        if identifier_size != 8 {
+            let message = format!("{}-byte identifiers aren't supported", identifier_size);
+            problem(&mut report, 19, message);
+            return Ok(report);
+        }
+        self.skip(8)?;
+        report.valid_bytes = HEADER_SIZE;
+
+        let file_bytes = report.file_bytes;
+        while self.position < file_bytes {
+            let start = self.position;
+            // The counts are only of sound records
+            let before = report.clone();
+            let message = match self.record(&mut report, file_bytes) {
+                Ok(()) => {
+                    report.valid_bytes = self.position;
+                    if !report.has_end || self.position == report.file_bytes {
+                        continue;
+                    }
+                    let trailing = report.file_bytes - self.position;
+                    problem(
+                        &mut report,
+                        self.position,
+                        format!("{} bytes after the HEAP DUMP END record", trailing),
+                    );
+                    break;
+                }
+                Err(Stop::Truncated) => format!(
+                    "Truncated: the record at {} runs past the end of the file",
+                    start
+                ),
+                Err(Stop::Problem(message)) => message,
+                Err(Stop::Io(e)) => return Err(e),
+            };
+            report = before;
+            problem(&mut report, start, message);
+            break;
+        }
+        // A 1.0.1 dump has one HEAP DUMP record, and no end
+        if self.segments && !report.has_end && report.problems.is_empty() {
+            let offset = report.valid_bytes;
+            let message = "Truncated: there's no HEAP DUMP END record".to_string();
+            problem(&mut report, offset, message);
+        }
+        Ok(report)
+    }
+
+    fn record(&mut self, report: &mut ValidationReport, file_end: u64) -> Walked {
+        let start = self.position;
+        let raw_tag = self.u8()?;
+        self.skip(4)?;
+        let length = u64::from(self.u32()?);
+        let end = self.position + length;
+        if end > file_end {
+            return Err(Stop::Truncated);
+        }
+        match FromPrimitive::from_u8(raw_tag) {
+            Some(RecordTag::Utf8String) if length < 8 => {
+                return Err(Stop::Problem(format!(
+                    "The string record at {} is {} bytes, too short for its ID",
+                    start, length
+                )));
+            }
+            Some(RecordTag::HeapDump) => self.segment(report, end)?,
+            Some(RecordTag::HeapDumpSegment) => {
+                self.segments = true;
+                self.segment(report, end)?;
+            }
+            Some(tag) => {
+                match tag {
+                    RecordTag::Utf8String => report.strings += 1,
+                    RecordTag::LoadClass => report.classes += 1,
+                    RecordTag::HeapDumpEnd => report.has_end = true,
+                    _ => {}
+                }
+                self.skip(length)?;
+            }
+            None => {
+                report.unknown_records += 1;
+                self.skip(length)?;
+            }
+        }
+        report.records += 1;
+        Ok(())
+    }
+
+    // The subrecords of a heap dump (segment), which must end exactly where it does
+    fn segment(&mut self, report: &mut ValidationReport, end: u64) -> Walked {
+        let id = 8;
+        while self.position < end {
+            let start = self.position;
+            let raw_subtag = self.u8()?;
+            let subtag = FromPrimitive::from_u8(raw_subtag).ok_or_else(|| {
+                Stop::Problem(format!(
+                    "Unknown heap dump subrecord tag {:#x} at {}",
+                    raw_subtag, start
+                ))
+            })?;
+            match subtag {
+                DataDumpSubRecordTag::RootUnknown
+                | DataDumpSubRecordTag::StickyClass
+                | DataDumpSubRecordTag::MonitorUsed => self.skip(id)?,
+                DataDumpSubRecordTag::JniGlobal => self.skip(id * 2)?,
+                DataDumpSubRecordTag::NativeStack | DataDumpSubRecordTag::ThreadBlock => {
+                    self.skip(id + 4)?
+                }
+                DataDumpSubRecordTag::JniLocal
+                | DataDumpSubRecordTag::JavaFrame
+                | DataDumpSubRecordTag::ThreadObject => self.skip(id + 8)?,
+                DataDumpSubRecordTag::ClassDump => self.class_dump()?,
+                DataDumpSubRecordTag::InstanceDump => {
+                    self.skip(id + 4 + id)?;
+                    let bytes = self.u32()?;
+                    self.skip(u64::from(bytes))?;
+                }
+                DataDumpSubRecordTag::ObjectArrayDump => {
+                    self.skip(id + 4)?;
+                    let elements = self.u32()?;
+                    self.skip(id + u64::from(elements) * id)?;
+                }
+                DataDumpSubRecordTag::PrimitiveArrayDump => {
+                    self.skip(id + 4)?;
+                    let elements = self.u32()?;
+                    let element_type = self.field_type()?;
+                    self.skip(u64::from(elements) * field_size(element_type))?;
+                }
+            }
+            if self.position > end {
+                return Err(Stop::Problem(format!(
+                    "The subrecord at {} runs {} bytes past the end of its segment",
+                    start,
+                    self.position - end
+                )));
+            }
+            match subtag {
+                DataDumpSubRecordTag::ClassDump => {}
+                DataDumpSubRecordTag::InstanceDump
+                | DataDumpSubRecordTag::ObjectArrayDump
+                | DataDumpSubRecordTag::PrimitiveArrayDump => report.objects += 1,
+                _ => report.gc_roots += 1,
+            }
+        }
+        Ok(())
+    }
+
+    fn class_dump(&mut self) -> Walked {
+        self.skip(8 + 4 + 8 * 6 + 4)?;
+        for _ in 0..self.u16()? {
+            self.skip(2)?;
+            let field_type = self.field_type()?;
+            self.skip(field_size(field_type))?;
+        }
+        for _ in 0..self.u16()? {
+            self.skip(8)?;
+            let field_type = self.field_type()?;
+            self.skip(field_size(field_type))?;
+        }
+        for _ in 0..self.u16()? {
+            self.skip(8)?;
+            self.field_type()?;
+        }
+        Ok(())
+    }
+
+    fn field_type(&mut self) -> std::result::Result<FieldTag, Stop> {
+        let raw = self.u8()?;
+        FromPrimitive::from_u8(raw).ok_or_else(|| {
+            Stop::Problem(format!(
+                "Unknown field type {:#x} at {}",
+                raw,
+                self.position - 1
+            ))
+        })
+    }
+
+    fn read(&mut self, buf: &mut [u8]) -> Result<()> {
+        self.reader.read_exact(buf)?;
+        self.position += buf.len() as u64;
+        Ok(())
+    }
+
+    fn u8(&mut self) -> Result<u8> {
+        let mut buf = [0u8; 1];
+        self.read(&mut buf)?;
+        Ok(buf[0])
+    }
+
+    fn u16(&mut self) -> Result<u16> {
+        let mut buf = [0u8; 2];
+        self.read(&mut buf)?;
+        Ok(u16::from_be_bytes(buf))
+    }
+
+    fn u32(&mut self) -> Result<u32> {
+        let mut buf = [0u8; 4];
+        self.read(&mut buf)?;
+        Ok(u32::from_be_bytes(buf))
+    }
+
+    // Seeking past the end of the file isn't an error, so a skip which goes past it is only
+    // noticed by the checks against the file's length
+    fn skip(&mut self, bytes: u64) -> Result<()> {
+        self.reader.seek_relative(bytes as i64)?;
+        self.position += bytes;
+        Ok(())
+    }
+}
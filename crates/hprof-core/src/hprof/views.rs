@@ -0,0 +1,412 @@
+//
+// Looking at a heap dump in increasing detail. Each step costs more than the one before, and
+// nothing is done until it's asked for:
+//
+// - Opening the dump indexes it: the strings, classes, stack traces, GC roots, and where each
+//   object is in the file.
+// - summary() reads the header of every object, which is enough for the class histogram.
+// - ClassView and ObjectView read the objects they're asked about, except for retained sizes and
+//   dominators, which need the dominator tree of the whole heap. That's built the first time any
+//   of them are asked for, and reads every object in the dump.
+//
+
+use std::collections::HashMap;
+use std::io::{Cursor, Result};
+use std::rc::Rc;
+
+use super::heap::{ClassKey, HeapObject};
+use super::referrers::ReferrerIndex;
+use super::{field_size, read_value, Dump, HprofJavaVirtualMachine, HprofReferenceType};
+use crate::model::Value;
+use crate::snapshot::{Histogram, HistogramEntry};
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Summary {
+    pub identifier_size: u32,
+    pub classes: u64,
+    pub threads: u64,
+    pub gc_roots: u64,
+    pub objects: u64,
+    pub shallow_bytes: u64,
+    pub histogram: Histogram,
+}
+
+impl HprofJavaVirtualMachine {
+    pub fn summary(&self) -> Result<Summary> {
+        let dump = &self.dump;
+        // Instances and shallow bytes
+        let mut totals: HashMap<ClassKey, (u64, u64)> = HashMap::new();
+        dump.for_each_object_header(|_, header| {
+            let entry = totals.entry(header.class_key()).or_default();
+            entry.0 += 1;
+            entry.1 += dump.header_shallow_size(&header);
+        })?;
+
+        let histogram = Histogram::new(
+            totals
+                .into_iter()
+                .map(|(key, (instances, shallow_bytes))| HistogramEntry {
+                    class_name: dump.class_key_name(key),
+                    instances,
+                    shallow_bytes: Some(shallow_bytes),
+                })
+                .collect(),
+        );
+        Ok(Summary {
+            identifier_size: dump.header.identifier_size,
+            classes: dump.class_dump_tab.len() as u64,
+            threads: self.thread_serials().len() as u64,
+            gc_roots: dump.roots.len() as u64,
+            objects: histogram.total_instances(),
+            shallow_bytes: histogram.total_bytes().unwrap_or(0),
+            histogram,
+        })
+    }
+
+    // All the classes with the given name (there can be more than one if several class loaders
+    // have loaded a class with that name)
+    pub fn class_views(&self, name: &str) -> Vec<ClassView> {
+        let mut ids = self.dump.class_object_ids(name);
+        ids.sort_unstable();
+        ids.into_iter()
+            .map(|class_object_id| ClassView {
+                dump: self.dump.clone(),
+                class_object_id,
+            })
+            .collect()
+    }
+
+    // None if there's no such object (or class) in the dump
+    pub fn object(&self, object_id: u64) -> Option<ObjectView> {
+        if self.dump.object_offsets.contains(object_id)
+            || self.dump.class_dump_tab.contains_key(&object_id)
+        {
+            Some(ObjectView {
+                dump: self.dump.clone(),
+                object_id,
+            })
+        } else {
+            None
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct ClassView {
+    dump: Rc<Dump>,
+    class_object_id: u64,
+}
+
+impl ClassView {
+    pub fn class_object_id(&self) -> u64 {
+        self.class_object_id
+    }
+
+    pub fn name(&self) -> String {
+        self.dump
+            .class_key_name(ClassKey::Class(self.class_object_id))
+    }
+
+    pub fn superclass(&self) -> Option<ClassView> {
+        let class = self.dump.class_dump_tab.get(&self.class_object_id)?;
+        match class.superclass_object_id {
+            0 => None,
+            id => Some(ClassView {
+                dump: self.dump.clone(),
+                class_object_id: id,
+            }),
+        }
+    }
+
+    // None for the bootstrap loader
+    pub fn class_loader(&self) -> Option<ObjectView> {
+        let class = self.dump.class_dump_tab.get(&self.class_object_id)?;
+        match class.class_loader_object_id {
+            0 => None,
+            id => Some(ObjectView {
+                dump: self.dump.clone(),
+                object_id: id,
+            }),
+        }
+    }
+
+    // The model's view of the class, for the things it does that this doesn't
+    pub fn reference_type(&self) -> Option<HprofReferenceType> {
+        self.dump
+            .class_serials
+            .get(&self.class_object_id)
+            .map(|&serial_num| HprofReferenceType {
+                dump: self.dump.clone(),
+                serial_num,
+            })
+    }
+
+    pub fn static_fields(&self) -> Vec<(String, Value)> {
+        match self.dump.class_dump_tab.get(&self.class_object_id) {
+            Some(class) => class
+                .static_fields
+                .iter()
+                .map(|(name_id, value)| {
+                    let name = self.dump.string(*name_id);
+                    (
+                        name.as_deref().unwrap_or("<unknown>").to_string(),
+                        value.clone(),
+                    )
+                })
+                .collect(),
+            None => vec![],
+        }
+    }
+
+    // Not including instances of subclasses. This reads the header of every object in the dump.
+    pub fn instances(&self) -> Result<Vec<ObjectView>> {
+        Ok(self
+            .dump
+            .instances_of(&[self.class_object_id])?
+            .into_iter()
+            .map(|object_id| ObjectView {
+                dump: self.dump.clone(),
+                object_id,
+            })
+            .collect())
+    }
+
+    // What would be freed if all the instances were collected
+    pub fn retained_size_of_instances(&self) -> Result<u64> {
+        let instances = self.dump.instances_of(&[self.class_object_id])?;
+        Ok(self.dump.graph()?.retained_size_of_all(&instances))
+    }
+}
+
+#[derive(Clone)]
+pub struct ObjectView {
+    pub(super) dump: Rc<Dump>,
+    pub(super) object_id: u64,
+}
+
+impl ObjectView {
+    pub fn id(&self) -> u64 {
+        self.object_id
+    }
+
+    pub fn class_name(&self) -> Result<String> {
+        Ok(self
+            .dump
+            .object_class_name(self.object_id)?
+            .unwrap_or_else(|| "<unknown>".to_string()))
+    }
+
+    // None for primitive arrays, which don't have a class in the dump
+    pub fn class(&self) -> Result<Option<ClassView>> {
+        if self.dump.class_dump_tab.contains_key(&self.object_id) {
+            return Ok(self.dump.class_object_ids("java.lang.Class").first().map(
+                |&class_object_id| ClassView {
+                    dump: self.dump.clone(),
+                    class_object_id,
+                },
+            ));
+        }
+        Ok(match self.dump.read_object(self.object_id)? {
+            Some(HeapObject::Instance {
+                class_object_id, ..
+            })
+            | Some(HeapObject::ObjectArray {
+                class_object_id, ..
+            }) => Some(ClassView {
+                dump: self.dump.clone(),
+                class_object_id,
+            }),
+            _ => None,
+        })
+    }
+
+    // If this is a class, the class itself (as opposed to java.lang.Class)
+    pub fn as_class(&self) -> Option<ClassView> {
+        if self.dump.class_dump_tab.contains_key(&self.object_id) {
+            Some(ClassView {
+                dump: self.dump.clone(),
+                class_object_id: self.object_id,
+            })
+        } else {
+            None
+        }
+    }
+
+    pub fn shallow_size(&self) -> Result<u64> {
+        self.dump.object_size(self.object_id)
+    }
+
+    // The fields of an instance (see HprofParser::instance_fields()), the elements of an array,
+    // or the static fields of a class
+    pub fn fields(&self) -> Result<Vec<(String, Value)>> {
+        if let Some(class) = self.as_class() {
+            return Ok(class.static_fields());
+        }
+        Ok(match self.dump.read_object(self.object_id)? {
+            Some(HeapObject::Instance { .. }) => self
+                .dump
+                .instance_fields(self.object_id)?
+                .unwrap_or_default(),
+            Some(HeapObject::ObjectArray { elements, .. }) => elements
+                .into_iter()
+                .enumerate()
+                .map(|(i, id)| {
+                    let value = match id {
+                        0 => Value::Null,
+                        id => Value::Object(id),
+                    };
+                    (format!("[{}]", i), value)
+                })
+                .collect(),
+            Some(HeapObject::PrimitiveArray { element_type, data }) => {
+                let length = data.len() as u64 / field_size(element_type);
+                let mut reader = Cursor::new(data);
+                (0..length)
+                    .map(|i| Ok((format!("[{}]", i), read_value(&mut reader, element_type)?)))
+                    .collect::<Result<_>>()?
+            }
+            None => vec![],
+        })
+    }
+
+    // The contents of a java.lang.String
+    pub fn as_string(&self) -> Result<Option<String>> {
+        self.dump.read_string(self.object_id)
+    }
+
+    // If this is an enum constant, its enum's simple name and its own, e.g. TimeUnit.SECONDS
+    pub fn as_enum(&self) -> Result<Option<String>> {
+        let class = match self.class()? {
+            Some(class) if self.as_class().is_none() => class,
+            _ => return Ok(None),
+        };
+        let is_enum = |class: &ClassView| {
+            class
+                .superclass()
+                .is_some_and(|superclass| superclass.name() == "java.lang.Enum")
+        };
+        // A constant with a body of its own is an instance of an anonymous subclass of its enum
+        let enum_class = if is_enum(&class) {
+            class
+        } else {
+            match class.superclass() {
+                Some(superclass) if is_enum(&superclass) => superclass,
+                _ => return Ok(None),
+            }
+        };
+        // java.lang.Enum's own field comes last, after any the enum declares with the same name
+        let name = self
+            .dump
+            .instance_fields(self.object_id)?
+            .unwrap_or_default()
+            .into_iter()
+            .rev()
+            .find(|(name, _)| name == "name");
+        let name = match name {
+            Some((_, Value::Object(name_id))) => self.dump.read_string(name_id)?,
+            _ => None,
+        };
+        Ok(name.map(|name| {
+            let class_name = enum_class.name();
+            let simple_name = class_name.rsplit(['.', '$']).next().unwrap_or(&class_name);
+            format!("{}.{}", simple_name, name)
+        }))
+    }
+
+    // A one line description, as Session::render() has it for live objects: Strings quoted, enum
+    // constants as Class.NAME, and anything else as class@id
+    pub fn render(&self) -> Result<String> {
+        if let Some(constant) = self.as_enum()? {
+            return Ok(constant);
+        }
+        let class_name = self.class_name()?;
+        if class_name == "java.lang.String" {
+            if let Some(string) = self.as_string()? {
+                return Ok(format!("{:?}", string));
+            }
+        }
+        Ok(format!("{}@{:x}", class_name, self.object_id))
+    }
+
+    // Everything this refers to, including its class
+    pub fn references(&self) -> Result<Vec<ObjectView>> {
+        let (_, references) = self
+            .dump
+            .outgoing_references(self.object_id, self.dump.follow_referents.get())?;
+        Ok(references
+            .into_iter()
+            .map(|object_id| ObjectView {
+                dump: self.dump.clone(),
+                object_id,
+            })
+            .collect())
+    }
+
+    pub fn retained_size(&self) -> Result<u64> {
+        Ok(self
+            .dump
+            .graph()?
+            .retained_size(self.object_id)
+            .unwrap_or(0))
+    }
+
+    // None if only GC roots dominate it
+    pub fn immediate_dominator(&self) -> Result<Option<ObjectView>> {
+        Ok(self
+            .dump
+            .graph()?
+            .immediate_dominator(self.object_id)
+            .map(|object_id| ObjectView {
+                dump: self.dump.clone(),
+                object_id,
+            }))
+    }
+
+    // The objects which refer to this one (see ReferrerIndex::referrers())
+    pub fn referrers(&self, index: &ReferrerIndex) -> Result<Vec<ObjectView>> {
+        Ok(index
+            .referrers(self.object_id)?
+            .into_iter()
+            .map(|object_id| ObjectView {
+                dump: self.dump.clone(),
+                object_id,
+            })
+            .collect())
+    }
+
+    // If this is a GC root which belongs to a thread, such as a local variable, which thread
+    // and where, e.g. "thread \"main\" at com.example.Worker.run(Worker.java:42)"
+    pub fn held_by(&self) -> Result<Option<String>> {
+        self.dump.root_holder(self.object_id)
+    }
+
+    // Like path_from_root(), but found with a ReferrerIndex, which can be built under a memory
+    // budget, rather than the dominator tree, which can't
+    pub fn path_to_root(&self, index: &ReferrerIndex) -> Result<Option<Vec<ObjectView>>> {
+        Ok(index.path_to_root(self.object_id)?.map(|path| {
+            path.into_iter()
+                .map(|object_id| ObjectView {
+                    dump: self.dump.clone(),
+                    object_id,
+                })
+                .collect()
+        }))
+    }
+
+    // The shortest chain of references to this from a GC root, starting with the root. None if
+    // it's garbage.
+    pub fn path_from_root(&self) -> Result<Option<Vec<ObjectView>>> {
+        Ok(self
+            .dump
+            .graph()?
+            .path_from_root(self.object_id)
+            .map(|path| {
+                path.into_iter()
+                    .map(|object_id| ObjectView {
+                        dump: self.dump.clone(),
+                        object_id,
+                    })
+                    .collect()
+            }))
+    }
+}
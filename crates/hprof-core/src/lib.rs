@@ -0,0 +1,46 @@
+//
+// Parsing heap dumps (hprof files), and the analyses which need a whole heap: retained sizes,
+// class loader leaks, wasted collection space and so on. Nothing here talks to a live JVM.
+//
+
+use std::fs::File;
+use std::io::{Error, Result};
+use std::path::Path;
+
+use crate::hprof::{HprofJavaVirtualMachine, StringCache, ValidationReport};
+
+#[macro_use]
+extern crate num_derive;
+
+pub mod hprof;
+
+use libjdb_model::{model, mutf8, names, pattern, snapshot};
+
+pub fn open_hprof<P: AsRef<Path>>(path: P) -> Result<HprofJavaVirtualMachine> {
+    HprofJavaVirtualMachine::new(File::open(path)?)
+}
+
+// Check a dump's structure without parsing it (see HprofJavaVirtualMachine::validate())
+pub fn validate_hprof<P: AsRef<Path>>(path: P) -> Result<ValidationReport> {
+    HprofJavaVirtualMachine::validate(File::open(path)?)
+}
+
+// Open several dumps of the same application (e.g. for heap_timeline()), sharing the strings they
+// have in common instead of keeping a copy for each of them. An error says which dump it's about.
+pub fn open_hprofs<P: AsRef<Path>>(paths: &[P]) -> Result<Vec<HprofJavaVirtualMachine>> {
+    let cache = StringCache::new();
+    paths
+        .iter()
+        .map(|path| {
+            File::open(path)
+                .and_then(|file| {
+                    HprofJavaVirtualMachine::with_string_cache(
+                        file,
+                        hprof::DEFAULT_STRING_BUDGET,
+                        &cache,
+                    )
+                })
+                .map_err(|e| Error::new(e.kind(), format!("{}: {}", path.as_ref().display(), e)))
+        })
+        .collect()
+}
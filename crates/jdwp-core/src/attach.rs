@@ -0,0 +1,219 @@
+//
+// HotSpot's dynamic attach mechanism, which is how jcmd talks to a JVM on the same machine
+// without it having been started with a debugging agent. The JVM only listens for attach
+// requests once asked to: we create .attach_pid<pid> in its working directory (or /tmp) and send
+// it SIGQUIT, which it then takes as that request rather than as one to print its threads. It
+// listens on a Unix socket, /tmp/.java_pid<pid>, which only its owner (or root) can connect to.
+//
+// Each operation is a connection of its own. The request is the protocol version, the operation
+// and three arguments, each NUL terminated, and the reply is a status line followed by the
+// operation's output.
+//
+// Only Linux for now, since that's where we can check that the process handles SIGQUIT before
+// sending it one: to anything but a JVM, SIGQUIT is fatal.
+//
+
+use std::io::{Error, ErrorKind, Result};
+
+use crate::jfr::{self, JfrSettings};
+
+// Runs a diagnostic command with its arguments, e.g. "GC.class_histogram -all", in the JVM with
+// this process ID, and returns its output. See also
+// JdwpJavaVirtualMachine::diagnostic_command(), for JVMs we're debugging over JDWP.
+pub fn diagnostic_command(pid: u32, command: &str) -> Result<String> {
+    execute(pid, "jcmd", &[command])
+}
+
+// Starts a Flight Recorder recording, returning what JFR.start had to say about it
+pub fn jfr_start(pid: u32, settings: &JfrSettings) -> Result<String> {
+    diagnostic_command(pid, &settings.start_command())
+}
+
+// See jfr::dump_command()
+pub fn jfr_dump(pid: u32, name: &str, filename: &str) -> Result<String> {
+    diagnostic_command(pid, &jfr::dump_command(name, filename))
+}
+
+// See jfr::stop_command()
+pub fn jfr_stop(pid: u32, name: &str, filename: Option<&str>) -> Result<String> {
+    diagnostic_command(pid, &jfr::stop_command(name, filename))
+}
+
+// Any attach operation (e.g. "properties" or "threaddump"), with up to three arguments
+pub fn execute(pid: u32, operation: &str, args: &[&str]) -> Result<String> {
+    if args.len() > 3 {
+        return Err(Error::new(
+            ErrorKind::InvalidInput,
+            "Attach operations take at most three arguments",
+        ));
+    }
+    platform::execute(pid, &request(operation, args))
+}
+
+const PROTOCOL_VERSION: &str = "1";
+
+fn request(operation: &str, args: &[&str]) -> Vec<u8> {
+    let mut request = vec![];
+    let padding = std::iter::repeat_n("", 3 - args.len());
+    for part in [PROTOCOL_VERSION, operation]
+        .iter()
+        .chain(args)
+        .copied()
+        .chain(padding)
+    {
+        request.extend_from_slice(part.as_bytes());
+        request.push(0);
+    }
+    request
+}
+
+// The status is 0 if the operation was carried out. For jcmd that doesn't mean the command
+// succeeded, since it reports its own errors in its output.
+fn parse_reply(reply: &[u8]) -> Result<String> {
+    let reply = String::from_utf8_lossy(reply);
+    let (status, output) = reply.split_once('\n').unwrap_or((&reply, ""));
+    match status.trim().parse::<i32>() {
+        Ok(0) => Ok(output.to_string()),
+        Ok(status) => Err(Error::other(format!(
+            "Attach operation failed with status {}: {}",
+            status,
+            output.trim()
+        ))),
+        Err(_) => Err(Error::new(
+            ErrorKind::InvalidData,
+            format!("Unexpected reply to attach request: {:?}", status),
+        )),
+    }
+}
+
+#[cfg(target_os = "linux")]
+mod platform {
+    use std::fs::{self, File};
+    use std::io::{Error, ErrorKind, Read, Result, Write};
+    use std::os::unix::net::UnixStream;
+    use std::path::{Path, PathBuf};
+    use std::process::Command;
+    use std::thread;
+    use std::time::{Duration, Instant};
+
+    // How long the JVM gets to start listening
+    const ATTACH_TIMEOUT: Duration = Duration::from_secs(10);
+    const SIGQUIT: u32 = 3;
+
+    pub(super) fn execute(pid: u32, request: &[u8]) -> Result<String> {
+        let socket = PathBuf::from(format!("/tmp/.java_pid{}", pid));
+        let mut stream = match UnixStream::connect(&socket) {
+            Ok(stream) => stream,
+            Err(_) => start_listening(pid, &socket)?,
+        };
+        stream.write_all(request)?;
+        let mut reply = vec![];
+        stream.read_to_end(&mut reply)?;
+        super::parse_reply(&reply)
+    }
+
+    fn start_listening(pid: u32, socket: &Path) -> Result<UnixStream> {
+        if !catches_sigquit(pid)? {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                format!(
+                    "Process {} doesn't look like a JVM which can be attached to",
+                    pid
+                ),
+            ));
+        }
+        // The JVM looks in its working directory first
+        let mut trigger = PathBuf::from(format!("/proc/{}/cwd/.attach_pid{}", pid, pid));
+        if File::create(&trigger).is_err() {
+            trigger = PathBuf::from(format!("/tmp/.attach_pid{}", pid));
+            File::create(&trigger)?;
+        }
+        let stream = signal_and_wait(pid, socket);
+        let _ = fs::remove_file(&trigger);
+        stream
+    }
+
+    // From the mask of caught signals in /proc/<pid>/status
+    fn catches_sigquit(pid: u32) -> Result<bool> {
+        let status = fs::read_to_string(format!("/proc/{}/status", pid))?;
+        let caught = status
+            .lines()
+            .find_map(|line| line.strip_prefix("SigCgt:"))
+            .and_then(|mask| u64::from_str_radix(mask.trim(), 16).ok())
+            .unwrap_or(0);
+        Ok(caught & (1 << (SIGQUIT - 1)) != 0)
+    }
+
+    fn signal_and_wait(pid: u32, socket: &Path) -> Result<UnixStream> {
+        let status = Command::new("kill")
+            .arg("-QUIT")
+            .arg(pid.to_string())
+            .status()?;
+        if !status.success() {
+            return Err(Error::other(format!("Couldn't signal process {}", pid)));
+        }
+        let deadline = Instant::now() + ATTACH_TIMEOUT;
+        let mut delay = Duration::from_millis(20);
+        loop {
+            thread::sleep(delay);
+            match UnixStream::connect(socket) {
+                Ok(stream) => return Ok(stream),
+                Err(_) if Instant::now() < deadline => {}
+                Err(e) => {
+                    return Err(Error::new(
+                        ErrorKind::TimedOut,
+                        format!(
+                            "Process {} didn't start listening at {} ({}). Was it started with \
+                             -XX:+DisableAttachMechanism, or by another user?",
+                            pid,
+                            socket.display(),
+                            e
+                        ),
+                    ))
+                }
+            }
+            delay = (delay * 2).min(Duration::from_millis(500));
+        }
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+mod platform {
+    use std::io::{Error, ErrorKind, Result};
+
+    pub(super) fn execute(_pid: u32, _request: &[u8]) -> Result<String> {
+        Err(Error::new(
+            ErrorKind::Unsupported,
+            "Attaching by process ID is only supported on Linux",
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn request_is_padded_to_three_args() {
+        assert_eq!(
+            request("jcmd", &["GC.heap_info"]),
+            b"1\0jcmd\0GC.heap_info\0\0\0".to_vec()
+        );
+        assert_eq!(
+            request("properties", &[]),
+            b"1\0properties\0\0\0\0".to_vec()
+        );
+    }
+
+    #[test]
+    fn replies() {
+        assert_eq!(parse_reply(b"0\nheap\n").unwrap(), "heap\n");
+        assert_eq!(parse_reply(b"0\n").unwrap(), "");
+        let err = parse_reply(b"101\nNo such operation\n").unwrap_err();
+        assert!(err.to_string().contains("No such operation"));
+        assert_eq!(
+            parse_reply(b"garbage").unwrap_err().kind(),
+            ErrorKind::InvalidData
+        );
+    }
+}
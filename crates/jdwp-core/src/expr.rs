@@ -0,0 +1,659 @@
+//
+// Java-like expressions, as typed by users for conditions and watches, e.g.
+//
+//   count > 100 && name != null
+//   this.size * 2 >= java.lang.Integer.MAX_VALUE - 1
+//
+// Only parsing is done here. Evaluating an expression needs a target to read variables and fields
+// from, see JdwpStackFrame::evaluate().
+//
+// The grammar is a subset of Java's: literals (including 10L, 1.5f, 'c' and "text"), names, field
+// access, arithmetic, comparisons and the boolean operators, with Java's precedence. Indexing and
+// method calls are parsed too, but what they can be applied to is up to the evaluator (for a map,
+// map["key"] looks up a key).
+//
+
+use std::fmt;
+use std::str::FromStr;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Expr {
+    Literal(Literal),
+    // A dotted name, e.g. count, this.count or java.lang.Integer.MAX_VALUE. Which part is a
+    // variable, a class or a field can only be worked out when it's evaluated.
+    Name(Vec<String>),
+    // A field of something other than a name, e.g. (a + b).x
+    Field(Box<Expr>, String),
+    // a[i]
+    Index(Box<Expr>, Box<Expr>),
+    // The object, the method's name and the arguments, e.g. set.contains(x)
+    Call(Box<Expr>, String, Vec<Expr>),
+    Unary(UnaryOp, Box<Expr>),
+    Binary(BinaryOp, Box<Expr>, Box<Expr>),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Literal {
+    Int(i32),
+    Long(i64),
+    Float(f32),
+    Double(f64),
+    Boolean(bool),
+    Char(u16),
+    String(String),
+    Null,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UnaryOp {
+    Neg,
+    Not,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BinaryOp {
+    Or,
+    And,
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Rem,
+}
+
+impl fmt::Display for BinaryOp {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let op = match self {
+            BinaryOp::Or => "||",
+            BinaryOp::And => "&&",
+            BinaryOp::Eq => "==",
+            BinaryOp::Ne => "!=",
+            BinaryOp::Lt => "<",
+            BinaryOp::Le => "<=",
+            BinaryOp::Gt => ">",
+            BinaryOp::Ge => ">=",
+            BinaryOp::Add => "+",
+            BinaryOp::Sub => "-",
+            BinaryOp::Mul => "*",
+            BinaryOp::Div => "/",
+            BinaryOp::Rem => "%",
+        };
+        write!(f, "{}", op)
+    }
+}
+
+#[derive(Debug)]
+pub struct ExprError {
+    msg: String,
+}
+
+impl std::error::Error for ExprError {}
+
+impl fmt::Display for ExprError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.msg)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    // The text of the number, without any minus sign in front of it
+    Number(String),
+    Char(u16),
+    String(String),
+    Ident(String),
+    Op(&'static str),
+}
+
+// Two character operators first, so that e.g. <= isn't read as <
+const OPERATORS: [&str; 20] = [
+    "||", "&&", "==", "!=", "<=", ">=", "<", ">", "+", "-", "*", "/", "%", "!", "(", ")", ".", "[",
+    "]", ",",
+];
+
+impl Expr {
+    pub fn parse(source: &str) -> Result<Expr, ExprError> {
+        let err = |msg: String| ExprError {
+            msg: format!("Invalid expression '{}': {}", source.trim(), msg),
+        };
+        let tokens = tokenize(source).map_err(err)?;
+        let mut parser = Parser { tokens, pos: 0 };
+        let expr = parser.expr(0).map_err(err)?;
+        match parser.peek() {
+            None => Ok(expr),
+            Some(token) => Err(err(format!("unexpected {}", describe(token)))),
+        }
+    }
+}
+
+impl FromStr for Expr {
+    type Err = ExprError;
+
+    fn from_str(s: &str) -> Result<Expr, ExprError> {
+        Expr::parse(s)
+    }
+}
+
+fn is_ident_start(c: char) -> bool {
+    c.is_alphabetic() || c == '_' || c == '$'
+}
+
+fn is_ident_part(c: char) -> bool {
+    c.is_alphanumeric() || c == '_' || c == '$'
+}
+
+fn tokenize(source: &str) -> Result<Vec<Token>, String> {
+    let chars: Vec<char> = source.chars().collect();
+    let mut tokens = vec![];
+    let mut i = 0;
+    while i < chars.len() {
+        let c = chars[i];
+        let next = chars.get(i + 1).copied();
+        if c.is_whitespace() {
+            i += 1;
+        } else if c.is_ascii_digit() || (c == '.' && next.is_some_and(|c| c.is_ascii_digit())) {
+            let start = i;
+            let hex = c == '0' && matches!(next, Some('x') | Some('X'));
+            while let Some(&c) = chars.get(i) {
+                // The sign of an exponent, as in 1e-5
+                let sign = (c == '+' || c == '-') && !hex && matches!(chars[i - 1], 'e' | 'E');
+                if !(c.is_ascii_alphanumeric() || c == '.' || c == '_' || sign) {
+                    break;
+                }
+                i += 1;
+            }
+            tokens.push(Token::Number(chars[start..i].iter().collect()));
+        } else if is_ident_start(c) {
+            let start = i;
+            while chars.get(i).is_some_and(|&c| is_ident_part(c)) {
+                i += 1;
+            }
+            tokens.push(Token::Ident(chars[start..i].iter().collect()));
+        } else if c == '"' || c == '\'' {
+            let (units, end) = quoted(&chars, i)?;
+            i = end;
+            if c == '"' {
+                tokens.push(Token::String(String::from_utf16_lossy(&units)));
+            } else if units.len() == 1 {
+                tokens.push(Token::Char(units[0]));
+            } else {
+                return Err("a character literal must hold exactly one character".to_string());
+            }
+        } else {
+            let op = OPERATORS
+                .iter()
+                .find(|op| op.chars().eq(chars[i..].iter().copied().take(op.len())))
+                .ok_or_else(|| format!("unexpected character '{}'", c))?;
+            tokens.push(Token::Op(op));
+            i += op.len();
+        }
+    }
+    Ok(tokens)
+}
+
+// Reads the string or character literal starting with the quote at chars[start]. Returns its
+// UTF-16 code units (as Java sees them) and the index just past the closing quote.
+fn quoted(chars: &[char], start: usize) -> Result<(Vec<u16>, usize), String> {
+    let quote = chars[start];
+    let mut units = vec![];
+    let mut i = start + 1;
+    loop {
+        let c = *chars.get(i).ok_or("unterminated literal")?;
+        i += 1;
+        if c == quote {
+            return Ok((units, i));
+        }
+        if c != '\\' {
+            let mut buf = [0; 2];
+            units.extend_from_slice(c.encode_utf16(&mut buf));
+            continue;
+        }
+        let escaped = *chars.get(i).ok_or("unterminated literal")?;
+        i += 1;
+        let unit = match escaped {
+            'n' => '\n' as u16,
+            't' => '\t' as u16,
+            'r' => '\r' as u16,
+            'b' => 8,
+            'f' => 12,
+            '0' => 0,
+            '\\' | '\'' | '"' => escaped as u16,
+            'u' => {
+                let hex: String = chars.iter().skip(i).take(4).collect();
+                i += 4;
+                match u16::from_str_radix(&hex, 16) {
+                    Ok(unit) if hex.len() == 4 => unit,
+                    _ => return Err(format!("invalid escape '\\u{}'", hex)),
+                }
+            }
+            _ => return Err(format!("invalid escape '\\{}'", escaped)),
+        };
+        units.push(unit);
+    }
+}
+
+fn describe(token: &Token) -> String {
+    match token {
+        Token::Number(text) | Token::Ident(text) => format!("'{}'", text),
+        Token::Op(op) => format!("'{}'", op),
+        Token::Char(_) => "character literal".to_string(),
+        Token::String(_) => "string literal".to_string(),
+    }
+}
+
+// Higher binds tighter
+fn precedence(op: BinaryOp) -> u8 {
+    match op {
+        BinaryOp::Or => 1,
+        BinaryOp::And => 2,
+        BinaryOp::Eq | BinaryOp::Ne => 3,
+        BinaryOp::Lt | BinaryOp::Le | BinaryOp::Gt | BinaryOp::Ge => 4,
+        BinaryOp::Add | BinaryOp::Sub => 5,
+        BinaryOp::Mul | BinaryOp::Div | BinaryOp::Rem => 6,
+    }
+}
+
+fn binary_op(op: &str) -> Option<BinaryOp> {
+    let op = match op {
+        "||" => BinaryOp::Or,
+        "&&" => BinaryOp::And,
+        "==" => BinaryOp::Eq,
+        "!=" => BinaryOp::Ne,
+        "<" => BinaryOp::Lt,
+        "<=" => BinaryOp::Le,
+        ">" => BinaryOp::Gt,
+        ">=" => BinaryOp::Ge,
+        "+" => BinaryOp::Add,
+        "-" => BinaryOp::Sub,
+        "*" => BinaryOp::Mul,
+        "/" => BinaryOp::Div,
+        "%" => BinaryOp::Rem,
+        _ => return None,
+    };
+    Some(op)
+}
+
+struct Parser {
+    tokens: Vec<Token>,
+    pos: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos)
+    }
+
+    fn next(&mut self) -> Result<Token, String> {
+        let token = self
+            .tokens
+            .get(self.pos)
+            .cloned()
+            .ok_or("unexpected end of expression")?;
+        self.pos += 1;
+        Ok(token)
+    }
+
+    fn eat(&mut self, op: &str) -> bool {
+        if self.peek() == Some(&Token::Op(op_str(op))) {
+            self.pos += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    // Binary operators are left associative, so 'a - b - c' is '(a - b) - c'
+    fn expr(&mut self, min_precedence: u8) -> Result<Expr, String> {
+        let mut lhs = self.unary()?;
+        while let Some(Token::Op(op)) = self.peek() {
+            let op = match binary_op(op) {
+                Some(op) if precedence(op) >= min_precedence => op,
+                _ => break,
+            };
+            self.pos += 1;
+            let rhs = self.expr(precedence(op) + 1)?;
+            lhs = Expr::Binary(op, Box::new(lhs), Box::new(rhs));
+        }
+        Ok(lhs)
+    }
+
+    fn unary(&mut self) -> Result<Expr, String> {
+        if self.eat("!") {
+            return Ok(Expr::Unary(UnaryOp::Not, Box::new(self.unary()?)));
+        }
+        if self.eat("-") {
+            // Folded into the literal, since -2147483648 is a valid int but 2147483648 isn't
+            if let Some(Token::Number(text)) = self.peek() {
+                let literal = number(text, true)?;
+                self.pos += 1;
+                return self.postfix(Expr::Literal(literal));
+            }
+            return Ok(Expr::Unary(UnaryOp::Neg, Box::new(self.unary()?)));
+        }
+        let primary = self.primary()?;
+        self.postfix(primary)
+    }
+
+    fn postfix(&mut self, mut expr: Expr) -> Result<Expr, String> {
+        loop {
+            if self.eat("[") {
+                let index = self.expr(0)?;
+                if !self.eat("]") {
+                    return Err("missing ']'".to_string());
+                }
+                expr = Expr::Index(Box::new(expr), Box::new(index));
+            } else if self.eat(".") {
+                let name = match self.next()? {
+                    Token::Ident(name) => name,
+                    token => {
+                        return Err(format!(
+                            "expected a name after '.', not {}",
+                            describe(&token)
+                        ))
+                    }
+                };
+                expr = match expr {
+                    Expr::Name(mut names) => {
+                        names.push(name);
+                        Expr::Name(names)
+                    }
+                    expr => Expr::Field(Box::new(expr), name),
+                };
+            } else if self.peek() == Some(&Token::Op("(")) {
+                // The last part of the name before the brackets is the method
+                let (object, method) = match expr {
+                    Expr::Name(mut names) if names.len() > 1 => {
+                        let method = names.pop().unwrap_or_default();
+                        (Expr::Name(names), method)
+                    }
+                    Expr::Field(object, method) => (*object, method),
+                    _ => return Err("only methods of an object can be called".to_string()),
+                };
+                self.pos += 1;
+                let mut args = vec![];
+                if !self.eat(")") {
+                    loop {
+                        args.push(self.expr(0)?);
+                        if self.eat(")") {
+                            break;
+                        }
+                        if !self.eat(",") {
+                            return Err("missing ')'".to_string());
+                        }
+                    }
+                }
+                expr = Expr::Call(Box::new(object), method, args);
+            } else {
+                return Ok(expr);
+            }
+        }
+    }
+
+    fn primary(&mut self) -> Result<Expr, String> {
+        let expr = match self.next()? {
+            Token::Number(text) => Expr::Literal(number(&text, false)?),
+            Token::Char(c) => Expr::Literal(Literal::Char(c)),
+            Token::String(s) => Expr::Literal(Literal::String(s)),
+            Token::Ident(name) => match name.as_str() {
+                "true" => Expr::Literal(Literal::Boolean(true)),
+                "false" => Expr::Literal(Literal::Boolean(false)),
+                "null" => Expr::Literal(Literal::Null),
+                _ => Expr::Name(vec![name]),
+            },
+            Token::Op("(") => {
+                let expr = self.expr(0)?;
+                if !self.eat(")") {
+                    return Err("missing ')'".to_string());
+                }
+                expr
+            }
+            token => return Err(format!("unexpected {}", describe(&token))),
+        };
+        Ok(expr)
+    }
+}
+
+// The &'static str for an operator, so tokens can be compared
+fn op_str(op: &str) -> &'static str {
+    OPERATORS.iter().find(|&&o| o == op).copied().unwrap_or("")
+}
+
+// Parses a numeric literal the way javac would, e.g. 10, 0x1F, 10L, 1_000, 1.5f, 1e3
+fn number(text: &str, negative: bool) -> Result<Literal, String> {
+    let invalid = || format!("invalid number '{}'", text);
+    let digits = text.replace('_', "");
+    let lower = digits.to_ascii_lowercase();
+    let hex = lower.starts_with("0x");
+    let is_float =
+        !hex && (lower.contains('.') || lower.contains('e') || lower.ends_with(['f', 'd']));
+    if is_float {
+        let (digits, float) = match lower.strip_suffix('f') {
+            Some(digits) => (digits, true),
+            None => (lower.strip_suffix('d').unwrap_or(&lower), false),
+        };
+        let value: f64 = digits.parse().map_err(|_| invalid())?;
+        let value = if negative { -value } else { value };
+        return Ok(if float {
+            Literal::Float(value as f32)
+        } else {
+            Literal::Double(value)
+        });
+    }
+
+    let (digits, long) = match lower.strip_suffix('l') {
+        Some(digits) => (digits, true),
+        None => (lower.as_str(), false),
+    };
+    let (digits, radix) = if hex {
+        (&digits[2..], 16)
+    } else if digits.len() > 1 && digits.starts_with('0') {
+        (&digits[1..], 8)
+    } else {
+        (digits, 10)
+    };
+    let value = u64::from_str_radix(digits, radix).map_err(|_| invalid())?;
+    // Hex and octal literals can use every bit, e.g. 0xFFFFFFFF is -1. Decimal ones can only go
+    // one past the maximum, and then only when negated.
+    let (max_bits, max_decimal) = if long {
+        (u64::MAX, i64::MAX as u64)
+    } else {
+        (u64::from(u32::MAX), i32::MAX as u64)
+    };
+    let too_large = if radix == 10 {
+        value > max_decimal + u64::from(negative)
+    } else {
+        value > max_bits
+    };
+    if too_large {
+        return Err(format!("number too large: '{}'", text));
+    }
+    Ok(if long {
+        let value = value as i64;
+        Literal::Long(if negative {
+            value.wrapping_neg()
+        } else {
+            value
+        })
+    } else {
+        let value = value as u32 as i32;
+        Literal::Int(if negative {
+            value.wrapping_neg()
+        } else {
+            value
+        })
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{BinaryOp, Expr, Literal, UnaryOp};
+
+    fn parse(s: &str) -> Expr {
+        Expr::parse(s).unwrap()
+    }
+
+    fn name(s: &str) -> Box<Expr> {
+        Box::new(Expr::Name(s.split('.').map(String::from).collect()))
+    }
+
+    fn int(i: i32) -> Box<Expr> {
+        Box::new(Expr::Literal(Literal::Int(i)))
+    }
+
+    #[test]
+    fn literals() {
+        assert_eq!(parse("42"), Expr::Literal(Literal::Int(42)));
+        assert_eq!(parse("-2147483648"), Expr::Literal(Literal::Int(i32::MIN)));
+        assert_eq!(parse("0xFFFFFFFF"), Expr::Literal(Literal::Int(-1)));
+        assert_eq!(parse("010"), Expr::Literal(Literal::Int(8)));
+        assert_eq!(parse("1_000L"), Expr::Literal(Literal::Long(1000)));
+        assert_eq!(
+            parse("-9223372036854775808L"),
+            Expr::Literal(Literal::Long(i64::MIN))
+        );
+        assert_eq!(parse("1.5f"), Expr::Literal(Literal::Float(1.5)));
+        assert_eq!(parse("1e-3"), Expr::Literal(Literal::Double(0.001)));
+        assert_eq!(parse(".5"), Expr::Literal(Literal::Double(0.5)));
+        assert_eq!(parse("'x'"), Expr::Literal(Literal::Char(u16::from(b'x'))));
+        assert_eq!(parse("'\\u00e9'"), Expr::Literal(Literal::Char(0xe9)));
+        assert_eq!(
+            parse(r#""a\"b\n""#),
+            Expr::Literal(Literal::String("a\"b\n".to_string()))
+        );
+        assert_eq!(parse("true"), Expr::Literal(Literal::Boolean(true)));
+        assert_eq!(parse("null"), Expr::Literal(Literal::Null));
+    }
+
+    #[test]
+    fn names() {
+        assert_eq!(parse("count"), *name("count"));
+        assert_eq!(
+            parse("java.lang.Integer.MAX_VALUE"),
+            *name("java.lang.Integer.MAX_VALUE")
+        );
+        assert_eq!(parse("$x_1"), *name("$x_1"));
+        assert_eq!(parse("(a).b"), *name("a.b"));
+        assert_eq!(
+            parse("(a + b).c"),
+            Expr::Field(
+                Box::new(Expr::Binary(BinaryOp::Add, name("a"), name("b"))),
+                "c".to_string()
+            )
+        );
+    }
+
+    #[test]
+    fn precedence() {
+        assert_eq!(
+            parse("a + 2 * 3 > 100"),
+            Expr::Binary(
+                BinaryOp::Gt,
+                Box::new(Expr::Binary(
+                    BinaryOp::Add,
+                    name("a"),
+                    Box::new(Expr::Binary(BinaryOp::Mul, int(2), int(3)))
+                )),
+                int(100)
+            )
+        );
+        assert_eq!(
+            parse("a || b && !c"),
+            Expr::Binary(
+                BinaryOp::Or,
+                name("a"),
+                Box::new(Expr::Binary(
+                    BinaryOp::And,
+                    name("b"),
+                    Box::new(Expr::Unary(UnaryOp::Not, name("c")))
+                ))
+            )
+        );
+        assert_eq!(
+            parse("a - b - c"),
+            Expr::Binary(
+                BinaryOp::Sub,
+                Box::new(Expr::Binary(BinaryOp::Sub, name("a"), name("b"))),
+                name("c")
+            )
+        );
+        assert_eq!(
+            parse("-(a - 1)"),
+            Expr::Unary(
+                UnaryOp::Neg,
+                Box::new(Expr::Binary(BinaryOp::Sub, name("a"), int(1)))
+            )
+        );
+    }
+
+    #[test]
+    fn index_and_call() {
+        assert_eq!(
+            parse("map[\"key\"] > 100"),
+            Expr::Binary(
+                BinaryOp::Gt,
+                Box::new(Expr::Index(
+                    name("map"),
+                    Box::new(Expr::Literal(Literal::String("key".to_string())))
+                )),
+                int(100)
+            )
+        );
+        assert_eq!(
+            parse("this.items.contains(x)"),
+            Expr::Call(name("this.items"), "contains".to_string(), vec![*name("x")])
+        );
+        assert_eq!(
+            parse("list[i + 1].name.size()"),
+            Expr::Call(
+                Box::new(Expr::Field(
+                    Box::new(Expr::Index(
+                        name("list"),
+                        Box::new(Expr::Binary(BinaryOp::Add, name("i"), int(1)))
+                    )),
+                    "name".to_string()
+                )),
+                "size".to_string(),
+                vec![]
+            )
+        );
+        assert_eq!(
+            parse("m.get(a, 2)[0]"),
+            Expr::Index(
+                Box::new(Expr::Call(
+                    name("m"),
+                    "get".to_string(),
+                    vec![*name("a"), *int(2)]
+                )),
+                int(0)
+            )
+        );
+    }
+
+    #[test]
+    fn errors() {
+        for bad in &[
+            "",
+            "a +",
+            "(a",
+            "a b",
+            "2147483648",
+            "0x1FFFFFFFF",
+            "'ab'",
+            "\"abc",
+            "a.1",
+            "a # b",
+            "a[1",
+            "f(x)",
+            "a.f(x",
+            "a.f(x,)",
+        ] {
+            assert!(Expr::parse(bad).is_err(), "{}", bad);
+        }
+    }
+}
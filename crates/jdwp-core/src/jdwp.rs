@@ -0,0 +1,3436 @@
+use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
+use num_traits::cast::FromPrimitive;
+use std::cell::{Cell, RefCell};
+use std::collections::{BTreeSet, HashMap, VecDeque};
+use std::convert::TryInto;
+use std::io::Result;
+use std::io::{Cursor, ErrorKind, Read, Write};
+use std::net::ToSocketAddrs;
+use std::net::{SocketAddr, TcpStream};
+use std::time::{Duration, Instant};
+
+use crate::model::{DeclaredField, Field, Items, Modifiers, ObjectReference, ThreadReference};
+use crate::model::{JavaVirtualMachine, Method, ReferenceType, StackFrame, TypeComponent, Value};
+use crate::mutf8;
+use crate::names;
+use crate::pattern::ClassPattern;
+use crate::smap::{self, Smap};
+use crate::snapshot::{Histogram, HistogramEntry};
+use crate::thread_filter::ThreadFilter;
+
+pub use ddm::{AndroidProcess, DdmChunk};
+pub use event::{
+    Event, EventKind, MethodTrace, MethodTraceEvent, MethodTraceKind, Modifier, SuspendPolicy,
+};
+pub use fetch::{FetchLimits, Fetched};
+pub use group::BreakpointGroup;
+pub use handshake::AttachOptions;
+pub use hooks::{Action, BreakpointEvent, EventHooks, ExceptionEvent, HookedEvent};
+pub use invoke::InvokePolicy;
+pub use memory::{HeapInfo, MemoryPool, MemoryUsage};
+pub use monitor::{ContentionKind, ContentionProfile, ContentionProfiler, ContentionRecord};
+pub use output::{OutputCapture, StandardStream};
+pub use pins::{CollectionPin, PinnedObject};
+pub use proxy::JdwpProxy;
+pub use queue::OverflowPolicy;
+pub use refresh::StaleFramePolicy;
+pub use sampler::InstanceSampler;
+pub use session::{
+    BreakpointId, BreakpointSpec, Session, SessionEvent, WatchId, WatchKind, WatchSpec,
+};
+pub use stall::{ProgressField, StallCapture, StallMonitor, StallReason, StallTriggers};
+pub use stats::{CommandStats, ConnectionStats, SlowCommand};
+pub use step::{CallTarget, StepDepth, StepFilters, StepSize, TargetStep};
+pub use supervisor::{StopSignal, Supervisor, TaskOutcome, TaskReport};
+pub use suspension::{LongSuspension, SuspendGuard, SuspensionStats};
+
+use bytecode::ConstantPool;
+use refresh::FrameRef;
+
+pub struct JdwpConnection {
+    stream: RefCell<TcpStream>, // TODO wrap in buffered stream?
+    // Which of the addresses the target's name resolved to we attached at
+    peer_addr: SocketAddr,
+    next_id: Cell<u32>,
+    field_id_size: u8,
+    method_id_size: u8,
+    object_id_size: u8,
+    reference_type_id_size: u8,
+    frame_id_size: u8,
+    version: JdwpVersion,
+    vm_name: String,
+    vm_version: String,
+    // None if the target doesn't say (JDWP 1.4 and later all do)
+    capabilities: Option<virtual_machine::CapabilitiesNewReply>,
+    // Events which arrived from the target while we were waiting for the reply to a command.
+    // They are handed out by next_event().
+    events: RefCell<queue::EventQueue>,
+    // Likewise for DDM chunks from Android VMs, see take_ddm_chunks()
+    ddm_chunks: RefCell<VecDeque<DdmChunk>>,
+    // Set once the IDs we have mean nothing any more. Every handle (thread, class, frame, ...)
+    // shares its connection, so checking here catches all of them.
+    disconnected: Cell<Option<Disconnect>>,
+    // Frame IDs are only valid until their thread is resumed. Every resume moves the connection
+    // on to a new epoch, and we remember the epoch in which each thread (or the whole VM) was last
+    // resumed, so that a frame can tell whether it predates that.
+    epoch: Cell<u64>,
+    vm_resumed: Cell<u64>,
+    threads_resumed: RefCell<HashMap<u64, u64>>,
+    // See connection_stats()
+    command_tracker: RefCell<stats::CommandTracker>,
+    // See set_invoke_policy()
+    invoke_policy: RefCell<InvokePolicy>,
+    // Invocations whose replies haven't been handed out yet, by packet ID. Their replies can
+    // arrive while we're waiting for the replies to other commands.
+    invokes: RefCell<HashMap<u32, invoke::PendingInvoke>>,
+    // Objects we've kept from being collected, see pins.rs
+    pins: RefCell<pins::PinTable>,
+    // See suspension_stats()
+    suspensions: RefCell<suspension::SuspensionTracker>,
+    // See set_fetch_limits()
+    fetch_limits: Cell<fetch::FetchLimits>,
+    // Both found the first time they're needed, see fetch.rs
+    string_layout: Cell<Option<fetch::StringLayout>>,
+    utf16_big_endian: Cell<Option<bool>>,
+    // How values of each class are rendered, by class ID, see session.rs
+    rendered_classes: RefCell<HashMap<u64, session::RenderedClass>>,
+    // See set_audit_log()
+    audit_log: RefCell<Option<audit::AuditLog>>,
+    // See set_step_filters()
+    step_filters: RefCell<StepFilters>,
+    // See set_thread_filter()
+    thread_filter: RefCell<ThreadFilter>,
+    // See set_stale_frame_policy()
+    stale_frame_policy: Cell<StaleFramePolicy>,
+    // See set_scan_for_virtual_threads()
+    scan_for_virtual_threads: Cell<bool>,
+    // See AttachOptions::read_only
+    read_only: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Disconnect {
+    Disposed,
+    VmDeath,
+}
+
+impl JdwpConnection {
+    pub fn new<A: ToSocketAddrs>(jvm_debug_addr: A) -> Result<Self> {
+        Self::with_options(jvm_debug_addr, &AttachOptions::default())
+    }
+
+    // Like new(), giving up if attaching takes longer than 'timeout' (see handshake.rs for what
+    // can go wrong)
+    pub fn with_handshake_timeout<A: ToSocketAddrs>(
+        jvm_debug_addr: A,
+        timeout: Duration,
+    ) -> Result<Self> {
+        Self::with_options(
+            jvm_debug_addr,
+            &AttachOptions {
+                timeout,
+                ..Default::default()
+            },
+        )
+    }
+
+    pub fn with_options<A: ToSocketAddrs>(
+        jvm_debug_addr: A,
+        options: &AttachOptions,
+    ) -> Result<Self> {
+        let deadline = Instant::now() + options.timeout;
+        let stream = handshake::connect_before(jvm_debug_addr, deadline, options.timeout)?;
+
+        let mut conn = JdwpConnection {
+            peer_addr: stream.peer_addr()?,
+            stream: RefCell::new(stream),
+            next_id: Cell::new(0),
+            // Unfortunately, the JDWP protocol isn't defined entirely
+            // statically. After establishing a connection, the client must
+            // query the JVM to figure out the size of certain fields that
+            // will be sent/recieved in future messages. Set the sizes to zero,
+            // but fill them in before we hand the struct to the caller.
+            field_id_size: 0,
+            method_id_size: 0,
+            object_id_size: 0,
+            reference_type_id_size: 0,
+            frame_id_size: 0,
+            // Likewise, filled in below
+            version: JdwpVersion::new(0, 0),
+            vm_name: String::new(),
+            vm_version: String::new(),
+            capabilities: None,
+            events: RefCell::new(Default::default()),
+            ddm_chunks: RefCell::new(VecDeque::new()),
+            disconnected: Cell::new(None),
+            epoch: Cell::new(0),
+            vm_resumed: Cell::new(0),
+            threads_resumed: RefCell::new(HashMap::new()),
+            command_tracker: RefCell::new(Default::default()),
+            invoke_policy: RefCell::new(Default::default()),
+            invokes: RefCell::new(HashMap::new()),
+            pins: RefCell::new(Default::default()),
+            suspensions: RefCell::new(Default::default()),
+            fetch_limits: Cell::new(Default::default()),
+            string_layout: Cell::new(None),
+            utf16_big_endian: Cell::new(None),
+            rendered_classes: RefCell::new(HashMap::new()),
+            audit_log: RefCell::new(None),
+            step_filters: RefCell::new(Default::default()),
+            thread_filter: RefCell::new(Default::default()),
+            stale_frame_policy: Cell::new(Default::default()),
+            scan_for_virtual_threads: Cell::new(false),
+            read_only: options.read_only,
+        };
+
+        // Ask for the version first, since the reply doesn't depend on any of the ID sizes, and
+        // everything else is easier to explain once we know what we're talking to
+        let before_deadline = |conn: &JdwpConnection| {
+            let remaining = handshake::time_left(deadline, options.timeout)?;
+            let stream = conn.stream.borrow();
+            stream.set_read_timeout(Some(remaining))?;
+            stream.set_write_timeout(Some(remaining))
+        };
+        before_deadline(&conn)?;
+        let version = virtual_machine::version(&conn).map_err(|e| attach_err(e, options))?;
+        conn.version = JdwpVersion::new(version.jdwp_major, version.jdwp_minor);
+        conn.vm_name = version.vm_name;
+        conn.vm_version = version.vm_version;
+
+        before_deadline(&conn)?;
+        let id_sizes = virtual_machine::id_sizes(&conn).map_err(|e| attach_err(e, options))?;
+        let sizes = [
+            ("field", id_sizes.field_id_size),
+            ("method", id_sizes.method_id_size),
+            ("object", id_sizes.object_id_size),
+            ("reference type", id_sizes.reference_type_id_size),
+            ("frame", id_sizes.frame_id_size),
+        ];
+        // Everything below assumes 8 byte IDs. Some older and 32 bit VMs use 4 byte ones.
+        for &(name, size) in &sizes {
+            if size != 8 {
+                return Err(unsupported_err(&format!(
+                    "{} {} uses {} byte {} IDs, and only 8 byte IDs are supported",
+                    conn.vm_name, conn.vm_version, size, name
+                )));
+            }
+        }
+        conn.field_id_size = id_sizes.field_id_size.try_into().unwrap();
+        conn.method_id_size = id_sizes.method_id_size.try_into().unwrap();
+        conn.object_id_size = id_sizes.object_id_size.try_into().unwrap();
+        conn.reference_type_id_size = id_sizes.reference_type_id_size.try_into().unwrap();
+        conn.frame_id_size = id_sizes.frame_id_size.try_into().unwrap();
+
+        // Android in particular leaves a lot of these out
+        before_deadline(&conn)?;
+        conn.capabilities = match virtual_machine::capabilities_new(&conn) {
+            Ok(capabilities) => Some(capabilities),
+            Err(e) if has_error_code(&e, &[error_code::NOT_IMPLEMENTED]) => None,
+            Err(e) => return Err(attach_err(e, options)),
+        };
+        {
+            let stream = conn.stream.borrow();
+            stream.set_read_timeout(None)?;
+            stream.set_write_timeout(None)?;
+        }
+        Ok(conn)
+    }
+
+    // The version of JDWP the target speaks, which determines which commands it has
+    pub fn version(&self) -> JdwpVersion {
+        self.version
+    }
+
+    // e.g. "OpenJDK 64-Bit Server VM"
+    pub fn vm_name(&self) -> &str {
+        &self.vm_name
+    }
+
+    // e.g. "11.0.2"
+    pub fn vm_version(&self) -> &str {
+        &self.vm_version
+    }
+
+    // The address we attached at, e.g. to say which one worked when a host name has several
+    pub fn peer_addr(&self) -> SocketAddr {
+        self.peer_addr
+    }
+
+    // Whether all_threads() looks for virtual threads on the heap, which it doesn't by default.
+    // VirtualMachine.AllThreads leaves them out unless the agent was started with
+    // includevirtualthreads=y, which is much the cheaper way to have them, but finding them means
+    // listing every VirtualThread instance, which on a busy server can take seconds each time.
+    // Only JDWP 21 and later are scanned, since virtual threads were a preview before that.
+    pub fn set_scan_for_virtual_threads(&self, scan: bool) {
+        self.scan_for_virtual_threads.set(scan)
+    }
+
+    pub fn scan_for_virtual_threads(&self) -> bool {
+        self.scan_for_virtual_threads.get()
+    }
+
+    // Fails with a NOT_IMPLEMENTED error if the target's too old to do 'what', so callers which
+    // can do without it can treat it like the target saying so itself
+    fn require_version(&self, version: JdwpVersion, what: &str) -> Result<()> {
+        if self.version >= version {
+            return Ok(());
+        }
+        Err(std::io::Error::new(
+            std::io::ErrorKind::Unsupported,
+            JdwpError {
+                msg: format!(
+                    "{} needs JDWP {}, but the target ({} {}) only supports JDWP {}",
+                    what, version, self.vm_name, self.vm_version, self.version
+                ),
+                kind: JdwpErrorKind::Unsupported,
+                error_code: Some(error_code::NOT_IMPLEMENTED),
+            },
+        ))
+    }
+
+    // The optional features the target supports, e.g. can_get_instance_info for counting instances
+    pub fn capabilities(&self) -> Option<&virtual_machine::CapabilitiesNewReply> {
+        self.capabilities.as_ref()
+    }
+
+    // Targets which don't say what they can do are given the benefit of the doubt
+    fn has_capability<F>(&self, has: F) -> bool
+    where
+        F: Fn(&virtual_machine::CapabilitiesNewReply) -> bool,
+    {
+        self.capabilities.as_ref().is_none_or(has)
+    }
+
+    // Like require_version(), for the optional features
+    fn require_capability<F>(&self, has: F, what: &str) -> Result<()>
+    where
+        F: Fn(&virtual_machine::CapabilitiesNewReply) -> bool,
+    {
+        match &self.capabilities {
+            Some(capabilities) if !has(capabilities) => Err(std::io::Error::new(
+                std::io::ErrorKind::Unsupported,
+                JdwpError {
+                    msg: format!(
+                        "{} isn't supported by the target ({} {})",
+                        what, self.vm_name, self.vm_version
+                    ),
+                    kind: JdwpErrorKind::Unsupported,
+                    error_code: Some(error_code::NOT_IMPLEMENTED),
+                },
+            )),
+            _ => Ok(()),
+        }
+    }
+
+    // Sends a command and returns the data of its reply, for commands the command set modules
+    // don't have. Events and stray replies which arrive in the meantime are dealt with as usual,
+    // and commands which resume threads are noticed, so frames are still invalidated correctly.
+    pub fn execute_cmd(&self, command_set: u8, command: u8, data: &[u8]) -> Result<Vec<u8>> {
+        let reply = {
+            let stream = &mut *self.stream.borrow_mut();
+            let id = self.send_cmd(stream, command_set, command, data)?;
+            self.read_reply(stream, id)
+                .map_err(|e| with_command(e, command_set, command, data))
+        };
+        self.pay_owed()?;
+        reply
+    }
+
+    // Like execute_cmd(), for several commands at once. Up to PIPELINE_DEPTH commands are sent
+    // before waiting for any of the replies, which saves a round trip per command. The target
+    // handles them in order, so this is no different from sending them one at a time, except
+    // that all of them are sent even if an earlier one fails.
+    fn execute_cmds(&self, cmds: &[(u8, u8, Vec<u8>)]) -> Result<Vec<Vec<u8>>> {
+        let replies = self.execute_pipelined(cmds);
+        self.pay_owed()?;
+        replies
+    }
+
+    fn execute_pipelined(&self, cmds: &[(u8, u8, Vec<u8>)]) -> Result<Vec<Vec<u8>>> {
+        const PIPELINE_DEPTH: usize = 32;
+        let stream = &mut *self.stream.borrow_mut();
+        let mut replies = Vec::with_capacity(cmds.len());
+        for batch in cmds.chunks(PIPELINE_DEPTH) {
+            let mut ids = Vec::with_capacity(batch.len());
+            for (command_set, command, data) in batch {
+                ids.push(self.send_cmd(stream, *command_set, *command, data)?);
+            }
+            // Read every reply before giving up, so that none are left to confuse later commands
+            let mut first_err = None;
+            for (id, (command_set, command, data)) in ids.into_iter().zip(batch) {
+                match self.read_reply(stream, id) {
+                    Ok(reply) => replies.push(reply),
+                    Err(e) if jdwp_error_code(&e).is_some() => {
+                        first_err
+                            .get_or_insert_with(|| with_command(e, *command_set, *command, data));
+                    }
+                    Err(e) => return Err(e),
+                }
+            }
+            if let Some(e) = first_err {
+                return Err(e);
+            }
+        }
+        Ok(replies)
+    }
+
+    // Returns the packet's ID, for read_reply()
+    fn send_cmd(
+        &self,
+        stream: &mut TcpStream,
+        command_set: u8,
+        command: u8,
+        data: &[u8],
+    ) -> Result<u32> {
+        self.check_connected()?;
+        self.audit(command_set, command, data)?;
+        self.note_resume(command_set, command, data);
+        let id = self.next_id.get();
+        self.next_id.set(id + 1);
+
+        let len = data.len() + HEADER_SIZE as usize;
+        let mut packet = Vec::with_capacity(len);
+        packet.write_u32::<BigEndian>(len.try_into().unwrap())?;
+        packet.write_u32::<BigEndian>(id)?;
+        packet.write_u8(0)?; // Flags
+        packet.write_u8(command_set)?;
+        packet.write_u8(command)?;
+        packet.extend_from_slice(data);
+        stream.write_all(&packet)?;
+        self.command_tracker
+            .borrow_mut()
+            .sent(id, command_set, command, len as u64);
+        Ok(id)
+    }
+
+    fn read_reply(&self, stream: &mut TcpStream, id: u32) -> Result<Vec<u8>> {
+        loop {
+            match read_packet(stream)? {
+                Packet::Reply {
+                    id: reply_id,
+                    error_code,
+                    data,
+                } => {
+                    if reply_id != id {
+                        if self.stray_reply(reply_id, error_code, data) {
+                            continue;
+                        }
+                        return Err(protocol_err(&format!(
+                            "Expected reply to packet {}, got reply to {}",
+                            id, reply_id
+                        )));
+                    }
+                    let bytes_received = (data.len() + HEADER_SIZE as usize) as u64;
+                    let slow = self.command_tracker.borrow_mut().replied(
+                        id,
+                        bytes_received,
+                        error_code != 0,
+                    );
+                    if let Some((log, cmd)) = slow {
+                        log(&cmd);
+                    }
+                    if error_code != 0 {
+                        return Err(target_err(error_code));
+                    }
+                    return Ok(data);
+                }
+                Packet::Command {
+                    command_set,
+                    command,
+                    data,
+                    ..
+                } => self.queue_events(command_set, command, &data)?,
+            }
+        }
+    }
+
+    // The values of fields of many objects, with one list of values per (object, fields) pair,
+    // in the same order. Requests for the same object are merged, and all of them are pipelined
+    // (see execute_cmds()), so this takes a handful of round trips rather than one per object.
+    pub fn get_values_bulk(&self, requests: &[(u64, &[u64])]) -> Result<Vec<Vec<Value>>> {
+        let mut objects: Vec<(u64, Vec<u64>)> = vec![];
+        let mut index: HashMap<u64, usize> = HashMap::new();
+        for &(object, fields) in requests {
+            let i = *index.entry(object).or_insert_with(|| {
+                objects.push((object, vec![]));
+                objects.len() - 1
+            });
+            for field in fields {
+                if !objects[i].1.contains(field) {
+                    objects[i].1.push(*field);
+                }
+            }
+        }
+
+        let mut cmds = Vec::with_capacity(objects.len());
+        for (object, fields) in &objects {
+            let mut data = vec![];
+            object.serialize(&mut data)?;
+            fields.as_slice().serialize(&mut data)?;
+            // ObjectReference.GetValues
+            cmds.push((9, 2, data));
+        }
+        let mut values: HashMap<(u64, u64), Value> = HashMap::new();
+        for ((object, fields), reply) in objects.iter().zip(self.execute_cmds(&cmds)?) {
+            let reply = object_reference::GetValuesReply::deserialize(&mut Cursor::new(reply))?;
+            if reply.values.len() != fields.len() {
+                return Err(protocol_err(&format!(
+                    "Asked for {} values, got {}",
+                    fields.len(),
+                    reply.values.len()
+                )));
+            }
+            for (field, value) in fields.iter().zip(reply.values) {
+                values.insert((*object, *field), value);
+            }
+        }
+        Ok(requests
+            .iter()
+            .map(|&(object, fields)| {
+                fields
+                    .iter()
+                    .map(|field| values[&(object, *field)].clone())
+                    .collect()
+            })
+            .collect())
+    }
+
+    // Rather than sending the target IDs from a handle which has outlived its connection, which
+    // at best fails confusingly and at worst refers to something else entirely
+    fn check_connected(&self) -> Result<()> {
+        let why = match self.disconnected.get() {
+            None => return Ok(()),
+            Some(Disconnect::Disposed) => "the connection to it has been disposed",
+            Some(Disconnect::VmDeath) => "it has exited",
+        };
+        Err(std::io::Error::new(
+            std::io::ErrorKind::NotConnected,
+            JdwpError {
+                msg: format!(
+                    "A handle to the target ({} {}) was used after {}",
+                    self.vm_name, self.vm_version, why
+                ),
+                kind: JdwpErrorKind::StaleHandle,
+                error_code: None,
+            },
+        ))
+    }
+
+    // Resumes are spotted here, rather than wherever they're sent from, so that none are missed.
+    // Resuming a thread doesn't always make it run (it may have been suspended more than once),
+    // but assuming it does only costs having to fetch frames again.
+    fn note_resume(&self, command_set: u8, command: u8, data: &[u8]) {
+        // VirtualMachine.Resume and ThreadReference.Resume
+        const VM_RESUME: (u8, u8) = (1, 9);
+        const THREAD_RESUME: (u8, u8) = (11, 3);
+        // ClassType.InvokeMethod, ClassType.NewInstance and ObjectReference.InvokeMethod run the
+        // thread while the method does, after which the target has thrown away its frames just as
+        // if it had been resumed
+        const CLASS_INVOKE: (u8, u8) = (3, 3);
+        const NEW_INSTANCE: (u8, u8) = (3, 4);
+        const OBJECT_INVOKE: (u8, u8) = (9, 6);
+        let epoch = self.epoch.get() + 1;
+        match (command_set, command) {
+            VM_RESUME => self.vm_resumed.set(epoch),
+            CLASS_INVOKE | NEW_INSTANCE | OBJECT_INVOKE => {
+                // The options are last, and the thread comes after the class or object
+                let single_threaded = data.len() >= 4
+                    && i32::from_be_bytes(data[data.len() - 4..].try_into().unwrap())
+                        & invoke_options::INVOKE_SINGLE_THREADED
+                        != 0;
+                if !single_threaded {
+                    self.vm_resumed.set(epoch);
+                }
+                let thread = match data.get(8..16) {
+                    Some(id) => u64::from_be_bytes(id.try_into().unwrap()),
+                    None => return,
+                };
+                self.threads_resumed.borrow_mut().insert(thread, epoch);
+            }
+            THREAD_RESUME => {
+                let thread = match data.get(..8) {
+                    Some(id) => u64::from_be_bytes(id.try_into().unwrap()),
+                    None => return,
+                };
+                self.threads_resumed.borrow_mut().insert(thread, epoch);
+            }
+            _ => return,
+        }
+        self.epoch.set(epoch);
+    }
+
+    // Fails if 'thread' has been resumed since 'epoch'
+    fn check_suspended_since(&self, thread: u64, epoch: u64) -> Result<()> {
+        let thread_resumed = self.threads_resumed.borrow().get(&thread).copied();
+        if self.vm_resumed.get() <= epoch && thread_resumed.unwrap_or(0) <= epoch {
+            return Ok(());
+        }
+        Err(std::io::Error::other(JdwpError {
+            msg: "The thread has been resumed since this frame was fetched, so it no longer \
+                  exists. Suspend the thread and fetch its frames again."
+                .to_string(),
+            kind: JdwpErrorKind::FrameInvalidated,
+            error_code: None,
+        }))
+    }
+
+    // The target can send us commands (events) at any time, including while we are waiting for a
+    // reply. Stash them until someone asks for them.
+    fn queue_events(&self, command_set: u8, command: u8, data: &[u8]) -> Result<()> {
+        if (command_set, command) == (event::COMPOSITE_COMMAND_SET, event::COMPOSITE_COMMAND) {
+            let composite = event::Composite::deserialize(&mut Cursor::new(data))?;
+            if composite
+                .events
+                .iter()
+                .any(|e| e.kind() == EventKind::VmDeath)
+            {
+                self.disconnected.set(Some(Disconnect::VmDeath));
+            }
+            self.events.borrow_mut().push(composite, data)?;
+        } else if (command_set, command) == (ddm::DDM_COMMAND_SET, ddm::DDM_COMMAND) {
+            self.queue_ddm_chunks(data)?;
+        }
+        // TODO There are no other commands sent by the VM in the spec, but other VMs may have
+        // their own
+        Ok(())
+    }
+}
+
+// The version of the JDWP protocol a VM speaks. Up to Java 8 this was 1.x for Java x, and since
+// then it's been the same as the Java version, e.g. 11.0.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct JdwpVersion {
+    pub major: i32,
+    pub minor: i32,
+}
+
+impl JdwpVersion {
+    pub const fn new(major: i32, minor: i32) -> Self {
+        JdwpVersion { major, minor }
+    }
+}
+
+impl fmt::Display for JdwpVersion {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}.{}", self.major, self.minor)
+    }
+}
+
+// The first versions with the commands and events we use which haven't always been there:
+// InstanceCounts, monitor events, method exit return values and source name filters
+const JDWP_1_6: JdwpVersion = JdwpVersion::new(1, 6);
+// Modules
+const JDWP_9: JdwpVersion = JdwpVersion::new(9, 0);
+// Virtual threads, which were a preview feature until Java 21
+const JDWP_19: JdwpVersion = JdwpVersion::new(19, 0);
+const JDWP_21: JdwpVersion = JdwpVersion::new(21, 0);
+
+pub const HEADER_SIZE: u32 = 11;
+pub const REPLY_FLAG: u8 = 0x80;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum Packet {
+    Reply {
+        id: u32,
+        error_code: u16,
+        data: Vec<u8>,
+    },
+    // From us, or (as composite events) from the target
+    Command {
+        id: u32,
+        command_set: u8,
+        command: u8,
+        data: Vec<u8>,
+    },
+}
+
+pub fn read_packet<R: Read>(stream: &mut R) -> Result<Packet> {
+    let len = stream.read_u32::<BigEndian>()?;
+    let id = stream.read_u32::<BigEndian>()?;
+    let flags = stream.read_u8()?;
+    let header_rest = stream.read_u16::<BigEndian>()?;
+    let data_len = len
+        .checked_sub(HEADER_SIZE)
+        .ok_or_else(|| protocol_err(&format!("Invalid packet length {}", len)))?;
+    let mut data = vec![0; data_len as usize];
+    stream.read_exact(&mut data)?;
+
+    // The last two bytes of the header are either an error code (for replies) or the command set
+    // and command (for commands).
+    if flags & REPLY_FLAG != 0 {
+        Ok(Packet::Reply {
+            id,
+            error_code: header_rest,
+            data,
+        })
+    } else {
+        let [command_set, command] = header_rest.to_be_bytes();
+        Ok(Packet::Command {
+            id,
+            command_set,
+            command,
+            data,
+        })
+    }
+}
+
+// A command sent while attaching which didn't get its reply before the deadline says so, rather
+// than with whatever the socket's error was
+fn attach_err(e: std::io::Error, options: &AttachOptions) -> std::io::Error {
+    match e.kind() {
+        ErrorKind::WouldBlock | ErrorKind::TimedOut => handshake::timed_out(options.timeout),
+        _ => e,
+    }
+}
+
+// Waits for the start of a packet without consuming anything, so that we never time out half way
+// through reading one. False if the deadline passed first.
+fn wait_for_packet(stream: &mut TcpStream, deadline: Instant) -> Result<bool> {
+    let now = Instant::now();
+    if now >= deadline {
+        return Ok(false);
+    }
+    stream.set_read_timeout(Some(deadline - now))?;
+    let ready = stream.peek(&mut [0u8; 1]);
+    stream.set_read_timeout(None)?;
+    match ready {
+        Ok(_) => Ok(true),
+        Err(e) if e.kind() == ErrorKind::WouldBlock || e.kind() == ErrorKind::TimedOut => Ok(false),
+        Err(e) => Err(e),
+    }
+}
+
+// TODO this struct gets used in a lot of type parameters. Maybe name it something shorter? But then
+// it would be less consistent.
+pub struct JdwpJavaVirtualMachine {
+    conn: Rc<JdwpConnection>,
+}
+
+impl JdwpJavaVirtualMachine {
+    pub fn new(conn: JdwpConnection) -> Self {
+        JdwpJavaVirtualMachine {
+            conn: Rc::new(conn),
+        }
+    }
+
+    pub fn version(&self) -> JdwpVersion {
+        self.conn.version()
+    }
+
+    // For the low-level API (see the protocol module), alongside the handles this hands out
+    pub fn connection(&self) -> &JdwpConnection {
+        &self.conn
+    }
+
+    // See JdwpConnection::set_scan_for_virtual_threads()
+    pub fn set_scan_for_virtual_threads(&self, scan: bool) {
+        self.conn.set_scan_for_virtual_threads(scan)
+    }
+
+    // The names of the loaded classes and interfaces, sorted, each once however many loaders
+    // have it. Unlike all_classes() and then name(), which is a round trip per class, this is a
+    // single command, so it's quick enough for completing class names as the user types.
+    pub fn class_names(&self) -> Result<Vec<String>> {
+        let mut names: Vec<String> = virtual_machine::all_classes(self.conn.as_ref())?
+            .classes
+            .into_iter()
+            .filter(|class| class.ref_type_tag != TypeTag::Array as u8)
+            .map(|class| signature_to_name(&class.signature))
+            .collect();
+        names.sort_unstable();
+        names.dedup();
+        Ok(names)
+    }
+
+    // VirtualMachine.AllThreads leaves out virtual threads (unless the agent was started with
+    // includevirtualthreads=y), so find them on the heap instead, if asked to (see
+    // JdwpConnection::set_scan_for_virtual_threads()). Unstarted and finished ones are left out.
+    // There can be hundreds of thousands, so their statuses are asked for all at once rather
+    // than a round trip each.
+    fn virtual_thread_ids(&self) -> Result<Vec<u64>> {
+        let conn = self.conn.as_ref();
+        if !conn.scan_for_virtual_threads()
+            || conn.version() < JDWP_21
+            || !conn.has_capability(|c| c.can_get_instance_info)
+        {
+            return Ok(vec![]);
+        }
+        let mut instances = vec![];
+        let classes = virtual_machine::classes_by_signature(conn, "Ljava/lang/VirtualThread;")?;
+        for class in classes.classes {
+            let reply = reference_type::instances(conn, class.type_id, 0)?;
+            instances.extend(reply.instances.into_iter().map(|i| i.object_id));
+        }
+        // ThreadReference.Status
+        let cmds: Vec<(u8, u8, Vec<u8>)> = instances
+            .iter()
+            .map(|id| (11, 4, id.to_be_bytes().to_vec()))
+            .collect();
+        let collected = [error_code::INVALID_OBJECT, error_code::INVALID_THREAD];
+        let statuses = match conn.execute_cmds(&cmds) {
+            Ok(replies) => replies
+                .into_iter()
+                .map(|reply| {
+                    let reply =
+                        thread_reference::StatusReply::deserialize(&mut Cursor::new(reply))?;
+                    Ok(Some(reply.thread_status))
+                })
+                .collect::<Result<Vec<_>>>()?,
+            // Something was collected, so find out which one by one
+            Err(e) if has_error_code(&e, &collected) => instances
+                .iter()
+                .map(|&id| match thread_reference::status(conn, id) {
+                    Ok(reply) => Ok(Some(reply.thread_status)),
+                    Err(e) if has_error_code(&e, &collected) => Ok(None),
+                    Err(e) => Err(e),
+                })
+                .collect::<Result<Vec<_>>>()?,
+            Err(e) => return Err(e),
+        };
+        Ok(instances
+            .into_iter()
+            .zip(statuses)
+            .filter(|(_, status)| status.is_some_and(|s| s != thread_status::ZOMBIE))
+            .map(|(id, _)| id)
+            .collect())
+    }
+
+    // Tell the target we're done with it. Any event requests we made are cancelled, threads we
+    // suspended are resumed and objects we pinned can be collected again. The socket itself is
+    // closed once the last handle using it is dropped.
+    pub fn dispose(self) -> Result<()> {
+        if self.conn.check_connected().is_ok() {
+            self.conn.enable_all_collection();
+        }
+        let disposed = virtual_machine::dispose(self.conn.as_ref());
+        self.conn.disconnected.set(Some(Disconnect::Disposed));
+        disposed?;
+        Ok(())
+    }
+}
+
+impl JavaVirtualMachine for JdwpJavaVirtualMachine {
+    type Field = JdwpField;
+    type Location = JdwpLocation;
+    type Method = JdwpMethod;
+    type ObjectReference = JdwpObjectReference;
+    type ReferenceType = JdwpReferenceType;
+    type ThreadReference = JdwpThreadReference;
+    type StackFrame = JdwpStackFrame;
+
+    // Including virtual threads, which can easily outnumber platform threads a thousand to one,
+    // if the agent includes them or the connection scans for them
+    fn all_threads(&self) -> Items<'_, JdwpThreadReference> {
+        let threads = virtual_machine::all_threads(self.conn.as_ref()).and_then(|reply| {
+            let mut threads = reply.threads;
+            threads.extend(self.virtual_thread_ids()?);
+            threads.sort_unstable();
+            threads.dedup();
+            Ok(threads)
+        });
+        let threads = match threads {
+            Ok(threads) => threads,
+            Err(e) => return failed(e),
+        };
+        Box::new(threads.into_iter().map(move |thread_id| {
+            Ok(JdwpThreadReference {
+                conn: self.conn.clone(),
+                thread_id,
+            })
+        }))
+    }
+
+    fn can_be_modified(&self) -> bool {
+        // TODO is there something we should check on the target, or is this true for all live debugging
+        true
+    }
+
+    fn suspend(&self) -> Result<()> {
+        self.conn.suspend_vm()
+    }
+
+    fn resume(&self) -> Result<()> {
+        self.conn.resume_vm()
+    }
+
+    fn classes_by_name(&self, name: &str) -> Result<Vec<JdwpReferenceType>> {
+        let signature = name_to_signature(name);
+        let classes = virtual_machine::classes_by_signature(self.conn.as_ref(), &signature)?
+            .classes
+            .iter()
+            .map(|class| JdwpReferenceType {
+                conn: self.conn.clone(),
+                class_id: class.type_id,
+            })
+            .collect();
+        Ok(classes)
+    }
+
+    fn all_classes(&self) -> Items<'_, JdwpReferenceType> {
+        let classes = match virtual_machine::all_classes(self.conn.as_ref()) {
+            Ok(reply) => reply.classes,
+            Err(e) => return failed(e),
+        };
+        Box::new(classes.into_iter().map(move |class| {
+            Ok(JdwpReferenceType {
+                conn: self.conn.clone(),
+                class_id: class.type_id,
+            })
+        }))
+    }
+
+    // Checking that the object exists would cost a round trip, so an ID the VM doesn't know (or
+    // an object it has since collected) only shows up as an error when the handle is used
+    fn object(&self, id: u64) -> Result<JdwpObjectReference> {
+        Ok(JdwpObjectReference {
+            conn: self.conn.clone(),
+            object_id: id,
+        })
+    }
+
+    fn array_values(&self, array: u64, first: usize, count: usize) -> Result<Vec<Value>> {
+        let conn = self.conn.as_ref();
+        let length = array_reference::length(conn, array)?.array_length as usize;
+        let first = first.min(length);
+        let count = count.min(length - first);
+        if count == 0 {
+            return Ok(vec![]);
+        }
+        Ok(
+            array_reference::get_values(conn, array, first as i32, count as i32)?
+                .values
+                .0,
+        )
+    }
+
+    fn class_histogram(&self) -> Result<Histogram> {
+        let conn = self.conn.as_ref();
+        conn.require_version(JDWP_1_6, "Counting instances")?;
+        conn.require_capability(|c| c.can_get_instance_info, "Counting instances")?;
+        let classes = virtual_machine::all_classes(conn)?.classes;
+        let mut entries = vec![];
+        // Asking for every class at once makes for a very large reply on big VMs
+        for chunk in classes.chunks(1024) {
+            let ids: Vec<u64> = chunk.iter().map(|c| c.type_id).collect();
+            let counts = virtual_machine::instance_counts(conn, &ids)?.counts;
+            for (class, count) in chunk.iter().zip(counts) {
+                if count > 0 {
+                    entries.push(HistogramEntry {
+                        class_name: signature_to_name(&class.signature),
+                        instances: count as u64,
+                        // TODO JDWP can't tell us the size of objects
+                        shallow_bytes: None,
+                    });
+                }
+            }
+        }
+        Ok(Histogram::new(entries))
+    }
+
+    fn locations_of_line(
+        &self,
+        class_pattern: &ClassPattern,
+        source_file: &str,
+        line: u32,
+    ) -> Result<Vec<JdwpLocation>> {
+        let conn = self.conn.as_ref();
+        let mut locations = vec![];
+        // Only classes that have already been loaded by the target will be found. Callers that
+        // want to set a breakpoint in a class that hasn't been loaded yet need to wait for it to
+        // be prepared and try again (Session does this for its breakpoints).
+        for class in virtual_machine::all_classes(conn)?.classes {
+            let type_tag = match FromPrimitive::from_u8(class.ref_type_tag) {
+                Some(TypeTag::Array) | None => continue,
+                Some(tag) => tag,
+            };
+            if !searched_for_lines(class_pattern, &signature_to_name(&class.signature)) {
+                continue;
+            }
+            locations.extend(locations_of_line_in_class(
+                &self.conn,
+                class.type_id,
+                type_tag,
+                source_file,
+                line,
+            )?);
+        }
+        Ok(locations)
+    }
+}
+
+// Nested classes share their outer class's source file, so a pattern naming the outer class
+// should find them too
+fn searched_for_lines(class_pattern: &ClassPattern, name: &str) -> bool {
+    let outer_name = name.split('$').next().unwrap_or(name);
+    class_pattern.matches(name) || class_pattern.matches(outer_name)
+}
+
+// The part of locations_of_line() for a single class
+fn locations_of_line_in_class(
+    conn: &Rc<JdwpConnection>,
+    class_id: u64,
+    type_tag: TypeTag,
+    source_file: &str,
+    line: u32,
+) -> Result<Vec<JdwpLocation>> {
+    let mut locations = vec![];
+    let class_lines = lines_in_class(conn, class_id, source_file, line)?;
+    if class_lines.is_empty() {
+        return Ok(locations);
+    }
+
+    for m in reference_type::methods(conn, class_id)?.methods {
+        let table = match method::line_table(conn, class_id, m.method_id) {
+            Ok(t) => t,
+            Err(e)
+                if has_error_code(
+                    &e,
+                    &[error_code::NATIVE_METHOD, error_code::ABSENT_INFORMATION],
+                ) =>
+            {
+                continue
+            }
+            Err(e) => return Err(e),
+        };
+        // Like JDI, return only a single location per line for each method: the one with the
+        // lowest code index.
+        for &class_line in &class_lines {
+            let code_index = table
+                .lines
+                .iter()
+                .filter(|entry| entry.line_number == class_line)
+                .map(|entry| entry.line_code_index)
+                .min();
+            if let Some(code_index) = code_index {
+                locations.push(JdwpLocation {
+                    conn: conn.clone(),
+                    location: Location {
+                        type_tag,
+                        class_id,
+                        method_id: m.method_id,
+                        location_idx: code_index as u64,
+                    },
+                });
+            }
+        }
+    }
+    Ok(locations)
+}
+
+// Figure out which lines of the class's line tables correspond to the given line of the given
+// source file. For plain Java classes this is either the line itself or nothing, depending on
+// whether the class was compiled from that file. Classes generated from other languages may carry
+// an SMAP in their SourceDebugExtension which maps several input files onto the class.
+fn lines_in_class(
+    conn: &JdwpConnection,
+    class_id: u64,
+    source_file: &str,
+    line: u32,
+) -> Result<Vec<u32>> {
+    let ignorable = [error_code::ABSENT_INFORMATION, error_code::NOT_IMPLEMENTED];
+    let mut lines = vec![];
+    match reference_type::source_file(conn, class_id) {
+        Ok(reply) if reply.source_file == smap::base_name(source_file) => lines.push(line),
+        Ok(_) => {}
+        Err(e) if has_error_code(&e, &ignorable) => {}
+        Err(e) => return Err(e),
+    }
+    let extension = conn
+        .require_capability(|c| c.can_get_source_debug_extension, "SourceDebugExtension")
+        .and_then(|_| reference_type::source_debug_extension(conn, class_id));
+    match extension {
+        Ok(reply) => {
+            if let Some(smap) = Smap::parse(&reply.extension) {
+                for l in smap.output_lines(source_file, line) {
+                    if !lines.contains(&l) {
+                        lines.push(l);
+                    }
+                }
+            }
+        }
+        Err(e) if has_error_code(&e, &ignorable) => {}
+        Err(e) => return Err(e),
+    }
+    Ok(lines)
+}
+
+// JDWP names types by their signatures, e.g. [Ljava/lang/String; for java.lang.String[]
+fn signature_to_name(signature: &str) -> String {
+    names::source_name(signature)
+}
+
+fn name_to_signature(name: &str) -> String {
+    names::signature(name)
+}
+
+// Any object in the target. The VM may collect it once nothing in the target refers to it, after
+// which it's reported as an invalid object.
+pub struct JdwpObjectReference {
+    conn: Rc<JdwpConnection>,
+    object_id: u64,
+}
+
+impl ObjectReference<JdwpJavaVirtualMachine> for JdwpObjectReference {
+    fn unique_id(&self) -> Result<u64> {
+        Ok(self.object_id)
+    }
+
+    fn reference_type(&self) -> Result<Box<dyn ReferenceType<JdwpJavaVirtualMachine>>> {
+        let reply = object_reference::reference_type(self.conn.as_ref(), self.object_id)?;
+        Ok(Box::new(JdwpReferenceType {
+            conn: self.conn.clone(),
+            class_id: reply.type_id,
+        }))
+    }
+
+    fn get_value(&self, field: &JdwpField) -> Result<Value> {
+        instance_field_value(self.conn.as_ref(), self.object_id, field)
+    }
+    // HotSpot's agent aborts the whole VM if ReflectedType is given an object which isn't a
+    // class, so check first
+    fn reflected_type(&self) -> Result<Option<JdwpReferenceType>> {
+        let conn = self.conn.as_ref();
+        let class_id = object_reference::reference_type(conn, self.object_id)?.type_id;
+        if reference_type::signature(conn, class_id)?.signature != "Ljava/lang/Class;" {
+            return Ok(None);
+        }
+        let reply = class_object_reference::reflected_type(conn, self.object_id)?;
+        Ok(match reply.type_id {
+            0 => None,
+            class_id => Some(JdwpReferenceType {
+                conn: self.conn.clone(),
+                class_id,
+            }),
+        })
+    }
+}
+
+// For the bulk methods which fail before they've found anything
+fn failed<'a, T: 'a>(e: std::io::Error) -> Items<'a, T> {
+    Box::new(std::iter::once(Err(e)))
+}
+
+fn instance_field_value(conn: &JdwpConnection, object_id: u64, field: &JdwpField) -> Result<Value> {
+    object_reference::get_values(conn, object_id, &[field.field_id])?
+        .values
+        .pop()
+        .ok_or_else(|| protocol_err("GetValues returned no values"))
+}
+
+pub struct JdwpThreadReference {
+    conn: Rc<JdwpConnection>,
+    thread_id: u64, // TODO should have a threadid type? or is this the thread id type?
+}
+
+impl ObjectReference<JdwpJavaVirtualMachine> for JdwpThreadReference {
+    // Does return type need to be a result?
+    fn unique_id(&self) -> Result<u64> {
+        Ok(self.thread_id)
+    }
+
+    fn reference_type(&self) -> Result<Box<dyn ReferenceType<JdwpJavaVirtualMachine>>> {
+        let reply = object_reference::reference_type(self.conn.as_ref(), self.thread_id)?;
+        Ok(Box::new(JdwpReferenceType {
+            conn: self.conn.clone(),
+            class_id: reply.type_id,
+        }) as Box<dyn ReferenceType<JdwpJavaVirtualMachine>>)
+    }
+
+    fn get_value(&self, field: &JdwpField) -> Result<Value> {
+        instance_field_value(self.conn.as_ref(), self.thread_id, field)
+    }
+    fn reflected_type(&self) -> Result<Option<JdwpReferenceType>> {
+        Ok(None)
+    }
+}
+
+impl ThreadReference<JdwpJavaVirtualMachine> for JdwpThreadReference {
+    fn name(&self) -> Result<String> {
+        Ok(thread_reference::name(self.conn.as_ref(), self.thread_id)?.name)
+    }
+
+    fn is_virtual(&self) -> Result<bool> {
+        let conn = self.conn.as_ref();
+        if conn.version() < JDWP_19 {
+            return Ok(false);
+        }
+        Ok(thread_reference::is_virtual(conn, self.thread_id)?.is_virtual)
+    }
+
+    // Fetched FRAMES_PAGE at a time, since callers often only want the top of the stack
+    fn frames(&self) -> Items<'_, JdwpStackFrame> {
+        let epoch = self.conn.epoch.get();
+        let count = match thread_reference::frame_count(self.conn.as_ref(), self.thread_id) {
+            Ok(reply) => reply.frame_count.max(0),
+            Err(e) => return failed(e),
+        };
+        Box::new(JdwpFrames {
+            conn: self.conn.clone(),
+            thread_id: self.thread_id,
+            epoch,
+            count,
+            next: 0,
+            page: VecDeque::new(),
+        })
+    }
+}
+
+const FRAMES_PAGE: i32 = 32;
+
+struct JdwpFrames {
+    conn: Rc<JdwpConnection>,
+    thread_id: u64,
+    epoch: u64,
+    count: i32,
+    // The depth of the next frame to return
+    next: i32,
+    // Frames which have been fetched but not returned yet
+    page: VecDeque<thread_reference::Frame>,
+}
+
+impl Iterator for JdwpFrames {
+    type Item = Result<JdwpStackFrame>;
+
+    fn next(&mut self) -> Option<Result<JdwpStackFrame>> {
+        if self.next >= self.count {
+            return None;
+        }
+        if self.page.is_empty() {
+            let conn = self.conn.as_ref();
+            // If the thread has run since the first page, the rest would be of a different stack
+            let fetched = conn
+                .check_suspended_since(self.thread_id, self.epoch)
+                .and_then(|_| {
+                    let length = FRAMES_PAGE.min(self.count - self.next);
+                    thread_reference::frames(conn, self.thread_id, self.next, length)
+                });
+            match fetched {
+                Ok(reply) if !reply.frames.is_empty() => self.page.extend(reply.frames),
+                Ok(_) => {
+                    self.count = self.next;
+                    return None;
+                }
+                Err(e) => {
+                    self.count = self.next;
+                    return Some(Err(e));
+                }
+            }
+        }
+        let frame = self.page.pop_front()?;
+        let depth = self.next;
+        self.next += 1;
+        Some(Ok(JdwpStackFrame {
+            conn: self.conn.clone(),
+            thread_id: self.thread_id,
+            frame_id: frame.frame_id,
+            epoch: self.epoch,
+            location: frame.location,
+            depth,
+        }))
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let remaining = (self.count - self.next) as usize;
+        (remaining, Some(remaining))
+    }
+}
+
+// Only usable until the thread is resumed, after which anything needing the frame fails with
+// JdwpErrorKind::FrameInvalidated (unless the StaleFramePolicy finds it again). Its location stays
+// valid though.
+pub struct JdwpStackFrame {
+    conn: Rc<JdwpConnection>,
+    thread_id: u64,
+    frame_id: u64, // TODO this should be a frameId type
+    // When the frame was fetched, see JdwpConnection::epoch
+    epoch: u64,
+    location: Location,
+    // 0 for the top of the stack
+    depth: i32,
+}
+
+impl JdwpStackFrame {
+    // Null in static and native methods
+    pub fn this_object(&self) -> Result<Value> {
+        let conn = self.conn.as_ref();
+        conn.with_frame(&self.frame_ref(), |frame_id| {
+            Ok(stack_frame::this_object(conn, self.thread_id, frame_id)?.object_this)
+        })
+    }
+
+    // The local variables (including arguments) in scope at the frame's current location. Fails
+    // with ABSENT_INFORMATION if the class was compiled without them (javac -g).
+    pub fn visible_variables(&self) -> Result<Vec<JdwpLocalVariable>> {
+        let conn = self.conn.as_ref();
+        conn.with_frame(&self.frame_ref(), |_| Ok(()))?;
+        let location = &self.location;
+        let table = method::variable_table(conn, location.class_id, location.method_id)?;
+        Ok(table
+            .slots
+            .into_iter()
+            .filter(|v| {
+                v.code_index <= location.location_idx
+                    && location.location_idx < v.code_index + v.length as u64
+            })
+            .map(|v| JdwpLocalVariable {
+                conn: self.conn.clone(),
+                thread_id: self.thread_id,
+                frame_id: self.frame_id,
+                epoch: self.epoch,
+                depth: self.depth,
+                location: self.location,
+                name: v.name,
+                signature: v.signature,
+                slot: v.slot,
+            })
+            .collect())
+    }
+
+    fn frame_ref(&self) -> FrameRef<'_> {
+        FrameRef {
+            thread_id: self.thread_id,
+            frame_id: self.frame_id,
+            epoch: self.epoch,
+            depth: self.depth,
+            location: &self.location,
+        }
+    }
+}
+
+// A local variable in a particular frame, which like the frame is only usable until its thread is
+// resumed
+pub struct JdwpLocalVariable {
+    conn: Rc<JdwpConnection>,
+    thread_id: u64,
+    frame_id: u64,
+    epoch: u64,
+    // Of the frame, for fetching it again (see StaleFramePolicy)
+    depth: i32,
+    location: Location,
+    name: String,
+    signature: String,
+    slot: i32,
+}
+
+impl JdwpLocalVariable {
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    // The JNI signature of the variable's type, e.g. I or Ljava/lang/String;
+    pub fn signature(&self) -> &str {
+        &self.signature
+    }
+
+    pub fn value(&self) -> Result<Value> {
+        let conn = self.conn.as_ref();
+        let frame = FrameRef {
+            thread_id: self.thread_id,
+            frame_id: self.frame_id,
+            epoch: self.epoch,
+            depth: self.depth,
+            location: &self.location,
+        };
+        let slot = SlotRequest {
+            slot: self.slot,
+            tag: self.signature.bytes().next().unwrap_or(b'L'),
+        };
+        conn.with_frame(&frame, |frame_id| {
+            stack_frame::get_values(conn, self.thread_id, frame_id, &[slot])?
+                .values
+                .pop()
+                .ok_or_else(|| protocol_err("GetValues returned no values"))
+        })
+    }
+}
+
+impl StackFrame<JdwpJavaVirtualMachine> for JdwpStackFrame {
+    fn location(&self) -> Result<JdwpLocation> {
+        Ok(JdwpLocation {
+            conn: self.conn.clone(),
+            location: self.location,
+        })
+    }
+}
+
+pub struct JdwpLocation {
+    conn: Rc<JdwpConnection>,
+    location: Location,
+}
+
+impl crate::model::Location<JdwpJavaVirtualMachine> for JdwpLocation {
+    fn line_number(&self) -> Result<Option<u32>> {
+        let reply = method::line_table(
+            self.conn.as_ref(),
+            self.location.class_id,
+            self.location.method_id,
+        );
+        let reply = match reply {
+            Ok(r) => r,
+            Err(e)
+                if has_error_code(
+                    &e,
+                    &[error_code::NATIVE_METHOD, error_code::ABSENT_INFORMATION],
+                ) =>
+            {
+                return Ok(None)
+            }
+            Err(e) => return Err(e),
+        };
+
+        // The JDWP documentation says that start and end will be -1 for a native method. In reality,
+        // it appears that the lineTable command will return a NATIVE_METHOD error code if the
+        // method is native, handled above. However, we handle this according to the documentation
+        // in case the behavior is not consistent.
+        if reply.start == -1 || reply.end == -1 {
+            return Ok(None);
+        }
+
+        // Some generated methods (e.g. lambda forms) have an empty line table
+        let mut best_line = match reply.lines.first() {
+            Some(entry) => entry.line_number,
+            None => return Ok(None),
+        };
+        for line_entry in reply.lines {
+            // TODO as_ ?? Does that do what we want
+            if line_entry.line_code_index as u64 > self.location.location_idx {
+                break;
+            }
+            best_line = line_entry.line_number;
+        }
+        Ok(Some(best_line))
+    }
+
+    fn method(&self) -> Result<JdwpMethod> {
+        Ok(JdwpMethod {
+            conn: self.conn.clone(),
+            method_id: self.location.method_id,
+            class_id: self.location.class_id,
+        })
+    }
+
+    fn declaring_type(&self) -> Result<JdwpReferenceType> {
+        Ok(JdwpReferenceType {
+            conn: self.conn.clone(),
+            class_id: self.location.class_id,
+        })
+    }
+}
+
+pub struct JdwpReferenceType {
+    conn: Rc<JdwpConnection>,
+    class_id: u64, // This can also be an interface, right? // TODO this should be a classId type
+}
+
+impl JdwpReferenceType {
+    // None for classes in the unnamed module. Only Java 9 and later have modules.
+    pub fn module_name(&self) -> Result<Option<String>> {
+        let conn = self.conn.as_ref();
+        conn.require_version(JDWP_9, "Finding the module of a class")?;
+        let module = reference_type::module(conn, self.class_id)?.module;
+        let name = module_reference::name(conn, module)?.name;
+        Ok(if name.is_empty() { None } else { Some(name) })
+    }
+
+    // Up to max instances of exactly this type (or all of them, if max is 0) whose value of the
+    // named instance field passes 'predicate', e.g. every connection with closed == false. JDWP
+    // can't filter instances itself, so the values are fetched in bulk and tested here. Instances
+    // collected before their values could be read are left out.
+    pub fn find_instances_where<P>(
+        &self,
+        field: &str,
+        mut predicate: P,
+        max: usize,
+    ) -> Result<Vec<u64>>
+    where
+        P: FnMut(&Value) -> bool,
+    {
+        let conn = self.conn.as_ref();
+        let field_id = match self
+            .all_fields()?
+            .into_iter()
+            .map(|declared| declared.field)
+            .find(|f| f.name == field && !Modifiers(f.mod_bits as u32).is_static())
+        {
+            Some(f) => f.field_id,
+            None => {
+                return Err(std::io::Error::new(
+                    ErrorKind::InvalidInput,
+                    format!("{} has no instance field {}", self.name()?, field),
+                ))
+            }
+        };
+        let fields = [field_id];
+
+        let mut found = vec![];
+        // So that we can stop early once there are enough
+        for chunk in self.instances_vec(0)?.chunks(1024) {
+            let requests: Vec<(u64, &[u64])> = chunk.iter().map(|&id| (id, &fields[..])).collect();
+            let values = match conn.get_values_bulk(&requests) {
+                Ok(values) => values,
+                Err(e) if has_error_code(&e, &[error_code::INVALID_OBJECT]) => {
+                    // Something was collected, so find out which one by one
+                    let mut values = vec![];
+                    for &id in chunk {
+                        match object_reference::get_values(conn, id, &fields) {
+                            Ok(reply) => values.push(reply.values),
+                            Err(e) if has_error_code(&e, &[error_code::INVALID_OBJECT]) => {
+                                values.push(vec![])
+                            }
+                            Err(e) => return Err(e),
+                        }
+                    }
+                    values
+                }
+                Err(e) => return Err(e),
+            };
+            for (&id, values) in chunk.iter().zip(values) {
+                if values.first().is_some_and(&mut predicate) {
+                    found.push(id);
+                    if found.len() == max {
+                        return Ok(found);
+                    }
+                }
+            }
+        }
+        Ok(found)
+    }
+}
+
+impl ReferenceType<JdwpJavaVirtualMachine> for JdwpReferenceType {
+    fn name(&self) -> Result<String> {
+        let class_sig = reference_type::signature(self.conn.as_ref(), self.class_id)?.signature;
+        Ok(signature_to_name(&class_sig))
+    }
+
+    fn source_name(&self) -> Result<Option<String>> {
+        match reference_type::source_file(self.conn.as_ref(), self.class_id) {
+            Ok(reply) => Ok(Some(reply.source_file)),
+            Err(e) if has_error_code(&e, &[error_code::ABSENT_INFORMATION]) => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    fn fields(&self) -> Items<'_, JdwpField> {
+        let fields = match reference_type::fields(self.conn.as_ref(), self.class_id) {
+            Ok(reply) => reply.fields,
+            Err(e) => return failed(e),
+        };
+        Box::new(fields.into_iter().map(move |field| {
+            Ok(JdwpField {
+                conn: self.conn.clone(),
+                field_id: field.field_id,
+                class_id: self.class_id,
+                name: field.name,
+                mod_bits: field.mod_bits,
+            })
+        }))
+    }
+
+    fn all_fields(&self) -> Result<Vec<DeclaredField<JdwpJavaVirtualMachine>>> {
+        let mut all_fields = vec![];
+        let mut class = Some(JdwpReferenceType {
+            conn: self.conn.clone(),
+            class_id: self.class_id,
+        });
+        while let Some(declaring_type) = class {
+            for field in declaring_type.fields() {
+                let field = field?;
+                all_fields.push(DeclaredField {
+                    declaring_type: JdwpReferenceType {
+                        conn: self.conn.clone(),
+                        class_id: declaring_type.class_id,
+                    },
+                    field,
+                });
+            }
+            class = declaring_type.superclass()?;
+        }
+        Ok(all_fields)
+    }
+
+    fn superclass(&self) -> Result<Option<JdwpReferenceType>> {
+        let conn = self.conn.as_ref();
+        let superclass = match class_type::superclass(conn, self.class_id) {
+            Ok(reply) => reply.superclass,
+            // Interfaces and arrays aren't classes as far as ClassType is concerned. Arrays are
+            // subclasses of Object though.
+            Err(e) if has_error_code(&e, &[error_code::INVALID_CLASS]) => {
+                let signature = reference_type::signature(conn, self.class_id)?.signature;
+                if !signature.starts_with('[') {
+                    return Ok(None);
+                }
+                virtual_machine::classes_by_signature(conn, "Ljava/lang/Object;")?
+                    .classes
+                    .first()
+                    .map_or(0, |class| class.type_id)
+            }
+            Err(e) => return Err(e),
+        };
+        Ok(match superclass {
+            0 => None,
+            class_id => Some(JdwpReferenceType {
+                conn: self.conn.clone(),
+                class_id,
+            }),
+        })
+    }
+
+    // JDWP has no command for the PermittedSubclasses attribute, but the attribute's name has to
+    // be in the constant pool of a class which has one, along with the classes it names. Of
+    // those, the ones which have been loaded and extend (or implement) this one are permitted,
+    // since the JVM wouldn't have loaded them otherwise. Permitted subclasses which haven't been
+    // loaded yet can't be told apart from the other classes the pool names, so aren't included.
+    fn permitted_subclasses(&self) -> Result<Option<Vec<String>>> {
+        let conn = self.conn.as_ref();
+        if !conn.has_capability(|c| c.can_get_constant_pool) {
+            return Ok(None);
+        }
+        let pool = reference_type::constant_pool(conn, self.class_id)?;
+        let pool = ConstantPool::parse(pool.count, &pool.bytes)?;
+        if !pool.has_utf8("PermittedSubclasses") {
+            return Ok(None);
+        }
+        let mut permitted = BTreeSet::new();
+        for class in pool.classes() {
+            let signature = name_to_signature(class);
+            for candidate in virtual_machine::classes_by_signature(conn, &signature)?.classes {
+                let extends = match class_type::superclass(conn, candidate.type_id) {
+                    Ok(reply) => reply.superclass == self.class_id,
+                    Err(e) if has_error_code(&e, &[error_code::INVALID_CLASS]) => false,
+                    Err(e) => return Err(e),
+                };
+                if extends
+                    || reference_type::interfaces(conn, candidate.type_id)?
+                        .interfaces
+                        .contains(&self.class_id)
+                {
+                    permitted.insert(signature_to_name(&signature));
+                }
+            }
+        }
+        Ok(Some(permitted.into_iter().collect()))
+    }
+
+    fn instances(&self, max: usize) -> Items<'_, u64> {
+        let conn = self.conn.as_ref();
+        let max = max.min(i32::MAX as usize) as i32;
+        let instances = conn
+            .require_version(JDWP_1_6, "Listing instances")
+            .and_then(|_| conn.require_capability(|c| c.can_get_instance_info, "Listing instances"))
+            .and_then(|_| reference_type::instances(conn, self.class_id, max));
+        match instances {
+            Ok(reply) => Box::new(reply.instances.into_iter().map(|i| Ok(i.object_id))),
+            Err(e) => failed(e),
+        }
+    }
+
+    fn get_value(&self, field: &JdwpField) -> Result<Value> {
+        let mut values =
+            reference_type::get_values(self.conn.as_ref(), self.class_id, &[field.field_id])?
+                .values;
+        values
+            .pop()
+            .ok_or_else(|| protocol_err("GetValues returned no values"))
+    }
+}
+
+#[allow(dead_code)]
+pub struct JdwpField {
+    conn: Rc<JdwpConnection>,
+    field_id: u64, // TODO this should be a fieldId type
+    class_id: u64, // method_id is only unique for a single class // TODO this should be a classId type
+    name: String,
+    mod_bits: i32,
+}
+
+impl TypeComponent for JdwpField {
+    // TODO should return ref to string owned by JdwpField
+    fn name(&self) -> Result<String> {
+        Ok(self.name.clone())
+    }
+}
+
+impl Field for JdwpField {
+    fn modifiers(&self) -> Result<Modifiers> {
+        Ok(Modifiers(self.mod_bits as u32))
+    }
+}
+
+pub struct JdwpMethod {
+    conn: Rc<JdwpConnection>,
+    method_id: u64, // TODO this should be a methodId type
+    class_id: u64, // method_id is only unique for a single class // TODO this should be a classId type
+}
+
+impl TypeComponent for JdwpMethod {
+    fn name(&self) -> Result<String> {
+        // TODO probably want to use methods_with_generics?
+        for method in reference_type::methods(self.conn.as_ref(), self.class_id)?.methods {
+            if method.method_id == self.method_id {
+                return Ok(method.name);
+            }
+        }
+        Err(protocol_err("failed to find TODO"))
+    }
+}
+
+impl Method<JdwpJavaVirtualMachine> for JdwpMethod {}
+
+pub trait Serialize {
+    fn serialize<W: Write>(self, writer: &mut W) -> Result<()>;
+}
+
+impl Serialize for u8 {
+    fn serialize<W: Write>(self, writer: &mut W) -> Result<()> {
+        writer.write_u8(self)
+    }
+}
+
+impl Serialize for u16 {
+    fn serialize<W: Write>(self, writer: &mut W) -> Result<()> {
+        writer.write_u16::<BigEndian>(self)
+    }
+}
+
+impl Serialize for u32 {
+    fn serialize<W: Write>(self, writer: &mut W) -> Result<()> {
+        writer.write_u32::<BigEndian>(self)
+    }
+}
+
+impl Serialize for i32 {
+    fn serialize<W: Write>(self, writer: &mut W) -> Result<()> {
+        writer.write_i32::<BigEndian>(self)
+    }
+}
+
+impl Serialize for u64 {
+    fn serialize<W: Write>(self, writer: &mut W) -> Result<()> {
+        writer.write_u64::<BigEndian>(self)
+    }
+}
+
+impl Serialize for &u64 {
+    fn serialize<W: Write>(self, writer: &mut W) -> Result<()> {
+        writer.write_u64::<BigEndian>(*self)
+    }
+}
+
+impl Serialize for bool {
+    fn serialize<W: Write>(self, writer: &mut W) -> Result<()> {
+        writer.write_u8(self as u8)
+    }
+}
+
+impl Serialize for &str {
+    fn serialize<W: Write>(self, writer: &mut W) -> Result<()> {
+        let utf8 = self.as_bytes();
+        writer.write_u32::<BigEndian>(utf8.len().try_into().unwrap())?;
+        writer.write_all(utf8).unwrap();
+        Ok(())
+    }
+}
+
+// Tagged, as in method arguments. Objects are sent with the plain object tag, which the target
+// accepts for any kind of object.
+impl Serialize for &Value {
+    fn serialize<W: Write>(self, writer: &mut W) -> Result<()> {
+        match *self {
+            Value::Boolean(b) => {
+                writer.write_u8(b'Z')?;
+                writer.write_u8(b as u8)
+            }
+            Value::Byte(b) => {
+                writer.write_u8(b'B')?;
+                writer.write_i8(b)
+            }
+            Value::Char(c) => {
+                writer.write_u8(b'C')?;
+                writer.write_u16::<BigEndian>(c)
+            }
+            Value::Short(s) => {
+                writer.write_u8(b'S')?;
+                writer.write_i16::<BigEndian>(s)
+            }
+            Value::Integer(i) => {
+                writer.write_u8(b'I')?;
+                writer.write_i32::<BigEndian>(i)
+            }
+            Value::Long(l) => {
+                writer.write_u8(b'J')?;
+                writer.write_i64::<BigEndian>(l)
+            }
+            Value::Float(f) => {
+                writer.write_u8(b'F')?;
+                writer.write_f32::<BigEndian>(f)
+            }
+            Value::Double(d) => {
+                writer.write_u8(b'D')?;
+                writer.write_f64::<BigEndian>(d)
+            }
+            Value::Object(id) | Value::ClassObject(id) => {
+                writer.write_u8(b'L')?;
+                writer.write_u64::<BigEndian>(id)
+            }
+            Value::Null => {
+                writer.write_u8(b'L')?;
+                writer.write_u64::<BigEndian>(0)
+            }
+            Value::Void => writer.write_u8(b'V'),
+        }
+    }
+}
+
+impl<'a, T> Serialize for &'a [T]
+where
+    &'a T: Serialize,
+{
+    fn serialize<W: Write>(self, writer: &mut W) -> Result<()> {
+        writer.write_i32::<BigEndian>(self.len().try_into().unwrap())?;
+        for item in self {
+            item.serialize(writer)?;
+        }
+        Ok(())
+    }
+}
+
+pub trait Deserialize {
+    fn deserialize<R: Read>(reader: &mut R) -> Result<Self>
+    where
+        Self: std::marker::Sized;
+}
+
+impl Deserialize for u8 {
+    fn deserialize<R: Read>(reader: &mut R) -> Result<Self> {
+        reader.read_u8()
+    }
+}
+
+impl Deserialize for u16 {
+    fn deserialize<R: Read>(reader: &mut R) -> Result<Self> {
+        reader.read_u16::<BigEndian>()
+    }
+}
+
+impl Deserialize for u32 {
+    fn deserialize<R: Read>(reader: &mut R) -> Result<Self> {
+        reader.read_u32::<BigEndian>()
+    }
+}
+
+impl Deserialize for i32 {
+    fn deserialize<R: Read>(reader: &mut R) -> Result<Self> {
+        reader.read_i32::<BigEndian>()
+    }
+}
+
+impl Deserialize for u64 {
+    fn deserialize<R: Read>(reader: &mut R) -> Result<Self> {
+        reader.read_u64::<BigEndian>()
+    }
+}
+
+impl Deserialize for i64 {
+    fn deserialize<R: Read>(reader: &mut R) -> Result<Self> {
+        reader.read_i64::<BigEndian>()
+    }
+}
+
+impl Deserialize for bool {
+    fn deserialize<R: Read>(reader: &mut R) -> Result<Self> {
+        Ok(reader.read_u8()? != 0)
+    }
+}
+
+// A tagged value: one byte giving the type, followed by the value itself
+impl Deserialize for Value {
+    fn deserialize<R: Read>(reader: &mut R) -> Result<Self> {
+        let tag = reader.read_u8()?;
+        deserialize_untagged_value(tag, reader)
+    }
+}
+
+// Values where the type is known from context (e.g. from a field's signature) are sent without a
+// tag. The tag here uses the same characters as JNI type signatures, plus a few extra ones for
+// specific kinds of object.
+fn deserialize_untagged_value<R: Read>(tag: u8, reader: &mut R) -> Result<Value> {
+    let value = match tag {
+        b'Z' => Value::Boolean(reader.read_u8()? != 0),
+        b'B' => Value::Byte(reader.read_i8()?),
+        b'C' => Value::Char(reader.read_u16::<BigEndian>()?),
+        b'S' => Value::Short(reader.read_i16::<BigEndian>()?),
+        b'I' => Value::Integer(reader.read_i32::<BigEndian>()?),
+        b'J' => Value::Long(reader.read_i64::<BigEndian>()?),
+        b'F' => Value::Float(reader.read_f32::<BigEndian>()?),
+        b'D' => Value::Double(reader.read_f64::<BigEndian>()?),
+        b'V' => Value::Void,
+        // Object, array, String, Thread, ThreadGroup and ClassLoader respectively
+        b'L' | b'[' | b's' | b't' | b'g' | b'l' => match reader.read_u64::<BigEndian>()? {
+            0 => Value::Null,
+            id => Value::Object(id),
+        },
+        b'c' => match reader.read_u64::<BigEndian>()? {
+            0 => Value::Null,
+            id => Value::ClassObject(id),
+        },
+        _ => return Err(protocol_err(&format!("{} is not a valid value tag", tag))),
+    };
+    Ok(value)
+}
+
+// The values of part of an array, as ArrayReference.GetValues sends them: primitives are untagged,
+// since they all have the array's type, but objects are tagged
+#[derive(Debug)]
+pub struct ArrayRegion(pub Vec<Value>);
+
+impl Deserialize for ArrayRegion {
+    fn deserialize<R: Read>(reader: &mut R) -> Result<Self> {
+        let tag = reader.read_u8()?;
+        let count = reader.read_i32::<BigEndian>()?;
+        let primitive = b"ZBCSIJFD".contains(&tag);
+        let mut values = vec![];
+        for _ in 0..count {
+            values.push(if primitive {
+                deserialize_untagged_value(tag, reader)?
+            } else {
+                Value::deserialize(reader)?
+            });
+        }
+        Ok(ArrayRegion(values))
+    }
+}
+
+impl Deserialize for String {
+    fn deserialize<R: Read>(reader: &mut R) -> Result<Self> {
+        let str_len = reader.read_u32::<BigEndian>()?;
+
+        let mut buf = vec![0; str_len as usize];
+        reader.read_exact(&mut buf)?;
+        Ok(mutf8::decode(&buf))
+    }
+}
+
+impl<T: Deserialize> Deserialize for Vec<T> {
+    fn deserialize<R: Read>(reader: &mut R) -> Result<Self> {
+        let count = reader.read_i32::<BigEndian>()?;
+        // TODO could we allocate a vec of an appropriate size using count?j
+        let mut r = vec![];
+        // TODO check > 0 ??
+        for _ in 0..count {
+            let val: T = Deserialize::deserialize(reader)?;
+            r.push(val);
+        }
+        Ok(r)
+    }
+}
+
+// TODO move me
+use std::rc::Rc;
+use std::{error::Error, fmt};
+
+#[derive(Debug)]
+struct JdwpError {
+    msg: String,
+    kind: JdwpErrorKind,
+    error_code: Option<u16>,
+}
+
+// What went wrong, for the errors which come from talking to the target (see jdwp_error_kind())
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum JdwpErrorKind {
+    // The target sent something we didn't understand
+    Protocol,
+    // The target is too old, or lacks an optional capability
+    Unsupported,
+    // The target reported an error for a command, see jdwp_error_code()
+    Target,
+    // A handle was used after its connection was disposed, or the target exited
+    StaleHandle,
+    // A stack frame (or local variable) was used after its thread was resumed
+    FrameInvalidated,
+    // A method invoked in the target threw, didn't return in time, or would have deadlocked. Any
+    // changes it made before that are left in place.
+    InvokeFailed,
+}
+
+impl Error for JdwpError {}
+
+impl fmt::Display for JdwpError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.msg)
+    }
+}
+
+// TODO imports?
+fn protocol_err(msg: &str) -> std::io::Error {
+    std::io::Error::new(
+        std::io::ErrorKind::InvalidData,
+        JdwpError {
+            msg: format!("JDWP Protocol Error: {}", msg),
+            kind: JdwpErrorKind::Protocol,
+            error_code: None,
+        },
+    )
+}
+
+// For things the target can't do, or we can't do with it
+fn unsupported_err(msg: &str) -> std::io::Error {
+    std::io::Error::new(
+        std::io::ErrorKind::Unsupported,
+        JdwpError {
+            msg: msg.to_string(),
+            kind: JdwpErrorKind::Unsupported,
+            error_code: None,
+        },
+    )
+}
+
+fn target_err(error_code: u16) -> std::io::Error {
+    std::io::Error::other(JdwpError {
+        msg: format!(
+            "Error from JDWP target: {}",
+            describe_error_code(error_code)
+        ),
+        kind: JdwpErrorKind::Target,
+        error_code: Some(error_code),
+    })
+}
+
+// For an error reply to a command, saying which command it was and what it was about, e.g.
+// "ThreadReference.Frames for thread 0x42 failed: INVALID_THREAD (10), the thread has exited or
+// isn't a thread"
+fn command_err(command_set: u8, command: u8, data: &[u8], error_code: u16) -> std::io::Error {
+    // Most command sets are named after what the first ID in the command is
+    let subject = match command_set {
+        2..=6 => Some("class"),
+        9 => Some("object"),
+        10 => Some("string"),
+        11 | 16 => Some("thread"),
+        12 => Some("thread group"),
+        13 => Some("array"),
+        14 => Some("class loader"),
+        17 => Some("class object"),
+        18 => Some("module"),
+        _ => None,
+    };
+    let subject = match (subject, data.get(..8)) {
+        (Some(kind), Some(id)) => {
+            format!(
+                " for {} {:#x}",
+                kind,
+                u64::from_be_bytes(id.try_into().unwrap())
+            )
+        }
+        _ => String::new(),
+    };
+    std::io::Error::other(JdwpError {
+        msg: format!(
+            "{}{} failed: {}",
+            stats::spec_command_name(command_set, command),
+            subject,
+            describe_error_code(error_code)
+        ),
+        kind: JdwpErrorKind::Target,
+        error_code: Some(error_code),
+    })
+}
+
+// Replaces the target_err() of an error reply with command_err(), leaving other errors alone
+fn with_command(err: std::io::Error, command_set: u8, command: u8, data: &[u8]) -> std::io::Error {
+    match (jdwp_error_kind(&err), jdwp_error_code(&err)) {
+        (Some(JdwpErrorKind::Target), Some(code)) => command_err(command_set, command, data, code),
+        _ => err,
+    }
+}
+
+// e.g. "INVALID_THREAD (10), the thread has exited or isn't a thread"
+fn describe_error_code(code: u16) -> String {
+    match (error_code::name(code), error_code::description(code)) {
+        (Some(name), Some(description)) => format!("{} ({}), {}", name, code, description),
+        (Some(name), None) => format!("{} ({})", name, code),
+        _ => format!("error code {}", code),
+    }
+}
+
+// Error codes reported by the target VM
+// https://docs.oracle.com/en/java/javase/21/docs/specs/jdwp/jdwp-protocol.html#JDWP_Error
+pub mod error_code {
+    pub const NONE: u16 = 0;
+    pub const INVALID_THREAD: u16 = 10;
+    pub const INVALID_THREAD_GROUP: u16 = 11;
+    pub const INVALID_PRIORITY: u16 = 12;
+    pub const THREAD_NOT_SUSPENDED: u16 = 13;
+    pub const THREAD_SUSPENDED: u16 = 14;
+    pub const THREAD_NOT_ALIVE: u16 = 15;
+    pub const INVALID_OBJECT: u16 = 20;
+    pub const INVALID_CLASS: u16 = 21;
+    pub const CLASS_NOT_PREPARED: u16 = 22;
+    pub const INVALID_METHODID: u16 = 23;
+    pub const INVALID_LOCATION: u16 = 24;
+    pub const INVALID_FIELDID: u16 = 25;
+    pub const INVALID_FRAMEID: u16 = 30;
+    pub const NO_MORE_FRAMES: u16 = 31;
+    pub const OPAQUE_FRAME: u16 = 32;
+    pub const NOT_CURRENT_FRAME: u16 = 33;
+    pub const TYPE_MISMATCH: u16 = 34;
+    pub const INVALID_SLOT: u16 = 35;
+    pub const DUPLICATE: u16 = 40;
+    pub const NOT_FOUND: u16 = 41;
+    pub const INVALID_MODULE: u16 = 42;
+    pub const INVALID_MONITOR: u16 = 50;
+    pub const NOT_MONITOR_OWNER: u16 = 51;
+    pub const INTERRUPT: u16 = 52;
+    pub const INVALID_CLASS_FORMAT: u16 = 60;
+    pub const CIRCULAR_CLASS_DEFINITION: u16 = 61;
+    pub const FAILS_VERIFICATION: u16 = 62;
+    pub const ADD_METHOD_NOT_IMPLEMENTED: u16 = 63;
+    pub const SCHEMA_CHANGE_NOT_IMPLEMENTED: u16 = 64;
+    pub const INVALID_TYPESTATE: u16 = 65;
+    pub const HIERARCHY_CHANGE_NOT_IMPLEMENTED: u16 = 66;
+    pub const DELETE_METHOD_NOT_IMPLEMENTED: u16 = 67;
+    pub const UNSUPPORTED_VERSION: u16 = 68;
+    pub const NAMES_DONT_MATCH: u16 = 69;
+    pub const CLASS_MODIFIERS_CHANGE_NOT_IMPLEMENTED: u16 = 70;
+    pub const METHOD_MODIFIERS_CHANGE_NOT_IMPLEMENTED: u16 = 71;
+    pub const CLASS_ATTRIBUTE_CHANGE_NOT_IMPLEMENTED: u16 = 72;
+    pub const NOT_IMPLEMENTED: u16 = 99;
+    pub const NULL_POINTER: u16 = 100;
+    pub const ABSENT_INFORMATION: u16 = 101;
+    pub const INVALID_EVENT_TYPE: u16 = 102;
+    pub const ILLEGAL_ARGUMENT: u16 = 103;
+    pub const OUT_OF_MEMORY: u16 = 110;
+    pub const ACCESS_DENIED: u16 = 111;
+    pub const VM_DEAD: u16 = 112;
+    pub const INTERNAL: u16 = 113;
+    pub const UNATTACHED_THREAD: u16 = 115;
+    pub const INVALID_TAG: u16 = 500;
+    pub const ALREADY_INVOKING: u16 = 502;
+    pub const INVALID_INDEX: u16 = 503;
+    pub const INVALID_LENGTH: u16 = 504;
+    pub const INVALID_STRING: u16 = 506;
+    pub const INVALID_CLASS_LOADER: u16 = 507;
+    pub const INVALID_ARRAY: u16 = 508;
+    pub const TRANSPORT_LOAD: u16 = 509;
+    pub const TRANSPORT_INIT: u16 = 510;
+    pub const NATIVE_METHOD: u16 = 511;
+    pub const INVALID_COUNT: u16 = 512;
+
+    // What the error means, more or less as the spec puts it, for the codes which aren't
+    // self-explanatory
+    pub fn description(code: u16) -> Option<&'static str> {
+        Some(match code {
+            INVALID_THREAD => "the thread has exited or isn't a thread",
+            INVALID_THREAD_GROUP => "not a thread group",
+            THREAD_NOT_SUSPENDED => "the thread has to be suspended (by an event or a suspend)",
+            THREAD_SUSPENDED => "the thread is already suspended",
+            THREAD_NOT_ALIVE => "the thread hasn't started or has already finished",
+            INVALID_OBJECT => "the object has been garbage collected, or the ID is wrong",
+            INVALID_CLASS => "the class has been unloaded, or the ID is wrong",
+            CLASS_NOT_PREPARED => "the class has been loaded but not prepared yet",
+            INVALID_METHODID => "the method isn't in that class",
+            INVALID_LOCATION => "there's no such location in the method",
+            INVALID_FIELDID => "the field isn't in that class",
+            INVALID_FRAMEID => "the frame is gone, since its thread has run",
+            NO_MORE_FRAMES => "there are no more Java frames on the thread's stack",
+            OPAQUE_FRAME => "the frame is native or otherwise can't be inspected",
+            NOT_CURRENT_FRAME => "only the thread's topmost frame can be used",
+            TYPE_MISMATCH => "the value doesn't have the type the variable or field does",
+            INVALID_SLOT => "there's no variable in that slot",
+            DUPLICATE => "it's been set already",
+            NOT_FOUND => "nothing matched",
+            INVALID_MONITOR => "not a monitor",
+            NOT_MONITOR_OWNER => "the thread doesn't own the monitor",
+            INTERRUPT => "the call was interrupted",
+            NOT_IMPLEMENTED => "the target doesn't implement this",
+            NULL_POINTER => "a null was given where an object is needed",
+            ABSENT_INFORMATION => "the class wasn't compiled with debug information for this",
+            INVALID_EVENT_TYPE => "the event can't be requested",
+            ILLEGAL_ARGUMENT => "one of the arguments is wrong",
+            OUT_OF_MEMORY => "the target ran out of memory",
+            ACCESS_DENIED => "the target's debugging agent doesn't allow it",
+            VM_DEAD => "the target VM is shutting down",
+            UNATTACHED_THREAD => "the thread isn't attached to the VM",
+            ALREADY_INVOKING => "the thread is already invoking a method",
+            INVALID_INDEX => "the index is out of bounds",
+            INVALID_LENGTH => "the length is out of bounds",
+            INVALID_STRING => "not a string",
+            INVALID_CLASS_LOADER => "not a class loader",
+            INVALID_ARRAY => "not an array",
+            NATIVE_METHOD => "native methods have no bytecode or lines",
+            INVALID_COUNT => "the count is wrong",
+            _ => return None,
+        })
+    }
+
+    // e.g. "INVALID_CLASS", as the spec names them
+    pub fn name(code: u16) -> Option<&'static str> {
+        Some(match code {
+            NONE => "NONE",
+            INVALID_THREAD => "INVALID_THREAD",
+            INVALID_THREAD_GROUP => "INVALID_THREAD_GROUP",
+            INVALID_PRIORITY => "INVALID_PRIORITY",
+            THREAD_NOT_SUSPENDED => "THREAD_NOT_SUSPENDED",
+            THREAD_SUSPENDED => "THREAD_SUSPENDED",
+            THREAD_NOT_ALIVE => "THREAD_NOT_ALIVE",
+            INVALID_OBJECT => "INVALID_OBJECT",
+            INVALID_CLASS => "INVALID_CLASS",
+            CLASS_NOT_PREPARED => "CLASS_NOT_PREPARED",
+            INVALID_METHODID => "INVALID_METHODID",
+            INVALID_LOCATION => "INVALID_LOCATION",
+            INVALID_FIELDID => "INVALID_FIELDID",
+            INVALID_FRAMEID => "INVALID_FRAMEID",
+            NO_MORE_FRAMES => "NO_MORE_FRAMES",
+            OPAQUE_FRAME => "OPAQUE_FRAME",
+            NOT_CURRENT_FRAME => "NOT_CURRENT_FRAME",
+            TYPE_MISMATCH => "TYPE_MISMATCH",
+            INVALID_SLOT => "INVALID_SLOT",
+            DUPLICATE => "DUPLICATE",
+            NOT_FOUND => "NOT_FOUND",
+            INVALID_MODULE => "INVALID_MODULE",
+            INVALID_MONITOR => "INVALID_MONITOR",
+            NOT_MONITOR_OWNER => "NOT_MONITOR_OWNER",
+            INTERRUPT => "INTERRUPT",
+            INVALID_CLASS_FORMAT => "INVALID_CLASS_FORMAT",
+            CIRCULAR_CLASS_DEFINITION => "CIRCULAR_CLASS_DEFINITION",
+            FAILS_VERIFICATION => "FAILS_VERIFICATION",
+            ADD_METHOD_NOT_IMPLEMENTED => "ADD_METHOD_NOT_IMPLEMENTED",
+            SCHEMA_CHANGE_NOT_IMPLEMENTED => "SCHEMA_CHANGE_NOT_IMPLEMENTED",
+            INVALID_TYPESTATE => "INVALID_TYPESTATE",
+            HIERARCHY_CHANGE_NOT_IMPLEMENTED => "HIERARCHY_CHANGE_NOT_IMPLEMENTED",
+            DELETE_METHOD_NOT_IMPLEMENTED => "DELETE_METHOD_NOT_IMPLEMENTED",
+            UNSUPPORTED_VERSION => "UNSUPPORTED_VERSION",
+            NAMES_DONT_MATCH => "NAMES_DONT_MATCH",
+            CLASS_MODIFIERS_CHANGE_NOT_IMPLEMENTED => "CLASS_MODIFIERS_CHANGE_NOT_IMPLEMENTED",
+            METHOD_MODIFIERS_CHANGE_NOT_IMPLEMENTED => "METHOD_MODIFIERS_CHANGE_NOT_IMPLEMENTED",
+            CLASS_ATTRIBUTE_CHANGE_NOT_IMPLEMENTED => "CLASS_ATTRIBUTE_CHANGE_NOT_IMPLEMENTED",
+            NOT_IMPLEMENTED => "NOT_IMPLEMENTED",
+            NULL_POINTER => "NULL_POINTER",
+            ABSENT_INFORMATION => "ABSENT_INFORMATION",
+            INVALID_EVENT_TYPE => "INVALID_EVENT_TYPE",
+            ILLEGAL_ARGUMENT => "ILLEGAL_ARGUMENT",
+            OUT_OF_MEMORY => "OUT_OF_MEMORY",
+            ACCESS_DENIED => "ACCESS_DENIED",
+            VM_DEAD => "VM_DEAD",
+            INTERNAL => "INTERNAL",
+            UNATTACHED_THREAD => "UNATTACHED_THREAD",
+            INVALID_TAG => "INVALID_TAG",
+            ALREADY_INVOKING => "ALREADY_INVOKING",
+            INVALID_INDEX => "INVALID_INDEX",
+            INVALID_LENGTH => "INVALID_LENGTH",
+            INVALID_STRING => "INVALID_STRING",
+            INVALID_CLASS_LOADER => "INVALID_CLASS_LOADER",
+            INVALID_ARRAY => "INVALID_ARRAY",
+            TRANSPORT_LOAD => "TRANSPORT_LOAD",
+            TRANSPORT_INIT => "TRANSPORT_INIT",
+            NATIVE_METHOD => "NATIVE_METHOD",
+            INVALID_COUNT => "INVALID_COUNT",
+            _ => return None,
+        })
+    }
+}
+
+// If the error was reported by the target VM (as opposed to e.g. a socket error), returns the
+// JDWP error code.
+pub fn jdwp_error_code(err: &std::io::Error) -> Option<u16> {
+    err.get_ref()
+        .and_then(|e| e.downcast_ref::<JdwpError>())
+        .and_then(|e| e.error_code)
+}
+
+// None for errors which didn't come from talking to the target, e.g. socket errors
+pub fn jdwp_error_kind(err: &std::io::Error) -> Option<JdwpErrorKind> {
+    err.get_ref()
+        .and_then(|e| e.downcast_ref::<JdwpError>())
+        .map(|e| e.kind)
+}
+
+// Options for ClassType.InvokeMethod and ObjectReference.InvokeMethod
+mod invoke_options {
+    // Only resume the invoking thread, rather than every thread, while the method runs
+    pub const INVOKE_SINGLE_THREADED: i32 = 0x01;
+}
+
+// From ThreadReference.Status. Only the ones we act on are listed here.
+mod thread_status {
+    // Finished, or not started yet
+    pub const ZOMBIE: i32 = 0;
+    pub const SLEEPING: i32 = 2;
+    // Blocked waiting to enter a monitor
+    pub const MONITOR: i32 = 3;
+    // In Object.wait(), or parked
+    pub const WAIT: i32 = 4;
+
+    // The suspend status flag, set if the thread has actually stopped
+    pub const SUSPEND_STATUS_SUSPENDED: i32 = 0x1;
+}
+
+fn has_error_code(err: &std::io::Error, codes: &[u16]) -> bool {
+    jdwp_error_code(err).is_some_and(|c| codes.contains(&c))
+}
+
+#[derive(Debug, FromPrimitive, Clone, Copy)]
+#[non_exhaustive]
+pub enum TypeTag {
+    Class = 1,
+    Interface = 2,
+    Array = 3,
+}
+
+impl Deserialize for TypeTag {
+    fn deserialize<R: Read>(reader: &mut R) -> Result<Self> {
+        let val = reader.read_u8()?;
+        FromPrimitive::from_u8(val)
+            .ok_or_else(|| protocol_err(&format!("{} is not a valid Type Tag", val)))
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Location {
+    pub type_tag: TypeTag,
+    pub class_id: u64,  // TODO
+    pub method_id: u64, // TODO
+    pub location_idx: u64,
+}
+
+impl Deserialize for Location {
+    fn deserialize<R: Read>(reader: &mut R) -> Result<Self> {
+        Ok(Location {
+            type_tag: Deserialize::deserialize(reader)?,
+            class_id: Deserialize::deserialize(reader)?,
+            method_id: Deserialize::deserialize(reader)?,
+            location_idx: Deserialize::deserialize(reader)?,
+        })
+    }
+}
+
+impl Serialize for &Location {
+    fn serialize<W: Write>(self, writer: &mut W) -> Result<()> {
+        writer.write_u8(self.type_tag as u8)?;
+        writer.write_u64::<BigEndian>(self.class_id)?;
+        writer.write_u64::<BigEndian>(self.method_id)?;
+        writer.write_u64::<BigEndian>(self.location_idx)
+    }
+}
+
+// A local variable to fetch in StackFrame.GetValues. The tag is the first character of the
+// variable's signature.
+#[derive(Debug, Clone, Copy)]
+pub struct SlotRequest {
+    pub slot: i32,
+    pub tag: u8,
+}
+
+impl Serialize for &SlotRequest {
+    fn serialize<W: Write>(self, writer: &mut W) -> Result<()> {
+        writer.write_i32::<BigEndian>(self.slot)?;
+        writer.write_u8(self.tag)
+    }
+}
+
+// TODO can we de-duplicate the struct/Serialize impl for response and additional types?
+// TODO use cmd_set as mod ?
+macro_rules! command_set {
+    ( set_name: $cmd_set_name:ident;
+      set_id: $set_id:expr;
+      $(command {
+          command_fn: $cmd:ident;
+          command_id: $cmd_id:expr;
+          args: {
+              $( $arg:ident: $arg_ty:ty ),*
+          }
+          response_type: $resp_name:ident {
+              $( $resp_val:ident: $resp_val_ty:ty ),*
+          }
+          $(
+              additional_type: $addn_name:ident {
+                  $( $addn_val:ident: $addn_val_ty:ty ),*
+              }
+          )*
+      } )+
+    ) => {
+        pub mod $cmd_set_name {
+            #[allow(unused_imports)]
+            use super::{Deserialize, JdwpConnection, Serialize, Location, TypeTag, Value};
+            use std::io::{Cursor, Read};
+            use std::io::Result;
+
+            pub const SET_ID: u8 = $set_id;
+            pub const SET_NAME: &str = stringify!($cmd_set_name);
+
+            // e.g. "classes_by_signature", for reporting on commands by number
+            pub fn command_name(command: u8) -> Option<&'static str> {
+                match command {
+                    $( $cmd_id => Some(stringify!($cmd)), )+
+                    _ => None,
+                }
+            }
+
+            $(
+
+            #[derive(Debug)]
+            #[non_exhaustive]
+            pub struct $resp_name {
+                $(
+                    pub $resp_val: $resp_val_ty,
+                )*
+            }
+
+            impl Deserialize for $resp_name {
+                #[allow(unused_variables)]
+                fn deserialize<R: Read>(reader: &mut R) -> Result<Self> {
+                    Ok($resp_name {
+                        $(
+                            $resp_val: Deserialize::deserialize(reader)?,
+                        )*
+                    })
+                }
+            }
+
+            $(
+                #[derive(Debug)]
+                #[non_exhaustive]
+                pub struct $addn_name {
+                    $(
+                        pub $addn_val: $addn_val_ty,
+                    )*
+                }
+
+                impl Deserialize for $addn_name {
+                    fn deserialize<R: Read>(reader: &mut R) -> Result<Self> {
+                        Ok($addn_name {
+                            $(
+                                $addn_val: Deserialize::deserialize(reader)?,
+                            )*
+                        })
+                    }
+                }
+            )*
+
+            pub fn $cmd(conn: &JdwpConnection $(, $arg: $arg_ty )* ) -> Result<$resp_name> {
+                #[allow(unused_mut)]
+                let mut buf = vec![];
+                $(
+                    $arg.serialize(&mut buf)?;
+                )*
+                let mut resp_buf = Cursor::new(conn.execute_cmd($set_id, $cmd_id, &buf)?);
+
+                Deserialize::deserialize(&mut resp_buf)
+            }
+            )+
+        }
+    };
+}
+
+// TODO Link to
+// https://docs.oracle.com/en/java/javase/11/docs/specs/jdwp/jdwp-protocol.html
+// in docs of generated module
+
+command_set! {
+    set_name: virtual_machine;
+    set_id: 1;
+    command {
+        command_fn: version;
+        command_id: 1;
+        args: {}
+        response_type: VersionReply {
+            description: String,
+            jdwp_major: i32,
+            jdwp_minor: i32,
+            vm_version: String,
+            vm_name: String
+        }
+    }
+    command {
+        command_fn: classes_by_signature;
+        command_id: 2;
+        args: {
+            signature: &str
+        }
+        response_type: ClassesBySignatureReply {
+            classes: Vec<ClassesBySignatureReplyClass>
+        }
+        additional_type: ClassesBySignatureReplyClass {
+            ref_type_tag: u8, // TODO could use custom type here
+            type_id: u64, // TODO this should be a referenceTypeId
+            status: u32 // TODO could use special enum here too
+        }
+    }
+    command {
+        command_fn: all_classes;
+        command_id: 3;
+        args: {}
+        response_type: AllClassesReply {
+            classes: Vec<AllClassesReplyClass>
+        }
+        additional_type: AllClassesReplyClass {
+            ref_type_tag: u8, // TODO could use custom type here
+            type_id: u64, // TODO this should be a referenceTypeId
+            signature: String,
+            status: u32 // TODO could use special enum here too
+        }
+    }
+
+    command {
+        command_fn: all_threads;
+        command_id: 4;
+        args: {}
+        response_type: AllThreadsReply {
+            threads: Vec<u64>  // TODO this should be threadId type
+        }
+    }
+    command {
+        command_fn: dispose;
+        command_id: 6;
+        args: {}
+        response_type: DisposeReply {}
+    }
+    command {
+        command_fn: id_sizes;
+        command_id: 7;
+        args: {}
+        response_type: IdSizesReply {
+            field_id_size: i32,
+            method_id_size: i32,
+            object_id_size: i32,
+            reference_type_id_size: i32,
+            frame_id_size: i32
+        }
+    }
+    command {
+        command_fn: suspend;
+        command_id: 8;
+        args: {}
+        response_type: SuspendReply {} // TODO do we need to define these emtpy replies?
+    }
+    command {
+        command_fn: resume;
+        command_id: 9;
+        args: {}
+        response_type: ResumeReply {}
+    }
+    command {
+        command_fn: exit;
+        command_id: 10;
+        args: {
+            exit_code: i32
+        }
+        response_type: ExitReply {}
+    }
+    command {
+        command_fn: create_string;
+        command_id: 11;
+        args: {
+            utf: &str
+        }
+        response_type: CreateStringReply {
+            string_object: u64 // TODO this should be an object_id type
+        }
+    }
+    command {
+        command_fn: hold_events;
+        command_id: 15;
+        args: {}
+        response_type: HoldEventsReply {}
+    }
+    command {
+        command_fn: release_events;
+        command_id: 16;
+        args: {}
+        response_type: ReleaseEventsReply {}
+    }
+    command {
+        command_fn: capabilities_new;
+        command_id: 17;
+        args: {}
+        response_type: CapabilitiesNewReply {
+            can_watch_field_modification: bool,
+            can_watch_field_access: bool,
+            can_get_bytecodes: bool,
+            can_get_synthetic_attribute: bool,
+            can_get_owned_monitor_info: bool,
+            can_get_current_contended_monitor: bool,
+            can_get_monitor_info: bool,
+            can_redefine_classes: bool,
+            can_add_method: bool,
+            can_unrestrictedly_redefine_classes: bool,
+            can_pop_frames: bool,
+            can_use_instance_filters: bool,
+            can_get_source_debug_extension: bool,
+            can_request_vm_death_event: bool,
+            can_set_default_stratum: bool,
+            can_get_instance_info: bool,
+            can_request_monitor_events: bool,
+            can_get_monitor_frame_info: bool,
+            can_use_source_name_filters: bool,
+            can_get_constant_pool: bool,
+            can_force_early_return: bool,
+            reserved22: bool,
+            reserved23: bool,
+            reserved24: bool,
+            reserved25: bool,
+            reserved26: bool,
+            reserved27: bool,
+            reserved28: bool,
+            reserved29: bool,
+            reserved30: bool,
+            reserved31: bool,
+            reserved32: bool
+        }
+    }
+    command {
+        command_fn: instance_counts;
+        command_id: 21;
+        args: {
+            ref_types: &[u64] // TODO this should be reference_type_id type
+        }
+        response_type: InstanceCountsReply {
+            counts: Vec<i64>
+        }
+    }
+}
+
+command_set! {
+    set_name: reference_type;
+    set_id: 2;
+    command {
+        command_fn: signature;
+        command_id: 1;
+        args: {
+            reference_type_id: u64 // TODO this should be reference_type_id type
+        }
+        response_type: SignatureReply {
+            signature: String
+        }
+    }
+    command {
+        command_fn: fields;
+        command_id: 4;
+        args: {
+            reference_type_id: u64 // TODO this should be reference_type_id type
+        }
+        response_type: FieldsReply {
+            fields: Vec<Field>
+        }
+        additional_type: Field {
+            field_id: u64, // TODO this should be a fieldId type
+            name: String,
+            signature: String,
+            mod_bits: i32
+        }
+    }
+    command {
+        command_fn: methods;
+        command_id: 5;
+        args: {
+            reference_type_id: u64 // TODO this should be reference_type_id type
+        }
+        response_type: MethodReply {
+            methods: Vec<Method>
+        }
+        additional_type: Method {
+            method_id: u64,  // TODO this should be a methodId type
+            name: String,
+            signature: String,
+            mod_bits: i32
+        }
+    }
+    command {
+        command_fn: source_file;
+        command_id: 7;
+        args: {
+            reference_type_id: u64 // TODO this should be reference_type_id type
+        }
+        response_type: SourceFileReply {
+            source_file: String
+        }
+    }
+    command {
+        command_fn: interfaces;
+        command_id: 10;
+        args: {
+            reference_type_id: u64 // TODO this should be reference_type_id type
+        }
+        // Only the ones the type declares, not its superclasses' or superinterfaces'
+        response_type: InterfacesReply {
+            interfaces: Vec<u64> // TODO this should be an interfaceId type
+        }
+    }
+    command {
+        command_fn: source_debug_extension;
+        command_id: 12;
+        args: {
+            reference_type_id: u64 // TODO this should be reference_type_id type
+        }
+        response_type: SourceDebugExtensionReply {
+            extension: String
+        }
+    }
+    command {
+        command_fn: get_values;
+        command_id: 6;
+        args: {
+            reference_type_id: u64, // TODO this should be reference_type_id type
+            fields: &[u64] // TODO this should be a fieldId type
+        }
+        // TODO it would be nice not to have to wrap the Vec in another struct when we don't need to
+        response_type: GetValuesReply {
+            values: Vec<Value>
+        }
+    }
+    command {
+        command_fn: instances;
+        command_id: 16;
+        args: {
+            reference_type_id: u64, // TODO this should be reference_type_id type
+            max_instances: i32
+        }
+        response_type: InstancesReply {
+            instances: Vec<TaggedObject>
+        }
+        additional_type: TaggedObject {
+            tag: u8,
+            object_id: u64 // TODO this should be an object_id type
+        }
+    }
+    command {
+        command_fn: constant_pool;
+        command_id: 18;
+        args: {
+            reference_type_id: u64 // TODO this should be reference_type_id type
+        }
+        // The entries as in a class file, without the count in front of them (see bytecode.rs)
+        response_type: ConstantPoolReply {
+            count: i32,
+            bytes: Vec<u8>
+        }
+    }
+    command {
+        command_fn: module;
+        command_id: 19;
+        args: {
+            reference_type_id: u64 // TODO this should be reference_type_id type
+        }
+        response_type: ModuleReply {
+            module: u64 // TODO this should be a moduleID type
+        }
+    }
+}
+
+command_set! {
+    set_name: class_type;
+    set_id: 3;
+    command {
+        command_fn: superclass;
+        command_id: 1;
+        args: {
+            class_id: u64 // TODO this should be a classId type
+        }
+        response_type: SuperclassReply {
+            superclass: u64 // TODO this should be a classId type
+        }
+    }
+    command {
+        command_fn: invoke_method;
+        command_id: 3;
+        args: {
+            class_id: u64, // TODO this should be a classId type
+            thread_id: u64, // TODO this should be a threadId type
+            method_id: u64, // TODO this should be a methodId type
+            arguments: &[Value],
+            options: i32
+        }
+        response_type: InvokeMethodReply {
+            return_value: Value,
+            exception: Value
+        }
+    }
+}
+
+command_set! {
+    set_name: array_type;
+    set_id: 4;
+    command {
+        command_fn: new_instance;
+        command_id: 1;
+        args: {
+            array_type_id: u64, // TODO this should be a referenceTypeId type
+            length: i32
+        }
+        response_type: NewInstanceReply {
+            new_array: Value
+        }
+    }
+}
+
+command_set! {
+    set_name: method;
+    set_id: 6;
+    command {
+        command_fn: line_table;
+        command_id: 1;
+        args: {
+            ref_type: u64, // TODO this should be a referenceTypeID type
+            method_id: u64 // TODO this should be a methodId type
+        }
+        response_type: LineTableReply {
+            start: i64,
+            end: i64,
+            lines: Vec<LineTableEntry>
+        }
+        additional_type: LineTableEntry {
+            line_code_index: i64,
+            line_number: u32
+        }
+    }
+    command {
+        command_fn: variable_table;
+        command_id: 2;
+        args: {
+            ref_type: u64, // TODO this should be a referenceTypeID type
+            method_id: u64 // TODO this should be a methodId type
+        }
+        response_type: VariableTableReply {
+            arg_cnt: i32,
+            slots: Vec<VariableSlot>
+        }
+        additional_type: VariableSlot {
+            code_index: u64,
+            name: String,
+            signature: String,
+            length: u32,
+            slot: i32
+        }
+    }
+    command {
+        command_fn: bytecodes;
+        command_id: 3;
+        args: {
+            ref_type: u64, // TODO this should be a referenceTypeID type
+            method_id: u64 // TODO this should be a methodId type
+        }
+        response_type: BytecodesReply {
+            bytes: Vec<u8>
+        }
+    }
+}
+
+command_set! {
+    set_name: object_reference;
+    set_id: 9;
+    command {
+        command_fn: reference_type;
+        command_id: 1;
+        args: {
+            object_id: u64 // TODO this should be an object_id type
+        }
+        response_type: ReferenceTypeReply {
+            type_tag: TypeTag,
+            type_id: u64 // TODO this should be a ReferenceTypeId type
+        }
+    }
+    command {
+        command_fn: get_values;
+        command_id: 2;
+        args: {
+            object_id: u64, // TODO this should be an object_id type
+            fields: &[u64] // TODO this should be a fieldId type
+        }
+        response_type: GetValuesReply {
+            values: Vec<Value>
+        }
+    }
+    command {
+        command_fn: monitor_info;
+        command_id: 5;
+        args: {
+            object_id: u64 // TODO this should be an object_id type
+        }
+        response_type: MonitorInfoReply {
+            owner: u64, // TODO this should be threadId type
+            entry_count: i32,
+            waiters: Vec<u64> // TODO this should be threadId type
+        }
+    }
+    command {
+        command_fn: invoke_method;
+        command_id: 6;
+        args: {
+            object_id: u64, // TODO this should be an object_id type
+            thread_id: u64, // TODO this should be a threadId type
+            class_id: u64, // TODO this should be a classId type
+            method_id: u64, // TODO this should be a methodId type
+            arguments: &[Value],
+            options: i32
+        }
+        response_type: InvokeMethodReply {
+            return_value: Value,
+            exception: Value
+        }
+    }
+    command {
+        command_fn: disable_collection;
+        command_id: 7;
+        args: {
+            object_id: u64 // TODO this should be an object_id type
+        }
+        response_type: DisableCollectionReply {}
+    }
+    command {
+        command_fn: enable_collection;
+        command_id: 8;
+        args: {
+            object_id: u64 // TODO this should be an object_id type
+        }
+        response_type: EnableCollectionReply {}
+    }
+}
+
+command_set! {
+    set_name: string_reference;
+    set_id: 10;
+    command {
+        command_fn: value;
+        command_id: 1;
+        args: {
+            string_object: u64 // TODO this should be an object_id type
+        }
+        response_type: ValueReply {
+            string_value: String
+        }
+    }
+}
+
+command_set! {
+    set_name: thread_reference;
+    set_id: 11;
+    command {
+        // TODO is this name good?
+        command_fn: name;
+        command_id: 1;
+        args: {
+            thread_id: u64 // TODO this should be threadId type
+        }
+        response_type: NameReply {
+            name: String
+        }
+    }
+    command {
+        command_fn: suspend;
+        command_id: 2;
+        args: {
+            thread_id: u64 // TODO this should be threadId type
+        }
+        response_type: SuspendReply {}
+    }
+    command {
+        command_fn: resume;
+        command_id: 3;
+        args: {
+            thread_id: u64 // TODO this should be threadId type
+        }
+        response_type: ResumeReply {}
+    }
+    command {
+        command_fn: status;
+        command_id: 4;
+        args: {
+            thread_id: u64 // TODO this should be threadId type
+        }
+        response_type: StatusReply {
+            thread_status: i32,
+            suspend_status: i32
+        }
+    }
+    command {
+        command_fn: frames;
+        command_id: 6;
+        args: {
+            thread_id: u64, // TODO this should be threadId type
+            start_frame: i32,
+            length: i32
+        }
+        response_type: FramesReply {
+            frames: Vec<Frame>
+        }
+        additional_type: Frame {
+            frame_id: u64, // TODO this should be a frameId type
+            location: Location
+            // Remaining fields make up a location, might want to create a distinct Location Type
+            //type_tag: u8,
+            //class_id: u64,  // TODO this should be a classId type
+            //method_id: u64,  // TODO this should be a methodId type
+            //location_index: u64
+        }
+    }
+    command {
+        command_fn: frame_count;
+        command_id: 7;
+        args: {
+            thread_id: u64 // TODO this should be threadId type
+        }
+        response_type: FrameCountReply {
+            frame_count: i32
+        }
+    }
+    command {
+        command_fn: owned_monitors;
+        command_id: 8;
+        args: {
+            thread_id: u64 // TODO this should be threadId type
+        }
+        response_type: OwnedMonitorsReply {
+            owned: Vec<Value>
+        }
+    }
+    command {
+        command_fn: current_contended_monitor;
+        command_id: 9;
+        args: {
+            thread_id: u64 // TODO this should be threadId type
+        }
+        response_type: CurrentContendedMonitorReply {
+            monitor: Value
+        }
+    }
+    command {
+        command_fn: suspend_count;
+        command_id: 12;
+        args: {
+            thread_id: u64 // TODO this should be threadId type
+        }
+        response_type: SuspendCountReply {
+            suspend_count: i32
+        }
+    }
+    command {
+        command_fn: is_virtual;
+        command_id: 15;
+        args: {
+            thread_id: u64 // TODO this should be threadId type
+        }
+        response_type: IsVirtualReply {
+            is_virtual: bool
+        }
+    }
+}
+
+command_set! {
+    set_name: array_reference;
+    set_id: 13;
+    command {
+        command_fn: length;
+        command_id: 1;
+        args: {
+            array_object: u64 // TODO this should be an object_id type
+        }
+        response_type: LengthReply {
+            array_length: i32
+        }
+    }
+    command {
+        command_fn: get_values;
+        command_id: 2;
+        args: {
+            array_object: u64, // TODO this should be an object_id type
+            first_index: i32,
+            length: i32
+        }
+        response_type: GetValuesReply {
+            values: super::ArrayRegion
+        }
+    }
+    command {
+        command_fn: set_values;
+        command_id: 3;
+        args: {
+            array_object: u64, // TODO this should be an object_id type
+            first_index: i32,
+            // Untagged, so only object IDs (for arrays of objects) for now
+            values: &[u64]
+        }
+        response_type: SetValuesReply {}
+    }
+}
+
+command_set! {
+    set_name: stack_frame;
+    set_id: 16;
+    command {
+        command_fn: get_values;
+        command_id: 1;
+        args: {
+            thread_id: u64, // TODO this should be threadId type
+            frame_id: u64, // TODO this should be a frameId type
+            slots: &[super::SlotRequest]
+        }
+        response_type: GetValuesReply {
+            values: Vec<Value>
+        }
+    }
+    command {
+        command_fn: this_object;
+        command_id: 3;
+        args: {
+            thread_id: u64, // TODO this should be threadId type
+            frame_id: u64 // TODO this should be a frameId type
+        }
+        response_type: ThisObjectReply {
+            object_this: Value
+        }
+    }
+}
+
+command_set! {
+    set_name: class_object_reference;
+    set_id: 17;
+    command {
+        command_fn: reflected_type;
+        command_id: 1;
+        args: {
+            class_object: u64 // TODO this should be a classObjectId type
+        }
+        response_type: ReflectedTypeReply {
+            type_tag: TypeTag,
+            type_id: u64 // TODO this should be a ReferenceTypeId type
+        }
+    }
+}
+
+command_set! {
+    set_name: module_reference;
+    set_id: 18;
+    command {
+        command_fn: name;
+        command_id: 1;
+        args: {
+            module: u64 // TODO this should be a moduleID type
+        }
+        response_type: NameReply {
+            name: String
+        }
+    }
+}
+
+// Declared last so that the command_set! macro is in scope
+mod audit;
+mod bytecode;
+mod ddm;
+mod diagnostic;
+mod eval;
+mod event;
+mod exception;
+#[cfg(test)]
+mod fake;
+mod fetch;
+mod group;
+mod handshake;
+mod hooks;
+mod invoke;
+mod memory;
+mod monitor;
+mod output;
+mod pins;
+pub mod protocol;
+mod proxy;
+mod queue;
+mod refresh;
+mod sampler;
+mod session;
+mod stall;
+mod stats;
+mod step;
+mod supervisor;
+mod suspension;
+mod thread_dump;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::jdwp::fake::{self, reply};
+
+    const CLASS: u64 = 0x20;
+    const CLOSED: u64 = 1;
+    // Instances are 0x1000 and up, and every third one is closed
+    const FIRST: u64 = 0x1000;
+    const INSTANCES: u64 = 2500;
+    // Which is collected before its values are read
+    const COLLECTED: u64 = FIRST + 1;
+
+    fn target() -> (Rc<JdwpConnection>, fake::Log) {
+        let answers = fake::Answers::default()
+            // ReferenceType.Signature
+            .on(2, 1, |_| reply!["Lcom/example/Connection;"])
+            // ReferenceType.Fields, with a static field as well
+            .on(2, 4, |_| {
+                reply![2, CLOSED, "closed", "Z", 0x02, 2u64, "COUNT", "I", 0x08]
+            })
+            // ClassType.Superclass
+            .on(3, 1, |_| reply![0u64])
+            // ReferenceType.Instances
+            .on(2, 16, |_| {
+                let mut data = INSTANCES.to_be_bytes()[4..].to_vec();
+                for id in FIRST..FIRST + INSTANCES {
+                    data.push(b'L');
+                    data.extend_from_slice(&id.to_be_bytes());
+                }
+                Ok(data)
+            })
+            // ObjectReference.GetValues
+            .on(9, 2, |data| match fake::first_id(data) {
+                COLLECTED => Err(error_code::INVALID_OBJECT),
+                id => reply![1, &Value::Boolean((id - FIRST).is_multiple_of(3))],
+            });
+        let log = answers.log();
+        (Rc::new(answers.attach()), log)
+    }
+
+    // How many of a command the target was sent
+    fn count(log: &fake::Log, command_set: u8, command: u8) -> usize {
+        let log = log.lock().unwrap();
+        log.iter()
+            .filter(|&&(set, cmd, _)| (set, cmd) == (command_set, command))
+            .count()
+    }
+
+    const THREAD: u64 = 0x40;
+
+    // A thread whose FrameCount is 'count' but which has 'actual' frames, failing as the target
+    // does when asked for frames it doesn't have
+    fn suspended_thread(count: i32, actual: i32) -> (JdwpThreadReference, fake::Log) {
+        let answers = fake::Answers::default()
+            // ThreadReference.FrameCount
+            .on(11, 7, move |_| reply![count])
+            // ThreadReference.Frames
+            .on(11, 6, move |data| {
+                let (start, length) = page(data);
+                if start + length > actual {
+                    return Err(error_code::INVALID_LENGTH);
+                }
+                let mut data = length.to_be_bytes().to_vec();
+                for depth in start..start + length {
+                    let location = Location {
+                        type_tag: TypeTag::Class,
+                        class_id: 0x20,
+                        method_id: 0x30,
+                        location_idx: depth as u64,
+                    };
+                    (0x100 + depth as u64).serialize(&mut data).unwrap();
+                    (&location).serialize(&mut data).unwrap();
+                }
+                Ok(data)
+            })
+            // ThreadReference.Resume
+            .on(11, 3, |_| reply![]);
+        let log = answers.log();
+        let thread = JdwpThreadReference {
+            conn: Rc::new(answers.attach()),
+            thread_id: THREAD,
+        };
+        (thread, log)
+    }
+
+    // The start and length of a ThreadReference.Frames command
+    fn page(data: &[u8]) -> (i32, i32) {
+        let mut args = Cursor::new(&data[8..]);
+        let start = args.read_i32::<BigEndian>().unwrap();
+        (start, args.read_i32::<BigEndian>().unwrap())
+    }
+
+    // The pages of frames the thread was asked for
+    fn pages(log: &fake::Log) -> Vec<(i32, i32)> {
+        let log = log.lock().unwrap();
+        log.iter()
+            .filter(|&&(set, cmd, _)| (set, cmd) == (11, 6))
+            .map(|(_, _, data)| page(data))
+            .collect()
+    }
+
+    #[test]
+    fn frame_pages() {
+        // Depths either side of a page boundary, and exactly on one
+        for (depth, expected) in [
+            (0, vec![]),
+            (1, vec![(0, 1)]),
+            (FRAMES_PAGE, vec![(0, FRAMES_PAGE)]),
+            (FRAMES_PAGE + 1, vec![(0, FRAMES_PAGE), (FRAMES_PAGE, 1)]),
+            (
+                2 * FRAMES_PAGE,
+                vec![(0, FRAMES_PAGE), (FRAMES_PAGE, FRAMES_PAGE)],
+            ),
+        ] {
+            let (thread, log) = suspended_thread(depth, depth);
+            let frames: Vec<JdwpStackFrame> = thread.frames().collect::<Result<_>>().unwrap();
+            assert_eq!(frames.len(), depth as usize);
+            for (i, frame) in frames.iter().enumerate() {
+                assert_eq!((frame.depth, frame.frame_id), (i as i32, 0x100 + i as u64));
+                assert_eq!(frame.location.location_idx, i as u64);
+            }
+            assert_eq!(pages(&log), expected, "{} frames", depth);
+        }
+
+        // Only the first page is fetched for the top of the stack
+        let (thread, log) = suspended_thread(100, 100);
+        assert_eq!(
+            thread.frames().take(FRAMES_PAGE as usize).count(),
+            FRAMES_PAGE as usize
+        );
+        assert_eq!(pages(&log), vec![(0, FRAMES_PAGE)]);
+    }
+
+    #[test]
+    fn frame_count_changes() {
+        // Fewer frames by the time the second page is fetched, which is an error after the first
+        // page, and the end
+        let (thread, _) = suspended_thread(FRAMES_PAGE + 8, FRAMES_PAGE + 3);
+        let mut frames = thread.frames();
+        assert_eq!(
+            frames.by_ref().take(FRAMES_PAGE as usize).count(),
+            FRAMES_PAGE as usize
+        );
+        let err = frames.next().unwrap().err().unwrap();
+        assert_eq!(jdwp_error_code(&err), Some(error_code::INVALID_LENGTH));
+        assert!(frames.next().is_none());
+
+        // More, which are left out, since the count is taken first
+        let (thread, _) = suspended_thread(FRAMES_PAGE + 1, FRAMES_PAGE + 5);
+        assert_eq!(thread.frames().count(), FRAMES_PAGE as usize + 1);
+
+        // Resumed between pages, so the rest of the stack isn't the same one
+        let (thread, log) = suspended_thread(FRAMES_PAGE + 1, FRAMES_PAGE + 1);
+        let mut frames = thread.frames();
+        assert_eq!(
+            frames.by_ref().take(FRAMES_PAGE as usize).count(),
+            FRAMES_PAGE as usize
+        );
+        thread_reference::resume(thread.conn.as_ref(), THREAD).unwrap();
+        let err = frames.next().unwrap().err().unwrap();
+        assert_eq!(jdwp_error_kind(&err), Some(JdwpErrorKind::FrameInvalidated));
+        assert!(frames.next().is_none());
+        assert_eq!(pages(&log).len(), 1);
+    }
+
+    fn class(conn: &Rc<JdwpConnection>) -> JdwpReferenceType {
+        JdwpReferenceType {
+            conn: conn.clone(),
+            class_id: CLASS,
+        }
+    }
+
+    // A JDK 21 target with two platform threads, and virtual threads 0x501 to 0x504, of which
+    // 0x502 has finished and 0x503 is collected before its status is asked for
+    fn virtual_threads(version: i32) -> (JdwpJavaVirtualMachine, fake::Log) {
+        let answers = fake::Answers::default()
+            // VirtualMachine.AllThreads
+            .on(1, 4, |_| reply![2, 0x101u64, 0x102u64])
+            // VirtualMachine.ClassesBySignature
+            .on(1, 2, |_| reply![1, 1u8, 0x50u64, 7])
+            // ReferenceType.Instances
+            .on(2, 16, |_| {
+                let mut data = 4i32.to_be_bytes().to_vec();
+                for id in 0x501u64..=0x504 {
+                    data.push(b't');
+                    data.extend_from_slice(&id.to_be_bytes());
+                }
+                Ok(data)
+            })
+            // ThreadReference.Status
+            .on(11, 4, |data| match fake::first_id(data) {
+                0x502 => reply![thread_status::ZOMBIE, 0],
+                0x503 => Err(error_code::INVALID_OBJECT),
+                _ => reply![thread_status::WAIT, 0],
+            });
+        let log = answers.log();
+        (JdwpJavaVirtualMachine::new(answers.attach_as(version)), log)
+    }
+
+    fn thread_ids(jvm: &JdwpJavaVirtualMachine) -> Vec<u64> {
+        jvm.all_threads()
+            .map(|thread| thread.map(|t| t.thread_id))
+            .collect::<Result<_>>()
+            .unwrap()
+    }
+
+    #[test]
+    fn all_threads() {
+        // Just what AllThreads says, unless asked to scan
+        let (jvm, log) = virtual_threads(21);
+        assert_eq!(thread_ids(&jvm), vec![0x101, 0x102]);
+        assert_eq!(count(&log, 2, 16), 0);
+
+        jvm.set_scan_for_virtual_threads(true);
+        assert_eq!(thread_ids(&jvm), vec![0x101, 0x102, 0x501, 0x504]);
+        // Once all together, and once each after one turned out to have been collected
+        assert_eq!(count(&log, 11, 4), 8);
+
+        // Not while they were a preview
+        let (jvm, log) = virtual_threads(19);
+        jvm.set_scan_for_virtual_threads(true);
+        assert_eq!(thread_ids(&jvm), vec![0x101, 0x102]);
+        assert_eq!(count(&log, 2, 16), 0);
+    }
+
+    #[test]
+    fn tagged_values() {
+        let value = |tag: u8, id: u64| {
+            let mut data = vec![tag];
+            data.extend_from_slice(&id.to_be_bytes());
+            Value::deserialize(&mut Cursor::new(data)).unwrap()
+        };
+        assert_eq!(value(b'c', 0x42), Value::ClassObject(0x42));
+        assert_eq!(value(b'c', 0), Value::Null);
+        for tag in *b"L[stgl" {
+            assert_eq!(value(tag, 0x42), Value::Object(0x42));
+        }
+
+        // Sent back as any other object
+        let mut data = vec![];
+        (&Value::ClassObject(0x42)).serialize(&mut data).unwrap();
+        assert_eq!(
+            value(b'L', 0x42),
+            Value::deserialize(&mut Cursor::new(data)).unwrap()
+        );
+    }
+
+    #[test]
+    fn find_instances_where() {
+        let (conn, log) = target();
+        let open = |value: &Value| *value == Value::Boolean(false);
+
+        // Every open one, which means reading them all, and the collected one is left out
+        let found = class(&conn)
+            .find_instances_where("closed", open, 0)
+            .unwrap();
+        let expected: Vec<u64> = (FIRST..FIRST + INSTANCES)
+            .filter(|id| !(id - FIRST).is_multiple_of(3) && *id != COLLECTED)
+            .collect();
+        assert_eq!(found, expected);
+
+        // Stopping once there are enough, within the first chunk, which is read at most twice
+        // since the collected one is in it
+        log.lock().unwrap().clear();
+        let found = class(&conn)
+            .find_instances_where("closed", open, 3)
+            .unwrap();
+        assert_eq!(found, vec![FIRST + 2, FIRST + 4, FIRST + 5]);
+        assert!(count(&log, 9, 2) <= 2 * 1024);
+
+        let found = class(&conn)
+            .find_instances_where("closed", |value| *value == Value::Boolean(true), 2)
+            .unwrap();
+        assert_eq!(found, vec![FIRST, FIRST + 3]);
+
+        for field in ["COUNT", "missing"] {
+            let err = class(&conn)
+                .find_instances_where(field, open, 0)
+                .unwrap_err();
+            assert_eq!(err.kind(), ErrorKind::InvalidInput);
+            assert_eq!(
+                err.to_string(),
+                format!("com.example.Connection has no instance field {}", field)
+            );
+        }
+    }
+}
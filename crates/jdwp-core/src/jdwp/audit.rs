@@ -0,0 +1,234 @@
+//
+// A record of every command which changes the state of the target: setting variables and fields,
+// invoking methods, redefining classes, stopping or interrupting threads, popping frames and
+// making the VM exit. Teams who attach to production need to be able to say afterwards what was
+// done to it, and this is how.
+//
+// The log is a text file which is only ever appended to, a line per command with tab separated
+// fields:
+//
+//   time (seconds since the epoch)  target address  command  arguments (the packet data, in hex)
+//
+// e.g. "1760000000.123\t10.0.0.5:8000\tObjectReference.SetValues\t00000000000012a4...". Each line
+// is written before the command is sent, and if it can't be the command isn't sent either, so
+// nothing goes unrecorded. Commands which only read are never logged.
+//
+// A read-only connection (see AttachOptions::read_only) refuses to send the same commands, and
+// the others which change the target without changing what the program itself sees: suspending
+// and resuming it, making event requests (which can suspend it), and creating or pinning objects
+// in its heap. That way a monitoring tool can be trusted not to change anything in the target,
+// bugs and all. It also means a read-only connection can't take a thread dump, since that
+// suspends the VM.
+//
+
+use std::fmt::Write as _;
+use std::fs::OpenOptions;
+use std::io::{Error, ErrorKind, Result, Write};
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use super::{JdwpConnection, JdwpJavaVirtualMachine};
+use crate::report::OutputSink;
+
+// The commands which change the target, by (command set, command)
+const AUDITED: &[((u8, u8), &str)] = &[
+    ((1, 10), "VirtualMachine.Exit"),
+    ((1, 18), "VirtualMachine.RedefineClasses"),
+    ((3, 2), "ClassType.SetValues"),
+    ((3, 3), "ClassType.InvokeMethod"),
+    ((3, 4), "ClassType.NewInstance"),
+    ((5, 1), "InterfaceType.InvokeMethod"),
+    ((9, 3), "ObjectReference.SetValues"),
+    ((9, 6), "ObjectReference.InvokeMethod"),
+    ((11, 10), "ThreadReference.Stop"),
+    ((11, 11), "ThreadReference.Interrupt"),
+    ((11, 14), "ThreadReference.ForceEarlyReturn"),
+    ((13, 3), "ArrayReference.SetValues"),
+    ((16, 2), "StackFrame.SetValues"),
+    ((16, 4), "StackFrame.PopFrames"),
+];
+
+// The commands a read-only connection refuses besides the audited ones
+const READ_ONLY_DENIED: &[((u8, u8), &str)] = &[
+    ((1, 8), "VirtualMachine.Suspend"),
+    ((1, 9), "VirtualMachine.Resume"),
+    ((1, 11), "VirtualMachine.CreateString"),
+    ((1, 15), "VirtualMachine.HoldEvents"),
+    ((4, 1), "ArrayType.NewInstance"),
+    ((9, 7), "ObjectReference.DisableCollection"),
+    ((11, 2), "ThreadReference.Suspend"),
+    ((11, 3), "ThreadReference.Resume"),
+    ((15, 1), "EventRequest.Set"),
+];
+
+pub(super) struct AuditLog {
+    out: Box<dyn Write>,
+}
+
+impl JdwpConnection {
+    // Append a line to the file at 'path' for every command which changes the target (see
+    // audit.rs), from now on, replacing any log set before. The file is created if need be.
+    pub fn set_audit_log<P: AsRef<Path>>(&self, path: P) -> Result<()> {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        self.set_audit_writer(file);
+        Ok(())
+    }
+
+    // Like set_audit_log(), appending to the artifact called 'name' in 'sink', which has to support
+    // appending (see OutputSink::append())
+    pub fn set_audit_sink<S: OutputSink + ?Sized>(&self, sink: &S, name: &str) -> Result<()> {
+        self.set_audit_writer(sink.append(name)?);
+        Ok(())
+    }
+
+    // Like set_audit_log(), writing somewhere other than a file
+    pub fn set_audit_writer<W: Write + 'static>(&self, out: W) {
+        *self.audit_log.borrow_mut() = Some(AuditLog { out: Box::new(out) });
+    }
+
+    pub fn stop_audit_log(&self) {
+        *self.audit_log.borrow_mut() = None;
+    }
+
+    // Whether the connection refuses to send commands which change the target
+    pub fn is_read_only(&self) -> bool {
+        self.read_only
+    }
+
+    // Called for every command before it's sent. An error means it mustn't be.
+    pub(super) fn audit(&self, command_set: u8, command: u8, data: &[u8]) -> Result<()> {
+        if self.read_only {
+            check_read_only(command_set, command)?;
+        }
+        let mut log = self.audit_log.borrow_mut();
+        let log = match log.as_mut() {
+            Some(log) => log,
+            None => return Ok(()),
+        };
+        let target = self.peer_addr().to_string();
+        match entry(SystemTime::now(), &target, command_set, command, data) {
+            Some(entry) => {
+                log.out.write_all(entry.as_bytes())?;
+                log.out.flush()
+            }
+            None => Ok(()),
+        }
+    }
+}
+
+impl JdwpJavaVirtualMachine {
+    // See JdwpConnection::set_audit_log()
+    pub fn set_audit_log<P: AsRef<Path>>(&self, path: P) -> Result<()> {
+        self.conn.set_audit_log(path)
+    }
+
+    // See JdwpConnection::set_audit_sink()
+    pub fn set_audit_sink<S: OutputSink + ?Sized>(&self, sink: &S, name: &str) -> Result<()> {
+        self.conn.set_audit_sink(sink, name)
+    }
+}
+
+fn find(
+    commands: &[((u8, u8), &'static str)],
+    command_set: u8,
+    command: u8,
+) -> Option<&'static str> {
+    commands
+        .iter()
+        .find(|&&(cmd, _)| cmd == (command_set, command))
+        .map(|&(_, name)| name)
+}
+
+fn changes_target(command_set: u8, command: u8) -> Option<&'static str> {
+    find(AUDITED, command_set, command)
+}
+
+fn check_read_only(command_set: u8, command: u8) -> Result<()> {
+    let denied = changes_target(command_set, command)
+        .or_else(|| find(READ_ONLY_DENIED, command_set, command));
+    match denied {
+        Some(name) => Err(Error::new(
+            ErrorKind::PermissionDenied,
+            format!("{} isn't allowed on a read-only connection", name),
+        )),
+        None => Ok(()),
+    }
+}
+
+// The line to log for a command, or None if it isn't one which changes the target
+fn entry(
+    time: SystemTime,
+    target: &str,
+    command_set: u8,
+    command: u8,
+    data: &[u8],
+) -> Option<String> {
+    let name = changes_target(command_set, command)?;
+    let since_epoch = time.duration_since(UNIX_EPOCH).unwrap_or_default();
+    let mut entry = format!(
+        "{}.{:03}\t{}\t{}\t",
+        since_epoch.as_secs(),
+        since_epoch.subsec_millis(),
+        target,
+        name
+    );
+    for byte in data {
+        let _ = write!(entry, "{:02x}", byte);
+    }
+    entry.push('\n');
+    Some(entry)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn entries() {
+        let time = UNIX_EPOCH + Duration::from_millis(1_760_000_000_123);
+        assert_eq!(
+            entry(time, "10.0.0.5:8000", 9, 3, &[0x12, 0xa4]).as_deref(),
+            Some("1760000000.123\t10.0.0.5:8000\tObjectReference.SetValues\t12a4\n")
+        );
+        assert_eq!(
+            entry(time, "10.0.0.5:8000", 1, 10, &[]).as_deref(),
+            Some("1760000000.123\t10.0.0.5:8000\tVirtualMachine.Exit\t\n")
+        );
+        // Reading a field changes nothing
+        assert_eq!(entry(time, "10.0.0.5:8000", 9, 2, &[0x12]), None);
+    }
+
+    #[test]
+    fn read_only() {
+        let err = check_read_only(11, 10).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::PermissionDenied);
+        assert!(err.to_string().contains("ThreadReference.Stop"), "{}", err);
+
+        let refused = [
+            ((3, 4), "ClassType.NewInstance"),
+            ((4, 1), "ArrayType.NewInstance"),
+            ((1, 11), "VirtualMachine.CreateString"),
+            ((9, 7), "ObjectReference.DisableCollection"),
+            ((15, 1), "EventRequest.Set"),
+            ((1, 8), "VirtualMachine.Suspend"),
+            ((1, 9), "VirtualMachine.Resume"),
+            ((11, 2), "ThreadReference.Suspend"),
+            ((11, 3), "ThreadReference.Resume"),
+        ];
+        for ((command_set, command), name) in refused {
+            let err = check_read_only(command_set, command).unwrap_err();
+            assert_eq!(err.kind(), ErrorKind::PermissionDenied, "{}", name);
+            assert!(err.to_string().contains(name), "{}", err);
+        }
+
+        // ThreadReference.Frames, ObjectReference.GetValues, VirtualMachine.Dispose and
+        // ObjectReference.EnableCollection, which only undoes DisableCollection
+        for (command_set, command) in [(11, 6), (9, 2), (1, 6), (9, 8)] {
+            assert!(check_read_only(command_set, command).is_ok());
+        }
+        // Only the audited commands are logged
+        let time = UNIX_EPOCH;
+        assert_eq!(entry(time, "10.0.0.5:8000", 1, 8, &[]), None);
+    }
+}
@@ -0,0 +1,265 @@
+//
+// Just enough of the class file format to find the calls a method makes: walking its bytecode an
+// instruction at a time (Method.Bytecodes), and looking up what each invoke instruction names in
+// the class's constant pool (ReferenceType.ConstantPool). See the JVM spec, chapters 4 and 6.
+//
+
+use std::convert::TryInto;
+use std::io::{Error, ErrorKind, Result};
+
+use crate::mutf8;
+
+// invokevirtual, invokespecial and invokestatic come in between
+const INVOKEVIRTUAL: u8 = 0xb6;
+const INVOKEINTERFACE: u8 = 0xb9;
+pub(super) const INVOKEDYNAMIC: u8 = 0xba;
+
+const TABLESWITCH: u8 = 0xaa;
+const LOOKUPSWITCH: u8 = 0xab;
+const WIDE: u8 = 0xc4;
+const IINC: u8 = 0x84;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(super) struct Invoke {
+    pub(super) code_index: u64,
+    pub(super) opcode: u8,
+    // Of the Methodref, InterfaceMethodref or InvokeDynamic entry
+    pub(super) constant: u16,
+}
+
+// Every invoke instruction in a method's bytecode, in order
+pub(super) fn invokes(code: &[u8]) -> Result<Vec<Invoke>> {
+    let mut invokes = vec![];
+    let mut pc = 0;
+    while pc < code.len() {
+        let opcode = code[pc];
+        if (INVOKEVIRTUAL..=INVOKEDYNAMIC).contains(&opcode) {
+            invokes.push(Invoke {
+                code_index: pc as u64,
+                opcode,
+                constant: u16::from_be_bytes(bytes(code, pc + 1)?),
+            });
+        }
+        pc += instruction_length(code, pc)?;
+    }
+    Ok(invokes)
+}
+
+// The length of the instruction at 'pc', including its operands
+fn instruction_length(code: &[u8], pc: usize) -> Result<usize> {
+    // The switches' operands start on a multiple of four bytes from the start of the method
+    let padding = 3 - pc % 4;
+    let length = match code[pc] {
+        0x10 | 0x12 | 0x15..=0x19 | 0x36..=0x3a | 0xa9 | 0xbc => 2,
+        0x11 | 0x13 | 0x14 | IINC | 0x99..=0xa8 | 0xb2..=0xb8 | 0xbb | 0xbd | 0xc0 | 0xc1 => 3,
+        0xc6 | 0xc7 => 3,
+        0xc5 => 4,
+        INVOKEINTERFACE | INVOKEDYNAMIC | 0xc8 | 0xc9 => 5,
+        WIDE => match code.get(pc + 1) {
+            Some(&IINC) => 6,
+            _ => 4,
+        },
+        TABLESWITCH => {
+            let operands = pc + 1 + padding;
+            let low = i32::from_be_bytes(bytes(code, operands + 4)?);
+            let high = i32::from_be_bytes(bytes(code, operands + 8)?);
+            let cases = (i64::from(high) - i64::from(low) + 1).max(0) as usize;
+            1 + padding + 12 + cases * 4
+        }
+        LOOKUPSWITCH => {
+            let operands = pc + 1 + padding;
+            let pairs = i32::from_be_bytes(bytes(code, operands + 4)?).max(0) as usize;
+            1 + padding + 8 + pairs * 8
+        }
+        _ => 1,
+    };
+    if pc + length > code.len() {
+        return Err(truncated());
+    }
+    Ok(length)
+}
+
+fn bytes<const N: usize>(code: &[u8], at: usize) -> Result<[u8; N]> {
+    code.get(at..at + N)
+        .map(|b| b.try_into().unwrap())
+        .ok_or_else(truncated)
+}
+
+fn truncated() -> Error {
+    Error::new(ErrorKind::InvalidData, "Truncated bytecode")
+}
+
+enum Constant {
+    Utf8(String),
+    Class(u16),
+    NameAndType(u16, u16),
+    // Fieldref, Methodref and InterfaceMethodref: the class and the NameAndType
+    MemberRef(u16, u16),
+    // The NameAndType (the bootstrap method it also names isn't needed)
+    InvokeDynamic(u16),
+    Other,
+}
+
+pub(super) struct ConstantPool {
+    // Index 0 isn't used, and neither is the one after each Long and Double
+    constants: Vec<Constant>,
+}
+
+impl ConstantPool {
+    pub(super) fn parse(count: i32, data: &[u8]) -> Result<ConstantPool> {
+        let mut constants = vec![Constant::Other];
+        let mut at = 0;
+        let u2 = |at: usize| -> Result<u16> { Ok(u16::from_be_bytes(bytes(data, at)?)) };
+        while constants.len() < count.max(0) as usize {
+            let tag = *data.get(at).ok_or_else(truncated)?;
+            let (constant, length) = match tag {
+                1 => {
+                    let length = usize::from(u2(at + 1)?);
+                    let text = data.get(at + 3..at + 3 + length).ok_or_else(truncated)?;
+                    let text = mutf8::decode(text);
+                    (Constant::Utf8(text), 3 + length)
+                }
+                7 => (Constant::Class(u2(at + 1)?), 3),
+                9..=11 => (Constant::MemberRef(u2(at + 1)?, u2(at + 3)?), 5),
+                12 => (Constant::NameAndType(u2(at + 1)?, u2(at + 3)?), 5),
+                18 => (Constant::InvokeDynamic(u2(at + 3)?), 5),
+                // Integer, Float, and Dynamic
+                3 | 4 | 17 => (Constant::Other, 5),
+                // Long and Double, which take up two entries
+                5 | 6 => {
+                    constants.push(Constant::Other);
+                    (Constant::Other, 9)
+                }
+                // String, MethodType, Module and Package
+                8 | 16 | 19 | 20 => (Constant::Other, 3),
+                // MethodHandle
+                15 => (Constant::Other, 4),
+                _ => {
+                    return Err(Error::new(
+                        ErrorKind::InvalidData,
+                        format!("Unknown constant pool tag {}", tag),
+                    ))
+                }
+            };
+            constants.push(constant);
+            at += length;
+        }
+        Ok(ConstantPool { constants })
+    }
+
+    // The class (in JNI form, e.g. java/util/List), name and descriptor of the method an invoke
+    // instruction calls. The class is None for invokedynamic.
+    pub(super) fn method(&self, index: u16) -> Option<(Option<&str>, &str, &str)> {
+        let (class, name_and_type) = match self.constants.get(usize::from(index))? {
+            Constant::MemberRef(class, name_and_type) => match self.get(*class)? {
+                Constant::Class(name) => (Some(self.utf8(*name)?), *name_and_type),
+                _ => return None,
+            },
+            Constant::InvokeDynamic(name_and_type) => (None, *name_and_type),
+            _ => return None,
+        };
+        match self.get(name_and_type)? {
+            Constant::NameAndType(name, descriptor) => {
+                Some((class, self.utf8(*name)?, self.utf8(*descriptor)?))
+            }
+            _ => None,
+        }
+    }
+
+    // Whether there's a Utf8 entry of exactly 'text', e.g. an attribute's name, which has to be
+    // in the pool for the class to have that attribute
+    pub(super) fn has_utf8(&self, text: &str) -> bool {
+        self.constants
+            .iter()
+            .any(|constant| matches!(constant, Constant::Utf8(t) if t == text))
+    }
+
+    // The classes the pool names, in JNI form
+    pub(super) fn classes(&self) -> impl Iterator<Item = &str> {
+        self.constants
+            .iter()
+            .filter_map(move |constant| match constant {
+                Constant::Class(name) => self.utf8(*name),
+                _ => None,
+            })
+    }
+
+    fn get(&self, index: u16) -> Option<&Constant> {
+        self.constants.get(usize::from(index))
+    }
+
+    fn utf8(&self, index: u16) -> Option<&str> {
+        match self.get(index)? {
+            Constant::Utf8(text) => Some(text),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn finds_invokes() {
+        let code = [
+            0x2a, // aload_0
+            0xb7, 0x00, 0x01, // invokespecial #1
+            0x10, 0x05, // bipush 5
+            0xaa, 0x00, // tableswitch, padded to index 8
+            0x00, 0x00, 0x00, 0x14, // default
+            0x00, 0x00, 0x00, 0x00, // low
+            0x00, 0x00, 0x00, 0x01, // high
+            0x00, 0x00, 0x00, 0x10, // 0
+            0x00, 0x00, 0x00, 0x12, // 1
+            0xc4, 0x84, 0x01, 0x00, 0x00, 0x01, // wide iinc
+            0xb9, 0x00, 0x02, 0x01, 0x00, // invokeinterface #2
+            0xb1, // return
+        ];
+        let found = invokes(&code).unwrap();
+        assert_eq!(
+            found,
+            [
+                Invoke {
+                    code_index: 1,
+                    // invokespecial
+                    opcode: 0xb7,
+                    constant: 1
+                },
+                Invoke {
+                    code_index: 34,
+                    opcode: INVOKEINTERFACE,
+                    constant: 2
+                },
+            ]
+        );
+        assert!(invokes(&code[..36]).is_err());
+    }
+
+    #[test]
+    fn constant_pool() {
+        let mut data = vec![];
+        // #1 Methodref #2.#4, #2 Class #3, #3 Utf8, #4 NameAndType #5:#6, #5 and #6 Utf8
+        data.extend([10, 0, 2, 0, 4, 7, 0, 3]);
+        data.extend([1, 0, 14]);
+        data.extend(b"java/util/List");
+        data.extend([12, 0, 5, 0, 6, 1, 0, 3]);
+        data.extend(b"add");
+        data.extend([1, 0, 21]);
+        data.extend(b"(Ljava/lang/Object;)Z");
+        // #7 and #8 Long, #9 InvokeDynamic 0:#4
+        data.extend([5, 0, 0, 0, 0, 0, 0, 0, 1, 18, 0, 0, 0, 4]);
+        let pool = ConstantPool::parse(10, &data).unwrap();
+        assert_eq!(
+            pool.method(1),
+            Some((Some("java/util/List"), "add", "(Ljava/lang/Object;)Z"))
+        );
+        assert_eq!(pool.method(9), Some((None, "add", "(Ljava/lang/Object;)Z")));
+        assert_eq!(pool.method(2), None);
+        let classes: Vec<&str> = pool.classes().collect();
+        assert_eq!(classes, ["java/util/List"]);
+        assert!(pool.has_utf8("add"));
+        assert!(!pool.has_utf8("ad"));
+        assert!(ConstantPool::parse(11, &data).is_err());
+    }
+}
@@ -0,0 +1,265 @@
+//
+// Android's VMs (ART, and Dalvik before it) speak DDM, the Dalvik Debug Monitor protocol, over the
+// same connection as JDWP. It's what Android Studio uses for things JDWP can't do, like finding
+// out which app a process is. DDM messages are "chunks": a four character type, a length and the
+// data. They're sent in either direction as commands in command set 0xC7, so the VM can send them
+// at any time, and they have to be recognised so they aren't mistaken for events.
+//
+// To attach to an app, make its JDWP port reachable first, e.g.
+//     adb forward tcp:8700 jdwp:<pid>
+//
+// ART still calls itself Dalvik in VirtualMachine.Version, and leaves out a lot of the optional
+// capabilities, which JdwpConnection::capabilities() reports.
+//
+
+use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
+use std::io::{Cursor, Read, Result};
+
+use super::{protocol_err, unsupported_err, JdwpConnection, JdwpJavaVirtualMachine};
+
+pub(super) const DDM_COMMAND_SET: u8 = 0xC7;
+pub(super) const DDM_COMMAND: u8 = 0x01;
+
+// The VM can send chunks nobody asked for (e.g. APNM when the app's name changes). Only the most
+// recent of these are kept.
+const MAX_QUEUED_CHUNKS: usize = 256;
+
+const HELO_PROTOCOL_VERSION: u32 = 1;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DdmChunk {
+    // e.g. "HELO"
+    pub chunk_type: String,
+    pub data: Vec<u8>,
+}
+
+// From the reply to a HELO chunk
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AndroidProcess {
+    pub pid: u32,
+    // e.g. "Android Runtime 2.1.0"
+    pub vm_identity: String,
+    // The package name, or "<pre-initialized>" if the app hasn't got that far yet
+    pub app_name: String,
+}
+
+impl JdwpConnection {
+    pub fn is_android(&self) -> bool {
+        self.vm_name == "Dalvik"
+    }
+
+    // Send a chunk to the VM, returning the chunks in its reply
+    pub fn ddm_request(&self, chunk_type: &str, data: &[u8]) -> Result<Vec<DdmChunk>> {
+        if !self.is_android() {
+            return Err(unsupported_err(&format!(
+                "DDM is only spoken by Android VMs, not {} {}",
+                self.vm_name, self.vm_version
+            )));
+        }
+        let mut buf = vec![];
+        write_chunk(&mut buf, chunk_type, data)?;
+        let reply = self.execute_cmd(DDM_COMMAND_SET, DDM_COMMAND, &buf)?;
+        read_chunks(&reply)
+    }
+
+    // The chunks the VM has sent without being asked, oldest first
+    pub fn take_ddm_chunks(&self) -> Vec<DdmChunk> {
+        self.ddm_chunks.borrow_mut().drain(..).collect()
+    }
+
+    pub(super) fn queue_ddm_chunks(&self, data: &[u8]) -> Result<()> {
+        let mut queue = self.ddm_chunks.borrow_mut();
+        queue.extend(read_chunks(data)?);
+        while queue.len() > MAX_QUEUED_CHUNKS {
+            queue.pop_front();
+        }
+        Ok(())
+    }
+}
+
+impl JdwpJavaVirtualMachine {
+    // Which app this is. Only works on Android.
+    pub fn android_process(&self) -> Result<AndroidProcess> {
+        let reply = self
+            .conn
+            .ddm_request("HELO", &HELO_PROTOCOL_VERSION.to_be_bytes())?;
+        let helo = reply
+            .iter()
+            .find(|chunk| chunk.chunk_type == "HELO")
+            .ok_or_else(|| protocol_err("No HELO chunk in the reply to HELO"))?;
+        read_helo(&helo.data)
+    }
+}
+
+fn read_helo(data: &[u8]) -> Result<AndroidProcess> {
+    // Newer VMs add more after this (the user ID, ABI and so on), which we don't need
+    let mut reader = Cursor::new(data);
+    let _protocol_version = reader.read_u32::<BigEndian>()?;
+    let pid = reader.read_u32::<BigEndian>()?;
+    let vm_identity_len = reader.read_u32::<BigEndian>()?;
+    let app_name_len = reader.read_u32::<BigEndian>()?;
+    let vm_identity = read_utf16(&mut reader, vm_identity_len)?;
+    let app_name = read_utf16(&mut reader, app_name_len)?;
+    Ok(AndroidProcess {
+        pid,
+        vm_identity,
+        app_name,
+    })
+}
+
+fn write_chunk(buf: &mut Vec<u8>, chunk_type: &str, data: &[u8]) -> Result<()> {
+    if chunk_type.len() != 4 {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            format!("DDM chunk types are four characters, not '{}'", chunk_type),
+        ));
+    }
+    buf.extend_from_slice(chunk_type.as_bytes());
+    buf.write_u32::<BigEndian>(data.len() as u32)?;
+    buf.extend_from_slice(data);
+    Ok(())
+}
+
+fn read_chunks(data: &[u8]) -> Result<Vec<DdmChunk>> {
+    let mut reader = Cursor::new(data);
+    let mut chunks = vec![];
+    while (reader.position() as usize) < data.len() {
+        let mut chunk_type = [0u8; 4];
+        reader.read_exact(&mut chunk_type)?;
+        let len = reader.read_u32::<BigEndian>()? as usize;
+        // Checked before allocating, since a garbled length could be anything
+        let left = data.len() - reader.position() as usize;
+        if len > left {
+            return Err(protocol_err(&format!(
+                "DDM chunk {} is {} bytes long, but only {} are left",
+                String::from_utf8_lossy(&chunk_type),
+                len,
+                left
+            )));
+        }
+        let mut data = vec![0; len];
+        reader.read_exact(&mut data)?;
+        chunks.push(DdmChunk {
+            chunk_type: String::from_utf8_lossy(&chunk_type).into_owned(),
+            data,
+        });
+    }
+    Ok(chunks)
+}
+
+// DDM strings are UTF-16, with the length in characters given separately
+fn read_utf16<R: Read>(reader: &mut R, len: u32) -> Result<String> {
+    let chars = (0..len)
+        .map(|_| reader.read_u16::<BigEndian>())
+        .collect::<Result<Vec<u16>>>()?;
+    Ok(String::from_utf16_lossy(&chars))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::ErrorKind;
+
+    use super::*;
+    use crate::jdwp::fake;
+
+    fn chunk(chunk_type: &str, data: &[u8]) -> DdmChunk {
+        DdmChunk {
+            chunk_type: chunk_type.to_string(),
+            data: data.to_vec(),
+        }
+    }
+
+    fn helo(pid: u32, vm_identity: &str, app_name: &str) -> Vec<u8> {
+        let mut data = vec![];
+        for n in [HELO_PROTOCOL_VERSION, pid] {
+            data.extend_from_slice(&n.to_be_bytes());
+        }
+        let (vm_identity, app_name): (Vec<u16>, Vec<u16>) = (
+            vm_identity.encode_utf16().collect(),
+            app_name.encode_utf16().collect(),
+        );
+        data.extend_from_slice(&(vm_identity.len() as u32).to_be_bytes());
+        data.extend_from_slice(&(app_name.len() as u32).to_be_bytes());
+        for c in vm_identity.iter().chain(&app_name) {
+            data.extend_from_slice(&c.to_be_bytes());
+        }
+        data
+    }
+
+    #[test]
+    fn chunks() {
+        let mut buf = vec![];
+        write_chunk(&mut buf, "HELO", &[0, 0, 0, 1]).unwrap();
+        write_chunk(&mut buf, "APNM", &[]).unwrap();
+        assert_eq!(&buf[..12], b"HELO\0\0\0\x04\0\0\0\x01");
+        assert_eq!(
+            read_chunks(&buf).unwrap(),
+            vec![chunk("HELO", &[0, 0, 0, 1]), chunk("APNM", &[])]
+        );
+        assert_eq!(read_chunks(&[]).unwrap(), vec![]);
+
+        let e = write_chunk(&mut buf, "HEL", &[]).err().unwrap();
+        assert_eq!(e.kind(), ErrorKind::InvalidInput);
+    }
+
+    #[test]
+    fn truncated_chunks() {
+        let mut buf = vec![];
+        write_chunk(&mut buf, "HELO", &[1, 2, 3, 4]).unwrap();
+        for len in 1..buf.len() {
+            assert!(read_chunks(&buf[..len]).is_err(), "{} bytes", len);
+        }
+        // A length which would be a very large allocation, if it were believed
+        let e = read_chunks(b"HELO\xff\xff\xff\xff").err().unwrap();
+        assert_eq!(
+            e.to_string(),
+            "JDWP Protocol Error: DDM chunk HELO is 4294967295 bytes long, but only 0 are left"
+        );
+    }
+
+    #[test]
+    fn helo_reply() {
+        let mut data = helo(1234, "Android Runtime 2.1.0", "com.example.app");
+        assert_eq!(
+            read_helo(&data).unwrap(),
+            AndroidProcess {
+                pid: 1234,
+                vm_identity: "Android Runtime 2.1.0".to_string(),
+                app_name: "com.example.app".to_string(),
+            }
+        );
+        // Whatever newer VMs add is ignored
+        data.extend_from_slice(&[0, 0, 0, 10]);
+        assert_eq!(read_helo(&data).unwrap().app_name, "com.example.app");
+
+        let data = helo(1234, "Android Runtime 2.1.0", "<pre-initialized>");
+        for len in 0..data.len() {
+            let e = read_helo(&data[..len]).err().unwrap();
+            assert_eq!(e.kind(), ErrorKind::UnexpectedEof);
+        }
+    }
+
+    #[test]
+    fn queued_chunks() {
+        let conn = fake::Answers::default().attach();
+        let mut buf = vec![];
+        for i in 0..MAX_QUEUED_CHUNKS + 2 {
+            write_chunk(&mut buf, "APNM", &(i as u32).to_be_bytes()).unwrap();
+        }
+        conn.queue_ddm_chunks(&buf).unwrap();
+        // Only the most recent are kept
+        let chunks = conn.take_ddm_chunks();
+        assert_eq!(chunks.len(), MAX_QUEUED_CHUNKS);
+        assert_eq!(chunks[0], chunk("APNM", &2u32.to_be_bytes()));
+        assert!(conn.take_ddm_chunks().is_empty());
+        assert!(conn.queue_ddm_chunks(&buf[..5]).is_err());
+    }
+
+    #[test]
+    fn not_android() {
+        let conn = fake::Answers::default().attach();
+        assert!(!conn.is_android());
+        let e = conn.ddm_request("HELO", &[]).err().unwrap();
+        assert_eq!(e.kind(), ErrorKind::Unsupported);
+    }
+}
@@ -0,0 +1,261 @@
+//
+// The diagnostic commands jcmd runs (GC.heap_info, Thread.print, VM.flags and dozens more, see
+// "jcmd <pid> help"), run through the DiagnosticCommand MBean which HotSpot registers with the
+// platform MBean server. Getting at it takes a handful of method invocations in the target, so
+// like JdwpObjectReference::invoke_method() it needs a thread suspended by an event to run them
+// in. A JVM on the same machine can be asked directly instead, see attach::diagnostic_command().
+//
+// The MBean has an operation per command, named by camel casing the command (GC.heap_info is
+// gcHeapInfo), which takes the command's arguments as a String[].
+//
+
+use std::convert::TryInto;
+use std::io::{Error, ErrorKind, Result};
+
+use super::invoke::{find_method, invoke_err};
+use super::{array_reference, array_type, class_object_reference, object_reference};
+use super::{jdwp_error_kind, JdwpErrorKind, Value};
+use super::{name_to_signature, JdwpConnection, JdwpJavaVirtualMachine, JdwpThreadReference};
+use super::{string_reference, virtual_machine};
+use crate::jfr::{self, JfrSettings};
+
+const MBEAN_NAME: &str = "com.sun.management:type=DiagnosticCommand";
+
+impl JdwpJavaVirtualMachine {
+    // Runs a diagnostic command with its arguments, e.g. "GC.class_histogram -all", and returns
+    // its output. The invocations follow the invoke policy (see set_invoke_policy()).
+    pub fn diagnostic_command(
+        &self,
+        thread: &JdwpThreadReference,
+        command: &str,
+    ) -> Result<String> {
+        let conn = self.conn.as_ref();
+        let mut words = command.split_whitespace();
+        let name = words
+            .next()
+            .ok_or_else(|| Error::new(ErrorKind::InvalidInput, "No diagnostic command given"))?;
+        let mut objects = Pinned::new(conn);
+        let thread = thread.thread_id;
+
+        let factory = load_class(conn, thread, "java.lang.management.ManagementFactory")?;
+        let server = invoke_static(
+            conn,
+            thread,
+            factory,
+            "getPlatformMBeanServer",
+            "()Ljavax/management/MBeanServer;",
+            &[],
+        )?;
+        let server = objects.pin(server)?;
+        let object_name = load_class(conn, thread, "javax.management.ObjectName")?;
+        let mbean_name = objects.string(MBEAN_NAME)?;
+        let mbean = invoke_static(
+            conn,
+            thread,
+            object_name,
+            "getInstance",
+            "(Ljava/lang/String;)Ljavax/management/ObjectName;",
+            &[mbean_name],
+        )?;
+        let mbean = objects.pin(mbean)?;
+
+        let operation = objects.string(&operation_name(name))?;
+        let args = words
+            .map(|word| objects.string(word))
+            .collect::<Result<Vec<_>>>()?;
+        let args = objects.array("[Ljava/lang/String;", &args)?;
+        let params = objects.array("[Ljava/lang/Object;", &[args])?;
+        let string_array = objects.string("[Ljava.lang.String;")?;
+        let signature = objects.array("[Ljava/lang/String;", &[string_array])?;
+
+        let server_id = match server {
+            Value::Object(id) => id,
+            _ => return Err(Error::other("There's no platform MBean server")),
+        };
+        let server_class = object_reference::reference_type(conn, server_id)?.type_id;
+        let (class, method) = find_method(
+            conn,
+            server_class,
+            "invoke",
+            "(Ljavax/management/ObjectName;Ljava/lang/String;[Ljava/lang/Object;[Ljava/lang/String;)\
+             Ljava/lang/Object;",
+            false,
+        )?
+        .ok_or_else(|| Error::other("The platform MBean server has no invoke() method"))?;
+        let output = conn
+            .invoke(
+                Some(server_id),
+                class,
+                thread,
+                method,
+                &[mbean, operation, params, signature],
+                "invoke",
+            )
+            .map_err(|e| match jdwp_error_kind(&e) {
+                // The MBean doesn't say much about what was wrong
+                Some(JdwpErrorKind::InvokeFailed) => invoke_err(format!(
+                    "{} failed: {}. The \"help\" command lists the commands this JVM has, \
+                     and \"help {}\" describes the arguments it takes.",
+                    name, e, name
+                )),
+                _ => e,
+            })?;
+        match output {
+            Value::Object(output) => Ok(string_reference::value(conn, output)?.string_value),
+            _ => Ok(String::new()),
+        }
+    }
+
+    // Starts a Flight Recorder recording, returning what JFR.start had to say about it
+    pub fn jfr_start(
+        &self,
+        thread: &JdwpThreadReference,
+        settings: &JfrSettings,
+    ) -> Result<String> {
+        self.diagnostic_command(thread, &settings.start_command())
+    }
+
+    // See jfr::dump_command()
+    pub fn jfr_dump(
+        &self,
+        thread: &JdwpThreadReference,
+        name: &str,
+        filename: &str,
+    ) -> Result<String> {
+        self.diagnostic_command(thread, &jfr::dump_command(name, filename))
+    }
+
+    // See jfr::stop_command()
+    pub fn jfr_stop(
+        &self,
+        thread: &JdwpThreadReference,
+        name: &str,
+        filename: Option<&str>,
+    ) -> Result<String> {
+        self.diagnostic_command(thread, &jfr::stop_command(name, filename))
+    }
+}
+
+// Objects we create in the target, which it's free to collect until they're used unless told
+// otherwise
+pub(super) struct Pinned<'a> {
+    conn: &'a JdwpConnection,
+    objects: Vec<u64>,
+}
+
+impl<'a> Pinned<'a> {
+    pub(super) fn new(conn: &'a JdwpConnection) -> Self {
+        Pinned {
+            conn,
+            objects: vec![],
+        }
+    }
+
+    pub(super) fn pin(&mut self, value: Value) -> Result<Value> {
+        if let Some(id) = value.object_id() {
+            self.conn.disable_collection(id, "method invocation")?;
+            self.objects.push(id);
+        }
+        Ok(value)
+    }
+
+    fn string(&mut self, s: &str) -> Result<Value> {
+        let id = virtual_machine::create_string(self.conn, s)?.string_object;
+        self.pin(Value::Object(id))
+    }
+
+    fn array(&mut self, signature: &str, elements: &[Value]) -> Result<Value> {
+        let class = virtual_machine::classes_by_signature(self.conn, signature)?
+            .classes
+            .first()
+            .map(|c| c.type_id)
+            .ok_or_else(|| Error::other(format!("{} isn't loaded", signature)))?;
+        let length = elements.len().try_into().unwrap();
+        let array = array_type::new_instance(self.conn, class, length)?.new_array;
+        let array = self.pin(array)?;
+        // Even setting no elements is out of bounds for an empty array
+        if let (Value::Object(id), false) = (&array, elements.is_empty()) {
+            let elements: Vec<u64> = elements
+                .iter()
+                .map(|element| element.object_id().unwrap_or(0))
+                .collect();
+            array_reference::set_values(self.conn, *id, 0, &elements)?;
+        }
+        Ok(array)
+    }
+}
+
+impl Drop for Pinned<'_> {
+    fn drop(&mut self) {
+        for &id in &self.objects {
+            // Nothing to be done if the target has gone
+            let _ = self.conn.enable_collection(id);
+        }
+    }
+}
+
+// Loading the class if need be, with Class.forName()
+pub(super) fn load_class(conn: &JdwpConnection, thread: u64, name: &str) -> Result<u64> {
+    let signature = name_to_signature(name);
+    if let Some(class) = virtual_machine::classes_by_signature(conn, &signature)?
+        .classes
+        .first()
+    {
+        return Ok(class.type_id);
+    }
+    let class_class = virtual_machine::classes_by_signature(conn, "Ljava/lang/Class;")?
+        .classes
+        .first()
+        .map(|c| c.type_id)
+        .ok_or_else(|| Error::other("java.lang.Class isn't loaded"))?;
+    let name = virtual_machine::create_string(conn, name)?.string_object;
+    let class = invoke_static(
+        conn,
+        thread,
+        class_class,
+        "forName",
+        "(Ljava/lang/String;)Ljava/lang/Class;",
+        &[Value::Object(name)],
+    )?;
+    match class {
+        Value::ClassObject(class) | Value::Object(class) => {
+            Ok(class_object_reference::reflected_type(conn, class)?.type_id)
+        }
+        _ => Err(Error::other("Class.forName() returned null")),
+    }
+}
+
+pub(super) fn invoke_static(
+    conn: &JdwpConnection,
+    thread: u64,
+    class: u64,
+    name: &str,
+    signature: &str,
+    args: &[Value],
+) -> Result<Value> {
+    let (class, method) = find_method(conn, class, name, signature, true)?
+        .ok_or_else(|| Error::other(format!("No static method {}{}", name, signature)))?;
+    conn.invoke(None, class, thread, method, args, name)
+}
+
+// The MBean operation for a command, as DiagnosticCommandImpl names them: lower case up to the
+// first '.' or '_', after which each '.' or '_' is dropped and the next character made upper case
+fn operation_name(command: &str) -> String {
+    let mut operation = String::with_capacity(command.len());
+    let mut lower = true;
+    let mut upper = false;
+    for c in command.chars() {
+        if c == '.' || c == '_' {
+            lower = false;
+            upper = true;
+        } else if upper {
+            upper = false;
+            operation.extend(c.to_uppercase());
+        } else if lower {
+            operation.extend(c.to_lowercase());
+        } else {
+            operation.push(c);
+        }
+    }
+    operation
+}
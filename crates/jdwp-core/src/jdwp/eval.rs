@@ -0,0 +1,587 @@
+//
+// Evaluating expressions (see crate::expr) in a suspended stack frame, e.g. for breakpoint
+// conditions. Names are looked up the way javac would: local variables first, then fields of the
+// frame's class (and its superclasses), then classes, so 'java.lang.Integer.MAX_VALUE' and
+// 'Integer.MAX_VALUE' both work.
+//
+// The only methods which can be called are a few on collections (see collections.rs), and a string
+// can only be compared with another. Comparing a String object with a string literal compares the
+// contents rather than identity, since that's what anyone writing name == "main" means.
+//
+// Boxed primitives (java.lang.Integer, Boolean, ...) are unboxed wherever Java would unbox them:
+// in arithmetic, in comparisons with a primitive, and as the operands of !, && and ||. So
+// 'count > 100' works whether count is an int or an Integer. As in Java, == between two boxes
+// compares identity.
+//
+
+use std::cmp::Ordering;
+use std::collections::HashMap;
+use std::io::{Error, ErrorKind, Result};
+
+use super::{array_reference, object_reference, reference_type, string_reference};
+use super::{error_code, has_error_code, name_to_signature, protocol_err};
+use super::{thread_reference, virtual_machine};
+use super::{JdwpConnection, JdwpLocalVariable, JdwpReferenceType, JdwpStackFrame};
+use crate::expr::{BinaryOp, Expr, Literal, UnaryOp};
+use crate::model::{Field, Modifiers, ReferenceType, TypeComponent, Value};
+
+// The classes Java unboxes, by signature
+const BOXES: [&str; 8] = [
+    "Ljava/lang/Boolean;",
+    "Ljava/lang/Byte;",
+    "Ljava/lang/Character;",
+    "Ljava/lang/Short;",
+    "Ljava/lang/Integer;",
+    "Ljava/lang/Long;",
+    "Ljava/lang/Float;",
+    "Ljava/lang/Double;",
+];
+
+impl JdwpStackFrame {
+    // Like JdwpStackFrame itself, only usable until the frame's thread is resumed. Evaluating
+    // something which has to invoke a method (see collections.rs) runs the thread, after which
+    // this frame, like any other of the thread's, has to be fetched again.
+    pub fn evaluate(&self, expr: &str) -> Result<Value> {
+        let expr = Expr::parse(expr).map_err(|e| Error::new(ErrorKind::InvalidInput, e))?;
+        self.evaluate_expr(&expr)
+    }
+
+    // For expressions evaluated over and over, e.g. a condition checked at every hit of a
+    // breakpoint, so they're only parsed once
+    pub fn evaluate_expr(&self, expr: &Expr) -> Result<Value> {
+        let mut evaluator = Evaluator {
+            frame: self,
+            refetched: None,
+            locals: None,
+            field_ids: HashMap::new(),
+        };
+        match evaluator.eval(expr)? {
+            Operand::Value(value) => Ok(value),
+            Operand::Text(_) => Err(eval_err(
+                "A string literal can only be compared, not be the result",
+            )),
+        }
+    }
+}
+
+// The primitive value of a java.lang.Integer, Boolean etc. None for any other object.
+pub(super) fn unbox(conn: &JdwpConnection, object_id: u64) -> Result<Option<Value>> {
+    let class_id = object_reference::reference_type(conn, object_id)?.type_id;
+    let signature = reference_type::signature(conn, class_id)?.signature;
+    boxed_value(conn, object_id, class_id, &signature)
+}
+
+// As unbox(), for callers which already know the object's class
+pub(super) fn boxed_value(
+    conn: &JdwpConnection,
+    object_id: u64,
+    class_id: u64,
+    signature: &str,
+) -> Result<Option<Value>> {
+    if !BOXES.contains(&signature) {
+        return Ok(None);
+    }
+    // The box classes are final, so the field is always declared by the object's own class
+    let field = reference_type::fields(conn, class_id)?
+        .fields
+        .into_iter()
+        .find(|f| f.name == "value" && f.mod_bits as u32 & Modifiers::STATIC == 0)
+        .ok_or_else(|| protocol_err("Boxed primitive class has no 'value' field"))?;
+    object_reference::get_values(conn, object_id, &[field.field_id])?
+        .values
+        .pop()
+        .map(Some)
+        .ok_or_else(|| protocol_err("GetValues returned no values"))
+}
+
+mod collections;
+
+fn eval_err(msg: &str) -> Error {
+    Error::new(ErrorKind::InvalidInput, msg.to_string())
+}
+
+enum Operand {
+    Value(Value),
+    // A string literal, which (with nothing invoked in the target) can't be made into a String
+    Text(String),
+}
+
+// Values after unboxing and Java's binary numeric promotion
+#[derive(Clone, Copy)]
+enum Number {
+    Int(i32),
+    Long(i64),
+    Float(f32),
+    Double(f64),
+}
+
+impl Number {
+    fn from_value(value: &Value) -> Option<Number> {
+        Some(match *value {
+            Value::Byte(b) => Number::Int(i32::from(b)),
+            Value::Char(c) => Number::Int(i32::from(c)),
+            Value::Short(s) => Number::Int(i32::from(s)),
+            Value::Integer(i) => Number::Int(i),
+            Value::Long(l) => Number::Long(l),
+            Value::Float(f) => Number::Float(f),
+            Value::Double(d) => Number::Double(d),
+            _ => return None,
+        })
+    }
+
+    fn as_double(self) -> f64 {
+        match self {
+            Number::Int(i) => f64::from(i),
+            Number::Long(l) => l as f64,
+            Number::Float(f) => f64::from(f),
+            Number::Double(d) => d,
+        }
+    }
+
+    fn as_float(self) -> f32 {
+        match self {
+            Number::Int(i) => i as f32,
+            Number::Long(l) => l as f32,
+            Number::Float(f) => f,
+            Number::Double(d) => d as f32,
+        }
+    }
+
+    fn as_long(self) -> i64 {
+        match self {
+            Number::Int(i) => i64::from(i),
+            Number::Long(l) => l,
+            // Never needed, floats and doubles are never promoted to longs
+            Number::Float(f) => f as i64,
+            Number::Double(d) => d as i64,
+        }
+    }
+
+    // Both operands converted to the wider of their two types
+    fn promote(a: Number, b: Number) -> (Number, Number) {
+        match (a, b) {
+            (Number::Double(_), _) | (_, Number::Double(_)) => {
+                (Number::Double(a.as_double()), Number::Double(b.as_double()))
+            }
+            (Number::Float(_), _) | (_, Number::Float(_)) => {
+                (Number::Float(a.as_float()), Number::Float(b.as_float()))
+            }
+            (Number::Long(_), _) | (_, Number::Long(_)) => {
+                (Number::Long(a.as_long()), Number::Long(b.as_long()))
+            }
+            _ => (a, b),
+        }
+    }
+
+    // None when either is NaN
+    fn compare(a: Number, b: Number) -> Option<Ordering> {
+        match Number::promote(a, b) {
+            (Number::Int(a), Number::Int(b)) => Some(a.cmp(&b)),
+            (Number::Long(a), Number::Long(b)) => Some(a.cmp(&b)),
+            (Number::Float(a), Number::Float(b)) => a.partial_cmp(&b),
+            (a, b) => a.as_double().partial_cmp(&b.as_double()),
+        }
+    }
+
+    // Integer overflow wraps, as in Java
+    fn arithmetic(op: BinaryOp, a: Number, b: Number) -> Result<Value> {
+        let div_by_zero = || eval_err("/ by zero");
+        Ok(match Number::promote(a, b) {
+            (Number::Int(a), Number::Int(b)) => Value::Integer(match op {
+                BinaryOp::Add => a.wrapping_add(b),
+                BinaryOp::Sub => a.wrapping_sub(b),
+                BinaryOp::Mul => a.wrapping_mul(b),
+                _ if b == 0 => return Err(div_by_zero()),
+                BinaryOp::Div => a.wrapping_div(b),
+                _ => a.wrapping_rem(b),
+            }),
+            (Number::Long(a), Number::Long(b)) => Value::Long(match op {
+                BinaryOp::Add => a.wrapping_add(b),
+                BinaryOp::Sub => a.wrapping_sub(b),
+                BinaryOp::Mul => a.wrapping_mul(b),
+                _ if b == 0 => return Err(div_by_zero()),
+                BinaryOp::Div => a.wrapping_div(b),
+                _ => a.wrapping_rem(b),
+            }),
+            (Number::Float(a), Number::Float(b)) => Value::Float(match op {
+                BinaryOp::Add => a + b,
+                BinaryOp::Sub => a - b,
+                BinaryOp::Mul => a * b,
+                BinaryOp::Div => a / b,
+                _ => a % b,
+            }),
+            (a, b) => {
+                let (a, b) = (a.as_double(), b.as_double());
+                Value::Double(match op {
+                    BinaryOp::Add => a + b,
+                    BinaryOp::Sub => a - b,
+                    BinaryOp::Mul => a * b,
+                    BinaryOp::Div => a / b,
+                    _ => a % b,
+                })
+            }
+        })
+    }
+}
+
+struct Evaluator<'a> {
+    frame: &'a JdwpStackFrame,
+    // The frame fetched again after a method was invoked, since invoking throws away the
+    // thread's frames
+    refetched: Option<JdwpStackFrame>,
+    // Fetched the first time a name is looked up
+    locals: Option<Vec<JdwpLocalVariable>>,
+    // Instance fields by class and name, for walking the insides of collections
+    field_ids: HashMap<(u64, String), u64>,
+}
+
+impl Evaluator<'_> {
+    fn conn(&self) -> &JdwpConnection {
+        self.frame.conn.as_ref()
+    }
+
+    fn frame(&self) -> &JdwpStackFrame {
+        self.refetched.as_ref().unwrap_or(self.frame)
+    }
+
+    // The thread is back where it was once an invoked method returns, so the frame is at the
+    // same depth
+    fn refetch_frame(&mut self) -> Result<()> {
+        let frame = self.frame();
+        let conn = frame.conn.clone();
+        let fetched = thread_reference::frames(&conn, frame.thread_id, frame.depth, 1)?
+            .frames
+            .pop()
+            .ok_or_else(|| protocol_err("Frames returned no frames"))?;
+        let refetched = JdwpStackFrame {
+            conn: conn.clone(),
+            thread_id: frame.thread_id,
+            frame_id: fetched.frame_id,
+            epoch: conn.epoch.get(),
+            location: fetched.location,
+            depth: frame.depth,
+        };
+        self.refetched = Some(refetched);
+        self.locals = None;
+        Ok(())
+    }
+
+    fn eval(&mut self, expr: &Expr) -> Result<Operand> {
+        Ok(Operand::Value(match expr {
+            Expr::Literal(literal) => match literal {
+                Literal::Int(i) => Value::Integer(*i),
+                Literal::Long(l) => Value::Long(*l),
+                Literal::Float(f) => Value::Float(*f),
+                Literal::Double(d) => Value::Double(*d),
+                Literal::Boolean(b) => Value::Boolean(*b),
+                Literal::Char(c) => Value::Char(*c),
+                Literal::String(s) => return Ok(Operand::Text(s.clone())),
+                Literal::Null => Value::Null,
+            },
+            Expr::Name(names) => self.name(names)?,
+            Expr::Field(expr, name) => {
+                let value = self.value(expr)?;
+                self.field_of(&value, name)?
+            }
+            Expr::Index(expr, index) => {
+                let value = self.value(expr)?;
+                let index = self.eval(index)?;
+                self.index(&value, index)?
+            }
+            Expr::Call(expr, method, args) => {
+                let value = self.value(expr)?;
+                let args = args
+                    .iter()
+                    .map(|arg| self.eval(arg))
+                    .collect::<Result<Vec<_>>>()?;
+                self.call(&value, method, args)?
+            }
+            Expr::Unary(UnaryOp::Not, expr) => Value::Boolean(!self.boolean(expr)?),
+            Expr::Unary(UnaryOp::Neg, expr) => {
+                let value = self.unboxed(expr)?;
+                match Number::from_value(&value) {
+                    Some(Number::Int(i)) => Value::Integer(i.wrapping_neg()),
+                    Some(Number::Long(l)) => Value::Long(l.wrapping_neg()),
+                    Some(Number::Float(f)) => Value::Float(-f),
+                    Some(Number::Double(d)) => Value::Double(-d),
+                    None => {
+                        return Err(eval_err(&format!(
+                            "bad operand for -: {}",
+                            type_name(&value)
+                        )))
+                    }
+                }
+            }
+            Expr::Binary(BinaryOp::And, a, b) => {
+                Value::Boolean(self.boolean(a)? && self.boolean(b)?)
+            }
+            Expr::Binary(BinaryOp::Or, a, b) => {
+                Value::Boolean(self.boolean(a)? || self.boolean(b)?)
+            }
+            Expr::Binary(op @ (BinaryOp::Eq | BinaryOp::Ne), a, b) => {
+                let a = self.eval(a)?;
+                let b = self.eval(b)?;
+                Value::Boolean(self.equal(a, b)? == (*op == BinaryOp::Eq))
+            }
+            Expr::Binary(op, a, b) => {
+                let (a, b) = (self.unboxed(a)?, self.unboxed(b)?);
+                let bad_operands = || {
+                    eval_err(&format!(
+                        "bad operand types for {}: {} and {}",
+                        op,
+                        type_name(&a),
+                        type_name(&b)
+                    ))
+                };
+                let (x, y) = match (Number::from_value(&a), Number::from_value(&b)) {
+                    (Some(x), Some(y)) => (x, y),
+                    _ => return Err(bad_operands()),
+                };
+                let ordering = Number::compare(x, y);
+                match op {
+                    BinaryOp::Lt => Value::Boolean(ordering == Some(Ordering::Less)),
+                    BinaryOp::Le => {
+                        Value::Boolean(matches!(ordering, Some(Ordering::Less | Ordering::Equal)))
+                    }
+                    BinaryOp::Gt => Value::Boolean(ordering == Some(Ordering::Greater)),
+                    BinaryOp::Ge => Value::Boolean(matches!(
+                        ordering,
+                        Some(Ordering::Greater | Ordering::Equal)
+                    )),
+                    _ => Number::arithmetic(*op, x, y)?,
+                }
+            }
+        }))
+    }
+
+    fn value(&mut self, expr: &Expr) -> Result<Value> {
+        match self.eval(expr)? {
+            Operand::Value(value) => Ok(value),
+            Operand::Text(_) => Err(eval_err("A string literal can only be compared")),
+        }
+    }
+
+    // The value, with boxed primitives unboxed
+    fn unboxed(&mut self, expr: &Expr) -> Result<Value> {
+        let value = self.value(expr)?;
+        Ok(match value {
+            Value::Object(id) => unbox(self.conn(), id)?.unwrap_or(value),
+            value => value,
+        })
+    }
+
+    fn boolean(&mut self, expr: &Expr) -> Result<bool> {
+        match self.unboxed(expr)? {
+            Value::Boolean(b) => Ok(b),
+            value => Err(eval_err(&format!(
+                "expected a boolean, not {}",
+                type_name(&value)
+            ))),
+        }
+    }
+
+    fn equal(&mut self, a: Operand, b: Operand) -> Result<bool> {
+        let conn = self.conn();
+        let (a, b) = match (a, b) {
+            (Operand::Text(a), Operand::Text(b)) => return Ok(a == b),
+            (Operand::Text(text), Operand::Value(value))
+            | (Operand::Value(value), Operand::Text(text)) => {
+                return match value {
+                    Value::Null => Ok(false),
+                    Value::Object(id) => Ok(string_value(conn, id)?.is_some_and(|s| s == text)),
+                    value => Err(eval_err(&format!(
+                        "incomparable types: String and {}",
+                        type_name(&value)
+                    ))),
+                }
+            }
+            (Operand::Value(a), Operand::Value(b)) => (a, b),
+        };
+        let is_reference = |v: &Value| v.object_id().is_some() || *v == Value::Null;
+        if is_reference(&a) && is_reference(&b) {
+            return Ok(a.object_id() == b.object_id());
+        }
+        // One side is primitive, so the other is unboxed if it's a box
+        let unbox_value = |value: Value| -> Result<Value> {
+            Ok(match value {
+                Value::Object(id) => unbox(conn, id)?.unwrap_or(value),
+                value => value,
+            })
+        };
+        let (a, b) = (unbox_value(a)?, unbox_value(b)?);
+        match (&a, &b) {
+            (Value::Boolean(x), Value::Boolean(y)) => Ok(x == y),
+            _ => match (Number::from_value(&a), Number::from_value(&b)) {
+                (Some(x), Some(y)) => Ok(Number::compare(x, y) == Some(Ordering::Equal)),
+                _ => Err(eval_err(&format!(
+                    "incomparable types: {} and {}",
+                    type_name(&a),
+                    type_name(&b)
+                ))),
+            },
+        }
+    }
+
+    fn name(&mut self, names: &[String]) -> Result<Value> {
+        let (value, fields) = self.first_name(names)?;
+        fields
+            .iter()
+            .try_fold(value, |value, name| self.field_of(&value, name))
+    }
+
+    // Works out what the start of a dotted name refers to. Returns its value and the rest of
+    // the name, which are fields.
+    fn first_name<'n>(&mut self, names: &'n [String]) -> Result<(Value, &'n [String])> {
+        let first = &names[0];
+        if first == "this" {
+            return match self.frame().this_object()? {
+                Value::Null => Err(eval_err("'this' can't be used in a static method")),
+                this => Ok((this, &names[1..])),
+            };
+        }
+        if let Some(local) = self.locals()?.iter().find(|v| v.name() == first) {
+            return Ok((local.value()?, &names[1..]));
+        }
+        // Fields are looked up in the class the code is in, rather than the class of 'this',
+        // which might be a subclass with fields of the same name
+        let this = match self.frame().this_object()? {
+            Value::Object(id) => Some(id),
+            _ => None,
+        };
+        if let Some(value) = self.field_value(self.frame().location.class_id, this, first)? {
+            return Ok((value, &names[1..]));
+        }
+        for i in 1..names.len() {
+            let class_name = names[..i].join(".");
+            let mut candidates = vec![class_name.clone()];
+            if i == 1 {
+                candidates.push(format!("java.lang.{}", class_name));
+            }
+            for candidate in candidates {
+                let classes = virtual_machine::classes_by_signature(
+                    self.conn(),
+                    &name_to_signature(&candidate),
+                )?
+                .classes;
+                if let Some(class) = classes.first() {
+                    return match self.field_value(class.type_id, None, &names[i])? {
+                        Some(value) => Ok((value, &names[i + 1..])),
+                        None => Err(eval_err(&format!(
+                            "{} has no field called '{}'",
+                            candidate, names[i]
+                        ))),
+                    };
+                }
+            }
+        }
+        Err(eval_err(&format!("cannot find symbol '{}'", first)))
+    }
+
+    // Without local variable information (classes compiled without javac -g), there just aren't
+    // any locals
+    fn locals(&mut self) -> Result<&[JdwpLocalVariable]> {
+        if self.locals.is_none() {
+            let locals = match self.frame().visible_variables() {
+                Ok(locals) => locals,
+                Err(e)
+                    if has_error_code(
+                        &e,
+                        &[error_code::ABSENT_INFORMATION, error_code::NATIVE_METHOD],
+                    ) =>
+                {
+                    vec![]
+                }
+                Err(e) => return Err(e),
+            };
+            self.locals = Some(locals);
+        }
+        Ok(self.locals.as_deref().unwrap_or(&[]))
+    }
+
+    fn field_of(&self, value: &Value, name: &str) -> Result<Value> {
+        match *value {
+            Value::Object(id) | Value::ClassObject(id) => {
+                let conn = self.conn();
+                let class_id = object_reference::reference_type(conn, id)?.type_id;
+                if name == "length"
+                    && reference_type::signature(conn, class_id)?
+                        .signature
+                        .starts_with('[')
+                {
+                    return Ok(Value::Integer(
+                        array_reference::length(conn, id)?.array_length,
+                    ));
+                }
+                self.field_value(class_id, Some(id), name)?
+                    .ok_or_else(|| eval_err(&format!("no field called '{}'", name)))
+            }
+            Value::Null => Err(eval_err(&format!(
+                "null has no fields (reading '{}')",
+                name
+            ))),
+            _ => Err(eval_err(&format!(
+                "{} can't be dereferenced (reading '{}')",
+                type_name(value),
+                name
+            ))),
+        }
+    }
+
+    // The value of the field called 'name' in the class or one of its superclasses, read from
+    // 'object' unless it's static. None if there's no such field.
+    fn field_value(&self, class_id: u64, object: Option<u64>, name: &str) -> Result<Option<Value>> {
+        let class = JdwpReferenceType {
+            conn: self.frame.conn.clone(),
+            class_id,
+        };
+        let declared = match class
+            .all_fields()?
+            .into_iter()
+            .find(|f| f.field.name == name)
+        {
+            Some(declared) => declared,
+            None => return Ok(None),
+        };
+        if declared.field.modifiers()?.is_static() {
+            return Ok(Some(declared.declaring_type.get_value(&declared.field)?));
+        }
+        match object {
+            Some(object_id) => Ok(Some(super::instance_field_value(
+                self.conn(),
+                object_id,
+                &declared.field,
+            )?)),
+            None => Err(eval_err(&format!(
+                "non-static field '{}' can't be read from a static context",
+                declared.field.name()?
+            ))),
+        }
+    }
+}
+
+// The contents of a java.lang.String, None for any other object
+fn string_value(conn: &JdwpConnection, object_id: u64) -> Result<Option<String>> {
+    let class_id = object_reference::reference_type(conn, object_id)?.type_id;
+    if reference_type::signature(conn, class_id)?.signature != "Ljava/lang/String;" {
+        return Ok(None);
+    }
+    Ok(Some(string_reference::value(conn, object_id)?.string_value))
+}
+
+// For error messages, as javac would name the type
+fn type_name(value: &Value) -> &'static str {
+    match value {
+        Value::Boolean(_) => "boolean",
+        Value::Byte(_) => "byte",
+        Value::Char(_) => "char",
+        Value::Short(_) => "short",
+        Value::Integer(_) => "int",
+        Value::Long(_) => "long",
+        Value::Float(_) => "float",
+        Value::Double(_) => "double",
+        Value::Object(_) => "Object",
+        Value::ClassObject(_) => "Class",
+        Value::Null => "null",
+        Value::Void => "void",
+    }
+}
@@ -0,0 +1,662 @@
+//
+// Looking inside collections the way their users do: map["key"], list[3], set.contains(x),
+// size(), isEmpty(), get(), containsKey() and containsValue(), rather than by walking a HashMap's
+// table of nodes by hand.
+//
+// The common implementations (HashMap, ArrayList, HashSet, TreeMap, ConcurrentHashMap, what
+// List.of() and friends return, and the Collections wrappers) are read straight from their
+// fields, which works even when the thread couldn't run any code. Their whole contents are read,
+// so a lookup in a very large collection takes a while. Anything else is asked, by invoking the
+// method in the frame's thread while every other thread stays suspended.
+//
+// Keys and elements are matched the way equals() would match them: by contents for strings and
+// boxed primitives (a literal 1 only matches an Integer, as map.get(1) would), and by identity for
+// classes which don't override equals(). For any other key the collection is asked, even one of
+// the known implementations.
+//
+
+use std::io::Result;
+
+use super::{boxed_value, eval_err, string_value, type_name, Evaluator, Operand};
+use crate::jdwp::invoke::find_method;
+use crate::jdwp::{
+    array_reference, class_type, object_reference, reference_type, string_reference,
+};
+use crate::jdwp::{protocol_err, signature_to_name, virtual_machine, JdwpConnection};
+use crate::model::{Modifiers, Value};
+
+enum Contents {
+    List(Vec<Value>),
+    Set(Vec<Value>),
+    // Keys and values
+    Map(Vec<(Value, Value)>),
+}
+
+// A key or element to look for, in the form it's compared in
+enum Wanted {
+    Null,
+    String(String),
+    // The signature of the box class, and the primitive value
+    Boxed(&'static str, Value),
+    Identity(u64),
+    // An object whose class has its own equals(), which only the target can run
+    Other(u64),
+}
+
+impl Evaluator<'_> {
+    // list[i], map[key] or array[i]
+    pub(super) fn index(&mut self, target: &Value, index: Operand) -> Result<Value> {
+        let object = self.object(target, "indexed")?;
+        let signature = self.signature_of(object)?;
+        if signature.starts_with('[') {
+            return self.array_element(object, &index);
+        }
+        let wanted = self.wanted(&index)?;
+        match self.contents(object)? {
+            Some(Contents::List(elements)) => {
+                let i = list_index(&index, elements.len())?;
+                Ok(elements[i].clone())
+            }
+            Some(Contents::Map(entries)) if !matches!(wanted, Wanted::Other(_)) => {
+                for (key, value) in entries {
+                    if self.matches(&key, &wanted)? {
+                        return Ok(value);
+                    }
+                }
+                Ok(Value::Null)
+            }
+            Some(Contents::Set(_)) => Err(eval_err("a Set can't be indexed, use contains()")),
+            _ => {
+                // Lists have get(int), maps get(Object)
+                if let Operand::Value(i @ Value::Integer(_)) = &index {
+                    if self
+                        .find_method(object, "get", "(I)Ljava/lang/Object;")?
+                        .is_some()
+                    {
+                        return self.invoke(
+                            object,
+                            "get",
+                            "(I)Ljava/lang/Object;",
+                            std::slice::from_ref(i),
+                        );
+                    }
+                }
+                let arg = self.argument(&index)?;
+                self.invoke(
+                    object,
+                    "get",
+                    "(Ljava/lang/Object;)Ljava/lang/Object;",
+                    &[arg],
+                )
+            }
+        }
+    }
+
+    // coll.method(args), for the handful of methods which only look at a collection
+    pub(super) fn call(
+        &mut self,
+        target: &Value,
+        method: &str,
+        mut args: Vec<Operand>,
+    ) -> Result<Value> {
+        if method == "get" && args.len() == 1 {
+            return self.index(target, args.remove(0));
+        }
+        let object = self.object(target, "called")?;
+        let signature = match (method, args.len()) {
+            ("size", 0) => "()I",
+            ("isEmpty", 0) => "()Z",
+            ("contains", 1) | ("containsKey", 1) | ("containsValue", 1) => "(Ljava/lang/Object;)Z",
+            _ => {
+                return Err(eval_err(&format!(
+                    "{}() with {} arguments can't be called. Only size(), isEmpty(), get(), \
+                     contains(), containsKey() and containsValue() on collections can.",
+                    method,
+                    args.len()
+                )))
+            }
+        };
+        let contents = self.contents(object)?;
+        if let Some(contents) = &contents {
+            match (method, contents) {
+                ("size", _) => return Ok(Value::Integer(contents.len() as i32)),
+                ("isEmpty", _) => return Ok(Value::Boolean(contents.len() == 0)),
+                _ => {}
+            }
+        }
+        // Both for sizes of collections we don't know, and lookups we can't do ourselves
+        let wanted = match args.first() {
+            Some(arg) => self.wanted(arg)?,
+            None => Wanted::Other(0),
+        };
+        if !matches!(wanted, Wanted::Other(_)) {
+            let candidates = match (method, contents) {
+                ("contains", Some(Contents::List(values)))
+                | ("contains", Some(Contents::Set(values))) => Some(values),
+                ("containsKey", Some(Contents::Map(entries))) => {
+                    Some(entries.into_iter().map(|(key, _)| key).collect())
+                }
+                ("containsValue", Some(Contents::Map(entries))) => {
+                    Some(entries.into_iter().map(|(_, value)| value).collect())
+                }
+                (_, Some(_)) => {
+                    return Err(eval_err(&format!(
+                        "{}() isn't a method of {}",
+                        method,
+                        signature_to_name(&self.signature_of(object)?)
+                    )))
+                }
+                (_, None) => None,
+            };
+            if let Some(candidates) = candidates {
+                for candidate in candidates {
+                    if self.matches(&candidate, &wanted)? {
+                        return Ok(Value::Boolean(true));
+                    }
+                }
+                return Ok(Value::Boolean(false));
+            }
+        }
+        let args = args
+            .iter()
+            .map(|arg| self.argument(arg))
+            .collect::<Result<Vec<_>>>()?;
+        self.invoke(object, method, signature, &args)
+    }
+
+    fn object(&self, target: &Value, what: &str) -> Result<u64> {
+        match *target {
+            Value::Object(id) | Value::ClassObject(id) => Ok(id),
+            Value::Null => Err(eval_err(&format!("null can't be {}", what))),
+            _ => Err(eval_err(&format!(
+                "{} can't be {}",
+                type_name(target),
+                what
+            ))),
+        }
+    }
+
+    fn signature_of(&self, object: u64) -> Result<String> {
+        let conn = self.conn();
+        let class_id = object_reference::reference_type(conn, object)?.type_id;
+        Ok(reference_type::signature(conn, class_id)?.signature)
+    }
+
+    fn array_element(&mut self, array: u64, index: &Operand) -> Result<Value> {
+        let conn = self.conn();
+        let length = array_reference::length(conn, array)?.array_length;
+        let i = list_index(index, length as usize)?;
+        let mut values = array_reference::get_values(conn, array, i as i32, 1)?
+            .values
+            .0;
+        values
+            .pop()
+            .ok_or_else(|| protocol_err("GetValues returned no values"))
+    }
+
+    fn array_values(&self, array: &Value) -> Result<Vec<Value>> {
+        let conn = self.conn();
+        match *array {
+            Value::Object(id) => {
+                let length = array_reference::length(conn, id)?.array_length;
+                Ok(array_reference::get_values(conn, id, 0, length)?.values.0)
+            }
+            _ => Ok(vec![]),
+        }
+    }
+
+    // The values of instance fields of the object, found by name in its class and superclasses
+    fn fields(&mut self, object: u64, names: &[&str]) -> Result<Vec<Value>> {
+        let conn = self.frame.conn.clone();
+        let class_id = object_reference::reference_type(&conn, object)?.type_id;
+        let mut field_ids = vec![];
+        for &name in names {
+            let key = (class_id, name.to_string());
+            if !self.field_ids.contains_key(&key) {
+                let field_id = instance_field_id(&conn, class_id, name)?;
+                self.field_ids.insert(key.clone(), field_id);
+            }
+            field_ids.push(self.field_ids[&key]);
+        }
+        Ok(object_reference::get_values(&conn, object, &field_ids)?.values)
+    }
+
+    fn field(&mut self, object: u64, name: &str) -> Result<Value> {
+        self.fields(object, &[name])?
+            .pop()
+            .ok_or_else(|| protocol_err("GetValues returned no values"))
+    }
+
+    // None for classes we don't know the insides of
+    fn contents(&mut self, object: u64) -> Result<Option<Contents>> {
+        let signature = self.signature_of(object)?;
+        let class_name = signature_to_name(&signature);
+        let contents = match class_name.as_str() {
+            "java.util.ArrayList" | "java.util.Vector" => {
+                let size = if class_name == "java.util.ArrayList" {
+                    "size"
+                } else {
+                    "elementCount"
+                };
+                let fields = self.fields(object, &["elementData", size])?;
+                let mut elements = self.array_values(&fields[0])?;
+                if let Value::Integer(size) = &fields[1] {
+                    elements.truncate((*size).max(0) as usize);
+                }
+                Contents::List(elements)
+            }
+            "java.util.Arrays$ArrayList" => Contents::List(self.array_field(object, "a")?),
+            "java.util.concurrent.CopyOnWriteArrayList" => {
+                Contents::List(self.array_field(object, "array")?)
+            }
+            "java.util.ImmutableCollections$ListN" => {
+                Contents::List(self.array_field(object, "elements")?)
+            }
+            "java.util.ImmutableCollections$List12" => {
+                Contents::List(self.present(object, &["e0", "e1"])?)
+            }
+            "java.util.LinkedList" => {
+                let mut elements = vec![];
+                let mut node = self.field(object, "first")?;
+                while let Value::Object(id) = node {
+                    let mut fields = self.fields(id, &["item", "next"])?.into_iter();
+                    elements.extend(fields.next());
+                    node = fields.next().unwrap_or(Value::Null);
+                }
+                Contents::List(elements)
+            }
+            "java.util.Collections$SingletonList" => {
+                Contents::List(vec![self.field(object, "element")?])
+            }
+            "java.util.Collections$EmptyList" => Contents::List(vec![]),
+
+            "java.util.HashSet" | "java.util.LinkedHashSet" => self.keys_of(object, "map")?,
+            "java.util.TreeSet" => self.keys_of(object, "m")?,
+            "java.util.concurrent.ConcurrentHashMap$KeySetView" => self.keys_of(object, "map")?,
+            "java.util.ImmutableCollections$SetN" => {
+                let elements = self.array_field(object, "elements")?;
+                Contents::Set(elements.into_iter().filter(|e| *e != Value::Null).collect())
+            }
+            "java.util.ImmutableCollections$Set12" => {
+                Contents::Set(self.present(object, &["e0", "e1"])?)
+            }
+            "java.util.Collections$SingletonSet" => {
+                Contents::Set(vec![self.field(object, "element")?])
+            }
+            "java.util.Collections$EmptySet" => Contents::Set(vec![]),
+
+            "java.util.HashMap" | "java.util.LinkedHashMap" => {
+                let table = self.field(object, "table")?;
+                match self.hash_entries(&table, "value")? {
+                    Some(entries) => Contents::Map(entries),
+                    None => return Ok(None),
+                }
+            }
+            "java.util.Hashtable" => {
+                let table = self.field(object, "table")?;
+                match self.hash_entries(&table, "value")? {
+                    Some(entries) => Contents::Map(entries),
+                    None => return Ok(None),
+                }
+            }
+            "java.util.concurrent.ConcurrentHashMap" => {
+                let table = self.field(object, "table")?;
+                match self.hash_entries(&table, "val")? {
+                    Some(entries) => Contents::Map(entries),
+                    None => return Ok(None),
+                }
+            }
+            "java.util.TreeMap" => {
+                let mut entries = vec![];
+                let mut stack = vec![self.field(object, "root")?];
+                while let Some(node) = stack.pop() {
+                    if let Value::Object(id) = node {
+                        let mut fields = self.fields(id, &["key", "value", "left", "right"])?;
+                        stack.extend(fields.drain(2..));
+                        entries.extend(pair(fields));
+                    }
+                }
+                Contents::Map(entries)
+            }
+            "java.util.ImmutableCollections$MapN" => {
+                // Keys and values alternate, with empty slots left null
+                let table = self.array_field(object, "table")?;
+                Contents::Map(
+                    table
+                        .chunks(2)
+                        .filter(|kv| kv.len() == 2 && kv[0] != Value::Null)
+                        .map(|kv| (kv[0].clone(), kv[1].clone()))
+                        .collect(),
+                )
+            }
+            "java.util.ImmutableCollections$Map1" => Contents::Map(
+                pair(self.fields(object, &["k0", "v0"])?)
+                    .into_iter()
+                    .collect(),
+            ),
+            "java.util.Collections$SingletonMap" => Contents::Map(
+                pair(self.fields(object, &["k", "v"])?)
+                    .into_iter()
+                    .collect(),
+            ),
+            "java.util.Collections$EmptyMap" => Contents::Map(vec![]),
+
+            // Wrappers, which hold the real collection in a field
+            "java.util.Collections$UnmodifiableList"
+            | "java.util.Collections$UnmodifiableRandomAccessList"
+            | "java.util.Collections$SynchronizedList"
+            | "java.util.Collections$SynchronizedRandomAccessList" => {
+                return self.wrapped(object, "list")
+            }
+            "java.util.Collections$UnmodifiableCollection"
+            | "java.util.Collections$UnmodifiableSet"
+            | "java.util.Collections$SynchronizedCollection"
+            | "java.util.Collections$SynchronizedSet" => return self.wrapped(object, "c"),
+            "java.util.Collections$UnmodifiableMap" | "java.util.Collections$SynchronizedMap" => {
+                return self.wrapped(object, "m")
+            }
+            _ => return Ok(None),
+        };
+        Ok(Some(contents))
+    }
+
+    fn array_field(&mut self, object: u64, name: &str) -> Result<Vec<Value>> {
+        let array = self.field(object, name)?;
+        self.array_values(&array)
+    }
+
+    fn wrapped(&mut self, object: u64, name: &str) -> Result<Option<Contents>> {
+        match self.field(object, name)? {
+            Value::Object(inner) => self.contents(inner),
+            _ => Ok(None),
+        }
+    }
+
+    fn keys_of(&mut self, object: u64, map_field: &str) -> Result<Contents> {
+        let keys = match self.field(object, map_field)? {
+            Value::Object(map) => match self.contents(map)? {
+                Some(Contents::Map(entries)) => entries.into_iter().map(|(key, _)| key).collect(),
+                _ => return Err(eval_err("a set's map isn't a known kind of map")),
+            },
+            _ => vec![],
+        };
+        Ok(Contents::Set(keys))
+    }
+
+    // The fields which hold an element. The immutable collections of one element mark the
+    // missing one with null or (in later versions) with the sentinel ImmutableCollections.EMPTY.
+    fn present(&mut self, object: u64, names: &[&str]) -> Result<Vec<Value>> {
+        let conn = self.conn();
+        let mut empty = Value::Null;
+        let classes =
+            virtual_machine::classes_by_signature(conn, "Ljava/util/ImmutableCollections;")?
+                .classes;
+        if let Some(class) = classes.first() {
+            if let Some(value) = self.field_value(class.type_id, None, "EMPTY")? {
+                empty = value;
+            }
+        }
+        Ok(self
+            .fields(object, names)?
+            .into_iter()
+            .filter(|v| *v != Value::Null && *v != empty)
+            .collect())
+    }
+
+    // The entries in a table of hash buckets, each a linked list of nodes. None if a
+    // ConcurrentHashMap is in the middle of resizing, when some of its entries have been moved
+    // to the new table.
+    fn hash_entries(
+        &mut self,
+        table: &Value,
+        value_field: &str,
+    ) -> Result<Option<Vec<(Value, Value)>>> {
+        let mut entries = vec![];
+        for bucket in self.array_values(table)? {
+            let mut node = bucket;
+            while let Value::Object(id) = node {
+                match signature_to_name(&self.signature_of(id)?).as_str() {
+                    // A ConcurrentHashMap bin which has been made into a tree keeps its nodes
+                    // in a list as well
+                    "java.util.concurrent.ConcurrentHashMap$TreeBin" => {
+                        node = self.field(id, "first")?;
+                        continue;
+                    }
+                    "java.util.concurrent.ConcurrentHashMap$ForwardingNode" => return Ok(None),
+                    "java.util.concurrent.ConcurrentHashMap$ReservationNode" => break,
+                    _ => {}
+                }
+                let mut fields = self.fields(id, &["key", value_field, "next"])?;
+                node = fields.pop().unwrap_or(Value::Null);
+                entries.extend(pair(fields));
+            }
+        }
+        Ok(Some(entries))
+    }
+
+    fn wanted(&mut self, operand: &Operand) -> Result<Wanted> {
+        let value = match operand {
+            Operand::Text(text) => return Ok(Wanted::String(text.clone())),
+            Operand::Value(value) => value.clone(),
+        };
+        let id = match value {
+            Value::Null => return Ok(Wanted::Null),
+            Value::Object(id) | Value::ClassObject(id) => id,
+            Value::Void => return Err(eval_err("void isn't a value")),
+            primitive => return Ok(Wanted::Boxed(box_signature(&primitive), primitive)),
+        };
+        let conn = self.conn();
+        let class_id = object_reference::reference_type(conn, id)?.type_id;
+        let signature = reference_type::signature(conn, class_id)?.signature;
+        if signature == "Ljava/lang/String;" {
+            let string = string_reference::value(conn, id)?.string_value;
+            return Ok(Wanted::String(string));
+        }
+        if let Some(value) = boxed_value(conn, id, class_id, &signature)? {
+            return Ok(Wanted::Boxed(box_signature(&value), value));
+        }
+        Ok(if uses_identity_equals(conn, class_id)? {
+            Wanted::Identity(id)
+        } else {
+            Wanted::Other(id)
+        })
+    }
+
+    fn matches(&mut self, candidate: &Value, wanted: &Wanted) -> Result<bool> {
+        let id = match (candidate, wanted) {
+            (Value::Null, Wanted::Null) => return Ok(true),
+            (
+                Value::Object(id) | Value::ClassObject(id),
+                Wanted::Identity(wanted) | Wanted::Other(wanted),
+            ) => return Ok(id == wanted),
+            (Value::Object(id), Wanted::String(_) | Wanted::Boxed(..)) => *id,
+            _ => return Ok(false),
+        };
+        let conn = self.conn();
+        Ok(match wanted {
+            Wanted::String(s) => string_value(conn, id)?.as_ref() == Some(s),
+            Wanted::Boxed(signature, value) => {
+                let class_id = object_reference::reference_type(conn, id)?.type_id;
+                let candidate_signature = reference_type::signature(conn, class_id)?.signature;
+                candidate_signature == *signature
+                    && boxed_value(conn, id, class_id, &candidate_signature)?.as_ref()
+                        == Some(value)
+            }
+            _ => false,
+        })
+    }
+
+    // An argument for an invoked method, which all take Objects. Primitives are boxed, and
+    // strings created in the target.
+    fn argument(&mut self, operand: &Operand) -> Result<Value> {
+        let conn = self.conn();
+        match operand {
+            Operand::Text(text) => Ok(Value::Object(
+                virtual_machine::create_string(conn, text)?.string_object,
+            )),
+            Operand::Value(value @ (Value::Object(_) | Value::ClassObject(_) | Value::Null)) => {
+                Ok(value.clone())
+            }
+            Operand::Value(primitive) => self.box_primitive(primitive),
+        }
+    }
+
+    fn box_primitive(&mut self, primitive: &Value) -> Result<Value> {
+        let conn = self.conn();
+        let signature = box_signature(primitive);
+        let class = virtual_machine::classes_by_signature(conn, signature)?
+            .classes
+            .first()
+            .map(|c| c.type_id)
+            .ok_or_else(|| eval_err(&format!("{} isn't loaded", signature_to_name(signature))))?;
+        let value_of = format!("({}){}", primitive_tag(primitive), signature);
+        let method = reference_type::methods(conn, class)?
+            .methods
+            .into_iter()
+            .find(|m| m.name == "valueOf" && m.signature == value_of)
+            .ok_or_else(|| protocol_err("Box class has no valueOf() method"))?;
+        let thread = self.frame().thread_id;
+        let value = conn.invoke(
+            None,
+            class,
+            thread,
+            method.method_id,
+            std::slice::from_ref(primitive),
+            "valueOf",
+        )?;
+        self.refetch_frame()?;
+        Ok(value)
+    }
+
+    // The class declaring the (instance) method which the object's class has, and the method's ID
+    fn find_method(&self, object: u64, name: &str, signature: &str) -> Result<Option<(u64, u64)>> {
+        let conn = self.conn();
+        let class = object_reference::reference_type(conn, object)?.type_id;
+        find_method(conn, class, name, signature, false)
+    }
+
+    // Runs the method on the object in the frame's thread, which has to have been suspended by an
+    // event (such as the breakpoint which stopped it)
+    fn invoke(
+        &mut self,
+        object: u64,
+        name: &str,
+        signature: &str,
+        args: &[Value],
+    ) -> Result<Value> {
+        let conn = self.conn();
+        let (class, method_id) = match self.find_method(object, name, signature)? {
+            Some(method) => method,
+            None => {
+                return Err(eval_err(&format!(
+                    "{} has no {}() method",
+                    signature_to_name(&self.signature_of(object)?),
+                    name
+                )))
+            }
+        };
+        let thread = self.frame().thread_id;
+        let value = conn.invoke(Some(object), class, thread, method_id, args, name)?;
+        self.refetch_frame()?;
+        Ok(value)
+    }
+}
+
+impl Contents {
+    fn len(&self) -> usize {
+        match self {
+            Contents::List(values) | Contents::Set(values) => values.len(),
+            Contents::Map(entries) => entries.len(),
+        }
+    }
+}
+
+fn list_index(index: &Operand, len: usize) -> Result<usize> {
+    let i = match index {
+        Operand::Value(Value::Integer(i)) => i64::from(*i),
+        Operand::Value(Value::Short(s)) => i64::from(*s),
+        Operand::Value(Value::Byte(b)) => i64::from(*b),
+        Operand::Value(Value::Char(c)) => i64::from(*c),
+        Operand::Value(value) => {
+            return Err(eval_err(&format!(
+                "an index has to be an int, not {}",
+                type_name(value)
+            )))
+        }
+        Operand::Text(_) => return Err(eval_err("an index has to be an int, not a String")),
+    };
+    if i < 0 || i as usize >= len {
+        return Err(eval_err(&format!(
+            "index {} out of bounds for length {}",
+            i, len
+        )));
+    }
+    Ok(i as usize)
+}
+
+// The instance field called 'name' in the class or its nearest superclass with one
+fn instance_field_id(conn: &JdwpConnection, class_id: u64, name: &str) -> Result<u64> {
+    let mut class = class_id;
+    while class != 0 {
+        let fields = reference_type::fields(conn, class)?.fields;
+        if let Some(field) = fields
+            .iter()
+            .find(|f| f.name == name && f.mod_bits as u32 & Modifiers::STATIC == 0)
+        {
+            return Ok(field.field_id);
+        }
+        class = class_type::superclass(conn, class)?.superclass;
+    }
+    Err(eval_err(&format!("no field called '{}'", name)))
+}
+
+// Whether the class inherits equals() from Object (or Enum, which doesn't change it), so equal
+// objects are the same object
+fn uses_identity_equals(conn: &JdwpConnection, class_id: u64) -> Result<bool> {
+    let mut class = class_id;
+    while class != 0 {
+        let methods = reference_type::methods(conn, class)?.methods;
+        if methods
+            .iter()
+            .any(|m| m.name == "equals" && m.signature == "(Ljava/lang/Object;)Z")
+        {
+            let signature = reference_type::signature(conn, class)?.signature;
+            return Ok(signature == "Ljava/lang/Object;" || signature == "Ljava/lang/Enum;");
+        }
+        class = class_type::superclass(conn, class)?.superclass;
+    }
+    Ok(true)
+}
+
+// The class Java boxes the primitive into
+fn box_signature(primitive: &Value) -> &'static str {
+    match primitive {
+        Value::Boolean(_) => "Ljava/lang/Boolean;",
+        Value::Byte(_) => "Ljava/lang/Byte;",
+        Value::Char(_) => "Ljava/lang/Character;",
+        Value::Short(_) => "Ljava/lang/Short;",
+        Value::Integer(_) => "Ljava/lang/Integer;",
+        Value::Long(_) => "Ljava/lang/Long;",
+        Value::Float(_) => "Ljava/lang/Float;",
+        _ => "Ljava/lang/Double;",
+    }
+}
+
+// The first two values, e.g. a node's key and value
+fn pair(values: Vec<Value>) -> Option<(Value, Value)> {
+    let mut values = values.into_iter();
+    Some((values.next()?, values.next()?))
+}
+
+fn primitive_tag(primitive: &Value) -> char {
+    match primitive {
+        Value::Boolean(_) => 'Z',
+        Value::Byte(_) => 'B',
+        Value::Char(_) => 'C',
+        Value::Short(_) => 'S',
+        Value::Integer(_) => 'I',
+        Value::Long(_) => 'J',
+        Value::Float(_) => 'F',
+        _ => 'D',
+    }
+}
@@ -0,0 +1,912 @@
+//
+// Events are the only packets the target VM sends without being asked. The client creates event
+// requests with the EventRequest command set, and the VM then sends an Event.Composite command
+// (possibly containing several events) every time one of the requests is triggered.
+//
+// https://docs.oracle.com/en/java/javase/11/docs/specs/jdwp/jdwp-protocol.html#JDWP_Event
+//
+
+use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
+use num_traits::cast::FromPrimitive;
+use std::collections::HashMap;
+use std::io::{Read, Result, Write};
+use std::rc::Rc;
+use std::time::{Duration, Instant};
+
+use super::JDWP_1_6;
+use super::{error_code, has_error_code, protocol_err, read_packet, wait_for_packet};
+use super::{reference_type, JdwpJavaVirtualMachine, JdwpMethod, JdwpThreadReference};
+use super::{Deserialize, JdwpConnection, JdwpVersion, Location, Packet, Serialize, TypeTag};
+use crate::model::Value;
+use crate::pattern::ClassPattern;
+
+pub(super) const COMPOSITE_COMMAND_SET: u8 = 64;
+pub(super) const COMPOSITE_COMMAND: u8 = 100;
+
+#[derive(Debug, FromPrimitive, Clone, Copy, PartialEq, Eq)]
+pub enum EventKind {
+    SingleStep = 1,
+    Breakpoint = 2,
+    FramePop = 3,
+    Exception = 4,
+    UserDefined = 5,
+    ThreadStart = 6,
+    ThreadDeath = 7,
+    ClassPrepare = 8,
+    ClassUnload = 9,
+    ClassLoad = 10,
+    FieldAccess = 20,
+    FieldModification = 21,
+    ExceptionCatch = 30,
+    MethodEntry = 40,
+    MethodExit = 41,
+    MethodExitWithReturnValue = 42,
+    MonitorContendedEnter = 43,
+    MonitorContendedEntered = 44,
+    MonitorWait = 45,
+    MonitorWaited = 46,
+    VmStart = 90,
+    VmDeath = 99,
+}
+
+impl EventKind {
+    // The first JDWP version with this kind of event, if it hasn't always been there
+    fn since(self) -> Option<JdwpVersion> {
+        match self {
+            EventKind::MethodExitWithReturnValue
+            | EventKind::MonitorContendedEnter
+            | EventKind::MonitorContendedEntered
+            | EventKind::MonitorWait
+            | EventKind::MonitorWaited => Some(JDWP_1_6),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, FromPrimitive, Clone, Copy, PartialEq, Eq)]
+pub enum SuspendPolicy {
+    None = 0,
+    EventThread = 1,
+    All = 2,
+}
+
+impl Deserialize for SuspendPolicy {
+    fn deserialize<R: Read>(reader: &mut R) -> Result<Self> {
+        let val = reader.read_u8()?;
+        FromPrimitive::from_u8(val)
+            .ok_or_else(|| protocol_err(&format!("{} is not a valid suspend policy", val)))
+    }
+}
+
+// Modifiers restrict which occurrences of an event kind are reported for a request
+#[derive(Debug, Clone)]
+pub enum Modifier {
+    Count(i32),
+    ThreadOnly(u64),
+    ClassOnly(u64),
+    // Patterns may start or end with '*', e.g. 'java.util.*' or '*.Foo'. See ClassPattern::to_jdwp().
+    ClassMatch(String),
+    ClassExclude(String),
+    LocationOnly(Location),
+    ExceptionOnly {
+        exception_or_null: u64,
+        caught: bool,
+        uncaught: bool,
+    },
+    FieldOnly {
+        declaring: u64,
+        field_id: u64,
+    },
+    Step {
+        thread: u64,
+        size: i32,
+        depth: i32,
+    },
+    InstanceOnly(u64),
+    SourceNameMatch(String),
+}
+
+impl Serialize for &Modifier {
+    fn serialize<W: Write>(self, writer: &mut W) -> Result<()> {
+        match *self {
+            Modifier::Count(count) => {
+                writer.write_u8(1)?;
+                count.serialize(writer)
+            }
+            Modifier::ThreadOnly(thread) => {
+                writer.write_u8(3)?;
+                thread.serialize(writer)
+            }
+            Modifier::ClassOnly(class) => {
+                writer.write_u8(4)?;
+                class.serialize(writer)
+            }
+            Modifier::ClassMatch(ref pattern) => {
+                writer.write_u8(5)?;
+                pattern.as_str().serialize(writer)
+            }
+            Modifier::ClassExclude(ref pattern) => {
+                writer.write_u8(6)?;
+                pattern.as_str().serialize(writer)
+            }
+            Modifier::LocationOnly(ref location) => {
+                writer.write_u8(7)?;
+                location.serialize(writer)
+            }
+            Modifier::ExceptionOnly {
+                exception_or_null,
+                caught,
+                uncaught,
+            } => {
+                writer.write_u8(8)?;
+                exception_or_null.serialize(writer)?;
+                caught.serialize(writer)?;
+                uncaught.serialize(writer)
+            }
+            Modifier::FieldOnly {
+                declaring,
+                field_id,
+            } => {
+                writer.write_u8(9)?;
+                declaring.serialize(writer)?;
+                field_id.serialize(writer)
+            }
+            Modifier::Step {
+                thread,
+                size,
+                depth,
+            } => {
+                writer.write_u8(10)?;
+                thread.serialize(writer)?;
+                size.serialize(writer)?;
+                depth.serialize(writer)
+            }
+            Modifier::InstanceOnly(object) => {
+                writer.write_u8(11)?;
+                object.serialize(writer)
+            }
+            Modifier::SourceNameMatch(ref pattern) => {
+                writer.write_u8(12)?;
+                pattern.as_str().serialize(writer)
+            }
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum Event {
+    VmStart {
+        request_id: i32,
+        thread: u64,
+    },
+    SingleStep {
+        request_id: i32,
+        thread: u64,
+        location: Location,
+    },
+    Breakpoint {
+        request_id: i32,
+        thread: u64,
+        location: Location,
+    },
+    MethodEntry {
+        request_id: i32,
+        thread: u64,
+        location: Location,
+    },
+    MethodExit {
+        request_id: i32,
+        thread: u64,
+        location: Location,
+    },
+    MethodExitWithReturnValue {
+        request_id: i32,
+        thread: u64,
+        location: Location,
+        value: Value,
+    },
+    MonitorContendedEnter {
+        request_id: i32,
+        thread: u64,
+        object: Value,
+        location: Location,
+    },
+    MonitorContendedEntered {
+        request_id: i32,
+        thread: u64,
+        object: Value,
+        location: Location,
+    },
+    MonitorWait {
+        request_id: i32,
+        thread: u64,
+        object: Value,
+        location: Location,
+        timeout: i64,
+    },
+    MonitorWaited {
+        request_id: i32,
+        thread: u64,
+        object: Value,
+        location: Location,
+        timed_out: bool,
+    },
+    Exception {
+        request_id: i32,
+        thread: u64,
+        location: Location,
+        exception: Value,
+        catch_location: Option<Location>, // None if the exception won't be caught
+    },
+    ThreadStart {
+        request_id: i32,
+        thread: u64,
+    },
+    ThreadDeath {
+        request_id: i32,
+        thread: u64,
+    },
+    ClassPrepare {
+        request_id: i32,
+        thread: u64,
+        ref_type_tag: TypeTag,
+        type_id: u64,
+        signature: String,
+        status: i32,
+    },
+    ClassUnload {
+        request_id: i32,
+        signature: String,
+    },
+    FieldAccess {
+        request_id: i32,
+        thread: u64,
+        location: Location,
+        ref_type_tag: TypeTag,
+        type_id: u64,
+        field_id: u64,
+        object: Value, // Null for static fields
+    },
+    FieldModification {
+        request_id: i32,
+        thread: u64,
+        location: Location,
+        ref_type_tag: TypeTag,
+        type_id: u64,
+        field_id: u64,
+        object: Value, // Null for static fields
+        value_to_be: Value,
+    },
+    VmDeath {
+        request_id: i32,
+    },
+}
+
+impl Event {
+    pub fn kind(&self) -> EventKind {
+        match self {
+            Event::VmStart { .. } => EventKind::VmStart,
+            Event::SingleStep { .. } => EventKind::SingleStep,
+            Event::Breakpoint { .. } => EventKind::Breakpoint,
+            Event::MethodEntry { .. } => EventKind::MethodEntry,
+            Event::MethodExit { .. } => EventKind::MethodExit,
+            Event::MethodExitWithReturnValue { .. } => EventKind::MethodExitWithReturnValue,
+            Event::MonitorContendedEnter { .. } => EventKind::MonitorContendedEnter,
+            Event::MonitorContendedEntered { .. } => EventKind::MonitorContendedEntered,
+            Event::MonitorWait { .. } => EventKind::MonitorWait,
+            Event::MonitorWaited { .. } => EventKind::MonitorWaited,
+            Event::Exception { .. } => EventKind::Exception,
+            Event::ThreadStart { .. } => EventKind::ThreadStart,
+            Event::ThreadDeath { .. } => EventKind::ThreadDeath,
+            Event::ClassPrepare { .. } => EventKind::ClassPrepare,
+            Event::ClassUnload { .. } => EventKind::ClassUnload,
+            Event::FieldAccess { .. } => EventKind::FieldAccess,
+            Event::FieldModification { .. } => EventKind::FieldModification,
+            Event::VmDeath { .. } => EventKind::VmDeath,
+        }
+    }
+
+    pub fn request_id(&self) -> i32 {
+        match *self {
+            Event::VmStart { request_id, .. }
+            | Event::SingleStep { request_id, .. }
+            | Event::Breakpoint { request_id, .. }
+            | Event::MethodEntry { request_id, .. }
+            | Event::MethodExit { request_id, .. }
+            | Event::MethodExitWithReturnValue { request_id, .. }
+            | Event::MonitorContendedEnter { request_id, .. }
+            | Event::MonitorContendedEntered { request_id, .. }
+            | Event::MonitorWait { request_id, .. }
+            | Event::MonitorWaited { request_id, .. }
+            | Event::Exception { request_id, .. }
+            | Event::ThreadStart { request_id, .. }
+            | Event::ThreadDeath { request_id, .. }
+            | Event::ClassPrepare { request_id, .. }
+            | Event::ClassUnload { request_id, .. }
+            | Event::FieldAccess { request_id, .. }
+            | Event::FieldModification { request_id, .. }
+            | Event::VmDeath { request_id } => request_id,
+        }
+    }
+
+    // The thread in which the event occurred, if there is one
+    pub fn thread(&self) -> Option<u64> {
+        match *self {
+            Event::VmStart { thread, .. }
+            | Event::SingleStep { thread, .. }
+            | Event::Breakpoint { thread, .. }
+            | Event::MethodEntry { thread, .. }
+            | Event::MethodExit { thread, .. }
+            | Event::MethodExitWithReturnValue { thread, .. }
+            | Event::MonitorContendedEnter { thread, .. }
+            | Event::MonitorContendedEntered { thread, .. }
+            | Event::MonitorWait { thread, .. }
+            | Event::MonitorWaited { thread, .. }
+            | Event::Exception { thread, .. }
+            | Event::ThreadStart { thread, .. }
+            | Event::ThreadDeath { thread, .. }
+            | Event::ClassPrepare { thread, .. }
+            | Event::FieldAccess { thread, .. }
+            | Event::FieldModification { thread, .. } => Some(thread),
+            Event::ClassUnload { .. } | Event::VmDeath { .. } => None,
+        }
+    }
+}
+
+impl Deserialize for Event {
+    fn deserialize<R: Read>(reader: &mut R) -> Result<Self> {
+        let kind = reader.read_u8()?;
+        let kind: EventKind = FromPrimitive::from_u8(kind)
+            .ok_or_else(|| protocol_err(&format!("{} is not a valid event kind", kind)))?;
+        let request_id = Deserialize::deserialize(reader)?;
+
+        let event = match kind {
+            EventKind::VmStart => Event::VmStart {
+                request_id,
+                thread: Deserialize::deserialize(reader)?,
+            },
+            EventKind::SingleStep => Event::SingleStep {
+                request_id,
+                thread: Deserialize::deserialize(reader)?,
+                location: Deserialize::deserialize(reader)?,
+            },
+            EventKind::Breakpoint => Event::Breakpoint {
+                request_id,
+                thread: Deserialize::deserialize(reader)?,
+                location: Deserialize::deserialize(reader)?,
+            },
+            EventKind::MethodEntry => Event::MethodEntry {
+                request_id,
+                thread: Deserialize::deserialize(reader)?,
+                location: Deserialize::deserialize(reader)?,
+            },
+            EventKind::MethodExit => Event::MethodExit {
+                request_id,
+                thread: Deserialize::deserialize(reader)?,
+                location: Deserialize::deserialize(reader)?,
+            },
+            EventKind::MethodExitWithReturnValue => Event::MethodExitWithReturnValue {
+                request_id,
+                thread: Deserialize::deserialize(reader)?,
+                location: Deserialize::deserialize(reader)?,
+                value: Deserialize::deserialize(reader)?,
+            },
+            EventKind::MonitorContendedEnter => Event::MonitorContendedEnter {
+                request_id,
+                thread: Deserialize::deserialize(reader)?,
+                object: Deserialize::deserialize(reader)?,
+                location: Deserialize::deserialize(reader)?,
+            },
+            EventKind::MonitorContendedEntered => Event::MonitorContendedEntered {
+                request_id,
+                thread: Deserialize::deserialize(reader)?,
+                object: Deserialize::deserialize(reader)?,
+                location: Deserialize::deserialize(reader)?,
+            },
+            EventKind::MonitorWait => Event::MonitorWait {
+                request_id,
+                thread: Deserialize::deserialize(reader)?,
+                object: Deserialize::deserialize(reader)?,
+                location: Deserialize::deserialize(reader)?,
+                timeout: Deserialize::deserialize(reader)?,
+            },
+            EventKind::MonitorWaited => Event::MonitorWaited {
+                request_id,
+                thread: Deserialize::deserialize(reader)?,
+                object: Deserialize::deserialize(reader)?,
+                location: Deserialize::deserialize(reader)?,
+                timed_out: Deserialize::deserialize(reader)?,
+            },
+            EventKind::Exception => Event::Exception {
+                request_id,
+                thread: Deserialize::deserialize(reader)?,
+                location: Deserialize::deserialize(reader)?,
+                exception: Deserialize::deserialize(reader)?,
+                catch_location: deserialize_optional_location(reader)?,
+            },
+            EventKind::ThreadStart => Event::ThreadStart {
+                request_id,
+                thread: Deserialize::deserialize(reader)?,
+            },
+            EventKind::ThreadDeath => Event::ThreadDeath {
+                request_id,
+                thread: Deserialize::deserialize(reader)?,
+            },
+            EventKind::ClassPrepare => Event::ClassPrepare {
+                request_id,
+                thread: Deserialize::deserialize(reader)?,
+                ref_type_tag: Deserialize::deserialize(reader)?,
+                type_id: Deserialize::deserialize(reader)?,
+                signature: Deserialize::deserialize(reader)?,
+                status: Deserialize::deserialize(reader)?,
+            },
+            EventKind::ClassUnload => Event::ClassUnload {
+                request_id,
+                signature: Deserialize::deserialize(reader)?,
+            },
+            EventKind::FieldAccess => Event::FieldAccess {
+                request_id,
+                thread: Deserialize::deserialize(reader)?,
+                location: Deserialize::deserialize(reader)?,
+                ref_type_tag: Deserialize::deserialize(reader)?,
+                type_id: Deserialize::deserialize(reader)?,
+                field_id: Deserialize::deserialize(reader)?,
+                object: Deserialize::deserialize(reader)?,
+            },
+            EventKind::FieldModification => Event::FieldModification {
+                request_id,
+                thread: Deserialize::deserialize(reader)?,
+                location: Deserialize::deserialize(reader)?,
+                ref_type_tag: Deserialize::deserialize(reader)?,
+                type_id: Deserialize::deserialize(reader)?,
+                field_id: Deserialize::deserialize(reader)?,
+                object: Deserialize::deserialize(reader)?,
+                value_to_be: Deserialize::deserialize(reader)?,
+            },
+            EventKind::VmDeath => Event::VmDeath { request_id },
+            EventKind::FramePop
+            | EventKind::UserDefined
+            | EventKind::ClassLoad
+            | EventKind::ExceptionCatch => {
+                // These are defined in the spec, but are never actually sent by the VM
+                return Err(protocol_err(&format!("Unexpected event kind {:?}", kind)));
+            }
+        };
+        Ok(event)
+    }
+}
+
+// A location which is all zeros means 'no location'. We can't use the regular Location
+// deserialization, since 0 isn't a valid type tag.
+fn deserialize_optional_location<R: Read>(reader: &mut R) -> Result<Option<Location>> {
+    let type_tag = reader.read_u8()?;
+    let class_id = reader.read_u64::<BigEndian>()?;
+    let method_id = reader.read_u64::<BigEndian>()?;
+    let location_idx = reader.read_u64::<BigEndian>()?;
+    if type_tag == 0 && class_id == 0 {
+        return Ok(None);
+    }
+    let type_tag = FromPrimitive::from_u8(type_tag)
+        .ok_or_else(|| protocol_err(&format!("{} is not a valid Type Tag", type_tag)))?;
+    Ok(Some(Location {
+        type_tag,
+        class_id,
+        method_id,
+        location_idx,
+    }))
+}
+
+#[derive(Debug)]
+pub(super) struct Composite {
+    // Whether the VM (or the thread the events happened in) was suspended for these events, and
+    // so needs resuming if they're thrown away
+    pub suspend_policy: SuspendPolicy,
+    pub events: Vec<Event>,
+}
+
+impl Deserialize for Composite {
+    fn deserialize<R: Read>(reader: &mut R) -> Result<Self> {
+        Ok(Composite {
+            suspend_policy: Deserialize::deserialize(reader)?,
+            events: Deserialize::deserialize(reader)?,
+        })
+    }
+}
+
+command_set! {
+    set_name: event_request;
+    set_id: 15;
+    command {
+        command_fn: set;
+        command_id: 1;
+        args: {
+            event_kind: u8,
+            suspend_policy: u8,
+            modifiers: &[super::Modifier]
+        }
+        response_type: SetReply {
+            request_id: i32
+        }
+    }
+    command {
+        command_fn: clear;
+        command_id: 2;
+        args: {
+            event_kind: u8,
+            request_id: i32
+        }
+        response_type: ClearReply {}
+    }
+}
+
+impl JdwpConnection {
+    // Create an event request, returning its id. Matching events can then be fetched using
+    // next_event().
+    pub fn set_event_request(
+        &self,
+        kind: EventKind,
+        suspend_policy: SuspendPolicy,
+        modifiers: &[Modifier],
+    ) -> Result<i32> {
+        if let Some(version) = kind.since() {
+            self.require_version(version, &format!("{:?} events", kind))?;
+        }
+        match kind {
+            EventKind::MonitorContendedEnter
+            | EventKind::MonitorContendedEntered
+            | EventKind::MonitorWait
+            | EventKind::MonitorWaited => {
+                self.require_capability(|c| c.can_request_monitor_events, "Monitor events")?
+            }
+            EventKind::FieldAccess => {
+                self.require_capability(|c| c.can_watch_field_access, "Field access events")?
+            }
+            EventKind::FieldModification => self.require_capability(
+                |c| c.can_watch_field_modification,
+                "Field modification events",
+            )?,
+            _ => {}
+        }
+        for modifier in modifiers {
+            match modifier {
+                Modifier::SourceNameMatch(_) => {
+                    self.require_version(JDWP_1_6, "Filtering events by source file")?;
+                    self.require_capability(
+                        |c| c.can_use_source_name_filters,
+                        "Filtering events by source file",
+                    )?;
+                }
+                Modifier::InstanceOnly(_) => self.require_capability(
+                    |c| c.can_use_instance_filters,
+                    "Filtering events by instance",
+                )?,
+                _ => {}
+            }
+        }
+        Ok(event_request::set(self, kind as u8, suspend_policy as u8, modifiers)?.request_id)
+    }
+
+    pub fn clear_event_request(&self, kind: EventKind, request_id: i32) -> Result<()> {
+        event_request::clear(self, kind as u8, request_id)?;
+        Ok(())
+    }
+
+    // Returns the oldest event for which wanted() returns true, waiting for up to timeout for one
+    // to arrive (or forever if timeout is None). Events that aren't wanted stay queued for other
+    // callers.
+    pub fn next_event<F: Fn(&Event) -> bool>(
+        &self,
+        timeout: Option<Duration>,
+        wanted: F,
+    ) -> Result<Option<Event>> {
+        let deadline = timeout.map(|t| Instant::now() + t);
+        loop {
+            let event = self.events.borrow_mut().take(&wanted)?;
+            self.pay_owed()?;
+            if event.is_some() {
+                return Ok(event);
+            }
+
+            self.check_connected()?;
+            let stream = &mut *self.stream.borrow_mut();
+            if let Some(deadline) = deadline {
+                if !wait_for_packet(stream, deadline)? {
+                    return Ok(None);
+                }
+            }
+            match read_packet(stream)? {
+                Packet::Command {
+                    command_set,
+                    command,
+                    data,
+                    ..
+                } => self.queue_events(command_set, command, &data)?,
+                // Only an invocation which was given up on can still have a reply on its way
+                Packet::Reply {
+                    id,
+                    error_code,
+                    data,
+                } => {
+                    if !self.stray_reply(id, error_code, data) {
+                        return Err(protocol_err(&format!("Unexpected reply to packet {}", id)));
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MethodTraceKind {
+    Entry,
+    Exit,
+}
+
+pub struct MethodTraceEvent {
+    pub kind: MethodTraceKind,
+    pub thread: JdwpThreadReference,
+    pub method: JdwpMethod,
+    // Only present for exits, and only if the target supports reporting return values
+    pub return_value: Option<Value>,
+}
+
+// A stream of method entries and exits in the target, created with
+// JdwpJavaVirtualMachine::trace_methods(). The event requests are cleared when this is dropped.
+pub struct MethodTrace {
+    conn: Rc<JdwpConnection>,
+    requests: Vec<(EventKind, i32)>,
+    max_per_second: Option<u32>,
+    window_start: Instant,
+    events_in_window: u32,
+    dropped: u64,
+    vm_dead: bool,
+    // Patterns the VM can't evaluate are checked here instead, caching the result per class
+    client_filter: Option<ClassPattern>,
+    class_matches: HashMap<u64, bool>,
+}
+
+impl JdwpJavaVirtualMachine {
+    // Trace entries to and exits from all methods of classes matching class_pattern. Tracing
+    // doesn't suspend any threads in the target, but every traced call still costs a round trip
+    // through the debug agent, so narrow patterns and a rate limit are a good idea on busy VMs.
+    // Regex patterns are particularly expensive, since the VM has to report every call for us to
+    // filter.
+    pub fn trace_methods(&self, class_pattern: &ClassPattern) -> Result<MethodTrace> {
+        let conn = self.conn.as_ref();
+        let (modifiers, client_filter) = match class_pattern.to_jdwp() {
+            Some(p) => (vec![Modifier::ClassMatch(p)], None),
+            None => (vec![], Some(class_pattern.clone())),
+        };
+        let mut trace = MethodTrace {
+            conn: self.conn.clone(),
+            requests: vec![],
+            max_per_second: None,
+            window_start: Instant::now(),
+            events_in_window: 0,
+            dropped: 0,
+            vm_dead: false,
+            client_filter,
+            class_matches: HashMap::new(),
+        };
+
+        let entry_id =
+            conn.set_event_request(EventKind::MethodEntry, SuspendPolicy::None, &modifiers)?;
+        trace.requests.push((EventKind::MethodEntry, entry_id));
+
+        // Older VMs can't report return values, in which case plain exits will have to do
+        let exit_kind = match conn.set_event_request(
+            EventKind::MethodExitWithReturnValue,
+            SuspendPolicy::None,
+            &modifiers,
+        ) {
+            Ok(id) => {
+                trace
+                    .requests
+                    .push((EventKind::MethodExitWithReturnValue, id));
+                None
+            }
+            Err(e)
+                if has_error_code(
+                    &e,
+                    &[error_code::NOT_IMPLEMENTED, error_code::INVALID_EVENT_TYPE],
+                ) =>
+            {
+                Some(EventKind::MethodExit)
+            }
+            Err(e) => return Err(e),
+        };
+        if let Some(kind) = exit_kind {
+            let id = conn.set_event_request(kind, SuspendPolicy::None, &modifiers)?;
+            trace.requests.push((kind, id));
+        }
+
+        Ok(trace)
+    }
+}
+
+impl MethodTrace {
+    // Report at most this many events per second. Events in excess of that are dropped (but
+    // counted, see dropped()). They're only dropped once they've got here, so this limits what the
+    // caller has to deal with, not what tracing costs the target, which still sends every one.
+    pub fn with_rate_limit(mut self, max_per_second: u32) -> Self {
+        self.max_per_second = Some(max_per_second);
+        self
+    }
+
+    // How many events have been dropped because of the rate limit
+    pub fn dropped(&self) -> u64 {
+        self.dropped
+    }
+
+    // Wait up to timeout (or forever, if None) for the next traced call. Returns None on timeout,
+    // or once the VM has exited.
+    pub fn next_event(&mut self, timeout: Option<Duration>) -> Result<Option<MethodTraceEvent>> {
+        let deadline = timeout.map(|t| Instant::now() + t);
+        while !self.vm_dead {
+            let remaining = deadline.map(|d| d.saturating_duration_since(Instant::now()));
+            let requests = &self.requests;
+            let event = self.conn.next_event(remaining, |e| {
+                e.kind() == EventKind::VmDeath
+                    || requests.iter().any(|&(_, id)| id == e.request_id())
+            })?;
+            let event = match event {
+                Some(e) => e,
+                None => return Ok(None),
+            };
+
+            let (kind, thread, location, return_value) = match event {
+                Event::MethodEntry {
+                    thread, location, ..
+                } => (MethodTraceKind::Entry, thread, location, None),
+                Event::MethodExit {
+                    thread, location, ..
+                } => (MethodTraceKind::Exit, thread, location, None),
+                Event::MethodExitWithReturnValue {
+                    thread,
+                    location,
+                    value,
+                    ..
+                } => (MethodTraceKind::Exit, thread, location, Some(value)),
+                _ => {
+                    self.vm_dead = true;
+                    break;
+                }
+            };
+            if !self.passes_client_filter(location.class_id)? {
+                continue;
+            }
+            if !self.within_rate_limit() {
+                self.dropped += 1;
+                continue;
+            }
+            return Ok(Some(MethodTraceEvent {
+                kind,
+                thread: JdwpThreadReference {
+                    conn: self.conn.clone(),
+                    thread_id: thread,
+                },
+                method: JdwpMethod {
+                    conn: self.conn.clone(),
+                    method_id: location.method_id,
+                    class_id: location.class_id,
+                },
+                return_value,
+            }));
+        }
+        Ok(None)
+    }
+
+    fn passes_client_filter(&mut self, class_id: u64) -> Result<bool> {
+        let filter = match &self.client_filter {
+            Some(f) => f,
+            None => return Ok(true),
+        };
+        if let Some(&matches) = self.class_matches.get(&class_id) {
+            return Ok(matches);
+        }
+        let signature = reference_type::signature(&self.conn, class_id)?.signature;
+        let matches = filter.matches(&signature);
+        self.class_matches.insert(class_id, matches);
+        Ok(matches)
+    }
+
+    fn within_rate_limit(&mut self) -> bool {
+        let max = match self.max_per_second {
+            Some(max) => max,
+            None => return true,
+        };
+        if self.window_start.elapsed() >= Duration::from_secs(1) {
+            self.window_start = Instant::now();
+            self.events_in_window = 0;
+        }
+        self.events_in_window += 1;
+        self.events_in_window <= max
+    }
+}
+
+impl Iterator for MethodTrace {
+    type Item = Result<MethodTraceEvent>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.next_event(None).transpose()
+    }
+}
+
+impl Drop for MethodTrace {
+    fn drop(&mut self) {
+        if self.vm_dead {
+            return;
+        }
+        for &(kind, id) in &self.requests {
+            // Nothing useful we can do with an error here, the connection is probably gone anyway
+            let _ = self.conn.clear_event_request(kind, id);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::jdwp::fake::{self, reply};
+
+    // Classes 0x20 and up, of which 0x20 and 0x22 are caches
+    fn method_trace(client_filter: Option<&str>) -> (MethodTrace, fake::Log) {
+        let answers = fake::Answers::default()
+            // ReferenceType.Signature
+            .on(2, 1, |data| match fake::first_id(data) {
+                0x20 => reply!["Lcom/example/Cache;"],
+                0x21 => reply!["Lcom/example/Cache$Entry;"],
+                _ => reply!["Lorg/other/Cache;"],
+            });
+        let log = answers.log();
+        let trace = MethodTrace {
+            conn: Rc::new(answers.attach()),
+            requests: vec![],
+            max_per_second: None,
+            window_start: Instant::now(),
+            events_in_window: 0,
+            dropped: 0,
+            vm_dead: false,
+            client_filter: client_filter.map(|p| ClassPattern::new(p).unwrap()),
+            class_matches: HashMap::new(),
+        };
+        (trace, log)
+    }
+
+    #[test]
+    fn client_filter() {
+        let (mut trace, log) = method_trace(Some("*.Cache"));
+        let passes: Vec<bool> = [0x20, 0x21, 0x22, 0x20, 0x21]
+            .iter()
+            .map(|&class| trace.passes_client_filter(class).unwrap())
+            .collect();
+        assert_eq!(passes, vec![true, false, true, true, false]);
+        // Each class's signature is only asked for once
+        assert_eq!(log.lock().unwrap().len(), 3);
+
+        let (mut trace, _) = method_trace(Some("com.example.*"));
+        assert!(trace.passes_client_filter(0x21).unwrap());
+        assert!(!trace.passes_client_filter(0x22).unwrap());
+
+        // The target filters for patterns it can evaluate, so nothing's asked
+        let (mut trace, log) = method_trace(None);
+        assert!(trace.passes_client_filter(0x22).unwrap());
+        assert!(log.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn rate_limit() {
+        let (trace, _) = method_trace(None);
+        let mut trace = trace.with_rate_limit(3);
+        let passed: Vec<bool> = (0..5).map(|_| trace.within_rate_limit()).collect();
+        assert_eq!(passed, vec![true, true, true, false, false]);
+
+        // A second later there's room for as many again
+        trace.window_start -= Duration::from_secs(1);
+        let passed: Vec<bool> = (0..4).map(|_| trace.within_rate_limit()).collect();
+        assert_eq!(passed, vec![true, true, true, false]);
+
+        let (mut trace, _) = method_trace(None);
+        assert!((0..1000).all(|_| trace.within_rate_limit()));
+    }
+}
@@ -0,0 +1,306 @@
+//
+// What an exception says about itself: its class, message, stack trace, suppressed exceptions and
+// causes, the way printStackTrace() would show them (see report::write_exception()). Without this,
+// an exception event only gives the ID of the Throwable.
+//
+// Everything is read from Throwable's fields. Suppressed exceptions are kept in an ArrayList, which
+// is read from its fields too; until one is added, the list is an empty one all Throwables share.
+// The one thing which may not be there is the stack trace: the JVM keeps it in an internal form
+// until something calls getStackTrace() (or prints it), and until then the stackTrace field is
+// Throwable.UNASSIGNED_STACK. Given a thread which was suspended by an event, getStackTrace() is
+// invoked in it to fill the trace in; without one, the trace is left out.
+//
+
+use std::collections::HashSet;
+use std::io::Result;
+
+use super::{array_reference, object_reference, reference_type, string_reference};
+use super::{signature_to_name, virtual_machine, ExceptionEvent};
+use super::{JdwpConnection, JdwpObjectReference, JdwpThreadReference};
+use crate::model::Value;
+use crate::snapshot::{ExceptionInfo, StackTraceLine};
+
+// Where the fields we need are, looked up once per capture
+struct Layout {
+    detail_message: u64,
+    cause: u64,
+    stack_trace: u64,
+    // Only since Java 7
+    suppressed_exceptions: Option<u64>,
+    // ArrayList and its elementData and size fields
+    array_list: Option<(u64, u64, u64)>,
+    // Throwable.UNASSIGNED_STACK, if the target has it
+    unassigned_stack: Option<u64>,
+    // StackTraceElement's declaringClass, methodName, fileName, lineNumber and, since JDK 9,
+    // moduleName
+    element_fields: Option<Vec<u64>>,
+}
+
+impl JdwpObjectReference {
+    // The exception, which has to be a Throwable, and its causes. 'thread' is for invoking
+    // getStackTrace() in if the trace hasn't been filled in yet, and has to have been suspended by
+    // an event (see invoke_method()).
+    pub fn capture_exception(&self, thread: Option<&JdwpThreadReference>) -> Result<ExceptionInfo> {
+        let layout = layout(self.conn.as_ref())?;
+        capture(self, self.object_id, thread, &layout, &mut HashSet::new())
+    }
+}
+
+impl ExceptionEvent {
+    // The exception which was thrown, invoking getStackTrace() in the event's thread if need be
+    pub fn capture(&self) -> Result<ExceptionInfo> {
+        match self.exception {
+            Value::Object(object_id) => JdwpObjectReference {
+                conn: self.thread.conn.clone(),
+                object_id,
+            }
+            .capture_exception(Some(&self.thread)),
+            _ => Err(super::protocol_err("Exception event without an exception")),
+        }
+    }
+}
+
+fn layout(conn: &JdwpConnection) -> Result<Layout> {
+    let class_id = |signature: &str| -> Result<Option<u64>> {
+        Ok(virtual_machine::classes_by_signature(conn, signature)?
+            .classes
+            .first()
+            .map(|class| class.type_id))
+    };
+    let throwable = class_id("Ljava/lang/Throwable;")?
+        .ok_or_else(|| super::protocol_err("java.lang.Throwable isn't loaded"))?;
+    let fields = reference_type::fields(conn, throwable)?.fields;
+    let field = |name: &str| {
+        fields
+            .iter()
+            .find(|f| f.name == name)
+            .map(|f| f.field_id)
+            .ok_or_else(|| super::protocol_err(&format!("Throwable has no {} field", name)))
+    };
+    let (detail_message, cause, stack_trace) = (
+        field("detailMessage")?,
+        field("cause")?,
+        field("stackTrace")?,
+    );
+    let suppressed_exceptions = field("suppressedExceptions").ok();
+    let unassigned_stack = match field("UNASSIGNED_STACK") {
+        Ok(field_id) => match reference_type::get_values(conn, throwable, &[field_id])?
+            .values
+            .first()
+        {
+            Some(Value::Object(array)) => Some(*array),
+            _ => None,
+        },
+        Err(_) => None,
+    };
+
+    // Loaded along with Throwable, but only used once a trace is filled in
+    let element_fields = match class_id("Ljava/lang/StackTraceElement;")? {
+        Some(element) => {
+            let fields = reference_type::fields(conn, element)?.fields;
+            let field = |name: &str| fields.iter().find(|f| f.name == name).map(|f| f.field_id);
+            let ids: Option<Vec<u64>> = ["declaringClass", "methodName", "fileName", "lineNumber"]
+                .iter()
+                .map(|name| field(name))
+                .collect();
+            ids.map(|mut ids| {
+                ids.extend(field("moduleName"));
+                ids
+            })
+        }
+        None => None,
+    };
+    let array_list = match class_id("Ljava/util/ArrayList;")? {
+        Some(array_list) => {
+            let fields = reference_type::fields(conn, array_list)?.fields;
+            let field = |name: &str| fields.iter().find(|f| f.name == name).map(|f| f.field_id);
+            field("elementData")
+                .zip(field("size"))
+                .map(|(element_data, size)| (array_list, element_data, size))
+        }
+        None => None,
+    };
+    Ok(Layout {
+        detail_message,
+        cause,
+        stack_trace,
+        suppressed_exceptions,
+        array_list,
+        unassigned_stack,
+        element_fields,
+    })
+}
+
+// In the order printStackTrace() visits them, so that the same ones are marked circular as it
+// would find to be: the exception, its suppressed exceptions, then its cause
+fn capture(
+    object: &JdwpObjectReference,
+    object_id: u64,
+    thread: Option<&JdwpThreadReference>,
+    layout: &Layout,
+    seen: &mut HashSet<u64>,
+) -> Result<ExceptionInfo> {
+    let circular = !seen.insert(object_id);
+    let (mut info, suppressed, cause) = capture_one(object, object_id, thread, layout, circular)?;
+    for suppressed in suppressed {
+        info.suppressed
+            .push(capture(object, suppressed, thread, layout, seen)?);
+    }
+    if let Some(cause) = cause {
+        info.cause = Some(Box::new(capture(object, cause, thread, layout, seen)?));
+    }
+    Ok(info)
+}
+
+// The exception without its suppressed exceptions and cause, and their IDs
+fn capture_one(
+    object: &JdwpObjectReference,
+    object_id: u64,
+    thread: Option<&JdwpThreadReference>,
+    layout: &Layout,
+    circular: bool,
+) -> Result<(ExceptionInfo, Vec<u64>, Option<u64>)> {
+    let conn = object.conn.as_ref();
+    let class_id = object_reference::reference_type(conn, object_id)?.type_id;
+    let class_name = signature_to_name(&reference_type::signature(conn, class_id)?.signature);
+    let fields: Vec<u64> = [layout.detail_message, layout.cause, layout.stack_trace]
+        .iter()
+        .copied()
+        .chain(layout.suppressed_exceptions)
+        .collect();
+    let values = object_reference::get_values(conn, object_id, &fields)?.values;
+    let message = match values.first() {
+        Some(Value::Object(message)) if *message != 0 => {
+            Some(string_reference::value(conn, *message)?.string_value)
+        }
+        _ => None,
+    };
+    let mut info = ExceptionInfo {
+        object_id,
+        class_name,
+        message,
+        stack_trace: None,
+        suppressed: vec![],
+        cause: None,
+        circular,
+    };
+    if circular {
+        return Ok((info, vec![], None));
+    }
+
+    // A Throwable with no cause is its own cause
+    let cause = match values.get(1) {
+        Some(Value::Object(cause)) if *cause != 0 && *cause != object_id => Some(*cause),
+        _ => None,
+    };
+    let trace = match values.get(2) {
+        Some(Value::Object(trace)) => *trace,
+        _ => 0,
+    };
+    let trace = if trace != 0 && Some(trace) == layout.unassigned_stack {
+        match thread {
+            Some(thread) => {
+                let exception = JdwpObjectReference {
+                    conn: object.conn.clone(),
+                    object_id,
+                };
+                match exception.invoke_method(
+                    thread,
+                    "getStackTrace",
+                    "()[Ljava/lang/StackTraceElement;",
+                    &[],
+                )? {
+                    Value::Object(trace) => Some(trace),
+                    _ => None,
+                }
+            }
+            None => None,
+        }
+    } else {
+        Some(trace)
+    };
+    info.stack_trace = match trace {
+        // Throwables made with writableStackTrace = false have none at all
+        Some(0) => Some(vec![]),
+        Some(trace) => stack_trace_lines(conn, trace, layout)?,
+        None => None,
+    };
+    let suppressed = match values.get(3) {
+        Some(Value::Object(list)) if *list != 0 => list_elements(conn, *list, layout)?,
+        _ => vec![],
+    };
+    Ok((info, suppressed, cause))
+}
+
+// The non-null elements of an ArrayList. Nothing for any other list, which for
+// suppressedExceptions is the shared empty one, or null if suppression was disabled.
+fn list_elements(conn: &JdwpConnection, list: u64, layout: &Layout) -> Result<Vec<u64>> {
+    let (class_id, element_data, size) = match layout.array_list {
+        Some(array_list) => array_list,
+        None => return Ok(vec![]),
+    };
+    if object_reference::reference_type(conn, list)?.type_id != class_id {
+        return Ok(vec![]);
+    }
+    let values = object_reference::get_values(conn, list, &[element_data, size])?.values;
+    let (array, size) = match values[..] {
+        [Value::Object(array), Value::Integer(size)] if array != 0 && size > 0 => (array, size),
+        _ => return Ok(vec![]),
+    };
+    Ok(array_reference::get_values(conn, array, 0, size)?
+        .values
+        .0
+        .into_iter()
+        .filter_map(|element| match element {
+            Value::Object(element) if element != 0 => Some(element),
+            _ => None,
+        })
+        .collect())
+}
+
+fn stack_trace_lines(
+    conn: &JdwpConnection,
+    trace: u64,
+    layout: &Layout,
+) -> Result<Option<Vec<StackTraceLine>>> {
+    let element_fields = match &layout.element_fields {
+        Some(fields) => fields,
+        None => return Ok(None),
+    };
+    let length = array_reference::length(conn, trace)?.array_length;
+    if length == 0 {
+        return Ok(Some(vec![]));
+    }
+    let elements = array_reference::get_values(conn, trace, 0, length)?
+        .values
+        .0;
+    let requests: Vec<(u64, &[u64])> = elements
+        .iter()
+        .filter_map(|element| match element {
+            Value::Object(element) if *element != 0 => Some((*element, element_fields.as_slice())),
+            _ => None,
+        })
+        .collect();
+    let string = |value: Option<&Value>| -> Result<Option<String>> {
+        match value {
+            Some(Value::Object(string)) if *string != 0 => {
+                Ok(Some(string_reference::value(conn, *string)?.string_value))
+            }
+            _ => Ok(None),
+        }
+    };
+    let mut lines = vec![];
+    for values in conn.get_values_bulk(&requests)? {
+        lines.push(StackTraceLine {
+            class_name: string(values.first())?.unwrap_or_default(),
+            method_name: string(values.get(1))?.unwrap_or_default(),
+            file_name: string(values.get(2))?,
+            line_number: match values.get(3) {
+                Some(Value::Integer(line)) => *line,
+                _ => -1,
+            },
+            module_name: string(values.get(4))?,
+        });
+    }
+    Ok(Some(lines))
+}
@@ -0,0 +1,144 @@
+//
+// A target for tests to attach to, or to give the address of (see Answers::listen()), which takes
+// one connection after another. It answers the commands sent while attaching itself, as a
+// JDK 17 VM (or whichever version Answers::attach_as() is given) with 8 byte IDs which doesn't
+// say what it can do. Every other command is looked up in the test's Answers, whose answer is the
+// reply's data or an error code, and logged. reply! builds the data with Serialize, the same as
+// commands are built.
+//
+
+use std::collections::HashMap;
+use std::convert::TryInto;
+use std::io::{Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+use super::protocol::write_packet;
+use super::{error_code, read_packet, JdwpConnection, Packet};
+
+pub(super) type Answer = std::result::Result<Vec<u8>, u16>;
+
+macro_rules! reply {
+    ($($part:expr),* $(,)?) => {{
+        #[allow(unused_mut)]
+        let mut data: Vec<u8> = vec![];
+        $(crate::jdwp::Serialize::serialize($part, &mut data).unwrap();)*
+        Ok(data)
+    }};
+}
+pub(super) use reply;
+
+// The commands the target was sent: (command set, command, data)
+pub(super) type Log = Arc<Mutex<Vec<(u8, u8, Vec<u8>)>>>;
+
+type Handler = Box<dyn FnMut(&[u8]) -> Answer + Send>;
+
+// How the target answers each (command set, command), given the command's data. A command it has
+// no answer for fails the test.
+#[derive(Default)]
+pub(super) struct Answers {
+    handlers: HashMap<(u8, u8), Handler>,
+    log: Log,
+}
+
+impl Answers {
+    pub(super) fn on<F>(mut self, command_set: u8, command: u8, answer: F) -> Answers
+    where
+        F: FnMut(&[u8]) -> Answer + Send + 'static,
+    {
+        self.handlers
+            .insert((command_set, command), Box::new(answer));
+        self
+    }
+
+    // Which stays usable after attaching
+    pub(super) fn log(&self) -> Log {
+        self.log.clone()
+    }
+
+    pub(super) fn attach(self) -> JdwpConnection {
+        self.attach_as(17)
+    }
+
+    // A JDK of the given feature version, e.g. 21
+    pub(super) fn attach_as(self, version: i32) -> JdwpConnection {
+        JdwpConnection::new(self.serve(version)).unwrap()
+    }
+
+    // For attaching to by address, as many times as need be, one connection after another
+    pub(super) fn listen(self) -> SocketAddr {
+        self.serve(17)
+    }
+
+    fn serve(mut self, version: i32) -> SocketAddr {
+        serve(version, move |command_set, command, data| {
+            let log = (command_set, command, data.to_vec());
+            self.log.lock().unwrap().push(log);
+            match self.handlers.get_mut(&(command_set, command)) {
+                Some(answer) => answer(data),
+                None => panic!("Unexpected command {}/{}", command_set, command),
+            }
+        })
+    }
+}
+
+// The object, thread or class ID most commands start with
+pub(super) fn first_id(data: &[u8]) -> u64 {
+    u64::from_be_bytes(data[..8].try_into().unwrap())
+}
+
+fn serve<F>(version: i32, mut answer: F) -> SocketAddr
+where
+    F: FnMut(u8, u8, &[u8]) -> Answer + Send + 'static,
+{
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            let mut stream = stream.unwrap();
+            stream.set_nodelay(true).unwrap();
+            converse(version, &mut stream, &mut answer);
+        }
+    });
+    addr
+}
+
+fn converse<F>(version: i32, stream: &mut TcpStream, answer: &mut F)
+where
+    F: FnMut(u8, u8, &[u8]) -> Answer,
+{
+    let mut handshake = [0; 14];
+    stream.read_exact(&mut handshake).unwrap();
+    stream.write_all(&handshake).unwrap();
+    // Until the connection's dropped
+    while let Ok(Packet::Command {
+        id,
+        command_set,
+        command,
+        data,
+    }) = read_packet(stream)
+    {
+        let answer = match (command_set, command) {
+            (1, 1) => {
+                let vm_version = format!("{}.0.2", version);
+                reply!["Fake", version, 0, vm_version.as_str(), "Fake VM"]
+            }
+            (1, 7) => reply![8, 8, 8, 8, 8],
+            (1, 17) => Err(error_code::NOT_IMPLEMENTED),
+            _ => answer(command_set, command, &data),
+        };
+        let (error_code, data) = match answer {
+            Ok(data) => (0, data),
+            Err(error_code) => (error_code, vec![]),
+        };
+        let reply = Packet::Reply {
+            id,
+            error_code,
+            data,
+        };
+        if write_packet(stream, &reply).is_err() {
+            break;
+        }
+    }
+}
@@ -0,0 +1,476 @@
+//
+// Limits on how much of a String or array is read by default. StringReference.Value and
+// ArrayReference.GetValues send the whole thing, so a glance at a 512MB char[] buffer would mean
+// 512MB over the network and in our memory, most likely just to see how it starts. Strings and
+// arrays read for showing to people go through here instead, which reads no more than the
+// connection's FetchLimits and says how much was left out. fetch_full() reads the rest when it's
+// really wanted.
+//
+// There's no command for reading part of a String, so long ones are read from their value array:
+// a char[] before JDK 9, and since then a byte[] holding Latin-1 or UTF-16, as its coder field
+// says.
+//
+
+use std::io::{Error, Result};
+use std::rc::Rc;
+
+use super::{array_reference, object_reference, reference_type, string_reference};
+use super::{virtual_machine, JdwpConnection, JdwpJavaVirtualMachine, JdwpObjectReference, Value};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FetchLimits {
+    // Of a String's contents, as the target stores them
+    pub string_bytes: usize,
+    // Of an array's elements, with object IDs counted at the connection's ID size
+    pub array_bytes: usize,
+}
+
+impl Default for FetchLimits {
+    fn default() -> Self {
+        FetchLimits {
+            string_bytes: 64 * 1024,
+            array_bytes: 64 * 1024,
+        }
+    }
+}
+
+// Where a String keeps its contents, found the first time a long string is read
+#[derive(Debug, Clone, Copy)]
+pub(super) struct StringLayout {
+    value_field: u64,
+    // Only since JDK 9
+    coder_field: Option<u64>,
+}
+
+// A String or array read up to the connection's FetchLimits
+pub struct Fetched<T> {
+    value: T,
+    // In chars for a string, elements for an array
+    fetched: usize,
+    length: usize,
+    conn: Rc<JdwpConnection>,
+    object: u64,
+}
+
+impl<T> Fetched<T> {
+    // As much as was read
+    pub fn value(&self) -> &T {
+        &self.value
+    }
+
+    pub fn into_value(self) -> T {
+        self.value
+    }
+
+    // The whole length, in chars for a string and elements for an array
+    pub fn length(&self) -> usize {
+        self.length
+    }
+
+    pub fn is_truncated(&self) -> bool {
+        self.fetched < self.length
+    }
+}
+
+impl Fetched<String> {
+    // The whole string, however long it is
+    pub fn fetch_full(&self) -> Result<String> {
+        if !self.is_truncated() {
+            return Ok(self.value.clone());
+        }
+        self.conn.check_connected()?;
+        Ok(string_reference::value(&self.conn, self.object)?.string_value)
+    }
+}
+
+impl Fetched<Vec<Value>> {
+    // Every element, however many there are
+    pub fn fetch_full(&self) -> Result<Vec<Value>> {
+        if !self.is_truncated() {
+            return Ok(self.value.clone());
+        }
+        self.conn.check_connected()?;
+        Ok(
+            array_reference::get_values(&self.conn, self.object, 0, self.length as i32)?
+                .values
+                .0,
+        )
+    }
+}
+
+impl JdwpConnection {
+    pub fn fetch_limits(&self) -> FetchLimits {
+        self.fetch_limits.get()
+    }
+
+    // The limits for strings and arrays read from now on
+    pub fn set_fetch_limits(&self, limits: FetchLimits) {
+        self.fetch_limits.set(limits)
+    }
+
+    // The start of a String, up to the string limit, then (fetched, length) in chars
+    pub(super) fn fetch_string(&self, string: u64) -> Result<(String, usize, usize)> {
+        let layout = self.string_layout(string)?;
+        let fields: Vec<u64> = Some(layout.value_field)
+            .into_iter()
+            .chain(layout.coder_field)
+            .collect();
+        let values = object_reference::get_values(self, string, &fields)?.values;
+        let (array, coder) = match values[..] {
+            [Value::Object(array), Value::Byte(coder)] => (array, Some(coder)),
+            [Value::Object(array)] => (array, None),
+            // Something we don't understand, so read it the slow way
+            _ => return self.fetch_whole_string(string),
+        };
+        let elements = array_reference::length(self, array)?.array_length as usize;
+        // A char[], or a byte[] with two bytes per char for UTF-16
+        let (length, bytes_per_char) = match coder {
+            None => (elements, 2),
+            Some(0) => (elements, 1),
+            Some(_) => (elements / 2, 2),
+        };
+        let limit = self.fetch_limits().string_bytes / bytes_per_char;
+        if length <= limit {
+            return self.fetch_whole_string(string);
+        }
+        let count = match coder {
+            None | Some(0) => limit,
+            Some(_) => limit * 2,
+        };
+        let values = array_reference::get_values(self, array, 0, count as i32)?
+            .values
+            .0;
+        let mut units: Vec<u16> = match coder {
+            // Latin-1
+            Some(0) => {
+                let value: String = values
+                    .iter()
+                    .map(|v| match v {
+                        Value::Byte(b) => char::from(*b as u8),
+                        _ => char::REPLACEMENT_CHARACTER,
+                    })
+                    .collect();
+                return Ok((value, limit, length));
+            }
+            Some(_) => {
+                let big_endian = self.utf16_big_endian()?;
+                values
+                    .chunks(2)
+                    .map(|pair| match pair {
+                        [Value::Byte(a), Value::Byte(b)] if big_endian => {
+                            u16::from_be_bytes([*a as u8, *b as u8])
+                        }
+                        [Value::Byte(a), Value::Byte(b)] => {
+                            u16::from_le_bytes([*a as u8, *b as u8])
+                        }
+                        _ => 0xFFFD,
+                    })
+                    .collect()
+            }
+            None => values
+                .iter()
+                .map(|v| match v {
+                    Value::Char(c) => *c,
+                    _ => 0xFFFD,
+                })
+                .collect(),
+        };
+        // Stopping between the halves of a surrogate pair would leave half a character, which
+        // would show as U+FFFD, so stop before it instead
+        if units
+            .last()
+            .is_some_and(|unit| (0xD800..0xDC00).contains(unit))
+        {
+            units.pop();
+        }
+        Ok((String::from_utf16_lossy(&units), units.len(), length))
+    }
+
+    fn fetch_whole_string(&self, string: u64) -> Result<(String, usize, usize)> {
+        let value = string_reference::value(self, string)?.string_value;
+        let length = value.encode_utf16().count();
+        Ok((value, length, length))
+    }
+
+    // The start of an array, up to the array limit, then (fetched, length) in elements
+    pub(super) fn fetch_array(&self, array: u64) -> Result<(Vec<Value>, usize, usize)> {
+        let length = array_reference::length(self, array)?.array_length as usize;
+        let type_id = object_reference::reference_type(self, array)?.type_id;
+        let signature = reference_type::signature(self, type_id)?.signature;
+        let element_bytes = match signature.as_bytes().get(1) {
+            Some(b'Z') | Some(b'B') => 1,
+            Some(b'C') | Some(b'S') => 2,
+            Some(b'I') | Some(b'F') => 4,
+            Some(b'J') | Some(b'D') => 8,
+            _ => usize::from(self.object_id_size).max(1),
+        };
+        let count = length.min(self.fetch_limits().array_bytes / element_bytes);
+        if count == 0 {
+            return Ok((vec![], 0, length));
+        }
+        let values = array_reference::get_values(self, array, 0, count as i32)?
+            .values
+            .0;
+        Ok((values, count, length))
+    }
+
+    fn string_layout(&self, string: u64) -> Result<StringLayout> {
+        if let Some(layout) = self.string_layout.get() {
+            return Ok(layout);
+        }
+        let class_id = object_reference::reference_type(self, string)?.type_id;
+        let fields = reference_type::fields(self, class_id)?.fields;
+        let field = |name: &str| fields.iter().find(|f| f.name == name).map(|f| f.field_id);
+        let value_field =
+            field("value").ok_or_else(|| Error::other("java.lang.String has no value field"))?;
+        let layout = StringLayout {
+            value_field,
+            coder_field: field("coder"),
+        };
+        self.string_layout.set(Some(layout));
+        Ok(layout)
+    }
+
+    // Whether UTF-16 strings store their high byte first, which depends on the target's platform.
+    // StringUTF16.HI_BYTE_SHIFT is 8 on big endian platforms. Only JDK 9 and later have it, and
+    // they load it early, but if it's not there the target is most likely little endian anyway.
+    // That guess isn't kept, so the answer is looked up again once the class is loaded.
+    fn utf16_big_endian(&self) -> Result<bool> {
+        if let Some(big_endian) = self.utf16_big_endian.get() {
+            return Ok(big_endian);
+        }
+        let classes = virtual_machine::classes_by_signature(self, "Ljava/lang/StringUTF16;")?;
+        let class_id = match classes.classes.first() {
+            Some(class) => class.type_id,
+            None => return Ok(false),
+        };
+        let fields = reference_type::fields(self, class_id)?.fields;
+        let big_endian = match fields.iter().find(|f| f.name == "HI_BYTE_SHIFT") {
+            Some(field) => {
+                let values = reference_type::get_values(self, class_id, &[field.field_id])?.values;
+                values.first() == Some(&Value::Integer(8))
+            }
+            None => false,
+        };
+        self.utf16_big_endian.set(Some(big_endian));
+        Ok(big_endian)
+    }
+}
+
+impl JdwpJavaVirtualMachine {
+    // See JdwpConnection::fetch_limits()
+    pub fn fetch_limits(&self) -> FetchLimits {
+        self.conn.fetch_limits()
+    }
+
+    // See JdwpConnection::set_fetch_limits()
+    pub fn set_fetch_limits(&self, limits: FetchLimits) {
+        self.conn.set_fetch_limits(limits)
+    }
+}
+
+impl JdwpObjectReference {
+    // The contents of a java.lang.String, up to the connection's string limit
+    pub fn string_value(&self) -> Result<Fetched<String>> {
+        let (value, fetched, length) = self.conn.fetch_string(self.object_id)?;
+        Ok(self.fetched(value, fetched, length))
+    }
+
+    // The elements of an array, up to the connection's array limit
+    pub fn array_values(&self) -> Result<Fetched<Vec<Value>>> {
+        let (value, fetched, length) = self.conn.fetch_array(self.object_id)?;
+        Ok(self.fetched(value, fetched, length))
+    }
+
+    fn fetched<T>(&self, value: T, fetched: usize, length: usize) -> Fetched<T> {
+        Fetched {
+            value,
+            fetched,
+            length,
+            conn: self.conn.clone(),
+            object: self.object_id,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::jdwp::fake::{self, reply};
+    use byteorder::{BigEndian, ReadBytesExt};
+    use std::io::Cursor;
+
+    const STRING_CLASS: u64 = 0x20;
+    const INT_ARRAY_CLASS: u64 = 0x21;
+    const STRING_UTF16: u64 = 0x22;
+    // Strings, each with its value array at 0x10 more
+    const UTF16: u64 = 0x100;
+    const LATIN1: u64 = 0x101;
+    const SHORT: u64 = 0x102;
+    const INTS: u64 = 0x200;
+
+    fn contents(string: u64) -> &'static str {
+        match string {
+            UTF16 => "abc\u{1F600}xyz",
+            LATIN1 => "hello world!",
+            _ => "hi",
+        }
+    }
+
+    // A JDK 17 target on a little endian machine
+    fn target() -> (Rc<JdwpConnection>, fake::Log) {
+        let answers = fake::Answers::default()
+            // ObjectReference.ReferenceType
+            .on(9, 1, |data| match fake::first_id(data) {
+                INTS => reply![1u8, INT_ARRAY_CLASS],
+                _ => reply![1u8, STRING_CLASS],
+            })
+            // ReferenceType.Signature
+            .on(2, 1, |_| reply!["[I"])
+            // ReferenceType.Fields
+            .on(2, 4, |data| match fake::first_id(data) {
+                STRING_CLASS => reply![2, 1u64, "value", "[B", 0x12, 2u64, "coder", "B", 0x12],
+                _ => reply![1, 3u64, "HI_BYTE_SHIFT", "I", 0x18],
+            })
+            // VirtualMachine.ClassesBySignature
+            .on(1, 2, |_| reply![1, 1u8, STRING_UTF16, 7u32])
+            // ReferenceType.GetValues, of HI_BYTE_SHIFT
+            .on(2, 6, |_| reply![1, &Value::Integer(0)])
+            // ObjectReference.GetValues, of value and coder
+            .on(9, 2, |data| {
+                let id = fake::first_id(data);
+                let coder = if id == UTF16 { 1 } else { 0 };
+                reply![2, &Value::Object(id + 0x10), &Value::Byte(coder)]
+            })
+            // ArrayReference.Length
+            .on(13, 1, |data| match fake::first_id(data) {
+                INTS => reply![10],
+                id if id == UTF16 + 0x10 => reply![16],
+                id => reply![contents(id - 0x10).len() as i32],
+            })
+            // ArrayReference.GetValues
+            .on(13, 2, |data| {
+                let mut args = Cursor::new(data);
+                let id = args.read_u64::<BigEndian>().unwrap();
+                let first = args.read_i32::<BigEndian>().unwrap() as usize;
+                let length = args.read_i32::<BigEndian>().unwrap() as usize;
+                let mut data = vec![];
+                if id == INTS {
+                    data.push(b'I');
+                    data.extend((length as i32).to_be_bytes());
+                    for i in first..first + length {
+                        data.extend((i as i32 * 10).to_be_bytes());
+                    }
+                } else {
+                    let bytes: Vec<u8> = if id == UTF16 + 0x10 {
+                        contents(UTF16)
+                            .encode_utf16()
+                            .flat_map(u16::to_le_bytes)
+                            .collect()
+                    } else {
+                        contents(id - 0x10).bytes().collect()
+                    };
+                    data.push(b'B');
+                    data.extend((length as i32).to_be_bytes());
+                    data.extend(&bytes[first..first + length]);
+                }
+                Ok(data)
+            })
+            // StringReference.Value
+            .on(10, 1, |data| reply![contents(fake::first_id(data))]);
+        let log = answers.log();
+        (Rc::new(answers.attach()), log)
+    }
+
+    // The times the target was asked about StringUTF16
+    fn lookups(log: &fake::Log) -> usize {
+        let log = log.lock().unwrap();
+        log.iter()
+            .filter(|(set, command, _)| (*set, *command) == (1, 2))
+            .count()
+    }
+
+    fn object(conn: &Rc<JdwpConnection>, object_id: u64) -> JdwpObjectReference {
+        JdwpObjectReference {
+            conn: conn.clone(),
+            object_id,
+        }
+    }
+
+    #[test]
+    fn truncation() {
+        let (conn, log) = target();
+        conn.set_fetch_limits(FetchLimits {
+            string_bytes: 8,
+            array_bytes: 8,
+        });
+        // Four chars would split the emoji's surrogate pair
+        assert_eq!(conn.fetch_string(UTF16).unwrap(), ("abc".into(), 3, 8));
+        assert_eq!(conn.fetch_string(UTF16).unwrap(), ("abc".into(), 3, 8));
+        assert_eq!(lookups(&log), 1);
+        conn.set_fetch_limits(FetchLimits {
+            string_bytes: 10,
+            array_bytes: 8,
+        });
+        assert_eq!(
+            conn.fetch_string(UTF16).unwrap(),
+            ("abc\u{1F600}".into(), 5, 8)
+        );
+        assert_eq!(
+            conn.fetch_string(LATIN1).unwrap(),
+            ("hello worl".into(), 10, 12)
+        );
+        assert_eq!(conn.fetch_string(SHORT).unwrap(), ("hi".into(), 2, 2));
+        assert_eq!(lookups(&log), 1);
+    }
+
+    #[test]
+    fn limits() {
+        let (conn, _) = target();
+        assert_eq!(conn.fetch_limits(), FetchLimits::default());
+        let (values, fetched, length) = conn.fetch_array(INTS).unwrap();
+        assert_eq!((values.len(), fetched, length), (10, 10, 10));
+
+        // Ints are four bytes each
+        conn.set_fetch_limits(FetchLimits {
+            string_bytes: 0,
+            array_bytes: 15,
+        });
+        let (values, fetched, length) = conn.fetch_array(INTS).unwrap();
+        assert_eq!(values, [0, 10, 20].map(Value::Integer));
+        assert_eq!((fetched, length), (3, 10));
+        // With no room for a single char, strings are left empty
+        assert_eq!(conn.fetch_string(LATIN1).unwrap(), ("".into(), 0, 12));
+
+        conn.set_fetch_limits(FetchLimits {
+            string_bytes: 0,
+            array_bytes: 3,
+        });
+        assert_eq!(conn.fetch_array(INTS).unwrap(), (vec![], 0, 10));
+    }
+
+    #[test]
+    fn fetch_full() {
+        let (conn, _) = target();
+        conn.set_fetch_limits(FetchLimits {
+            string_bytes: 4,
+            array_bytes: 8,
+        });
+        let string = object(&conn, UTF16).string_value().unwrap();
+        assert!(string.is_truncated());
+        assert_eq!(string.value(), "ab");
+        assert_eq!(string.length(), 8);
+        assert_eq!(string.fetch_full().unwrap(), contents(UTF16));
+
+        let short = object(&conn, SHORT).string_value().unwrap();
+        assert!(!short.is_truncated());
+        assert_eq!(short.fetch_full().unwrap(), "hi");
+
+        let array = object(&conn, INTS).array_values().unwrap();
+        assert!(array.is_truncated());
+        assert_eq!(array.value().len(), 2);
+        let full = array.fetch_full().unwrap();
+        assert_eq!(full.len(), 10);
+        assert_eq!(full[9], Value::Integer(90));
+    }
+}
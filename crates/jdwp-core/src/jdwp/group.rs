@@ -0,0 +1,132 @@
+//
+// Event requests which only make sense together, e.g. a breakpoint at the start of a transaction
+// and another at its end, or method entry and exit for the same class. Set one at a time, an
+// event from the first can arrive before the others exist, and a trace built from them starts
+// half way through. A BreakpointGroup sets (and clears) all of its requests while the VM holds
+// back events (VirtualMachine.HoldEvents), so either none of them has reported anything yet or
+// all of them are in place.
+//
+
+use std::io::Result;
+use std::rc::Rc;
+
+use super::event::{Event, EventKind, Modifier, SuspendPolicy};
+use super::{virtual_machine, JdwpConnection, JdwpJavaVirtualMachine, JdwpLocation};
+
+struct GroupRequest {
+    kind: EventKind,
+    suspend_policy: SuspendPolicy,
+    modifiers: Vec<Modifier>,
+}
+
+// Created with JdwpJavaVirtualMachine::breakpoint_group(). Its requests are cleared when it's
+// dropped.
+pub struct BreakpointGroup {
+    conn: Rc<JdwpConnection>,
+    requests: Vec<GroupRequest>,
+    // The kind and ID of each request the target has for us, while armed
+    armed: Vec<(EventKind, i32)>,
+}
+
+impl JdwpJavaVirtualMachine {
+    pub fn breakpoint_group(&self) -> BreakpointGroup {
+        BreakpointGroup {
+            conn: self.conn.clone(),
+            requests: vec![],
+            armed: vec![],
+        }
+    }
+}
+
+impl BreakpointGroup {
+    // Requests added while the group is armed are only set the next time it's armed
+    pub fn add(
+        &mut self,
+        kind: EventKind,
+        suspend_policy: SuspendPolicy,
+        modifiers: Vec<Modifier>,
+    ) {
+        self.requests.push(GroupRequest {
+            kind,
+            suspend_policy,
+            modifiers,
+        });
+    }
+
+    pub fn add_breakpoint(&mut self, location: &JdwpLocation, suspend_policy: SuspendPolicy) {
+        self.add(
+            EventKind::Breakpoint,
+            suspend_policy,
+            vec![Modifier::LocationOnly(location.location)],
+        );
+    }
+
+    // Set every request in the group. If the target refuses any of them, the ones already set
+    // are cleared again, so the group is either entirely armed or not at all.
+    pub fn arm(&mut self) -> Result<()> {
+        if self.is_armed() {
+            return Ok(());
+        }
+        let conn = self.conn.as_ref();
+        virtual_machine::hold_events(conn)?;
+        let mut result = Ok(());
+        for request in &self.requests {
+            match conn.set_event_request(request.kind, request.suspend_policy, &request.modifiers) {
+                Ok(id) => self.armed.push((request.kind, id)),
+                Err(e) => {
+                    result = Err(e);
+                    break;
+                }
+            }
+        }
+        if result.is_err() {
+            for (kind, id) in self.armed.drain(..) {
+                let _ = conn.clear_event_request(kind, id);
+            }
+        }
+        // Always released, or the VM would hold onto every event from now on
+        virtual_machine::release_events(conn)?;
+        result
+    }
+
+    // Clear every request in the group. Events they've already reported stay queued.
+    pub fn disarm(&mut self) -> Result<()> {
+        if !self.is_armed() {
+            return Ok(());
+        }
+        let conn = self.conn.as_ref();
+        virtual_machine::hold_events(conn)?;
+        let mut result = Ok(());
+        for (kind, id) in self.armed.drain(..) {
+            // Keep going, so that one failure doesn't leave the rest armed
+            if let Err(e) = conn.clear_event_request(kind, id) {
+                result = result.and(Err(e));
+            }
+        }
+        virtual_machine::release_events(conn)?;
+        result
+    }
+
+    pub fn is_armed(&self) -> bool {
+        !self.armed.is_empty()
+    }
+
+    // Whether the event was reported for one of the group's requests
+    pub fn contains(&self, event: &Event) -> bool {
+        self.armed.iter().any(|&(_, id)| id == event.request_id())
+    }
+
+    // The IDs of the group's requests while armed, in the order they were added
+    pub fn request_ids(&self) -> Vec<i32> {
+        self.armed.iter().map(|&(_, id)| id).collect()
+    }
+}
+
+impl Drop for BreakpointGroup {
+    fn drop(&mut self) {
+        // Nothing useful we can do with an error here, the connection is probably gone anyway
+        if self.conn.check_connected().is_ok() {
+            let _ = self.disarm();
+        }
+    }
+}
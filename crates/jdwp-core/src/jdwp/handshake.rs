@@ -0,0 +1,300 @@
+//
+// The JDWP handshake: we send "JDWP-Handshake" and the agent sends it straight back. Anything
+// else means whatever is listening isn't a JDWP agent (or isn't one we can talk to), and it's
+// much easier to say so here than to let the first command fail on a garbled reply.
+//
+// Nothing is kept from a failed handshake, so it's always safe to try again. The errors say
+// which kind of failure it was: TimedOut if nothing (or not enough) came back in time,
+// ConnectionAborted if the other end hung up, which is what JDWP agents do while another
+// debugger is attached, and InvalidData if something other than the handshake came back.
+//
+// Attaching has one deadline for everything: connecting, the handshake, and the first few
+// commands, which ask what the target is. Each step gets whatever time is left, so an unroutable
+// address, or a target which accepts the connection and then says nothing, fails when the
+// deadline passes rather than after a timeout for each step.
+//
+
+use std::io::{Error, ErrorKind, Read, Result, Write};
+use std::net::{SocketAddr, TcpStream, ToSocketAddrs};
+use std::time::{Duration, Instant};
+
+use super::protocol_err;
+
+const HANDSHAKE: &[u8; 14] = b"JDWP-Handshake";
+
+// How long attaching may take by default
+pub(super) const DEFAULT_TIMEOUT: Duration = Duration::from_secs(10);
+
+// See attach_live_with_options()
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AttachOptions {
+    // How long attaching may take altogether, after which it fails with TimedOut
+    pub timeout: Duration,
+    // Refuse to send any command which changes the target (see audit.rs), including suspending it,
+    // failing with PermissionDenied instead. It can't be turned off again for the connection.
+    pub read_only: bool,
+}
+
+impl Default for AttachOptions {
+    fn default() -> Self {
+        AttachOptions {
+            timeout: DEFAULT_TIMEOUT,
+            read_only: false,
+        }
+    }
+}
+
+// Connects to the first address which accepts, and does the handshake
+pub(super) fn connect<A: ToSocketAddrs>(addr: A, timeout: Duration) -> Result<TcpStream> {
+    connect_before(addr, Instant::now() + timeout, timeout)
+}
+
+// Like connect(), giving up at 'deadline', which is 'timeout' after attaching started.
+//
+// A host name often resolves to more than one address, typically an IPv6 one and an IPv4 one,
+// and the agent is usually only listening on one of them. They're tried in the order they
+// resolved in, each getting an equal share of the time that's left, so that one which never
+// answers can't use up the whole deadline before the others get a turn. If none of them work the
+// error says what went wrong with each.
+pub(super) fn connect_before<A: ToSocketAddrs>(
+    addr: A,
+    deadline: Instant,
+    timeout: Duration,
+) -> Result<TcpStream> {
+    let addrs: Vec<SocketAddr> = addr.to_socket_addrs()?.collect();
+    let mut failures: Vec<(SocketAddr, Error)> = vec![];
+    for (i, &addr) in addrs.iter().enumerate() {
+        let remaining = match time_left(deadline, timeout) {
+            Ok(remaining) => remaining,
+            Err(e) => {
+                failures.push((addr, e));
+                break;
+            }
+        };
+        let share = remaining / (addrs.len() - i) as u32;
+        let attempt = TcpStream::connect_timeout(&addr, share).and_then(|mut stream| {
+            handshake(&mut stream, deadline, timeout)?;
+            Ok(stream)
+        });
+        match attempt {
+            Ok(stream) => return Ok(stream),
+            Err(e) => failures.push((addr, e)),
+        }
+    }
+    Err(match failures.len() {
+        0 => Error::new(ErrorKind::InvalidInput, "No addresses to connect to"),
+        1 => failures.pop().expect("one failure").1,
+        _ => {
+            // The kind is the last one's, which is the one the deadline cut short if any was
+            let kind = failures.last().expect("several failures").1.kind();
+            let each: Vec<String> = failures
+                .iter()
+                .map(|(addr, e)| format!("{}: {}", addr, e))
+                .collect();
+            Error::new(
+                kind,
+                format!(
+                    "Couldn't attach at any of {} addresses ({})",
+                    failures.len(),
+                    each.join("; ")
+                ),
+            )
+        }
+    })
+}
+
+// How long there is until 'deadline', or a TimedOut error if it's passed
+pub(super) fn time_left(deadline: Instant, timeout: Duration) -> Result<Duration> {
+    let remaining = deadline.saturating_duration_since(Instant::now());
+    if remaining.is_zero() {
+        return Err(timed_out(timeout));
+    }
+    Ok(remaining)
+}
+
+pub(super) fn timed_out(timeout: Duration) -> Error {
+    Error::new(
+        ErrorKind::TimedOut,
+        format!(
+            "Timed out after {}s attaching to the target",
+            timeout.as_secs_f64()
+        ),
+    )
+}
+
+// The other side of the handshake, for debuggers connecting to us (see JdwpProxy)
+pub(super) fn accept(stream: &mut TcpStream, timeout: Duration) -> Result<()> {
+    stream.set_read_timeout(Some(timeout))?;
+    let mut request = [0; HANDSHAKE.len()];
+    stream.read_exact(&mut request)?;
+    stream.set_read_timeout(None)?;
+    if &request != HANDSHAKE {
+        return Err(protocol_err(&format!(
+            "Expected the JDWP handshake, got {}",
+            describe_reply(&request)
+        )));
+    }
+    stream.write_all(HANDSHAKE)
+}
+
+fn handshake(stream: &mut TcpStream, deadline: Instant, timeout: Duration) -> Result<()> {
+    let remaining = time_left(deadline, timeout)?;
+    stream.set_read_timeout(Some(remaining))?;
+    stream.set_write_timeout(Some(remaining))?;
+    stream.write_all(HANDSHAKE)?;
+    let mut reply = [0; HANDSHAKE.len()];
+    let mut len = 0;
+    while len < reply.len() {
+        match stream.read(&mut reply[len..]) {
+            Ok(0) => break,
+            Ok(n) => {
+                len += n;
+                // Give up as soon as it can't be the handshake, rather than waiting for more
+                if reply[..len] != HANDSHAKE[..len] {
+                    break;
+                }
+            }
+            Err(e) if e.kind() == ErrorKind::Interrupted => {}
+            Err(e) if e.kind() == ErrorKind::WouldBlock || e.kind() == ErrorKind::TimedOut => {
+                return Err(Error::new(
+                    ErrorKind::TimedOut,
+                    format!(
+                        "No JDWP handshake within {}s ({}). Is this the address the JVM's \
+                         -agentlib:jdwp option gives?",
+                        timeout.as_secs_f64(),
+                        describe_reply(&reply[..len])
+                    ),
+                ));
+            }
+            Err(e) => return Err(e),
+        }
+    }
+    stream.set_read_timeout(None)?;
+    stream.set_write_timeout(None)?;
+    check_reply(&reply[..len])
+}
+
+fn check_reply(reply: &[u8]) -> Result<()> {
+    if reply == HANDSHAKE {
+        Ok(())
+    } else if reply.is_empty() {
+        Err(Error::new(
+            ErrorKind::ConnectionAborted,
+            "The connection was closed during the JDWP handshake. JDWP agents only take one \
+             debugger at a time, so another may be attached.",
+        ))
+    } else {
+        Err(protocol_err(&format!(
+            "Expected the JDWP handshake, got {}",
+            describe_reply(reply)
+        )))
+    }
+}
+
+// What a reply which wasn't the handshake looks like it came from
+fn describe_reply(reply: &[u8]) -> String {
+    let guess = match reply {
+        [] => return "nothing".to_string(),
+        r if r.starts_with(b"HTTP/") => " (an HTTP server)",
+        r if r.starts_with(b"SSH-") => " (an SSH server)",
+        // Content types handshake and alert, then the major version
+        [0x15 | 0x16, 0x03, ..] => " (a TLS server)",
+        r if HANDSHAKE.starts_with(r) => " (the start of the handshake, but no more)",
+        _ => "",
+    };
+    format!("{:?}{}", String::from_utf8_lossy(reply), guess)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::TcpListener;
+    use std::thread;
+
+    // Something which answers the handshake with 'reply', then hangs up
+    fn listener(reply: &'static [u8]) -> std::net::SocketAddr {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut request = [0; 14];
+            stream.read_exact(&mut request).unwrap();
+            stream.write_all(reply).unwrap();
+            thread::sleep(Duration::from_millis(200));
+        });
+        addr
+    }
+
+    fn connect_to(reply: &'static [u8]) -> Result<TcpStream> {
+        connect(listener(reply), Duration::from_secs(5))
+    }
+
+    #[test]
+    fn handshake_replies() {
+        assert!(connect_to(HANDSHAKE).is_ok());
+
+        let err = connect_to(b"HTTP/1.1 400 Bad Request\r\n\r\n").unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidData);
+        assert!(err.to_string().contains("an HTTP server"), "{}", err);
+
+        let err = connect_to(&[0x15, 0x03, 0x01, 0x00, 0x02, 0x02, 0x32]).unwrap_err();
+        assert!(err.to_string().contains("a TLS server"), "{}", err);
+
+        let err = connect_to(b"").unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::ConnectionAborted);
+    }
+
+    #[test]
+    fn accept_handshake() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let _ = accept(&mut stream, Duration::from_secs(5));
+        });
+        assert!(connect(addr, Duration::from_secs(5)).is_ok());
+    }
+
+    #[test]
+    fn handshake_timeout() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let err = connect(addr, Duration::from_millis(100)).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::TimedOut);
+        drop(listener);
+    }
+
+    #[test]
+    fn address_fallback() {
+        // Nothing's listening here any more, so connecting is refused
+        let closed = TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap();
+        let open = listener(HANDSHAKE);
+        let stream = connect(&[closed, open][..], Duration::from_secs(5)).unwrap();
+        assert_eq!(stream.peer_addr().unwrap(), open);
+
+        let err = connect(&[closed, closed][..], Duration::from_secs(5)).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::ConnectionRefused);
+        assert!(err.to_string().contains(&closed.to_string()), "{}", err);
+        assert!(err.to_string().contains("2 addresses"), "{}", err);
+    }
+
+    #[test]
+    fn attach_deadline() {
+        // Answers the handshake, then never replies to the version command
+        let addr = listener(HANDSHAKE);
+        let started = Instant::now();
+        let options = AttachOptions {
+            timeout: Duration::from_millis(100),
+            ..Default::default()
+        };
+        let err = match crate::jdwp::JdwpConnection::with_options(addr, &options) {
+            Ok(_) => panic!("attached to a target which never replied"),
+            Err(e) => e,
+        };
+        assert_eq!(err.kind(), ErrorKind::TimedOut, "{}", err);
+        assert!(started.elapsed() < Duration::from_secs(2));
+    }
+}
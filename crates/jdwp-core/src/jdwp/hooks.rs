@@ -0,0 +1,225 @@
+//
+// Callbacks for events, for automation which would otherwise be an event loop of its own: set a
+// breakpoint, and every time it's hit capture some state and let the thread go on. Each hook
+// says what happens next with the Action it returns, and run() takes care of resuming threads and
+// clearing the requests afterwards.
+//
+// Hooked events suspend only the thread they happen in, which stays suspended while its hook
+// runs, so the hook can look at its frames. Other threads carry on meanwhile.
+//
+
+use std::io::Result;
+use std::rc::Rc;
+use std::time::{Duration, Instant};
+
+use super::event::{Event, EventKind, Modifier, SuspendPolicy};
+use super::{thread_reference, JdwpConnection, JdwpJavaVirtualMachine, JdwpLocation};
+use super::{JdwpReferenceType, JdwpThreadReference};
+use crate::model::Value;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Action {
+    // Resume the thread and forget the event
+    Resume,
+    // Resume the thread, keeping the event for take_recorded()
+    Record,
+    // Leave the thread suspended, and return the event from run() so the caller can deal with it
+    Suspend,
+}
+
+pub struct BreakpointEvent {
+    pub thread: JdwpThreadReference,
+    pub location: JdwpLocation,
+}
+
+pub struct ExceptionEvent {
+    pub thread: JdwpThreadReference,
+    // Where it was thrown
+    pub location: JdwpLocation,
+    pub exception: Value,
+    // None if nothing will catch it
+    pub catch_location: Option<JdwpLocation>,
+}
+
+pub enum HookedEvent {
+    Breakpoint(BreakpointEvent),
+    Exception(ExceptionEvent),
+}
+
+type BreakpointHook<'a> = Box<dyn FnMut(&BreakpointEvent) -> Action + 'a>;
+type ExceptionHook<'a> = Box<dyn FnMut(&ExceptionEvent) -> Action + 'a>;
+
+// Created with JdwpJavaVirtualMachine::event_hooks(). Its requests are cleared when it's dropped.
+pub struct EventHooks<'a> {
+    conn: Rc<JdwpConnection>,
+    // By request ID
+    breakpoints: Vec<(i32, BreakpointHook<'a>)>,
+    exceptions: Vec<(i32, ExceptionHook<'a>)>,
+    recorded: Vec<HookedEvent>,
+    vm_dead: bool,
+}
+
+impl JdwpJavaVirtualMachine {
+    pub fn event_hooks<'a>(&self) -> EventHooks<'a> {
+        EventHooks {
+            conn: self.conn.clone(),
+            breakpoints: vec![],
+            exceptions: vec![],
+            recorded: vec![],
+            vm_dead: false,
+        }
+    }
+}
+
+impl<'a> EventHooks<'a> {
+    // Call 'hook' whenever a thread reaches 'location', e.g. one from locations_of_line()
+    pub fn on_breakpoint<F>(&mut self, location: &JdwpLocation, hook: F) -> Result<()>
+    where
+        F: FnMut(&BreakpointEvent) -> Action + 'a,
+    {
+        let id = self.conn.set_event_request(
+            EventKind::Breakpoint,
+            SuspendPolicy::EventThread,
+            &[Modifier::LocationOnly(location.location)],
+        )?;
+        self.breakpoints.push((id, Box::new(hook)));
+        Ok(())
+    }
+
+    // Call 'hook' whenever an exception of class 'exception_class' (or a subclass), or of any
+    // class if None, is thrown, and will be caught (if 'caught') or won't be (if 'uncaught')
+    pub fn on_exception<F>(
+        &mut self,
+        exception_class: Option<&JdwpReferenceType>,
+        caught: bool,
+        uncaught: bool,
+        hook: F,
+    ) -> Result<()>
+    where
+        F: FnMut(&ExceptionEvent) -> Action + 'a,
+    {
+        let id = self.conn.set_event_request(
+            EventKind::Exception,
+            SuspendPolicy::EventThread,
+            &[Modifier::ExceptionOnly {
+                exception_or_null: exception_class.map_or(0, |class| class.class_id),
+                caught,
+                uncaught,
+            }],
+        )?;
+        self.exceptions.push((id, Box::new(hook)));
+        Ok(())
+    }
+
+    // Call the hooks as their events arrive, for up to 'timeout' (or forever, if None). Returns
+    // the event whose hook said Action::Suspend, with its thread still suspended, or None on
+    // timeout or once the VM has exited (see is_vm_dead()).
+    pub fn run(&mut self, timeout: Option<Duration>) -> Result<Option<HookedEvent>> {
+        let deadline = timeout.map(|t| Instant::now() + t);
+        while !self.vm_dead {
+            let remaining = deadline.map(|d| d.saturating_duration_since(Instant::now()));
+            let (breakpoints, exceptions) = (&self.breakpoints, &self.exceptions);
+            let event = self.conn.next_event(remaining, |e| {
+                let id = e.request_id();
+                e.kind() == EventKind::VmDeath
+                    || breakpoints.iter().any(|(b, _)| *b == id)
+                    || exceptions.iter().any(|(x, _)| *x == id)
+            })?;
+            let event = match event {
+                Some(e) => e,
+                None => return Ok(None),
+            };
+
+            let request_id = event.request_id();
+            let location = |location| JdwpLocation {
+                conn: self.conn.clone(),
+                location,
+            };
+            let (thread_id, action, hooked) = match event {
+                Event::Breakpoint {
+                    thread,
+                    location: l,
+                    ..
+                } => {
+                    let event = BreakpointEvent {
+                        thread: self.thread(thread),
+                        location: location(l),
+                    };
+                    let action = match self.breakpoints.iter_mut().find(|(b, _)| *b == request_id) {
+                        Some((_, hook)) => hook(&event),
+                        None => Action::Resume,
+                    };
+                    (thread, action, HookedEvent::Breakpoint(event))
+                }
+                Event::Exception {
+                    thread,
+                    location: l,
+                    exception,
+                    catch_location,
+                    ..
+                } => {
+                    let event = ExceptionEvent {
+                        thread: self.thread(thread),
+                        location: location(l),
+                        exception,
+                        catch_location: catch_location.map(location),
+                    };
+                    let action = match self.exceptions.iter_mut().find(|(x, _)| *x == request_id) {
+                        Some((_, hook)) => hook(&event),
+                        None => Action::Resume,
+                    };
+                    (thread, action, HookedEvent::Exception(event))
+                }
+                _ => {
+                    self.vm_dead = true;
+                    break;
+                }
+            };
+
+            match action {
+                Action::Suspend => return Ok(Some(hooked)),
+                Action::Record => self.recorded.push(hooked),
+                Action::Resume => {}
+            }
+            thread_reference::resume(&self.conn, thread_id)?;
+        }
+        Ok(None)
+    }
+
+    // The events whose hooks said Action::Record, oldest first, since the last call
+    pub fn take_recorded(&mut self) -> Vec<HookedEvent> {
+        std::mem::take(&mut self.recorded)
+    }
+
+    pub fn is_vm_dead(&self) -> bool {
+        self.vm_dead
+    }
+
+    fn thread(&self, thread_id: u64) -> JdwpThreadReference {
+        JdwpThreadReference {
+            conn: self.conn.clone(),
+            thread_id,
+        }
+    }
+}
+
+impl Drop for EventHooks<'_> {
+    fn drop(&mut self) {
+        if self.vm_dead {
+            return;
+        }
+        let requests = self
+            .breakpoints
+            .iter()
+            .map(|(id, _)| (EventKind::Breakpoint, *id))
+            .chain(
+                self.exceptions
+                    .iter()
+                    .map(|(id, _)| (EventKind::Exception, *id)),
+            );
+        for (kind, id) in requests {
+            // Nothing useful we can do with an error here, the connection is probably gone anyway
+            let _ = self.conn.clear_event_request(kind, id);
+        }
+    }
+}
@@ -0,0 +1,462 @@
+//
+// Running methods in the target (ClassType.InvokeMethod and ObjectReference.InvokeMethod, and
+// constructors with ClassType.NewInstance). The thread has to have been suspended by an event,
+// and while the method runs the target resumes either that thread alone or every thread.
+// Resuming every thread lets the rest of the program move on behind the user's back, so by
+// default only the invoking thread runs (see InvokePolicy). That has its own hazard: if the
+// method needs a monitor held by a thread which is still suspended, it blocks forever, and so
+// would we, waiting for its reply.
+//
+// So an invocation's reply is waited for in slices, and in between we look for the thread it's
+// blocked on: a suspended thread owning a monitor the invoking thread is waiting to enter. That
+// thread is either resumed until the method returns (and then suspended again), or the invocation
+// is given up on straight away. Nothing the method did before then is undone, since there's no
+// rolling back the target's heap, which is what the InvokeFailed errors are there to say.
+//
+// An invocation which has been given up on is still running in the target, and its reply can
+// turn up at any time, including while we wait for the reply to some other command. Until then
+// its packet ID is remembered, so the reply can be recognised and dropped (see stray_reply()).
+//
+
+use std::io::{Cursor, Result};
+use std::time::{Duration, Instant};
+
+use super::invoke_options::INVOKE_SINGLE_THREADED;
+use super::virtual_machine;
+use super::{class_type, object_reference, reference_type, string_reference, thread_reference};
+use super::{
+    command_err, error_code, has_error_code, protocol_err, read_packet, signature_to_name,
+};
+use super::{wait_for_packet, Deserialize, JdwpError, JdwpErrorKind, Modifiers, Packet, Serialize};
+use super::{JdwpConnection, JdwpJavaVirtualMachine, JdwpObjectReference, JdwpReferenceType};
+use super::{JdwpThreadReference, Value, HEADER_SIZE};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InvokePolicy {
+    // Only resume the invoking thread while the method runs. Otherwise every thread is resumed,
+    // which can't deadlock on a suspended thread, but lets the whole program carry on.
+    pub single_threaded: bool,
+    // How long to wait for the method to return before giving up on it
+    pub timeout: Duration,
+    // What to do when the method blocks on a monitor held by a suspended thread: resume that
+    // thread until the method returns, or fail straight away
+    pub resume_monitor_owners: bool,
+}
+
+impl Default for InvokePolicy {
+    fn default() -> Self {
+        InvokePolicy {
+            single_threaded: true,
+            timeout: Duration::from_secs(10),
+            resume_monitor_owners: false,
+        }
+    }
+}
+
+pub(super) enum PendingInvoke {
+    Waiting,
+    // The error code and data, which arrived while waiting for something else
+    Replied(u16, Vec<u8>),
+    // Given up on, so the reply can be dropped when it comes
+    Abandoned,
+}
+
+// How long to wait for a reply before checking whether the method is stuck
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+// ClassType.InvokeMethod, ClassType.NewInstance and ObjectReference.InvokeMethod
+const CLASS_INVOKE: u8 = 3;
+const NEW_INSTANCE: u8 = 4;
+const OBJECT_INVOKE: u8 = 6;
+
+impl JdwpConnection {
+    // Applies to every method invoked from now on, including by the expression evaluator
+    pub fn set_invoke_policy(&self, policy: InvokePolicy) {
+        *self.invoke_policy.borrow_mut() = policy;
+    }
+
+    pub fn invoke_policy(&self) -> InvokePolicy {
+        self.invoke_policy.borrow().clone()
+    }
+
+    // Runs a method in 'thread', which has to have been suspended by an event. 'object' is None
+    // for static methods, and 'name' is only for errors. An exception thrown by the method is
+    // returned as an InvokeFailed error.
+    pub(super) fn invoke(
+        &self,
+        object: Option<u64>,
+        class: u64,
+        thread: u64,
+        method: u64,
+        args: &[Value],
+        name: &str,
+    ) -> Result<Value> {
+        let mut data = vec![];
+        let (command_set, command) = match object {
+            Some(object) => {
+                object.serialize(&mut data)?;
+                thread.serialize(&mut data)?;
+                class.serialize(&mut data)?;
+                (object_reference::SET_ID, OBJECT_INVOKE)
+            }
+            None => {
+                class.serialize(&mut data)?;
+                thread.serialize(&mut data)?;
+                (class_type::SET_ID, CLASS_INVOKE)
+            }
+        };
+        method.serialize(&mut data)?;
+        self.send_invoke(command_set, command, data, thread, args, name)
+    }
+
+    // Like invoke(), running a constructor of 'class' to make a new instance of it
+    pub(super) fn new_instance(
+        &self,
+        class: u64,
+        thread: u64,
+        constructor: u64,
+        args: &[Value],
+        name: &str,
+    ) -> Result<Value> {
+        let mut data = vec![];
+        class.serialize(&mut data)?;
+        thread.serialize(&mut data)?;
+        constructor.serialize(&mut data)?;
+        self.send_invoke(class_type::SET_ID, NEW_INSTANCE, data, thread, args, name)
+    }
+
+    // The rest of an invocation, after the target of the command and the method
+    fn send_invoke(
+        &self,
+        command_set: u8,
+        command: u8,
+        mut data: Vec<u8>,
+        thread: u64,
+        args: &[Value],
+        name: &str,
+    ) -> Result<Value> {
+        let policy = self.invoke_policy();
+        args.serialize(&mut data)?;
+        let options = if policy.single_threaded {
+            INVOKE_SINGLE_THREADED
+        } else {
+            0
+        };
+        options.serialize(&mut data)?;
+
+        let id = self.send_cmd(&mut self.stream.borrow_mut(), command_set, command, &data)?;
+        self.invokes.borrow_mut().insert(id, PendingInvoke::Waiting);
+        // Each thread we resume for the invocation, and how many times
+        let mut resumed = vec![];
+        let reply = self.await_invoke(id, thread, &policy, &mut resumed, name);
+        if reply.is_err() {
+            self.invokes
+                .borrow_mut()
+                .insert(id, PendingInvoke::Abandoned);
+        }
+        // Even if the method is still running, the threads it was waiting for go back to how the
+        // user left them
+        for (owner, count) in resumed {
+            for _ in 0..count {
+                thread_reference::suspend(self, owner)?;
+            }
+        }
+        self.pay_owed()?;
+
+        let (error_code, reply) = reply?;
+        if error_code != 0 {
+            return Err(command_err(command_set, command, &data, error_code));
+        }
+        let reply = object_reference::InvokeMethodReply::deserialize(&mut Cursor::new(reply))?;
+        if let Value::Object(exception) = reply.exception {
+            return Err(invoke_err(format!(
+                "{}() threw {}",
+                name,
+                describe_exception(self, exception, true)?
+            )));
+        }
+        Ok(reply.return_value)
+    }
+
+    fn await_invoke(
+        &self,
+        id: u32,
+        thread: u64,
+        policy: &InvokePolicy,
+        resumed: &mut Vec<(u64, i32)>,
+        name: &str,
+    ) -> Result<(u16, Vec<u8>)> {
+        let deadline = Instant::now() + policy.timeout;
+        loop {
+            if let Some(reply) = self.take_invoke_reply(id) {
+                return Ok(reply);
+            }
+            let now = Instant::now();
+            if now >= deadline {
+                return Err(invoke_err(format!(
+                    "{}() didn't return within {:?}. It's still running in the target, so its \
+                     thread stays resumed until it does, and anything it has done so far stays \
+                     done.",
+                    name, policy.timeout
+                )));
+            }
+            let got_packet = {
+                let stream = &mut *self.stream.borrow_mut();
+                let got_packet = wait_for_packet(stream, deadline.min(now + POLL_INTERVAL))?;
+                if got_packet {
+                    match read_packet(stream)? {
+                        Packet::Reply {
+                            id: reply_id,
+                            error_code,
+                            data,
+                        } => {
+                            if !self.stray_reply(reply_id, error_code, data) {
+                                return Err(protocol_err(&format!(
+                                    "Unexpected reply to packet {}",
+                                    reply_id
+                                )));
+                            }
+                        }
+                        Packet::Command {
+                            command_set,
+                            command,
+                            data,
+                            ..
+                        } => self.queue_events(command_set, command, &data)?,
+                    }
+                }
+                got_packet
+            };
+            if !got_packet && policy.single_threaded {
+                self.unblock(thread, policy, resumed, name)?;
+            }
+        }
+    }
+
+    fn take_invoke_reply(&self, id: u32) -> Option<(u16, Vec<u8>)> {
+        let mut invokes = self.invokes.borrow_mut();
+        match invokes.remove(&id)? {
+            PendingInvoke::Replied(error_code, data) => Some((error_code, data)),
+            pending => {
+                invokes.insert(id, pending);
+                None
+            }
+        }
+    }
+
+    // If the invoking thread (or one we resumed for it) is waiting to enter a monitor owned by a
+    // suspended thread, resume the owner or fail, as the policy says. ThreadReference.Status is
+    // no help, since the target reports a thread running an invocation as running whatever it's
+    // doing, so this goes by the monitors' waiters instead.
+    fn unblock(
+        &self,
+        thread: u64,
+        policy: &InvokePolicy,
+        resumed: &mut Vec<(u64, i32)>,
+        name: &str,
+    ) -> Result<()> {
+        if !self.has_capability(|c| c.can_get_owned_monitor_info && c.can_get_monitor_info) {
+            return Ok(());
+        }
+        let running: Vec<u64> = std::iter::once(thread)
+            .chain(resumed.iter().map(|&(owner, _)| owner))
+            .collect();
+        for owner in virtual_machine::all_threads(self)?.threads {
+            if running.contains(&owner) {
+                continue;
+            }
+            // Threads can finish while we look
+            let suspend_count = match thread_reference::suspend_count(self, owner) {
+                Ok(reply) => reply.suspend_count,
+                Err(e) if has_error_code(&e, &[error_code::INVALID_THREAD]) => continue,
+                Err(e) => return Err(e),
+            };
+            if suspend_count == 0 {
+                continue;
+            }
+            for monitor in thread_reference::owned_monitors(self, owner)?.owned {
+                let monitor = match monitor {
+                    Value::Object(monitor) | Value::ClassObject(monitor) => monitor,
+                    _ => continue,
+                };
+                let waiters = object_reference::monitor_info(self, monitor)?.waiters;
+                if !waiters.iter().any(|waiter| running.contains(waiter)) {
+                    continue;
+                }
+                if !policy.resume_monitor_owners {
+                    return Err(invoke_err(format!(
+                        "{}() is blocked on a monitor held by suspended thread \"{}\", so it \
+                         would never return. It's left waiting for the monitor, with its thread \
+                         resumed until it returns, and anything it did before that stays done.",
+                        name,
+                        thread_reference::name(self, owner)?.name
+                    )));
+                }
+                for _ in 0..suspend_count {
+                    thread_reference::resume(self, owner)?;
+                }
+                resumed.push((owner, suspend_count));
+                return Ok(());
+            }
+        }
+        Ok(())
+    }
+
+    // For replies which aren't to the command being waited for. They can only be to invocations
+    // (see invoke()): returns false for anything else.
+    pub(super) fn stray_reply(&self, id: u32, error_code: u16, data: Vec<u8>) -> bool {
+        let bytes_received = (data.len() + HEADER_SIZE as usize) as u64;
+        {
+            let mut invokes = self.invokes.borrow_mut();
+            match invokes.remove(&id) {
+                None => return false,
+                Some(PendingInvoke::Abandoned) => {}
+                Some(_) => {
+                    invokes.insert(id, PendingInvoke::Replied(error_code, data));
+                }
+            }
+        }
+        let slow = self
+            .command_tracker
+            .borrow_mut()
+            .replied(id, bytes_received, error_code != 0);
+        if let Some((log, cmd)) = slow {
+            log(&cmd);
+        }
+        true
+    }
+}
+
+impl JdwpJavaVirtualMachine {
+    // See JdwpConnection::set_invoke_policy()
+    pub fn set_invoke_policy(&self, policy: InvokePolicy) {
+        self.conn.set_invoke_policy(policy)
+    }
+
+    pub fn invoke_policy(&self) -> InvokePolicy {
+        self.conn.invoke_policy()
+    }
+}
+
+impl JdwpObjectReference {
+    // Runs the object's method with this name and JNI signature (e.g. "(I)Ljava/lang/String;")
+    // in 'thread', which has to have been suspended by an event. See InvokePolicy for how.
+    pub fn invoke_method(
+        &self,
+        thread: &JdwpThreadReference,
+        name: &str,
+        signature: &str,
+        args: &[Value],
+    ) -> Result<Value> {
+        let conn = self.conn.as_ref();
+        let class = object_reference::reference_type(conn, self.object_id)?.type_id;
+        let (class, method) = find_method(conn, class, name, signature, false)?
+            .ok_or_else(|| no_method_err(conn, class, name, signature))?;
+        conn.invoke(
+            Some(self.object_id),
+            class,
+            thread.thread_id,
+            method,
+            args,
+            name,
+        )
+    }
+}
+
+impl JdwpReferenceType {
+    // Like JdwpObjectReference::invoke_method(), for the class's static methods
+    pub fn invoke_static_method(
+        &self,
+        thread: &JdwpThreadReference,
+        name: &str,
+        signature: &str,
+        args: &[Value],
+    ) -> Result<Value> {
+        let conn = self.conn.as_ref();
+        let (_, method) = find_method(conn, self.class_id, name, signature, true)?
+            .ok_or_else(|| no_method_err(conn, self.class_id, name, signature))?;
+        conn.invoke(None, self.class_id, thread.thread_id, method, args, name)
+    }
+}
+
+// The class in 'class' or its superclasses declaring the (static or instance) method, and the
+// method's ID
+pub(super) fn find_method(
+    conn: &JdwpConnection,
+    class: u64,
+    name: &str,
+    signature: &str,
+    is_static: bool,
+) -> Result<Option<(u64, u64)>> {
+    let mut class = class;
+    while class != 0 {
+        let methods = reference_type::methods(conn, class)?.methods;
+        if let Some(method) = methods.iter().find(|m| {
+            m.name == name
+                && m.signature == signature
+                && (m.mod_bits as u32 & Modifiers::STATIC != 0) == is_static
+        }) {
+            return Ok(Some((class, method.method_id)));
+        }
+        class = class_type::superclass(conn, class)?.superclass;
+    }
+    Ok(None)
+}
+
+fn no_method_err(conn: &JdwpConnection, class: u64, name: &str, signature: &str) -> std::io::Error {
+    let class = match reference_type::signature(conn, class) {
+        Ok(reply) => signature_to_name(&reply.signature),
+        Err(e) => return e,
+    };
+    std::io::Error::new(
+        std::io::ErrorKind::NotFound,
+        format!("{} has no method {}{}", class, name, signature),
+    )
+}
+
+// e.g. "java.lang.IllegalStateException: not started". This reads Throwable's fields rather than
+// invoking getMessage(), which could fail (or block) as well. Exceptions without a message are
+// often wrappers, so their cause is described too.
+fn describe_exception(conn: &JdwpConnection, exception: u64, with_cause: bool) -> Result<String> {
+    let class = object_reference::reference_type(conn, exception)?.type_id;
+    let name = signature_to_name(&reference_type::signature(conn, class)?.signature);
+    let throwable = match virtual_machine::classes_by_signature(conn, "Ljava/lang/Throwable;")?
+        .classes
+        .first()
+    {
+        Some(throwable) => throwable.type_id,
+        None => return Ok(name),
+    };
+    let fields = reference_type::fields(conn, throwable)?.fields;
+    let field = |name: &str| fields.iter().find(|f| f.name == name).map(|f| f.field_id);
+    let (message, cause) = match (field("detailMessage"), field("cause")) {
+        (Some(message), Some(cause)) => (message, cause),
+        _ => return Ok(name),
+    };
+    let mut values = object_reference::get_values(conn, exception, &[message, cause])?.values;
+    let cause = values.pop();
+    match values.pop() {
+        Some(Value::Object(message)) => Ok(format!(
+            "{}: {}",
+            name,
+            string_reference::value(conn, message)?.string_value
+        )),
+        // A Throwable with no cause is its own cause
+        _ => match cause {
+            Some(Value::Object(cause)) if with_cause && cause != exception => Ok(format!(
+                "{} (caused by {})",
+                name,
+                describe_exception(conn, cause, false)?
+            )),
+            _ => Ok(name),
+        },
+    }
+}
+
+pub(super) fn invoke_err(msg: String) -> std::io::Error {
+    std::io::Error::other(JdwpError {
+        msg,
+        kind: JdwpErrorKind::InvokeFailed,
+        error_code: None,
+    })
+}
@@ -0,0 +1,191 @@
+//
+// Forcing a collection and asking how big the heap is, which together with class_histogram() make
+// up the usual way of confirming a leak: collect, count the instances of a class, do whatever
+// leaks, collect and count again. Both run code in the target, so as for diagnostic_command()
+// they need a thread which was suspended by an event.
+//
+// Collecting goes through the GC.run diagnostic command rather than System.gc(), since
+// -XX:+DisableExplicitGC makes System.gc() do nothing. Sizes come from the MemoryMXBean and
+// MemoryPoolMXBeans, the same numbers JConsole shows.
+//
+
+use std::convert::TryFrom;
+use std::io::{Error, Result};
+
+use super::diagnostic::{invoke_static, load_class, Pinned};
+use super::invoke::find_method;
+use super::{array_reference, class_type, object_reference, reference_type, string_reference};
+use super::{jdwp_error_kind, JdwpConnection, JdwpErrorKind, JdwpJavaVirtualMachine};
+use super::{JdwpThreadReference, Value};
+
+// Sizes in bytes
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MemoryUsage {
+    // What the JVM asked the OS for at startup, if it says
+    pub init: Option<u64>,
+    pub used: u64,
+    // What the OS has actually given the JVM
+    pub committed: u64,
+    // None if there's no limit
+    pub max: Option<u64>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MemoryPool {
+    // e.g. "G1 Old Gen", "Metaspace"
+    pub name: String,
+    // As opposed to non-heap memory, such as metaspace and the code cache
+    pub heap: bool,
+    pub usage: MemoryUsage,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HeapInfo {
+    pub heap: MemoryUsage,
+    pub non_heap: MemoryUsage,
+    pub pools: Vec<MemoryPool>,
+}
+
+impl JdwpJavaVirtualMachine {
+    // Runs a full collection, in 'thread' (which has to have been suspended by an event)
+    pub fn force_gc(&self, thread: &JdwpThreadReference) -> Result<()> {
+        match self.diagnostic_command(thread, "GC.run") {
+            Ok(_) => Ok(()),
+            // Runtimes put together without java.management can still be asked politely
+            Err(e) if jdwp_error_kind(&e) == Some(JdwpErrorKind::InvokeFailed) => {
+                let conn = self.conn.as_ref();
+                let system = load_class(conn, thread.thread_id, "java.lang.System")?;
+                invoke_static(conn, thread.thread_id, system, "gc", "()V", &[])?;
+                Ok(())
+            }
+            Err(e) => Err(e),
+        }
+    }
+
+    // Heap and non-heap usage, overall and per memory pool, found out by running code in
+    // 'thread' (which has to have been suspended by an event)
+    pub fn heap_info(&self, thread: &JdwpThreadReference) -> Result<HeapInfo> {
+        let conn = self.conn.as_ref();
+        let mut beans = MxBeans {
+            conn,
+            thread: thread.thread_id,
+            objects: Pinned::new(conn),
+        };
+        let factory = load_class(conn, beans.thread, "java.lang.management.ManagementFactory")?;
+        let memory = beans.get(
+            factory,
+            "getMemoryMXBean",
+            "Ljava/lang/management/MemoryMXBean;",
+        )?;
+        let heap = beans.call(&memory, "getHeapMemoryUsage", USAGE)?;
+        let non_heap = beans.call(&memory, "getNonHeapMemoryUsage", USAGE)?;
+
+        let list = beans.get(factory, "getMemoryPoolMXBeans", "Ljava/util/List;")?;
+        let array = beans.call(&list, "toArray", "[Ljava/lang/Object;")?;
+        let pool_beans = match array {
+            Value::Object(array) => {
+                let length = array_reference::length(conn, array)?.array_length;
+                array_reference::get_values(conn, array, 0, length)?
+                    .values
+                    .0
+            }
+            _ => vec![],
+        };
+        let mut pools = Vec::with_capacity(pool_beans.len());
+        for pool in &pool_beans {
+            // Null for pools which are no longer in use
+            let usage = match beans.call(pool, "getUsage", USAGE)? {
+                Value::Null => continue,
+                usage => usage,
+            };
+            let name = beans.call(pool, "getName", "Ljava/lang/String;")?;
+            let kind = beans.call(pool, "getType", "Ljava/lang/management/MemoryType;")?;
+            pools.push(MemoryPool {
+                name: string(conn, &name)?,
+                heap: string(conn, &field(conn, &kind, "name")?)? == "HEAP",
+                usage: memory_usage(conn, &usage)?,
+            });
+        }
+
+        Ok(HeapInfo {
+            heap: memory_usage(conn, &heap)?,
+            non_heap: memory_usage(conn, &non_heap)?,
+            pools,
+        })
+    }
+}
+
+const USAGE: &str = "Ljava/lang/management/MemoryUsage;";
+
+// Calls getters on MXBeans, keeping what they return from being collected until we're done
+struct MxBeans<'a> {
+    conn: &'a JdwpConnection,
+    thread: u64,
+    objects: Pinned<'a>,
+}
+
+impl MxBeans<'_> {
+    // A static method with no arguments, returning 'returns'
+    fn get(&mut self, class: u64, name: &str, returns: &str) -> Result<Value> {
+        let signature = format!("(){}", returns);
+        let value = invoke_static(self.conn, self.thread, class, name, &signature, &[])?;
+        self.objects.pin(value)
+    }
+
+    // Likewise for an instance method
+    fn call(&mut self, object: &Value, name: &str, returns: &str) -> Result<Value> {
+        let object = match object {
+            Value::Object(object) => *object,
+            _ => return Err(Error::other(format!("Can't call {}() on null", name))),
+        };
+        let signature = format!("(){}", returns);
+        let class = object_reference::reference_type(self.conn, object)?.type_id;
+        let (class, method) = find_method(self.conn, class, name, &signature, false)?
+            .ok_or_else(|| Error::other(format!("No method {}{}", name, signature)))?;
+        let value = self
+            .conn
+            .invoke(Some(object), class, self.thread, method, &[], name)?;
+        self.objects.pin(value)
+    }
+}
+
+fn memory_usage(conn: &JdwpConnection, usage: &Value) -> Result<MemoryUsage> {
+    // -1 for undefined
+    let size = |name| match field(conn, usage, name)? {
+        Value::Long(size) => Ok(u64::try_from(size).ok()),
+        value => Err(Error::other(format!("MemoryUsage.{} is {:?}", name, value))),
+    };
+    Ok(MemoryUsage {
+        init: size("init")?,
+        used: size("used")?.unwrap_or(0),
+        committed: size("committed")?.unwrap_or(0),
+        max: size("max")?,
+    })
+}
+
+// An instance field, declared by the object's class or a superclass
+fn field(conn: &JdwpConnection, object: &Value, name: &str) -> Result<Value> {
+    let object = match object {
+        Value::Object(object) => *object,
+        _ => return Err(Error::other(format!("Can't read {} from null", name))),
+    };
+    let mut class = object_reference::reference_type(conn, object)?.type_id;
+    while class != 0 {
+        let fields = reference_type::fields(conn, class)?.fields;
+        if let Some(field) = fields.iter().find(|f| f.name == name) {
+            return object_reference::get_values(conn, object, &[field.field_id])?
+                .values
+                .pop()
+                .ok_or_else(|| Error::other("GetValues returned no values"));
+        }
+        class = class_type::superclass(conn, class)?.superclass;
+    }
+    Err(Error::other(format!("No field {}", name)))
+}
+
+fn string(conn: &JdwpConnection, value: &Value) -> Result<String> {
+    match value {
+        Value::Object(string) => Ok(string_reference::value(conn, *string)?.string_value),
+        _ => Ok(String::new()),
+    }
+}
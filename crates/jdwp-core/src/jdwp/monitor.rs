@@ -0,0 +1,404 @@
+//
+// Lock contention profiling, built on the monitor events (MONITOR_CONTENDED_ENTER/ENTERED and
+// MONITOR_WAIT/WAITED). Each time a thread blocks on a monitor we capture the stack of the
+// blocked thread and of the thread holding the monitor, and when it gets the monitor we record
+// how long it took. Contentions with the same monitor class and stacks are aggregated.
+//
+
+use std::cmp::Reverse;
+use std::collections::hash_map::{Entry, HashMap};
+use std::io::Result;
+use std::rc::Rc;
+use std::time::{Duration, Instant};
+
+use super::event::{Event, EventKind, SuspendPolicy};
+use super::{error_code, has_error_code, signature_to_name};
+use super::{method, object_reference, reference_type, thread_reference};
+use super::{JdwpConnection, JdwpJavaVirtualMachine, Location};
+use crate::model::Value;
+use crate::pattern::ClassPattern;
+use crate::snapshot::FrameInfo;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ContentionKind {
+    // Blocked trying to enter a synchronized block/method
+    Enter,
+    // Called Object.wait()
+    Wait,
+}
+
+#[derive(Debug, Clone)]
+pub struct ContentionRecord {
+    pub kind: ContentionKind,
+    pub monitor_class: String,
+    // Empty for waits, and if the monitor had been released by the time we looked
+    pub holder_stack: Vec<String>,
+    pub waiter_stack: Vec<String>,
+    pub count: u64,
+    pub total: Duration,
+    pub max: Duration,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct ContentionProfile {
+    // Sorted by total time blocked, longest first
+    pub records: Vec<ContentionRecord>,
+}
+
+struct Pending {
+    kind: ContentionKind,
+    monitor_class: String,
+    holder_stack: Vec<String>,
+    waiter_stack: Vec<String>,
+    start: Instant,
+}
+
+type ProfileKey = (ContentionKind, String, Vec<String>, Vec<String>);
+
+pub struct ContentionProfiler {
+    conn: Rc<JdwpConnection>,
+    class_pattern: Option<ClassPattern>,
+    requests: Vec<(EventKind, i32)>,
+    pending: HashMap<u64, Pending>,
+    records: HashMap<ProfileKey, ContentionRecord>,
+    frame_names: FrameNameCache,
+    vm_dead: bool,
+}
+
+impl JdwpJavaVirtualMachine {
+    // Start profiling monitor contention, optionally only for monitors whose class matches
+    // class_pattern. Each contention briefly suspends
+    // the blocked thread and the monitor's owner while their stacks are captured, so durations
+    // are approximate and include some of that overhead.
+    pub fn profile_contention(
+        &self,
+        class_pattern: Option<&ClassPattern>,
+    ) -> Result<ContentionProfiler> {
+        let mut profiler = ContentionProfiler {
+            conn: self.conn.clone(),
+            class_pattern: class_pattern.cloned(),
+            requests: vec![],
+            pending: HashMap::new(),
+            records: HashMap::new(),
+            frame_names: FrameNameCache::default(),
+            vm_dead: false,
+        };
+        let kinds = [
+            (EventKind::MonitorContendedEnter, SuspendPolicy::EventThread),
+            (EventKind::MonitorContendedEntered, SuspendPolicy::None),
+            (EventKind::MonitorWait, SuspendPolicy::EventThread),
+            (EventKind::MonitorWaited, SuspendPolicy::None),
+        ];
+        for &(kind, suspend_policy) in &kinds {
+            let id = self.conn.set_event_request(kind, suspend_policy, &[])?;
+            profiler.requests.push((kind, id));
+        }
+        Ok(profiler)
+    }
+}
+
+impl ContentionProfiler {
+    // Process events for the given amount of time
+    pub fn run_for(&mut self, duration: Duration) -> Result<()> {
+        let deadline = Instant::now() + duration;
+        loop {
+            let now = Instant::now();
+            if now >= deadline || !self.poll(Some(deadline - now))? {
+                return Ok(());
+            }
+        }
+    }
+
+    // Wait up to timeout (or forever) for a single monitor event and process it. Returns false if
+    // no event arrived in time, or the VM has exited.
+    pub fn poll(&mut self, timeout: Option<Duration>) -> Result<bool> {
+        if self.vm_dead {
+            return Ok(false);
+        }
+        let requests = &self.requests;
+        let event = self.conn.next_event(timeout, |e| {
+            e.kind() == EventKind::VmDeath || requests.iter().any(|&(_, id)| id == e.request_id())
+        })?;
+        match event {
+            Some(Event::MonitorContendedEnter {
+                thread,
+                object,
+                location,
+                ..
+            }) => self.begin(ContentionKind::Enter, thread, object, location)?,
+            Some(Event::MonitorWait {
+                thread,
+                object,
+                location,
+                ..
+            }) => self.begin(ContentionKind::Wait, thread, object, location)?,
+            Some(Event::MonitorContendedEntered { thread, .. })
+            | Some(Event::MonitorWaited { thread, .. }) => self.end(thread),
+            Some(Event::VmDeath { .. }) => {
+                self.vm_dead = true;
+                return Ok(false);
+            }
+            Some(_) => {}
+            None => return Ok(false),
+        }
+        Ok(true)
+    }
+
+    pub fn profile(&self) -> ContentionProfile {
+        profile(&self.records)
+    }
+
+    // Called with the event thread suspended. We must resume it whatever happens.
+    fn begin(
+        &mut self,
+        kind: ContentionKind,
+        thread: u64,
+        object: Value,
+        location: Location,
+    ) -> Result<()> {
+        // The blocking started when the event was sent, and capturing the stacks takes a while
+        let start = Instant::now();
+        let captured = self.capture(kind, thread, object, location, start);
+        thread_reference::resume(&self.conn, thread)?;
+        if let Some(pending) = captured? {
+            self.pending.insert(thread, pending);
+        }
+        Ok(())
+    }
+
+    fn capture(
+        &mut self,
+        kind: ContentionKind,
+        thread: u64,
+        object: Value,
+        location: Location,
+        start: Instant,
+    ) -> Result<Option<Pending>> {
+        let conn = self.conn.as_ref();
+        let object_id = match object {
+            // A class, for static synchronized methods
+            Value::Object(id) | Value::ClassObject(id) => id,
+            _ => return Ok(None),
+        };
+        let type_id = object_reference::reference_type(conn, object_id)?.type_id;
+        let monitor_class = signature_to_name(&reference_type::signature(conn, type_id)?.signature);
+        if let Some(pattern) = &self.class_pattern {
+            if !pattern.matches(&monitor_class) {
+                return Ok(None);
+            }
+        }
+
+        let mut waiter_stack = self.frame_names.stack(conn, thread)?;
+        if waiter_stack.is_empty() {
+            waiter_stack.push(self.frame_names.describe(conn, &location)?);
+        }
+
+        let mut holder_stack = vec![];
+        if kind == ContentionKind::Enter {
+            let owner = object_reference::monitor_info(conn, object_id)?.owner;
+            // The owner may have released the monitor in the meantime
+            if owner != 0 && owner != thread {
+                thread_reference::suspend(conn, owner)?;
+                let stack = self.frame_names.stack(conn, owner);
+                thread_reference::resume(conn, owner)?;
+                holder_stack = stack?;
+            }
+        }
+
+        Ok(Some(Pending {
+            kind,
+            monitor_class,
+            holder_stack,
+            waiter_stack,
+            start,
+        }))
+    }
+
+    fn end(&mut self, thread: u64) {
+        let pending = match self.pending.remove(&thread) {
+            Some(p) => p,
+            None => return,
+        };
+        let elapsed = pending.start.elapsed();
+        aggregate(&mut self.records, pending, elapsed);
+    }
+}
+
+// Count a contention which lasted 'elapsed' in the record of those with the same kind, monitor
+// class and stacks
+fn aggregate(
+    records: &mut HashMap<ProfileKey, ContentionRecord>,
+    pending: Pending,
+    elapsed: Duration,
+) {
+    let key = (
+        pending.kind,
+        pending.monitor_class.clone(),
+        pending.holder_stack.clone(),
+        pending.waiter_stack.clone(),
+    );
+    let record = records.entry(key).or_insert(ContentionRecord {
+        kind: pending.kind,
+        monitor_class: pending.monitor_class,
+        holder_stack: pending.holder_stack,
+        waiter_stack: pending.waiter_stack,
+        count: 0,
+        total: Duration::from_secs(0),
+        max: Duration::from_secs(0),
+    });
+    record.count += 1;
+    record.total += elapsed;
+    record.max = record.max.max(elapsed);
+}
+
+fn profile(records: &HashMap<ProfileKey, ContentionRecord>) -> ContentionProfile {
+    let mut records: Vec<ContentionRecord> = records.values().cloned().collect();
+    records.sort_by_key(|r| Reverse(r.total));
+    ContentionProfile { records }
+}
+
+impl Drop for ContentionProfiler {
+    fn drop(&mut self) {
+        if self.vm_dead {
+            return;
+        }
+        for &(kind, id) in &self.requests {
+            let _ = self.conn.clear_event_request(kind, id);
+        }
+    }
+}
+
+// Formatting a frame takes several round trips, and the same frames come up over and over, so
+// cache what we can. Shallow thread dumps (see capture_shallow_thread_dump()) use it too.
+#[derive(Default)]
+pub(super) struct FrameNameCache {
+    classes: HashMap<u64, String>,
+    methods: HashMap<(u64, u64), String>,
+    line_tables: HashMap<(u64, u64), Option<method::LineTableReply>>,
+}
+
+impl FrameNameCache {
+    fn stack(&mut self, conn: &JdwpConnection, thread: u64) -> Result<Vec<String>> {
+        let frames = thread_reference::frames(conn, thread, 0, -1)?.frames;
+        frames
+            .iter()
+            .map(|frame| self.describe(conn, &frame.location))
+            .collect()
+    }
+
+    fn describe(&mut self, conn: &JdwpConnection, location: &Location) -> Result<String> {
+        let frame = self.frame(conn, location)?;
+        Ok(match frame.line_number {
+            Some(line) => format!("{}.{}:{}", frame.class_name, frame.method_name, line),
+            None => format!("{}.{}", frame.class_name, frame.method_name),
+        })
+    }
+
+    pub(super) fn frame(
+        &mut self,
+        conn: &JdwpConnection,
+        location: &Location,
+    ) -> Result<FrameInfo> {
+        if let Entry::Vacant(entry) = self.classes.entry(location.class_id) {
+            let signature = reference_type::signature(conn, location.class_id)?.signature;
+            entry.insert(signature_to_name(&signature));
+        }
+        let method_key = (location.class_id, location.method_id);
+        if !self.methods.contains_key(&method_key) {
+            for m in reference_type::methods(conn, location.class_id)?.methods {
+                self.methods
+                    .insert((location.class_id, m.method_id), m.name);
+            }
+        }
+        if let Entry::Vacant(entry) = self.line_tables.entry(method_key) {
+            let table = match method::line_table(conn, location.class_id, location.method_id) {
+                Ok(t) => Some(t),
+                Err(e)
+                    if has_error_code(
+                        &e,
+                        &[error_code::NATIVE_METHOD, error_code::ABSENT_INFORMATION],
+                    ) =>
+                {
+                    None
+                }
+                Err(e) => return Err(e),
+            };
+            entry.insert(table);
+        }
+
+        let class_name = self.classes[&location.class_id].clone();
+        let method_name = self
+            .methods
+            .get(&method_key)
+            .map(String::as_str)
+            .unwrap_or("<unknown>")
+            .to_string();
+        let line_number = self.line_tables[&method_key].as_ref().and_then(|table| {
+            table
+                .lines
+                .iter()
+                .filter(|entry| entry.line_code_index as u64 <= location.location_idx)
+                .max_by_key(|entry| entry.line_code_index)
+                .map(|entry| entry.line_number)
+        });
+        Ok(FrameInfo {
+            class_name,
+            method_name,
+            line_number,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pending(kind: ContentionKind, class: &str, holder: &[&str], waiter: &[&str]) -> Pending {
+        Pending {
+            kind,
+            monitor_class: class.to_string(),
+            holder_stack: holder.iter().map(|f| f.to_string()).collect(),
+            waiter_stack: waiter.iter().map(|f| f.to_string()).collect(),
+            start: Instant::now(),
+        }
+    }
+
+    #[test]
+    fn aggregation() {
+        use ContentionKind::{Enter, Wait};
+        let mut records = HashMap::new();
+        let ms = Duration::from_millis;
+        for (pending, elapsed) in [
+            (pending(Enter, "Cache", &["put"], &["get"]), ms(30)),
+            (pending(Enter, "Cache", &["put"], &["get"]), ms(10)),
+            (pending(Enter, "Cache", &["put"], &["get"]), ms(20)),
+            // Differing in each part of the key
+            (pending(Wait, "Cache", &["put"], &["get"]), ms(100)),
+            (pending(Enter, "Pool", &["put"], &["get"]), ms(5)),
+            (pending(Enter, "Cache", &["clear"], &["get"]), ms(1)),
+            (pending(Enter, "Cache", &["put"], &["remove"]), ms(50)),
+        ] {
+            aggregate(&mut records, pending, elapsed);
+        }
+
+        let records = profile(&records).records;
+        let summary: Vec<_> = records
+            .iter()
+            .map(|r| (r.kind, r.monitor_class.as_str(), r.count, r.total, r.max))
+            .collect();
+        assert_eq!(
+            summary,
+            vec![
+                (Wait, "Cache", 1, ms(100), ms(100)),
+                (Enter, "Cache", 3, ms(60), ms(30)),
+                (Enter, "Cache", 1, ms(50), ms(50)),
+                (Enter, "Pool", 1, ms(5), ms(5)),
+                (Enter, "Cache", 1, ms(1), ms(1)),
+            ]
+        );
+        assert_eq!(records[1].holder_stack, ["put"]);
+        assert_eq!(records[1].waiter_stack, ["get"]);
+        assert_eq!(records[2].waiter_stack, ["remove"]);
+        assert_eq!(records[4].holder_stack, ["clear"]);
+    }
+}
@@ -0,0 +1,295 @@
+//
+// What the target prints to System.out and System.err, for when there's no console to watch:
+// a container, a service started by something else, a machine we can only reach through the
+// debug port.
+//
+// Finding the streams is just reading System's fields. Seeing what's printed to them takes
+// swapping in a PrintStream of our own, writing to a ByteArrayOutputStream, which takes method
+// invocations and so a thread suspended by an event. After that, what's been printed is read
+// straight out of the buffer's fields, which doesn't need a thread, and can be done while the
+// target runs. Teeing, so that the output still goes where it used to as well, writes what's
+// been read to the old stream, which is an invocation again, so that only happens when poll() is
+// given a thread to do it in.
+//
+// The buffer only grows until restore() puts the old stream back: emptying it while the target
+// runs could lose whatever was printed in between.
+//
+
+use std::io::{Error, ErrorKind, Result, Write};
+use std::rc::Rc;
+
+use super::diagnostic::{invoke_static, load_class};
+use super::invoke::invoke_err;
+use super::{array_reference, object_reference, reference_type, virtual_machine};
+use super::{JdwpConnection, JdwpJavaVirtualMachine, JdwpObjectReference, JdwpThreadReference};
+use super::{Modifiers, Value};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StandardStream {
+    Out,
+    Err,
+}
+
+impl StandardStream {
+    fn field(self) -> &'static str {
+        match self {
+            StandardStream::Out => "out",
+            StandardStream::Err => "err",
+        }
+    }
+
+    fn setter(self) -> &'static str {
+        match self {
+            StandardStream::Out => "setOut",
+            StandardStream::Err => "setErr",
+        }
+    }
+}
+
+// Created with JdwpJavaVirtualMachine::capture_output(). If it's dropped without restore(), the
+// target keeps printing into the buffer, and the buffer is let go of once something else replaces
+// the stream.
+pub struct OutputCapture {
+    conn: Rc<JdwpConnection>,
+    stream: StandardStream,
+    tee: bool,
+    system: u64,
+    // The stream we replaced, and ours, and the ByteArrayOutputStream it writes to
+    original: u64,
+    printer: u64,
+    buffer: u64,
+    // ByteArrayOutputStream's buf and count
+    buf_field: u64,
+    count_field: u64,
+    // How much of the buffer has been given to the sink, and written to the old stream
+    read: i32,
+    forwarded: i32,
+}
+
+impl JdwpJavaVirtualMachine {
+    // The PrintStream System.out or System.err is now, or None if it's been set to null
+    pub fn standard_stream(&self, stream: StandardStream) -> Result<Option<JdwpObjectReference>> {
+        let conn = self.conn.as_ref();
+        let system = system_class(conn)?;
+        Ok(
+            read_stream(conn, system, stream)?.map(|object_id| JdwpObjectReference {
+                conn: self.conn.clone(),
+                object_id,
+            }),
+        )
+    }
+
+    // Send what the target prints to 'stream' to us instead, to be read with poll(), or, with
+    // 'tee', to us as well. 'thread' has to have been suspended by an event, and is where the
+    // streams are made and swapped (see invoke_method()).
+    pub fn capture_output(
+        &self,
+        thread: &JdwpThreadReference,
+        stream: StandardStream,
+        tee: bool,
+    ) -> Result<OutputCapture> {
+        let conn = self.conn.as_ref();
+        let thread_id = thread.thread_id;
+        let system = system_class(conn)?;
+        let original = read_stream(conn, system, stream)?.ok_or_else(|| {
+            Error::new(
+                ErrorKind::NotFound,
+                format!("System.{} is null", stream.field()),
+            )
+        })?;
+
+        let buffer_class = load_class(conn, thread_id, "java.io.ByteArrayOutputStream")?;
+        let fields = reference_type::fields(conn, buffer_class)?.fields;
+        let field = |name: &str| {
+            fields
+                .iter()
+                .find(|f| f.name == name)
+                .map(|f| f.field_id)
+                .ok_or_else(|| Error::other(format!("ByteArrayOutputStream has no {} field", name)))
+        };
+        let (buf_field, count_field) = (field("buf")?, field("count")?);
+        let buffer = construct(conn, thread_id, buffer_class, "()V", &[])?;
+        conn.disable_collection(buffer, "output capture")?;
+        let mut capture = OutputCapture {
+            conn: self.conn.clone(),
+            stream,
+            tee,
+            system,
+            original,
+            printer: 0,
+            buffer,
+            buf_field,
+            count_field,
+            read: 0,
+            forwarded: 0,
+        };
+
+        let printer_class = load_class(conn, thread_id, "java.io.PrintStream")?;
+        let printer = construct(
+            conn,
+            thread_id,
+            printer_class,
+            "(Ljava/io/OutputStream;)V",
+            &[Value::Object(buffer)],
+        )?;
+        conn.disable_collection(printer, "output capture")?;
+        capture.printer = printer;
+        set_stream(conn, thread_id, system, stream, printer)?;
+        Ok(capture)
+    }
+}
+
+impl OutputCapture {
+    pub fn stream(&self) -> StandardStream {
+        self.stream
+    }
+
+    // Writes whatever has been printed since the last call to 'sink', returning how many bytes
+    // that was. When teeing, it's also written to the old stream if there's a 'thread' to do it
+    // in, suspended by an event; otherwise that waits for a call with one, or restore().
+    pub fn poll<W: Write>(
+        &mut self,
+        sink: &mut W,
+        thread: Option<&JdwpThreadReference>,
+    ) -> Result<usize> {
+        let conn = self.conn.as_ref();
+        // count is read before buf, since the buffer is copied to a bigger one before count
+        // grows past the end of it: whichever buf we get has at least count bytes in it
+        let values =
+            object_reference::get_values(conn, self.buffer, &[self.count_field, self.buf_field])?
+                .values;
+        let (count, buf) = match (values.first(), values.get(1)) {
+            (Some(Value::Integer(count)), Some(Value::Object(buf))) => (*count, *buf),
+            _ => return Err(super::protocol_err("ByteArrayOutputStream has no buffer")),
+        };
+
+        let mut printed = 0;
+        if count > self.read {
+            let bytes = array_reference::get_values(conn, buf, self.read, count - self.read)?
+                .values
+                .0;
+            let bytes: Vec<u8> = bytes
+                .iter()
+                .map(|b| match b {
+                    Value::Byte(b) => *b as u8,
+                    _ => b'?',
+                })
+                .collect();
+            sink.write_all(&bytes)?;
+            printed = bytes.len();
+            self.read = count;
+        }
+
+        if let (true, Some(thread)) = (self.tee, thread) {
+            if count > self.forwarded {
+                let original = JdwpObjectReference {
+                    conn: self.conn.clone(),
+                    object_id: self.original,
+                };
+                original.invoke_method(
+                    thread,
+                    "write",
+                    "([BII)V",
+                    &[
+                        Value::Object(buf),
+                        Value::Integer(self.forwarded),
+                        Value::Integer(count - self.forwarded),
+                    ],
+                )?;
+                self.forwarded = count;
+            }
+        }
+        Ok(printed)
+    }
+
+    // Puts the old stream back, unless something else has replaced ours since, and writes
+    // whatever is left in the buffer to 'sink' (and the old stream, when teeing). 'thread' has to
+    // have been suspended by an event.
+    pub fn restore<W: Write>(mut self, sink: &mut W, thread: &JdwpThreadReference) -> Result<()> {
+        let conn = self.conn.as_ref();
+        if read_stream(conn, self.system, self.stream)? == Some(self.printer) {
+            set_stream(
+                conn,
+                thread.thread_id,
+                self.system,
+                self.stream,
+                self.original,
+            )?;
+        }
+        self.poll(sink, Some(thread))?;
+        Ok(())
+    }
+}
+
+impl Drop for OutputCapture {
+    fn drop(&mut self) {
+        // Whatever System.out is now keeps ours alive for as long as it's needed
+        for object in [self.buffer, self.printer] {
+            if object != 0 && self.conn.check_connected().is_ok() {
+                let _ = self.conn.enable_collection(object);
+            }
+        }
+    }
+}
+
+fn system_class(conn: &JdwpConnection) -> Result<u64> {
+    virtual_machine::classes_by_signature(conn, "Ljava/lang/System;")?
+        .classes
+        .first()
+        .map(|class| class.type_id)
+        .ok_or_else(|| Error::other("java.lang.System isn't loaded"))
+}
+
+fn read_stream(conn: &JdwpConnection, system: u64, stream: StandardStream) -> Result<Option<u64>> {
+    let field = reference_type::fields(conn, system)?
+        .fields
+        .iter()
+        .find(|f| f.name == stream.field() && f.mod_bits as u32 & Modifiers::STATIC != 0)
+        .map(|f| f.field_id)
+        .ok_or_else(|| Error::other(format!("System has no {} field", stream.field())))?;
+    match reference_type::get_values(conn, system, &[field])?
+        .values
+        .first()
+    {
+        Some(Value::Object(stream)) => Ok(Some(*stream)),
+        _ => Ok(None),
+    }
+}
+
+fn set_stream(
+    conn: &JdwpConnection,
+    thread: u64,
+    system: u64,
+    stream: StandardStream,
+    printer: u64,
+) -> Result<()> {
+    invoke_static(
+        conn,
+        thread,
+        system,
+        stream.setter(),
+        "(Ljava/io/PrintStream;)V",
+        &[Value::Object(printer)],
+    )?;
+    Ok(())
+}
+
+// A new instance of 'class', made with its constructor with this JNI signature
+fn construct(
+    conn: &JdwpConnection,
+    thread: u64,
+    class: u64,
+    signature: &str,
+    args: &[Value],
+) -> Result<u64> {
+    let constructor = reference_type::methods(conn, class)?
+        .methods
+        .iter()
+        .find(|m| m.name == "<init>" && m.signature == signature)
+        .map(|m| m.method_id)
+        .ok_or_else(|| Error::other(format!("No constructor {}", signature)))?;
+    match conn.new_instance(class, thread, constructor, args, "<init>")? {
+        Value::Object(object) => Ok(object),
+        _ => Err(invoke_err("The constructor made nothing".to_string())),
+    }
+}
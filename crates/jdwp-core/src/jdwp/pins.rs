@@ -0,0 +1,245 @@
+//
+// Objects we've told the target not to collect. ObjectReference.DisableCollection nests: the
+// target only lets an object go once every DisableCollection has been matched by an
+// EnableCollection, so one forgotten call pins the object (and everything it refers to) for as
+// long as the VM runs, and a long session which keeps forgetting slowly fills the heap. So every
+// DisableCollection goes through the connection's pin table: pinned_objects() lists what's
+// pinned, CollectionPin re-enables collection when it's dropped, and dispose() re-enables
+// whatever is left.
+//
+
+use std::collections::HashMap;
+use std::io::Result;
+use std::rc::Rc;
+use std::time::Instant;
+
+use super::{object_reference, JdwpConnection, JdwpJavaVirtualMachine, JdwpObjectReference};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PinnedObject {
+    pub object: u64,
+    // How many DisableCollections haven't been matched by an EnableCollection yet
+    pub count: u32,
+    // Why it was first pinned, e.g. "method invocation" for an argument we created
+    pub reason: String,
+    // When it was first pinned
+    pub since: Instant,
+}
+
+#[derive(Debug, Default)]
+pub(super) struct PinTable {
+    pins: HashMap<u64, PinnedObject>,
+}
+
+impl JdwpConnection {
+    pub(super) fn disable_collection(&self, object: u64, reason: &str) -> Result<()> {
+        object_reference::disable_collection(self, object)?;
+        self.pins
+            .borrow_mut()
+            .pins
+            .entry(object)
+            .or_insert_with(|| PinnedObject {
+                object,
+                count: 0,
+                reason: reason.to_string(),
+                since: Instant::now(),
+            })
+            .count += 1;
+        Ok(())
+    }
+
+    // The object is forgotten even if this fails, since the usual reason is that the target (and
+    // so the pin) has gone
+    pub(super) fn enable_collection(&self, object: u64) -> Result<()> {
+        {
+            let mut table = self.pins.borrow_mut();
+            match table.pins.get_mut(&object) {
+                Some(pin) if pin.count > 1 => pin.count -= 1,
+                Some(_) => {
+                    table.pins.remove(&object);
+                }
+                None => return Ok(()),
+            }
+        }
+        object_reference::enable_collection(self, object)?;
+        Ok(())
+    }
+
+    // Unpin everything, before disposing of the connection. Errors are ignored, so that one
+    // object which has gone doesn't leave the rest pinned.
+    pub(super) fn enable_all_collection(&self) {
+        let pins: Vec<PinnedObject> = self
+            .pins
+            .borrow_mut()
+            .pins
+            .drain()
+            .map(|(_, p)| p)
+            .collect();
+        for pin in pins {
+            for _ in 0..pin.count {
+                let _ = object_reference::enable_collection(self, pin.object);
+            }
+        }
+    }
+}
+
+impl JdwpJavaVirtualMachine {
+    // Every object we've kept from being collected and not let go of yet, longest pinned first
+    pub fn pinned_objects(&self) -> Vec<PinnedObject> {
+        let mut pins: Vec<PinnedObject> = self.conn.pins.borrow().pins.values().cloned().collect();
+        pins.sort_by_key(|p| (p.since, p.object));
+        pins
+    }
+}
+
+impl JdwpObjectReference {
+    // Keep the target from collecting the object until the returned pin is dropped (or released,
+    // or the connection disposed of), e.g. to hold on to an object found with instances() while
+    // the target runs
+    pub fn disable_collection(&self) -> Result<CollectionPin> {
+        self.conn
+            .disable_collection(self.object_id, "disable_collection()")?;
+        Ok(CollectionPin {
+            conn: self.conn.clone(),
+            object: self.object_id,
+            released: false,
+        })
+    }
+}
+
+pub struct CollectionPin {
+    conn: Rc<JdwpConnection>,
+    object: u64,
+    released: bool,
+}
+
+impl CollectionPin {
+    pub fn object(&self) -> u64 {
+        self.object
+    }
+
+    // Let the target collect the object again, reporting any error rather than ignoring it as
+    // dropping the pin does
+    pub fn release(mut self) -> Result<()> {
+        self.released = true;
+        self.conn.check_connected()?;
+        self.conn.enable_collection(self.object)
+    }
+}
+
+impl Drop for CollectionPin {
+    fn drop(&mut self) {
+        // After dispose() there's nothing left to unpin
+        if !self.released && self.conn.check_connected().is_ok() {
+            let _ = self.conn.enable_collection(self.object);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::jdwp::fake::{self, reply};
+    use crate::jdwp::{error_code, jdwp_error_code};
+
+    const DISABLE: u8 = 7;
+    const ENABLE: u8 = 8;
+    // Which the target says has been collected when it's let go of
+    const COLLECTED: u64 = 0x99;
+
+    fn target() -> (JdwpJavaVirtualMachine, fake::Log) {
+        let answers = fake::Answers::default()
+            // VirtualMachine.Dispose
+            .on(1, 6, |_| reply![])
+            .on(9, DISABLE, |_| reply![])
+            .on(9, ENABLE, |data| match fake::first_id(data) {
+                COLLECTED => Err(error_code::INVALID_OBJECT),
+                _ => reply![],
+            });
+        let log = answers.log();
+        (JdwpJavaVirtualMachine::new(answers.attach()), log)
+    }
+
+    // The ObjectReference commands the target was sent, and the objects they were about
+    fn sent(log: &fake::Log) -> Vec<(u8, u64)> {
+        log.lock()
+            .unwrap()
+            .iter()
+            .filter(|(command_set, _, _)| *command_set == 9)
+            .map(|(_, command, data)| (*command, fake::first_id(data)))
+            .collect()
+    }
+
+    fn object(jvm: &JdwpJavaVirtualMachine, object_id: u64) -> JdwpObjectReference {
+        JdwpObjectReference {
+            conn: jvm.conn.clone(),
+            object_id,
+        }
+    }
+
+    fn counts(jvm: &JdwpJavaVirtualMachine) -> Vec<(u64, u32)> {
+        jvm.pinned_objects()
+            .iter()
+            .map(|p| (p.object, p.count))
+            .collect()
+    }
+
+    #[test]
+    fn refcount() {
+        let (jvm, log) = target();
+        let first = object(&jvm, 0x10).disable_collection().unwrap();
+        let second = object(&jvm, 0x10).disable_collection().unwrap();
+        assert_eq!(counts(&jvm), vec![(0x10, 2)]);
+        assert_eq!(jvm.pinned_objects()[0].reason, "disable_collection()");
+
+        drop(first);
+        assert_eq!(counts(&jvm), vec![(0x10, 1)]);
+        drop(second);
+        assert!(counts(&jvm).is_empty());
+        assert_eq!(
+            sent(&log),
+            vec![
+                (DISABLE, 0x10),
+                (DISABLE, 0x10),
+                (ENABLE, 0x10),
+                (ENABLE, 0x10)
+            ]
+        );
+    }
+
+    #[test]
+    fn release() {
+        let (jvm, log) = target();
+        let pin = object(&jvm, 0x10).disable_collection().unwrap();
+        pin.release().unwrap();
+        assert_eq!(sent(&log).len(), 2);
+
+        // Forgotten even though the target couldn't let it go, and only let go of once
+        let pin = object(&jvm, COLLECTED).disable_collection().unwrap();
+        let err = pin.release().unwrap_err();
+        assert_eq!(jdwp_error_code(&err), Some(error_code::INVALID_OBJECT));
+        assert!(counts(&jvm).is_empty());
+        assert_eq!(sent(&log).len(), 4);
+    }
+
+    #[test]
+    fn dispose() {
+        let (jvm, log) = target();
+        let pin = object(&jvm, 0x10).disable_collection().unwrap();
+        let _again = object(&jvm, 0x10).disable_collection().unwrap();
+        let _other = object(&jvm, 0x20).disable_collection().unwrap();
+        log.lock().unwrap().clear();
+
+        jvm.dispose().unwrap();
+        let mut enabled = sent(&log);
+        enabled.sort_unstable();
+        assert_eq!(
+            enabled,
+            vec![(ENABLE, 0x10), (ENABLE, 0x10), (ENABLE, 0x20)]
+        );
+
+        // Dropping a pin afterwards doesn't try to unpin it again
+        drop(pin);
+        assert_eq!(sent(&log).len(), 3);
+    }
+}
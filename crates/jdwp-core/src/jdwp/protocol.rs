@@ -0,0 +1,119 @@
+//
+// The low-level JDWP API, for tools which need commands (or options of commands) the rest of
+// this crate doesn't use, or which want to sit between a debugger and a target. It's everything
+// here: packets and how they're read and written, one module per command set with a function per
+// command, the error codes, and the Serialize and Deserialize traits the command functions use to
+// encode arguments and decode replies. The packet format is described in
+// https://docs.oracle.com/en/java/javase/21/docs/specs/jdwp/jdwp-spec.html and the commands in
+// https://docs.oracle.com/en/java/javase/21/docs/specs/jdwp/jdwp-protocol.html. Names follow the
+// spec's, in snake case: ClassesBySignature in VirtualMachine is
+// virtual_machine::classes_by_signature().
+//
+// This API is versioned on its own, by API_VERSION, rather than along with the JavaVirtualMachine
+// model or the crate. Within a major version, commands, reply fields and error codes are only
+// added, never removed or changed, so code written against 1.x keeps compiling with any later
+// 1.y. Reply structs and the enums are #[non_exhaustive], so that adding a field or a variant
+// isn't a breaking change: replies can only be had by deserializing them, and matches need a
+// wildcard arm.
+//
+// Commands go through a JdwpConnection, which may be shared with the high-level API (see
+// JdwpJavaVirtualMachine::connection()). Each command function sends its command and waits for
+// the reply, and events arriving in the meantime are queued for next_event() as usual.
+//
+
+use std::convert::TryInto;
+use std::io::{Error, ErrorKind, Result, Write};
+
+use byteorder::{BigEndian, WriteBytesExt};
+
+pub use super::event::event_request;
+pub use super::{array_reference, array_type, class_object_reference, class_type, method};
+pub use super::{error_code, jdwp_error_code, jdwp_error_kind, JdwpErrorKind};
+pub use super::{module_reference, object_reference, reference_type, stack_frame};
+pub use super::{read_packet, Packet, HEADER_SIZE, REPLY_FLAG};
+pub use super::{string_reference, thread_reference, virtual_machine};
+pub use super::{ArrayRegion, Deserialize, JdwpConnection, Location, Serialize, TypeTag};
+
+// (major, minor), see above
+pub const API_VERSION: (u32, u32) = (1, 0);
+
+pub fn write_packet<W: Write>(writer: &mut W, packet: &Packet) -> Result<()> {
+    let (id, flags, header_rest, data) = match packet {
+        Packet::Reply {
+            id,
+            error_code,
+            data,
+        } => (*id, REPLY_FLAG, *error_code, data),
+        Packet::Command {
+            id,
+            command_set,
+            command,
+            data,
+        } => (*id, 0, u16::from_be_bytes([*command_set, *command]), data),
+    };
+    let len: u32 = (data.len() + HEADER_SIZE as usize)
+        .try_into()
+        .map_err(|_| Error::new(ErrorKind::InvalidInput, "Packet too large"))?;
+    let mut header = Vec::with_capacity(HEADER_SIZE as usize);
+    header.write_u32::<BigEndian>(len)?;
+    header.write_u32::<BigEndian>(id)?;
+    header.write_u8(flags)?;
+    header.write_u16::<BigEndian>(header_rest)?;
+    writer.write_all(&header)?;
+    writer.write_all(data)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    #[test]
+    fn packets_round_trip() {
+        let packets = [
+            Packet::Command {
+                id: 7,
+                command_set: 1,
+                command: 2,
+                data: b"\0\0\0\x01X".to_vec(),
+            },
+            Packet::Reply {
+                id: 7,
+                error_code: error_code::INVALID_CLASS,
+                data: vec![],
+            },
+        ];
+        let mut bytes = vec![];
+        for packet in &packets {
+            write_packet(&mut bytes, packet).unwrap();
+        }
+        assert_eq!(&bytes[..11], b"\0\0\0\x10\0\0\0\x07\0\x01\x02");
+        let mut reader = Cursor::new(bytes);
+        for packet in &packets {
+            assert_eq!(&read_packet(&mut reader).unwrap(), packet);
+        }
+    }
+
+    #[test]
+    fn error_code_names() {
+        assert_eq!(error_code::name(21), Some("INVALID_CLASS"));
+        assert_eq!(
+            error_code::name(error_code::ALREADY_INVOKING),
+            Some("ALREADY_INVOKING")
+        );
+        assert_eq!(error_code::name(1), None);
+
+        let err = super::super::command_err(
+            thread_reference::SET_ID,
+            6,
+            &0x42u64.to_be_bytes(),
+            error_code::INVALID_THREAD,
+        );
+        assert_eq!(
+            err.to_string(),
+            "ThreadReference.Frames for thread 0x42 failed: INVALID_THREAD (10), the thread has \
+             exited or isn't a thread"
+        );
+        assert_eq!(jdwp_error_code(&err), Some(error_code::INVALID_THREAD));
+    }
+}
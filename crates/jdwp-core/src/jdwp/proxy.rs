@@ -0,0 +1,914 @@
+//
+// A JDWP agent only takes one debugger at a time, which rules out, say, leaving a sampler
+// attached to a service while someone debugs it. JdwpProxy holds the one connection and accepts
+// any number of debuggers itself (anything which speaks JDWP, not just this crate), passing their
+// commands on and sorting out whose replies and events are whose:
+//
+//  - Each client has its own packet IDs, which are mapped onto the proxy's own when its commands
+//    are sent on.
+//  - Event requests belong to the client which made them, and each client only gets events for
+//    its own requests. Events which aren't for any request (VMDeath) go to every client.
+//  - VirtualMachine.Dispose only disconnects the client, and EventRequest.ClearAllBreakpoints only
+//    clears its own breakpoints.
+//  - When a client goes, by Dispose or by just hanging up, its event requests are cleared and
+//    it's resumed the VM (and threads) as many times as it suspended them, counting suspensions
+//    by its events. Events for its requests which were already on the way are resumed too.
+//
+// An event packet suspends the target once, however many clients' events are in it, so the
+// suspension is counted against the client whose request the first of them is for: that's who
+// would have been the only one to see it without the proxy.
+//
+// Packets to clients are written once the proxy's state is unlocked, each client's under a lock
+// of its own, so that a client which is slow to read only holds up its own packets.
+//
+// What clients can't be kept from is seeing each other's effects on the target: if one suspends
+// the VM it's suspended for all of them, and resuming it invalidates everyone's frames.
+//
+
+use std::collections::{HashMap, HashSet};
+use std::convert::TryInto;
+use std::io::{Cursor, Error, ErrorKind, Result};
+use std::net::{Shutdown, SocketAddr, TcpListener, TcpStream, ToSocketAddrs};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::thread;
+
+use byteorder::{BigEndian, ReadBytesExt};
+
+use super::event::{event_request, COMPOSITE_COMMAND, COMPOSITE_COMMAND_SET};
+use super::protocol::write_packet;
+use super::{handshake, thread_reference, virtual_machine};
+use super::{read_packet, target_err, unsupported_err, Deserialize, Event, Packet};
+
+// The commands the proxy looks at, besides the composite event command
+const ID_SIZES: u8 = 7;
+const DISPOSE: u8 = 6;
+const SUSPEND: u8 = 8;
+const RESUME: u8 = 9;
+const THREAD_SUSPEND: u8 = 2;
+const THREAD_RESUME: u8 = 3;
+const SET: u8 = 1;
+const CLEAR: u8 = 2;
+const CLEAR_ALL_BREAKPOINTS: u8 = 3;
+
+const BREAKPOINT: u8 = 2;
+const SUSPEND_EVENT_THREAD: u8 = 1;
+const SUSPEND_ALL: u8 = 2;
+
+pub struct JdwpProxy {
+    shared: Arc<Shared>,
+}
+
+struct Shared {
+    upstream: Mutex<TcpStream>,
+    state: Mutex<State>,
+    // Set once the proxy has been shut down or the target has gone
+    closed: AtomicBool,
+    local_addr: SocketAddr,
+}
+
+#[derive(Default)]
+struct State {
+    next_id: u32,
+    next_client: u64,
+    clients: HashMap<u64, Client>,
+    // Commands sent to the target whose replies haven't come back yet, by the proxy's packet ID
+    pending: HashMap<u32, Pending>,
+    // Event requests, by ID, with the client which made them and their event kind
+    requests: HashMap<i32, (u64, u8)>,
+    // Requests of clients which have gone. Events for them may still arrive.
+    orphaned: HashSet<i32>,
+}
+
+struct Client {
+    // For shutting the connection down, which mustn't wait for a write to finish
+    stream: TcpStream,
+    writer: Arc<Mutex<TcpStream>>,
+    // How many more times the client (or its events) suspended the VM, or each thread, than it
+    // resumed it
+    vm_suspends: i64,
+    thread_suspends: HashMap<u64, i64>,
+}
+
+// Packets for clients, to be written once the state is unlocked (see deliver())
+type Outgoing = Vec<(Arc<Mutex<TcpStream>>, Packet)>;
+
+enum Pending {
+    // The client's packet ID, and the event kind for EventRequest.Set
+    Client {
+        client: u64,
+        id: u32,
+        set_kind: Option<u8>,
+    },
+    // Sent by the proxy itself, so the reply is dropped
+    Proxy,
+}
+
+impl JdwpProxy {
+    // Connects to the target at 'target', then accepts debuggers at 'listen' (port 0 picks a free
+    // port, see local_addr())
+    pub fn start<T: ToSocketAddrs, L: ToSocketAddrs>(target: T, listen: L) -> Result<JdwpProxy> {
+        let mut upstream = handshake::connect(target, handshake::DEFAULT_TIMEOUT)?;
+        check_id_sizes(&mut upstream)?;
+        let listener = TcpListener::bind(listen)?;
+        let shared = Arc::new(Shared {
+            upstream: Mutex::new(upstream.try_clone()?),
+            state: Mutex::new(State {
+                // 0 was the ID sizes command
+                next_id: 1,
+                ..Default::default()
+            }),
+            closed: AtomicBool::new(false),
+            local_addr: listener.local_addr()?,
+        });
+        let reader = shared.clone();
+        thread::spawn(move || read_target(reader, upstream));
+        let acceptor = shared.clone();
+        thread::spawn(move || accept_clients(acceptor, listener));
+        Ok(JdwpProxy { shared })
+    }
+
+    // Where debuggers should attach
+    pub fn local_addr(&self) -> SocketAddr {
+        self.shared.local_addr
+    }
+
+    pub fn client_count(&self) -> usize {
+        self.shared.state().clients.len()
+    }
+
+    // False once the target has gone, after which there's nothing left to proxy
+    pub fn is_connected(&self) -> bool {
+        !self.shared.closed.load(Ordering::SeqCst)
+    }
+
+    // Disconnects every client and disposes of the connection to the target, which resumes
+    // anything still suspended. Dropping the proxy does the same.
+    pub fn shutdown(self) {}
+}
+
+impl Drop for JdwpProxy {
+    fn drop(&mut self) {
+        let shared = &self.shared;
+        if shared.closed.swap(true, Ordering::SeqCst) {
+            return;
+        }
+        {
+            let mut state = shared.state();
+            for client in state.clients.values() {
+                let _ = client.stream.shutdown(Shutdown::Both);
+            }
+            state.clients.clear();
+            let _ = shared.send(
+                &mut state,
+                virtual_machine::SET_ID,
+                DISPOSE,
+                vec![],
+                Pending::Proxy,
+            );
+        }
+        let _ = shared.upstream.lock().unwrap().shutdown(Shutdown::Both);
+        // Wake the acceptor up so that it notices
+        let _ = TcpStream::connect(shared.local_addr);
+    }
+}
+
+// We parse events to split them up between clients, and like the rest of this crate that
+// assumes 8 byte IDs
+fn check_id_sizes(upstream: &mut TcpStream) -> Result<()> {
+    write_packet(
+        upstream,
+        &Packet::Command {
+            id: 0,
+            command_set: virtual_machine::SET_ID,
+            command: ID_SIZES,
+            data: vec![],
+        },
+    )?;
+    loop {
+        match read_packet(upstream)? {
+            Packet::Reply {
+                id: 0,
+                error_code: 0,
+                data,
+            } => {
+                let sizes = virtual_machine::IdSizesReply::deserialize(&mut Cursor::new(data))?;
+                let all = [
+                    sizes.field_id_size,
+                    sizes.method_id_size,
+                    sizes.object_id_size,
+                    sizes.reference_type_id_size,
+                    sizes.frame_id_size,
+                ];
+                if all.iter().any(|&size| size != 8) {
+                    return Err(unsupported_err(
+                        "The target doesn't use 8 byte IDs, which the proxy needs",
+                    ));
+                }
+                return Ok(());
+            }
+            Packet::Reply { error_code, .. } => return Err(target_err(error_code)),
+            // Events from before anyone was attached, such as VMStart, are nobody's. If they
+            // suspended the VM, it stays suspended until the first client resumes it.
+            Packet::Command { .. } => {}
+        }
+    }
+}
+
+fn accept_clients(shared: Arc<Shared>, listener: TcpListener) {
+    for stream in listener.incoming() {
+        if shared.closed.load(Ordering::SeqCst) {
+            return;
+        }
+        let stream = match stream {
+            Ok(stream) => stream,
+            Err(_) => continue,
+        };
+        let shared = shared.clone();
+        thread::spawn(move || serve_client(shared, stream));
+    }
+}
+
+fn serve_client(shared: Arc<Shared>, mut stream: TcpStream) {
+    if handshake::accept(&mut stream, handshake::DEFAULT_TIMEOUT).is_err() {
+        return;
+    }
+    let client = match shared.register(&stream) {
+        Ok(Some(client)) => client,
+        Ok(None) | Err(_) => return,
+    };
+    // Debuggers only send commands: the target never sends them any which need replies
+    while let Ok(Packet::Command {
+        id,
+        command_set,
+        command,
+        data,
+    }) = read_packet(&mut stream)
+    {
+        match shared.forward(client, id, command_set, command, data) {
+            Ok(true) => {}
+            Ok(false) | Err(_) => break,
+        }
+    }
+    shared.disconnect(client);
+}
+
+fn read_target(shared: Arc<Shared>, mut upstream: TcpStream) {
+    while let Ok(packet) = read_packet(&mut upstream) {
+        shared.route(packet);
+    }
+    // The target has gone, so there's nothing for the clients to talk to
+    shared.closed.store(true, Ordering::SeqCst);
+    for client in shared.state().clients.values() {
+        let _ = client.stream.shutdown(Shutdown::Both);
+    }
+    let _ = TcpStream::connect(shared.local_addr);
+}
+
+// Writes packets collected while the state was locked. The first error is returned, after trying
+// the rest.
+fn deliver(outgoing: Outgoing) -> Result<()> {
+    let mut result = Ok(());
+    for (writer, packet) in outgoing {
+        let written = write_packet(&mut *writer.lock().unwrap(), &packet);
+        result = result.and(written);
+    }
+    result
+}
+
+impl Shared {
+    fn state(&self) -> MutexGuard<'_, State> {
+        self.state.lock().unwrap()
+    }
+
+    // Adds a client which has connected. None if the proxy has been shut down.
+    fn register(&self, stream: &TcpStream) -> Result<Option<u64>> {
+        let client = Client {
+            stream: stream.try_clone()?,
+            writer: Arc::new(Mutex::new(stream.try_clone()?)),
+            vm_suspends: 0,
+            thread_suspends: HashMap::new(),
+        };
+        let mut state = self.state();
+        if self.closed.load(Ordering::SeqCst) {
+            return Ok(None);
+        }
+        let id = state.next_client;
+        state.next_client += 1;
+        state.clients.insert(id, client);
+        Ok(Some(id))
+    }
+
+    // Sorts out who a packet from the target is for, and sends it to them
+    fn route(&self, packet: Packet) {
+        let mut outgoing = vec![];
+        {
+            let mut state = self.state();
+            match packet {
+                Packet::Reply {
+                    id,
+                    error_code,
+                    data,
+                } => self.route_reply(&mut state, id, error_code, data, &mut outgoing),
+                Packet::Command {
+                    id,
+                    command_set: COMPOSITE_COMMAND_SET,
+                    command: COMPOSITE_COMMAND,
+                    data,
+                } => self.route_events(&mut state, id, &data, &mut outgoing),
+                // Anything else the target sends unprompted, such as DDM chunks, goes to everyone
+                packet => {
+                    for client in state.clients.values() {
+                        outgoing.push((client.writer.clone(), packet.clone()));
+                    }
+                }
+            }
+        }
+        // If writing to a client fails, its reader notices too
+        let _ = deliver(outgoing);
+    }
+
+    fn send(
+        &self,
+        state: &mut State,
+        command_set: u8,
+        command: u8,
+        data: Vec<u8>,
+        pending: Pending,
+    ) -> Result<()> {
+        let id = state.next_id;
+        state.next_id = state.next_id.wrapping_add(1);
+        state.pending.insert(id, pending);
+        let packet = Packet::Command {
+            id,
+            command_set,
+            command,
+            data,
+        };
+        write_packet(&mut *self.upstream.lock().unwrap(), &packet)
+    }
+
+    // Replies to a command the proxy handled itself
+    fn reply(&self, state: &State, client: u64, id: u32, outgoing: &mut Outgoing) -> Result<()> {
+        let client = state
+            .clients
+            .get(&client)
+            .ok_or_else(|| Error::new(ErrorKind::NotConnected, "Client has gone"))?;
+        let reply = Packet::Reply {
+            id,
+            error_code: 0,
+            data: vec![],
+        };
+        outgoing.push((client.writer.clone(), reply));
+        Ok(())
+    }
+
+    // Passes a client's command on. False if the client is done.
+    fn forward(
+        &self,
+        client: u64,
+        id: u32,
+        command_set: u8,
+        command: u8,
+        data: Vec<u8>,
+    ) -> Result<bool> {
+        let (more, outgoing) = {
+            let mut state = self.state();
+            self.forward_locked(&mut state, client, id, command_set, command, data)?
+        };
+        deliver(outgoing)?;
+        Ok(more)
+    }
+
+    // forward() with the state locked, and the replies it makes itself still to be written
+    fn forward_locked(
+        &self,
+        state: &mut State,
+        client: u64,
+        id: u32,
+        command_set: u8,
+        command: u8,
+        data: Vec<u8>,
+    ) -> Result<(bool, Outgoing)> {
+        let mut outgoing = vec![];
+        match (command_set, command) {
+            (virtual_machine::SET_ID, DISPOSE) => {
+                self.reply(state, client, id, &mut outgoing)?;
+                return Ok((false, outgoing));
+            }
+            (event_request::SET_ID, CLEAR_ALL_BREAKPOINTS) => {
+                let breakpoints: Vec<i32> = state
+                    .requests
+                    .iter()
+                    .filter(|(_, &owner)| owner == (client, BREAKPOINT))
+                    .map(|(&request, _)| request)
+                    .collect();
+                for request in breakpoints {
+                    self.clear(state, request)?;
+                }
+                self.reply(state, client, id, &mut outgoing)?;
+                return Ok((true, outgoing));
+            }
+            (event_request::SET_ID, CLEAR) => {
+                if let Some(request) = data.get(1..5) {
+                    let request = i32::from_be_bytes(request.try_into().unwrap());
+                    if state.requests.get(&request).map(|r| r.0) == Some(client) {
+                        state.requests.remove(&request);
+                    }
+                }
+            }
+            _ => {}
+        }
+        if let Some(c) = state.clients.get_mut(&client) {
+            match (command_set, command) {
+                (virtual_machine::SET_ID, SUSPEND) => c.vm_suspends += 1,
+                (virtual_machine::SET_ID, RESUME) => c.vm_suspends -= 1,
+                (thread_reference::SET_ID, THREAD_SUSPEND | THREAD_RESUME) => {
+                    if let Some(thread) = data.get(..8) {
+                        let thread = u64::from_be_bytes(thread.try_into().unwrap());
+                        let count = c.thread_suspends.entry(thread).or_default();
+                        *count += if command == THREAD_SUSPEND { 1 } else { -1 };
+                    }
+                }
+                _ => {}
+            }
+        }
+        let set_kind = match (command_set, command) {
+            (event_request::SET_ID, SET) => data.first().copied(),
+            _ => None,
+        };
+        let pending = Pending::Client {
+            client,
+            id,
+            set_kind,
+        };
+        self.send(state, command_set, command, data, pending)?;
+        Ok((true, outgoing))
+    }
+
+    fn clear(&self, state: &mut State, request: i32) -> Result<()> {
+        match state.requests.remove(&request) {
+            Some((_, kind)) => self.clear_request(state, kind, request),
+            None => Ok(()),
+        }
+    }
+
+    fn clear_request(&self, state: &mut State, kind: u8, request: i32) -> Result<()> {
+        let mut data = vec![kind];
+        data.extend_from_slice(&request.to_be_bytes());
+        self.send(state, event_request::SET_ID, CLEAR, data, Pending::Proxy)
+    }
+
+    // Clears up after a client which has gone
+    fn disconnect(&self, client: u64) {
+        let mut state = self.state();
+        let state = &mut *state;
+        let c = match state.clients.remove(&client) {
+            Some(c) => c,
+            None => return,
+        };
+        let _ = c.stream.shutdown(Shutdown::Both);
+        let requests: Vec<i32> = state
+            .requests
+            .iter()
+            .filter(|(_, &(owner, _))| owner == client)
+            .map(|(&request, _)| request)
+            .collect();
+        for request in requests {
+            let _ = self.clear(state, request);
+            state.orphaned.insert(request);
+        }
+        for _ in 0..c.vm_suspends {
+            let _ = self.send(
+                state,
+                virtual_machine::SET_ID,
+                RESUME,
+                vec![],
+                Pending::Proxy,
+            );
+        }
+        for (thread, count) in c.thread_suspends {
+            for _ in 0..count {
+                let data = thread.to_be_bytes().to_vec();
+                let _ = self.send(
+                    state,
+                    thread_reference::SET_ID,
+                    THREAD_RESUME,
+                    data,
+                    Pending::Proxy,
+                );
+            }
+        }
+    }
+
+    fn route_reply(
+        &self,
+        state: &mut State,
+        id: u32,
+        error_code: u16,
+        data: Vec<u8>,
+        outgoing: &mut Outgoing,
+    ) {
+        let (client, client_id, set_kind) = match state.pending.remove(&id) {
+            Some(Pending::Client {
+                client,
+                id,
+                set_kind,
+            }) => (client, id, set_kind),
+            Some(Pending::Proxy) | None => return,
+        };
+        if let (Some(kind), 0, Some(request)) = (set_kind, error_code, data.get(..4)) {
+            let request = i32::from_be_bytes(request.try_into().unwrap());
+            if state.clients.contains_key(&client) {
+                state.requests.insert(request, (client, kind));
+            } else {
+                // The client went before its request was made
+                let _ = self.clear_request(state, kind, request);
+                state.orphaned.insert(request);
+            }
+        }
+        if let Some(c) = state.clients.get(&client) {
+            let reply = Packet::Reply {
+                id: client_id,
+                error_code,
+                data,
+            };
+            outgoing.push((c.writer.clone(), reply));
+        }
+    }
+
+    // Splits a composite event packet up between the clients whose requests it's for
+    fn route_events(&self, state: &mut State, id: u32, data: &[u8], outgoing: &mut Outgoing) {
+        let events = match split_events(data) {
+            Ok(events) => events,
+            Err(_) => {
+                // Better everyone gets something they didn't ask for than nobody gets it
+                let packet = Packet::Command {
+                    id,
+                    command_set: COMPOSITE_COMMAND_SET,
+                    command: COMPOSITE_COMMAND,
+                    data: data.to_vec(),
+                };
+                for client in state.clients.values() {
+                    outgoing.push((client.writer.clone(), packet.clone()));
+                }
+                return;
+            }
+        };
+        let suspend_policy = data[0];
+        let mut by_client: HashMap<u64, Vec<&[u8]>> = HashMap::new();
+        // The client the packet's suspension is counted against, with its event's thread
+        let mut charged: Option<(u64, Option<u64>)> = None;
+        let mut orphan_thread = None;
+        for (event, raw) in &events {
+            let request = event.request_id();
+            if request == 0 {
+                for &client in state.clients.keys() {
+                    by_client.entry(client).or_default().push(raw);
+                }
+            } else if let Some(&(client, _)) = state.requests.get(&request) {
+                by_client.entry(client).or_default().push(raw);
+                charged.get_or_insert((client, event.thread()));
+            } else if state.orphaned.contains(&request) {
+                orphan_thread = Some(event.thread());
+            }
+        }
+
+        for (client, events) in by_client {
+            let c = match state.clients.get(&client) {
+                Some(c) => c,
+                None => continue,
+            };
+            let mut data = vec![suspend_policy];
+            data.extend_from_slice(&(events.len() as i32).to_be_bytes());
+            for event in events {
+                data.extend_from_slice(event);
+            }
+            let packet = Packet::Command {
+                id,
+                command_set: COMPOSITE_COMMAND_SET,
+                command: COMPOSITE_COMMAND,
+                data,
+            };
+            outgoing.push((c.writer.clone(), packet));
+        }
+
+        if let Some((client, thread)) = charged {
+            // Counted even if the client can't be written to, so that it's resumed when it goes
+            if let Some(c) = state.clients.get_mut(&client) {
+                match (suspend_policy, thread) {
+                    (SUSPEND_ALL, _) => c.vm_suspends += 1,
+                    (SUSPEND_EVENT_THREAD, Some(thread)) => {
+                        *c.thread_suspends.entry(thread).or_default() += 1
+                    }
+                    _ => {}
+                }
+            }
+        } else {
+            // Nobody is left to resume after these
+            match (suspend_policy, orphan_thread) {
+                (SUSPEND_ALL, Some(_)) => {
+                    let _ = self.send(
+                        state,
+                        virtual_machine::SET_ID,
+                        RESUME,
+                        vec![],
+                        Pending::Proxy,
+                    );
+                }
+                (SUSPEND_EVENT_THREAD, Some(Some(thread))) => {
+                    let data = thread.to_be_bytes().to_vec();
+                    let _ = self.send(
+                        state,
+                        thread_reference::SET_ID,
+                        THREAD_RESUME,
+                        data,
+                        Pending::Proxy,
+                    );
+                }
+                _ => {}
+            }
+        }
+    }
+}
+
+// The events in a composite packet, each with its bytes
+fn split_events(data: &[u8]) -> Result<Vec<(Event, &[u8])>> {
+    let mut reader = Cursor::new(data);
+    let _suspend_policy = reader.read_u8()?;
+    let count = reader.read_i32::<BigEndian>()?;
+    let mut events = vec![];
+    for _ in 0..count {
+        let start = reader.position() as usize;
+        let event = Event::deserialize(&mut reader)?;
+        events.push((event, &data[start..reader.position() as usize]));
+    }
+    Ok(events)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    const THREAD_START: u8 = 6;
+    const VM_DEATH: u8 = 99;
+
+    fn socket_pair() -> (TcpStream, TcpStream) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let ours = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let (theirs, _) = listener.accept().unwrap();
+        theirs
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+        (ours, theirs)
+    }
+
+    // A proxy without its threads, and the target's end of its connection
+    fn proxy() -> (Shared, TcpStream) {
+        let (upstream, target) = socket_pair();
+        let shared = Shared {
+            local_addr: upstream.local_addr().unwrap(),
+            upstream: Mutex::new(upstream),
+            state: Mutex::new(State {
+                next_id: 1,
+                ..Default::default()
+            }),
+            closed: AtomicBool::new(false),
+        };
+        (shared, target)
+    }
+
+    // A client, and its end of the connection
+    fn client(shared: &Shared) -> (u64, TcpStream) {
+        let (ours, theirs) = socket_pair();
+        (shared.register(&ours).unwrap().unwrap(), theirs)
+    }
+
+    fn command(stream: &mut TcpStream) -> (u32, u8, u8, Vec<u8>) {
+        match read_packet(stream).unwrap() {
+            Packet::Command {
+                id,
+                command_set,
+                command,
+                data,
+            } => (id, command_set, command, data),
+            reply => panic!("Expected a command, got {:?}", reply),
+        }
+    }
+
+    // Nothing more has been sent
+    fn assert_quiet(stream: &mut TcpStream) {
+        stream
+            .set_read_timeout(Some(Duration::from_millis(100)))
+            .unwrap();
+        let err = read_packet(stream).unwrap_err();
+        assert!(
+            matches!(err.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut),
+            "{}",
+            err
+        );
+        stream
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+    }
+
+    fn thread_start(request: i32, thread: u64) -> Vec<u8> {
+        let mut event = vec![THREAD_START];
+        event.extend_from_slice(&request.to_be_bytes());
+        event.extend_from_slice(&thread.to_be_bytes());
+        event
+    }
+
+    fn vm_death() -> Vec<u8> {
+        let mut event = vec![VM_DEATH];
+        event.extend_from_slice(&0i32.to_be_bytes());
+        event
+    }
+
+    fn composite(suspend_policy: u8, events: &[Vec<u8>]) -> Vec<u8> {
+        let mut data = vec![suspend_policy];
+        data.extend_from_slice(&(events.len() as i32).to_be_bytes());
+        for event in events {
+            data.extend_from_slice(event);
+        }
+        data
+    }
+
+    fn events_packet(id: u32, data: Vec<u8>) -> Packet {
+        Packet::Command {
+            id,
+            command_set: COMPOSITE_COMMAND_SET,
+            command: COMPOSITE_COMMAND,
+            data,
+        }
+    }
+
+    // Makes an event request for the client, which the target numbers 'request'
+    fn set_request(
+        shared: &Shared,
+        target: &mut TcpStream,
+        client: (u64, &mut TcpStream),
+        request: i32,
+    ) {
+        let (client, stream) = client;
+        let data = vec![THREAD_START, SUSPEND_ALL, 0, 0, 0, 0];
+        assert!(shared
+            .forward(client, 1000, event_request::SET_ID, SET, data)
+            .unwrap());
+        let (id, _, _, _) = command(target);
+        shared.route(Packet::Reply {
+            id,
+            error_code: 0,
+            data: request.to_be_bytes().to_vec(),
+        });
+        assert_eq!(
+            read_packet(stream).unwrap(),
+            Packet::Reply {
+                id: 1000,
+                error_code: 0,
+                data: request.to_be_bytes().to_vec(),
+            }
+        );
+    }
+
+    #[test]
+    fn id_remapping() {
+        let (shared, mut target) = proxy();
+        let (a, mut a_stream) = client(&shared);
+        let (b, mut b_stream) = client(&shared);
+
+        // Both clients use the same packet ID, which the target mustn't see twice
+        assert!(shared
+            .forward(a, 7, virtual_machine::SET_ID, 1, vec![])
+            .unwrap());
+        assert!(shared
+            .forward(b, 7, virtual_machine::SET_ID, 1, vec![])
+            .unwrap());
+        let (a_id, set, command_id, _) = command(&mut target);
+        let (b_id, _, _, _) = command(&mut target);
+        assert_eq!((set, command_id), (virtual_machine::SET_ID, 1));
+        assert_ne!(a_id, b_id);
+
+        // Replies go back to whoever asked, under their own IDs, whatever order they come in
+        let reply = |id, data: &[u8]| Packet::Reply {
+            id,
+            error_code: 0,
+            data: data.to_vec(),
+        };
+        shared.route(reply(b_id, b"b"));
+        shared.route(reply(a_id, b"a"));
+        assert_eq!(read_packet(&mut a_stream).unwrap(), reply(7, b"a"));
+        assert_eq!(read_packet(&mut b_stream).unwrap(), reply(7, b"b"));
+        assert_quiet(&mut a_stream);
+
+        // A reply to a command the proxy sent itself goes to nobody
+        shared
+            .clear_request(&mut shared.state(), THREAD_START, 1)
+            .unwrap();
+        let (proxy_id, _, _, _) = command(&mut target);
+        shared.route(reply(proxy_id, b""));
+        assert_quiet(&mut a_stream);
+        assert_quiet(&mut b_stream);
+    }
+
+    #[test]
+    fn splitting_events() {
+        let data = composite(
+            SUSPEND_ALL,
+            &[thread_start(10, 0x100), thread_start(20, 0x200), vm_death()],
+        );
+        let events = split_events(&data).unwrap();
+        let requests: Vec<i32> = events.iter().map(|(e, _)| e.request_id()).collect();
+        assert_eq!(requests, [10, 20, 0]);
+        assert_eq!(events[1].1, &thread_start(20, 0x200)[..]);
+        assert_eq!(events[0].0.thread(), Some(0x100));
+        assert!(split_events(&data[..data.len() - 1]).is_err());
+
+        let (shared, mut target) = proxy();
+        let (a, mut a_stream) = client(&shared);
+        let (b, mut b_stream) = client(&shared);
+        set_request(&shared, &mut target, (a, &mut a_stream), 10);
+        set_request(&shared, &mut target, (b, &mut b_stream), 20);
+
+        // Each client gets its own events, and everyone gets the ones for no request
+        shared.route(events_packet(5, data));
+        assert_eq!(
+            read_packet(&mut a_stream).unwrap(),
+            events_packet(
+                5,
+                composite(SUSPEND_ALL, &[thread_start(10, 0x100), vm_death()])
+            )
+        );
+        assert_eq!(
+            read_packet(&mut b_stream).unwrap(),
+            events_packet(
+                5,
+                composite(SUSPEND_ALL, &[thread_start(20, 0x200), vm_death()])
+            )
+        );
+        // The VM was suspended once, by a's request
+        let state = shared.state();
+        assert_eq!(state.clients[&a].vm_suspends, 1);
+        assert_eq!(state.clients[&b].vm_suspends, 0);
+    }
+
+    #[test]
+    fn disconnect_cleanup() {
+        let (shared, mut target) = proxy();
+        let (a, mut a_stream) = client(&shared);
+        set_request(&shared, &mut target, (a, &mut a_stream), 10);
+        set_request(&shared, &mut target, (a, &mut a_stream), 11);
+
+        // Two of a's events in one packet, which only suspends the VM once
+        let both = [thread_start(10, 0x100), thread_start(11, 0x100)];
+        shared.route(events_packet(5, composite(SUSPEND_ALL, &both)));
+        read_packet(&mut a_stream).unwrap();
+        // And a thread, twice, resumed once
+        let thread = 0x200u64.to_be_bytes().to_vec();
+        for command_id in [THREAD_SUSPEND, THREAD_SUSPEND, THREAD_RESUME] {
+            let data = thread.clone();
+            assert!(shared
+                .forward(a, 1, thread_reference::SET_ID, command_id, data)
+                .unwrap());
+            command(&mut target);
+        }
+
+        shared.disconnect(a);
+        let mut sent = vec![];
+        for _ in 0..4 {
+            let (_, set, command_id, data) = command(&mut target);
+            sent.push((set, command_id, data));
+        }
+        sent.sort();
+        let clear = |request: i32| {
+            let mut data = vec![THREAD_START];
+            data.extend_from_slice(&request.to_be_bytes());
+            (event_request::SET_ID, CLEAR, data)
+        };
+        assert_eq!(
+            sent,
+            [
+                (virtual_machine::SET_ID, RESUME, vec![]),
+                (thread_reference::SET_ID, THREAD_RESUME, thread),
+                clear(10),
+                clear(11),
+            ]
+        );
+        assert_quiet(&mut target);
+
+        // An event for one of its requests which was already on the way is resumed by the proxy
+        let late = composite(SUSPEND_EVENT_THREAD, &[thread_start(10, 0x300)]);
+        shared.route(events_packet(6, late));
+        let (_, set, command_id, data) = command(&mut target);
+        assert_eq!(
+            (set, command_id, data),
+            (
+                thread_reference::SET_ID,
+                THREAD_RESUME,
+                0x300u64.to_be_bytes().to_vec()
+            )
+        );
+        assert_quiet(&mut target);
+    }
+}
@@ -0,0 +1,385 @@
+//
+// The events which have arrived from the target but haven't been asked for yet. By default
+// there's no limit on how many are kept. That's fine for breakpoints, which usually suspend the
+// thread that hit them, so only a few arrive before the client catches up. It isn't fine for
+// events which don't suspend anything: a method entry request on a hot method can produce events
+// far faster than any client can handle them, until it runs out of memory. So the queue can be
+// given a limit, along with what to do once it's reached (see OverflowPolicy).
+//
+// The queue fills up while we're reading from the connection, when we can't send commands. Any
+// suspending or resuming that has to be done is remembered, and done as soon as the read is over.
+//
+
+use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
+use std::collections::VecDeque;
+use std::fs::{File, OpenOptions};
+use std::io::{BufReader, Cursor, Read, Result, Seek, SeekFrom, Write};
+use std::mem;
+use std::path::PathBuf;
+
+use super::event::{Composite, Event, SuspendPolicy};
+use super::{
+    thread_reference, virtual_machine, Deserialize, JdwpConnection, JdwpJavaVirtualMachine,
+};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum OverflowPolicy {
+    // Suspend the whole VM until the queue has been drained to half the limit. Nothing is lost,
+    // but events which were already on their way still arrive, so the limit can be overshot a
+    // little.
+    SuspendVm,
+    // Throw away events which don't fit, counting them (see dropped_events()). Threads which were
+    // suspended for them are resumed. VM death is never thrown away.
+    Drop,
+    // Write events which don't fit to this file, and read them back in order as the queue drains.
+    // The file is created (or truncated) when the limit is set.
+    SpillToDisk(PathBuf),
+}
+
+enum Overflow {
+    SuspendVm,
+    Drop,
+    SpillToDisk(Spill),
+}
+
+// Composite event packets, as they came from the target, each preceded by its length
+struct Spill {
+    file: File,
+    read_offset: u64,
+    packets: usize,
+}
+
+// Something to tell the target once we're no longer in the middle of reading from it
+#[derive(Debug, PartialEq, Eq)]
+enum Owed {
+    SuspendVm,
+    ResumeVm,
+    ResumeThread(u64),
+}
+
+#[derive(Default)]
+pub(super) struct EventQueue {
+    events: VecDeque<Event>,
+    limit: Option<(usize, Overflow)>,
+    dropped: u64,
+    // Whether we suspended the VM because of SuspendVm, so have to resume it
+    suspended_vm: bool,
+    owed: Vec<Owed>,
+}
+
+impl EventQueue {
+    pub(super) fn push(&mut self, composite: Composite, packet: &[u8]) -> Result<()> {
+        let (limit, overflow) = match &mut self.limit {
+            Some((limit, overflow)) => (*limit, overflow),
+            None => {
+                self.events.extend(composite.events);
+                return Ok(());
+            }
+        };
+        match overflow {
+            Overflow::SuspendVm => {
+                self.events.extend(composite.events);
+                if self.events.len() >= limit && !self.suspended_vm {
+                    self.suspended_vm = true;
+                    self.owed.push(Owed::SuspendVm);
+                }
+            }
+            Overflow::Drop => {
+                let vm_death = composite
+                    .events
+                    .iter()
+                    .any(|e| matches!(e, Event::VmDeath { .. }));
+                if self.events.len() < limit || vm_death {
+                    self.events.extend(composite.events);
+                    return Ok(());
+                }
+                self.dropped += composite.events.len() as u64;
+                match composite.suspend_policy {
+                    SuspendPolicy::None => {}
+                    SuspendPolicy::EventThread => {
+                        // All the events in a composite happened in the same thread
+                        if let Some(thread) = composite.events.iter().find_map(Event::thread) {
+                            self.owed.push(Owed::ResumeThread(thread));
+                        }
+                    }
+                    SuspendPolicy::All => self.owed.push(Owed::ResumeVm),
+                }
+            }
+            Overflow::SpillToDisk(spill) => {
+                // Once anything has been spilled, everything after it has to be too, to keep the
+                // events in order
+                if self.events.len() < limit && spill.packets == 0 {
+                    self.events.extend(composite.events);
+                    return Ok(());
+                }
+                spill.file.seek(SeekFrom::End(0))?;
+                let mut buf = Vec::with_capacity(packet.len() + 4);
+                buf.write_u32::<BigEndian>(packet.len() as u32)?;
+                buf.extend_from_slice(packet);
+                spill.file.write_all(&buf)?;
+                spill.packets += 1;
+            }
+        }
+        Ok(())
+    }
+
+    // Removes and returns the oldest event for which wanted() returns true. Spilled events are
+    // read back if there's nothing wanted in memory, which can take the queue over its limit if
+    // what's there is never wanted.
+    pub(super) fn take<F: Fn(&Event) -> bool>(&mut self, wanted: F) -> Result<Option<Event>> {
+        let mut event = self.remove_wanted(&wanted);
+        while event.is_none() && self.unspill()? {
+            event = self.remove_wanted(&wanted);
+        }
+        if event.is_none() {
+            return Ok(None);
+        }
+        if let Some((limit, _)) = self.limit {
+            while self.events.len() < limit && self.unspill()? {}
+            if self.suspended_vm && self.events.len() <= limit / 2 {
+                self.suspended_vm = false;
+                self.owed.push(Owed::ResumeVm);
+            }
+        }
+        Ok(event)
+    }
+
+    fn remove_wanted<F: Fn(&Event) -> bool>(&mut self, wanted: &F) -> Option<Event> {
+        let i = self.events.iter().position(wanted)?;
+        self.events.remove(i)
+    }
+
+    // Moves the oldest spilled packet back into memory. False if there wasn't one.
+    fn unspill(&mut self) -> Result<bool> {
+        let spill = match &mut self.limit {
+            Some((_, Overflow::SpillToDisk(spill))) if spill.packets > 0 => spill,
+            _ => return Ok(false),
+        };
+        spill.file.seek(SeekFrom::Start(spill.read_offset))?;
+        let mut reader = BufReader::new(&spill.file);
+        let len = reader.read_u32::<BigEndian>()?;
+        let mut packet = vec![0; len as usize];
+        reader.read_exact(&mut packet)?;
+        spill.read_offset += 4 + u64::from(len);
+        spill.packets -= 1;
+        if spill.packets == 0 {
+            spill.file.set_len(0)?;
+            spill.read_offset = 0;
+        }
+        let composite = Composite::deserialize(&mut Cursor::new(packet))?;
+        self.events.extend(composite.events);
+        Ok(true)
+    }
+}
+
+impl JdwpConnection {
+    // Limit the number of events kept waiting for next_event(), replacing any limit set before
+    pub fn set_event_queue_limit(&self, limit: usize, policy: OverflowPolicy) -> Result<()> {
+        let overflow = match policy {
+            OverflowPolicy::SuspendVm => Overflow::SuspendVm,
+            OverflowPolicy::Drop => Overflow::Drop,
+            OverflowPolicy::SpillToDisk(path) => Overflow::SpillToDisk(Spill {
+                file: OpenOptions::new()
+                    .read(true)
+                    .write(true)
+                    .create(true)
+                    .truncate(true)
+                    .open(path)?,
+                read_offset: 0,
+                packets: 0,
+            }),
+        };
+        self.remove_event_queue_limit()?;
+        self.events.borrow_mut().limit = Some((limit.max(1), overflow));
+        Ok(())
+    }
+
+    // Anything spilled to disk is read back into memory, and the VM is resumed if the queue was
+    // what suspended it
+    pub fn remove_event_queue_limit(&self) -> Result<()> {
+        {
+            let mut queue = self.events.borrow_mut();
+            while queue.unspill()? {}
+            queue.limit = None;
+            if mem::take(&mut queue.suspended_vm) {
+                queue.owed.push(Owed::ResumeVm);
+            }
+        }
+        self.pay_owed()
+    }
+
+    // How many events have been thrown away because of OverflowPolicy::Drop
+    pub fn dropped_events(&self) -> u64 {
+        self.events.borrow().dropped
+    }
+
+    // Send the suspends and resumes the queue has asked for. Only call this when not reading
+    // from the stream.
+    pub(super) fn pay_owed(&self) -> Result<()> {
+        // Taken first, since these commands may queue more events
+        let owed = mem::take(&mut self.events.borrow_mut().owed);
+        // Nothing is suspended any more once the VM has gone
+        if self.disconnected.get().is_some() {
+            return Ok(());
+        }
+        for owed in owed {
+            match owed {
+                Owed::SuspendVm => {
+                    virtual_machine::suspend(self)?;
+                }
+                Owed::ResumeVm => {
+                    virtual_machine::resume(self)?;
+                }
+                Owed::ResumeThread(thread) => {
+                    thread_reference::resume(self, thread)?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+impl JdwpJavaVirtualMachine {
+    // See JdwpConnection::set_event_queue_limit()
+    pub fn set_event_queue_limit(&self, limit: usize, policy: OverflowPolicy) -> Result<()> {
+        self.conn.set_event_queue_limit(limit, policy)
+    }
+
+    pub fn dropped_events(&self) -> u64 {
+        self.conn.dropped_events()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A composite packet with one VM start event, which is as simple as events get, with the
+    // request ID 'id' in thread 0x100 + id
+    fn composite(suspend_policy: SuspendPolicy, id: i32) -> (Composite, Vec<u8>) {
+        let mut packet = vec![suspend_policy as u8];
+        packet.extend_from_slice(&1i32.to_be_bytes());
+        packet.push(90);
+        packet.extend_from_slice(&id.to_be_bytes());
+        packet.extend_from_slice(&(0x100 + id as u64).to_be_bytes());
+        let composite = Composite::deserialize(&mut Cursor::new(&packet)).unwrap();
+        (composite, packet)
+    }
+
+    fn push(queue: &mut EventQueue, suspend_policy: SuspendPolicy, id: i32) {
+        let (composite, packet) = composite(suspend_policy, id);
+        queue.push(composite, &packet).unwrap();
+    }
+
+    fn queued(queue: &EventQueue) -> Vec<i32> {
+        queue.events.iter().map(Event::request_id).collect()
+    }
+
+    fn take_all(queue: &mut EventQueue) -> Vec<i32> {
+        let mut taken = vec![];
+        while let Some(event) = queue.take(|_| true).unwrap() {
+            taken.push(event.request_id());
+        }
+        taken
+    }
+
+    #[test]
+    fn unlimited() {
+        let mut queue = EventQueue::default();
+        for id in 0..100 {
+            push(&mut queue, SuspendPolicy::All, id);
+        }
+        assert_eq!(queued(&queue), (0..100).collect::<Vec<_>>());
+        assert!(queue.owed.is_empty());
+    }
+
+    #[test]
+    fn drop() {
+        let mut queue = EventQueue {
+            limit: Some((2, Overflow::Drop)),
+            ..Default::default()
+        };
+        push(&mut queue, SuspendPolicy::None, 1);
+        push(&mut queue, SuspendPolicy::None, 2);
+        push(&mut queue, SuspendPolicy::None, 3);
+        push(&mut queue, SuspendPolicy::EventThread, 4);
+        push(&mut queue, SuspendPolicy::All, 5);
+        assert_eq!(queued(&queue), vec![1, 2]);
+        assert_eq!(queue.dropped, 3);
+        // Whatever was suspended for the dropped events is resumed
+        assert_eq!(queue.owed, vec![Owed::ResumeThread(0x104), Owed::ResumeVm]);
+
+        // VM death goes over the limit rather than be lost
+        let mut packet = vec![0];
+        packet.extend_from_slice(&1i32.to_be_bytes());
+        packet.push(99);
+        packet.extend_from_slice(&6i32.to_be_bytes());
+        let death = Composite::deserialize(&mut Cursor::new(&packet)).unwrap();
+        queue.push(death, &packet).unwrap();
+        assert_eq!(queued(&queue), vec![1, 2, 6]);
+        assert_eq!(queue.dropped, 3);
+
+        // Once there's room there's room again
+        assert_eq!(take_all(&mut queue), vec![1, 2, 6]);
+        push(&mut queue, SuspendPolicy::None, 7);
+        assert_eq!(queued(&queue), vec![7]);
+    }
+
+    #[test]
+    fn suspend_vm() {
+        let mut queue = EventQueue {
+            limit: Some((4, Overflow::SuspendVm)),
+            ..Default::default()
+        };
+        for id in 0..6 {
+            push(&mut queue, SuspendPolicy::None, id);
+        }
+        // Nothing's lost, and the VM is only suspended once
+        assert_eq!(queued(&queue).len(), 6);
+        assert_eq!(queue.owed, vec![Owed::SuspendVm]);
+        queue.owed.clear();
+
+        // Resumed once it's down to half the limit
+        for _ in 0..3 {
+            queue.take(|_| true).unwrap();
+        }
+        assert!(queue.owed.is_empty());
+        queue.take(|_| true).unwrap();
+        assert_eq!(queue.owed, vec![Owed::ResumeVm]);
+        assert_eq!(queue.dropped, 0);
+    }
+
+    #[test]
+    fn spill_to_disk() {
+        let path = std::env::temp_dir().join(format!("libjdb-spill-{}", std::process::id()));
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(&path)
+            .unwrap();
+        let spill = Spill {
+            file,
+            read_offset: 0,
+            packets: 0,
+        };
+        let mut queue = EventQueue {
+            limit: Some((2, Overflow::SpillToDisk(spill))),
+            ..Default::default()
+        };
+        for id in 0..5 {
+            push(&mut queue, SuspendPolicy::None, id);
+        }
+        assert_eq!(queued(&queue), vec![0, 1]);
+
+        // Read back in order, as the queue drains, and nothing's dropped
+        assert_eq!(queue.take(|_| true).unwrap().unwrap().request_id(), 0);
+        assert_eq!(queued(&queue), vec![1, 2]);
+        push(&mut queue, SuspendPolicy::None, 5);
+        assert_eq!(take_all(&mut queue), vec![1, 2, 3, 4, 5]);
+        assert_eq!(queue.dropped, 0);
+        assert!(queue.owed.is_empty());
+        std::fs::remove_file(path).unwrap();
+    }
+}
@@ -0,0 +1,237 @@
+//
+// Smoothing over the most common race in interactive use: a frame fetched a moment ago which is
+// gone by the time it's used, because something ran its thread in between. Often the thread is
+// back where it was (an invoked method has returned, or another debugger on a JdwpProxy resumed
+// and suspended it at the same breakpoint), and the frame could just be fetched again.
+//
+// With the StaleFramePolicy turned on, reading a frame's variables or 'this' which fails because
+// the frame is stale (JdwpErrorKind::FrameInvalidated, INVALID_FRAMEID or THREAD_NOT_SUSPENDED)
+// fetches the frame at the same depth and tries once more, as long as the thread is at exactly
+// the same location: a frame further along would show different values than the one asked about,
+// so then the original error stands.
+//
+
+use std::io::Result;
+
+use super::{error_code, has_error_code, jdwp_error_kind, thread_reference};
+use super::{JdwpConnection, JdwpErrorKind, JdwpJavaVirtualMachine, Location};
+
+// Off by default, see above
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct StaleFramePolicy {
+    // Fetch a stale frame again and retry, if its thread is still suspended
+    pub refetch_frames: bool,
+    // Suspend the thread first if it's running, which leaves it suspended (with one more
+    // suspension to resume) even if the retry doesn't work out
+    pub resuspend_threads: bool,
+}
+
+// What's needed to find a frame again
+pub(super) struct FrameRef<'a> {
+    pub(super) thread_id: u64,
+    pub(super) frame_id: u64,
+    // When the frame was fetched, see JdwpConnection::epoch
+    pub(super) epoch: u64,
+    pub(super) depth: i32,
+    pub(super) location: &'a Location,
+}
+
+impl JdwpConnection {
+    // Applies to frames and variables used from now on, including ones fetched before
+    pub fn set_stale_frame_policy(&self, policy: StaleFramePolicy) {
+        self.stale_frame_policy.set(policy);
+    }
+
+    pub fn stale_frame_policy(&self) -> StaleFramePolicy {
+        self.stale_frame_policy.get()
+    }
+
+    // Runs 'op' with the frame's ID, and if the frame turns out to be stale, with the ID of the
+    // frame fetched again, as the policy allows
+    pub(super) fn with_frame<T, F>(&self, frame: &FrameRef, op: F) -> Result<T>
+    where
+        F: Fn(u64) -> Result<T>,
+    {
+        let err = match self
+            .check_suspended_since(frame.thread_id, frame.epoch)
+            .and_then(|_| op(frame.frame_id))
+        {
+            Err(e) if is_stale(&e) => e,
+            result => return result,
+        };
+        // Whatever goes wrong fetching it again, what the caller needs to know is that the frame
+        // was stale
+        match self.refetch(frame) {
+            Ok(Some(frame_id)) => op(frame_id),
+            _ => Err(err),
+        }
+    }
+
+    // The ID of the frame fetched again, if the policy allows it and it's still the same frame
+    fn refetch(&self, frame: &FrameRef) -> Result<Option<u64>> {
+        let policy = self.stale_frame_policy();
+        if !policy.refetch_frames {
+            return Ok(None);
+        }
+        if thread_reference::suspend_count(self, frame.thread_id)?.suspend_count == 0 {
+            if !policy.resuspend_threads {
+                return Ok(None);
+            }
+            thread_reference::suspend(self, frame.thread_id)?;
+        }
+        let fetched = thread_reference::frames(self, frame.thread_id, frame.depth, 1)?
+            .frames
+            .pop();
+        Ok(fetched
+            .filter(|fetched| same_location(&fetched.location, frame.location))
+            .map(|fetched| fetched.frame_id))
+    }
+}
+
+impl JdwpJavaVirtualMachine {
+    // See JdwpConnection::set_stale_frame_policy()
+    pub fn set_stale_frame_policy(&self, policy: StaleFramePolicy) {
+        self.conn.set_stale_frame_policy(policy)
+    }
+
+    pub fn stale_frame_policy(&self) -> StaleFramePolicy {
+        self.conn.stale_frame_policy()
+    }
+}
+
+fn is_stale(err: &std::io::Error) -> bool {
+    jdwp_error_kind(err) == Some(JdwpErrorKind::FrameInvalidated)
+        || has_error_code(
+            err,
+            &[
+                error_code::INVALID_FRAMEID,
+                error_code::THREAD_NOT_SUSPENDED,
+            ],
+        )
+}
+
+fn same_location(a: &Location, b: &Location) -> bool {
+    (a.class_id, a.method_id, a.location_idx) == (b.class_id, b.method_id, b.location_idx)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::jdwp::fake::{self, reply};
+    use crate::jdwp::{command_err, jdwp_error_code, TypeTag};
+
+    const THREAD: u64 = 0x10;
+    const STALE: u64 = 1;
+    const FRESH: u64 = 2;
+
+    fn location(location_idx: u64) -> Location {
+        Location {
+            type_tag: TypeTag::Class,
+            class_id: 0x20,
+            method_id: 0x30,
+            location_idx,
+        }
+    }
+
+    // A target whose thread has the given suspend count, and is at the given location when its
+    // frames are fetched again, or refuses to give them
+    fn target(suspend_count: i32, frames: Option<u64>) -> (JdwpConnection, fake::Log) {
+        let answers = fake::Answers::default()
+            // ThreadReference.SuspendCount
+            .on(11, 12, move |_| reply![suspend_count])
+            // ThreadReference.Suspend
+            .on(11, 2, |_| reply![])
+            // ThreadReference.Frames
+            .on(11, 6, move |_| match frames {
+                Some(location_idx) => reply![1, FRESH, &location(location_idx)],
+                None => Err(error_code::INVALID_THREAD),
+            });
+        let log = answers.log();
+        (answers.attach(), log)
+    }
+
+    // The commands the target was sent
+    fn sent(log: &fake::Log) -> Vec<(u8, u8)> {
+        log.lock()
+            .unwrap()
+            .iter()
+            .map(|&(command_set, command, _)| (command_set, command))
+            .collect()
+    }
+
+    // Reads the frame with 'conn', which only works with the fresh frame
+    fn read(conn: &JdwpConnection) -> Result<u64> {
+        let location = location(5);
+        let frame = FrameRef {
+            thread_id: THREAD,
+            frame_id: STALE,
+            epoch: 0,
+            depth: 0,
+            location: &location,
+        };
+        conn.with_frame(&frame, |frame_id| match frame_id {
+            FRESH => Ok(frame_id),
+            _ => Err(command_err(
+                16,
+                1,
+                &frame_id.to_be_bytes(),
+                error_code::INVALID_FRAMEID,
+            )),
+        })
+    }
+
+    fn is_original(result: Result<u64>) -> bool {
+        result.is_err_and(|e| jdwp_error_code(&e) == Some(error_code::INVALID_FRAMEID))
+    }
+
+    #[test]
+    fn off() {
+        let (conn, log) = target(1, Some(5));
+        assert!(is_original(read(&conn)));
+        assert!(sent(&log).is_empty());
+    }
+
+    #[test]
+    fn refetch_frames() {
+        let policy = StaleFramePolicy {
+            refetch_frames: true,
+            resuspend_threads: false,
+        };
+        let (conn, log) = target(1, Some(5));
+        conn.set_stale_frame_policy(policy);
+        assert_eq!(read(&conn).unwrap(), FRESH);
+        assert_eq!(sent(&log), vec![(11, 12), (11, 6)]);
+
+        // Running, so it's left alone
+        let (conn, log) = target(0, Some(5));
+        conn.set_stale_frame_policy(policy);
+        assert!(is_original(read(&conn)));
+        assert_eq!(sent(&log), vec![(11, 12)]);
+
+        // Somewhere else now
+        let (conn, _) = target(1, Some(6));
+        conn.set_stale_frame_policy(policy);
+        assert!(is_original(read(&conn)));
+
+        // The frames can't be fetched again, which the caller isn't told about
+        let (conn, _) = target(1, None);
+        conn.set_stale_frame_policy(policy);
+        assert!(is_original(read(&conn)));
+    }
+
+    #[test]
+    fn resuspend_threads() {
+        let policy = StaleFramePolicy {
+            refetch_frames: true,
+            resuspend_threads: true,
+        };
+        let (conn, log) = target(0, Some(5));
+        conn.set_stale_frame_policy(policy);
+        assert_eq!(read(&conn).unwrap(), FRESH);
+        assert_eq!(sent(&log), vec![(11, 12), (11, 2), (11, 6)]);
+
+        let (conn, _) = target(0, None);
+        conn.set_stale_frame_policy(policy);
+        assert!(is_original(read(&conn)));
+    }
+}
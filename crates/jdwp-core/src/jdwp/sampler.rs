@@ -0,0 +1,130 @@
+//
+// Watching a suspected leak grow without taking one heap dump after another. The sampler counts
+// the instances of a few classes every so often (VirtualMachine.InstanceCounts), and keeps the
+// counts as an InstanceSeries, which report::write_instance_series_csv() and
+// write_instance_sample_prometheus() can export.
+//
+// Counting doesn't suspend anything, but it does make the VM walk its heap, so on a big heap
+// samples should be minutes apart rather than seconds. The patterns are matched against the
+// loaded classes again for every sample, since the classes worth watching are often loaded late.
+// A class loaded by more than one class loader is counted once, with every loader's instances.
+//
+
+use std::collections::BTreeMap;
+use std::io::Result;
+use std::thread;
+use std::time::{Duration, Instant, SystemTime};
+
+use super::{error_code, has_error_code, signature_to_name, virtual_machine};
+use super::{JdwpConnection, JdwpJavaVirtualMachine, StopSignal, JDWP_1_6};
+use crate::pattern::ClassPattern;
+use crate::snapshot::{InstanceSample, InstanceSeries};
+
+pub struct InstanceSampler {
+    jvm: JdwpJavaVirtualMachine,
+    classes: Vec<ClassPattern>,
+    interval: Duration,
+    series: InstanceSeries,
+}
+
+impl JdwpJavaVirtualMachine {
+    // Start counting the instances of the classes matching any of 'classes', every 'interval'
+    // when run with run_for(). Fails if the target can't count instances.
+    pub fn instance_sampler(
+        &self,
+        classes: Vec<ClassPattern>,
+        interval: Duration,
+    ) -> Result<InstanceSampler> {
+        let conn = self.conn.as_ref();
+        conn.require_version(JDWP_1_6, "Counting instances")?;
+        conn.require_capability(|c| c.can_get_instance_info, "Counting instances")?;
+        Ok(InstanceSampler {
+            jvm: JdwpJavaVirtualMachine {
+                conn: self.conn.clone(),
+            },
+            classes,
+            interval,
+            series: InstanceSeries::default(),
+        })
+    }
+}
+
+impl InstanceSampler {
+    // Sample every interval for the given amount of time
+    pub fn run_for(&mut self, duration: Duration) -> Result<()> {
+        let deadline = Instant::now() + duration;
+        loop {
+            self.sample()?;
+            let now = Instant::now();
+            if now >= deadline {
+                return Ok(());
+            }
+            thread::sleep(self.interval.min(deadline - now));
+        }
+    }
+
+    // Sample every interval until 'stop' is, e.g. as a Supervisor task
+    pub fn run_until(&mut self, stop: &StopSignal) -> Result<()> {
+        while !stop.is_stopped() {
+            self.sample()?;
+            stop.wait(self.interval);
+        }
+        Ok(())
+    }
+
+    // Count the instances now, adding the sample to the series
+    pub fn sample(&mut self) -> Result<&InstanceSample> {
+        let conn = self.jvm.conn.as_ref();
+        let mut classes = vec![];
+        for class in virtual_machine::all_classes(conn)?.classes {
+            let name = signature_to_name(&class.signature);
+            if self.classes.iter().any(|pattern| pattern.matches(&name)) {
+                classes.push((class.type_id, name));
+            }
+        }
+
+        let time = SystemTime::now();
+        let mut counts = BTreeMap::new();
+        // As with class_histogram(), a reply for thousands of classes at once would be huge
+        for chunk in classes.chunks(1024) {
+            for ((_, name), count) in chunk.iter().zip(count_instances(conn, chunk)?) {
+                if let Some(count) = count {
+                    *counts.entry(name.clone()).or_insert(0) += count;
+                }
+            }
+        }
+        self.series.samples.push(InstanceSample { time, counts });
+        Ok(self.series.samples.last().expect("just pushed"))
+    }
+
+    // Every sample so far
+    pub fn series(&self) -> &InstanceSeries {
+        &self.series
+    }
+
+    pub fn into_series(self) -> InstanceSeries {
+        self.series
+    }
+}
+
+// The count for each class, or None for a class which was unloaded since it was listed
+fn count_instances(conn: &JdwpConnection, classes: &[(u64, String)]) -> Result<Vec<Option<u64>>> {
+    let ids: Vec<u64> = classes.iter().map(|(id, _)| *id).collect();
+    match virtual_machine::instance_counts(conn, &ids) {
+        Ok(reply) => Ok(reply
+            .counts
+            .iter()
+            .map(|&c| Some(c.max(0) as u64))
+            .collect()),
+        // One unloaded class fails the lot, so count them one at a time to find it
+        Err(e) if has_error_code(&e, &[error_code::INVALID_CLASS]) && ids.len() > 1 => {
+            let mut counts = vec![];
+            for class in classes {
+                counts.extend(count_instances(conn, std::slice::from_ref(class))?);
+            }
+            Ok(counts)
+        }
+        Err(e) if has_error_code(&e, &[error_code::INVALID_CLASS]) => Ok(vec![None]),
+        Err(e) => Err(e),
+    }
+}
@@ -0,0 +1,1298 @@
+//
+// A debugging session which outlives its connection to the target. Breakpoints, watched fields
+// and renderers are kept by name (source file and line, class and field name) rather than by the
+// IDs the VM hands out, since those mean nothing to another connection, let alone to a restarted
+// target. So a session can detach(), leaving the target to run freely, and reattach() later,
+// possibly to a new process listening on the same address, and everything is armed again.
+//
+// Breakpoints and watches on classes which haven't been loaded yet are armed when the classes are
+// prepared, which is the usual case right after a restart. Their events suspend the thread they
+// happen in, which the caller needs to resume.
+//
+// A class initialization breakpoint (see break_on_class_initialization()) is hit on entry to a
+// class's static initializer, <clinit>, which runs exactly once, when the class is first used.
+// It's set as each matching class is prepared, before the class can be initialized. Classes
+// without a static initializer (no static fields with initial values, no static blocks) never hit
+// one.
+//
+// Since none of that depends on the connection, a session can also be saved to a file and loaded
+// again another day. The file is text, a line per setting with tab separated fields, so it can be
+// written or edited by hand too:
+//
+//   address     host:port
+//   breakpoint  class pattern, source file, line
+//   class_init  class pattern
+//   watch       access or modification, class name, field name
+//   renderer    class name, template
+//   label       thread name, label
+//   audit_log   path (see audit.rs)
+//
+// Labels for objects (see annotations_mut()) aren't saved, since object IDs only mean something
+// to the connection which handed them out. They're forgotten on reattach() for the same reason.
+//
+// Backslashes, tabs and newlines within fields are written as \\, \t and \n. Blank lines and lines
+// starting with '#' are ignored.
+//
+
+use std::collections::hash_map::{Entry, HashMap};
+use std::collections::{BTreeMap, HashSet};
+use std::fs::File;
+use std::io::{BufRead, BufReader, BufWriter, Error, ErrorKind, Result, Write};
+use std::path::Path;
+use std::rc::Rc;
+use std::time::{Duration, Instant};
+
+use num_traits::cast::FromPrimitive;
+
+use super::eval;
+use super::event::{Event, EventKind, Modifier, SuspendPolicy};
+use super::TypeTag;
+use super::{class_object_reference, class_type, object_reference, reference_type};
+use super::{locations_of_line_in_class, searched_for_lines, signature_to_name};
+use super::{thread_reference, virtual_machine};
+use super::{JdwpConnection, JdwpJavaVirtualMachine, JdwpLocation, JdwpThreadReference, Location};
+use crate::annotation::Annotations;
+use crate::model::{JavaVirtualMachine, Modifiers, Value};
+use crate::pattern::ClassPattern;
+use crate::report::{self, OutputSink};
+
+#[derive(Debug, Clone)]
+pub struct BreakpointSpec {
+    pub class_pattern: ClassPattern,
+    pub source_file: String,
+    pub line: u32,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct BreakpointId(u32);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WatchKind {
+    Access,
+    Modification,
+}
+
+#[derive(Debug, Clone)]
+pub struct WatchSpec {
+    // e.g. java.util.HashMap
+    pub class_name: String,
+    pub field_name: String,
+    pub kind: WatchKind,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct WatchId(u32);
+
+pub enum SessionEvent {
+    Breakpoint {
+        breakpoint: BreakpointId,
+        thread: JdwpThreadReference,
+        location: JdwpLocation,
+    },
+    Watch {
+        watch: WatchId,
+        thread: JdwpThreadReference,
+        // Null for static fields
+        object: Value,
+        // Only for modifications
+        value_to_be: Option<Value>,
+    },
+    // The target has exited, and the session is detached. reattach() once it's back.
+    VmDeath,
+}
+
+// What an event request was made for
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Armed {
+    Breakpoint(BreakpointId),
+    Watch(WatchId),
+}
+
+// A renderer's template, split up
+enum Piece {
+    Text(String),
+    Field(u64),
+}
+
+// Everything which only means something to the current connection
+struct Attachment {
+    jvm: JdwpJavaVirtualMachine,
+    requests: HashMap<i32, (EventKind, Armed)>,
+    // What has already been armed, so that a class being prepared after a breakpoint or watch was
+    // added doesn't get a second request. (class ID, method ID, code index) and (class ID, field
+    // ID) respectively.
+    breakpoint_locations: HashSet<(BreakpointId, (u64, u64, u64))>,
+    watched_fields: HashSet<(WatchId, (u64, u64))>,
+}
+
+pub struct Session {
+    address: String,
+    attachment: Option<Attachment>,
+    next_id: u32,
+    breakpoints: Vec<(BreakpointId, BreakpointSpec)>,
+    // Class initialization breakpoints, by the classes they're for
+    initializers: Vec<(BreakpointId, ClassPattern)>,
+    watches: Vec<(WatchId, WatchSpec)>,
+    // Templates for showing objects of a class, by class name. See set_renderer().
+    renderers: BTreeMap<String, String>,
+    annotations: Annotations,
+    // See set_audit_log()
+    audit_log: Option<String>,
+}
+
+impl Session {
+    // A session which isn't attached yet. Add breakpoints and so on, then reattach().
+    pub fn new(address: &str) -> Session {
+        Session {
+            address: address.to_string(),
+            attachment: None,
+            next_id: 1,
+            breakpoints: vec![],
+            initializers: vec![],
+            watches: vec![],
+            renderers: BTreeMap::new(),
+            annotations: Annotations::new(),
+            audit_log: None,
+        }
+    }
+
+    pub fn attach(address: &str) -> Result<Session> {
+        let mut session = Session::new(address);
+        session.reattach()?;
+        Ok(session)
+    }
+
+    pub fn address(&self) -> &str {
+        &self.address
+    }
+
+    pub fn is_attached(&self) -> bool {
+        self.attachment.is_some()
+    }
+
+    pub fn jvm(&self) -> Result<&JdwpJavaVirtualMachine> {
+        Ok(&self.attached()?.jvm)
+    }
+
+    // Dispose of the connection, which cancels our event requests and lets the target run, but
+    // keep the breakpoints, watches and renderers. The session is detached even if this fails
+    // (e.g. because the target has already gone).
+    pub fn detach(&mut self) -> Result<()> {
+        match self.attachment.take() {
+            Some(attachment) => attachment.jvm.dispose(),
+            None => Ok(()),
+        }
+    }
+
+    // Connect to the target at the session's address (detaching first if need be) and arm all
+    // the breakpoints and watches again. A restarting target may not be listening yet, in which
+    // case this fails and can simply be retried.
+    pub fn reattach(&mut self) -> Result<()> {
+        let _ = self.detach();
+        self.annotations.clear_objects();
+        let jvm = crate::attach_live(self.address.as_str())?;
+        if let Some(path) = &self.audit_log {
+            jvm.set_audit_log(path)?;
+        }
+        self.attachment = Some(Attachment {
+            jvm,
+            requests: HashMap::new(),
+            breakpoint_locations: HashSet::new(),
+            watched_fields: HashSet::new(),
+        });
+        for (id, spec) in self.breakpoints.clone() {
+            self.arm_breakpoint(id, &spec)?;
+        }
+        for (id, pattern) in self.initializers.clone() {
+            self.arm_initializer(id, &pattern)?;
+        }
+        for (id, spec) in self.watches.clone() {
+            self.arm_watch(id, &spec)?;
+        }
+        Ok(())
+    }
+
+    // Log every command which changes the target to the file at 'path', on this connection and
+    // every one after it. See JdwpConnection::set_audit_log().
+    pub fn set_audit_log(&mut self, path: &str) -> Result<()> {
+        if let Some(attachment) = &self.attachment {
+            attachment.jvm.set_audit_log(path)?;
+        }
+        self.audit_log = Some(path.to_string());
+        Ok(())
+    }
+
+    pub fn audit_log(&self) -> Option<&str> {
+        self.audit_log.as_deref()
+    }
+
+    pub fn add_breakpoint(&mut self, spec: BreakpointSpec) -> Result<BreakpointId> {
+        let id = BreakpointId(self.allocate_id());
+        if self.is_attached() {
+            let armed = self.arm_breakpoint(id, &spec);
+            self.disarm_on_error(armed, Armed::Breakpoint(id))?;
+        }
+        self.breakpoints.push((id, spec));
+        Ok(id)
+    }
+
+    // Stop each class matching the pattern as it's initialized, i.e. on entry to its static
+    // initializer. Hits are reported as SessionEvent::Breakpoint, with a location in <clinit>.
+    pub fn break_on_class_initialization(
+        &mut self,
+        class_pattern: ClassPattern,
+    ) -> Result<BreakpointId> {
+        let id = BreakpointId(self.allocate_id());
+        if self.is_attached() {
+            let armed = self.arm_initializer(id, &class_pattern);
+            self.disarm_on_error(armed, Armed::Breakpoint(id))?;
+        }
+        self.initializers.push((id, class_pattern));
+        Ok(id)
+    }
+
+    // Removes a breakpoint of either kind
+    pub fn remove_breakpoint(&mut self, id: BreakpointId) -> Result<()> {
+        self.breakpoints.retain(|(b, _)| *b != id);
+        self.initializers.retain(|(b, _)| *b != id);
+        if let Some(attachment) = &mut self.attachment {
+            attachment.breakpoint_locations.retain(|(b, _)| *b != id);
+            attachment.disarm(|armed| matches!(armed, Armed::Breakpoint(b) if b == id))?;
+        }
+        Ok(())
+    }
+
+    pub fn breakpoints(&self) -> impl Iterator<Item = (BreakpointId, &BreakpointSpec)> {
+        self.breakpoints.iter().map(|(id, spec)| (*id, spec))
+    }
+
+    pub fn initialization_breakpoints(
+        &self,
+    ) -> impl Iterator<Item = (BreakpointId, &ClassPattern)> {
+        self.initializers.iter().map(|(id, pattern)| (*id, pattern))
+    }
+
+    pub fn watch_field(&mut self, spec: WatchSpec) -> Result<WatchId> {
+        let id = WatchId(self.allocate_id());
+        if self.is_attached() {
+            let armed = self.arm_watch(id, &spec);
+            self.disarm_on_error(armed, Armed::Watch(id))?;
+        }
+        self.watches.push((id, spec));
+        Ok(id)
+    }
+
+    pub fn unwatch_field(&mut self, id: WatchId) -> Result<()> {
+        self.watches.retain(|(w, _)| *w != id);
+        if let Some(attachment) = &mut self.attachment {
+            attachment.watched_fields.retain(|(w, _)| *w != id);
+            attachment.disarm(|armed| matches!(armed, Armed::Watch(w) if w == id))?;
+        }
+        Ok(())
+    }
+
+    pub fn watches(&self) -> impl Iterator<Item = (WatchId, &WatchSpec)> {
+        self.watches.iter().map(|(id, spec)| (*id, spec))
+    }
+
+    // Show objects of the given class (not its subclasses) using a template, in which '{name}' is
+    // replaced by the value of the field 'name', e.g. "User {name} <{email}>". Only fields
+    // declared by the class itself can be named.
+    pub fn set_renderer(&mut self, class_name: &str, template: &str) {
+        self.renderers
+            .insert(class_name.to_string(), template.to_string());
+    }
+
+    pub fn remove_renderer(&mut self, class_name: &str) {
+        self.renderers.remove(class_name);
+    }
+
+    pub fn renderers(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.renderers
+            .iter()
+            .map(|(class, template)| (class.as_str(), template.as_str()))
+    }
+
+    // Save the address, breakpoints, watches and renderers (but not the connection) to a file
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<()> {
+        let mut out = BufWriter::new(File::create(path)?);
+        self.write_settings(&mut out)?;
+        out.flush()
+    }
+
+    // Like save(), to the artifact called 'name' in 'sink', returning where it ended up
+    pub fn save_to<S: OutputSink + ?Sized>(&self, sink: &S, name: &str) -> Result<String> {
+        report::write_to(sink, name, |mut out| self.write_settings(&mut out))
+    }
+
+    // A session with the settings in a file written by save(), which isn't attached yet
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Session> {
+        let path = path.as_ref();
+        Session::read_settings(BufReader::new(File::open(path)?)).map_err(|e| {
+            Error::new(
+                e.kind(),
+                format!("Error loading session from {}: {}", path.display(), e),
+            )
+        })
+    }
+
+    fn write_settings<W: Write>(&self, out: &mut W) -> Result<()> {
+        writeln!(out, "# libjdb session")?;
+        write_setting(out, &["address", &self.address])?;
+        for (_, spec) in &self.breakpoints {
+            let line = spec.line.to_string();
+            let fields = [
+                "breakpoint",
+                spec.class_pattern.as_str(),
+                &spec.source_file,
+                &line,
+            ];
+            write_setting(out, &fields)?;
+        }
+        for (_, pattern) in &self.initializers {
+            write_setting(out, &["class_init", pattern.as_str()])?;
+        }
+        for (_, spec) in &self.watches {
+            let kind = match spec.kind {
+                WatchKind::Access => "access",
+                WatchKind::Modification => "modification",
+            };
+            write_setting(out, &["watch", kind, &spec.class_name, &spec.field_name])?;
+        }
+        for (class_name, template) in &self.renderers {
+            write_setting(out, &["renderer", class_name, template])?;
+        }
+        for (thread_name, label) in self.annotations.threads() {
+            write_setting(out, &["label", thread_name, label])?;
+        }
+        if let Some(path) = &self.audit_log {
+            write_setting(out, &["audit_log", path])?;
+        }
+        Ok(())
+    }
+
+    fn read_settings<R: BufRead>(reader: R) -> Result<Session> {
+        let mut session = Session::new("");
+        let mut address = None;
+        for (n, line) in reader.lines().enumerate() {
+            let line = line?;
+            if line.trim().is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let err = |msg: &str| {
+                Error::new(
+                    ErrorKind::InvalidData,
+                    format!("line {}: {}: {:?}", n + 1, msg, line),
+                )
+            };
+            let fields = line
+                .split('\t')
+                .map(unescape)
+                .collect::<Option<Vec<String>>>()
+                .ok_or_else(|| err("Invalid escape"))?;
+            let fields: Vec<&str> = fields.iter().map(String::as_str).collect();
+            match fields.as_slice() {
+                ["address", a] => address = Some(a.to_string()),
+                ["breakpoint", pattern, source_file, line] => {
+                    let spec = BreakpointSpec {
+                        class_pattern: pattern.parse().map_err(|e| err(&format!("{}", e)))?,
+                        source_file: source_file.to_string(),
+                        line: line.parse().map_err(|_| err("Invalid line number"))?,
+                    };
+                    session.add_breakpoint(spec)?;
+                }
+                ["class_init", pattern] => {
+                    let pattern = pattern.parse().map_err(|e| err(&format!("{}", e)))?;
+                    session.break_on_class_initialization(pattern)?;
+                }
+                ["watch", kind, class_name, field_name] => {
+                    let kind = match *kind {
+                        "access" => WatchKind::Access,
+                        "modification" => WatchKind::Modification,
+                        _ => return Err(err("Unknown kind of watch")),
+                    };
+                    session.watch_field(WatchSpec {
+                        class_name: class_name.to_string(),
+                        field_name: field_name.to_string(),
+                        kind,
+                    })?;
+                }
+                ["renderer", class_name, template] => session.set_renderer(class_name, template),
+                ["label", thread_name, label] => {
+                    session.annotations.label_thread(thread_name, label)
+                }
+                ["audit_log", path] => session.audit_log = Some(path.to_string()),
+                _ => return Err(err("Unknown setting")),
+            }
+        }
+        session.address = address
+            .ok_or_else(|| Error::new(ErrorKind::InvalidData, "No address in the session file"))?;
+        Ok(session)
+    }
+
+    // Labels for objects and threads, for render() and to pass to the report writers
+    pub fn annotations(&self) -> &Annotations {
+        &self.annotations
+    }
+
+    pub fn annotations_mut(&mut self) -> &mut Annotations {
+        &mut self.annotations
+    }
+
+    // A one line description of a value: primitives as themselves, Strings quoted, objects with a
+    // renderer using it, enum constants as Class.NAME, records as their toString() would have
+    // them (e.g. Point[x=1, y=2]), and other objects as class@id. Labelled objects have
+    // " [label]" added.
+    pub fn render(&self, value: &Value) -> Result<String> {
+        Ok(self.render_all(std::slice::from_ref(value))?.remove(0))
+    }
+
+    // Like render(), for many values. The fields the renderers need are fetched for all of the
+    // objects at once.
+    pub fn render_all(&self, values: &[Value]) -> Result<Vec<String>> {
+        let conn = self.attached()?.jvm.conn.as_ref();
+        let mut templates: HashMap<u64, Option<Rc<Vec<Piece>>>> = HashMap::new();
+        let mut pieces = Vec::with_capacity(values.len());
+        for value in values {
+            pieces.push(match *value {
+                Value::Object(id) => {
+                    let class_id = object_reference::reference_type(conn, id)?.type_id;
+                    match templates.entry(class_id) {
+                        Entry::Occupied(e) => e.get().clone(),
+                        Entry::Vacant(e) => e.insert(self.template(conn, class_id)?).clone(),
+                    }
+                }
+                _ => None,
+            });
+        }
+
+        let requests: Vec<(u64, Vec<u64>)> = values
+            .iter()
+            .zip(&pieces)
+            .filter_map(|(value, pieces)| match (value, pieces) {
+                (Value::Object(id), Some(pieces)) => Some((*id, field_ids(pieces))),
+                _ => None,
+            })
+            .collect();
+        let requests: Vec<(u64, &[u64])> = requests
+            .iter()
+            .map(|(id, fields)| (*id, fields.as_slice()))
+            .collect();
+        let mut field_values = conn.get_values_bulk(&requests)?.into_iter();
+
+        values
+            .iter()
+            .zip(&pieces)
+            .map(|(value, pieces)| match pieces {
+                Some(pieces) => {
+                    let mut field_values = field_values.next().unwrap_or_default().into_iter();
+                    let mut rendered = String::new();
+                    for piece in pieces.iter() {
+                        match piece {
+                            Piece::Text(text) => rendered.push_str(text),
+                            Piece::Field(_) => match field_values.next() {
+                                Some(value) => rendered.push_str(&render_value(conn, &value)?),
+                                None => return Err(super::protocol_err("Missing field value")),
+                            },
+                        }
+                    }
+                    Ok(rendered)
+                }
+                None => render_value(conn, value),
+            })
+            .zip(values)
+            .map(|(rendered, value)| match value.object_id() {
+                Some(id) => Ok(rendered? + &self.annotations.object_suffix(id)),
+                None => rendered,
+            })
+            .collect()
+    }
+
+    // Wait up to timeout (or forever, if None) for a breakpoint to be hit or a watched field to
+    // be accessed. Returns None on timeout.
+    pub fn next_event(&mut self, timeout: Option<Duration>) -> Result<Option<SessionEvent>> {
+        let deadline = timeout.map(|t| Instant::now() + t);
+        loop {
+            let attachment = self.attached()?;
+            let conn = attachment.jvm.conn.clone();
+            let requests = &attachment.requests;
+            let remaining = deadline.map(|d| d.saturating_duration_since(Instant::now()));
+            let event = conn.next_event(remaining, |e| {
+                e.kind() == EventKind::VmDeath || requests.contains_key(&e.request_id())
+            })?;
+            let event = match event {
+                Some(e) => e,
+                None => return Ok(None),
+            };
+            let armed = requests.get(&event.request_id()).map(|&(_, armed)| armed);
+            let thread = |thread_id| JdwpThreadReference {
+                conn: conn.clone(),
+                thread_id,
+            };
+
+            match (event, armed) {
+                (
+                    Event::ClassPrepare {
+                        thread: thread_id,
+                        ref_type_tag,
+                        type_id,
+                        signature,
+                        ..
+                    },
+                    Some(armed),
+                ) => {
+                    // Arm before resuming, so that the class can't run any code first
+                    let armed_class = self.arm_in_class(armed, type_id, ref_type_tag, &signature);
+                    thread_reference::resume(&conn, thread_id)?;
+                    armed_class?;
+                }
+                (
+                    Event::Breakpoint {
+                        thread: thread_id,
+                        location,
+                        ..
+                    },
+                    Some(Armed::Breakpoint(breakpoint)),
+                ) => {
+                    return Ok(Some(SessionEvent::Breakpoint {
+                        breakpoint,
+                        thread: thread(thread_id),
+                        location: JdwpLocation {
+                            conn: conn.clone(),
+                            location,
+                        },
+                    }))
+                }
+                (
+                    Event::FieldAccess {
+                        thread: thread_id,
+                        object,
+                        ..
+                    },
+                    Some(Armed::Watch(watch)),
+                ) => {
+                    return Ok(Some(SessionEvent::Watch {
+                        watch,
+                        thread: thread(thread_id),
+                        object,
+                        value_to_be: None,
+                    }))
+                }
+                (
+                    Event::FieldModification {
+                        thread: thread_id,
+                        object,
+                        value_to_be,
+                        ..
+                    },
+                    Some(Armed::Watch(watch)),
+                ) => {
+                    return Ok(Some(SessionEvent::Watch {
+                        watch,
+                        thread: thread(thread_id),
+                        object,
+                        value_to_be: Some(value_to_be),
+                    }))
+                }
+                (Event::VmDeath { .. }, _) => {
+                    // There's nothing left to dispose of
+                    self.attachment = None;
+                    return Ok(Some(SessionEvent::VmDeath));
+                }
+                // A request which has since been removed
+                _ => {}
+            }
+        }
+    }
+
+    // The renderer for a class, parsed, or None if it hasn't got one
+    fn template(&self, conn: &JdwpConnection, class_id: u64) -> Result<Option<Rc<Vec<Piece>>>> {
+        let class_name = signature_to_name(&reference_type::signature(conn, class_id)?.signature);
+        let template = match self.renderers.get(&class_name) {
+            Some(template) => template,
+            None => return Ok(None),
+        };
+
+        let fields = reference_type::fields(conn, class_id)?.fields;
+        let mut pieces = vec![];
+        let mut rest = template.as_str();
+        while let Some(start) = rest.find('{') {
+            let end = match rest[start..].find('}') {
+                Some(end) => start + end,
+                None => break,
+            };
+            pieces.push(Piece::Text(rest[..start].to_string()));
+            let name = &rest[start + 1..end];
+            let field = fields.iter().find(|f| f.name == name).ok_or_else(|| {
+                Error::new(
+                    ErrorKind::InvalidInput,
+                    format!(
+                        "The renderer for {} uses '{}', which isn't one of its fields",
+                        class_name, name
+                    ),
+                )
+            })?;
+            pieces.push(Piece::Field(field.field_id));
+            rest = &rest[end + 1..];
+        }
+        pieces.push(Piece::Text(rest.to_string()));
+        Ok(Some(Rc::new(pieces)))
+    }
+
+    fn allocate_id(&mut self) -> u32 {
+        let id = self.next_id;
+        self.next_id += 1;
+        id
+    }
+
+    fn attached(&self) -> Result<&Attachment> {
+        self.attachment.as_ref().ok_or_else(|| {
+            Error::new(
+                ErrorKind::NotConnected,
+                format!("The session isn't attached to {}", self.address),
+            )
+        })
+    }
+
+    fn attached_mut(&mut self) -> Result<&mut Attachment> {
+        let address = &self.address;
+        self.attachment.as_mut().ok_or_else(|| {
+            Error::new(
+                ErrorKind::NotConnected,
+                format!("The session isn't attached to {}", address),
+            )
+        })
+    }
+
+    // A breakpoint or watch which couldn't be armed isn't kept, so cancel whatever requests were
+    // made for it before it failed
+    fn disarm_on_error(&mut self, armed: Result<()>, what: Armed) -> Result<()> {
+        if armed.is_err() {
+            let attachment = self.attached_mut()?;
+            match what {
+                Armed::Breakpoint(id) => attachment.breakpoint_locations.retain(|(b, _)| *b != id),
+                Armed::Watch(id) => attachment.watched_fields.retain(|(w, _)| *w != id),
+            }
+            let _ = attachment.disarm(|armed| armed == what);
+        }
+        armed
+    }
+
+    fn arm_breakpoint(&mut self, id: BreakpointId, spec: &BreakpointSpec) -> Result<()> {
+        let attachment = self.attached_mut()?;
+        // Hear about classes loaded from now on before looking at the ones already loaded, so
+        // that none can slip through in between. Nested classes are included in exact patterns,
+        // since they share the outer class's source file. Regex patterns can't be evaluated by
+        // the VM, so they see every class being prepared.
+        let modifiers = match spec.class_pattern.to_jdwp() {
+            Some(p) if !p.contains('*') => vec![Modifier::ClassMatch(format!("{}*", p))],
+            Some(p) => vec![Modifier::ClassMatch(p)],
+            None => vec![],
+        };
+        attachment.request(EventKind::ClassPrepare, &modifiers, Armed::Breakpoint(id))?;
+
+        let locations =
+            attachment
+                .jvm
+                .locations_of_line(&spec.class_pattern, &spec.source_file, spec.line)?;
+        attachment.arm_locations(id, locations)
+    }
+
+    fn arm_initializer(&mut self, id: BreakpointId, class_pattern: &ClassPattern) -> Result<()> {
+        let attachment = self.attached_mut()?;
+        // Nested classes have initializers of their own, so unlike arm_breakpoint() an exact
+        // pattern is left as it is
+        let modifiers = match class_pattern.to_jdwp() {
+            Some(p) => vec![Modifier::ClassMatch(p)],
+            None => vec![],
+        };
+        attachment.request(EventKind::ClassPrepare, &modifiers, Armed::Breakpoint(id))?;
+
+        // Classes already loaded may not have been initialized yet
+        const INITIALIZED: u32 = 4;
+        let conn = attachment.jvm.conn.clone();
+        for class in virtual_machine::all_classes(&conn)?.classes {
+            let type_tag = match FromPrimitive::from_u8(class.ref_type_tag) {
+                Some(TypeTag::Array) | None => continue,
+                Some(tag) => tag,
+            };
+            if class.status & INITIALIZED == 0
+                && class_pattern.matches(&signature_to_name(&class.signature))
+            {
+                attachment.arm_initializer(&conn, id, class.type_id, type_tag)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn arm_watch(&mut self, id: WatchId, spec: &WatchSpec) -> Result<()> {
+        let attachment = self.attached_mut()?;
+        let modifiers = [Modifier::ClassMatch(spec.class_name.clone())];
+        attachment.request(EventKind::ClassPrepare, &modifiers, Armed::Watch(id))?;
+
+        let conn = attachment.jvm.conn.clone();
+        for class in attachment.jvm.classes_by_name(&spec.class_name)? {
+            attachment.arm_field(&conn, id, spec, class.class_id)?;
+        }
+        Ok(())
+    }
+
+    // A class has been prepared, which a breakpoint or watch may apply to
+    fn arm_in_class(
+        &mut self,
+        armed: Armed,
+        class_id: u64,
+        type_tag: TypeTag,
+        signature: &str,
+    ) -> Result<()> {
+        let name = signature_to_name(signature);
+        match armed {
+            Armed::Breakpoint(id) if self.initializers.iter().any(|(b, _)| *b == id) => {
+                if !self
+                    .initializers
+                    .iter()
+                    .any(|(b, p)| *b == id && p.matches(&name))
+                {
+                    return Ok(());
+                }
+                let attachment = self.attached_mut()?;
+                let conn = attachment.jvm.conn.clone();
+                attachment.arm_initializer(&conn, id, class_id, type_tag)
+            }
+            Armed::Breakpoint(id) => {
+                let spec = match self.breakpoints.iter().find(|(b, _)| *b == id) {
+                    Some((_, spec)) if searched_for_lines(&spec.class_pattern, &name) => {
+                        spec.clone()
+                    }
+                    _ => return Ok(()),
+                };
+                let attachment = self.attached_mut()?;
+                let conn = attachment.jvm.conn.clone();
+                let locations = locations_of_line_in_class(
+                    &conn,
+                    class_id,
+                    type_tag,
+                    &spec.source_file,
+                    spec.line,
+                )?;
+                attachment.arm_locations(id, locations)
+            }
+            Armed::Watch(id) => {
+                let spec = match self.watches.iter().find(|(w, _)| *w == id) {
+                    Some((_, spec)) if spec.class_name == name => spec.clone(),
+                    _ => return Ok(()),
+                };
+                let attachment = self.attached_mut()?;
+                let conn = attachment.jvm.conn.clone();
+                attachment.arm_field(&conn, id, &spec, class_id)
+            }
+        }
+    }
+}
+
+impl Attachment {
+    fn request(&mut self, kind: EventKind, modifiers: &[Modifier], armed: Armed) -> Result<()> {
+        // Class prepare events only need to hold up the class's initialization while we set
+        // requests in it
+        let request_id =
+            self.jvm
+                .conn
+                .set_event_request(kind, SuspendPolicy::EventThread, modifiers)?;
+        self.requests.insert(request_id, (kind, armed));
+        Ok(())
+    }
+
+    fn arm_locations(&mut self, id: BreakpointId, locations: Vec<JdwpLocation>) -> Result<()> {
+        for location in locations {
+            let l = location.location;
+            let key = (l.class_id, l.method_id, l.location_idx);
+            if self.breakpoint_locations.insert((id, key)) {
+                let modifiers = [Modifier::LocationOnly(l)];
+                self.request(EventKind::Breakpoint, &modifiers, Armed::Breakpoint(id))?;
+            }
+        }
+        Ok(())
+    }
+
+    // A breakpoint at the start of the class's static initializer, if it has one
+    fn arm_initializer(
+        &mut self,
+        conn: &Rc<JdwpConnection>,
+        id: BreakpointId,
+        class_id: u64,
+        type_tag: TypeTag,
+    ) -> Result<()> {
+        let methods = reference_type::methods(conn, class_id)?.methods;
+        let clinit = match methods.iter().find(|m| m.name == "<clinit>") {
+            Some(clinit) => clinit,
+            None => return Ok(()),
+        };
+        let location = JdwpLocation {
+            conn: conn.clone(),
+            location: Location {
+                type_tag,
+                class_id,
+                method_id: clinit.method_id,
+                location_idx: 0,
+            },
+        };
+        self.arm_locations(id, vec![location])
+    }
+
+    fn arm_field(
+        &mut self,
+        conn: &Rc<JdwpConnection>,
+        id: WatchId,
+        spec: &WatchSpec,
+        class_id: u64,
+    ) -> Result<()> {
+        let kind = match spec.kind {
+            WatchKind::Access => EventKind::FieldAccess,
+            WatchKind::Modification => EventKind::FieldModification,
+        };
+        for field in reference_type::fields(conn, class_id)?.fields {
+            if field.name == spec.field_name
+                && self.watched_fields.insert((id, (class_id, field.field_id)))
+            {
+                let modifiers = [Modifier::FieldOnly {
+                    declaring: class_id,
+                    field_id: field.field_id,
+                }];
+                self.request(kind, &modifiers, Armed::Watch(id))?;
+            }
+        }
+        Ok(())
+    }
+
+    fn disarm<F: Fn(Armed) -> bool>(&mut self, matches: F) -> Result<()> {
+        let ids: Vec<i32> = self
+            .requests
+            .iter()
+            .filter(|(_, &(_, armed))| matches(armed))
+            .map(|(&id, _)| id)
+            .collect();
+        for id in ids {
+            if let Some((kind, _)) = self.requests.remove(&id) {
+                self.jvm.conn.clear_event_request(kind, id)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+fn field_ids(pieces: &[Piece]) -> Vec<u64> {
+    pieces
+        .iter()
+        .filter_map(|piece| match piece {
+            Piece::Field(id) => Some(*id),
+            Piece::Text(_) => None,
+        })
+        .collect()
+}
+
+// How deep records within records are written out, before they're left as class@id
+const RECORD_DEPTH: usize = 3;
+
+fn render_value(conn: &JdwpConnection, value: &Value) -> Result<String> {
+    render_nested(conn, value, RECORD_DEPTH)
+}
+
+fn render_nested(conn: &JdwpConnection, value: &Value, depth: usize) -> Result<String> {
+    Ok(match *value {
+        Value::Object(id) => {
+            let class_id = object_reference::reference_type(conn, id)?.type_id;
+            let signature = reference_type::signature(conn, class_id)?.signature;
+            let class_name = signature_to_name(&signature);
+            if class_name == "java.lang.String" {
+                // Long strings are cut short, see JdwpConnection::set_fetch_limits()
+                let (string, fetched, length) = conn.fetch_string(id)?;
+                if fetched < length {
+                    format!("{:?}... ({} chars)", string, length)
+                } else {
+                    format!("{:?}", string)
+                }
+            } else if let Some(unboxed) = eval::boxed_value(conn, id, class_id, &signature)? {
+                // An Integer shows as 42, the same as an int would
+                unboxed.to_string()
+            } else if let Some(constant) = render_enum(conn, id, class_id, &signature)? {
+                constant
+            } else if let Some(record) = render_record(conn, id, class_id, &signature, depth)? {
+                record
+            } else {
+                format!("{}@{:x}", class_name, id)
+            }
+        }
+        // As Class.toString() would, apart from arrays, which are named as in Java source
+        Value::ClassObject(id) => {
+            let reflected = class_object_reference::reflected_type(conn, id)?;
+            // Primitive types have no reference type to name
+            if reflected.type_id == 0 {
+                return Ok(format!("java.lang.Class@{:x}", id));
+            }
+            let signature = reference_type::signature(conn, reflected.type_id)?.signature;
+            let kind = match reflected.type_tag {
+                TypeTag::Interface => "interface",
+                _ => "class",
+            };
+            format!("{} {}", kind, signature_to_name(&signature))
+        }
+        _ => value.to_string(),
+    })
+}
+
+// An enum constant as Class.NAME, e.g. TimeUnit.SECONDS, from the name java.lang.Enum keeps. None
+// for anything else.
+fn render_enum(
+    conn: &JdwpConnection,
+    id: u64,
+    class_id: u64,
+    signature: &str,
+) -> Result<Option<String>> {
+    if !signature.starts_with('L') {
+        return Ok(None);
+    }
+    let (class_name, name_field) = match rendered_class(conn, class_id)? {
+        RenderedClass::Enum {
+            class_name,
+            name_field,
+        } => (class_name, name_field),
+        _ => return Ok(None),
+    };
+    match object_reference::get_values(conn, id, &[name_field])?
+        .values
+        .pop()
+    {
+        Some(Value::Object(name)) => Ok(Some(format!(
+            "{}.{}",
+            simple_name(&class_name),
+            conn.fetch_string(name)?.0
+        ))),
+        _ => Ok(None),
+    }
+}
+
+// What there is to know about a class to render its instances, looked up once per class rather
+// than once per value, since a list of a thousand records would otherwise mean a thousand
+// superclass lookups
+#[derive(Debug, Clone)]
+pub(crate) enum RenderedClass {
+    // The enum's own name (not that of a constant's anonymous subclass), and java.lang.Enum's
+    // name field
+    Enum { class_name: String, name_field: u64 },
+    // The names and IDs of its components, in declaration order
+    Record(Vec<(String, u64)>),
+    Other,
+}
+
+fn rendered_class(conn: &JdwpConnection, class_id: u64) -> Result<RenderedClass> {
+    if let Some(rendered) = conn.rendered_classes.borrow().get(&class_id) {
+        return Ok(rendered.clone());
+    }
+    // A constant with a body of its own is an instance of an anonymous subclass of its enum, so
+    // Enum can be two steps up. Records are final, so Record is only ever one.
+    let mut rendered = RenderedClass::Other;
+    let mut class = class_id;
+    for step in 0..2 {
+        let superclass = class_type::superclass(conn, class)?.superclass;
+        if superclass == 0 {
+            break;
+        }
+        match reference_type::signature(conn, superclass)?
+            .signature
+            .as_str()
+        {
+            "Ljava/lang/Enum;" => {
+                let fields = reference_type::fields(conn, superclass)?.fields;
+                if let Some(field) = fields.iter().find(|field| field.name == "name") {
+                    rendered = RenderedClass::Enum {
+                        class_name: signature_to_name(
+                            &reference_type::signature(conn, class)?.signature,
+                        ),
+                        name_field: field.field_id,
+                    };
+                }
+                break;
+            }
+            "Ljava/lang/Record;" if step == 0 => {
+                rendered = RenderedClass::Record(
+                    reference_type::fields(conn, class_id)?
+                        .fields
+                        .into_iter()
+                        .filter(|field| field.mod_bits as u32 & Modifiers::STATIC == 0)
+                        .map(|field| (field.name, field.field_id))
+                        .collect(),
+                );
+                break;
+            }
+            _ => class = superclass,
+        }
+    }
+    conn.rendered_classes
+        .borrow_mut()
+        .insert(class_id, rendered.clone());
+    Ok(rendered)
+}
+
+// A record the way its toString() writes it, e.g. Point[x=1, y=2], with its components rendered
+// like any other value. None for anything else.
+fn render_record(
+    conn: &JdwpConnection,
+    id: u64,
+    class_id: u64,
+    signature: &str,
+    depth: usize,
+) -> Result<Option<String>> {
+    // Arrays aren't classes as far as ClassType is concerned
+    if depth == 0 || !signature.starts_with('L') {
+        return Ok(None);
+    }
+    let components = match rendered_class(conn, class_id)? {
+        RenderedClass::Record(components) => components,
+        _ => return Ok(None),
+    };
+    let field_ids: Vec<u64> = components.iter().map(|(_, field_id)| *field_id).collect();
+    let values = object_reference::get_values(conn, id, &field_ids)?.values;
+    let components = components
+        .iter()
+        .zip(&values)
+        .map(|((name, _), value)| {
+            Ok(format!(
+                "{}={}",
+                name,
+                render_nested(conn, value, depth - 1)?
+            ))
+        })
+        .collect::<Result<Vec<_>>>()?;
+    let class_name = signature_to_name(signature);
+    Ok(Some(format!(
+        "{}[{}]",
+        simple_name(&class_name),
+        components.join(", ")
+    )))
+}
+
+// As getSimpleName() has it, e.g. Entry for java.util.Map$Entry
+fn simple_name(class_name: &str) -> &str {
+    class_name.rsplit(['.', '$']).next().unwrap_or(class_name)
+}
+
+fn write_setting<W: Write>(out: &mut W, fields: &[&str]) -> Result<()> {
+    let fields: Vec<String> = fields.iter().map(|field| escape(field)).collect();
+    writeln!(out, "{}", fields.join("\t"))
+}
+
+fn escape(field: &str) -> String {
+    field
+        .replace('\\', "\\\\")
+        .replace('\t', "\\t")
+        .replace('\n', "\\n")
+}
+
+fn unescape(field: &str) -> Option<String> {
+    let mut unescaped = String::with_capacity(field.len());
+    let mut chars = field.chars();
+    while let Some(c) = chars.next() {
+        unescaped.push(match c {
+            '\\' => match chars.next()? {
+                '\\' => '\\',
+                't' => '\t',
+                'n' => '\n',
+                _ => return None,
+            },
+            c => c,
+        });
+    }
+    Some(unescaped)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::jdwp::fake::{self, reply};
+    use crate::jdwp::{error_code, jdwp_error_code};
+    use std::io::Cursor;
+    use std::net::TcpListener;
+
+    // A target with one class, com.example.Cache, with one field, size. 'watchable' is whether it
+    // can watch fields being modified.
+    fn target(watchable: bool) -> (String, fake::Log) {
+        let mut request_id = 0;
+        let answers = fake::Answers::default()
+            // EventRequest.Set
+            .on(15, 1, move |data| {
+                if data[0] == EventKind::FieldModification as u8 && !watchable {
+                    return Err(error_code::NOT_IMPLEMENTED);
+                }
+                request_id += 1;
+                reply![request_id]
+            })
+            // EventRequest.Clear
+            .on(15, 2, |_| reply![])
+            // VirtualMachine.ClassesBySignature
+            .on(1, 2, |_| reply![1, 1u8, 0x50u64, 7u32])
+            // ReferenceType.Fields
+            .on(2, 4, |_| reply![1, 0x60u64, "size", "I", 0])
+            // VirtualMachine.Suspend, Resume and Dispose
+            .on(1, 8, |_| reply![])
+            .on(1, 9, |_| reply![])
+            .on(1, 6, |_| reply![]);
+        let log = answers.log();
+        (answers.listen().to_string(), log)
+    }
+
+    // The commands sent since last time, with the kind of event for EventRequest.Set
+    fn sent(log: &fake::Log) -> Vec<(u8, u8, Option<u8>)> {
+        log.lock()
+            .unwrap()
+            .drain(..)
+            .map(|(set, cmd, data)| {
+                let kind = data.first().copied().filter(|_| (set, cmd) == (15, 1));
+                (set, cmd, kind)
+            })
+            .collect()
+    }
+
+    fn cache_size() -> WatchSpec {
+        WatchSpec {
+            class_name: "com.example.Cache".to_string(),
+            field_name: "size".to_string(),
+            kind: WatchKind::Modification,
+        }
+    }
+
+    #[test]
+    fn attach_and_detach() {
+        let (address, log) = target(true);
+        let mut session = Session::new(&address);
+        let watch = session.watch_field(cache_size()).unwrap();
+        assert!(sent(&log).is_empty());
+
+        // Armed on attaching: for the class being prepared, and in it now it's loaded
+        session.reattach().unwrap();
+        assert!(session.is_attached());
+        let (prepare, modification) = (
+            Some(EventKind::ClassPrepare as u8),
+            Some(EventKind::FieldModification as u8),
+        );
+        let arming = vec![
+            (15, 1, prepare),
+            (1, 2, None),
+            (2, 4, None),
+            (15, 1, modification),
+        ];
+        assert_eq!(sent(&log), arming);
+
+        let guard = session.jvm().unwrap().suspend_guard().unwrap();
+        guard.resume().unwrap();
+        assert_eq!(sent(&log), vec![(1, 8, None), (1, 9, None)]);
+
+        // Nothing's heard from the target once detached, but the watch is kept
+        session.detach().unwrap();
+        assert_eq!(sent(&log), vec![(1, 6, None)]);
+        assert!(!session.is_attached());
+        let e = session.jvm().err().unwrap();
+        assert_eq!(e.kind(), ErrorKind::NotConnected);
+        let e = session.next_event(Some(Duration::ZERO)).err().unwrap();
+        assert_eq!(e.kind(), ErrorKind::NotConnected);
+        session.detach().unwrap();
+        assert_eq!(session.watches().count(), 1);
+
+        // And armed again on the next connection, which unwatching disarms
+        session.reattach().unwrap();
+        assert_eq!(sent(&log), arming);
+        session.unwatch_field(watch).unwrap();
+        assert_eq!(sent(&log), vec![(15, 2, None), (15, 2, None)]);
+        assert!(session.next_event(Some(Duration::ZERO)).unwrap().is_none());
+        session.detach().unwrap();
+    }
+
+    #[test]
+    fn attach_errors() {
+        // Nothing's listening
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+        drop(listener);
+        assert!(Session::attach(&address).is_err());
+        let mut session = Session::new(&address);
+        assert!(session.reattach().is_err());
+        assert!(!session.is_attached());
+
+        // A watch the target can't arm is refused, and not kept, nor is the request for its class
+        // being prepared
+        let (address, log) = target(false);
+        let mut session = Session::attach(&address).unwrap();
+        sent(&log);
+        let e = session.watch_field(cache_size()).err().unwrap();
+        assert_eq!(jdwp_error_code(&e), Some(error_code::NOT_IMPLEMENTED));
+        assert_eq!(session.watches().count(), 0);
+        let (prepare, modification) = (
+            Some(EventKind::ClassPrepare as u8),
+            Some(EventKind::FieldModification as u8),
+        );
+        assert_eq!(
+            sent(&log),
+            vec![
+                (15, 1, prepare),
+                (1, 2, None),
+                (2, 4, None),
+                (15, 1, modification),
+                (15, 2, None)
+            ]
+        );
+        session.detach().unwrap();
+    }
+
+    #[test]
+    fn settings_round_trip() {
+        let mut session = Session::new("localhost:8000");
+        let spec = BreakpointSpec {
+            class_pattern: "com.example.*".parse().unwrap(),
+            source_file: "Server.java".to_string(),
+            line: 42,
+        };
+        session.add_breakpoint(spec).unwrap();
+        session
+            .break_on_class_initialization("com.example.Config".parse().unwrap())
+            .unwrap();
+        let spec = WatchSpec {
+            class_name: "com.example.Cache".to_string(),
+            field_name: "size".to_string(),
+            kind: WatchKind::Modification,
+        };
+        session.watch_field(spec).unwrap();
+        session.set_renderer("com.example.User", "User {name}\t<{email}>\\");
+        session.annotations_mut().label_thread("worker-17", "stuck");
+        session.set_audit_log("/var/log/jdb-audit.log").unwrap();
+        session
+            .annotations_mut()
+            .label_object(0x1234, "leaked-cache");
+
+        let mut saved = vec![];
+        session.write_settings(&mut saved).unwrap();
+        let loaded = Session::read_settings(Cursor::new(saved)).unwrap();
+        assert_eq!(loaded.address(), "localhost:8000");
+        assert!(!loaded.is_attached());
+        let (_, breakpoint) = loaded.breakpoints().next().unwrap();
+        assert_eq!(breakpoint.class_pattern.as_str(), "com.example.*");
+        assert_eq!(breakpoint.line, 42);
+        let (_, pattern) = loaded.initialization_breakpoints().next().unwrap();
+        assert_eq!(pattern.as_str(), "com.example.Config");
+        let (_, watch) = loaded.watches().next().unwrap();
+        assert_eq!(watch.kind, WatchKind::Modification);
+        assert_eq!(
+            loaded.renderers().collect::<Vec<_>>(),
+            [("com.example.User", "User {name}\t<{email}>\\")]
+        );
+        assert_eq!(
+            loaded.annotations().thread_label("worker-17"),
+            Some("stuck")
+        );
+        assert_eq!(loaded.annotations().object_label(0x1234), None);
+        assert_eq!(loaded.audit_log(), Some("/var/log/jdb-audit.log"));
+
+        let bad = Cursor::new("address\tx\nwatch\tread\tA\tb\n");
+        let err = Session::read_settings(bad).err().unwrap();
+        assert_eq!(err.kind(), ErrorKind::InvalidData);
+        assert!(err.to_string().starts_with("line 2:"), "{}", err);
+    }
+}
@@ -0,0 +1,471 @@
+//
+// Catching a hang in the act. A stalled service is usually noticed (and restarted) long after
+// the moment worth looking at, so this samples the target at a low rate and captures a thread
+// dump, and optionally a class histogram, as soon as it looks stuck. Two symptoms are watched
+// for: many threads blocked on monitors at once, and a static counter (requests served, messages
+// processed...) which has stopped moving.
+//
+// Sampling doesn't suspend anything: it asks for each thread's status and reads one static
+// field. Only a capture suspends the VM, for as long as it takes to walk the stacks. Captures are
+// written to an OutputSink (usually a directory), named by the time they were taken, and are at
+// least 'cooldown' apart so that a long stall doesn't fill the disk.
+//
+// Only the threads the connection's thread filter lets through are counted, and captured. Each
+// thread's name is looked up the first time it's seen and kept, so a thread which is renamed
+// afterwards is still counted (or not) under its old name.
+//
+
+use std::collections::HashMap;
+use std::io::{Error, ErrorKind, Result, Write};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use super::{error_code, has_error_code, name_to_signature, thread_status};
+use super::{reference_type, thread_reference, virtual_machine};
+use super::{JdwpConnection, JdwpJavaVirtualMachine, StopSignal, Value};
+use crate::annotation::Annotations;
+use crate::model::JavaVirtualMachine;
+use crate::report::{self, OutputSink};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StallTriggers {
+    // How often to sample
+    pub interval: Duration,
+    // Capture when at least this many threads are blocked on monitors
+    pub blocked_threads: Option<usize>,
+    // Capture when this static field hasn't changed for a while
+    pub progress_field: Option<ProgressField>,
+    // Capture a class histogram along with the thread dump. This can take a while on a big heap.
+    pub histogram: bool,
+    // The least time between captures
+    pub cooldown: Duration,
+}
+
+impl Default for StallTriggers {
+    fn default() -> Self {
+        StallTriggers {
+            interval: Duration::from_secs(5),
+            blocked_threads: Some(10),
+            progress_field: None,
+            histogram: false,
+            cooldown: Duration::from_secs(60),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ProgressField {
+    // e.g. com.example.Server
+    pub class_name: String,
+    // A static field of the class, e.g. a request counter
+    pub field_name: String,
+    // How long it has to stay the same to count as stalled
+    pub stalled_after: Duration,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum StallReason {
+    // How many threads were blocked
+    BlockedThreads(usize),
+    // The field, as Class.field, and for how long it hadn't changed
+    NoProgress {
+        field: String,
+        stalled_for: Duration,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StallCapture {
+    pub reason: StallReason,
+    pub time: SystemTime,
+    // How long the target was suspended for to take the thread dump
+    pub suspended_for: Duration,
+    // Where the thread dump, then the histogram if there is one, ended up (see
+    // SinkWriter::finish())
+    pub artifacts: Vec<String>,
+}
+
+struct Progress {
+    name: String,
+    class_id: u64,
+    field_id: u64,
+    stalled_after: Duration,
+    value: Option<Value>,
+    changed: Instant,
+}
+
+pub struct StallMonitor {
+    jvm: JdwpJavaVirtualMachine,
+    triggers: StallTriggers,
+    sink: Box<dyn OutputSink>,
+    annotations: Annotations,
+    progress: Option<Progress>,
+    last_capture: Option<Instant>,
+    captures: Vec<StallCapture>,
+    // Each thread's name, for the thread filter
+    thread_names: HashMap<u64, String>,
+}
+
+impl JdwpJavaVirtualMachine {
+    // Start watching for stalls, writing captures to 'sink' (e.g. a report::DirectorySink). The
+    // class with the progress field, if any, has to have been loaded already.
+    pub fn stall_monitor<S: OutputSink + 'static>(
+        &self,
+        triggers: StallTriggers,
+        sink: S,
+    ) -> Result<StallMonitor> {
+        let progress = match &triggers.progress_field {
+            Some(field) => Some(self.progress(field)?),
+            None => None,
+        };
+        Ok(StallMonitor {
+            jvm: JdwpJavaVirtualMachine {
+                conn: self.conn.clone(),
+            },
+            triggers,
+            sink: Box::new(sink),
+            annotations: Annotations::new(),
+            progress,
+            last_capture: None,
+            captures: vec![],
+            thread_names: HashMap::new(),
+        })
+    }
+
+    fn progress(&self, field: &ProgressField) -> Result<Progress> {
+        let conn = self.conn.as_ref();
+        let signature = name_to_signature(&field.class_name);
+        let not_found = |what: &str| {
+            Error::new(
+                ErrorKind::NotFound,
+                format!("No {} {}.{}", what, field.class_name, field.field_name),
+            )
+        };
+        let class_id = virtual_machine::classes_by_signature(conn, &signature)?
+            .classes
+            .first()
+            .map(|c| c.type_id)
+            .ok_or_else(|| not_found("loaded class for"))?;
+        let field_id = reference_type::fields(conn, class_id)?
+            .fields
+            .iter()
+            .find(|f| f.name == field.field_name)
+            .map(|f| f.field_id)
+            .ok_or_else(|| not_found("field"))?;
+        Ok(Progress {
+            name: format!("{}.{}", field.class_name, field.field_name),
+            class_id,
+            field_id,
+            stalled_after: field.stalled_after,
+            value: None,
+            changed: Instant::now(),
+        })
+    }
+}
+
+impl StallMonitor {
+    // Labels for the threads in the captured thread dumps
+    pub fn set_annotations(&mut self, annotations: Annotations) {
+        self.annotations = annotations;
+    }
+
+    // Sample every interval for the given amount of time
+    pub fn run_for(&mut self, duration: Duration) -> Result<()> {
+        let deadline = Instant::now() + duration;
+        loop {
+            self.sample()?;
+            let now = Instant::now();
+            if now >= deadline {
+                return Ok(());
+            }
+            thread::sleep(self.triggers.interval.min(deadline - now));
+        }
+    }
+
+    // Sample every interval until 'stop' is, e.g. as a Supervisor task
+    pub fn run_until(&mut self, stop: &StopSignal) -> Result<()> {
+        while !stop.is_stopped() {
+            self.sample()?;
+            stop.wait(self.triggers.interval);
+        }
+        Ok(())
+    }
+
+    // Take one sample, and a capture if it looks like a stall (and the last capture was long
+    // enough ago)
+    pub fn sample(&mut self) -> Result<Option<&StallCapture>> {
+        let reason = match self.check()? {
+            Some(reason) => reason,
+            None => return Ok(None),
+        };
+        if self
+            .last_capture
+            .is_some_and(|last| last.elapsed() < self.triggers.cooldown)
+        {
+            return Ok(None);
+        }
+        let capture = self.capture(reason)?;
+        self.last_capture = Some(Instant::now());
+        self.captures.push(capture);
+        Ok(self.captures.last())
+    }
+
+    // Every capture so far, oldest first
+    pub fn captures(&self) -> &[StallCapture] {
+        &self.captures
+    }
+
+    fn check(&mut self) -> Result<Option<StallReason>> {
+        let conn = self.jvm.conn.as_ref();
+        if let Some(progress) = &mut self.progress {
+            let value = reference_type::get_values(conn, progress.class_id, &[progress.field_id])?
+                .values
+                .pop();
+            if value != progress.value {
+                progress.value = value;
+                progress.changed = Instant::now();
+            } else if progress.changed.elapsed() >= progress.stalled_after {
+                return Ok(Some(StallReason::NoProgress {
+                    field: progress.name.clone(),
+                    stalled_for: progress.changed.elapsed(),
+                }));
+            }
+        }
+        if let Some(limit) = self.triggers.blocked_threads {
+            let filter = self.jvm.thread_filter();
+            let mut blocked = 0;
+            for thread in virtual_machine::all_threads(conn)?.threads {
+                let status = if filter.is_all() {
+                    thread_reference::status(conn, thread)
+                } else {
+                    match thread_name(conn, &mut self.thread_names, thread) {
+                        Ok(name) if !filter.matches(&name) => continue,
+                        Ok(_) => thread_reference::status(conn, thread),
+                        Err(e) => Err(e),
+                    }
+                };
+                match status {
+                    Ok(status) if status.thread_status == thread_status::MONITOR => blocked += 1,
+                    Ok(_) => {}
+                    // Threads can finish while we look
+                    Err(e) if has_error_code(&e, &[error_code::INVALID_THREAD]) => {}
+                    Err(e) => return Err(e),
+                }
+            }
+            if blocked >= limit {
+                return Ok(Some(StallReason::BlockedThreads(blocked)));
+            }
+        }
+        Ok(None)
+    }
+
+    fn capture(&self, reason: StallReason) -> Result<StallCapture> {
+        let time = SystemTime::now();
+        let millis = time
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_millis())
+            .unwrap_or(0);
+        let mut artifacts = vec![];
+
+        let dump = self.jvm.capture_thread_dump()?;
+        let mut out = self.sink.create(&format!("stall-{}-threads.txt", millis))?;
+        writeln!(out, "{}", describe(&reason))?;
+        writeln!(
+            out,
+            "Target suspended for {}ms",
+            dump.suspended_for().as_millis()
+        )?;
+        report::write_stack_groups_annotated(&dump.stacks(), &self.annotations, &mut out)?;
+        artifacts.push(out.finish()?);
+
+        if self.triggers.histogram {
+            let histogram = self.jvm.class_histogram()?;
+            artifacts.push(report::write_to(
+                self.sink.as_ref(),
+                &format!("stall-{}-histogram.txt", millis),
+                |out| report::write_histogram(&histogram, None, out),
+            )?);
+        }
+        Ok(StallCapture {
+            reason,
+            time,
+            suspended_for: dump.suspended_for(),
+            artifacts,
+        })
+    }
+}
+
+fn thread_name(
+    conn: &JdwpConnection,
+    names: &mut HashMap<u64, String>,
+    thread_id: u64,
+) -> Result<String> {
+    if let Some(name) = names.get(&thread_id) {
+        return Ok(name.clone());
+    }
+    let name = thread_reference::name(conn, thread_id)?.name;
+    names.insert(thread_id, name.clone());
+    Ok(name)
+}
+
+fn describe(reason: &StallReason) -> String {
+    match reason {
+        StallReason::BlockedThreads(count) => {
+            format!("{} threads were blocked on monitors", count)
+        }
+        StallReason::NoProgress { field, stalled_for } => {
+            format!("{} hadn't changed for {}s", field, stalled_for.as_secs())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use super::*;
+    use crate::jdwp::fake::{self, reply};
+    use crate::jdwp::thread_status;
+    use crate::report::StdoutSink;
+    use crate::thread_filter::ThreadFilter;
+
+    const CLASS: u64 = 0x10;
+    const FIELD: u64 = 0x20;
+
+    // Threads 1 to 3 are blocked and 4 is sleeping, with thread 3 called "gc" and the rest
+    // "worker-N". Thread 5 has just finished. com.example.Server.requests is whatever 'requests'
+    // holds.
+    fn target(requests: Arc<Mutex<i32>>) -> (JdwpJavaVirtualMachine, fake::Log) {
+        let answers = fake::Answers::default()
+            .on(1, 4, |_| reply![5, 1u64, 2u64, 3u64, 4u64, 5u64])
+            .on(11, 1, |data| match fake::first_id(data) {
+                3 => reply!["gc"],
+                5 => Err(error_code::INVALID_THREAD),
+                id => reply![format!("worker-{}", id).as_str()],
+            })
+            .on(11, 4, |data| match fake::first_id(data) {
+                1..=3 => reply![thread_status::MONITOR, 0],
+                4 => reply![thread_status::SLEEPING, 0],
+                _ => Err(error_code::INVALID_THREAD),
+            })
+            .on(1, 2, |_| reply![1, 1u8, CLASS, 7u32])
+            .on(2, 4, |_| reply![1, FIELD, "requests", "I", 8])
+            .on(2, 6, move |_| {
+                let requests = *requests.lock().unwrap();
+                reply![1, &Value::Integer(requests)]
+            });
+        let log = answers.log();
+        (JdwpJavaVirtualMachine::new(answers.attach()), log)
+    }
+
+    fn triggers(
+        blocked_threads: Option<usize>,
+        progress_field: Option<ProgressField>,
+    ) -> StallTriggers {
+        StallTriggers {
+            blocked_threads,
+            progress_field,
+            ..Default::default()
+        }
+    }
+
+    fn requests(stalled_after: Duration) -> ProgressField {
+        ProgressField {
+            class_name: "com.example.Server".to_string(),
+            field_name: "requests".to_string(),
+            stalled_after,
+        }
+    }
+
+    #[test]
+    fn blocked_threads() {
+        let (jvm, log) = target(Arc::new(Mutex::new(0)));
+        let mut monitor = jvm
+            .stall_monitor(triggers(Some(3), None), StdoutSink)
+            .unwrap();
+        assert_eq!(
+            monitor.check().unwrap(),
+            Some(StallReason::BlockedThreads(3))
+        );
+        monitor.triggers.blocked_threads = Some(4);
+        assert_eq!(monitor.check().unwrap(), None);
+
+        // Only the workers count, and each name is only asked for once
+        jvm.set_thread_filter(ThreadFilter {
+            include: vec!["worker-*".to_string()],
+            exclude: vec![],
+        });
+        monitor.triggers.blocked_threads = Some(2);
+        assert_eq!(
+            monitor.check().unwrap(),
+            Some(StallReason::BlockedThreads(2))
+        );
+        assert_eq!(
+            monitor.check().unwrap(),
+            Some(StallReason::BlockedThreads(2))
+        );
+        let names = log
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|c| (c.0, c.1) == (11, 1))
+            .count();
+        // Thread 5 had finished before its name could be had, so it's asked for again
+        assert_eq!(names, 4 + 2);
+    }
+
+    #[test]
+    fn no_progress() {
+        let requests_served = Arc::new(Mutex::new(0));
+        let (jvm, _) = target(requests_served.clone());
+        let stalled_after = Duration::from_secs(30);
+        let triggers = triggers(None, Some(requests(stalled_after)));
+        let mut monitor = jvm.stall_monitor(triggers, StdoutSink).unwrap();
+        // The first value is a change from nothing
+        assert_eq!(monitor.check().unwrap(), None);
+        assert_eq!(monitor.check().unwrap(), None);
+
+        let stall_for = |monitor: &mut StallMonitor, time: Duration| {
+            monitor.progress.as_mut().unwrap().changed -= time;
+        };
+        stall_for(&mut monitor, stalled_after);
+        match monitor.check().unwrap() {
+            Some(StallReason::NoProgress { field, stalled_for }) => {
+                assert_eq!(field, "com.example.Server.requests");
+                assert!(stalled_for >= stalled_after);
+            }
+            reason => panic!("Expected no progress, got {:?}", reason),
+        }
+
+        // Moving again
+        *requests_served.lock().unwrap() += 1;
+        assert_eq!(monitor.check().unwrap(), None);
+        stall_for(&mut monitor, stalled_after - Duration::from_secs(1));
+        assert_eq!(monitor.check().unwrap(), None);
+    }
+
+    #[test]
+    fn missing_field() {
+        let (jvm, _) = target(Arc::new(Mutex::new(0)));
+        let mut field = requests(Duration::from_secs(30));
+        field.field_name = "responses".to_string();
+        let e = jvm
+            .stall_monitor(triggers(None, Some(field)), StdoutSink)
+            .err()
+            .unwrap();
+        assert_eq!(e.kind(), ErrorKind::NotFound);
+        assert_eq!(e.to_string(), "No field com.example.Server.responses");
+    }
+
+    #[test]
+    fn cooldown() {
+        let (jvm, _) = target(Arc::new(Mutex::new(0)));
+        let mut monitor = jvm
+            .stall_monitor(triggers(Some(1), None), StdoutSink)
+            .unwrap();
+        // Stalled, but it hasn't been long since the last capture
+        monitor.last_capture = Some(Instant::now());
+        assert_eq!(monitor.sample().unwrap(), None);
+        assert!(monitor.captures().is_empty());
+    }
+}
@@ -0,0 +1,356 @@
+//
+// Counting the commands sent over a connection, and how long the target took to answer them. A
+// tool which is fast against a local VM can be painfully slow against one on the other side of
+// the world, and this is how to find out which commands are to blame (usually thousands of small
+// ones which could have been batched, see JdwpConnection::get_values_bulk()).
+//
+// The time for a command is from sending it to reading its reply, so for pipelined commands it
+// includes waiting for the ones ahead of it.
+//
+
+use std::collections::HashMap;
+use std::rc::Rc;
+use std::time::{Duration, Instant};
+
+use super::ddm::DDM_COMMAND_SET;
+use super::event::event_request;
+use super::thread_reference;
+use super::virtual_machine;
+use super::{array_reference, array_type, class_object_reference, class_type, method};
+use super::{module_reference, object_reference, reference_type, stack_frame, string_reference};
+use super::{JdwpConnection, JdwpJavaVirtualMachine};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CommandStats {
+    pub command_set: u8,
+    pub command: u8,
+    // e.g. "virtual_machine::all_threads", or just the numbers for commands we don't know
+    pub name: String,
+    pub count: u64,
+    // How many of them the target answered with an error
+    pub errors: u64,
+    // Including the packet headers
+    pub bytes_sent: u64,
+    pub bytes_received: u64,
+    pub total: Duration,
+    pub max: Duration,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ConnectionStats {
+    // Sorted by total time, longest first
+    pub commands: Vec<CommandStats>,
+}
+
+impl ConnectionStats {
+    pub fn total_commands(&self) -> u64 {
+        self.commands.iter().map(|c| c.count).sum()
+    }
+
+    pub fn total_time(&self) -> Duration {
+        self.commands.iter().map(|c| c.total).sum()
+    }
+
+    pub fn bytes_sent(&self) -> u64 {
+        self.commands.iter().map(|c| c.bytes_sent).sum()
+    }
+
+    pub fn bytes_received(&self) -> u64 {
+        self.commands.iter().map(|c| c.bytes_received).sum()
+    }
+}
+
+// Passed to the function given to log_slow_commands()
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SlowCommand {
+    pub command_set: u8,
+    pub command: u8,
+    pub name: String,
+    pub elapsed: Duration,
+    pub bytes_sent: u64,
+    pub bytes_received: u64,
+}
+
+struct InFlight {
+    command_set: u8,
+    command: u8,
+    bytes_sent: u64,
+    start: Instant,
+}
+
+type SlowCommandLog = Rc<dyn Fn(&SlowCommand)>;
+
+#[derive(Default)]
+pub(super) struct CommandTracker {
+    // By packet ID
+    in_flight: HashMap<u32, InFlight>,
+    stats: HashMap<(u8, u8), CommandStats>,
+    slow_command_log: Option<(Duration, SlowCommandLog)>,
+}
+
+impl CommandTracker {
+    pub(super) fn sent(&mut self, id: u32, command_set: u8, command: u8, bytes_sent: u64) {
+        self.in_flight.insert(
+            id,
+            InFlight {
+                command_set,
+                command,
+                bytes_sent,
+                start: Instant::now(),
+            },
+        );
+    }
+
+    // Returns the command to pass to the slow command log, if it took long enough. It's up to
+    // the caller to do so, once it's no longer borrowing the tracker, since the log might want
+    // to look at the stats too.
+    pub(super) fn replied(
+        &mut self,
+        id: u32,
+        bytes_received: u64,
+        is_error: bool,
+    ) -> Option<(SlowCommandLog, SlowCommand)> {
+        let cmd = self.in_flight.remove(&id)?;
+        let elapsed = cmd.start.elapsed();
+        let stats = self
+            .stats
+            .entry((cmd.command_set, cmd.command))
+            .or_insert_with(|| CommandStats {
+                command_set: cmd.command_set,
+                command: cmd.command,
+                name: command_name(cmd.command_set, cmd.command),
+                count: 0,
+                errors: 0,
+                bytes_sent: 0,
+                bytes_received: 0,
+                total: Duration::from_secs(0),
+                max: Duration::from_secs(0),
+            });
+        stats.count += 1;
+        if is_error {
+            stats.errors += 1;
+        }
+        stats.bytes_sent += cmd.bytes_sent;
+        stats.bytes_received += bytes_received;
+        stats.total += elapsed;
+        stats.max = stats.max.max(elapsed);
+
+        match &self.slow_command_log {
+            Some((threshold, log)) if elapsed >= *threshold => Some((
+                log.clone(),
+                SlowCommand {
+                    command_set: cmd.command_set,
+                    command: cmd.command,
+                    name: stats.name.clone(),
+                    elapsed,
+                    bytes_sent: cmd.bytes_sent,
+                    bytes_received,
+                },
+            )),
+            _ => None,
+        }
+    }
+}
+
+impl JdwpConnection {
+    // Every command sent since the connection was made (including the few needed to set it up),
+    // or since the last reset_connection_stats()
+    pub fn connection_stats(&self) -> ConnectionStats {
+        let mut commands: Vec<CommandStats> = self
+            .command_tracker
+            .borrow()
+            .stats
+            .values()
+            .cloned()
+            .collect();
+        commands.sort_by(|a, b| {
+            (b.total, a.command_set, a.command).cmp(&(a.total, b.command_set, b.command))
+        });
+        ConnectionStats { commands }
+    }
+
+    pub fn reset_connection_stats(&self) {
+        self.command_tracker.borrow_mut().stats.clear();
+    }
+
+    // Call 'log' for every command which takes at least 'threshold' to answer, replacing any
+    // function given before. It's called as soon as the reply arrives, before the command
+    // returns.
+    pub fn log_slow_commands<F>(&self, threshold: Duration, log: F)
+    where
+        F: Fn(&SlowCommand) + 'static,
+    {
+        self.command_tracker.borrow_mut().slow_command_log = Some((threshold, Rc::new(log)));
+    }
+
+    pub fn stop_logging_slow_commands(&self) {
+        self.command_tracker.borrow_mut().slow_command_log = None;
+    }
+}
+
+impl JdwpJavaVirtualMachine {
+    // See JdwpConnection::connection_stats()
+    pub fn connection_stats(&self) -> ConnectionStats {
+        self.conn.connection_stats()
+    }
+
+    // See JdwpConnection::log_slow_commands()
+    pub fn log_slow_commands<F>(&self, threshold: Duration, log: F)
+    where
+        F: Fn(&SlowCommand) + 'static,
+    {
+        self.conn.log_slow_commands(threshold, log)
+    }
+}
+
+// The command set modules, as (SET_ID, SET_NAME, command_name)
+macro_rules! command_sets {
+    ($($set:ident),*) => {
+        [$(($set::SET_ID, $set::SET_NAME, $set::command_name as fn(u8) -> Option<&'static str>)),*]
+    };
+}
+
+// As the spec names it, e.g. ThreadReference.Frames rather than thread_reference::frames
+pub(super) fn spec_command_name(command_set: u8, command: u8) -> String {
+    let camel_case = |name: &str| -> String {
+        name.split('_')
+            .map(|word| {
+                let mut chars = word.chars();
+                match chars.next() {
+                    Some(first) => first.to_ascii_uppercase().to_string() + chars.as_str(),
+                    None => String::new(),
+                }
+            })
+            .collect()
+    };
+    let name = command_name(command_set, command);
+    match name.split_once("::") {
+        Some((set, command)) => format!("{}.{}", camel_case(set), camel_case(command)),
+        None => name,
+    }
+}
+
+fn command_name(command_set: u8, command: u8) -> String {
+    if command_set == DDM_COMMAND_SET {
+        return "ddm::chunk".to_string();
+    }
+    let sets = command_sets!(
+        virtual_machine,
+        reference_type,
+        class_type,
+        array_type,
+        method,
+        object_reference,
+        string_reference,
+        thread_reference,
+        array_reference,
+        stack_frame,
+        class_object_reference,
+        module_reference,
+        event_request
+    );
+    sets.iter()
+        .find(|&&(id, _, _)| id == command_set)
+        .and_then(|&(_, set_name, command_name)| {
+            command_name(command).map(|name| format!("{}::{}", set_name, name))
+        })
+        .unwrap_or_else(|| format!("{}/{}", command_set, command))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+    use std::thread;
+
+    use super::*;
+    use crate::jdwp::error_code;
+    use crate::jdwp::fake::{self, reply};
+
+    // Thread names come straight back, and string values slowly, or not at all for object 0
+    fn target() -> JdwpConnection {
+        fake::Answers::default()
+            .on(11, 1, |_| reply!["main"])
+            .on(10, 1, |data| {
+                thread::sleep(Duration::from_millis(50));
+                match fake::first_id(data) {
+                    0 => Err(error_code::INVALID_OBJECT),
+                    _ => reply!["value"],
+                }
+            })
+            .attach()
+    }
+
+    #[test]
+    fn counters() {
+        let conn = target();
+        // Attaching took a few commands of its own
+        assert!(conn.connection_stats().total_commands() > 0);
+        conn.reset_connection_stats();
+        assert_eq!(conn.connection_stats(), ConnectionStats::default());
+
+        thread_reference::name(&conn, 1).unwrap();
+        thread_reference::name(&conn, 2).unwrap();
+        string_reference::value(&conn, 1).unwrap();
+        assert!(string_reference::value(&conn, 0).is_err());
+
+        let stats = conn.connection_stats();
+        let summary: Vec<_> = stats
+            .commands
+            .iter()
+            .map(|c| (c.name.as_str(), c.count, c.errors))
+            .collect();
+        // The slow one first
+        assert_eq!(
+            summary,
+            vec![
+                ("string_reference::value", 2, 1),
+                ("thread_reference::name", 2, 0)
+            ]
+        );
+        let names = &stats.commands[1];
+        // An 11 byte header and an ID each way, and "main" back with its length
+        assert_eq!(names.bytes_sent, 2 * (11 + 8));
+        assert_eq!(names.bytes_received, 2 * (11 + 8));
+        let values = &stats.commands[0];
+        assert!(values.total >= Duration::from_millis(100));
+        assert!(values.max >= Duration::from_millis(50) && values.max <= values.total);
+        assert_eq!(stats.total_commands(), 4);
+        assert_eq!(stats.total_time(), values.total + names.total);
+        assert_eq!(stats.bytes_sent(), 4 * (11 + 8));
+
+        // A snapshot doesn't change as more commands are sent
+        thread_reference::name(&conn, 3).unwrap();
+        assert_eq!(stats.total_commands(), 4);
+        assert_eq!(conn.connection_stats().total_commands(), 5);
+        conn.reset_connection_stats();
+        assert_eq!(conn.connection_stats().total_commands(), 0);
+    }
+
+    #[test]
+    fn slow_commands() {
+        let conn = target();
+        let slow = Rc::new(RefCell::new(vec![]));
+        let log = slow.clone();
+        conn.log_slow_commands(Duration::from_millis(25), move |cmd| {
+            log.borrow_mut().push((cmd.name.clone(), cmd.elapsed));
+        });
+        thread_reference::name(&conn, 1).unwrap();
+        string_reference::value(&conn, 1).unwrap();
+        assert_eq!(slow.borrow().len(), 1);
+        assert_eq!(slow.borrow()[0].0, "string_reference::value");
+        assert!(slow.borrow()[0].1 >= Duration::from_millis(50));
+
+        conn.stop_logging_slow_commands();
+        string_reference::value(&conn, 1).unwrap();
+        assert_eq!(slow.borrow().len(), 1);
+    }
+
+    #[test]
+    fn names() {
+        assert_eq!(command_name(11, 1), "thread_reference::name");
+        assert_eq!(spec_command_name(11, 1), "ThreadReference.Name");
+        assert_eq!(spec_command_name(1, 7), "VirtualMachine.IdSizes");
+        assert_eq!(command_name(DDM_COMMAND_SET, 1), "ddm::chunk");
+        assert_eq!(spec_command_name(200, 3), "200/3");
+    }
+}
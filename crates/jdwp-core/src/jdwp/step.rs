@@ -0,0 +1,448 @@
+//
+// Stepping a thread a line (or an instruction) at a time. Left to itself, stepping into a call
+// lands in whatever runs first, which is more often than not the JDK, a framework or the code
+// the compiler generated around a lambda or a generic method, rather than the code the user is
+// debugging. Step filters say where not to stop.
+//
+// Class patterns the target can evaluate are sent with the step request as ClassExclude
+// modifiers, and the target steps through those classes without reporting anything. The rest,
+// regex patterns and synthetic and bridge methods, which JDWP has no modifier for, are checked
+// here when a step event arrives, and stepping carries on if it's in one of them.
+//
+// When a line makes several calls, e.g. process(parse(read())), "smart step into" steps into the
+// one the user picks rather than the first. call_targets() lists the calls, by reading the
+// method's bytecode for the line (see bytecode.rs). step_into_target() then steps into each call
+// in turn, stepping straight back out of the ones it doesn't want, until the one it's in was made
+// by the chosen instruction, which it tells by the calling frame's code index.
+//
+
+use std::io::{Error, ErrorKind, Result};
+use std::time::{Duration, Instant};
+
+use super::bytecode::{self, ConstantPool, INVOKEDYNAMIC};
+use super::event::{Event, EventKind, Modifier, SuspendPolicy};
+use super::{method, reference_type, signature_to_name, thread_reference};
+use super::{JdwpConnection, JdwpJavaVirtualMachine, JdwpLocation, JdwpStackFrame};
+use super::{JdwpThreadReference, Location};
+use crate::model::Modifiers;
+use crate::pattern::ClassPattern;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StepDepth {
+    // Into any method called on the way
+    Into = 0,
+    // Over calls, staying in the current frame
+    Over = 1,
+    // Out of the current frame, to its caller
+    Out = 2,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StepSize {
+    // A bytecode instruction
+    Min = 0,
+    // A source line, or the whole method if it hasn't got a line table
+    Line = 1,
+}
+
+#[derive(Debug, Clone)]
+pub struct StepFilters {
+    // Classes stepping never stops in, e.g. java.*
+    pub classes: Vec<ClassPattern>,
+    // Don't stop in methods the compiler made up, or in the classes the JVM spins for lambdas.
+    // Lambda bodies are synthetic too, but they're the user's code, so they're stopped in.
+    pub skip_synthetic: bool,
+    // Don't stop in bridge methods, which only cast their arguments and call the real method
+    pub skip_bridges: bool,
+}
+
+impl StepFilters {
+    // Stop wherever the step ends up
+    pub fn none() -> Self {
+        StepFilters {
+            classes: vec![],
+            skip_synthetic: false,
+            skip_bridges: false,
+        }
+    }
+}
+
+impl Default for StepFilters {
+    // The JDK and Kotlin's standard library, and anything synthetic
+    fn default() -> Self {
+        let classes = [
+            "java.*",
+            "javax.*",
+            "jdk.*",
+            "sun.*",
+            "com.sun.*",
+            "kotlin.*",
+        ];
+        StepFilters {
+            classes: classes
+                .iter()
+                .map(|pattern| pattern.parse().expect("valid pattern"))
+                .collect(),
+            skip_synthetic: true,
+            skip_bridges: true,
+        }
+    }
+}
+
+// A call made by the line a frame is on, see JdwpStackFrame::call_targets()
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CallTarget {
+    // The class the call names, e.g. java.util.List, which isn't necessarily the class whose
+    // method runs
+    pub class_name: String,
+    pub method_name: String,
+    // The method's JNI signature, e.g. (Ljava/lang/Object;)Z
+    pub signature: String,
+    // Of the invoke instruction
+    pub code_index: u64,
+    // The method making the call, as (class ID, method ID)
+    caller: (u64, u64),
+}
+
+// How step_into_target() went
+pub enum TargetStep {
+    // Stopped in the chosen call, at the first place the step filters allow
+    Reached(JdwpLocation),
+    // The line finished without making the call, e.g. because it's in a branch which wasn't
+    // taken, or returned or threw. Stopped wherever stepping got to.
+    Missed(JdwpLocation),
+}
+
+// ACC_BRIDGE, which is the same bit as ACC_VOLATILE is for fields
+const BRIDGE: u32 = 0x0040;
+// JDWP sets these bits in a method's modifiers if it's synthetic, whether or not the class file
+// flags it as ACC_SYNTHETIC
+const JDWP_SYNTHETIC: u32 = 0xf000_0000;
+
+impl JdwpConnection {
+    // Applies to every step from now on
+    pub fn set_step_filters(&self, filters: StepFilters) {
+        *self.step_filters.borrow_mut() = filters;
+    }
+
+    pub fn step_filters(&self) -> StepFilters {
+        self.step_filters.borrow().clone()
+    }
+}
+
+impl JdwpJavaVirtualMachine {
+    // See JdwpConnection::set_step_filters()
+    pub fn set_step_filters(&self, filters: StepFilters) {
+        self.conn.set_step_filters(filters)
+    }
+
+    pub fn step_filters(&self) -> StepFilters {
+        self.conn.step_filters()
+    }
+}
+
+impl JdwpThreadReference {
+    // Resumes the thread, which has to be suspended, until it has taken a step somewhere the step
+    // filters allow, and returns where, with the thread suspended again. Waits for up to
+    // 'timeout' (or forever, if None), and returns None on timeout, leaving the thread running,
+    // or once the VM has exited.
+    //
+    // If the thread stops for something else on the way, e.g. a breakpoint, that event is left
+    // for whoever is waiting for it, and the step only finishes once the thread is resumed.
+    pub fn step(
+        &self,
+        depth: StepDepth,
+        size: StepSize,
+        timeout: Option<Duration>,
+    ) -> Result<Option<JdwpLocation>> {
+        let conn = self.conn.as_ref();
+        let filters = conn.step_filters();
+        let mut modifiers = vec![Modifier::Step {
+            thread: self.thread_id,
+            size: size as i32,
+            depth: depth as i32,
+        }];
+        modifiers.extend(
+            filters
+                .classes
+                .iter()
+                .filter_map(|pattern| pattern.to_jdwp())
+                .map(Modifier::ClassExclude),
+        );
+        let request_id = conn.set_event_request(
+            EventKind::SingleStep,
+            SuspendPolicy::EventThread,
+            &modifiers,
+        )?;
+        let stepped = self.step_until_allowed(request_id, &filters, timeout);
+        // A step request has to be cleared before the thread can be stepped again
+        if conn.check_connected().is_ok() {
+            conn.clear_event_request(EventKind::SingleStep, request_id)?;
+        }
+        stepped
+    }
+
+    fn step_until_allowed(
+        &self,
+        request_id: i32,
+        filters: &StepFilters,
+        timeout: Option<Duration>,
+    ) -> Result<Option<JdwpLocation>> {
+        let conn = self.conn.as_ref();
+        let deadline = timeout.map(|t| Instant::now() + t);
+        loop {
+            thread_reference::resume(conn, self.thread_id)?;
+            let remaining = deadline.map(|d| d.saturating_duration_since(Instant::now()));
+            let event = conn.next_event(remaining, |e| {
+                e.kind() == EventKind::VmDeath || e.request_id() == request_id
+            })?;
+            let location = match event {
+                Some(Event::SingleStep { location, .. }) => location,
+                _ => return Ok(None),
+            };
+            let class_name =
+                signature_to_name(&reference_type::signature(conn, location.class_id)?.signature);
+            let method = reference_type::methods(conn, location.class_id)?
+                .methods
+                .into_iter()
+                .find(|m| m.method_id == location.method_id);
+            let (method_name, mod_bits) = match &method {
+                Some(method) => (method.name.as_str(), method.mod_bits as u32),
+                None => ("", 0),
+            };
+            if !filtered(filters, &class_name, method_name, mod_bits) {
+                return Ok(Some(JdwpLocation {
+                    conn: self.conn.clone(),
+                    location,
+                }));
+            }
+        }
+    }
+}
+
+impl JdwpStackFrame {
+    // The calls the frame's current line makes, in the order they appear in the bytecode, leaving
+    // out calls to classes the step filters skip. Lambdas and string concatenation
+    // (invokedynamic) aren't included, since making a lambda doesn't run it.
+    pub fn call_targets(&self) -> Result<Vec<CallTarget>> {
+        let conn = self.conn.as_ref();
+        conn.require_capability(|c| c.can_get_bytecodes, "Reading bytecode")?;
+        conn.require_capability(|c| c.can_get_constant_pool, "Reading the constant pool")?;
+        let Location {
+            class_id,
+            method_id,
+            location_idx,
+            ..
+        } = self.location;
+        let table = method::line_table(conn, class_id, method_id)?;
+        let mut lines: Vec<(u64, u32)> = table
+            .lines
+            .iter()
+            .map(|entry| (entry.line_code_index as u64, entry.line_number))
+            .collect();
+        lines.sort_unstable();
+        let line = match line_at(&lines, location_idx) {
+            Some(line) => line,
+            None => return Ok(vec![]),
+        };
+        // A line can be in several pieces, e.g. a for loop's condition and its update
+        let end = table.end as u64 + 1;
+        let ranges: Vec<(u64, u64)> = lines
+            .iter()
+            .enumerate()
+            .filter(|(_, &(_, l))| l == line)
+            .map(|(i, &(start, _))| (start, lines.get(i + 1).map_or(end, |&(next, _)| next)))
+            .collect();
+
+        let code = method::bytecodes(conn, class_id, method_id)?.bytes;
+        let pool = reference_type::constant_pool(conn, class_id)?;
+        let pool = ConstantPool::parse(pool.count, &pool.bytes)?;
+        let filters = conn.step_filters();
+        let mut targets = vec![];
+        for invoke in bytecode::invokes(&code)? {
+            if invoke.opcode == INVOKEDYNAMIC
+                || !ranges
+                    .iter()
+                    .any(|&(start, end)| start <= invoke.code_index && invoke.code_index < end)
+            {
+                continue;
+            }
+            let (class, name, signature) = match pool.method(invoke.constant) {
+                Some((Some(class), name, signature)) => (class, name, signature),
+                _ => continue,
+            };
+            if filters.classes.iter().any(|p| p.matches(class)) {
+                continue;
+            }
+            targets.push(CallTarget {
+                class_name: class.replace('/', "."),
+                method_name: name.to_string(),
+                signature: signature.to_string(),
+                code_index: invoke.code_index,
+                caller: (class_id, method_id),
+            });
+        }
+        Ok(targets)
+    }
+}
+
+impl JdwpThreadReference {
+    // Steps into the call 'target', which has to be one of call_targets() for the thread's top
+    // frame, with the thread suspended. Waits for up to 'timeout' in all (or forever, if None),
+    // and returns None on timeout, leaving the thread running, or once the VM has exited.
+    pub fn step_into_target(
+        &self,
+        target: &CallTarget,
+        timeout: Option<Duration>,
+    ) -> Result<Option<TargetStep>> {
+        let conn = self.conn.as_ref();
+        let top = self.frame_location(0)?;
+        if (top.class_id, top.method_id) != target.caller {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                format!(
+                    "{}() isn't called from the thread's current frame",
+                    target.method_name
+                ),
+            ));
+        }
+        let table = method::line_table(conn, top.class_id, top.method_id)?;
+        let mut lines: Vec<(u64, u32)> = table
+            .lines
+            .iter()
+            .map(|entry| (entry.line_code_index as u64, entry.line_number))
+            .collect();
+        // Line tables are in whatever order the compiler wrote them, which for loops and
+        // try/finally isn't the code's
+        lines.sort_unstable();
+        let line = line_at(&lines, top.location_idx);
+        let base = self.frame_count()?;
+        let deadline = timeout.map(|t| Instant::now() + t);
+        let remaining = || deadline.map(|d| d.saturating_duration_since(Instant::now()));
+
+        loop {
+            let location = match self.step(StepDepth::Into, StepSize::Line, remaining())? {
+                Some(location) => location,
+                None => return Ok(None),
+            };
+            let depth = self.frame_count()?;
+            if depth <= base {
+                return Ok(Some(TargetStep::Missed(location)));
+            }
+            // The frame we started in is under the ones the call has pushed
+            let caller = self.frame_location(depth - base)?;
+            if caller.location_idx == target.code_index {
+                return Ok(Some(TargetStep::Reached(location)));
+            }
+
+            // Some other call on the line, so finish it and try the next one
+            let mut location = location;
+            let mut depth = depth;
+            while depth > base {
+                location = match self.step(StepDepth::Out, StepSize::Line, remaining())? {
+                    Some(location) => location,
+                    None => return Ok(None),
+                };
+                depth = self.frame_count()?;
+            }
+            let l = location.location;
+            if depth < base
+                || (l.class_id, l.method_id) != target.caller
+                || line_at(&lines, l.location_idx) != line
+            {
+                return Ok(Some(TargetStep::Missed(location)));
+            }
+        }
+    }
+
+    fn frame_count(&self) -> Result<i32> {
+        Ok(thread_reference::frame_count(self.conn.as_ref(), self.thread_id)?.frame_count)
+    }
+
+    fn frame_location(&self, depth: i32) -> Result<Location> {
+        thread_reference::frames(self.conn.as_ref(), self.thread_id, depth, 1)?
+            .frames
+            .first()
+            .map(|frame| frame.location)
+            .ok_or_else(|| super::protocol_err("The thread has no frames"))
+    }
+}
+
+// The line a code index is on, given (code index, line) sorted by code index
+fn line_at(lines: &[(u64, u32)], code_index: u64) -> Option<u32> {
+    lines
+        .iter()
+        .take_while(|&&(start, _)| start <= code_index)
+        .last()
+        .map(|&(_, line)| line)
+}
+
+// Whether a step which lands in this method should carry on
+fn filtered(filters: &StepFilters, class_name: &str, method_name: &str, mod_bits: u32) -> bool {
+    if filters.classes.iter().any(|p| p.matches(class_name)) {
+        return true;
+    }
+    let synthetic = mod_bits & (JDWP_SYNTHETIC | Modifiers::SYNTHETIC) != 0;
+    if filters.skip_synthetic
+        && ((synthetic && !method_name.starts_with("lambda$")) || class_name.contains("$$Lambda"))
+    {
+        return true;
+    }
+    filters.skip_bridges && mod_bits & BRIDGE != 0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn filters() {
+        let filters = StepFilters::default();
+        assert!(filtered(&filters, "java.util.ArrayList", "add", 0x0001));
+        assert!(filtered(
+            &filters,
+            "kotlin.collections.CollectionsKt",
+            "map",
+            0x0009
+        ));
+        assert!(!filtered(&filters, "com.example.Worker", "run", 0x0001));
+        // A bridge, and an accessor javac made for an inner class
+        assert!(filtered(&filters, "com.example.Box", "get", 0x1041));
+        assert!(filtered(
+            &filters,
+            "com.example.Outer",
+            "access$000",
+            0xf000_1008
+        ));
+        // Lambda bodies are the user's, the classes which call them aren't
+        assert!(!filtered(
+            &filters,
+            "com.example.Worker",
+            "lambda$run$0",
+            0x100a
+        ));
+        assert!(filtered(
+            &filters,
+            "com.example.Worker$$Lambda/0x0000000800c03000",
+            "run",
+            0x0001
+        ));
+        assert!(!filtered(
+            &StepFilters::none(),
+            "java.util.ArrayList",
+            "add",
+            0x1041
+        ));
+    }
+
+    #[test]
+    fn lines() {
+        // A while loop, whose condition javac puts after the body
+        let mut lines = vec![(0, 10), (12, 11), (4, 12), (9, 13)];
+        lines.sort_unstable();
+        assert_eq!(line_at(&lines, 0), Some(10));
+        assert_eq!(line_at(&lines, 5), Some(12));
+        assert_eq!(line_at(&lines, 11), Some(13));
+        assert_eq!(line_at(&lines, 20), Some(11));
+    }
+}
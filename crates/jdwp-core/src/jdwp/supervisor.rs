@@ -0,0 +1,218 @@
+//
+// Running samplers, monitors and proxies in the background, and getting rid of them all again.
+// A connection can't be shared between threads, so a task is a closure which attaches (or starts
+// a JdwpProxy) on its own thread, and does its work until it's told to stop:
+//
+//   supervisor.spawn("stalls", move |stop| {
+//       let jvm = attach_live(address)?;
+//       jvm.stall_monitor(triggers, sink)?.run_until(&stop)
+//   })?;
+//
+// Stopping is cooperative: StopSignal::wait() stands in for the sleep between samples, and
+// returns as soon as the task is stopped. A task which fails or panics only takes its own thread
+// down, and shutdown() (or dropping the supervisor) stops the rest, newest first, and waits for
+// each of them, so that nothing is left attached once it returns.
+//
+
+use std::any::Any;
+use std::io::{Error, Result};
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+// Handed to each task, to see whether it should stop
+#[derive(Clone, Default)]
+pub struct StopSignal {
+    stopped: Arc<(Mutex<bool>, Condvar)>,
+}
+
+impl StopSignal {
+    pub fn new() -> StopSignal {
+        StopSignal::default()
+    }
+
+    pub fn stop(&self) {
+        let (stopped, changed) = &*self.stopped;
+        *stopped.lock().unwrap_or_else(|e| e.into_inner()) = true;
+        changed.notify_all();
+    }
+
+    pub fn is_stopped(&self) -> bool {
+        *self.stopped.0.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    // Sleep for 'timeout', or until stopped if that's sooner. True if stopped.
+    pub fn wait(&self, timeout: Duration) -> bool {
+        let deadline = Instant::now() + timeout;
+        let (stopped, changed) = &*self.stopped;
+        let mut is_stopped = stopped.lock().unwrap_or_else(|e| e.into_inner());
+        while !*is_stopped {
+            let now = Instant::now();
+            if now >= deadline {
+                break;
+            }
+            is_stopped = changed
+                .wait_timeout(is_stopped, deadline - now)
+                .unwrap_or_else(|e| e.into_inner())
+                .0;
+        }
+        *is_stopped
+    }
+}
+
+#[derive(Debug)]
+pub enum TaskOutcome {
+    Finished,
+    Failed(Error),
+    // With the panic's message, if it had one
+    Panicked(String),
+}
+
+#[derive(Debug)]
+pub struct TaskReport {
+    pub name: String,
+    pub outcome: TaskOutcome,
+}
+
+struct Task {
+    name: String,
+    stop: StopSignal,
+    handle: JoinHandle<Result<()>>,
+}
+
+#[derive(Default)]
+pub struct Supervisor {
+    tasks: Vec<Task>,
+}
+
+impl Supervisor {
+    pub fn new() -> Supervisor {
+        Supervisor::default()
+    }
+
+    // Run 'task' on a thread of its own, named 'name'
+    pub fn spawn<F>(&mut self, name: &str, task: F) -> Result<()>
+    where
+        F: FnOnce(StopSignal) -> Result<()> + Send + 'static,
+    {
+        let stop = StopSignal::new();
+        let signal = stop.clone();
+        let handle = thread::Builder::new()
+            .name(name.to_string())
+            .spawn(move || task(signal))?;
+        self.tasks.push(Task {
+            name: name.to_string(),
+            stop,
+            handle,
+        });
+        Ok(())
+    }
+
+    // The tasks which haven't returned yet, oldest first
+    pub fn running(&self) -> Vec<&str> {
+        self.tasks
+            .iter()
+            .filter(|t| !t.handle.is_finished())
+            .map(|t| t.name.as_str())
+            .collect()
+    }
+
+    // Tell the tasks named 'name' to stop, without waiting for them. False if there are none.
+    pub fn stop(&self, name: &str) -> bool {
+        let mut found = false;
+        for task in self.tasks.iter().filter(|t| t.name == name) {
+            task.stop.stop();
+            found = true;
+        }
+        found
+    }
+
+    // Take the tasks which have returned, with how they ended
+    pub fn reap(&mut self) -> Vec<TaskReport> {
+        let (finished, running) = self.tasks.drain(..).partition(|t| t.handle.is_finished());
+        self.tasks = running;
+        finished.into_iter().map(join).collect()
+    }
+
+    // Stop every task, newest first, waiting for each to return. The reports are oldest first,
+    // and include the tasks which had returned but weren't reaped.
+    pub fn shutdown(mut self) -> Vec<TaskReport> {
+        self.stop_all()
+    }
+
+    fn stop_all(&mut self) -> Vec<TaskReport> {
+        let mut reports = vec![];
+        while let Some(task) = self.tasks.pop() {
+            task.stop.stop();
+            reports.push(join(task));
+        }
+        reports.reverse();
+        reports
+    }
+}
+
+impl Drop for Supervisor {
+    fn drop(&mut self) {
+        self.stop_all();
+    }
+}
+
+fn join(task: Task) -> TaskReport {
+    let outcome = match task.handle.join() {
+        Ok(Ok(())) => TaskOutcome::Finished,
+        Ok(Err(e)) => TaskOutcome::Failed(e),
+        Err(panic) => TaskOutcome::Panicked(panic_message(panic)),
+    };
+    TaskReport {
+        name: task.name,
+        outcome,
+    }
+}
+
+fn panic_message(panic: Box<dyn Any + Send>) -> String {
+    match panic.downcast::<String>() {
+        Ok(message) => *message,
+        Err(panic) => match panic.downcast::<&'static str>() {
+            Ok(message) => message.to_string(),
+            Err(_) => String::new(),
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn shutdown() {
+        let mut supervisor = Supervisor::new();
+        supervisor
+            .spawn("sampler", |stop| {
+                while !stop.wait(Duration::from_secs(60)) {}
+                Ok(())
+            })
+            .unwrap();
+        supervisor
+            .spawn("failing", |_| Err(Error::other("target went away")))
+            .unwrap();
+        supervisor
+            .spawn("panicking", |_| panic!("sampler bug"))
+            .unwrap();
+
+        while supervisor.running().len() > 1 {
+            thread::sleep(Duration::from_millis(10));
+        }
+        assert_eq!(supervisor.running(), ["sampler"]);
+        let reaped = supervisor.reap();
+        match &reaped[0].outcome {
+            TaskOutcome::Failed(e) => assert_eq!(e.to_string(), "target went away"),
+            outcome => panic!("{:?}", outcome),
+        }
+        assert!(matches!(&reaped[1].outcome, TaskOutcome::Panicked(m) if m == "sampler bug"));
+
+        let reports = supervisor.shutdown();
+        assert_eq!(reports.len(), 1);
+        assert_eq!(reports[0].name, "sampler");
+        assert!(matches!(reports[0].outcome, TaskOutcome::Finished));
+    }
+}
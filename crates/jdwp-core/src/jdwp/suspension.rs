@@ -0,0 +1,242 @@
+//
+// How long we've kept the target suspended. Anything which suspends the VM stops the application
+// for as long as it takes, and against a busy service that pause is what its users notice, so
+// it's measured: SuspendGuard and capture_thread_dump() say how long their own suspension lasted,
+// suspension_stats() adds them all up, and log_long_suspensions() calls back whenever one goes on
+// too long.
+//
+// Only suspend() and resume() (which SuspendGuard and the captures use) are counted, not threads
+// stopped by events. Suspensions nest, so the target counts as suspended from the first suspend()
+// until the resume() which matches it.
+//
+
+use std::io::Result;
+use std::rc::Rc;
+use std::time::{Duration, Instant};
+
+use super::{virtual_machine, JdwpConnection, JdwpJavaVirtualMachine};
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct SuspensionStats {
+    // How many times the target was suspended and resumed again
+    pub count: u64,
+    pub total: Duration,
+    pub max: Duration,
+}
+
+// Passed to the function given to log_long_suspensions()
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LongSuspension {
+    pub suspended_for: Duration,
+}
+
+type LongSuspensionLog = Rc<dyn Fn(&LongSuspension)>;
+
+#[derive(Default)]
+pub(super) struct SuspensionTracker {
+    // How many suspend()s haven't been matched by a resume() yet
+    depth: u32,
+    since: Option<Instant>,
+    stats: SuspensionStats,
+    long_suspension_log: Option<(Duration, LongSuspensionLog)>,
+}
+
+impl JdwpConnection {
+    pub(super) fn suspend_vm(&self) -> Result<()> {
+        virtual_machine::suspend(self)?;
+        let mut tracker = self.suspensions.borrow_mut();
+        if tracker.depth == 0 {
+            tracker.since = Some(Instant::now());
+        }
+        tracker.depth += 1;
+        Ok(())
+    }
+
+    pub(super) fn resume_vm(&self) -> Result<()> {
+        virtual_machine::resume(self)?;
+        let long = {
+            let mut tracker = self.suspensions.borrow_mut();
+            // A resume() for something suspended by an event, say
+            if tracker.depth == 0 {
+                return Ok(());
+            }
+            tracker.depth -= 1;
+            let since = match tracker.since {
+                Some(since) if tracker.depth == 0 => since,
+                _ => return Ok(()),
+            };
+            tracker.since = None;
+            let elapsed = since.elapsed();
+            tracker.stats.count += 1;
+            tracker.stats.total += elapsed;
+            tracker.stats.max = tracker.stats.max.max(elapsed);
+            match &tracker.long_suspension_log {
+                Some((threshold, log)) if elapsed >= *threshold => Some((
+                    log.clone(),
+                    LongSuspension {
+                        suspended_for: elapsed,
+                    },
+                )),
+                _ => None,
+            }
+        };
+        // Called once the tracker isn't borrowed, since the log might want the stats
+        if let Some((log, long)) = long {
+            log(&long);
+        }
+        Ok(())
+    }
+
+    // Every suspension since the connection was made, not counting one still going on
+    pub fn suspension_stats(&self) -> SuspensionStats {
+        self.suspensions.borrow().stats
+    }
+
+    // How long the target has been suspended for, if it is
+    pub fn suspended_for(&self) -> Option<Duration> {
+        self.suspensions.borrow().since.map(|since| since.elapsed())
+    }
+
+    // Call 'log' whenever the target is resumed after being suspended for at least 'threshold',
+    // replacing any function given before
+    pub fn log_long_suspensions<F>(&self, threshold: Duration, log: F)
+    where
+        F: Fn(&LongSuspension) + 'static,
+    {
+        self.suspensions.borrow_mut().long_suspension_log = Some((threshold, Rc::new(log)));
+    }
+
+    pub fn stop_logging_long_suspensions(&self) {
+        self.suspensions.borrow_mut().long_suspension_log = None;
+    }
+}
+
+impl JdwpJavaVirtualMachine {
+    // Suspend the VM until the returned guard is dropped (or resumed)
+    pub fn suspend_guard(&self) -> Result<SuspendGuard> {
+        self.conn.suspend_vm()?;
+        Ok(SuspendGuard {
+            conn: self.conn.clone(),
+            start: Instant::now(),
+            resumed: false,
+        })
+    }
+
+    // See JdwpConnection::suspension_stats()
+    pub fn suspension_stats(&self) -> SuspensionStats {
+        self.conn.suspension_stats()
+    }
+
+    // See JdwpConnection::log_long_suspensions()
+    pub fn log_long_suspensions<F>(&self, threshold: Duration, log: F)
+    where
+        F: Fn(&LongSuspension) + 'static,
+    {
+        self.conn.log_long_suspensions(threshold, log)
+    }
+}
+
+pub struct SuspendGuard {
+    conn: Rc<JdwpConnection>,
+    start: Instant,
+    resumed: bool,
+}
+
+impl SuspendGuard {
+    // How long the guard has kept the VM suspended so far
+    pub fn elapsed(&self) -> Duration {
+        self.start.elapsed()
+    }
+
+    // Resume the VM, reporting any error rather than ignoring it as dropping the guard does, and
+    // return how long it was suspended for
+    pub fn resume(mut self) -> Result<Duration> {
+        self.resumed = true;
+        self.conn.check_connected()?;
+        self.conn.resume_vm()?;
+        Ok(self.start.elapsed())
+    }
+}
+
+impl Drop for SuspendGuard {
+    fn drop(&mut self) {
+        // After dispose() there's nothing left to resume
+        if !self.resumed && self.conn.check_connected().is_ok() {
+            let _ = self.conn.resume_vm();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::jdwp::fake::{self, reply};
+    use std::cell::Cell;
+
+    const SUSPEND: u8 = 8;
+    const RESUME: u8 = 9;
+
+    fn target() -> (JdwpJavaVirtualMachine, fake::Log) {
+        let answers = fake::Answers::default()
+            .on(1, SUSPEND, |_| reply![])
+            .on(1, RESUME, |_| reply![])
+            // VirtualMachine.Dispose
+            .on(1, 6, |_| reply![]);
+        let log = answers.log();
+        (JdwpJavaVirtualMachine::new(answers.attach()), log)
+    }
+
+    // The VirtualMachine commands the target was sent
+    fn sent(log: &fake::Log) -> Vec<u8> {
+        log.lock()
+            .unwrap()
+            .iter()
+            .map(|&(_, command, _)| command)
+            .collect()
+    }
+
+    #[test]
+    fn nested() {
+        let (jvm, log) = target();
+        let logged = Rc::new(Cell::new(0));
+        let counter = logged.clone();
+        jvm.log_long_suspensions(Duration::ZERO, move |_| counter.set(counter.get() + 1));
+
+        let outer = jvm.suspend_guard().unwrap();
+        let inner = jvm.suspend_guard().unwrap();
+        drop(inner);
+        // Still suspended by the outer guard
+        assert!(jvm.conn.suspended_for().is_some());
+        assert_eq!(jvm.suspension_stats().count, 0);
+        assert_eq!(logged.get(), 0);
+
+        outer.resume().unwrap();
+        assert_eq!(jvm.conn.suspended_for(), None);
+        assert_eq!(jvm.suspension_stats().count, 1);
+        assert_eq!(logged.get(), 1);
+        assert_eq!(sent(&log), vec![SUSPEND, SUSPEND, RESUME, RESUME]);
+
+        // Resuming what something else suspended isn't counted
+        jvm.conn.resume_vm().unwrap();
+        assert_eq!(jvm.suspension_stats().count, 1);
+        assert_eq!(logged.get(), 1);
+    }
+
+    #[test]
+    fn resume_on_drop() {
+        let (jvm, log) = target();
+        {
+            let _guard = jvm.suspend_guard().unwrap();
+            assert!(jvm.conn.suspended_for().is_some());
+        }
+        assert_eq!(jvm.conn.suspended_for(), None);
+        assert_eq!(jvm.suspension_stats().count, 1);
+        assert_eq!(sent(&log), vec![SUSPEND, RESUME]);
+
+        // Disposing of the connection resumes the target, so the guard doesn't
+        let guard = jvm.suspend_guard().unwrap();
+        jvm.dispose().unwrap();
+        drop(guard);
+        assert_eq!(sent(&log), vec![SUSPEND, RESUME, SUSPEND, 6]);
+    }
+}
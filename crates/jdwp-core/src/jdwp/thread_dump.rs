@@ -0,0 +1,233 @@
+//
+// A thread dump taken all at once. Walking a thread's stack, then asking for its status and its
+// monitors, takes several round trips per thread, and if the VM runs in between the pieces don't
+// agree: a thread shown blocked on a lock its owner has since let go of, or holding a monitor
+// from a frame that has already returned. capture_thread_dump() suspends the whole VM, reads
+// everything, and only then resumes it, so the ThreadDump it returns is one moment.
+//
+// For a VM with thousands of threads that moment can last minutes, nearly all of it spent walking
+// stacks frame by frame. capture_shallow_thread_dump() only reads the top few frames of each
+// thread, a page per thread, and looks the names of each method up once rather than once per
+// frame, so the whole VM is done in seconds. The threads which turn out to be interesting can
+// then have their whole stack read with capture_full_stack(), which only suspends that thread.
+//
+// Both only capture the threads the connection's thread filter (see set_thread_filter()) lets
+// through. A thread left out costs the one round trip for its name, and nothing else.
+//
+// Unlike jstack's, these dumps have nothing about safepoints: whether a thread is at one, how long
+// the VM took to reach one, or time spent in them. JDWP has no command which says, and suspending
+// threads through it isn't a safepoint operation, so there's nothing to read it from. The
+// suspension time the dump does have is how long we kept the VM suspended, not a safepoint pause.
+//
+
+use std::collections::HashMap;
+use std::io::Result;
+use std::time::SystemTime;
+
+use super::monitor::FrameNameCache;
+use super::{error_code, has_error_code, signature_to_name, thread_status};
+use super::{object_reference, reference_type, thread_reference};
+use super::{JdwpJavaVirtualMachine, JdwpThreadReference, Value};
+use crate::model::{JavaVirtualMachine, ObjectReference, ThreadReference};
+use crate::snapshot::{MonitorInfo, ThreadDump, ThreadInfo, ThreadStack, ThreadState};
+use crate::thread_filter::ThreadFilter;
+
+impl JdwpJavaVirtualMachine {
+    // Which threads capture_thread_dump() and the other operations which go through every
+    // thread (stall sampling) look at, from now on
+    pub fn set_thread_filter(&self, filter: ThreadFilter) {
+        *self.conn.thread_filter.borrow_mut() = filter;
+    }
+
+    pub fn thread_filter(&self) -> ThreadFilter {
+        self.conn.thread_filter.borrow().clone()
+    }
+
+    // Suspend the VM, capture every thread's stack, state and monitors, and resume it again,
+    // whether or not the capture worked. A VM which was already suspended stays suspended. The dump
+    // says how long the capture kept the VM suspended.
+    pub fn capture_thread_dump(&self) -> Result<ThreadDump> {
+        self.capture_dump(None)
+    }
+
+    // Like capture_thread_dump(), but with only the top 'depth' frames of each stack (see
+    // ThreadStack::omitted_frames). A depth of 0 still gives every thread's state and monitors.
+    pub fn capture_shallow_thread_dump(&self, depth: usize) -> Result<ThreadDump> {
+        self.capture_dump(Some(depth))
+    }
+
+    // The whole stack of one thread, e.g. one from a shallow dump (see
+    // ThreadDump::replace_stack()). Only the thread is suspended while it's read, and left as it
+    // was afterwards.
+    pub fn capture_full_stack(&self, thread_id: u64) -> Result<ThreadStack> {
+        let conn = self.conn.as_ref();
+        let thread = JdwpThreadReference {
+            conn: self.conn.clone(),
+            thread_id,
+        };
+        thread_reference::suspend(conn, thread_id)?;
+        let stack = ThreadStack::capture::<JdwpJavaVirtualMachine>(&thread);
+        thread_reference::resume(conn, thread_id)?;
+        stack
+    }
+
+    fn capture_dump(&self, depth: Option<usize>) -> Result<ThreadDump> {
+        let has_monitors = self.conn.has_capability(|c| {
+            c.can_get_owned_monitor_info && c.can_get_current_contended_monitor
+        });
+        let suspended = self.suspend_guard()?;
+        let time = SystemTime::now();
+        let threads = self.capture_threads(has_monitors, depth);
+        let suspended_for = suspended.resume()?;
+        Ok(ThreadDump::new(time, threads?, has_monitors, suspended_for))
+    }
+
+    fn capture_threads(&self, has_monitors: bool, depth: Option<usize>) -> Result<Vec<ThreadInfo>> {
+        // Monitors are often shared, e.g. by every thread in a pool waiting on the same queue
+        let mut class_names = HashMap::new();
+        let mut frame_names = FrameNameCache::default();
+        let filter = self.thread_filter();
+        let mut threads = vec![];
+        for thread in self.all_threads() {
+            let thread = thread?;
+            let included = if filter.is_all() {
+                Ok(true)
+            } else {
+                thread.name().map(|name| filter.matches(&name))
+            };
+            let captured = included.and_then(|included| {
+                if !included {
+                    return Ok(None);
+                }
+                self.capture_thread(
+                    &thread,
+                    has_monitors,
+                    depth,
+                    &mut class_names,
+                    &mut frame_names,
+                )
+                .map(Some)
+            });
+            match captured {
+                Ok(Some(info)) => threads.push(info),
+                Ok(None) => {}
+                // Threads which finished before the VM was suspended
+                Err(e) if has_error_code(&e, &[error_code::INVALID_THREAD]) => {}
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(threads)
+    }
+
+    fn capture_thread(
+        &self,
+        thread: &JdwpThreadReference,
+        has_monitors: bool,
+        depth: Option<usize>,
+        class_names: &mut HashMap<u64, String>,
+        frame_names: &mut FrameNameCache,
+    ) -> Result<ThreadInfo> {
+        let conn = self.conn.as_ref();
+        let thread_id = thread.unique_id()?;
+        let status = thread_reference::status(conn, thread_id)?;
+        let state = match status.thread_status {
+            thread_status::ZOMBIE => ThreadState::Zombie,
+            thread_status::SLEEPING => ThreadState::Sleeping,
+            thread_status::MONITOR => ThreadState::Monitor,
+            thread_status::WAIT => ThreadState::Wait,
+            // RUNNING, or something newer than we know of
+            _ => ThreadState::Running,
+        };
+        let suspended = status.suspend_status & thread_status::SUSPEND_STATUS_SUSPENDED != 0;
+
+        // A finished thread has no stack and holds nothing
+        if state == ThreadState::Zombie {
+            return Ok(ThreadInfo {
+                stack: ThreadStack {
+                    thread_id,
+                    name: thread.name()?,
+                    is_virtual: thread.is_virtual()?,
+                    frames: vec![],
+                    omitted_frames: 0,
+                },
+                state,
+                suspended,
+                owned_monitors: vec![],
+                contended_monitor: None,
+            });
+        }
+
+        let stack = match depth {
+            Some(depth) => self.capture_top(thread, depth, frame_names)?,
+            None => ThreadStack::capture::<JdwpJavaVirtualMachine>(thread)?,
+        };
+        let mut owned_monitors = vec![];
+        let mut contended_monitor = None;
+        if has_monitors {
+            for monitor in thread_reference::owned_monitors(conn, thread_id)?.owned {
+                if let Some(monitor) = self.monitor_info(monitor, class_names)? {
+                    owned_monitors.push(monitor);
+                }
+            }
+            let monitor = thread_reference::current_contended_monitor(conn, thread_id)?.monitor;
+            contended_monitor = self.monitor_info(monitor, class_names)?;
+        }
+        Ok(ThreadInfo {
+            stack,
+            state,
+            suspended,
+            owned_monitors,
+            contended_monitor,
+        })
+    }
+
+    // The top 'depth' frames of the thread's stack, fetched in one go
+    fn capture_top(
+        &self,
+        thread: &JdwpThreadReference,
+        depth: usize,
+        frame_names: &mut FrameNameCache,
+    ) -> Result<ThreadStack> {
+        let conn = self.conn.as_ref();
+        let count = thread_reference::frame_count(conn, thread.thread_id)?
+            .frame_count
+            .max(0) as usize;
+        let length = depth.min(count);
+        let mut frames = vec![];
+        if length > 0 {
+            for frame in thread_reference::frames(conn, thread.thread_id, 0, length as i32)?.frames
+            {
+                frames.push(frame_names.frame(conn, &frame.location)?);
+            }
+        }
+        Ok(ThreadStack {
+            thread_id: thread.thread_id,
+            name: thread.name()?,
+            is_virtual: thread.is_virtual()?,
+            omitted_frames: count - frames.len(),
+            frames,
+        })
+    }
+
+    fn monitor_info(
+        &self,
+        monitor: Value,
+        class_names: &mut HashMap<u64, String>,
+    ) -> Result<Option<MonitorInfo>> {
+        let object = match monitor {
+            Value::Object(object) | Value::ClassObject(object) if object != 0 => object,
+            _ => return Ok(None),
+        };
+        let conn = self.conn.as_ref();
+        let type_id = object_reference::reference_type(conn, object)?.type_id;
+        let class_name = match class_names.get(&type_id) {
+            Some(name) => name.clone(),
+            None => {
+                let name = signature_to_name(&reference_type::signature(conn, type_id)?.signature);
+                class_names.insert(type_id, name.clone());
+                name
+            }
+        };
+        Ok(Some(MonitorInfo { object, class_name }))
+    }
+}
@@ -0,0 +1,115 @@
+//
+// Flight Recorder control, as the JFR.start, JFR.dump and JFR.stop diagnostic commands. These
+// only build the commands: they're run either over JDWP (see
+// JdwpJavaVirtualMachine::jfr_start()) or through the attach mechanism (see attach::jfr_start()),
+// so that one session can take stacks and heap data and a recording too.
+//
+// Recordings are written by the target, so file names are paths on the target's machine.
+//
+
+use std::time::Duration;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct JfrSettings {
+    // To refer to the recording by later, in jfr_dump() and jfr_stop()
+    pub name: String,
+    // "default" or "profile" (the .jfc files the JDK comes with), or the path of a .jfc file
+    pub settings: String,
+    // Stop (and write the recording to filename, if given) once this has passed
+    pub duration: Option<Duration>,
+    // Where to write the recording when it stops
+    pub filename: Option<String>,
+    // Limits on how much is kept, oldest first
+    pub max_age: Option<Duration>,
+    pub max_size: Option<u64>,
+}
+
+impl Default for JfrSettings {
+    fn default() -> Self {
+        JfrSettings {
+            name: "libjdb".to_string(),
+            settings: "default".to_string(),
+            duration: None,
+            filename: None,
+            max_age: None,
+            max_size: None,
+        }
+    }
+}
+
+impl JfrSettings {
+    pub fn start_command(&self) -> String {
+        let mut command = format!(
+            "JFR.start name={} settings={}",
+            quote(&self.name),
+            quote(&self.settings)
+        );
+        if let Some(duration) = self.duration {
+            command += &format!(" duration={}s", duration.as_secs().max(1));
+        }
+        if let Some(filename) = &self.filename {
+            command += &format!(" filename={}", quote(filename));
+        }
+        if let Some(max_age) = self.max_age {
+            command += &format!(" maxage={}s", max_age.as_secs().max(1));
+        }
+        if let Some(max_size) = self.max_size {
+            command += &format!(" maxsize={}", max_size);
+        }
+        command
+    }
+}
+
+// Writes what the recording has so far to 'filename', leaving it running
+pub fn dump_command(name: &str, filename: &str) -> String {
+    format!("JFR.dump name={} filename={}", quote(name), quote(filename))
+}
+
+// Stops the recording, writing it to 'filename' if given (or to the filename it was started
+// with, if any)
+pub fn stop_command(name: &str, filename: Option<&str>) -> String {
+    match filename {
+        Some(filename) => format!("JFR.stop name={} filename={}", quote(name), quote(filename)),
+        None => format!("JFR.stop name={}", quote(name)),
+    }
+}
+
+// The diagnostic command parser splits arguments at spaces, except in quotes
+fn quote(value: &str) -> String {
+    if value.contains(char::is_whitespace) {
+        format!("\"{}\"", value)
+    } else {
+        value.to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn commands() {
+        assert_eq!(
+            JfrSettings::default().start_command(),
+            "JFR.start name=libjdb settings=default"
+        );
+        let settings = JfrSettings {
+            name: "leak".to_string(),
+            settings: "profile".to_string(),
+            duration: Some(Duration::from_secs(60)),
+            filename: Some("/tmp/my recording.jfr".to_string()),
+            max_age: None,
+            max_size: Some(1 << 20),
+        };
+        assert_eq!(
+            settings.start_command(),
+            "JFR.start name=leak settings=profile duration=60s \
+             filename=\"/tmp/my recording.jfr\" maxsize=1048576"
+        );
+        assert_eq!(
+            dump_command("leak", "/tmp/a.jfr"),
+            "JFR.dump name=leak filename=/tmp/a.jfr"
+        );
+        assert_eq!(stop_command("leak", None), "JFR.stop name=leak");
+    }
+}
@@ -0,0 +1,38 @@
+//
+// Live JVMs: JDWP, for JVMs started with a debugging agent, and the attach mechanism, for ones on
+// the same machine which weren't.
+//
+
+use std::io::Result;
+use std::net::ToSocketAddrs;
+
+use crate::jdwp::{AttachOptions, JdwpConnection, JdwpJavaVirtualMachine};
+
+#[macro_use]
+extern crate num_derive;
+
+pub mod attach;
+pub mod expr;
+pub mod jdwp;
+pub mod jfr;
+mod smap;
+
+use libjdb_model::{annotation, model, mutf8, names, pattern, report, snapshot, thread_filter};
+
+// TODO get rid of boxing?
+pub fn attach_live<A: ToSocketAddrs>(jvm_debug_addr: A) -> Result<JdwpJavaVirtualMachine> {
+    Ok(JdwpJavaVirtualMachine::new(JdwpConnection::new(
+        jvm_debug_addr,
+    )?))
+}
+
+// Like attach_live(), e.g. to give up sooner on an address nothing answers at
+pub fn attach_live_with_options<A: ToSocketAddrs>(
+    jvm_debug_addr: A,
+    options: &AttachOptions,
+) -> Result<JdwpJavaVirtualMachine> {
+    Ok(JdwpJavaVirtualMachine::new(JdwpConnection::with_options(
+        jvm_debug_addr,
+        options,
+    )?))
+}
@@ -0,0 +1,279 @@
+//
+// Parsing of JSR-45 source maps (SMAP), as found in the SourceDebugExtension
+// attribute of classes compiled from languages other than Java (JSP, Kotlin
+// inline functions, etc.). An SMAP maps lines in one or more input source
+// files onto lines of the generated class, which is what the JVM's line
+// tables refer to.
+//
+// Reference:
+//     https://jcp.org/en/jsr/detail?id=45
+//
+
+#[derive(Debug)]
+struct FileInfo {
+    id: u32,
+    name: String,
+    path: Option<String>,
+}
+
+#[derive(Debug)]
+struct LineInfo {
+    input_start: u32,
+    file_id: u32,
+    repeat_count: u32,
+    output_start: u32,
+    output_increment: u32,
+}
+
+#[derive(Debug)]
+struct Stratum {
+    files: Vec<FileInfo>,
+    lines: Vec<LineInfo>,
+}
+
+#[derive(Debug)]
+pub struct Smap {
+    strata: Vec<Stratum>,
+}
+
+impl Smap {
+    // Returns None if the text isn't a well formed SMAP. We are fairly lenient
+    // about sections we don't understand (vendor sections, embedded SMAPs), and
+    // just skip over them.
+    pub fn parse(text: &str) -> Option<Smap> {
+        let mut lines = text.lines().map(str::trim_end);
+        if lines.next()? != "SMAP" {
+            return None;
+        }
+        // Name of the generated file and the default stratum. Neither is needed to resolve lines.
+        let _output_file = lines.next()?;
+        let _default_stratum = lines.next()?;
+
+        let mut strata: Vec<Stratum> = vec![];
+        let mut section = "";
+        let mut last_file_id = 0;
+        let mut pending_file: Option<(u32, String)> = None;
+        for line in lines {
+            if let Some(header) = line.strip_prefix('*') {
+                section = header.split(' ').next().unwrap_or("");
+                if section == "S" {
+                    strata.push(Stratum {
+                        files: vec![],
+                        lines: vec![],
+                    });
+                } else if section == "E" {
+                    break;
+                }
+                continue;
+            }
+            let stratum = match strata.last_mut() {
+                Some(s) => s,
+                None => continue,
+            };
+            match section {
+                "F" => {
+                    if let Some((id, name)) = pending_file.take() {
+                        // The previous line was '+ id name', so this one is the path.
+                        stratum.files.push(FileInfo {
+                            id,
+                            name,
+                            path: Some(line.to_string()),
+                        });
+                        continue;
+                    }
+                    let (has_path, entry) = match line.strip_prefix('+') {
+                        Some(rest) => (true, rest.trim_start()),
+                        None => (false, line),
+                    };
+                    let (id, name) = entry.split_once(' ')?;
+                    let id = id.parse().ok()?;
+                    let name = name.trim().to_string();
+                    if has_path {
+                        pending_file = Some((id, name));
+                    } else {
+                        stratum.files.push(FileInfo {
+                            id,
+                            name,
+                            path: None,
+                        });
+                    }
+                }
+                "L" => {
+                    let info = parse_line_info(line, last_file_id)?;
+                    last_file_id = info.file_id;
+                    stratum.lines.push(info);
+                }
+                _ => {}
+            }
+        }
+
+        Some(Smap { strata })
+    }
+
+    // All lines of the output (class) file that were generated from the given
+    // line of the given input file, across all strata.
+    pub fn output_lines(&self, source_file: &str, line: u32) -> Vec<u32> {
+        let mut out = vec![];
+        for stratum in &self.strata {
+            let file_ids: Vec<u32> = stratum
+                .files
+                .iter()
+                .filter(|f| file_matches(f, source_file))
+                .map(|f| f.id)
+                .collect();
+            for info in &stratum.lines {
+                if !file_ids.contains(&info.file_id) {
+                    continue;
+                }
+                let offset = match line.checked_sub(info.input_start) {
+                    Some(offset) if offset < info.repeat_count => offset,
+                    _ => continue,
+                };
+                // Entries whose output lines would be past u32::MAX are nonsense, and left out
+                let increment = info.output_increment.max(1);
+                let first = match offset
+                    .checked_mul(info.output_increment)
+                    .and_then(|o| o.checked_add(info.output_start))
+                    .filter(|first| first.checked_add(increment - 1).is_some())
+                {
+                    Some(first) => first,
+                    None => continue,
+                };
+                for n in 0..increment {
+                    if !out.contains(&(first + n)) {
+                        out.push(first + n);
+                    }
+                }
+            }
+        }
+        out
+    }
+}
+
+fn file_matches(file: &FileInfo, source_file: &str) -> bool {
+    let wanted = base_name(source_file);
+    file.name == wanted
+        || file
+            .path
+            .as_deref()
+            .is_some_and(|p| p == source_file || p.ends_with(&format!("/{}", source_file)))
+}
+
+pub fn base_name(path: &str) -> &str {
+    path.rsplit(['/', '\\']).next().unwrap_or(path)
+}
+
+// Line info entries look like 'InputStartLine[#LineFileID][,RepeatCount]:OutputStartLine[,OutputLineIncrement]'.
+// If the file id is omitted, it is the same as the previous entry.
+fn parse_line_info(line: &str, last_file_id: u32) -> Option<LineInfo> {
+    let (input, output) = line.split_once(':')?;
+
+    let (input, repeat_count) = match input.split_once(',') {
+        Some((start, count)) => (start, count.parse().ok()?),
+        None => (input, 1),
+    };
+    let (input_start, file_id) = match input.split_once('#') {
+        Some((start, id)) => (start.parse().ok()?, id.parse().ok()?),
+        None => (input.parse().ok()?, last_file_id),
+    };
+    let (output_start, output_increment) = match output.split_once(',') {
+        Some((start, inc)) => (start.parse().ok()?, inc.parse().ok()?),
+        None => (output.parse().ok()?, 1),
+    };
+
+    Some(LineInfo {
+        input_start,
+        file_id,
+        repeat_count,
+        output_start,
+        output_increment,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Laid out like the example in JSR-45: a generated file with lines from Hi.foo, then from
+    // the file it includes, then from Hi.foo again
+    const EXAMPLE: &str = "SMAP
+Hi.java
+Foo
+*S Foo
+*F
+1 Hi.foo
+2 Incl.foo
+*L
+1#1,5:1,2
+1#2,2:11,2
+7#1,2:15,2
+*E
+";
+
+    #[test]
+    fn example() {
+        let smap = Smap::parse(EXAMPLE).unwrap();
+        assert_eq!(smap.output_lines("Hi.foo", 1), vec![1, 2]);
+        assert_eq!(smap.output_lines("Hi.foo", 3), vec![5, 6]);
+        assert_eq!(smap.output_lines("Hi.foo", 5), vec![9, 10]);
+        assert!(smap.output_lines("Hi.foo", 6).is_empty());
+        assert_eq!(smap.output_lines("Hi.foo", 8), vec![17, 18]);
+        assert_eq!(smap.output_lines("Incl.foo", 2), vec![13, 14]);
+        assert!(smap.output_lines("Incl.foo", 3).is_empty());
+        assert!(smap.output_lines("Other.foo", 1).is_empty());
+    }
+
+    #[test]
+    fn defaults() {
+        // No repeat count means one line, no file ID the previous entry's, and an increment of
+        // zero maps every input line to the same output line
+        let smap = Smap::parse(
+            "SMAP\nMain.java\nKotlin\n*S KotlinDebug\n*F\n1 Main.kt\n2 Inline.kt\n*L\n\
+             3#2:40\n4:50\n10,3:60,0\n*E\n",
+        )
+        .unwrap();
+        assert_eq!(smap.output_lines("Inline.kt", 3), vec![40]);
+        assert!(smap.output_lines("Inline.kt", 5).is_empty());
+        assert_eq!(smap.output_lines("Inline.kt", 4), vec![50]);
+        assert_eq!(smap.output_lines("Inline.kt", 12), vec![60]);
+        assert!(smap.output_lines("Main.kt", 3).is_empty());
+    }
+
+    #[test]
+    fn file_paths() {
+        let smap = Smap::parse(
+            "SMAP\nMain.java\nKotlin\n*S Kotlin\n*F\n+ 1 Main.kt\ncom/example/Main.kt\n\
+             *L\n1#1,2:7\n*E\n",
+        )
+        .unwrap();
+        assert_eq!(smap.output_lines("Main.kt", 2), vec![8]);
+        assert_eq!(smap.output_lines("com/example/Main.kt", 2), vec![8]);
+        assert_eq!(smap.output_lines("src/main/kotlin/Main.kt", 1), vec![7]);
+    }
+
+    #[test]
+    fn malformed() {
+        for text in [
+            "",
+            "NOT AN SMAP\nA.java\nJava\n",
+            "SMAP\nA.java\n",
+            "SMAP\nA.java\nJava\n*S Java\n*F\nA.java\n",
+            "SMAP\nA.java\nJava\n*S Java\n*F\n1 A.java\n*L\n1#x:5\n",
+            "SMAP\nA.java\nJava\n*S Java\n*F\n1 A.java\n*L\n1,-2:5\n",
+            "SMAP\nA.java\nJava\n*S Java\n*F\n1 A.java\n*L\n1\n",
+        ] {
+            assert!(Smap::parse(text).is_none(), "{:?}", text);
+        }
+
+        // Entries whose lines don't fit are left out, rather than overflowing
+        let smap = Smap::parse(
+            "SMAP\nA.java\nJava\n*S Java\n*F\n1 A.java\n*L\n1#1,3:4294967295\n\
+             4294967290,10:1\n1,2:4000000000,2000000000\n20:9\n*E\n",
+        )
+        .unwrap();
+        assert_eq!(smap.output_lines("A.java", 1), vec![4294967295]);
+        assert!(smap.output_lines("A.java", 2).is_empty());
+        assert_eq!(smap.output_lines("A.java", 4294967295), vec![6]);
+        assert_eq!(smap.output_lines("A.java", 20), vec![9]);
+    }
+}
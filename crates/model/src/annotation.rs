@@ -0,0 +1,113 @@
+//
+// Labels the user has given objects and threads ("leaked-cache", "stuck-worker-17"), which reports
+// and renderers show next to them, so that the interesting ones stand out in a long thread dump or
+// object listing.
+//
+// Objects are labelled by ID, which only means something within one heap dump or one JDWP
+// connection. Threads are labelled by name, which survives reconnecting and restarts, so thread
+// labels are what's worth keeping from one investigation to the next.
+//
+// Annotations also carry the session's source links (see source_link.rs), since they go
+// everywhere a report does: each frame of a stack is followed by its link, if it has one.
+//
+
+use std::collections::BTreeMap;
+
+use crate::snapshot::FrameInfo;
+use crate::source_link::SourceLinks;
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Annotations {
+    objects: BTreeMap<u64, String>,
+    threads: BTreeMap<String, String>,
+    source_links: Option<SourceLinks>,
+}
+
+impl Annotations {
+    pub fn new() -> Annotations {
+        Annotations::default()
+    }
+
+    pub fn label_object(&mut self, object: u64, label: &str) {
+        self.objects.insert(object, label.to_string());
+    }
+
+    pub fn unlabel_object(&mut self, object: u64) {
+        self.objects.remove(&object);
+    }
+
+    pub fn object_label(&self, object: u64) -> Option<&str> {
+        self.objects.get(&object).map(String::as_str)
+    }
+
+    pub fn objects(&self) -> impl Iterator<Item = (u64, &str)> {
+        self.objects.iter().map(|(id, label)| (*id, label.as_str()))
+    }
+
+    // Forget the object labels, e.g. because the IDs they were given by belong to a connection
+    // which has gone
+    pub fn clear_objects(&mut self) {
+        self.objects.clear();
+    }
+
+    pub fn label_thread(&mut self, thread_name: &str, label: &str) {
+        self.threads
+            .insert(thread_name.to_string(), label.to_string());
+    }
+
+    pub fn unlabel_thread(&mut self, thread_name: &str) {
+        self.threads.remove(thread_name);
+    }
+
+    pub fn thread_label(&self, thread_name: &str) -> Option<&str> {
+        self.threads.get(thread_name).map(String::as_str)
+    }
+
+    pub fn threads(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.threads
+            .iter()
+            .map(|(name, label)| (name.as_str(), label.as_str()))
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.objects.is_empty() && self.threads.is_empty()
+    }
+
+    // What to write after an object in a report: " [label]", or nothing if it has no label
+    pub fn object_suffix(&self, object: u64) -> String {
+        suffix(self.object_label(object))
+    }
+
+    // Likewise for a thread
+    pub fn thread_suffix(&self, thread_name: &str) -> String {
+        suffix(self.thread_label(thread_name))
+    }
+
+    // None to stop linking frames
+    pub fn set_source_links(&mut self, links: Option<SourceLinks>) {
+        self.source_links = links;
+    }
+
+    pub fn source_links(&self) -> Option<&SourceLinks> {
+        self.source_links.as_ref()
+    }
+
+    pub fn frame_link(&self, frame: &FrameInfo) -> Option<String> {
+        self.source_links.as_ref()?.link(frame)
+    }
+
+    // What to write after a frame: " " and its link, or nothing if it has none
+    pub fn frame_suffix(&self, frame: &FrameInfo) -> String {
+        match self.frame_link(frame) {
+            Some(link) => format!(" {}", link),
+            None => String::new(),
+        }
+    }
+}
+
+fn suffix(label: Option<&str>) -> String {
+    match label {
+        Some(label) => format!(" [{}]", label),
+        None => String::new(),
+    }
+}
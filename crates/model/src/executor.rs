@@ -0,0 +1,447 @@
+//
+// Thread pools: how each ThreadPoolExecutor and ForkJoinPool (and each instance of a subclass, such
+// as ScheduledThreadPoolExecutor) is sized, what its workers are up to, and what's waiting in its
+// queue. This works on any backend, but live targets should be suspended first, or the queues
+// will change while they're being read.
+//
+// Everything comes from the pools' private fields, which differ between JDK versions. What a
+// particular JDK doesn't have is left as None, rather than being an error.
+//
+
+use std::collections::HashMap;
+use std::io::Result;
+
+use crate::model::{Field, JavaVirtualMachine, ObjectReference, ReferenceType};
+use crate::model::{ThreadReference, TypeComponent, Value};
+
+const THREAD_POOL_EXECUTOR: &str = "java.util.concurrent.ThreadPoolExecutor";
+const FORK_JOIN_POOL: &str = "java.util.concurrent.ForkJoinPool";
+
+// Following linked queues stops here, so that a corrupt (or changing) list can't keep us going
+const MAX_LINKED_NODES: u64 = 1_000_000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExecutorKind {
+    ThreadPool,
+    ForkJoin,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PoolWorker {
+    pub thread: Option<u64>,
+    pub thread_name: Option<String>,
+    // Whether it's running a task. Only known for ThreadPoolExecutors.
+    pub busy: Option<bool>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct QueuedTask {
+    pub task: u64,
+    pub class_name: String,
+    // What a FutureTask (as submit() wraps tasks in), or the adapter a ForkJoinPool wraps plain
+    // Runnables and Callables in, runs
+    pub wrapped_class_name: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ExecutorInfo {
+    pub executor: u64,
+    pub class_name: String,
+    pub kind: ExecutorKind,
+    // corePoolSize, or a ForkJoinPool's parallelism
+    pub core_pool_size: Option<i64>,
+    pub max_pool_size: Option<i64>,
+    pub shutdown: Option<bool>,
+    pub workers: Vec<PoolWorker>,
+    // The number of workers running tasks
+    pub active_workers: Option<u64>,
+    pub completed_tasks: Option<i64>,
+    // ThreadPoolExecutors only, as a ForkJoinPool has a queue per worker
+    pub queue_class: Option<String>,
+    // None if the kind of queue isn't one we know how to read
+    pub queue_length: Option<u64>,
+    // The first tasks in the queue, next to run first as far as the queue's order goes
+    pub queued: Vec<QueuedTask>,
+}
+
+// Every thread pool, longest queue first, with up to 'sample_size' of each one's queued tasks
+pub fn executors<Jvm: JavaVirtualMachine>(
+    jvm: &Jvm,
+    sample_size: usize,
+) -> Result<Vec<ExecutorInfo>> {
+    let thread_names: HashMap<u64, String> = jvm
+        .all_threads()
+        .map(|thread| {
+            let thread = thread?;
+            Ok((thread.unique_id()?, thread.name()?))
+        })
+        .collect::<Result<_>>()?;
+    let mut heap = Heap {
+        jvm,
+        fields: HashMap::new(),
+        kinds: HashMap::new(),
+        thread_names,
+    };
+
+    let mut executors = vec![];
+    for entry in jvm.class_histogram()?.entries {
+        if entry.class_name.ends_with("[]") {
+            continue;
+        }
+        for class in jvm.classes_by_name(&entry.class_name)? {
+            let kind = match heap.pool_kind(&class)? {
+                Some(kind) => kind,
+                None => continue,
+            };
+            for executor in class.instances(0) {
+                let executor = executor?;
+                executors.push(match kind {
+                    ExecutorKind::ThreadPool => heap.thread_pool(executor, sample_size)?,
+                    ExecutorKind::ForkJoin => heap.fork_join_pool(executor, sample_size)?,
+                });
+            }
+        }
+    }
+    executors.sort_by(|a, b| (b.queue_length, a.executor).cmp(&(a.queue_length, b.executor)));
+    Ok(executors)
+}
+
+struct Heap<'a, Jvm: JavaVirtualMachine> {
+    jvm: &'a Jvm,
+    // Each class's instance fields, by class name, subclass's first
+    fields: HashMap<String, Vec<(String, Jvm::Field)>>,
+    // Which classes are pools, by name
+    kinds: HashMap<String, Option<ExecutorKind>>,
+    thread_names: HashMap<u64, String>,
+}
+
+impl<'a, Jvm: JavaVirtualMachine> Heap<'a, Jvm> {
+    fn pool_kind(&mut self, class: &Jvm::ReferenceType) -> Result<Option<ExecutorKind>> {
+        let name = class.name()?;
+        if let Some(kind) = self.kinds.get(&name) {
+            return Ok(*kind);
+        }
+        let kind = match name.as_str() {
+            THREAD_POOL_EXECUTOR => Some(ExecutorKind::ThreadPool),
+            FORK_JOIN_POOL => Some(ExecutorKind::ForkJoin),
+            _ => match class.superclass()? {
+                Some(superclass) => self.pool_kind(&superclass)?,
+                None => None,
+            },
+        };
+        self.kinds.insert(name, kind);
+        Ok(kind)
+    }
+
+    fn thread_pool(&mut self, executor: u64, sample_size: usize) -> Result<ExecutorInfo> {
+        // The run state is in the top 3 bits of ctl, and is negative only while running
+        let ctl = match self.object(executor, "ctl")? {
+            Some(ctl) => self.int(ctl, "value")?,
+            None => None,
+        };
+        let mut workers = vec![];
+        if let Some(set) = self.object(executor, "workers")? {
+            for worker in self.hash_set(set)? {
+                let thread = self.object(worker, "thread")?;
+                workers.push(PoolWorker {
+                    thread,
+                    thread_name: thread.and_then(|t| self.thread_names.get(&t).cloned()),
+                    // Workers hold their own lock while they run a task
+                    busy: self.int(worker, "state")?.map(|state| state == 1),
+                });
+            }
+        }
+        let queue = self.object(executor, "workQueue")?;
+        let (queue_length, queued) = match queue {
+            Some(queue) => self.queue(queue, sample_size)?,
+            None => (None, vec![]),
+        };
+        Ok(ExecutorInfo {
+            executor,
+            class_name: self.class_name(executor)?,
+            kind: ExecutorKind::ThreadPool,
+            core_pool_size: self.int(executor, "corePoolSize")?,
+            max_pool_size: self.int(executor, "maximumPoolSize")?,
+            shutdown: ctl.map(|ctl| ctl >= 0),
+            active_workers: Some(workers.iter().filter(|w| w.busy == Some(true)).count() as u64),
+            workers,
+            completed_tasks: self.int(executor, "completedTaskCount")?,
+            queue_class: match queue {
+                Some(queue) => Some(self.class_name(queue)?),
+                None => None,
+            },
+            queue_length,
+            queued,
+        })
+    }
+
+    fn fork_join_pool(&mut self, executor: u64, sample_size: usize) -> Result<ExecutorInfo> {
+        // JDK 19 and later have a field of its own. Before that it's in the low 16 bits of mode
+        // (or of config, in JDK 8).
+        let parallelism = match self.int(executor, "parallelism")? {
+            Some(parallelism) => Some(parallelism),
+            None => match self.int(executor, "mode")? {
+                Some(mode) => Some(mode & 0xffff),
+                None => self.int(executor, "config")?.map(|config| config & 0xffff),
+            },
+        };
+        // The top 16 bits of ctl are the number of active workers less the parallelism
+        let active_workers = match (parallelism, self.int(executor, "ctl")?) {
+            (Some(parallelism), Some(ctl)) => {
+                Some((parallelism + i64::from((ctl >> 48) as i16)).max(0) as u64)
+            }
+            _ => None,
+        };
+
+        let queues = match self.object(executor, "queues")? {
+            Some(queues) => Some(queues),
+            None => self.object(executor, "workQueues")?,
+        };
+        let mut workers = vec![];
+        let mut queue_length = 0;
+        let mut queued = vec![];
+        let queues = match queues {
+            Some(queues) => self.jvm.array_values(queues, 0, usize::MAX)?,
+            None => vec![],
+        };
+        for queue in queues {
+            let queue = match queue {
+                Value::Object(queue) => queue,
+                _ => continue,
+            };
+            if let Some(thread) = self.object(queue, "owner")? {
+                workers.push(PoolWorker {
+                    thread: Some(thread),
+                    thread_name: self.thread_names.get(&thread).cloned(),
+                    busy: None,
+                });
+            }
+            let (base, top) = match (self.int(queue, "base")?, self.int(queue, "top")?) {
+                (Some(base), Some(top)) if top > base => (base, top),
+                _ => continue,
+            };
+            queue_length += (top - base) as u64;
+            // The array is a ring buffer whose length is a power of two
+            if let Some(array) = self.object(queue, "array")? {
+                let tasks = self.jvm.array_values(array, 0, usize::MAX)?;
+                for i in base..top {
+                    if queued.len() >= sample_size || tasks.is_empty() {
+                        break;
+                    }
+                    if let Value::Object(task) = tasks[(i as usize) & (tasks.len() - 1)] {
+                        queued.push(self.task(task)?);
+                    }
+                }
+            }
+        }
+        Ok(ExecutorInfo {
+            executor,
+            class_name: self.class_name(executor)?,
+            kind: ExecutorKind::ForkJoin,
+            core_pool_size: parallelism,
+            max_pool_size: None,
+            shutdown: None,
+            workers,
+            active_workers,
+            completed_tasks: None,
+            queue_class: None,
+            queue_length: Some(queue_length),
+            queued,
+        })
+    }
+
+    // The length and first tasks of the kinds of queue the JDK has
+    fn queue(&mut self, queue: u64, sample_size: usize) -> Result<(Option<u64>, Vec<QueuedTask>)> {
+        let class_name = self.class_name(queue)?;
+        let short_name = class_name.rsplit('.').next().unwrap_or(&class_name);
+        let items = match short_name {
+            "SynchronousQueue" => return Ok((Some(0), vec![])),
+            "LinkedBlockingQueue" => {
+                // head is a dummy node, whose item is always null
+                let head = self.object(queue, "head")?;
+                let next = match head {
+                    Some(head) => self.object(head, "next")?,
+                    None => None,
+                };
+                let count = match self.object(queue, "count")? {
+                    Some(count) => self.int(count, "value")?,
+                    None => None,
+                };
+                let (_, items) = self.linked_items(next, sample_size)?;
+                (count.map(|c| c as u64), items)
+            }
+            "LinkedBlockingDeque" => {
+                let first = self.object(queue, "first")?;
+                let (_, items) = self.linked_items(first, sample_size)?;
+                (self.int(queue, "count")?.map(|c| c as u64), items)
+            }
+            "LinkedTransferQueue" => {
+                // No count is kept, and consumers waiting for items are nodes too
+                let head = self.object(queue, "head")?;
+                let (length, items) = self.linked_items(head, sample_size)?;
+                (Some(length), items)
+            }
+            "ArrayBlockingQueue" => {
+                let count = self.int(queue, "count")?.unwrap_or(0).max(0) as usize;
+                let take_index = self.int(queue, "takeIndex")?.unwrap_or(0).max(0) as usize;
+                let mut items = vec![];
+                if let Some(array) = self.object(queue, "items")? {
+                    let array = self.jvm.array_values(array, 0, usize::MAX)?;
+                    for i in 0..count.min(sample_size).min(array.len()) {
+                        items.push(array[(take_index + i) % array.len()].clone());
+                    }
+                }
+                (Some(count as u64), items)
+            }
+            // These are heaps, so only the first is necessarily next
+            "PriorityBlockingQueue" | "ScheduledThreadPoolExecutor$DelayedWorkQueue" => {
+                self.array_queue(queue, sample_size)?
+            }
+            "DelayQueue" => match self.object(queue, "q")? {
+                Some(q) => self.array_queue(q, sample_size)?,
+                None => (None, vec![]),
+            },
+            _ => (None, vec![]),
+        };
+        let (length, items) = items;
+        let mut tasks = vec![];
+        for item in items {
+            if let Value::Object(task) = item {
+                tasks.push(self.task(task)?);
+            }
+        }
+        Ok((length, tasks))
+    }
+
+    // Queues keeping their items in 'queue', with 'size' of them
+    fn array_queue(&mut self, queue: u64, sample_size: usize) -> Result<(Option<u64>, Vec<Value>)> {
+        let size = self.int(queue, "size")?.map(|s| s.max(0) as u64);
+        let items = match (self.object(queue, "queue")?, size) {
+            (Some(array), Some(size)) => {
+                self.jvm
+                    .array_values(array, 0, sample_size.min(size as usize))?
+            }
+            _ => vec![],
+        };
+        Ok((size, items))
+    }
+
+    // The number of non-null items from 'node' on, and the first of them
+    fn linked_items(
+        &mut self,
+        mut node: Option<u64>,
+        sample_size: usize,
+    ) -> Result<(u64, Vec<Value>)> {
+        let mut count = 0;
+        let mut items = vec![];
+        while let Some(n) = node {
+            if count >= MAX_LINKED_NODES {
+                break;
+            }
+            if let Some(item) = self.field(n, "item")? {
+                if item != Value::Null {
+                    count += 1;
+                    if items.len() < sample_size {
+                        items.push(item);
+                    }
+                }
+            }
+            node = match self.object(n, "next")? {
+                // A node which has been taken points to itself
+                Some(next) if next == n => None,
+                next => next,
+            };
+        }
+        Ok((count, items))
+    }
+
+    fn task(&mut self, task: u64) -> Result<QueuedTask> {
+        let wrapped = match self.object(task, "callable")? {
+            // Runnables passed to submit() are adapted into Callables
+            Some(callable) => match self.object(callable, "task")? {
+                Some(runnable) => Some(runnable),
+                None => Some(callable),
+            },
+            None => self.object(task, "runnable")?,
+        };
+        Ok(QueuedTask {
+            task,
+            class_name: self.class_name(task)?,
+            wrapped_class_name: match wrapped {
+                Some(wrapped) => Some(self.class_name(wrapped)?),
+                None => None,
+            },
+        })
+    }
+
+    // The elements of a HashSet, which are the keys of its HashMap
+    fn hash_set(&mut self, set: u64) -> Result<Vec<u64>> {
+        let mut elements = vec![];
+        let table = match self.object(set, "map")? {
+            Some(map) => self.object(map, "table")?,
+            None => None,
+        };
+        let table = match table {
+            Some(table) => self.jvm.array_values(table, 0, usize::MAX)?,
+            None => return Ok(elements),
+        };
+        for bin in table {
+            let mut node = match bin {
+                Value::Object(node) => Some(node),
+                _ => None,
+            };
+            // Big bins are trees, but their nodes are still chained by next
+            while let Some(n) = node {
+                if let Some(key) = self.object(n, "key")? {
+                    elements.push(key);
+                }
+                node = self.object(n, "next")?;
+            }
+        }
+        Ok(elements)
+    }
+
+    fn class_name(&self, object: u64) -> Result<String> {
+        self.jvm.object(object)?.reference_type()?.name()
+    }
+
+    // The value of the instance field with the given name, or None if the object has no such
+    // field
+    fn field(&mut self, object: u64, name: &str) -> Result<Option<Value>> {
+        let object = self.jvm.object(object)?;
+        let class = object.reference_type()?;
+        let class_name = class.name()?;
+        if !self.fields.contains_key(&class_name) {
+            let mut fields = vec![];
+            for declared in class.all_fields()? {
+                if !declared.field.modifiers()?.is_static() {
+                    fields.push((declared.field.name()?, declared.field));
+                }
+            }
+            self.fields.insert(class_name.clone(), fields);
+        }
+        match self.fields[&class_name].iter().find(|(n, _)| n == name) {
+            Some((_, field)) => Ok(Some(object.get_value(field)?)),
+            None => Ok(None),
+        }
+    }
+
+    // None for a missing field or a null
+    fn object(&mut self, object: u64, name: &str) -> Result<Option<u64>> {
+        Ok(match self.field(object, name)? {
+            Some(Value::Object(id)) => Some(id),
+            _ => None,
+        })
+    }
+
+    // Any integral field
+    fn int(&mut self, object: u64, name: &str) -> Result<Option<i64>> {
+        Ok(match self.field(object, name)? {
+            Some(Value::Byte(b)) => Some(i64::from(b)),
+            Some(Value::Short(s)) => Some(i64::from(s)),
+            Some(Value::Integer(i)) => Some(i64::from(i)),
+            Some(Value::Long(l)) => Some(l),
+            _ => None,
+        })
+    }
+}
@@ -0,0 +1,18 @@
+//
+// The parts of libjdb which don't depend on where the data comes from: the JavaVirtualMachine
+// model which each backend implements, the plain data snapshots captured through it, type names,
+// the JVM's modified UTF-8, class patterns, thread name filters, the user's labels for objects
+// and threads, links from frames to source code, thread pool introspection, and text reports of
+// those snapshots.
+//
+
+pub mod annotation;
+pub mod executor;
+pub mod model;
+pub mod mutf8;
+pub mod names;
+pub mod pattern;
+pub mod report;
+pub mod snapshot;
+pub mod source_link;
+pub mod thread_filter;
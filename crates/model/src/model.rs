@@ -0,0 +1,334 @@
+use crate::pattern::ClassPattern;
+use crate::snapshot::Histogram;
+use std::fmt;
+use std::io::Result;
+
+// What the bulk methods (all_threads(), all_classes(), frames(), fields() and instances()) return.
+// Backends fetch items as they're asked for where they can, so a caller which only wants the
+// first few doesn't pay for the rest, which is also why each item can fail on its own. size_hint()
+// is exact whenever the backend knows how many there are. Each of these methods has a _vec()
+// counterpart for callers who want them all anyway.
+pub type Items<'a, T> = Box<dyn Iterator<Item = Result<T>> + 'a>;
+
+pub trait JavaVirtualMachine
+where
+    Self::Field: Field,
+    Self::Location: Location<Self>,
+    Self::Method: Method<Self>,
+    Self::ObjectReference: ObjectReference<Self>,
+    Self::ReferenceType: ReferenceType<Self>,
+    Self::StackFrame: StackFrame<Self>,
+    Self::ThreadReference: ThreadReference<Self>,
+{
+    type Field;
+    type Location;
+    type Method;
+    type ObjectReference;
+    type ReferenceType;
+    type StackFrame;
+    type ThreadReference;
+
+    // Should this take mut self or should we rely on interior mutability?
+    // TODO we need to limit lifetime of ThreadReference to prevent tcp connection from leaking
+    //   important b/c JVM only allows one connection at a time, and we don't want to let people
+    //   accidentally leave the connection open. All structs that could have been created by calling
+    //   methods on this JavaVirtualMachine (and all the struct that could have been created by those
+    //   struct, etc.) need to be dropped when this is dropped.
+    //
+    // Actually, this isn't good enough
+    fn all_threads(&self) -> Items<'_, Self::ThreadReference>;
+
+    fn all_threads_vec(&self) -> Result<Vec<Self::ThreadReference>> {
+        self.all_threads().collect()
+    }
+
+    fn can_be_modified(&self) -> bool;
+
+    // TODO what should happen if you try to suspend an hprof? Should it succeed or should you get
+    // an error?
+    fn suspend(&self) -> Result<()>;
+    fn resume(&self) -> Result<()>;
+
+    // All loaded classes with the given name (e.g. java.util.HashMap). There can be more than one
+    // if several class loaders have loaded a class with that name.
+    fn classes_by_name(&self, name: &str) -> Result<Vec<Self::ReferenceType>>;
+
+    // Every loaded class, interface and array type
+    fn all_classes(&self) -> Items<'_, Self::ReferenceType>;
+
+    fn all_classes_vec(&self) -> Result<Vec<Self::ReferenceType>> {
+        self.all_classes().collect()
+    }
+
+    // The object a Value::Object refers to, e.g. to read its fields
+    fn object(&self, id: u64) -> Result<Self::ObjectReference>;
+
+    // Elements first to first + count - 1 of an array, or as many of them as there are. An error
+    // if the object isn't an array.
+    fn array_values(&self, array: u64, first: usize, count: usize) -> Result<Vec<Value>>;
+
+    // Number of instances (and their size, if known) of each class
+    fn class_histogram(&self) -> Result<Histogram>;
+
+    // Resolve a line of a source file, as the user sees it, into the code locations generated for
+    // it, e.g. to set a breakpoint on. Only classes whose names match class_pattern are searched.
+    // A line may map to several locations (e.g. several methods of an inner class, or code inlined
+    // from a different file). An empty result means no loaded class has code at that line.
+    fn locations_of_line(
+        &self,
+        class_pattern: &ClassPattern,
+        source_file: &str,
+        line: u32,
+    ) -> Result<Vec<Self::Location>>;
+}
+
+// TODO understand why ?Sized is needed here
+pub trait ObjectReference<Jvm: JavaVirtualMachine + ?Sized> {
+    // TODO delete me? Not sure what the correct thing to return here is
+    fn unique_id(&self) -> Result<u64>;
+    fn reference_type(&self) -> Result<Box<dyn ReferenceType<Jvm>>>;
+    // The value of an instance field, which may be declared by a superclass of this object's class
+    fn get_value(&self, field: &Jvm::Field) -> Result<Value>;
+    // If this is a java.lang.Class object, the type it stands for. None for any other object, and
+    // for the classes of primitive types (e.g. int.class).
+    fn reflected_type(&self) -> Result<Option<Jvm::ReferenceType>>;
+}
+
+pub trait ThreadReference<Jvm: JavaVirtualMachine + ?Sized>: ObjectReference<Jvm> {
+    fn name(&self) -> Result<String>;
+    // Innermost first
+    fn frames(&self) -> Items<'_, Jvm::StackFrame>;
+    fn frames_vec(&self) -> Result<Vec<Jvm::StackFrame>> {
+        self.frames().collect()
+    }
+    // Whether this is a virtual thread (Java 21 and later) rather than a platform thread
+    fn is_virtual(&self) -> Result<bool>;
+}
+
+pub trait StackFrame<Jvm: JavaVirtualMachine + ?Sized> {
+    fn location(&self) -> Result<Jvm::Location>;
+}
+
+pub trait Location<Jvm: JavaVirtualMachine + ?Sized> {
+    fn line_number(&self) -> Result<Option<u32>>;
+    fn method(&self) -> Result<Jvm::Method>;
+    fn declaring_type(&self) -> Result<Jvm::ReferenceType>;
+}
+
+pub trait ReferenceType<Jvm: JavaVirtualMachine + ?Sized> {
+    fn name(&self) -> Result<String>;
+    // The name of the source file this type was compiled from (without any directories), if known
+    fn source_name(&self) -> Result<Option<String>>;
+    // Only the fields declared by this type itself, see all_fields()
+    fn fields(&self) -> Items<'_, Jvm::Field>;
+    fn fields_vec(&self) -> Result<Vec<Jvm::Field>> {
+        self.fields().collect()
+    }
+    // The fields of this class and all of its superclasses, this class's first
+    fn all_fields(&self) -> Result<Vec<DeclaredField<Jvm>>>;
+    // None for java.lang.Object and interfaces
+    fn superclass(&self) -> Result<Option<Jvm::ReferenceType>>;
+    // Whether this is a record class, which is to say it extends java.lang.Record
+    fn is_record(&self) -> Result<bool> {
+        match self.superclass()? {
+            Some(superclass) => Ok(superclass.name()? == "java.lang.Record"),
+            None => Ok(false),
+        }
+    }
+    // Whether this is an enum, which is to say it extends java.lang.Enum. As with Class.isEnum(),
+    // the classes of constants with bodies of their own aren't enums themselves.
+    fn is_enum(&self) -> Result<bool> {
+        match self.superclass()? {
+            Some(superclass) => Ok(superclass.name()? == "java.lang.Enum"),
+            None => Ok(false),
+        }
+    }
+    // The names of a record's components, in order, or nothing for other types. A record can't
+    // declare instance fields of its own, so its components are the ones it has.
+    fn record_components(&self) -> Result<Vec<String>> {
+        if !self.is_record()? {
+            return Ok(vec![]);
+        }
+        let mut components = vec![];
+        for field in self.fields() {
+            let field = field?;
+            if !field.modifiers()?.is_static() {
+                components.push(field.name()?);
+            }
+        }
+        Ok(components)
+    }
+    // The classes (and interfaces) a sealed type permits to extend it, in the source form. None
+    // if it isn't sealed, or the backend can't tell.
+    fn permitted_subclasses(&self) -> Result<Option<Vec<String>>> {
+        Ok(None)
+    }
+    // The IDs of up to max instances of exactly this type (not of subclasses), or of all of them
+    // if max is 0
+    fn instances(&self, max: usize) -> Items<'_, u64>;
+    fn instances_vec(&self, max: usize) -> Result<Vec<u64>> {
+        self.instances(max).collect()
+    }
+    // The value of a static field
+    fn get_value(&self, field: &Jvm::Field) -> Result<Value>;
+}
+
+pub trait TypeComponent {
+    fn name(&self) -> Result<String>;
+}
+
+pub trait Method<Jvm: JavaVirtualMachine + ?Sized>: TypeComponent {}
+
+pub trait Field: TypeComponent {
+    fn modifiers(&self) -> Result<Modifiers>;
+}
+
+// A field along with the type which declares it, for telling inherited fields apart
+pub struct DeclaredField<Jvm: JavaVirtualMachine + ?Sized> {
+    pub declaring_type: Jvm::ReferenceType,
+    pub field: Jvm::Field,
+}
+
+// Access flags, as in class files (see the JVM spec, section 4.5). Heap dumps only record whether
+// a field is static.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Modifiers(pub u32);
+
+impl Modifiers {
+    pub const PUBLIC: u32 = 0x0001;
+    pub const PRIVATE: u32 = 0x0002;
+    pub const PROTECTED: u32 = 0x0004;
+    pub const STATIC: u32 = 0x0008;
+    pub const FINAL: u32 = 0x0010;
+    pub const VOLATILE: u32 = 0x0040;
+    pub const TRANSIENT: u32 = 0x0080;
+    pub const SYNTHETIC: u32 = 0x1000;
+
+    pub fn contains(self, flag: u32) -> bool {
+        self.0 & flag != 0
+    }
+
+    pub fn is_static(self) -> bool {
+        self.contains(Self::STATIC)
+    }
+
+    // e.g. "private static final", in the order javac would want them
+    pub fn keywords(self) -> String {
+        let names = [
+            (Self::PUBLIC, "public"),
+            (Self::PROTECTED, "protected"),
+            (Self::PRIVATE, "private"),
+            (Self::STATIC, "static"),
+            (Self::FINAL, "final"),
+            (Self::TRANSIENT, "transient"),
+            (Self::VOLATILE, "volatile"),
+        ];
+        names
+            .iter()
+            .filter(|&&(flag, _)| self.contains(flag))
+            .map(|&(_, name)| name)
+            .collect::<Vec<_>>()
+            .join(" ")
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    Boolean(bool),
+    Byte(i8),
+    Char(u16), // A UTF-16 code unit, which isn't necessarily a valid char on its own
+    Short(i16),
+    Integer(i32),
+    Long(i64),
+    Float(f32),
+    Double(f64),
+    Object(u64), // See JavaVirtualMachine::object() for a handle to the object itself
+    // A java.lang.Class. JDWP tags these, and heap dumps give class objects the IDs of the classes
+    // they stand for. See ObjectReference::reflected_type() for the class.
+    ClassObject(u64),
+    Null,
+    Void, // Only used for return values of void methods
+}
+
+impl Value {
+    // None for anything but a char, and for chars which are half of a surrogate pair
+    pub fn as_char(&self) -> Option<char> {
+        match *self {
+            Value::Char(c) => char::from_u32(u32::from(c)),
+            _ => None,
+        }
+    }
+
+    // The object it refers to, whatever kind of object it is
+    pub fn object_id(&self) -> Option<u64> {
+        match *self {
+            Value::Object(id) | Value::ClassObject(id) => Some(id),
+            _ => None,
+        }
+    }
+}
+
+// As Java would print the value, e.g. 1.0 rather than 1 for floats and doubles. Chars are quoted,
+// with unpaired surrogates escaped. Objects can only be shown by ID.
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Value::Boolean(b) => write!(f, "{}", b),
+            Value::Byte(b) => write!(f, "{}", b),
+            Value::Char(c) => match self.as_char() {
+                Some(ch) => write!(f, "'{}'", ch),
+                None => write!(f, "'\\u{:04x}'", c),
+            },
+            Value::Short(s) => write!(f, "{}", s),
+            Value::Integer(i) => write!(f, "{}", i),
+            Value::Long(l) => write!(f, "{}", l),
+            Value::Float(x) => write_floating(f, f64::from(x), &x.to_string()),
+            Value::Double(x) => write_floating(f, x, &x.to_string()),
+            Value::Object(id) | Value::ClassObject(id) => write!(f, "@{:x}", id),
+            Value::Null => write!(f, "null"),
+            Value::Void => write!(f, "void"),
+        }
+    }
+}
+
+// 'shortest' is Rust's shortest round-tripping form of the value, which is what Java prints too,
+// apart from the special values and whole numbers
+fn write_floating(f: &mut fmt::Formatter, x: f64, shortest: &str) -> fmt::Result {
+    if x.is_nan() {
+        write!(f, "NaN")
+    } else if x.is_infinite() {
+        write!(f, "{}Infinity", if x < 0.0 { "-" } else { "" })
+    } else if shortest.contains('.') || shortest.contains('e') {
+        write!(f, "{}", shortest)
+    } else {
+        write!(f, "{}.0", shortest)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Value;
+
+    #[test]
+    fn display() {
+        assert_eq!(Value::Float(1.0).to_string(), "1.0");
+        assert_eq!(Value::Float(0.1).to_string(), "0.1");
+        assert_eq!(Value::Double(-2.5).to_string(), "-2.5");
+        assert_eq!(Value::Double(f64::NEG_INFINITY).to_string(), "-Infinity");
+        assert_eq!(Value::Float(f32::NAN).to_string(), "NaN");
+        assert_eq!(Value::Char(0x41).to_string(), "'A'");
+        assert_eq!(Value::Char(0xd800).to_string(), "'\\ud800'");
+        assert_eq!(Value::Char(0xd800).as_char(), None);
+        assert_eq!(Value::Boolean(true).to_string(), "true");
+        assert_eq!(Value::Null.to_string(), "null");
+        assert_eq!(Value::ClassObject(0x2a).to_string(), "@2a");
+    }
+
+    #[test]
+    fn object_id() {
+        assert_eq!(Value::Object(1).object_id(), Some(1));
+        assert_eq!(Value::ClassObject(2).object_id(), Some(2));
+        assert_eq!(Value::Null.object_id(), None);
+        assert_eq!(Value::Long(3).object_id(), None);
+    }
+}
@@ -0,0 +1,98 @@
+//
+// Modified UTF-8, which is how the JVM writes strings out: in class files (and so constant pools),
+// heap dumps, and JDWP from older JVMs. It differs from UTF-8 in two ways:
+//
+//   NUL is written as the two bytes C0 80, so an encoded string never has a zero byte in it
+//   characters outside the Basic Multilingual Plane are written as their UTF-16 surrogate pair,
+//   each half encoded separately in three bytes, rather than in one four byte sequence
+//
+// Neither is valid UTF-8, so String::from_utf8() rejects them, and from_utf8_lossy() turns them
+// into replacement characters. decode() takes standard UTF-8 too, since newer JVMs convert
+// strings to it for JDWP.
+//
+
+// Anything malformed, including half a surrogate pair on its own, becomes U+FFFD, as with
+// String::from_utf8_lossy()
+pub fn decode(bytes: &[u8]) -> String {
+    // Most strings are ASCII, which all three encodings agree on
+    if let Ok(text) = std::str::from_utf8(bytes) {
+        return text.to_string();
+    }
+    // Surrogates are collected as they are, and paired up by from_utf16_lossy()
+    let mut units: Vec<u16> = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let lead = bytes[i];
+        let (length, initial) = match lead {
+            0x00..=0x7f => (1, u32::from(lead)),
+            0xc0..=0xdf => (2, u32::from(lead & 0x1f)),
+            0xe0..=0xef => (3, u32::from(lead & 0x0f)),
+            // Standard UTF-8's four byte sequences
+            0xf0..=0xf7 => (4, u32::from(lead & 0x07)),
+            _ => (0, 0),
+        };
+        let rest = bytes
+            .get(i + 1..i + length)
+            .filter(|rest| length > 0 && rest.iter().all(|&b| b & 0xc0 == 0x80));
+        match rest {
+            Some(rest) => {
+                let code = rest
+                    .iter()
+                    .fold(initial, |code, &b| code << 6 | u32::from(b & 0x3f));
+                match char::from_u32(code) {
+                    Some(c) => units.extend_from_slice(c.encode_utf16(&mut [0; 2])),
+                    // Half a surrogate pair
+                    None if code <= 0xffff => units.push(code as u16),
+                    None => units.push(0xfffd),
+                }
+                i += length;
+            }
+            None => {
+                units.push(0xfffd);
+                i += 1;
+            }
+        }
+    }
+    String::from_utf16_lossy(&units)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::decode;
+
+    #[test]
+    fn plain() {
+        assert_eq!(decode(b"java/lang/String"), "java/lang/String");
+        assert_eq!(decode("caf\u{e9}".as_bytes()), "caf\u{e9}");
+        assert_eq!(decode(b""), "");
+    }
+
+    #[test]
+    fn nul() {
+        assert_eq!(decode(&[b'a', 0xc0, 0x80, b'b']), "a\0b");
+    }
+
+    #[test]
+    fn supplementary_characters() {
+        // U+1F600 as the surrogate pair D83D DE00, each half in three bytes
+        let modified = [0xed, 0xa0, 0xbd, 0xed, 0xb8, 0x80];
+        assert_eq!(decode(&modified), "\u{1f600}");
+        // And in standard UTF-8's four bytes
+        assert_eq!(decode("\u{1f600}".as_bytes()), "\u{1f600}");
+        let mut mixed = b"x".to_vec();
+        mixed.extend_from_slice(&modified);
+        mixed.extend_from_slice(&[0xc0, 0x80]);
+        mixed.extend_from_slice("\u{10348}".as_bytes());
+        assert_eq!(decode(&mixed), "x\u{1f600}\0\u{10348}");
+    }
+
+    #[test]
+    fn malformed() {
+        // A high surrogate without its low half, and a low one without its high half
+        assert_eq!(decode(&[0xed, 0xa0, 0xbd, b'a']), "\u{fffd}a");
+        assert_eq!(decode(&[b'a', 0xed, 0xb8, 0x80]), "a\u{fffd}");
+        // Cut short, and a stray continuation byte
+        assert_eq!(decode(&[b'a', 0xe2, 0x82]), "a\u{fffd}\u{fffd}");
+        assert_eq!(decode(&[0x80, b'b']), "\u{fffd}b");
+    }
+}
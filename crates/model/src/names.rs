@@ -0,0 +1,333 @@
+//
+// Converting between the ways a JVM names types and the way Java source does. A class has three
+// names:
+//
+//   java.util.Map$Entry       the binary name, which is the source name with $ for nested classes
+//   java/util/Map$Entry       the internal name, which class files and heap dumps use
+//   Ljava/util/Map$Entry;     the type signature, which JDWP uses
+//
+// Array types have no name of their own, so their internal name is their signature ([I,
+// [[Ljava/lang/String;), and in source they're int[] and java.lang.String[][]. Everything the
+// model hands out is in the source form, whichever backend it came from.
+//
+// Hidden classes (since JDK 15, and the VM-anonymous classes before them, which lambdas and
+// method handles are made of) have a suffix the JVM makes up to keep their names unique, which
+// each way of looking at them writes differently:
+//
+//   com.example.Worker$$Lambda/0x0000000800c02a00     Class.getName(), and so the source form
+//   com/example/Worker$$Lambda.0x0000000800c02a00     the internal name, as JVMTI and JDWP have it
+//   com/example/Worker$$Lambda+0x0000000800c02a00     the JVM's own symbol, as heap dumps have it
+//
+// The suffix (and the numbers in the names of other generated classes) is different in every
+// run, so display_name() leaves it out, for reports meant to be read or compared across runs.
+// The data keeps the source form, so the raw name is still there.
+//
+
+const PRIMITIVES: &[(char, &str)] = &[
+    ('Z', "boolean"),
+    ('B', "byte"),
+    ('C', "char"),
+    ('S', "short"),
+    ('I', "int"),
+    ('J', "long"),
+    ('F', "float"),
+    ('D', "double"),
+    ('V', "void"),
+];
+
+// The source form of a type name given in any of the forms above, e.g. [[I is int[][]. Names
+// already in the source form are returned unchanged.
+pub fn source_name(name: &str) -> String {
+    let element = name.trim_start_matches('[');
+    let dimensions = name.len() - element.len();
+    let primitive = match (dimensions, element.len()) {
+        // A class in the default package can be called I, so a lone letter is only a primitive
+        // type in an array signature
+        (0, _) | (_, 2..) => None,
+        _ => element.chars().next().and_then(primitive_name),
+    };
+    let element = match primitive {
+        Some(primitive) => primitive.to_string(),
+        None => {
+            let class = class_name(element);
+            match hidden_suffix(class) {
+                Some(separator) => format!(
+                    "{}/{}",
+                    class[..separator].replace('/', "."),
+                    &class[separator + 1..]
+                ),
+                None => class.replace('/', "."),
+            }
+        }
+    };
+    format!("{}{}", element, "[]".repeat(dimensions))
+}
+
+// Whether the type (or an array's element type) is a hidden class, in any of the forms above
+pub fn is_hidden_class(name: &str) -> bool {
+    hidden_suffix(class_name(name.trim_start_matches('['))).is_some()
+}
+
+// The source form without what changes from run to run, so the same class has the same name in
+// every run: hidden classes lose their suffix, and lambdas, reflection accessors and proxies the
+// numbers the JVM gives them, e.g. com.example.Worker$$Lambda for
+// com.example.Worker$$Lambda$14/0x0000000800066840, and
+// jdk.internal.reflect.GeneratedMethodAccessor for jdk.internal.reflect.GeneratedMethodAccessor42.
+// Distinct classes can end up with the same display name.
+pub fn display_name(name: &str) -> String {
+    let source = source_name(name);
+    let element = source.trim_end_matches("[]");
+    let dimensions = &source[element.len()..];
+    let element = match hidden_suffix(element) {
+        Some(separator) => &element[..separator],
+        None => element,
+    };
+    let element = GENERATED_CLASS_MARKERS
+        .iter()
+        .find_map(|marker| {
+            let start = element.rfind(marker)?;
+            let number = &element[start + marker.len()..];
+            let numbered = !number.is_empty() && number.bytes().all(|b| b.is_ascii_digit());
+            numbered.then(|| &element[..start + marker.trim_end_matches('$').len()])
+        })
+        .unwrap_or(element);
+    format!("{}{}", element, dimensions)
+}
+
+// The names of classes the JVM generates end in one of these and then a number
+const GENERATED_CLASS_MARKERS: &[&str] = &[
+    "$$Lambda$",
+    "$Proxy",
+    "GeneratedMethodAccessor",
+    "GeneratedConstructorAccessor",
+    "GeneratedSerializationConstructorAccessor",
+];
+
+// Where the separator before a hidden class's suffix is, if it has one. The suffix is the class's
+// address in hex (or, for VM-anonymous classes on older JDKs, a decimal number), which no real
+// class name can be.
+fn hidden_suffix(class: &str) -> Option<usize> {
+    let separator = class.rfind(['/', '.', '+'])?;
+    let suffix = &class[separator + 1..];
+    let hex = suffix
+        .strip_prefix("0x")
+        .filter(|digits| !digits.is_empty() && digits.bytes().all(|b| b.is_ascii_hexdigit()));
+    let decimal = !suffix.is_empty() && suffix.bytes().all(|b| b.is_ascii_digit());
+    (separator > 0 && (hex.is_some() || decimal)).then_some(separator)
+}
+
+// The internal form of a type name given in any form, e.g. java/lang/String for java.lang.String,
+// or [Ljava/lang/String; for java.lang.String[]. Primitive types keep their source names.
+pub fn internal_name(name: &str) -> String {
+    let source = source_name(name);
+    let element = source.trim_end_matches("[]");
+    let dimensions = (source.len() - element.len()) / 2;
+    let class = match hidden_suffix(element) {
+        Some(separator) => format!(
+            "{}.{}",
+            element[..separator].replace('.', "/"),
+            &element[separator + 1..]
+        ),
+        None => element.replace('.', "/"),
+    };
+    if dimensions == 0 {
+        return class;
+    }
+    let element = match primitive_letter(element) {
+        Some(letter) => letter.to_string(),
+        None => format!("L{};", class),
+    };
+    format!("{}{}", "[".repeat(dimensions), element)
+}
+
+// The type signature of a type name given in any form, e.g. Ljava/lang/String; or I
+pub fn signature(name: &str) -> String {
+    let internal = internal_name(name);
+    if internal.starts_with('[') {
+        return internal;
+    }
+    match primitive_letter(&internal) {
+        Some(letter) => letter.to_string(),
+        None => format!("L{};", internal),
+    }
+}
+
+// java/lang/String for Ljava/lang/String;, and anything else unchanged
+fn class_name(name: &str) -> &str {
+    name.strip_prefix('L')
+        .and_then(|n| n.strip_suffix(';'))
+        .unwrap_or(name)
+}
+
+fn primitive_name(letter: char) -> Option<&'static str> {
+    PRIMITIVES
+        .iter()
+        .find(|&&(l, _)| l == letter)
+        .map(|&(_, name)| name)
+}
+
+fn primitive_letter(name: &str) -> Option<char> {
+    PRIMITIVES
+        .iter()
+        .find(|&&(_, n)| n == name)
+        .map(|&(letter, _)| letter)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{display_name, internal_name, is_hidden_class, signature, source_name};
+
+    #[test]
+    fn classes() {
+        for name in &[
+            "java/util/Map$Entry",
+            "Ljava/util/Map$Entry;",
+            "java.util.Map$Entry",
+        ] {
+            assert_eq!(source_name(name), "java.util.Map$Entry");
+            assert_eq!(internal_name(name), "java/util/Map$Entry");
+            assert_eq!(signature(name), "Ljava/util/Map$Entry;");
+        }
+    }
+
+    #[test]
+    fn default_package() {
+        // Single letter classes aren't primitives outside arrays
+        assert_eq!(source_name("I"), "I");
+        assert_eq!(source_name("LI;"), "I");
+        assert_eq!(source_name("Foo"), "Foo");
+        assert_eq!(signature("Foo"), "LFoo;");
+        assert_eq!(source_name("[LI;"), "I[]");
+        assert_eq!(internal_name("I[]"), "[LI;");
+    }
+
+    #[test]
+    fn primitive_arrays() {
+        let cases = [
+            ("[Z", "boolean[]"),
+            ("[B", "byte[]"),
+            ("[C", "char[]"),
+            ("[S", "short[]"),
+            ("[I", "int[]"),
+            ("[J", "long[]"),
+            ("[F", "float[]"),
+            ("[D", "double[]"),
+            ("[[I", "int[][]"),
+            ("[[[J", "long[][][]"),
+        ];
+        for &(jni, source) in &cases {
+            assert_eq!(source_name(jni), source);
+            assert_eq!(source_name(source), source);
+            assert_eq!(internal_name(source), jni);
+            assert_eq!(internal_name(jni), jni);
+            assert_eq!(signature(source), jni);
+        }
+    }
+
+    #[test]
+    fn object_arrays() {
+        let cases = [
+            ("[Ljava/lang/String;", "java.lang.String[]"),
+            ("[[Ljava/lang/String;", "java.lang.String[][]"),
+            ("[Ljava/util/Map$Entry;", "java.util.Map$Entry[]"),
+        ];
+        for &(jni, source) in &cases {
+            assert_eq!(source_name(jni), source);
+            assert_eq!(internal_name(source), jni);
+            assert_eq!(signature(source), jni);
+        }
+        // Dotted signatures, as JDWP names used to be shown
+        assert_eq!(source_name("[Ljava.lang.String;"), "java.lang.String[]");
+    }
+
+    #[test]
+    fn primitives() {
+        assert_eq!(source_name("int"), "int");
+        assert_eq!(internal_name("int"), "int");
+        assert_eq!(signature("int"), "I");
+        assert_eq!(signature("void"), "V");
+        assert_eq!(signature("boolean"), "Z");
+    }
+
+    #[test]
+    fn hidden_classes() {
+        for name in &[
+            "com.example.Worker$$Lambda/0x0000000800c02a00",
+            "com/example/Worker$$Lambda.0x0000000800c02a00",
+            "com/example/Worker$$Lambda+0x0000000800c02a00",
+            "Lcom/example/Worker$$Lambda.0x0000000800c02a00;",
+        ] {
+            assert!(is_hidden_class(name));
+            assert_eq!(
+                source_name(name),
+                "com.example.Worker$$Lambda/0x0000000800c02a00"
+            );
+            assert_eq!(
+                internal_name(name),
+                "com/example/Worker$$Lambda.0x0000000800c02a00"
+            );
+            assert_eq!(
+                signature(name),
+                "Lcom/example/Worker$$Lambda.0x0000000800c02a00;"
+            );
+            assert_eq!(display_name(name), "com.example.Worker$$Lambda");
+        }
+        // JDK 8's VM-anonymous classes
+        assert_eq!(
+            source_name("com/example/Worker$$Lambda$14/1096979270"),
+            "com.example.Worker$$Lambda$14/1096979270"
+        );
+        assert_eq!(
+            display_name("com/example/Worker$$Lambda$14/1096979270"),
+            "com.example.Worker$$Lambda"
+        );
+        assert_eq!(
+            display_name("[Ljava/lang/invoke/LambdaForm$MH+0x0000000800c01000;"),
+            "java.lang.invoke.LambdaForm$MH[]"
+        );
+        assert_eq!(
+            internal_name("java.lang.invoke.LambdaForm$MH/0x0000000800c01000[]"),
+            "[Ljava/lang/invoke/LambdaForm$MH.0x0000000800c01000;"
+        );
+        // Nothing else looks like one
+        for name in &[
+            "java.lang.String",
+            "com.example.v2.Foo",
+            "[I",
+            "int",
+            "Foo$1",
+        ] {
+            assert!(!is_hidden_class(name));
+            assert_eq!(display_name(name), source_name(name));
+        }
+    }
+
+    #[test]
+    fn generated_classes() {
+        let cases = [
+            (
+                "jdk/internal/reflect/GeneratedMethodAccessor42",
+                "jdk.internal.reflect.GeneratedMethodAccessor",
+            ),
+            (
+                "sun.reflect.GeneratedSerializationConstructorAccessor7",
+                "sun.reflect.GeneratedSerializationConstructorAccessor",
+            ),
+            ("com.sun.proxy.$Proxy12", "com.sun.proxy.$Proxy"),
+            (
+                "com.example.Worker$$Lambda$14",
+                "com.example.Worker$$Lambda",
+            ),
+            ("[Lcom/sun/proxy/$Proxy3;", "com.sun.proxy.$Proxy[]"),
+            // Bound method handle species are named for their fields, which don't change
+            (
+                "java.lang.invoke.BoundMethodHandle$Species_LL",
+                "java.lang.invoke.BoundMethodHandle$Species_LL",
+            ),
+            ("com.example.$ProxyFactory", "com.example.$ProxyFactory"),
+        ];
+        for &(name, display) in &cases {
+            assert_eq!(display_name(name), display);
+        }
+    }
+}
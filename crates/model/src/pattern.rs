@@ -0,0 +1,268 @@
+//
+// Class name patterns, used wherever the user gets to pick a set of classes (event filters,
+// contention profiling, source line resolution, ...). The syntax is the one JDWP uses for its
+// ClassMatch/ClassExclude modifiers, so that most patterns can be handed straight to the target
+// VM:
+//
+//   java.util.HashMap    an exact class name
+//   java.util.*          any class whose name starts with 'java.util.'
+//   *.HashMap            any class whose name ends with '.HashMap'
+//   *                    any class
+//
+// With the 'regex' feature, a pattern of the form /regex/ is matched as a regular expression
+// against the whole class name. These can't be evaluated by the VM, so they are applied on the
+// client side.
+//
+// Class names may be given either in the dotted form (java.util.Map$Entry) or in the JNI form
+// (java/util/Map$Entry or Ljava/util/Map$Entry;), both for patterns and for the names they are
+// matched against.
+//
+
+use std::borrow::Cow;
+use std::fmt;
+use std::str::FromStr;
+
+#[derive(Debug, Clone)]
+enum Kind {
+    Any,
+    Exact(String),
+    Prefix(String),
+    Suffix(String),
+    #[cfg(feature = "regex")]
+    Regex(regex::Regex),
+}
+
+#[derive(Debug, Clone)]
+pub struct ClassPattern {
+    source: String,
+    kind: Kind,
+}
+
+#[derive(Debug)]
+pub struct PatternError {
+    msg: String,
+}
+
+impl std::error::Error for PatternError {}
+
+impl fmt::Display for PatternError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.msg)
+    }
+}
+
+impl ClassPattern {
+    pub fn new(pattern: &str) -> Result<ClassPattern, PatternError> {
+        let pattern = pattern.trim();
+        let err = |msg: &str| PatternError {
+            msg: format!("Invalid class pattern '{}': {}", pattern, msg),
+        };
+
+        if pattern.len() >= 2 && pattern.starts_with('/') && pattern.ends_with('/') {
+            return Self::regex(&pattern[1..pattern.len() - 1]);
+        }
+
+        let kind = if pattern == "*" {
+            Kind::Any
+        } else if let Some(prefix) = pattern.strip_suffix('*') {
+            Kind::Prefix(normalize_glob_part(prefix))
+        } else if let Some(suffix) = pattern.strip_prefix('*') {
+            Kind::Suffix(normalize_glob_part(suffix))
+        } else {
+            Kind::Exact(to_dotted(pattern).into_owned())
+        };
+
+        let inner = match &kind {
+            Kind::Exact(s) | Kind::Prefix(s) | Kind::Suffix(s) => s.as_str(),
+            _ => "",
+        };
+        if inner.contains('*') {
+            return Err(err(
+                "'*' is only allowed at the start or end of the pattern",
+            ));
+        }
+        if matches!(kind, Kind::Exact(_)) && inner.is_empty() {
+            return Err(err("the pattern is empty"));
+        }
+
+        Ok(ClassPattern {
+            source: pattern.to_string(),
+            kind,
+        })
+    }
+
+    #[cfg(feature = "regex")]
+    pub fn regex(regex: &str) -> Result<ClassPattern, PatternError> {
+        // Anchor the expression, so that it has to match the whole name like the other kinds of
+        // pattern do
+        let compiled =
+            regex::Regex::new(&format!("^(?:{})$", regex)).map_err(|e| PatternError {
+                msg: format!("Invalid class pattern '/{}/': {}", regex, e),
+            })?;
+        Ok(ClassPattern {
+            source: format!("/{}/", regex),
+            kind: Kind::Regex(compiled),
+        })
+    }
+
+    #[cfg(not(feature = "regex"))]
+    pub fn regex(regex: &str) -> Result<ClassPattern, PatternError> {
+        Err(PatternError {
+            msg: format!(
+                "Invalid class pattern '/{}/': regular expressions require the 'regex' feature",
+                regex
+            ),
+        })
+    }
+
+    pub fn any() -> ClassPattern {
+        ClassPattern {
+            source: "*".to_string(),
+            kind: Kind::Any,
+        }
+    }
+
+    // The pattern as it was written
+    pub fn as_str(&self) -> &str {
+        &self.source
+    }
+
+    // The name may be in the dotted or JNI form
+    pub fn matches(&self, class_name: &str) -> bool {
+        let name = to_dotted(class_name);
+        match &self.kind {
+            Kind::Any => true,
+            Kind::Exact(exact) => *name == **exact,
+            Kind::Prefix(prefix) => name.starts_with(prefix.as_str()),
+            Kind::Suffix(suffix) => name.ends_with(suffix.as_str()),
+            #[cfg(feature = "regex")]
+            Kind::Regex(regex) => regex.is_match(&name),
+        }
+    }
+
+    // The equivalent pattern in the syntax of the JDWP ClassMatch modifier, if there is one. If
+    // this returns None, the pattern can't be evaluated by the target VM and events need to be
+    // filtered by the client instead.
+    pub fn to_jdwp(&self) -> Option<String> {
+        match &self.kind {
+            Kind::Any => Some("*".to_string()),
+            Kind::Exact(exact) => Some(exact.clone()),
+            Kind::Prefix(prefix) => Some(format!("{}*", prefix)),
+            Kind::Suffix(suffix) => Some(format!("*{}", suffix)),
+            #[cfg(feature = "regex")]
+            Kind::Regex(_) => None,
+        }
+    }
+}
+
+impl FromStr for ClassPattern {
+    type Err = PatternError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        ClassPattern::new(s)
+    }
+}
+
+impl fmt::Display for ClassPattern {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.source)
+    }
+}
+
+// Convert a class name from the JNI form to the dotted form. Names which are already dotted are
+// returned as is, without allocating.
+fn to_dotted(name: &str) -> Cow<'_, str> {
+    let name = match name.strip_prefix('L').and_then(|n| n.strip_suffix(';')) {
+        Some(inner) => inner,
+        None => name,
+    };
+    if name.contains('/') {
+        Cow::Owned(name.replace('/', "."))
+    } else {
+        Cow::Borrowed(name)
+    }
+}
+
+// Only the package separators need converting in partial names; a leading 'L' could be part of a
+// class name ('*.List'), so we don't try to strip it.
+fn normalize_glob_part(part: &str) -> String {
+    part.replace('/', ".")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::ClassPattern;
+
+    fn matches(pattern: &str, name: &str) -> bool {
+        ClassPattern::new(pattern).unwrap().matches(name)
+    }
+
+    #[test]
+    fn exact() {
+        assert!(matches("java.util.HashMap", "java.util.HashMap"));
+        assert!(!matches("java.util.HashMap", "java.util.HashMapX"));
+        assert!(!matches("java.util.HashMap", "java.util.HashMap$Node"));
+        assert!(matches("java.util.Map$Entry", "java.util.Map$Entry"));
+    }
+
+    #[test]
+    fn prefix_and_suffix() {
+        assert!(matches("java.util.*", "java.util.HashMap"));
+        assert!(matches(
+            "java.util.*",
+            "java.util.concurrent.ConcurrentHashMap"
+        ));
+        assert!(!matches("java.util.*", "java.utility.Foo"));
+        assert!(matches("*.HashMap", "java.util.HashMap"));
+        assert!(!matches("*.HashMap", "java.util.LinkedHashMap"));
+        assert!(matches("*", "Foo"));
+    }
+
+    #[test]
+    fn jni_names() {
+        assert!(matches("java.util.*", "java/util/HashMap"));
+        assert!(matches("java.util.HashMap", "Ljava/util/HashMap;"));
+        assert!(matches("*.HashMap", "Ljava/util/HashMap;"));
+        assert!(matches("java/util/*", "java.util.HashMap"));
+        assert!(matches("Ljava/util/HashMap;", "java.util.HashMap"));
+        assert!(matches("*/HashMap", "java.util.HashMap"));
+        // A class called 'List' in the default package isn't a JNI name
+        assert!(matches("*List", "List"));
+        assert!(matches("List", "List"));
+    }
+
+    #[test]
+    fn jdwp_form() {
+        let p = ClassPattern::new("java/util/*").unwrap();
+        assert_eq!(p.to_jdwp().as_deref(), Some("java.util.*"));
+        assert_eq!(p.as_str(), "java/util/*");
+        let p = ClassPattern::new("Ljava/lang/String;").unwrap();
+        assert_eq!(p.to_jdwp().as_deref(), Some("java.lang.String"));
+    }
+
+    #[test]
+    fn invalid() {
+        assert!(ClassPattern::new("java.*.Map").is_err());
+        assert!(ClassPattern::new("").is_err());
+        assert!(ClassPattern::new("**").is_err());
+    }
+
+    #[cfg(feature = "regex")]
+    #[test]
+    fn regex() {
+        assert!(matches("/java\\.util\\.[A-Z]\\w*Map/", "java.util.HashMap"));
+        assert!(matches("/java\\.util\\.[A-Z]\\w*Map/", "java/util/TreeMap"));
+        assert!(!matches(
+            "/java\\.util\\.[A-Z]\\w*Map/",
+            "java.util.HashMap$Node"
+        ));
+        assert!(ClassPattern::new("/java.util.(/").is_err());
+        assert!(ClassPattern::new("/.*/").unwrap().to_jdwp().is_none());
+    }
+
+    #[cfg(not(feature = "regex"))]
+    #[test]
+    fn regex_requires_feature() {
+        assert!(ClassPattern::new("/java\\.util\\..*/").is_err());
+    }
+}
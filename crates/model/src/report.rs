@@ -0,0 +1,988 @@
+//
+// Human readable output of the snapshots any backend can capture: thread dumps, class histograms
+// and thread pools. libjdb::report adds the reports which only heap dumps can produce.
+//
+
+use std::collections::BTreeMap;
+use std::io::{Result, Write};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::annotation::Annotations;
+use crate::executor::{ExecutorInfo, ExecutorKind};
+use crate::model::JavaVirtualMachine;
+use crate::names::display_name;
+use crate::snapshot::{group_stacks, ClassDelta, FrameInfo, Histogram, HistogramDiff};
+use crate::snapshot::{ExceptionInfo, MonitorInfo, StackTraceLine, ThreadDump, ThreadStack};
+use crate::snapshot::{InstanceSample, InstanceSeries, ThreadState};
+
+mod sink;
+
+pub use sink::{write_to, DirectorySink, OutputSink, SinkWriter, StdoutSink};
+
+// How many of the threads in a group are named before they're just counted
+const GROUP_NAMES_LIMIT: usize = 10;
+
+// Each stack is followed by its hash (see snapshot::frames_hash()) as "stack=", so that logs of
+// many dumps can be grouped by stack.
+pub fn write_thread_dump<Jvm: JavaVirtualMachine, W: Write + ?Sized>(
+    jvm: &Jvm,
+    out: &mut W,
+) -> Result<()> {
+    write_thread_dump_annotated::<Jvm, W>(jvm, &Annotations::new(), out)
+}
+
+// Like write_thread_dump(), with the labels and source links in 'annotations'. Labelled threads
+// have their labels after their names, here and in the other thread dumps.
+pub fn write_thread_dump_annotated<Jvm: JavaVirtualMachine, W: Write + ?Sized>(
+    jvm: &Jvm,
+    annotations: &Annotations,
+    out: &mut W,
+) -> Result<()> {
+    for thread in jvm.all_threads() {
+        write_stack_trace_annotated::<Jvm, W>(&thread?, annotations, out)?;
+    }
+    Ok(())
+}
+
+pub fn write_stack_trace<Jvm: JavaVirtualMachine, W: Write + ?Sized>(
+    thread: &Jvm::ThreadReference,
+    out: &mut W,
+) -> Result<()> {
+    write_stack_trace_annotated::<Jvm, W>(thread, &Annotations::new(), out)
+}
+
+// Like write_stack_trace(), with the labels and source links in 'annotations'
+pub fn write_stack_trace_annotated<Jvm: JavaVirtualMachine, W: Write + ?Sized>(
+    thread: &Jvm::ThreadReference,
+    annotations: &Annotations,
+    out: &mut W,
+) -> Result<()> {
+    let stack = ThreadStack::capture::<Jvm>(thread)?;
+    // TODO thread_id is not the same as the thread number, or the nid. How do we get those?
+    let kind = if stack.is_virtual { " (virtual)" } else { "" };
+    let label = annotations.thread_suffix(&stack.name);
+    writeln!(
+        out,
+        "\nThread {}: {}{}{} stack={:016x}",
+        stack.thread_id,
+        stack.name,
+        kind,
+        label,
+        stack.frames_hash()
+    )?;
+    write_frames(&stack.frames, annotations, out)
+}
+
+// Like write_thread_dump(), but threads with the same stack are written once, as
+// "N threads (names...) at:", with the biggest groups first
+pub fn write_grouped_thread_dump<Jvm: JavaVirtualMachine, W: Write + ?Sized>(
+    jvm: &Jvm,
+    out: &mut W,
+) -> Result<()> {
+    write_grouped_thread_dump_annotated::<Jvm, W>(jvm, &Annotations::new(), out)
+}
+
+// Like write_grouped_thread_dump(), with the labels and source links in 'annotations'
+pub fn write_grouped_thread_dump_annotated<Jvm: JavaVirtualMachine, W: Write + ?Sized>(
+    jvm: &Jvm,
+    annotations: &Annotations,
+    out: &mut W,
+) -> Result<()> {
+    write_stack_groups_annotated(&ThreadStack::capture_all(jvm)?, annotations, out)
+}
+
+// The grouped layout of write_grouped_thread_dump(), for stacks which have already been captured
+pub fn write_stack_groups<W: Write + ?Sized>(stacks: &[ThreadStack], out: &mut W) -> Result<()> {
+    write_stack_groups_annotated(stacks, &Annotations::new(), out)
+}
+
+// Like write_stack_groups(), with the labels and source links in 'annotations'
+pub fn write_stack_groups_annotated<W: Write + ?Sized>(
+    stacks: &[ThreadStack],
+    annotations: &Annotations,
+    out: &mut W,
+) -> Result<()> {
+    for group in group_stacks(stacks) {
+        if let [(id, name)] = &group.threads[..] {
+            let is_virtual = stacks.iter().any(|s| s.thread_id == *id && s.is_virtual);
+            let kind = if is_virtual { " (virtual)" } else { "" };
+            let label = annotations.thread_suffix(name);
+            writeln!(
+                out,
+                "\nThread {}: {}{}{} stack={:016x}",
+                id,
+                name,
+                kind,
+                label,
+                group.frames_hash()
+            )?;
+        } else {
+            // Labelled threads first, so that they aren't among the ones only counted
+            let mut threads: Vec<&(u64, String)> = group.threads.iter().collect();
+            threads.sort_by_key(|(_, name)| annotations.thread_label(name).is_none());
+            let mut names: Vec<String> = threads
+                .iter()
+                .take(GROUP_NAMES_LIMIT)
+                .map(|(_, name)| format!("{}{}", name, annotations.thread_suffix(name)))
+                .collect();
+            if group.threads.len() > GROUP_NAMES_LIMIT {
+                names.push(format!("{} more", group.threads.len() - GROUP_NAMES_LIMIT));
+            }
+            writeln!(
+                out,
+                "\n{} threads ({}) stack={:016x} at:",
+                group.threads.len(),
+                names.join(", "),
+                group.frames_hash()
+            )?;
+        }
+        write_frames(&group.frames, annotations, out)?;
+    }
+    Ok(())
+}
+
+// How long the target was paused for, then each thread in the dump with its state and, if the
+// target reported them, the monitors it holds and the one it's blocked on. Stacks which were cut
+// short end with "... N more", as Java's own traces do. There's no safepoint information, which
+// JDWP can't provide (see thread_dump.rs).
+pub fn write_captured_thread_dump<W: Write + ?Sized>(dump: &ThreadDump, out: &mut W) -> Result<()> {
+    write_captured_thread_dump_annotated(dump, &Annotations::new(), out)
+}
+
+// Like write_captured_thread_dump(), with the labels and source links in 'annotations'
+pub fn write_captured_thread_dump_annotated<W: Write + ?Sized>(
+    dump: &ThreadDump,
+    annotations: &Annotations,
+    out: &mut W,
+) -> Result<()> {
+    writeln!(
+        out,
+        "Target suspended for {}ms",
+        dump.suspended_for().as_millis()
+    )?;
+    for thread in dump.threads() {
+        let stack = &thread.stack;
+        let kind = if stack.is_virtual { " (virtual)" } else { "" };
+        let label = annotations.thread_suffix(&stack.name);
+        let suspended = if thread.suspended {
+            ""
+        } else {
+            ", not suspended"
+        };
+        writeln!(
+            out,
+            "\nThread {}: {}{}{} ({}{}) stack={:016x}",
+            stack.thread_id,
+            stack.name,
+            kind,
+            label,
+            state_name(thread.state),
+            suspended,
+            stack.frames_hash()
+        )?;
+        write_frames(&stack.frames, annotations, out)?;
+        if stack.omitted_frames > 0 {
+            writeln!(out, "   ... {} more", stack.omitted_frames)?;
+        }
+        if let Some(monitor) = &thread.contended_monitor {
+            let verb = match thread.state {
+                ThreadState::Monitor => "waiting to lock",
+                _ => "waiting on",
+            };
+            let owner = match dump.monitor_owner(monitor.object) {
+                Some(owner) => format!(" held by \"{}\"", owner.stack.name),
+                None => String::new(),
+            };
+            writeln!(out, "   - {} {}{}", verb, describe_monitor(monitor), owner)?;
+        }
+        for monitor in &thread.owned_monitors {
+            writeln!(out, "   - locked {}", describe_monitor(monitor))?;
+        }
+    }
+    Ok(())
+}
+
+fn state_name(state: ThreadState) -> &'static str {
+    match state {
+        ThreadState::Running => "running",
+        ThreadState::Sleeping => "sleeping",
+        ThreadState::Monitor => "blocked",
+        ThreadState::Wait => "waiting",
+        ThreadState::Zombie => "finished",
+    }
+}
+
+fn describe_monitor(monitor: &MonitorInfo) -> String {
+    format!(
+        "<{:#x}> (a {})",
+        monitor.object,
+        display_name(&monitor.class_name)
+    )
+}
+
+// Classes are written under their display names, without the numbers which change from run to
+// run. A lambda's generated class has what it calls where the line number would be, e.g.
+// "Worker$$Lambda.run(-> lambda$start$0)". Frames with a source link have it after them.
+fn write_frames<W: Write + ?Sized>(
+    frames: &[FrameInfo],
+    annotations: &Annotations,
+    out: &mut W,
+) -> Result<()> {
+    for (i, frame) in frames.iter().enumerate() {
+        let (class_name, place) = match frame.lambda_host() {
+            Some(host) => {
+                let target = match i.checked_sub(1).map(|inner| &frames[inner]) {
+                    Some(target) if target.class_name == host => {
+                        format!("-> {}", target.method_name)
+                    }
+                    Some(target) => format!(
+                        "-> {}.{}",
+                        display_name(&target.class_name),
+                        target.method_name
+                    ),
+                    None => String::new(),
+                };
+                (format!("{}$$Lambda", host), target)
+            }
+            None => match frame.line_number {
+                Some(n) => (display_name(&frame.class_name), format!(":{}", n)),
+                None => (display_name(&frame.class_name), String::new()),
+            },
+        };
+        writeln!(
+            out,
+            "   {}.{}({}){}",
+            class_name,
+            frame.method_name,
+            place,
+            annotations.frame_suffix(frame)
+        )?;
+    }
+    Ok(())
+}
+
+// An exception, its suppressed exceptions and its causes, the way Throwable.printStackTrace()
+// writes them, including the "... N more" for frames one has in common with the exception it's
+// part of. Suppressed exceptions are indented a tab further than the exception they were
+// suppressed by.
+pub fn write_exception<W: Write + ?Sized>(exception: &ExceptionInfo, out: &mut W) -> Result<()> {
+    write_enclosed_exception(exception, &[], "", "", out)
+}
+
+// As Throwable.printEnclosedStackTrace() has it, with 'enclosing' the trace of the exception this
+// one is a cause or suppressed exception of
+fn write_enclosed_exception<W: Write + ?Sized>(
+    e: &ExceptionInfo,
+    enclosing: &[StackTraceLine],
+    caption: &str,
+    prefix: &str,
+    out: &mut W,
+) -> Result<()> {
+    let header = match &e.message {
+        Some(message) => format!("{}: {}", e.class_name, message),
+        None => e.class_name.clone(),
+    };
+    if e.circular {
+        return writeln!(out, "{}{}[CIRCULAR REFERENCE: {}]", prefix, caption, header);
+    }
+    writeln!(out, "{}{}{}", prefix, caption, header)?;
+    let trace: &[StackTraceLine] = match &e.stack_trace {
+        Some(trace) => {
+            let mut in_common = 0;
+            while in_common < trace.len()
+                && in_common < enclosing.len()
+                && trace[trace.len() - 1 - in_common] == enclosing[enclosing.len() - 1 - in_common]
+            {
+                in_common += 1;
+            }
+            for line in &trace[..trace.len() - in_common] {
+                writeln!(out, "{}\tat {}", prefix, stack_trace_line(line))?;
+            }
+            if in_common > 0 {
+                writeln!(out, "{}\t... {} more", prefix, in_common)?;
+            }
+            trace
+        }
+        None => {
+            writeln!(out, "{}\t(stack trace not filled in yet)", prefix)?;
+            &[]
+        }
+    };
+    let indented = format!("{}\t", prefix);
+    for suppressed in &e.suppressed {
+        write_enclosed_exception(suppressed, trace, "Suppressed: ", &indented, out)?;
+    }
+    match &e.cause {
+        Some(cause) => write_enclosed_exception(cause, trace, "Caused by: ", prefix, out),
+        None => Ok(()),
+    }
+}
+
+// As StackTraceElement.toString() has it, e.g. java.base/java.lang.Thread.run(Thread.java:829)
+fn stack_trace_line(line: &StackTraceLine) -> String {
+    let module = match &line.module_name {
+        Some(module) => format!("{}/", module),
+        None => String::new(),
+    };
+    let place = match (&line.file_name, line.line_number) {
+        (_, -2) => "Native Method".to_string(),
+        (Some(file), n) if n >= 0 => format!("{}:{}", file, n),
+        (Some(file), _) => file.clone(),
+        (None, _) => "Unknown Source".to_string(),
+    };
+    format!(
+        "{}{}.{}({})",
+        module, line.class_name, line.method_name, place
+    )
+}
+
+// Writes the largest 'limit' entries of the histogram (or all of them), in the same layout as
+// 'jmap -histo', but under display names, so e.g. all the lambdas written in one class are one
+// entry
+pub fn write_histogram<W: Write + ?Sized>(
+    histogram: &Histogram,
+    limit: Option<usize>,
+    out: &mut W,
+) -> Result<()> {
+    let histogram = &histogram.by_display_name();
+    writeln!(out, " num     #instances         #bytes  class name")?;
+    writeln!(out, "----------------------------------------------")?;
+    let limit = limit.unwrap_or(histogram.entries.len());
+    for (i, entry) in histogram.entries.iter().take(limit).enumerate() {
+        let bytes = match entry.shallow_bytes {
+            Some(b) => b.to_string(),
+            None => "?".to_string(),
+        };
+        writeln!(
+            out,
+            "{:>4}: {:>14} {:>14}  {}",
+            i + 1,
+            entry.instances,
+            bytes,
+            entry.class_name
+        )?;
+    }
+    let total_bytes = match histogram.total_bytes() {
+        Some(b) => b.to_string(),
+        None => "?".to_string(),
+    };
+    writeln!(
+        out,
+        "Total {:>14} {:>14}",
+        histogram.total_instances(),
+        total_bytes
+    )
+}
+
+// How each class changed from histogram 'a' to histogram 'b', which can come from two dumps, two
+// live class_histogram() calls a few minutes apart, or one of each. Much quicker than comparing
+// object graphs, and usually enough to see what's growing. Sizes are only compared if both
+// histograms have them, so between live snapshots classes are ordered by instance count. Classes
+// are compared by display name, as hidden classes have different names in each run.
+pub fn histogram_diff(a: &Histogram, b: &Histogram) -> HistogramDiff {
+    let sized = a.total_bytes().is_some() && b.total_bytes().is_some();
+    let mut classes: BTreeMap<String, ClassDelta> = BTreeMap::new();
+    for (histogram, after) in [(a, false), (b, true)] {
+        for entry in &histogram.entries {
+            let class_name = display_name(&entry.class_name);
+            let delta = classes
+                .entry(class_name.clone())
+                .or_insert_with(|| ClassDelta {
+                    class_name,
+                    before_instances: 0,
+                    after_instances: 0,
+                    before_bytes: Some(0).filter(|_| sized),
+                    after_bytes: Some(0).filter(|_| sized),
+                });
+            let (instances, bytes) = if after {
+                (&mut delta.after_instances, &mut delta.after_bytes)
+            } else {
+                (&mut delta.before_instances, &mut delta.before_bytes)
+            };
+            *instances += entry.instances;
+            if let (Some(total), Some(size)) = (bytes.as_mut(), entry.shallow_bytes) {
+                *total += size;
+            }
+        }
+    }
+    let mut classes: Vec<ClassDelta> = classes.into_values().collect();
+    // Stable, so classes which grew the same stay in name order
+    classes.sort_by_key(|c| std::cmp::Reverse((c.byte_growth().unwrap_or(0), c.instance_growth())));
+    HistogramDiff { classes }
+}
+
+// The 'limit' classes which have grown the most (or all that have grown), then the totals
+pub fn write_histogram_diff<W: Write + ?Sized>(
+    diff: &HistogramDiff,
+    limit: Option<usize>,
+    out: &mut W,
+) -> Result<()> {
+    let bytes = |bytes: Option<i64>| match bytes {
+        Some(b) => format!("{:+}", b),
+        None => "?".to_string(),
+    };
+    writeln!(
+        out,
+        "      before       after     #instances         #bytes  class name"
+    )?;
+    writeln!(
+        out,
+        "-----------------------------------------------------------------"
+    )?;
+    let growing: Vec<_> = diff.growing().collect();
+    let limit = limit.unwrap_or(growing.len());
+    for class in growing.iter().take(limit) {
+        writeln!(
+            out,
+            "{:>12} {:>11} {:>+14} {:>14}  {}",
+            class.before_instances,
+            class.after_instances,
+            class.instance_growth(),
+            bytes(class.byte_growth()),
+            class.class_name
+        )?;
+    }
+    if growing.is_empty() {
+        writeln!(out, "No class has grown")?;
+    }
+    writeln!(
+        out,
+        "Total {:>+32} {:>14}",
+        diff.instance_growth(),
+        bytes(diff.byte_growth())
+    )
+}
+
+// The series as CSV, for a spreadsheet or a plot: a header of "time" and the class names, then a
+// row per sample. Times are in seconds since the Unix epoch.
+pub fn write_instance_series_csv<W: Write + ?Sized>(
+    series: &InstanceSeries,
+    out: &mut W,
+) -> Result<()> {
+    let classes = series.class_names();
+    let header: Vec<String> = classes.iter().map(|name| csv_field(name)).collect();
+    writeln!(out, "time,{}", header.join(","))?;
+    for sample in &series.samples {
+        let counts: Vec<String> = classes
+            .iter()
+            .map(|name| sample.count(name).to_string())
+            .collect();
+        let time = sample
+            .time
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs_f64();
+        writeln!(out, "{:.3},{}", time, counts.join(","))?;
+    }
+    Ok(())
+}
+
+// Quoted if it needs to be. Class names can't have commas or quotes in them, but hidden classes'
+// names are made up by the JVM.
+pub fn csv_field(field: &str) -> String {
+    if field.contains([',', '"', '\n']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_string()
+    }
+}
+
+// One sample in Prometheus's text format, as a jvm_class_instances gauge per class, e.g. for
+// node_exporter's textfile collector to pick up. 'time' adds the sample's timestamp, which
+// Prometheus only wants for samples it isn't scraping as they're taken.
+pub fn write_instance_sample_prometheus<W: Write + ?Sized>(
+    sample: &InstanceSample,
+    time: bool,
+    out: &mut W,
+) -> Result<()> {
+    writeln!(
+        out,
+        "# HELP jvm_class_instances Instances of the class on the heap, collected or not."
+    )?;
+    writeln!(out, "# TYPE jvm_class_instances gauge")?;
+    let timestamp = if time {
+        format!(" {}", millis_since_epoch(sample.time))
+    } else {
+        String::new()
+    };
+    for (class_name, count) in &sample.counts {
+        let label = class_name
+            .replace('\\', "\\\\")
+            .replace('"', "\\\"")
+            .replace('\n', "\\n");
+        writeln!(
+            out,
+            "jvm_class_instances{{class=\"{}\"}} {}{}",
+            label, count, timestamp
+        )?;
+    }
+    Ok(())
+}
+
+fn millis_since_epoch(time: SystemTime) -> u128 {
+    time.duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis())
+        .unwrap_or(0)
+}
+
+// Each pool's size and state, its workers, then the first of its queued tasks
+pub fn write_executor_report<W: Write + ?Sized>(
+    executors: &[ExecutorInfo],
+    out: &mut W,
+) -> Result<()> {
+    write_executor_report_annotated(executors, &Annotations::new(), out)
+}
+
+// Like write_executor_report(), with the labels in 'annotations'
+pub fn write_executor_report_annotated<W: Write + ?Sized>(
+    executors: &[ExecutorInfo],
+    annotations: &Annotations,
+    out: &mut W,
+) -> Result<()> {
+    let known = |n: Option<i64>| match n {
+        Some(n) => n.to_string(),
+        None => "?".to_string(),
+    };
+    for e in executors {
+        let sizes = match e.kind {
+            ExecutorKind::ThreadPool => format!(
+                "core {}, max {}",
+                known(e.core_pool_size),
+                known(e.max_pool_size)
+            ),
+            ExecutorKind::ForkJoin => format!("parallelism {}", known(e.core_pool_size)),
+        };
+        let active = known(e.active_workers.map(|n| n as i64));
+        writeln!(
+            out,
+            "
+{}@{:x}{}: {} of {} workers active ({}){}",
+            e.class_name,
+            e.executor,
+            annotations.object_suffix(e.executor),
+            active,
+            e.workers.len(),
+            sizes,
+            if e.shutdown == Some(true) {
+                ", shut down"
+            } else {
+                ""
+            }
+        )?;
+        let queue = match &e.queue_class {
+            Some(class) => format!(" in {}", class),
+            None => String::new(),
+        };
+        let completed = match e.completed_tasks {
+            Some(n) => format!(", {} completed", n),
+            None => String::new(),
+        };
+        let length = known(e.queue_length.map(|n| n as i64));
+        writeln!(out, "   {} queued{}{}", length, queue, completed)?;
+
+        let mut workers: Vec<String> = e
+            .workers
+            .iter()
+            .map(|w| {
+                let name = match (&w.thread_name, w.thread) {
+                    (Some(name), _) => format!("{}{}", name, annotations.thread_suffix(name)),
+                    (None, Some(thread)) => format!("@{:x}", thread),
+                    (None, None) => "(no thread)".to_string(),
+                };
+                match w.busy {
+                    Some(true) => format!("{} (busy)", name),
+                    _ => name,
+                }
+            })
+            .collect();
+        workers.sort();
+        if !workers.is_empty() {
+            writeln!(out, "   workers: {}", workers.join(", "))?;
+        }
+        for task in &e.queued {
+            let wrapped = match &task.wrapped_class_name {
+                Some(class) => format!(" running {}", class),
+                None => String::new(),
+            };
+            writeln!(
+                out,
+                "   queued: {}@{:x}{}{}",
+                task.class_name,
+                task.task,
+                annotations.object_suffix(task.task),
+                wrapped
+            )?;
+        }
+    }
+    if executors.is_empty() {
+        writeln!(out, "No thread pools")?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::executor::{PoolWorker, QueuedTask};
+    use crate::snapshot::{HistogramEntry, ThreadInfo};
+    use crate::source_link::SourceLinks;
+    use std::time::{Duration, UNIX_EPOCH};
+
+    fn histogram(entries: &[(&str, u64, Option<u64>)]) -> Histogram {
+        Histogram::new(
+            entries
+                .iter()
+                .map(|&(class_name, instances, shallow_bytes)| HistogramEntry {
+                    class_name: class_name.to_string(),
+                    instances,
+                    shallow_bytes,
+                })
+                .collect(),
+        )
+    }
+
+    #[test]
+    fn executor_report() {
+        let worker = |name: &str, busy| PoolWorker {
+            thread: Some(1),
+            thread_name: Some(name.to_string()),
+            busy: Some(busy),
+        };
+        let executor = ExecutorInfo {
+            executor: 0x10,
+            class_name: "java.util.concurrent.ThreadPoolExecutor".to_string(),
+            kind: ExecutorKind::ThreadPool,
+            core_pool_size: Some(2),
+            max_pool_size: Some(4),
+            shutdown: Some(false),
+            workers: vec![
+                worker("pool-1-thread-2", false),
+                worker("pool-1-thread-1", true),
+            ],
+            active_workers: Some(1),
+            completed_tasks: Some(12),
+            queue_class: Some("java.util.concurrent.LinkedBlockingQueue".to_string()),
+            queue_length: Some(7),
+            queued: vec![QueuedTask {
+                task: 0x20,
+                class_name: "java.util.concurrent.FutureTask".to_string(),
+                wrapped_class_name: Some("Job".to_string()),
+            }],
+        };
+        let mut annotations = Annotations::new();
+        annotations.label_thread("pool-1-thread-1", "stuck");
+        let mut out = vec![];
+        write_executor_report_annotated(&[executor], &annotations, &mut out).unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "\njava.util.concurrent.ThreadPoolExecutor@10: 1 of 2 workers active (core 2, max 4)\n\
+             \x20  7 queued in java.util.concurrent.LinkedBlockingQueue, 12 completed\n\
+             \x20  workers: pool-1-thread-1 [stuck] (busy), pool-1-thread-2\n\
+             \x20  queued: java.util.concurrent.FutureTask@20 running Job\n"
+        );
+    }
+
+    #[test]
+    fn diff_sorts_by_growth() {
+        let a = histogram(&[
+            ("java.lang.String", 100, Some(2400)),
+            ("[I", 10, Some(1000)),
+            ("Session", 5, Some(120)),
+        ]);
+        let b = histogram(&[
+            ("java.lang.String", 90, Some(2160)),
+            ("int[]", 12, Some(5000)),
+            ("Session", 50, Some(1200)),
+            ("Cache$Entry", 1, Some(32)),
+        ]);
+        let diff = histogram_diff(&a, &b);
+        let order: Vec<_> = diff.classes.iter().map(|c| c.class_name.as_str()).collect();
+        assert_eq!(
+            order,
+            ["int[]", "Session", "Cache$Entry", "java.lang.String"]
+        );
+        assert_eq!(diff.classes[0].byte_growth(), Some(4000));
+        assert_eq!(diff.classes[2].before_bytes, Some(0));
+        assert_eq!(diff.growing().count(), 3);
+        assert_eq!(diff.instance_growth(), 38);
+        assert_eq!(diff.byte_growth(), Some(4872));
+    }
+
+    #[test]
+    fn hidden_classes_by_display_name() {
+        let a = histogram(&[
+            (
+                "com.example.Worker$$Lambda$14/0x0000000800066840",
+                1,
+                Some(16),
+            ),
+            (
+                "com.example.Worker$$Lambda$15/0x0000000800066c40",
+                2,
+                Some(32),
+            ),
+        ]);
+        let b = histogram(&[("com/example/Worker$$Lambda+0x0000000800c02a00", 5, Some(80))]);
+        let diff = histogram_diff(&a, &b);
+        assert_eq!(diff.classes.len(), 1);
+        assert_eq!(diff.classes[0].class_name, "com.example.Worker$$Lambda");
+        assert_eq!(diff.classes[0].instance_growth(), 2);
+
+        let mut out = vec![];
+        write_histogram(&a, None, &mut out).unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            " num     #instances         #bytes  class name\n\
+             ----------------------------------------------\n\
+             \x20  1:              3             48  com.example.Worker$$Lambda\n\
+             Total              3             48\n"
+        );
+    }
+
+    #[test]
+    fn diff_without_sizes() {
+        let a = histogram(&[("Session", 5, None), ("Request", 8, None)]);
+        let b = histogram(&[("Session", 50, Some(1200)), ("Request", 9, Some(72))]);
+        let diff = histogram_diff(&a, &b);
+        assert_eq!(diff.classes[0].class_name, "Session");
+        assert_eq!(diff.classes[0].instance_growth(), 45);
+        assert_eq!(diff.classes[0].after_bytes, None);
+        assert_eq!(diff.byte_growth(), None);
+
+        let mut out = vec![];
+        write_histogram_diff(&diff, Some(1), &mut out).unwrap();
+        let out = String::from_utf8(out).unwrap();
+        assert!(out.contains("+45              ?  Session"));
+        assert!(!out.contains("Request"));
+    }
+
+    #[test]
+    fn captured_thread_dump() {
+        let lock = MonitorInfo {
+            object: 0x42,
+            class_name: "Cache".to_string(),
+        };
+        let thread = |thread_id, name: &str, state, method: &str| ThreadInfo {
+            stack: ThreadStack {
+                thread_id,
+                name: name.to_string(),
+                is_virtual: false,
+                frames: vec![FrameInfo {
+                    class_name: "Cache".to_string(),
+                    method_name: method.to_string(),
+                    line_number: Some(10),
+                }],
+                omitted_frames: 0,
+            },
+            state,
+            suspended: true,
+            owned_monitors: vec![],
+            contended_monitor: None,
+        };
+        let mut holder = thread(1, "loader", ThreadState::Running, "load");
+        holder.owned_monitors.push(lock.clone());
+        let mut waiter = thread(2, "reader", ThreadState::Monitor, "get");
+        waiter.contended_monitor = Some(lock);
+        waiter.suspended = false;
+        waiter.stack.omitted_frames = 3;
+        let dump = ThreadDump::new(
+            UNIX_EPOCH,
+            vec![holder, waiter],
+            true,
+            Duration::from_millis(12),
+        );
+
+        let mut out = vec![];
+        write_captured_thread_dump(&dump, &mut out).unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "Target suspended for 12ms\n\
+             \nThread 1: loader (running) stack=55344b857f32073a\n\
+             \x20  Cache.load(:10)\n\
+             \x20  - locked <0x42> (a Cache)\n\
+             \nThread 2: reader (blocked, not suspended) stack=c1d0ed813118f8c0\n\
+             \x20  Cache.get(:10)\n\
+             \x20  ... 3 more\n\
+             \x20  - waiting to lock <0x42> (a Cache) held by \"loader\"\n"
+        );
+    }
+    #[test]
+    fn instance_series() {
+        let sample = |secs, counts: &[(&str, u64)]| InstanceSample {
+            time: UNIX_EPOCH + Duration::from_secs(secs),
+            counts: counts.iter().map(|(c, n)| (c.to_string(), *n)).collect(),
+        };
+        let series = InstanceSeries {
+            samples: vec![
+                sample(100, &[("Session", 10)]),
+                sample(160, &[("Session", 25), ("Request$\"1\"", 3)]),
+            ],
+        };
+        assert_eq!(series.growth("Session"), 15);
+        assert_eq!(series.growth("Request$\"1\""), 3);
+
+        let mut out = vec![];
+        write_instance_series_csv(&series, &mut out).unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "time,\"Request$\"\"1\"\"\",Session\n100.000,0,10\n160.000,3,25\n"
+        );
+
+        let mut out = vec![];
+        write_instance_sample_prometheus(&series.samples[1], true, &mut out).unwrap();
+        let out = String::from_utf8(out).unwrap();
+        assert!(out.ends_with(
+            "jvm_class_instances{class=\"Request$\\\"1\\\"\"} 3 160000\n\
+             jvm_class_instances{class=\"Session\"} 25 160000\n"
+        ));
+    }
+
+    #[test]
+    fn lambda_frames() {
+        let frame = |class_name: &str, method_name: &str, line_number| FrameInfo {
+            class_name: class_name.to_string(),
+            method_name: method_name.to_string(),
+            line_number,
+        };
+        let stack = ThreadStack {
+            thread_id: 1,
+            name: "main".to_string(),
+            is_virtual: false,
+            frames: vec![
+                frame("java.lang.String", "length", None),
+                frame("Worker$$Lambda$15/0x0000000800c04000", "apply", None),
+                frame("Worker", "lambda$start$0", Some(12)),
+                frame("Worker$$Lambda/0x0000000800c03000", "run", None),
+                frame(
+                    "java.lang.invoke.LambdaForm$DMH/0x0000000800c08000",
+                    "invoke",
+                    None,
+                ),
+                frame("Worker", "start", Some(10)),
+            ],
+            omitted_frames: 0,
+        };
+        let annotations = Annotations::new();
+        let mut out = vec![];
+        write_stack_groups_annotated(std::slice::from_ref(&stack), &annotations, &mut out).unwrap();
+        let out = String::from_utf8(out).unwrap();
+        assert!(
+            out.ends_with(
+                "\x20  java.lang.String.length()\n\
+                 \x20  Worker$$Lambda.apply(-> java.lang.String.length)\n\
+                 \x20  Worker.lambda$start$0(:12)\n\
+                 \x20  Worker$$Lambda.run(-> lambda$start$0)\n\
+                 \x20  java.lang.invoke.LambdaForm$DMH.invoke()\n\
+                 \x20  Worker.start(:10)\n"
+            ),
+            "{}",
+            out
+        );
+
+        let collapsed = stack.without_hidden_frames();
+        let methods: Vec<&str> = collapsed
+            .frames
+            .iter()
+            .map(|f| f.method_name.as_str())
+            .collect();
+        assert_eq!(methods, ["length", "lambda$start$0", "start"]);
+    }
+
+    #[test]
+    fn source_links() {
+        let frame = |class_name: &str, line_number| FrameInfo {
+            class_name: class_name.to_string(),
+            method_name: "run".to_string(),
+            line_number,
+        };
+        let stack = ThreadStack {
+            thread_id: 1,
+            name: "main".to_string(),
+            is_virtual: false,
+            frames: vec![
+                frame("java.lang.Thread", Some(829)),
+                frame("com.example.Worker$1", Some(12)),
+                frame("com.example.Worker", None),
+            ],
+            omitted_frames: 0,
+        };
+        let mut annotations = Annotations::new();
+        annotations.set_source_links(Some(SourceLinks {
+            template: "https://git.example.com/{package}/{file}#{line}".to_string(),
+            packages: vec!["com.example".to_string()],
+        }));
+        let mut out = vec![];
+        write_stack_groups_annotated(&[stack], &annotations, &mut out).unwrap();
+        let out = String::from_utf8(out).unwrap();
+        assert!(
+            out.ends_with(
+                "\x20  java.lang.Thread.run(:829)\n\
+                 \x20  com.example.Worker$1.run(:12) \
+                 https://git.example.com/com/example/Worker.java#12\n\
+                 \x20  com.example.Worker.run()\n"
+            ),
+            "{}",
+            out
+        );
+    }
+
+    #[test]
+    fn exception() {
+        let line = |class_name: &str, method_name: &str, line_number| StackTraceLine {
+            module_name: None,
+            class_name: class_name.to_string(),
+            method_name: method_name.to_string(),
+            file_name: Some(format!("{}.java", class_name)),
+            line_number,
+        };
+        let mut run = line("Thread", "run", 829);
+        run.module_name = Some("java.base".to_string());
+        let exception = |object_id, class_name: &str, message: Option<&str>| ExceptionInfo {
+            object_id,
+            class_name: class_name.to_string(),
+            message: message.map(str::to_string),
+            stack_trace: None,
+            suppressed: vec![],
+            cause: None,
+            circular: false,
+        };
+        // Caused by an exception which was caused by it in turn
+        let mut circular = exception(1, "java.lang.IllegalStateException", Some("not started"));
+        circular.circular = true;
+        let mut cause = exception(2, "java.io.IOException", None);
+        cause.stack_trace = Some(vec![
+            line("Socket", "read0", -2),
+            line("Worker", "poll", 12),
+            run.clone(),
+        ]);
+        cause.cause = Some(Box::new(circular));
+        let mut close = exception(3, "java.io.IOException", Some("close failed"));
+        close.stack_trace = Some(vec![
+            line("Connection", "close", 7),
+            line("Worker", "start", 41),
+            run.clone(),
+        ]);
+        let mut thrown = exception(1, "java.lang.IllegalStateException", Some("not started"));
+        thrown.stack_trace = Some(vec![line("Worker", "start", 40), run]);
+        thrown.suppressed = vec![close];
+        thrown.cause = Some(Box::new(cause));
+
+        let mut out = vec![];
+        write_exception(&thrown, &mut out).unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "java.lang.IllegalStateException: not started\n\
+             \tat Worker.start(Worker.java:40)\n\
+             \tat java.base/Thread.run(Thread.java:829)\n\
+             \tSuppressed: java.io.IOException: close failed\n\
+             \t\tat Connection.close(Connection.java:7)\n\
+             \t\tat Worker.start(Worker.java:41)\n\
+             \t\t... 1 more\n\
+             Caused by: java.io.IOException\n\
+             \tat Socket.read0(Native Method)\n\
+             \tat Worker.poll(Worker.java:12)\n\
+             \t... 1 more\n\
+             Caused by: [CIRCULAR REFERENCE: java.lang.IllegalStateException: not started]\n"
+        );
+    }
+}
@@ -0,0 +1,166 @@
+//
+// Where reports and other artifacts (stall captures, script output) go. The writers in this module
+// take any Write, and write_to() writes any of them to a sink, but things which produce several
+// named artifacts on their own, like StallMonitor, scripts and fleet::write_thread_dumps(), need
+// somewhere to create them. The audit log, saved sessions and saved snapshots can go to a sink too.
+// By default that's a directory, but an embedder can supply its own sink to, say, upload each
+// artifact to a case-management system as it's finished.
+//
+// An artifact is complete once its writer is finished. Writers which are dropped without being
+// finished may leave a partial artifact behind (as a file would) or none at all.
+//
+
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufWriter, Error, ErrorKind, Result, Write};
+use std::path::PathBuf;
+
+pub trait OutputSink {
+    // A new artifact called 'name' (e.g. "stall-1700000000000-threads.txt"), replacing any
+    // existing one
+    fn create(&self, name: &str) -> Result<Box<dyn SinkWriter>>;
+
+    // Adds to the artifact called 'name', creating it if need be. Sinks which can't (most object
+    // stores can't) needn't implement this.
+    fn append(&self, name: &str) -> Result<Box<dyn SinkWriter>> {
+        Err(Error::new(
+            ErrorKind::Unsupported,
+            format!(
+                "Can't append to {}: this sink doesn't support appending",
+                name
+            ),
+        ))
+    }
+}
+
+pub trait SinkWriter: Write {
+    // Called once everything has been written. Returns where the artifact ended up, such as a
+    // path or a URL.
+    fn finish(self: Box<Self>) -> Result<String>;
+}
+
+// Writes 'name' to 'sink' with 'write', returning where it ended up. For example:
+//
+//   write_to(&sink, "histogram.txt", |out| write_histogram(&histogram, None, out))
+//
+pub fn write_to<S, F>(sink: &S, name: &str, write: F) -> Result<String>
+where
+    S: OutputSink + ?Sized,
+    F: FnOnce(&mut dyn Write) -> Result<()>,
+{
+    let mut out = sink.create(name)?;
+    write(&mut out)?;
+    out.finish()
+}
+
+// A file per artifact, in a directory which is created when the first artifact is. Names may
+// include subdirectories, and absolute names ignore the directory altogether.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DirectorySink {
+    dir: PathBuf,
+}
+
+impl DirectorySink {
+    pub fn new<P: Into<PathBuf>>(dir: P) -> DirectorySink {
+        DirectorySink { dir: dir.into() }
+    }
+
+    fn open(&self, name: &str, options: &OpenOptions) -> Result<Box<dyn SinkWriter>> {
+        let path = self.dir.join(name);
+        if let Some(parent) = path.parent().filter(|p| !p.as_os_str().is_empty()) {
+            fs::create_dir_all(parent)?;
+        }
+        let file = options.open(&path)?;
+        Ok(Box::new(FileWriter {
+            path,
+            out: BufWriter::new(file),
+        }))
+    }
+}
+
+impl OutputSink for DirectorySink {
+    fn create(&self, name: &str) -> Result<Box<dyn SinkWriter>> {
+        self.open(
+            name,
+            OpenOptions::new().write(true).create(true).truncate(true),
+        )
+    }
+
+    fn append(&self, name: &str) -> Result<Box<dyn SinkWriter>> {
+        self.open(name, OpenOptions::new().create(true).append(true))
+    }
+}
+
+struct FileWriter {
+    path: PathBuf,
+    out: BufWriter<File>,
+}
+
+impl Write for FileWriter {
+    fn write(&mut self, buf: &[u8]) -> Result<usize> {
+        self.out.write(buf)
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        self.out.flush()
+    }
+}
+
+impl SinkWriter for FileWriter {
+    fn finish(mut self: Box<Self>) -> Result<String> {
+        self.out.flush()?;
+        Ok(self.path.display().to_string())
+    }
+}
+
+// Everything to stdout, one artifact after another
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct StdoutSink;
+
+impl OutputSink for StdoutSink {
+    fn create(&self, _name: &str) -> Result<Box<dyn SinkWriter>> {
+        Ok(Box::new(StdoutWriter))
+    }
+
+    fn append(&self, name: &str) -> Result<Box<dyn SinkWriter>> {
+        self.create(name)
+    }
+}
+
+struct StdoutWriter;
+
+impl Write for StdoutWriter {
+    fn write(&mut self, buf: &[u8]) -> Result<usize> {
+        io::stdout().write(buf)
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        io::stdout().flush()
+    }
+}
+
+impl SinkWriter for StdoutWriter {
+    fn finish(self: Box<Self>) -> Result<String> {
+        io::stdout().flush()?;
+        Ok("-".to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn directory_sink() {
+        let dir = std::env::temp_dir().join(format!("libjdb-sink-{}", std::process::id()));
+        let sink = DirectorySink::new(&dir);
+        let path = write_to(&sink, "captures/a.txt", |out| writeln!(out, "one")).unwrap();
+        assert_eq!(path, dir.join("captures/a.txt").display().to_string());
+        let mut out = sink.append("captures/a.txt").unwrap();
+        writeln!(out, "two").unwrap();
+        out.finish().unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), "one\ntwo\n");
+        write_to(&sink, "captures/a.txt", |out| writeln!(out, "three")).unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), "three\n");
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,617 @@
+//
+// Plain data captured from a JVM (live or from a dump). Unlike the model handles, these don't
+// hold on to a connection, so they can be kept around, compared, and written out after the
+// target is gone. With the "serde" feature they can also be saved, and loaded again somewhere
+// else (see libjdb::saved).
+//
+
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::io::Result;
+use std::time::{Duration, SystemTime};
+
+use crate::model::{
+    JavaVirtualMachine, Location, ObjectReference, ReferenceType, StackFrame, ThreadReference,
+    TypeComponent,
+};
+use crate::names::display_name;
+use crate::thread_filter::ThreadFilter;
+
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct HistogramEntry {
+    pub class_name: String,
+    pub instances: u64,
+    // The total shallow size of the instances, if the backend knows it
+    pub shallow_bytes: Option<u64>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Histogram {
+    // Sorted by size (or, if sizes aren't known, instance count), largest first
+    pub entries: Vec<HistogramEntry>,
+}
+
+impl Histogram {
+    pub fn new(mut entries: Vec<HistogramEntry>) -> Histogram {
+        entries.sort_by(|a, b| {
+            (b.shallow_bytes, b.instances, &a.class_name).cmp(&(
+                a.shallow_bytes,
+                a.instances,
+                &b.class_name,
+            ))
+        });
+        Histogram { entries }
+    }
+
+    pub fn total_instances(&self) -> u64 {
+        self.entries.iter().map(|e| e.instances).sum()
+    }
+
+    pub fn total_bytes(&self) -> Option<u64> {
+        self.entries.iter().map(|e| e.shallow_bytes).sum()
+    }
+
+    // The entries under their display names (see names::display_name()), with the classes which
+    // share one added together, e.g. every lambda written in one class
+    pub fn by_display_name(&self) -> Histogram {
+        let mut merged: BTreeMap<String, HistogramEntry> = BTreeMap::new();
+        for entry in &self.entries {
+            let class_name = display_name(&entry.class_name);
+            let total = merged
+                .entry(class_name.clone())
+                .or_insert_with(|| HistogramEntry {
+                    class_name,
+                    instances: 0,
+                    shallow_bytes: Some(0),
+                });
+            total.instances += entry.instances;
+            total.shallow_bytes = total
+                .shallow_bytes
+                .zip(entry.shallow_bytes)
+                .map(|(a, b)| a + b);
+        }
+        Histogram::new(merged.into_values().collect())
+    }
+}
+
+// How one class changed between two histograms (see report::histogram_diff())
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ClassDelta {
+    // In the Java source form, e.g. java.lang.String[] for arrays
+    pub class_name: String,
+    pub before_instances: u64,
+    pub after_instances: u64,
+    // None unless both histograms know sizes
+    pub before_bytes: Option<u64>,
+    pub after_bytes: Option<u64>,
+}
+
+impl ClassDelta {
+    // Negative if the class shrank
+    pub fn instance_growth(&self) -> i64 {
+        self.after_instances as i64 - self.before_instances as i64
+    }
+
+    pub fn byte_growth(&self) -> Option<i64> {
+        Some(self.after_bytes? as i64 - self.before_bytes? as i64)
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct HistogramDiff {
+    // Every class in either histogram, biggest growth (in bytes if known, otherwise instances)
+    // first
+    pub classes: Vec<ClassDelta>,
+}
+
+impl HistogramDiff {
+    // The classes with more instances (or, if sizes are known, bytes) than before
+    pub fn growing(&self) -> impl Iterator<Item = &ClassDelta> {
+        self.classes
+            .iter()
+            .filter(|c| c.byte_growth().unwrap_or_else(|| c.instance_growth()) > 0)
+    }
+
+    pub fn instance_growth(&self) -> i64 {
+        self.classes.iter().map(|c| c.instance_growth()).sum()
+    }
+
+    pub fn byte_growth(&self) -> Option<i64> {
+        self.classes.iter().map(|c| c.byte_growth()).sum()
+    }
+}
+
+// The instances of a few classes counted at one moment, see InstanceSeries
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct InstanceSample {
+    pub time: SystemTime,
+    // By class name. Classes which weren't loaded yet have no entry.
+    pub counts: BTreeMap<String, u64>,
+}
+
+impl InstanceSample {
+    pub fn count(&self, class_name: &str) -> u64 {
+        self.counts.get(class_name).copied().unwrap_or(0)
+    }
+}
+
+// The same classes counted again and again (see JdwpJavaVirtualMachine::instance_sampler()),
+// which shows a leak growing without taking one heap dump after another
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct InstanceSeries {
+    // Oldest first
+    pub samples: Vec<InstanceSample>,
+}
+
+impl InstanceSeries {
+    // Every class in any of the samples, sorted by name
+    pub fn class_names(&self) -> Vec<&str> {
+        let names: BTreeSet<&str> = self
+            .samples
+            .iter()
+            .flat_map(|s| s.counts.keys().map(String::as_str))
+            .collect();
+        names.into_iter().collect()
+    }
+
+    // How many more instances of the class there were in the last sample than in the first.
+    // Negative if it shrank.
+    pub fn growth(&self, class_name: &str) -> i64 {
+        match (self.samples.first(), self.samples.last()) {
+            (Some(first), Some(last)) => {
+                last.count(class_name) as i64 - first.count(class_name) as i64
+            }
+            _ => 0,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct FrameInfo {
+    pub class_name: String,
+    pub method_name: String,
+    pub line_number: Option<u32>,
+}
+
+// Classes which are only plumbing between one frame worth seeing and the next: method handle
+// adapters, and the accessors reflection generates
+const HIDDEN_CLASS_PREFIXES: &[&str] = &[
+    "java.lang.invoke.LambdaForm$",
+    "java.lang.invoke.DirectMethodHandle$Holder",
+    "java.lang.invoke.DelegatingMethodHandle$Holder",
+    "java.lang.invoke.Invokers$Holder",
+    "jdk.internal.reflect.GeneratedMethodAccessor",
+    "jdk.internal.reflect.GeneratedConstructorAccessor",
+    "jdk.internal.reflect.NativeMethodAccessorImpl",
+    "jdk.internal.reflect.DelegatingMethodAccessorImpl",
+    "jdk.internal.reflect.DirectMethodHandleAccessor",
+    "sun.reflect.GeneratedMethodAccessor",
+    "sun.reflect.GeneratedConstructorAccessor",
+    "sun.reflect.NativeMethodAccessorImpl",
+    "sun.reflect.DelegatingMethodAccessorImpl",
+];
+
+impl FrameInfo {
+    // For a frame in one of the classes the JVM generates for lambdas and method references, e.g.
+    // com.example.Worker$$Lambda$14/0x0000000800066840 (or, since JDK 21, without the $14), the
+    // class the lambda was written in. Its method just calls the next frame in, which is the
+    // lambda's body (or the method referred to).
+    pub fn lambda_host(&self) -> Option<&str> {
+        self.class_name
+            .find("$$Lambda")
+            .map(|end| &self.class_name[..end])
+    }
+
+    // Whether the frame is one of the JVM's own plumbing (see HIDDEN_CLASS_PREFIXES) or a
+    // lambda's generated class, which a stack is easier to read without
+    pub fn is_hidden(&self) -> bool {
+        self.lambda_host().is_some()
+            || HIDDEN_CLASS_PREFIXES
+                .iter()
+                .any(|prefix| self.class_name.starts_with(prefix))
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ThreadStack {
+    pub thread_id: u64,
+    pub name: String,
+    pub is_virtual: bool,
+    // Innermost frame first
+    pub frames: Vec<FrameInfo>,
+    // How many frames there were below the last of 'frames', when only the top of the stack was
+    // captured (see JdwpJavaVirtualMachine::capture_shallow_thread_dump())
+    pub omitted_frames: usize,
+}
+
+impl ThreadStack {
+    // Live threads need to be suspended
+    pub fn capture<Jvm: JavaVirtualMachine>(thread: &Jvm::ThreadReference) -> Result<ThreadStack> {
+        let mut frames = vec![];
+        for frame in thread.frames() {
+            let location = frame?.location()?;
+            frames.push(FrameInfo {
+                class_name: location.declaring_type()?.name()?,
+                method_name: location.method()?.name()?,
+                line_number: location.line_number()?,
+            });
+        }
+        Ok(ThreadStack {
+            thread_id: thread.unique_id()?,
+            name: thread.name()?,
+            is_virtual: thread.is_virtual()?,
+            frames,
+            omitted_frames: 0,
+        })
+    }
+
+    // See frames_hash()
+    pub fn frames_hash(&self) -> u64 {
+        frames_hash(&self.frames)
+    }
+
+    // The stack with its hidden frames (see FrameInfo::is_hidden()) left out, which keeps stacks
+    // heavy with streams and lambdas readable
+    pub fn without_hidden_frames(&self) -> ThreadStack {
+        ThreadStack {
+            frames: self
+                .frames
+                .iter()
+                .filter(|frame| !frame.is_hidden())
+                .cloned()
+                .collect(),
+            ..self.clone()
+        }
+    }
+
+    // Every thread's stack. Live targets need to be suspended.
+    pub fn capture_all<Jvm: JavaVirtualMachine>(jvm: &Jvm) -> Result<Vec<ThreadStack>> {
+        ThreadStack::capture_matching(jvm, &ThreadFilter::all())
+    }
+
+    // The stacks of the threads 'filter' lets through, without walking the others'
+    pub fn capture_matching<Jvm: JavaVirtualMachine>(
+        jvm: &Jvm,
+        filter: &ThreadFilter,
+    ) -> Result<Vec<ThreadStack>> {
+        let mut stacks = vec![];
+        for thread in jvm.all_threads() {
+            let thread = thread?;
+            if filter.is_all() || filter.matches(&thread.name()?) {
+                stacks.push(ThreadStack::capture::<Jvm>(&thread)?);
+            }
+        }
+        Ok(stacks)
+    }
+}
+
+// What a thread was doing when it was captured
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum ThreadState {
+    Running,
+    // In Thread.sleep()
+    Sleeping,
+    // Blocked waiting to enter a monitor
+    Monitor,
+    // In Object.wait(), or parked
+    Wait,
+    // Finished, or not started yet
+    Zombie,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct MonitorInfo {
+    pub object: u64,
+    pub class_name: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ThreadInfo {
+    pub stack: ThreadStack,
+    pub state: ThreadState,
+    // Whether the thread had actually stopped when it was captured. A thread running native code
+    // carries on until it comes back to Java, so its stack may be out of date by the time it's
+    // read.
+    pub suspended: bool,
+    // The monitors the thread holds, and the one it's blocked on or waiting for, if the target
+    // can tell us (see ThreadDump::has_monitors())
+    pub owned_monitors: Vec<MonitorInfo>,
+    pub contended_monitor: Option<MonitorInfo>,
+}
+
+// Every thread, captured with the whole VM suspended, so unlike a series of separate calls the
+// stacks, states and monitors all agree with each other. See
+// JdwpJavaVirtualMachine::capture_thread_dump().
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ThreadDump {
+    time: SystemTime,
+    threads: Vec<ThreadInfo>,
+    has_monitors: bool,
+    suspended_for: Duration,
+}
+
+impl ThreadDump {
+    pub fn new(
+        time: SystemTime,
+        threads: Vec<ThreadInfo>,
+        has_monitors: bool,
+        suspended_for: Duration,
+    ) -> ThreadDump {
+        ThreadDump {
+            time,
+            threads,
+            has_monitors,
+            suspended_for,
+        }
+    }
+
+    // When the VM was suspended
+    pub fn time(&self) -> SystemTime {
+        self.time
+    }
+
+    // How long the VM was kept suspended to take the dump, which is how long the application was
+    // paused for
+    pub fn suspended_for(&self) -> Duration {
+        self.suspended_for
+    }
+
+    pub fn threads(&self) -> &[ThreadInfo] {
+        &self.threads
+    }
+
+    pub fn thread(&self, thread_id: u64) -> Option<&ThreadInfo> {
+        self.threads.iter().find(|t| t.stack.thread_id == thread_id)
+    }
+
+    // False if the target can't report which monitors threads hold, in which case every thread's
+    // monitors are empty
+    pub fn has_monitors(&self) -> bool {
+        self.has_monitors
+    }
+
+    // Puts 'stack' in place of the stack of the thread it belongs to, e.g. the whole stack of a
+    // thread from a shallow dump (see JdwpJavaVirtualMachine::capture_full_stack()), which is from
+    // later than the rest of the dump. False if the dump doesn't have the thread.
+    pub fn replace_stack(&mut self, stack: ThreadStack) -> bool {
+        match self
+            .threads
+            .iter_mut()
+            .find(|t| t.stack.thread_id == stack.thread_id)
+        {
+            Some(thread) => {
+                thread.stack = stack;
+                true
+            }
+            None => false,
+        }
+    }
+
+    // Whether any stack was cut short, see ThreadStack::omitted_frames
+    pub fn is_shallow(&self) -> bool {
+        self.threads.iter().any(|t| t.stack.omitted_frames > 0)
+    }
+
+    // Just the stacks, e.g. for group_stacks()
+    pub fn stacks(&self) -> Vec<ThreadStack> {
+        self.threads.iter().map(|t| t.stack.clone()).collect()
+    }
+
+    // The dump with every stack's hidden frames left out, see
+    // ThreadStack::without_hidden_frames()
+    pub fn without_hidden_frames(&self) -> ThreadDump {
+        let mut dump = self.clone();
+        for thread in &mut dump.threads {
+            thread.stack = thread.stack.without_hidden_frames();
+        }
+        dump
+    }
+
+    // The thread holding 'monitor', if any
+    pub fn monitor_owner(&self, monitor: u64) -> Option<&ThreadInfo> {
+        self.threads
+            .iter()
+            .find(|t| t.owned_monitors.iter().any(|m| m.object == monitor))
+    }
+}
+
+// One element of a Throwable's stack trace, as java.lang.StackTraceElement has it
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct StackTraceLine {
+    // e.g. java.base. None for classes which aren't in a named module, and before JDK 9.
+    pub module_name: Option<String>,
+    pub class_name: String,
+    pub method_name: String,
+    pub file_name: Option<String>,
+    // As Java has it: negative if unknown, and -2 for native methods
+    pub line_number: i32,
+}
+
+// A Throwable, as printStackTrace() would show it
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ExceptionInfo {
+    pub object_id: u64,
+    pub class_name: String,
+    pub message: Option<String>,
+    // Innermost first. None if the JVM hasn't filled the trace in yet, which it only does when
+    // something asks for it.
+    pub stack_trace: Option<Vec<StackTraceLine>>,
+    // Those added with addSuppressed(), e.g. by try-with-resources when closing failed too
+    pub suppressed: Vec<ExceptionInfo>,
+    pub cause: Option<Box<ExceptionInfo>>,
+    // This has been shown further up already, as a cause or suppressed exception of itself or of
+    // one of its causes, so its trace, suppressed exceptions and cause are left out
+    pub circular: bool,
+}
+
+impl ExceptionInfo {
+    // This exception, then its cause, then its cause's cause and so on
+    pub fn chain(&self) -> impl Iterator<Item = &ExceptionInfo> {
+        std::iter::successors(Some(self), |e| e.cause.as_deref())
+    }
+}
+
+// Threads with identical stacks, which is most of the threads in a typical thread pool
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct StackGroup {
+    // (thread ID, name), sorted by name
+    pub threads: Vec<(u64, String)>,
+    pub frames: Vec<FrameInfo>,
+}
+
+impl StackGroup {
+    // See frames_hash()
+    pub fn frames_hash(&self) -> u64 {
+        frames_hash(&self.frames)
+    }
+}
+
+// A hash of a stack's class and method names and line numbers, which is the same from one
+// capture, run or host to the next as long as the code is, so that logs of many captures can be
+// grouped by stack. Classes are hashed by their display name, since hidden classes' names (a
+// lambda's, say) are different in every run. This is FNV-1a, since the standard library's
+// hashers are free to change between releases. Reports write it as 16 hex digits.
+pub fn frames_hash(frames: &[FrameInfo]) -> u64 {
+    const OFFSET_BASIS: u64 = 0xcbf2_9ce4_8422_2325;
+    const PRIME: u64 = 0x0000_0100_0000_01b3;
+    let mut hash = OFFSET_BASIS;
+    let mut add = |bytes: &[u8]| {
+        for &b in bytes {
+            hash = (hash ^ u64::from(b)).wrapping_mul(PRIME);
+        }
+    };
+    for frame in frames {
+        add(display_name(&frame.class_name).as_bytes());
+        add(b".");
+        add(frame.method_name.as_bytes());
+        match frame.line_number {
+            Some(line) => add(format!(":{}", line).as_bytes()),
+            None => add(b":"),
+        }
+        add(b"\n");
+    }
+    hash
+}
+
+// Largest groups first. Groups of the same size are sorted by their first thread's name, so the
+// order doesn't change from one capture to the next unless the threads do.
+pub fn group_stacks(stacks: &[ThreadStack]) -> Vec<StackGroup> {
+    let mut groups: HashMap<&[FrameInfo], Vec<(u64, String)>> = HashMap::new();
+    for stack in stacks {
+        groups
+            .entry(&stack.frames)
+            .or_default()
+            .push((stack.thread_id, stack.name.clone()));
+    }
+    let mut groups: Vec<StackGroup> = groups
+        .into_iter()
+        .map(|(frames, mut threads)| {
+            threads.sort_by(|a, b| (&a.1, a.0).cmp(&(&b.1, b.0)));
+            StackGroup {
+                threads,
+                frames: frames.to_vec(),
+            }
+        })
+        .collect();
+    groups.sort_by(|a, b| {
+        (b.threads.len(), &a.threads[0].1, a.threads[0].0).cmp(&(
+            a.threads.len(),
+            &b.threads[0].1,
+            b.threads[0].0,
+        ))
+    });
+    groups
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn stack(thread_id: u64, name: &str, methods: &[&str]) -> ThreadStack {
+        ThreadStack {
+            thread_id,
+            name: name.to_string(),
+            is_virtual: false,
+            frames: methods
+                .iter()
+                .map(|m| FrameInfo {
+                    class_name: "Worker".to_string(),
+                    method_name: m.to_string(),
+                    line_number: Some(1),
+                })
+                .collect(),
+            omitted_frames: 0,
+        }
+    }
+
+    #[test]
+    fn grouping() {
+        let stacks = vec![
+            stack(4, "pool-2", &["park", "take"]),
+            stack(1, "main", &["run"]),
+            stack(3, "pool-1", &["park", "take"]),
+            stack(2, "gc", &[]),
+        ];
+        let groups = group_stacks(&stacks);
+        assert_eq!(groups.len(), 3);
+        assert_eq!(
+            groups[0].threads,
+            vec![(3, "pool-1".to_string()), (4, "pool-2".to_string())]
+        );
+        assert_eq!(groups[0].frames, stacks[0].frames);
+        assert_eq!(groups[1].threads, vec![(2, "gc".to_string())]);
+        assert_eq!(groups[2].threads, vec![(1, "main".to_string())]);
+    }
+
+    #[test]
+    fn hashing() {
+        let a = stack(1, "pool-1", &["park", "take"]);
+        let b = stack(2, "pool-2", &["park", "take"]);
+        assert_eq!(a.frames_hash(), b.frames_hash());
+        assert_ne!(
+            a.frames_hash(),
+            stack(1, "pool-1", &["take", "park"]).frames_hash()
+        );
+        let mut moved = a.clone();
+        moved.frames[0].line_number = Some(2);
+        assert_ne!(a.frames_hash(), moved.frames_hash());
+        // The hash is written to logs, so it mustn't change
+        assert_eq!(frames_hash(&[]), 0xcbf2_9ce4_8422_2325);
+        let frame = FrameInfo {
+            class_name: "Cache".to_string(),
+            method_name: "load".to_string(),
+            line_number: Some(10),
+        };
+        assert_eq!(frames_hash(&[frame]), 0x5534_4b85_7f32_073a);
+    }
+
+    #[test]
+    fn hashing_lambdas() {
+        let lambda = |class_name: &str| {
+            let mut stack = stack(1, "main", &["run", "lambda$main$0"]);
+            stack.frames[0].class_name = class_name.to_string();
+            stack
+        };
+        let a = lambda("Worker$$Lambda/0x0000000800c02a00");
+        let b = lambda("Worker$$Lambda/0x0000000800066840");
+        assert_eq!(a.frames_hash(), b.frames_hash());
+        assert_ne!(
+            a.frames_hash(),
+            lambda("Reader$$Lambda/0x0000000800c02a00").frames_hash()
+        );
+    }
+}
@@ -0,0 +1,136 @@
+//
+// Links from stack frames to the code they're in, so that a thread dump pasted into chat takes
+// whoever reads it straight to the line. Links are made from a template, in which
+//
+//   {package}   the package of the frame's class as a path, e.g. com/example/server
+//   {class}     the top level class it's nested in, e.g. RequestHandler
+//   {file}      the source file, taken to be {class}.java unless the caller knows better
+//   {line}      the line number
+//
+// are replaced, e.g. for GitHub:
+//
+//   https://github.com/example/server/blob/main/src/main/java/{package}/{file}#L{line}
+//
+// Classes in the default package have no {package}, and the '/' after it goes too, so that the
+// link is to src/main/java/Main.java rather than src/main/java//Main.java.
+//
+// Frames without a line number (native methods, classes compiled without debug information) get
+// no link, and nor do frames outside the template's packages, which are most likely in the JDK
+// or a library the template doesn't point into.
+//
+
+use crate::snapshot::FrameInfo;
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SourceLinks {
+    pub template: String,
+    // The packages to link, and the packages under them, e.g. "com.example". Empty for every
+    // class.
+    pub packages: Vec<String>,
+}
+
+impl SourceLinks {
+    pub fn new(template: &str) -> SourceLinks {
+        SourceLinks {
+            template: template.to_string(),
+            packages: vec![],
+        }
+    }
+
+    pub fn link(&self, frame: &FrameInfo) -> Option<String> {
+        self.link_to(&frame.class_name, None, frame.line_number)
+    }
+
+    // For when the class's source file is known, e.g. from the target
+    pub fn link_to(
+        &self,
+        class_name: &str,
+        source_file: Option<&str>,
+        line_number: Option<u32>,
+    ) -> Option<String> {
+        let line = line_number?;
+        if !self.packages.is_empty()
+            && !self.packages.iter().any(|package| {
+                class_name
+                    .strip_prefix(package.as_str())
+                    .is_some_and(|rest| rest.starts_with('.'))
+            })
+        {
+            return None;
+        }
+        // Nested, local and generated classes are all in their top level class's file
+        let outer = class_name.split('$').next().unwrap_or(class_name);
+        let (package, class) = match outer.rsplit_once('.') {
+            Some((package, class)) => (package.replace('.', "/"), class),
+            None => (String::new(), outer),
+        };
+        let file = match source_file {
+            Some(file) => file.to_string(),
+            None => format!("{}.java", class),
+        };
+        let template = if package.is_empty() {
+            self.template.replace("{package}/", "")
+        } else {
+            self.template.clone()
+        };
+        Some(
+            template
+                .replace("{package}", &package)
+                .replace("{class}", class)
+                .replace("{file}", &file)
+                .replace("{line}", &line.to_string()),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::SourceLinks;
+    use crate::snapshot::FrameInfo;
+
+    const GITHUB: &str = "https://github.com/example/server/blob/main/{package}/{file}#L{line}";
+
+    fn frame(class_name: &str, line_number: Option<u32>) -> FrameInfo {
+        FrameInfo {
+            class_name: class_name.to_string(),
+            method_name: "run".to_string(),
+            line_number,
+        }
+    }
+
+    #[test]
+    fn links() {
+        let links = SourceLinks::new(GITHUB);
+        assert_eq!(
+            links.link(&frame("com.example.Worker$Task", Some(42))),
+            Some("https://github.com/example/server/blob/main/com/example/Worker.java#L42".into())
+        );
+        assert_eq!(
+            links.link(&frame("Main", Some(7))),
+            Some("https://github.com/example/server/blob/main/Main.java#L7".into())
+        );
+        assert_eq!(links.link(&frame("com.example.Worker", None)), None);
+        assert_eq!(
+            links.link_to("com.example.UtilKt", Some("Util.kt"), Some(3)),
+            Some("https://github.com/example/server/blob/main/com/example/Util.kt#L3".into())
+        );
+
+        let search = SourceLinks::new("https://code.example.com/search?q={class}+line:{line}");
+        assert_eq!(
+            search.link(&frame("com.example.Worker", Some(1))),
+            Some("https://code.example.com/search?q=Worker+line:1".into())
+        );
+    }
+
+    #[test]
+    fn packages() {
+        let links = SourceLinks {
+            template: GITHUB.to_string(),
+            packages: vec!["com.example".to_string()],
+        };
+        assert!(links.link(&frame("com.example.Worker", Some(1))).is_some());
+        assert!(links.link(&frame("com.example.db.Pool", Some(1))).is_some());
+        assert!(links.link(&frame("com.examples.Worker", Some(1))).is_none());
+        assert!(links.link(&frame("java.lang.Thread", Some(1))).is_none());
+    }
+}
@@ -0,0 +1,115 @@
+//
+// Picking threads by name for the operations which go through every thread (thread dumps, stall
+// sampling), so that a tool only interested in, say, request handlers doesn't pay the round
+// trips for the dozens of GC and compiler threads every JVM has. The patterns are globs, in which
+// '*' matches any run of characters (including none) and anything else only itself:
+//
+//   http-nio-*           Tomcat's request handlers
+//   GC Thread*           and "GC task thread#0 (ParallelGC)" on older JVMs
+//   *                    any thread
+//
+// A thread is included if it matches one of the include patterns (or there aren't any) and none
+// of the exclude patterns. Unlike class patterns, names are matched as they are, since thread
+// names are free text.
+//
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ThreadFilter {
+    // Empty for every thread
+    pub include: Vec<String>,
+    pub exclude: Vec<String>,
+}
+
+impl ThreadFilter {
+    // Every thread
+    pub fn all() -> ThreadFilter {
+        ThreadFilter::default()
+    }
+
+    pub fn is_all(&self) -> bool {
+        self.include.is_empty() && self.exclude.is_empty()
+    }
+
+    pub fn matches(&self, thread_name: &str) -> bool {
+        (self.include.is_empty() || self.include.iter().any(|p| glob_matches(p, thread_name)))
+            && !self.exclude.iter().any(|p| glob_matches(p, thread_name))
+    }
+}
+
+fn glob_matches(pattern: &str, name: &str) -> bool {
+    let mut parts = pattern.split('*');
+    // Without a '*' there's only the one part, which has to be the whole name
+    let first = parts.next().unwrap_or("");
+    let mut rest = match name.strip_prefix(first) {
+        Some(rest) => rest,
+        None => return false,
+    };
+    let parts: Vec<&str> = parts.collect();
+    let (last, middle) = match parts.split_last() {
+        Some(split) => split,
+        None => return rest.is_empty(),
+    };
+    // Taking the first place each middle part fits leaves the most room for the ones after it
+    for part in middle {
+        match rest.find(part) {
+            Some(at) => rest = &rest[at + part.len()..],
+            None => return false,
+        }
+    }
+    rest.ends_with(last)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::ThreadFilter;
+
+    fn filter(include: &[&str], exclude: &[&str]) -> ThreadFilter {
+        ThreadFilter {
+            include: include.iter().map(|p| p.to_string()).collect(),
+            exclude: exclude.iter().map(|p| p.to_string()).collect(),
+        }
+    }
+
+    #[test]
+    fn globs() {
+        let matches = |pattern: &str, name: &str| filter(&[pattern], &[]).matches(name);
+        assert!(matches("main", "main"));
+        assert!(!matches("main", "main-2"));
+        assert!(matches("http-nio-*", "http-nio-8080-exec-1"));
+        assert!(!matches("http-nio-*", "https-nio-8443-exec-1"));
+        assert!(matches("*exec*", "http-nio-8080-exec-1"));
+        assert!(matches("*-exec-*", "pool-1-exec-12"));
+        assert!(matches(
+            "GC task thread#* (ParallelGC)",
+            "GC task thread#3 (ParallelGC)"
+        ));
+        assert!(!matches(
+            "GC task thread#* (ParallelGC)",
+            "GC task thread#3 (G1)"
+        ));
+        assert!(matches("a*a", "aa"));
+        assert!(!matches("a*a", "a"));
+        assert!(matches("*", ""));
+        // Nothing but '*' is special
+        assert!(!matches("worker-?", "worker-1"));
+        assert!(matches("[main]", "[main]"));
+    }
+
+    #[test]
+    fn include_and_exclude() {
+        assert!(ThreadFilter::all().is_all());
+        assert!(ThreadFilter::all().matches("anything"));
+
+        let handlers = filter(&["http-nio-*", "https-*"], &["*-Poller", "*-Acceptor"]);
+        assert!(!handlers.is_all());
+        assert!(handlers.matches("http-nio-8080-exec-3"));
+        assert!(handlers.matches("https-jsse-nio-8443-exec-1"));
+        assert!(!handlers.matches("http-nio-8080-Poller"));
+        assert!(!handlers.matches("main"));
+
+        let quiet = filter(&[], &["GC *", "C1 CompilerThread*", "C2 CompilerThread*"]);
+        assert!(quiet.matches("main"));
+        assert!(!quiet.matches("GC Thread#0"));
+        assert!(!quiet.matches("C2 CompilerThread0"));
+    }
+}
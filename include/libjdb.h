@@ -0,0 +1,120 @@
+#ifndef LIBJDB_H
+#define LIBJDB_H
+
+/* Generated by cbindgen from src/capi.rs. Don't edit by hand. */
+
+#include <stdbool.h>
+#include <stddef.h>
+#include <stdint.h>
+
+typedef enum JdbError {
+  JDB_ERROR_OK = 0,
+  JDB_ERROR_INVALID_ARGUMENT = 1,
+  JDB_ERROR_IO = 2,
+  JDB_ERROR_TARGET = 3,
+  JDB_ERROR_NOT_FOUND = 4,
+  JDB_ERROR_INTERNAL = 5,
+  JDB_ERROR_STALE_HANDLE = 6,
+} JdbError;
+
+typedef struct JdbJvm JdbJvm;
+
+typedef struct JdbThread JdbThread;
+
+typedef struct JdbFrame {
+  char *class_name;
+  char *method_name;
+  int32_t line_number;
+} JdbFrame;
+
+typedef enum JdbValue_Tag {
+  JDB_VALUE_BOOLEAN,
+  JDB_VALUE_BYTE,
+  JDB_VALUE_CHAR,
+  JDB_VALUE_SHORT,
+  JDB_VALUE_INTEGER,
+  JDB_VALUE_LONG,
+  JDB_VALUE_FLOAT,
+  JDB_VALUE_DOUBLE,
+  JDB_VALUE_OBJECT,
+  JDB_VALUE_NULL,
+  JDB_VALUE_VOID,
+} JdbValue_Tag;
+
+typedef struct JdbValue {
+  JdbValue_Tag tag;
+  union {
+    struct {
+      bool boolean;
+    };
+    struct {
+      int8_t byte;
+    };
+    struct {
+      uint16_t char_;
+    };
+    struct {
+      int16_t short_;
+    };
+    struct {
+      int32_t integer;
+    };
+    struct {
+      int64_t long_;
+    };
+    struct {
+      float float_;
+    };
+    struct {
+      double double_;
+    };
+    struct {
+      uint64_t object;
+    };
+  };
+} JdbValue;
+
+#ifdef __cplusplus
+extern "C" {
+#endif // __cplusplus
+
+const char *jdb_last_error_message(void);
+
+void jdb_string_free(char *s);
+
+enum JdbError jdb_attach(const char *address, struct JdbJvm **jvm_out);
+
+enum JdbError jdb_detach(struct JdbJvm *jvm);
+
+enum JdbError jdb_suspend(const struct JdbJvm *jvm);
+
+enum JdbError jdb_resume(const struct JdbJvm *jvm);
+
+enum JdbError jdb_all_threads(const struct JdbJvm *jvm,
+                              struct JdbThread ***threads_out,
+                              size_t *count_out);
+
+void jdb_threads_free(struct JdbThread **threads, size_t count);
+
+enum JdbError jdb_thread_id(const struct JdbThread *thread, uint64_t *id_out);
+
+enum JdbError jdb_thread_name(const struct JdbThread *thread, char **name_out);
+
+enum JdbError jdb_thread_is_virtual(const struct JdbThread *thread, bool *is_virtual_out);
+
+enum JdbError jdb_stack_trace(const struct JdbThread *thread,
+                              struct JdbFrame **frames_out,
+                              size_t *count_out);
+
+void jdb_frames_free(struct JdbFrame *frames, size_t count);
+
+enum JdbError jdb_static_value(const struct JdbJvm *jvm,
+                               const char *class_name,
+                               const char *field_name,
+                               struct JdbValue *value_out);
+
+#ifdef __cplusplus
+}  // extern "C"
+#endif  // __cplusplus
+
+#endif  /* LIBJDB_H */
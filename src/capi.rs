@@ -0,0 +1,384 @@
+//
+// C API, so that libjdb can be embedded in debuggers and tools which aren't written in Rust. The
+// header is include/libjdb.h, which is generated from this file by cbindgen; run
+//
+//   cbindgen --config cbindgen.toml --output include/libjdb.h
+//
+// after changing anything here. The conventions are:
+//
+//  - Handles (JdbJvm, JdbThread) are opaque pointers, released with the matching function.
+//  - Every function returns a JdbError. Results go in out parameters, which are only written on
+//    success. A description of the last error on the calling thread is available from
+//    jdb_last_error_message().
+//  - Strings are NUL terminated UTF-8, both ways. Strings returned by the library must be released
+//    with jdb_string_free().
+//  - All the handles obtained from one attached JVM share its connection, so they must only be
+//    used from one thread at a time. Using them after the JVM has been detached fails with
+//    JDB_ERROR_STALE_HANDLE.
+//
+// Pointers passed in must be NULL or valid, and handles must not be used after being released;
+// that's the whole of the safety contract for the unsafe functions here.
+//
+#![allow(clippy::missing_safety_doc)]
+
+use std::cell::RefCell;
+use std::convert::TryInto;
+use std::ffi::{CStr, CString};
+use std::io;
+use std::os::raw::c_char;
+use std::panic::{self, AssertUnwindSafe};
+use std::ptr;
+
+use crate::jdwp::{self, JdwpErrorKind, JdwpJavaVirtualMachine, JdwpThreadReference};
+use crate::model::{
+    JavaVirtualMachine, Location, ObjectReference, ReferenceType, StackFrame, ThreadReference,
+    TypeComponent, Value,
+};
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum JdbError {
+    Ok = 0,
+    // A NULL pointer, or a string which isn't valid UTF-8
+    InvalidArgument = 1,
+    // Couldn't talk to the target (e.g. the connection was refused or lost)
+    Io = 2,
+    // The target reported an error for a command
+    Target = 3,
+    // The class or field asked for doesn't exist
+    NotFound = 4,
+    // A bug in libjdb
+    Internal = 5,
+    // A handle was used after its JVM was detached, or the target exited
+    StaleHandle = 6,
+}
+
+pub struct JdbJvm {
+    jvm: JdwpJavaVirtualMachine,
+}
+
+pub struct JdbThread {
+    thread: JdwpThreadReference,
+}
+
+#[repr(C)]
+pub struct JdbFrame {
+    pub class_name: *mut c_char,
+    pub method_name: *mut c_char,
+    // -1 if unknown (e.g. native methods)
+    pub line_number: i32,
+}
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum JdbValue {
+    Boolean(bool),
+    Byte(i8),
+    // A UTF-16 code unit
+    Char(u16),
+    Short(i16),
+    Integer(i32),
+    Long(i64),
+    Float(f32),
+    Double(f64),
+    // An object ID, which is only meaningful to the target
+    Object(u64),
+    Null,
+    Void,
+}
+
+struct CallError {
+    code: JdbError,
+    msg: String,
+}
+
+impl From<io::Error> for CallError {
+    fn from(e: io::Error) -> Self {
+        let code = if jdwp::jdwp_error_kind(&e) == Some(JdwpErrorKind::StaleHandle) {
+            JdbError::StaleHandle
+        } else if jdwp::jdwp_error_code(&e).is_some() {
+            JdbError::Target
+        } else {
+            JdbError::Io
+        };
+        CallError {
+            code,
+            msg: e.to_string(),
+        }
+    }
+}
+
+fn error(code: JdbError, msg: &str) -> CallError {
+    CallError {
+        code,
+        msg: msg.to_string(),
+    }
+}
+
+thread_local! {
+    static LAST_ERROR: RefCell<Option<CString>> = const { RefCell::new(None) };
+}
+
+// Runs the body of an API function, recording any error (or panic, which mustn't unwind into C)
+// for jdb_last_error_message()
+fn ffi_call<F: FnOnce() -> Result<(), CallError>>(f: F) -> JdbError {
+    let result = match panic::catch_unwind(AssertUnwindSafe(f)) {
+        Ok(result) => result,
+        Err(_) => Err(error(JdbError::Internal, "libjdb panicked")),
+    };
+    let (code, msg) = match result {
+        Ok(()) => (JdbError::Ok, None),
+        // Interior NULs can't be represented, but shouldn't ever be in an error message anyway
+        Err(e) => (e.code, CString::new(e.msg.replace('\0', "")).ok()),
+    };
+    LAST_ERROR.with(|last| *last.borrow_mut() = msg);
+    code
+}
+
+unsafe fn str_arg<'a>(s: *const c_char) -> Result<&'a str, CallError> {
+    if s.is_null() {
+        return Err(error(JdbError::InvalidArgument, "NULL string"));
+    }
+    CStr::from_ptr(s)
+        .to_str()
+        .map_err(|_| error(JdbError::InvalidArgument, "String is not valid UTF-8"))
+}
+
+unsafe fn handle_arg<'a, T>(handle: *const T) -> Result<&'a T, CallError> {
+    handle
+        .as_ref()
+        .ok_or_else(|| error(JdbError::InvalidArgument, "NULL handle"))
+}
+
+unsafe fn write_out<T>(out: *mut T, value: T) -> Result<(), CallError> {
+    if out.is_null() {
+        return Err(error(JdbError::InvalidArgument, "NULL out parameter"));
+    }
+    out.write(value);
+    Ok(())
+}
+
+fn to_c_string(s: String) -> *mut c_char {
+    // Java strings can contain NULs, which we have no way to return
+    CString::new(s.replace('\0', ""))
+        .expect("NULs were removed")
+        .into_raw()
+}
+
+// Hand a Vec to C as a pointer and length. It must be freed with free_boxed_slice().
+fn into_raw_parts<T>(v: Vec<T>) -> (*mut T, usize) {
+    let len = v.len();
+    (Box::into_raw(v.into_boxed_slice()) as *mut T, len)
+}
+
+unsafe fn free_boxed_slice<T>(ptr: *mut T, len: usize) -> Vec<T> {
+    if ptr.is_null() {
+        return vec![];
+    }
+    Box::from_raw(ptr::slice_from_raw_parts_mut(ptr, len)).into_vec()
+}
+
+// The message for the last error returned to the calling thread, or NULL if the last call
+// succeeded. The string is owned by the library, and is valid until the next call on this thread.
+#[no_mangle]
+pub extern "C" fn jdb_last_error_message() -> *const c_char {
+    LAST_ERROR.with(|last| match &*last.borrow() {
+        Some(msg) => msg.as_ptr(),
+        None => ptr::null(),
+    })
+}
+
+// Release a string returned by the library. NULL is ignored.
+#[no_mangle]
+pub unsafe extern "C" fn jdb_string_free(s: *mut c_char) {
+    if !s.is_null() {
+        drop(CString::from_raw(s));
+    }
+}
+
+// Attach to a JVM listening for a debugger at address ("host:port")
+#[no_mangle]
+pub unsafe extern "C" fn jdb_attach(address: *const c_char, jvm_out: *mut *mut JdbJvm) -> JdbError {
+    ffi_call(|| {
+        let jvm = crate::attach_live(str_arg(address)?)?;
+        write_out(jvm_out, Box::into_raw(Box::new(JdbJvm { jvm })))
+    })
+}
+
+// Detach from the JVM, leaving it running, and release the handle. The handle is released even if
+// this fails. NULL is ignored.
+#[no_mangle]
+pub unsafe extern "C" fn jdb_detach(jvm: *mut JdbJvm) -> JdbError {
+    ffi_call(|| {
+        if jvm.is_null() {
+            return Ok(());
+        }
+        Ok(Box::from_raw(jvm).jvm.dispose()?)
+    })
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn jdb_suspend(jvm: *const JdbJvm) -> JdbError {
+    ffi_call(|| Ok(handle_arg(jvm)?.jvm.suspend()?))
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn jdb_resume(jvm: *const JdbJvm) -> JdbError {
+    ffi_call(|| Ok(handle_arg(jvm)?.jvm.resume()?))
+}
+
+// Get handles for all the live threads in the JVM. The array must be released with
+// jdb_threads_free().
+#[no_mangle]
+pub unsafe extern "C" fn jdb_all_threads(
+    jvm: *const JdbJvm,
+    threads_out: *mut *mut *mut JdbThread,
+    count_out: *mut usize,
+) -> JdbError {
+    ffi_call(|| {
+        let jvm = handle_arg(jvm)?;
+        if threads_out.is_null() || count_out.is_null() {
+            return Err(error(JdbError::InvalidArgument, "NULL out parameter"));
+        }
+        let threads: Vec<*mut JdbThread> = jvm
+            .jvm
+            .all_threads_vec()?
+            .into_iter()
+            .map(|thread| Box::into_raw(Box::new(JdbThread { thread })))
+            .collect();
+        let (ptr, len) = into_raw_parts(threads);
+        write_out(threads_out, ptr)?;
+        write_out(count_out, len)
+    })
+}
+
+// Release an array returned by jdb_all_threads(), and all the handles in it
+#[no_mangle]
+pub unsafe extern "C" fn jdb_threads_free(threads: *mut *mut JdbThread, count: usize) {
+    for thread in free_boxed_slice(threads, count) {
+        if !thread.is_null() {
+            drop(Box::from_raw(thread));
+        }
+    }
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn jdb_thread_id(thread: *const JdbThread, id_out: *mut u64) -> JdbError {
+    ffi_call(|| write_out(id_out, handle_arg(thread)?.thread.unique_id()?))
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn jdb_thread_name(
+    thread: *const JdbThread,
+    name_out: *mut *mut c_char,
+) -> JdbError {
+    ffi_call(|| {
+        let name = handle_arg(thread)?.thread.name()?;
+        write_out(name_out, to_c_string(name))
+    })
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn jdb_thread_is_virtual(
+    thread: *const JdbThread,
+    is_virtual_out: *mut bool,
+) -> JdbError {
+    ffi_call(|| write_out(is_virtual_out, handle_arg(thread)?.thread.is_virtual()?))
+}
+
+// Get the stack of a suspended thread, innermost frame first. The array must be released with
+// jdb_frames_free().
+#[no_mangle]
+pub unsafe extern "C" fn jdb_stack_trace(
+    thread: *const JdbThread,
+    frames_out: *mut *mut JdbFrame,
+    count_out: *mut usize,
+) -> JdbError {
+    ffi_call(|| {
+        let thread = handle_arg(thread)?;
+        if frames_out.is_null() || count_out.is_null() {
+            return Err(error(JdbError::InvalidArgument, "NULL out parameter"));
+        }
+        let mut frames = vec![];
+        for frame in thread.thread.frames() {
+            let location = frame?.location()?;
+            let class_name = location.declaring_type()?.name()?;
+            let method_name = location.method()?.name()?;
+            let line_number = match location.line_number()? {
+                Some(n) => n.try_into().unwrap_or(i32::MAX),
+                None => -1,
+            };
+            frames.push(JdbFrame {
+                class_name: to_c_string(class_name),
+                method_name: to_c_string(method_name),
+                line_number,
+            });
+        }
+        let (ptr, len) = into_raw_parts(frames);
+        write_out(frames_out, ptr)?;
+        write_out(count_out, len)
+    })
+}
+
+// Release an array returned by jdb_stack_trace(), and the strings in it
+#[no_mangle]
+pub unsafe extern "C" fn jdb_frames_free(frames: *mut JdbFrame, count: usize) {
+    for frame in free_boxed_slice(frames, count) {
+        jdb_string_free(frame.class_name);
+        jdb_string_free(frame.method_name);
+    }
+}
+
+// Read the value of a static field. If several class loaders have loaded a class with this name,
+// the first one which has the field is used.
+#[no_mangle]
+pub unsafe extern "C" fn jdb_static_value(
+    jvm: *const JdbJvm,
+    class_name: *const c_char,
+    field_name: *const c_char,
+    value_out: *mut JdbValue,
+) -> JdbError {
+    ffi_call(|| {
+        let jvm = handle_arg(jvm)?;
+        let class_name = str_arg(class_name)?;
+        let field_name = str_arg(field_name)?;
+        let classes = jvm.jvm.classes_by_name(class_name)?;
+        if classes.is_empty() {
+            return Err(error(
+                JdbError::NotFound,
+                &format!("No loaded class named {}", class_name),
+            ));
+        }
+        for class in classes {
+            for field in class.fields() {
+                let field = field?;
+                if field.name()? == field_name {
+                    return write_out(value_out, class.get_value(&field)?.into());
+                }
+            }
+        }
+        Err(error(
+            JdbError::NotFound,
+            &format!("{} has no field named {}", class_name, field_name),
+        ))
+    })
+}
+
+impl From<Value> for JdbValue {
+    fn from(value: Value) -> Self {
+        match value {
+            Value::Boolean(b) => JdbValue::Boolean(b),
+            Value::Byte(b) => JdbValue::Byte(b),
+            Value::Char(c) => JdbValue::Char(c),
+            Value::Short(s) => JdbValue::Short(s),
+            Value::Integer(i) => JdbValue::Integer(i),
+            Value::Long(l) => JdbValue::Long(l),
+            Value::Float(f) => JdbValue::Float(f),
+            Value::Double(d) => JdbValue::Double(d),
+            // Class objects are objects like any other to C, which keeps the tags as they were
+            Value::Object(id) | Value::ClassObject(id) => JdbValue::Object(id),
+            Value::Null => JdbValue::Null,
+            Value::Void => JdbValue::Void,
+        }
+    }
+}
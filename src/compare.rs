@@ -0,0 +1,76 @@
+//
+// Comparing a heap dump with the live JVM it was taken from. A dump is a single moment, so it
+// can't say whether the thousands of instances of some class are a leak or just a busy period;
+// counting them again in the live VM a while later can. Only instance counts are compared, since
+// JDWP can't tell us sizes.
+//
+
+use std::collections::HashMap;
+use std::io::Result;
+
+use crate::hprof::HprofJavaVirtualMachine;
+use crate::jdwp::JdwpJavaVirtualMachine;
+use crate::model::JavaVirtualMachine;
+use crate::names::source_name;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ClassGrowth {
+    // In the Java source form, e.g. java.lang.String[] for arrays
+    pub class_name: String,
+    pub dump_instances: u64,
+    pub live_instances: u64,
+}
+
+impl ClassGrowth {
+    // Negative if there are fewer instances now than in the dump
+    pub fn growth(&self) -> i64 {
+        self.live_instances as i64 - self.dump_instances as i64
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct LiveComparison {
+    // Every class with instances in either, biggest growth first
+    pub classes: Vec<ClassGrowth>,
+}
+
+impl LiveComparison {
+    // The classes with more instances now than in the dump
+    pub fn growing(&self) -> impl Iterator<Item = &ClassGrowth> {
+        self.classes.iter().filter(|c| c.growth() > 0)
+    }
+}
+
+// Count the instances of every class in 'jvm' and compare them with 'heap', which should be a
+// dump of the same process. Garbage which hasn't been collected yet is counted on both sides (the
+// dump includes unreachable objects unless it was taken with 'live'), so for a fair comparison
+// either force a GC first or look only at large, steady growth.
+pub fn compare_live_to_dump(
+    jvm: &JdwpJavaVirtualMachine,
+    heap: &HprofJavaVirtualMachine,
+) -> Result<LiveComparison> {
+    let mut counts: HashMap<String, (u64, u64)> = HashMap::new();
+    for entry in heap.class_histogram()?.entries {
+        counts.entry(source_name(&entry.class_name)).or_default().0 += entry.instances;
+    }
+    for entry in jvm.class_histogram()?.entries {
+        counts.entry(source_name(&entry.class_name)).or_default().1 += entry.instances;
+    }
+
+    // Dumps list classes separately rather than as instances of java.lang.Class, so its count
+    // is meaningless
+    counts.remove("java.lang.Class");
+
+    let mut classes: Vec<ClassGrowth> = counts
+        .into_iter()
+        .map(
+            |(class_name, (dump_instances, live_instances))| ClassGrowth {
+                class_name,
+                dump_instances,
+                live_instances,
+            },
+        )
+        .collect();
+    classes.sort_by(|a, b| (b.growth(), &a.class_name).cmp(&(a.growth(), &b.class_name)));
+    Ok(LiveComparison { classes })
+}
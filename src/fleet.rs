@@ -0,0 +1,168 @@
+//
+// Capturing from many targets at once, e.g. every instance of a service during an incident. Each
+// target gets a thread of its own, since a connection can't be shared between threads, and so
+// that one slow or unreachable target doesn't hold up the rest. write_thread_dumps() then writes
+// the dumps to an OutputSink, an artifact per target.
+//
+
+use std::io::{Result, Write};
+use std::thread;
+
+use crate::attach_live;
+use crate::report::{write_to, OutputSink};
+use crate::snapshot::ThreadDump;
+use crate::thread_filter::ThreadFilter;
+
+#[derive(Debug)]
+pub struct TargetDump {
+    // As given to capture_thread_dumps()
+    pub address: String,
+    pub dump: Result<ThreadDump>,
+}
+
+impl TargetDump {
+    // A name for the target's file, e.g. stacks-host1-8000.txt for host1:8000
+    pub fn file_name(&self) -> String {
+        self.file_name_with("txt")
+    }
+
+    fn file_name_with(&self, extension: &str) -> String {
+        let target: String = self
+            .address
+            .chars()
+            .map(|c| match c {
+                'a'..='z' | 'A'..='Z' | '0'..='9' | '.' | '-' | '_' => c,
+                _ => '-',
+            })
+            .collect();
+        format!("stacks-{}.{}", target, extension)
+    }
+}
+
+// Writes each dump to 'sink' with 'write' (e.g. report::write_captured_thread_dump()), named as
+// file_name() has it but with 'extension' rather than txt. The results are in the same order as
+// the targets, each with the target's address and where its dump ended up, or why it couldn't be
+// captured or written.
+pub fn write_thread_dumps<S, F>(
+    targets: Vec<TargetDump>,
+    sink: &S,
+    extension: &str,
+    mut write: F,
+) -> Vec<(String, Result<String>)>
+where
+    S: OutputSink + ?Sized,
+    F: FnMut(&ThreadDump, &mut dyn Write) -> Result<()>,
+{
+    targets
+        .into_iter()
+        .map(|target| {
+            let name = target.file_name_with(extension);
+            let written = target
+                .dump
+                .and_then(|dump| write_to(sink, &name, |out| write(&dump, out)));
+            (target.address, written)
+        })
+        .collect()
+}
+
+// Attach to each address in parallel, take a thread dump (see
+// JdwpJavaVirtualMachine::capture_thread_dump()) and detach again. The results are in the same
+// order as the addresses, and a target which couldn't be reached has the error instead.
+pub fn capture_thread_dumps(addresses: &[String]) -> Vec<TargetDump> {
+    capture_each(addresses, None, &ThreadFilter::all())
+}
+
+// capture_thread_dumps() with only the top 'depth' frames of each stack (see
+// JdwpJavaVirtualMachine::capture_shallow_thread_dump()), for targets with so many threads that
+// a full dump would pause them for too long
+pub fn capture_shallow_thread_dumps(addresses: &[String], depth: usize) -> Vec<TargetDump> {
+    capture_each(addresses, Some(depth), &ThreadFilter::all())
+}
+
+// capture_thread_dumps(), or capture_shallow_thread_dumps() given a depth, with only the threads
+// 'filter' lets through (see JdwpJavaVirtualMachine::set_thread_filter())
+pub fn capture_filtered_thread_dumps(
+    addresses: &[String],
+    depth: Option<usize>,
+    filter: &ThreadFilter,
+) -> Vec<TargetDump> {
+    capture_each(addresses, depth, filter)
+}
+
+fn capture_each(
+    addresses: &[String],
+    depth: Option<usize>,
+    filter: &ThreadFilter,
+) -> Vec<TargetDump> {
+    let handles: Vec<_> = addresses
+        .iter()
+        .map(|address| {
+            let address = address.clone();
+            let filter = filter.clone();
+            thread::spawn(move || {
+                let jvm = attach_live(address.as_str())?;
+                jvm.set_thread_filter(filter);
+                let dump = match depth {
+                    Some(depth) => jvm.capture_shallow_thread_dump(depth),
+                    None => jvm.capture_thread_dump(),
+                };
+                // The dump is just as good if we couldn't detach cleanly
+                let _ = jvm.dispose();
+                dump
+            })
+        })
+        .collect();
+    addresses
+        .iter()
+        .zip(handles)
+        .map(|(address, handle)| TargetDump {
+            address: address.clone(),
+            dump: handle
+                .join()
+                .unwrap_or_else(|_| Err(std::io::Error::other("capture panicked"))),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::io::{Error, ErrorKind};
+    use std::time::{Duration, UNIX_EPOCH};
+
+    use super::*;
+    use crate::report::DirectorySink;
+
+    #[test]
+    fn write_to_sink() {
+        let dir = std::env::temp_dir().join(format!("libjdb-fleet-{}", std::process::id()));
+        let sink = DirectorySink::new(&dir);
+        let dump = ThreadDump::new(UNIX_EPOCH, vec![], false, Duration::from_millis(3));
+        let targets = vec![
+            TargetDump {
+                address: "host1:8000".to_string(),
+                dump: Ok(dump),
+            },
+            TargetDump {
+                address: "host2:8000".to_string(),
+                dump: Err(Error::new(ErrorKind::ConnectionRefused, "refused")),
+            },
+        ];
+        let written = write_thread_dumps(targets, &sink, "txt", |dump, out| {
+            writeln!(out, "{} threads", dump.threads().len())
+        });
+        assert_eq!(written.len(), 2);
+        let (address, path) = &written[0];
+        assert_eq!(address, "host1:8000");
+        let path = path.as_ref().unwrap();
+        assert!(path.ends_with("stacks-host1-8000.txt"), "{}", path);
+        assert_eq!(fs::read_to_string(path).unwrap(), "0 threads\n");
+        let (address, err) = &written[1];
+        assert_eq!(address, "host2:8000");
+        assert_eq!(
+            err.as_ref().unwrap_err().kind(),
+            ErrorKind::ConnectionRefused
+        );
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
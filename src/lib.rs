@@ -1,25 +1,26 @@
-use crate::jdwp::JdwpJavaVirtualMachine;
-use jdwp::JdwpConnection;
-use model::JavaVirtualMachine;
-use std::io::Result;
-use std::net::ToSocketAddrs;
+//
+// libjdb is a facade over the crates in crates/ (see Cargo.toml), so that programs which only
+// read heap dumps can depend on hprof-core alone, and so on. The facade adds what needs more than
+// one of them: comparing dumps with live JVMs, capturing from many targets at once, scripts,
+// reports and the C and Python APIs.
+//
 
-#[macro_use]
-extern crate num_derive;
+// jdwp::protocol, the low-level JDWP API, is versioned on its own (see there). Everything else
+// follows this crate's version.
+pub use hprof_core::{hprof, open_hprof, open_hprofs, validate_hprof};
+pub use jdwp_core::{attach, attach_live, attach_live_with_options, expr, jdwp, jfr};
+pub use libjdb_model::{
+    annotation, executor, model, mutf8, names, pattern, snapshot, source_link, thread_filter,
+};
 
-// These shouldn't be 'pub' long term, maybe?
-pub mod hprof;
-pub mod jdwp;
-pub mod model;
-
-//fn foo<A: ToSocketAddrs>(jvm_debug_addr: A) -> Box<dyn ThreadReference> {
-//    let jdwpJvm = attach_live(jvm_debug_addr).unwrap();
-//    jdwpJvm.all_threads().unwrap()[0]
-//}
-
-// TODO get rid of boxing?
-pub fn attach_live<A: ToSocketAddrs>(jvm_debug_addr: A) -> Result<JdwpJavaVirtualMachine> {
-    Ok(JdwpJavaVirtualMachine::new(JdwpConnection::new(
-        jvm_debug_addr,
-    )?))
-}
+#[cfg(feature = "capi")]
+pub mod capi;
+pub mod compare;
+pub mod fleet;
+#[cfg(feature = "python")]
+mod python;
+pub mod report;
+#[cfg(feature = "serde")]
+pub mod saved;
+#[cfg(feature = "script")]
+pub mod script;
@@ -0,0 +1,375 @@
+//
+// Python bindings, built with the 'python' feature (e.g. with 'maturin develop'). The module is
+// called 'libjdb':
+//
+//   import libjdb
+//   jvm = libjdb.attach_live("localhost:8000")
+//   jvm.suspend()
+//   for thread in jvm.threads():
+//       print(thread.unique_id, thread.name)
+//       for frame in thread.frames():
+//           print("   ", frame)
+//   jvm.resume()
+//   jvm.detach()
+//
+// Heap dumps are opened with libjdb.open_hprof(path), and their threads and frames are the same
+// classes as a live target's:
+//
+//   dump = libjdb.open_hprof("app.hprof")
+//   for thread in dump.threads():
+//       print(thread.name, [str(frame) for frame in thread.frames()])
+//
+// Everything is a thin wrapper around the Rust model types. Like those, the handles share the
+// connection to the target (or the open dump), so they aren't thread safe; Python raises an error
+// if they are used from a thread other than the one which created them. Objects, e.g. methods'
+// return values, are given as their IDs.
+//
+
+use pyo3::exceptions::PyValueError;
+use pyo3::prelude::*;
+use pyo3::IntoPyObjectExt;
+use std::io::{Error, ErrorKind};
+use std::time::Duration;
+
+use crate::hprof::{HprofJavaVirtualMachine, HprofLocation, HprofThreadReference};
+use crate::jdwp::{
+    JdwpJavaVirtualMachine, JdwpLocation, JdwpThreadReference, MethodTrace, MethodTraceKind,
+};
+use crate::model::{
+    JavaVirtualMachine, Location, ObjectReference, ReferenceType, StackFrame, ThreadReference,
+    TypeComponent, Value,
+};
+use crate::pattern::ClassPattern;
+use crate::snapshot::Histogram;
+
+#[pymodule]
+fn libjdb(m: &Bound<'_, PyModule>) -> PyResult<()> {
+    m.add_function(wrap_pyfunction!(attach_live, m)?)?;
+    m.add_function(wrap_pyfunction!(open_hprof, m)?)?;
+    m.add_class::<PyJvm>()?;
+    m.add_class::<PyHeapDump>()?;
+    m.add_class::<PyThread>()?;
+    m.add_class::<PyStackFrame>()?;
+    m.add_class::<PyMethodTrace>()?;
+    m.add_class::<PyMethodTraceEvent>()?;
+    Ok(())
+}
+
+#[pyfunction]
+fn attach_live(address: &str) -> PyResult<PyJvm> {
+    Ok(PyJvm {
+        jvm: Some(crate::attach_live(address)?),
+    })
+}
+
+#[pyfunction]
+fn open_hprof(path: &str) -> PyResult<PyHeapDump> {
+    Ok(PyHeapDump {
+        dump: crate::open_hprof(path)?,
+    })
+}
+
+#[pyclass(unsendable, name = "JavaVirtualMachine")]
+struct PyJvm {
+    // None once detached
+    jvm: Option<JdwpJavaVirtualMachine>,
+}
+
+impl PyJvm {
+    fn jvm(&self) -> PyResult<&JdwpJavaVirtualMachine> {
+        match &self.jvm {
+            Some(jvm) => Ok(jvm),
+            None => Err(Error::new(ErrorKind::NotConnected, "Detached from the JVM").into()),
+        }
+    }
+}
+
+#[pymethods]
+impl PyJvm {
+    fn threads(&self) -> PyResult<Vec<PyThread>> {
+        let threads = self.jvm()?.all_threads_vec()?;
+        Ok(threads
+            .into_iter()
+            .map(|thread| PyThread {
+                thread: Thread::Live(thread),
+            })
+            .collect())
+    }
+
+    fn suspend(&self) -> PyResult<()> {
+        Ok(self.jvm()?.suspend()?)
+    }
+
+    fn resume(&self) -> PyResult<()> {
+        Ok(self.jvm()?.resume()?)
+    }
+
+    #[getter]
+    fn can_be_modified(&self) -> PyResult<bool> {
+        Ok(self.jvm()?.can_be_modified())
+    }
+
+    // A list of (class name, instances, shallow bytes or None), largest first
+    fn class_histogram(&self) -> PyResult<Vec<(String, u64, Option<u64>)>> {
+        Ok(histogram_entries(self.jvm()?.class_histogram()?))
+    }
+
+    #[pyo3(signature = (class_pattern, rate_limit=None))]
+    fn trace_methods(
+        &self,
+        class_pattern: &str,
+        rate_limit: Option<u32>,
+    ) -> PyResult<PyMethodTrace> {
+        let pattern =
+            ClassPattern::new(class_pattern).map_err(|e| PyValueError::new_err(e.to_string()))?;
+        let mut trace = self.jvm()?.trace_methods(&pattern)?;
+        if let Some(max) = rate_limit {
+            trace = trace.with_rate_limit(max);
+        }
+        Ok(PyMethodTrace { trace })
+    }
+
+    // Close the connection, leaving the target running. Handles obtained from this JVM can't be
+    // used afterwards.
+    fn detach(&mut self) -> PyResult<()> {
+        match self.jvm.take() {
+            Some(jvm) => Ok(jvm.dispose()?),
+            None => Ok(()),
+        }
+    }
+}
+
+// The threads in a dump are those which had a stack trace in it, which is usually all of them
+#[pyclass(unsendable, name = "HeapDump")]
+struct PyHeapDump {
+    dump: HprofJavaVirtualMachine,
+}
+
+#[pymethods]
+impl PyHeapDump {
+    fn threads(&self) -> PyResult<Vec<PyThread>> {
+        let threads = self.dump.all_threads_vec()?;
+        Ok(threads
+            .into_iter()
+            .map(|thread| PyThread {
+                thread: Thread::Dump(thread),
+            })
+            .collect())
+    }
+
+    // As JavaVirtualMachine.class_histogram() has it, always with shallow bytes
+    fn class_histogram(&self) -> PyResult<Vec<(String, u64, Option<u64>)>> {
+        Ok(histogram_entries(self.dump.class_histogram()?))
+    }
+}
+
+fn histogram_entries(histogram: Histogram) -> Vec<(String, u64, Option<u64>)> {
+    histogram
+        .entries
+        .into_iter()
+        .map(|e| (e.class_name, e.instances, e.shallow_bytes))
+        .collect()
+}
+
+// Threads and frames from either backend, which Python sees as the same classes
+enum Thread {
+    Live(JdwpThreadReference),
+    Dump(HprofThreadReference),
+}
+
+enum FrameLocation {
+    Live(JdwpLocation),
+    Dump(HprofLocation),
+}
+
+// 'call' on whichever backend's handle is in 'value', named 'handle'
+macro_rules! either {
+    ($value:expr, $kind:ident, $handle:ident => $call:expr) => {
+        match $value {
+            $kind::Live($handle) => $call,
+            $kind::Dump($handle) => $call,
+        }
+    };
+}
+
+#[pyclass(unsendable, name = "ThreadReference")]
+struct PyThread {
+    thread: Thread,
+}
+
+#[pymethods]
+impl PyThread {
+    #[getter]
+    fn unique_id(&self) -> PyResult<u64> {
+        Ok(either!(&self.thread, Thread, thread => thread.unique_id())?)
+    }
+
+    #[getter]
+    fn name(&self) -> PyResult<String> {
+        Ok(either!(&self.thread, Thread, thread => thread.name())?)
+    }
+
+    #[getter]
+    fn is_virtual(&self) -> PyResult<bool> {
+        Ok(either!(&self.thread, Thread, thread => thread.is_virtual())?)
+    }
+
+    // A live target's thread must be suspended
+    fn frames(&self) -> PyResult<Vec<PyStackFrame>> {
+        match &self.thread {
+            Thread::Live(thread) => frames::<JdwpJavaVirtualMachine>(thread, FrameLocation::Live),
+            Thread::Dump(thread) => frames::<HprofJavaVirtualMachine>(thread, FrameLocation::Dump),
+        }
+    }
+
+    fn __repr__(&self) -> PyResult<String> {
+        Ok(format!(
+            "<ThreadReference {}: {}>",
+            self.unique_id()?,
+            self.name()?
+        ))
+    }
+}
+
+fn frames<Jvm: JavaVirtualMachine>(
+    thread: &Jvm::ThreadReference,
+    location: fn(Jvm::Location) -> FrameLocation,
+) -> PyResult<Vec<PyStackFrame>> {
+    thread
+        .frames()
+        .map(|frame| {
+            Ok(PyStackFrame {
+                location: location(frame?.location()?),
+            })
+        })
+        .collect()
+}
+
+#[pyclass(unsendable, name = "StackFrame")]
+struct PyStackFrame {
+    location: FrameLocation,
+}
+
+#[pymethods]
+impl PyStackFrame {
+    #[getter]
+    fn class_name(&self) -> PyResult<String> {
+        Ok(either!(&self.location, FrameLocation, location => {
+            location.declaring_type().and_then(|class| class.name())
+        })?)
+    }
+
+    #[getter]
+    fn method_name(&self) -> PyResult<String> {
+        Ok(either!(&self.location, FrameLocation, location => {
+            location.method().and_then(|method| method.name())
+        })?)
+    }
+
+    #[getter]
+    fn line_number(&self) -> PyResult<Option<u32>> {
+        Ok(either!(&self.location, FrameLocation, location => location.line_number())?)
+    }
+
+    // In the same format as report::write_stack_trace()
+    fn __str__(&self) -> PyResult<String> {
+        let line = match self.line_number()? {
+            Some(n) => format!(":{}", n),
+            None => String::new(),
+        };
+        Ok(format!(
+            "{}.{}({})",
+            self.class_name()?,
+            self.method_name()?,
+            line
+        ))
+    }
+}
+
+#[pyclass(unsendable, name = "MethodTrace")]
+struct PyMethodTrace {
+    trace: MethodTrace,
+}
+
+#[pymethods]
+impl PyMethodTrace {
+    // Wait up to timeout seconds (or forever) for the next traced call. Returns None on timeout,
+    // or once the VM has exited.
+    #[pyo3(signature = (timeout=None))]
+    fn next_event(
+        &mut self,
+        py: Python<'_>,
+        timeout: Option<f64>,
+    ) -> PyResult<Option<PyMethodTraceEvent>> {
+        // Negative, NaN or too large for a Duration
+        let timeout = timeout
+            .map(Duration::try_from_secs_f64)
+            .transpose()
+            .map_err(|e| PyValueError::new_err(format!("Invalid timeout: {}", e)))?;
+        let event = match self.trace.next_event(timeout)? {
+            Some(e) => e,
+            None => return Ok(None),
+        };
+        let kind = match event.kind {
+            MethodTraceKind::Entry => "entry",
+            MethodTraceKind::Exit => "exit",
+        };
+        let return_value = match &event.return_value {
+            Some(value) => value_to_py(py, value)?,
+            None => py.None(),
+        };
+        Ok(Some(PyMethodTraceEvent {
+            kind,
+            method_name: event.method.name()?,
+            thread: Py::new(
+                py,
+                PyThread {
+                    thread: Thread::Live(event.thread),
+                },
+            )?,
+            return_value,
+        }))
+    }
+
+    #[getter]
+    fn dropped(&self) -> u64 {
+        self.trace.dropped()
+    }
+
+    fn __iter__(slf: PyRef<'_, Self>) -> PyRef<'_, Self> {
+        slf
+    }
+
+    fn __next__(&mut self, py: Python<'_>) -> PyResult<Option<PyMethodTraceEvent>> {
+        self.next_event(py, None)
+    }
+}
+
+#[pyclass(unsendable, name = "MethodTraceEvent")]
+struct PyMethodTraceEvent {
+    // "entry" or "exit"
+    #[pyo3(get)]
+    kind: &'static str,
+    #[pyo3(get)]
+    method_name: String,
+    #[pyo3(get)]
+    thread: Py<PyThread>,
+    // None for entries, and if the target doesn't report return values
+    #[pyo3(get)]
+    return_value: Py<PyAny>,
+}
+
+fn value_to_py(py: Python<'_>, value: &Value) -> PyResult<Py<PyAny>> {
+    match value {
+        Value::Boolean(b) => b.into_py_any(py),
+        Value::Byte(b) => b.into_py_any(py),
+        Value::Char(c) => String::from_utf16_lossy(&[*c]).into_py_any(py),
+        Value::Short(s) => s.into_py_any(py),
+        Value::Integer(i) => i.into_py_any(py),
+        Value::Long(l) => l.into_py_any(py),
+        Value::Float(f) => f.into_py_any(py),
+        Value::Double(d) => d.into_py_any(py),
+        Value::Object(id) | Value::ClassObject(id) => id.into_py_any(py),
+        Value::Null | Value::Void => Ok(py.None()),
+    }
+}
@@ -0,0 +1,499 @@
+//
+// Human readable output of the things we can capture from a JVM. The thread dump and histogram
+// writers, which work on any backend, come from libjdb_model::report.
+//
+
+use std::io::{Result, Write};
+
+use crate::annotation::Annotations;
+use crate::compare::LiveComparison;
+use crate::hprof::{
+    ClassLoaderReport, ClassOverhead, CollectionWasteReport, DirectBufferReport, DominatorRow,
+    FinalizationReport, HeapTimeline, OverheadReport, ThreadLocalReport, ValidationReport,
+};
+
+#[cfg(feature = "html")]
+mod html;
+
+#[cfg(feature = "html")]
+pub use html::HtmlReport;
+pub use libjdb_model::report::*;
+
+// The 'limit' classes which have grown the most since the dump (or all that have grown)
+pub fn write_live_comparison<W: Write + ?Sized>(
+    comparison: &LiveComparison,
+    limit: Option<usize>,
+    out: &mut W,
+) -> Result<()> {
+    writeln!(out, "      in dump         now       growth  class name")?;
+    writeln!(out, "--------------------------------------------------")?;
+    let growing: Vec<_> = comparison.growing().collect();
+    let limit = limit.unwrap_or(growing.len());
+    for class in growing.iter().take(limit) {
+        writeln!(
+            out,
+            "{:>13} {:>11} {:>+12}  {}",
+            class.dump_instances,
+            class.live_instances,
+            class.growth(),
+            class.class_name
+        )?;
+    }
+    if growing.is_empty() {
+        writeln!(out, "No class has more instances than in the dump")?;
+    }
+    Ok(())
+}
+
+// The values held by ThreadLocals, totalled up by class, then the 'limit' largest entries (or all
+// of them). Stale entries, whose ThreadLocal has been collected, are the likely leaks.
+pub fn write_thread_local_report<W: Write + ?Sized>(
+    report: &ThreadLocalReport,
+    limit: Option<usize>,
+    out: &mut W,
+) -> Result<()> {
+    write_thread_local_report_annotated(report, limit, &Annotations::new(), out)
+}
+
+// Like write_thread_local_report(), with the labels in 'annotations'. Here and in the other heap
+// dump reports, labelled objects and threads have their labels after them.
+pub fn write_thread_local_report_annotated<W: Write + ?Sized>(
+    report: &ThreadLocalReport,
+    limit: Option<usize>,
+    annotations: &Annotations,
+    out: &mut W,
+) -> Result<()> {
+    writeln!(out, "    #entries     #stale      #retained  value class")?;
+    writeln!(
+        out,
+        "-------------------------------------------------------"
+    )?;
+    for class in report.by_value_class() {
+        writeln!(
+            out,
+            "{:>12} {:>10} {:>14}  {}",
+            class.entries, class.stale_entries, class.retained_bytes, class.value_class
+        )?;
+    }
+    writeln!(out)?;
+    let limit = limit.unwrap_or(report.entries.len());
+    for entry in report.entries.iter().take(limit) {
+        let thread_local = match (&entry.thread_local, &entry.thread_local_class) {
+            (None, _) => "STALE (ThreadLocal collected)".to_string(),
+            (Some(id), Some(class)) => format!("{}@{:x}", class, id),
+            (Some(id), None) => format!("{:x}", id),
+        };
+        let loader = match &entry.value_class_loader {
+            Some(loader) => format!(", loaded by {}", loader),
+            None => String::new(),
+        };
+        writeln!(
+            out,
+            "{:>14}  {}@{:x}{}{} in thread \"{}\"{} via {}{}",
+            entry.retained_bytes,
+            entry.value_class,
+            entry.value,
+            annotations.object_suffix(entry.value),
+            loader,
+            entry.thread_name,
+            annotations.thread_suffix(&entry.thread_name),
+            thread_local,
+            if entry.inheritable {
+                " (inheritable)"
+            } else {
+                ""
+            }
+        )?;
+    }
+    Ok(())
+}
+
+// Every class loader, then the reference chains which keep the suspected leaks alive
+pub fn write_class_loader_report<W: Write + ?Sized>(
+    report: &ClassLoaderReport,
+    out: &mut W,
+) -> Result<()> {
+    write_class_loader_report_annotated(report, &Annotations::new(), out)
+}
+
+// Like write_class_loader_report(), with the labels in 'annotations'
+pub fn write_class_loader_report_annotated<W: Write + ?Sized>(
+    report: &ClassLoaderReport,
+    annotations: &Annotations,
+    out: &mut W,
+) -> Result<()> {
+    writeln!(
+        out,
+        "    #classes      #retained  held by      class loader"
+    )?;
+    writeln!(
+        out,
+        "--------------------------------------------------------"
+    )?;
+    for loader in &report.loaders {
+        let name = match loader.loader {
+            Some(id) => format!(
+                "{}@{:x}{}",
+                loader.loader_class,
+                id,
+                annotations.object_suffix(id)
+            ),
+            None => loader.loader_class.clone(),
+        };
+        let retained_by = match loader.loader {
+            Some(_) => format!("{:?}", loader.retained_by),
+            None => String::new(),
+        };
+        writeln!(
+            out,
+            "{:>12} {:>14}  {:<12} {}",
+            loader.classes.len(),
+            loader.retained_bytes,
+            retained_by,
+            name
+        )?;
+    }
+    for loader in report.leak_suspects() {
+        writeln!(
+            out,
+            "\nPossible leak: {}@{:x}{} ({} classes, {} bytes retained)",
+            loader.loader_class,
+            loader.loader.unwrap_or(0),
+            annotations.object_suffix(loader.loader.unwrap_or(0)),
+            loader.classes.len(),
+            loader.retained_bytes
+        )?;
+        let root_kind = match loader.root_kind {
+            Some(kind) => format!("{:?}", kind),
+            None => "unknown".to_string(),
+        };
+        for (i, step) in loader.reference_chain.iter().enumerate() {
+            if i == 0 {
+                writeln!(out, "   {} ({} root)", step, root_kind)?;
+            } else {
+                writeln!(out, "   {}{}", " ".repeat(i), step)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+// Wasted collection space by collection class and holder, then the 'limit' worst collections (or
+// all of them)
+pub fn write_collection_waste_report<W: Write + ?Sized>(
+    report: &CollectionWasteReport,
+    limit: Option<usize>,
+    out: &mut W,
+) -> Result<()> {
+    write_collection_waste_report_annotated(report, limit, &Annotations::new(), out)
+}
+
+// Like write_collection_waste_report(), with the labels in 'annotations'
+pub fn write_collection_waste_report_annotated<W: Write + ?Sized>(
+    report: &CollectionWasteReport,
+    limit: Option<usize>,
+    annotations: &Annotations,
+    out: &mut W,
+) -> Result<()> {
+    writeln!(
+        out,
+        "#collections     #empty        #wasted  collection class (holder)"
+    )?;
+    writeln!(
+        out,
+        "------------------------------------------------------------------"
+    )?;
+    for summary in report.by_class() {
+        writeln!(
+            out,
+            "{:>12} {:>10} {:>14}  {} ({})",
+            summary.collections,
+            summary.empty_collections,
+            summary.wasted_bytes,
+            summary.collection_class,
+            summary.holder_class.as_deref().unwrap_or("GC root")
+        )?;
+    }
+    writeln!(out, "Total {:>31}", report.total_wasted_bytes())?;
+    writeln!(out)?;
+    let limit = limit.unwrap_or(report.collections.len());
+    for c in report.collections.iter().take(limit) {
+        writeln!(
+            out,
+            "{:>14}  {}@{:x}{} with {} of {} slots used, held by {}",
+            c.wasted_bytes,
+            c.collection_class,
+            c.collection,
+            annotations.object_suffix(c.collection),
+            c.size,
+            c.capacity,
+            c.holder_class.as_deref().unwrap_or("GC root")
+        )?;
+    }
+    Ok(())
+}
+
+// Boxed primitives, tiny arrays, then the 'limit' classes (or all of them) with the most header
+// and padding overhead
+pub fn write_overhead_report<W: Write + ?Sized>(
+    report: &OverheadReport,
+    limit: Option<usize>,
+    out: &mut W,
+) -> Result<()> {
+    let sections: [(&str, &[ClassOverhead]); 3] = [
+        ("Boxed primitives (excluding cached values)", &report.boxed),
+        ("Tiny arrays", &report.tiny_arrays),
+        ("Header and padding overhead by class", &report.classes),
+    ];
+    for (title, classes) in sections {
+        writeln!(out, "{}:", title)?;
+        writeln!(
+            out,
+            "  #instances         #bytes      #overhead  class name"
+        )?;
+        writeln!(
+            out,
+            "---------------------------------------------------------"
+        )?;
+        for class in classes.iter().take(limit.unwrap_or(classes.len())) {
+            writeln!(
+                out,
+                "{:>12} {:>14} {:>14}  {}",
+                class.instances, class.shallow_bytes, class.overhead_bytes, class.class_name
+            )?;
+        }
+        writeln!(out)?;
+    }
+    writeln!(out, "Total overhead {:>14}", report.total_overhead_bytes())
+}
+
+// Objects waiting for a finalizer or cleaner, and what they're holding on to, by class
+pub fn write_finalization_report<W: Write + ?Sized>(
+    report: &FinalizationReport,
+    out: &mut W,
+) -> Result<()> {
+    writeln!(
+        out,
+        "   #pending         #bytes      #retained      #live  kind       class name"
+    )?;
+    writeln!(
+        out,
+        "-----------------------------------------------------------------------------"
+    )?;
+    for entry in &report.entries {
+        writeln!(
+            out,
+            "{:>10} {:>14} {:>14} {:>10}  {:<10} {}",
+            entry.pending,
+            entry.pending_shallow_bytes,
+            entry.pending_retained_bytes,
+            entry.live,
+            format!("{:?}", entry.kind),
+            entry.class_name
+        )?;
+    }
+    writeln!(
+        out,
+        "Total {:>4} pending, {} bytes retained",
+        report.pending(),
+        report.pending_retained_bytes()
+    )
+}
+
+// Native memory held by DirectByteBuffers by holder class and loader, then the 'limit' largest
+// buffers (or all of them)
+pub fn write_direct_buffer_report<W: Write + ?Sized>(
+    report: &DirectBufferReport,
+    limit: Option<usize>,
+    out: &mut W,
+) -> Result<()> {
+    write_direct_buffer_report_annotated(report, limit, &Annotations::new(), out)
+}
+
+// Like write_direct_buffer_report(), with the labels in 'annotations'
+pub fn write_direct_buffer_report_annotated<W: Write + ?Sized>(
+    report: &DirectBufferReport,
+    limit: Option<usize>,
+    annotations: &Annotations,
+    out: &mut W,
+) -> Result<()> {
+    writeln!(
+        out,
+        "  #buffers      #capacity  kind       holder class (class loader)"
+    )?;
+    writeln!(
+        out,
+        "------------------------------------------------------------------"
+    )?;
+    let holder = |class: Option<&str>, loader: Option<&str>, reachable: bool| match class {
+        Some(class) => format!("{} ({})", class, loader.unwrap_or("<bootstrap>")),
+        None if reachable => "GC root".to_string(),
+        None => "awaiting cleaner".to_string(),
+    };
+    for group in report.groups() {
+        writeln!(
+            out,
+            "{:>10} {:>14}  {:<10} {}",
+            group.buffers,
+            group.capacity,
+            format!("{:?}", group.kind),
+            holder(
+                group.holder_class.as_deref(),
+                group.holder_class_loader.as_deref(),
+                group.reachable
+            )
+        )?;
+    }
+    writeln!(
+        out,
+        "Total {:>19} (and {} slices and duplicates)",
+        report.total_capacity(),
+        report.views
+    )?;
+    writeln!(out)?;
+    let limit = limit.unwrap_or(report.entries.len());
+    for entry in report.entries.iter().take(limit) {
+        let held_by = match entry.holder {
+            Some(id) => format!(
+                "{}@{:x}{}",
+                entry.holder_class.as_deref().unwrap_or("<unknown>"),
+                id,
+                annotations.object_suffix(id)
+            ),
+            None => holder(None, None, entry.reachable),
+        };
+        writeln!(
+            out,
+            "{:>14}  {:?} buffer @{:x}{}, held by {}",
+            entry.capacity,
+            entry.kind,
+            entry.object,
+            annotations.object_suffix(entry.object),
+            held_by
+        )?;
+    }
+    Ok(())
+}
+
+// Whether a dump is intact, and if it isn't, how much of it can be used and what's wrong with it
+pub fn write_validation_report<W: Write + ?Sized>(
+    report: &ValidationReport,
+    out: &mut W,
+) -> Result<()> {
+    if report.is_intact() {
+        write!(out, "intact")?;
+    } else if report.is_recoverable() {
+        write!(
+            out,
+            "damaged, the first {} of {} bytes are usable",
+            report.valid_bytes, report.file_bytes
+        )?;
+    } else {
+        write!(out, "unusable")?;
+    }
+    writeln!(
+        out,
+        ": {} records, {} strings, {} classes, {} objects, {} GC roots",
+        report.records, report.strings, report.classes, report.objects, report.gc_roots
+    )?;
+    if report.unknown_records > 0 {
+        writeln!(
+            out,
+            "  {} records of unknown kinds, which are skipped",
+            report.unknown_records
+        )?;
+    }
+    for problem in &report.problems {
+        writeln!(out, "  at {}: {}", problem.offset, problem.message)?;
+    }
+    Ok(())
+}
+
+// The rows of a dominator tree (see DominatorTree::rows()) as CSV, for a treemap or icicle chart:
+// a header, then "id,parent,class,shallow,retained" for each object, with IDs in hex and an empty
+// parent for the tree's roots
+pub fn write_dominator_rows_csv<W: Write + ?Sized>(
+    rows: &[DominatorRow],
+    out: &mut W,
+) -> Result<()> {
+    writeln!(out, "id,parent,class,shallow,retained")?;
+    for row in rows {
+        let parent = row
+            .parent
+            .map(|parent| format!("{:x}", parent))
+            .unwrap_or_default();
+        writeln!(
+            out,
+            "{:x},{},{},{},{}",
+            row.object_id,
+            parent,
+            csv_field(&row.class_name),
+            row.shallow_bytes,
+            row.retained_bytes
+        )?;
+    }
+    Ok(())
+}
+
+// The dumps, the classes which grew in every one of them, then where the 'limit' largest objects
+// in the last dump (or all the suspects) came from. Times are since the first dump.
+pub fn write_heap_timeline<W: Write + ?Sized>(
+    timeline: &HeapTimeline,
+    limit: Option<usize>,
+    out: &mut W,
+) -> Result<()> {
+    let start = match timeline.dumps.first() {
+        Some(dump) => dump.time,
+        None => return Ok(()),
+    };
+    let since_start = |time: std::time::SystemTime| {
+        format!(
+            "+{}s",
+            time.duration_since(start).unwrap_or_default().as_secs()
+        )
+    };
+    writeln!(out, "Dumps:")?;
+    for (i, dump) in timeline.dumps.iter().enumerate() {
+        writeln!(
+            out,
+            "  #{:<3} {:>10} {:>12} objects {:>14} bytes",
+            i + 1,
+            since_start(dump.time),
+            dump.objects,
+            dump.shallow_bytes
+        )?;
+    }
+    writeln!(out, "\nClasses which grew in every dump (bytes in each):")?;
+    for class in timeline.steadily_growing() {
+        let bytes: Vec<String> = class.shallow_bytes.iter().map(u64::to_string).collect();
+        writeln!(out, "  {}: {}", class.class_name, bytes.join(" -> "))?;
+    }
+    writeln!(
+        out,
+        "\nLargest objects in the last dump (retained bytes in each):"
+    )?;
+    let limit = limit.unwrap_or(timeline.suspects.len());
+    for suspect in timeline.suspects.iter().take(limit) {
+        let bytes: Vec<String> = suspect
+            .retained_bytes
+            .iter()
+            .map(|bytes| match bytes {
+                Some(bytes) => bytes.to_string(),
+                None => "-".to_string(),
+            })
+            .collect();
+        let first_seen = match timeline.first_seen_time(suspect) {
+            Some(time) => since_start(time),
+            None => "?".to_string(),
+        };
+        writeln!(out, "  {}", suspect.path)?;
+        writeln!(
+            out,
+            "    first seen in dump #{} ({}): {}",
+            suspect.first_seen() + 1,
+            first_seen,
+            bytes.join(" -> ")
+        )?;
+    }
+    Ok(())
+}
@@ -0,0 +1,306 @@
+//
+// The same reports as a single self-contained HTML file (no scripts, stylesheets or images to go
+// missing), for sending to people who won't run anything to read them. Stacks and reference
+// chains are collapsible, so a dump of thousands of threads is still readable.
+//
+
+use std::fmt::Write as _;
+use std::io::{Result, Write};
+
+use crate::annotation::Annotations;
+use crate::hprof::{ClassLoaderReport, ThreadLocalReport};
+use crate::snapshot::{group_stacks, FrameInfo, Histogram, ThreadStack};
+
+const STYLE: &str = "
+body { font-family: sans-serif; margin: 2em; }
+h2 { border-bottom: 1px solid #ccc; }
+table { border-collapse: collapse; }
+th, td { padding: 2px 12px; text-align: left; }
+td.n, th.n { text-align: right; font-family: monospace; }
+tr:nth-child(even) { background: #f4f4f4; }
+summary { cursor: pointer; margin: 4px 0; }
+details details { margin-left: 1.5em; }
+.frames, .chain { font-family: monospace; }
+.note { color: #666; }
+";
+
+// Sections are added one at a time, and written out together
+pub struct HtmlReport {
+    title: String,
+    body: String,
+    annotations: Annotations,
+}
+
+impl HtmlReport {
+    pub fn new(title: &str) -> HtmlReport {
+        HtmlReport {
+            title: title.to_string(),
+            body: String::new(),
+            annotations: Annotations::new(),
+        }
+    }
+
+    // Labels for the objects and threads in the sections added after this, and links for the
+    // frames of their stacks
+    pub fn set_annotations(&mut self, annotations: &Annotations) {
+        self.annotations = annotations.clone();
+    }
+
+    // Threads with the same stack are shown once, biggest groups first
+    pub fn add_thread_dump(&mut self, stacks: &[ThreadStack]) {
+        let groups = group_stacks(stacks);
+        let annotations = &self.annotations;
+        let b = &mut self.body;
+        let _ = writeln!(
+            b,
+            "<h2>Threads</h2>\n<p class=\"note\">{} threads, {} distinct stacks</p>",
+            stacks.len(),
+            groups.len()
+        );
+        for group in groups {
+            let summary = match &group.threads[..] {
+                [(id, name)] => format!(
+                    "Thread {}: {}",
+                    id,
+                    escape(&format!("{}{}", name, annotations.thread_suffix(name)))
+                ),
+                threads => format!("{} threads with the same stack", threads.len()),
+            };
+            let top = group
+                .frames
+                .first()
+                .map(|f| format!(" &mdash; {}", escape(&frame_text(f))))
+                .unwrap_or_default();
+            let _ = writeln!(
+                b,
+                "<details><summary>{}{} <span class=\"note\">stack={:016x}</span></summary>",
+                summary,
+                top,
+                group.frames_hash()
+            );
+            if group.threads.len() > 1 {
+                let names: Vec<String> = group
+                    .threads
+                    .iter()
+                    .map(|(id, name)| {
+                        let name = format!("{}{}", name, annotations.thread_suffix(name));
+                        format!("{} ({})", escape(&name), id)
+                    })
+                    .collect();
+                let _ = writeln!(
+                    b,
+                    "<details><summary>Threads</summary><p>{}</p></details>",
+                    names.join(", ")
+                );
+            }
+            let _ = writeln!(b, "<div class=\"frames\">");
+            for frame in &group.frames {
+                let text = escape(&frame_text(frame));
+                let _ = match annotations.frame_link(frame) {
+                    Some(link) => writeln!(b, "<a href=\"{}\">{}</a><br>", escape(&link), text),
+                    None => writeln!(b, "{}<br>", text),
+                };
+            }
+            let _ = writeln!(b, "</div></details>");
+        }
+    }
+
+    // The largest 'limit' entries (or all of them)
+    pub fn add_histogram(&mut self, histogram: &Histogram, limit: Option<usize>) {
+        let b = &mut self.body;
+        let total_bytes = match histogram.total_bytes() {
+            Some(bytes) => bytes.to_string(),
+            None => "?".to_string(),
+        };
+        let _ = writeln!(
+            b,
+            "<h2>Class histogram</h2>\n<p class=\"note\">{} instances, {} bytes</p>",
+            histogram.total_instances(),
+            total_bytes
+        );
+        let _ = writeln!(
+            b,
+            "<table><tr><th class=\"n\">#</th><th class=\"n\">Instances</th>\
+             <th class=\"n\">Bytes</th><th>Class</th></tr>"
+        );
+        let limit = limit.unwrap_or(histogram.entries.len());
+        for (i, entry) in histogram.entries.iter().take(limit).enumerate() {
+            let bytes = match entry.shallow_bytes {
+                Some(bytes) => bytes.to_string(),
+                None => "?".to_string(),
+            };
+            let _ = writeln!(
+                b,
+                "<tr><td class=\"n\">{}</td><td class=\"n\">{}</td><td class=\"n\">{}</td>\
+                 <td>{}</td></tr>",
+                i + 1,
+                entry.instances,
+                bytes,
+                escape(&entry.class_name)
+            );
+        }
+        let _ = writeln!(b, "</table>");
+    }
+
+    // Every class loader, then the suspected leaks with the reference chains keeping them alive
+    pub fn add_class_loader_report(&mut self, report: &ClassLoaderReport) {
+        let annotations = &self.annotations;
+        let b = &mut self.body;
+        let _ = writeln!(b, "<h2>Class loaders</h2>");
+        let _ = writeln!(
+            b,
+            "<table><tr><th class=\"n\">Classes</th><th class=\"n\">Retained bytes</th>\
+             <th>Held by</th><th>Class loader</th></tr>"
+        );
+        for loader in &report.loaders {
+            let (name, retained_by) = match loader.loader {
+                Some(id) => (
+                    format!(
+                        "{}@{:x}{}",
+                        loader.loader_class,
+                        id,
+                        annotations.object_suffix(id)
+                    ),
+                    format!("{:?}", loader.retained_by),
+                ),
+                None => (loader.loader_class.clone(), String::new()),
+            };
+            let _ = writeln!(
+                b,
+                "<tr><td class=\"n\">{}</td><td class=\"n\">{}</td><td>{}</td><td>{}</td></tr>",
+                loader.classes.len(),
+                loader.retained_bytes,
+                retained_by,
+                escape(&name)
+            );
+        }
+        let _ = writeln!(b, "</table>");
+
+        let _ = writeln!(b, "<h2>Possible class loader leaks</h2>");
+        let mut any = false;
+        for loader in report.leak_suspects() {
+            any = true;
+            let _ = writeln!(
+                b,
+                "<details open><summary>{}@{:x}{} ({} classes, {} bytes retained)</summary>",
+                escape(&loader.loader_class),
+                loader.loader.unwrap_or(0),
+                escape(&annotations.object_suffix(loader.loader.unwrap_or(0))),
+                loader.classes.len(),
+                loader.retained_bytes
+            );
+            let root_kind = match loader.root_kind {
+                Some(kind) => format!("{:?}", kind),
+                None => "unknown".to_string(),
+            };
+            let mut chain = loader.reference_chain.clone();
+            if let Some(root) = chain.first_mut() {
+                *root = format!("{} ({} root)", root, root_kind);
+            }
+            write_chain(b, &chain);
+            let _ = writeln!(
+                b,
+                "<details><summary>Classes</summary><p class=\"chain\">{}</p></details>",
+                loader
+                    .classes
+                    .iter()
+                    .map(|c| escape(c))
+                    .collect::<Vec<_>>()
+                    .join("<br>")
+            );
+            let _ = writeln!(b, "</details>");
+        }
+        if !any {
+            let _ = writeln!(b, "<p class=\"note\">None found</p>");
+        }
+    }
+
+    // Values held by ThreadLocals, stale entries (whose ThreadLocal has been collected) first
+    pub fn add_thread_local_report(&mut self, report: &ThreadLocalReport) {
+        let annotations = &self.annotations;
+        let b = &mut self.body;
+        let _ = writeln!(b, "<h2>ThreadLocal values</h2>");
+        let _ = writeln!(
+            b,
+            "<table><tr><th class=\"n\">Entries</th><th class=\"n\">Stale</th>\
+             <th class=\"n\">Retained bytes</th><th>Value class</th></tr>"
+        );
+        for class in report.by_value_class() {
+            let _ = writeln!(
+                b,
+                "<tr><td class=\"n\">{}</td><td class=\"n\">{}</td><td class=\"n\">{}</td>\
+                 <td>{}</td></tr>",
+                class.entries,
+                class.stale_entries,
+                class.retained_bytes,
+                escape(&class.value_class)
+            );
+        }
+        let _ = writeln!(b, "</table>");
+        let stale: Vec<_> = report.stale_entries().collect();
+        if !stale.is_empty() {
+            let _ = writeln!(
+                b,
+                "<details open><summary>{} stale entries</summary><div class=\"chain\">",
+                stale.len()
+            );
+            for entry in stale {
+                let _ = writeln!(
+                    b,
+                    "{} bytes: {}@{:x}{} in thread &quot;{}&quot;{}<br>",
+                    entry.retained_bytes,
+                    escape(&entry.value_class),
+                    entry.value,
+                    escape(&annotations.object_suffix(entry.value)),
+                    escape(&entry.thread_name),
+                    escape(&annotations.thread_suffix(&entry.thread_name))
+                );
+            }
+            let _ = writeln!(b, "</div></details>");
+        }
+    }
+
+    pub fn write<W: Write + ?Sized>(&self, out: &mut W) -> Result<()> {
+        write!(
+            out,
+            "<!DOCTYPE html>\n<html><head><meta charset=\"utf-8\">\n<title>{title}</title>\n\
+             <style>{style}</style></head>\n<body>\n<h1>{title}</h1>\n{body}</body></html>\n",
+            title = escape(&self.title),
+            style = STYLE,
+            body = self.body
+        )
+    }
+}
+
+// Each step nested inside the one before, so the chain can be folded up from any point
+fn write_chain(b: &mut String, chain: &[String]) {
+    for step in chain {
+        let _ = write!(
+            b,
+            "<details open class=\"chain\"><summary>{}</summary>",
+            escape(step)
+        );
+    }
+    let _ = writeln!(b, "{}", "</details>".repeat(chain.len()));
+}
+
+fn frame_text(frame: &FrameInfo) -> String {
+    match frame.line_number {
+        Some(n) => format!("{}.{}(:{})", frame.class_name, frame.method_name, n),
+        None => format!("{}.{}()", frame.class_name, frame.method_name),
+    }
+}
+
+fn escape(s: &str) -> String {
+    let mut escaped = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '&' => escaped.push_str("&amp;"),
+            '"' => escaped.push_str("&quot;"),
+            _ => escaped.push(c),
+        }
+    }
+    escaped
+}
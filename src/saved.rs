@@ -0,0 +1,275 @@
+//
+// Saving snapshots to look at later, away from the target: capture thread dumps or a histogram on
+// the server the JVM runs on, copy the files off, and load them on a laptop to compare, group or
+// report on with the same library, long after the connection has gone. What can be saved is a
+// Snapshot: ThreadDump, Histogram, HistogramDiff, InstanceSeries, hprof::HeapTimeline (which is
+// the leak report) and hprof::RootPath.
+//
+// Snapshots are saved as JSON, without any whitespace. It's compact enough for thread dumps and
+// histograms, and can still be read by jq or a script. The snapshot is wrapped in
+//
+//   {"version":1,"kind":"thread_dump","data":{...}}
+//
+// so that loading one kind as another fails with a clear error rather than a confusing one about
+// missing fields, and so that the format can change later without older files being misread.
+// version goes up when a change means older code can't read the file.
+//
+
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Error, ErrorKind, Read, Result, Write};
+use std::path::Path;
+
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+
+use crate::hprof::{HeapTimeline, RootPath};
+use crate::report::{write_to, OutputSink};
+use crate::snapshot::{Histogram, HistogramDiff, InstanceSeries, ThreadDump};
+
+// The newest version this code writes and reads
+pub const VERSION: u32 = 1;
+
+// What can be saved, and what it's called in the file
+pub trait Snapshot: Serialize + DeserializeOwned {
+    const KIND: &'static str;
+}
+
+impl Snapshot for ThreadDump {
+    const KIND: &'static str = "thread_dump";
+}
+
+impl Snapshot for Histogram {
+    const KIND: &'static str = "histogram";
+}
+
+impl Snapshot for HistogramDiff {
+    const KIND: &'static str = "histogram_diff";
+}
+
+impl Snapshot for InstanceSeries {
+    const KIND: &'static str = "instance_series";
+}
+
+impl Snapshot for HeapTimeline {
+    const KIND: &'static str = "heap_timeline";
+}
+
+impl Snapshot for RootPath {
+    const KIND: &'static str = "root_path";
+}
+
+#[derive(Serialize)]
+struct Saving<'a, T> {
+    version: u32,
+    kind: &'static str,
+    data: &'a T,
+}
+
+// The data is only deserialized once the version and kind are known to be right
+#[derive(Deserialize)]
+struct Loading {
+    version: u32,
+    kind: String,
+    data: serde_json::Value,
+}
+
+pub fn save<T: Snapshot, W: Write>(snapshot: &T, out: W) -> Result<()> {
+    let saving = Saving {
+        version: VERSION,
+        kind: T::KIND,
+        data: snapshot,
+    };
+    serde_json::to_writer(out, &saving).map_err(Error::from)
+}
+
+// Fails with ErrorKind::InvalidData if 'input' isn't a saved T
+pub fn load<T: Snapshot, R: Read>(input: R) -> Result<T> {
+    let invalid = |message: String| Error::new(ErrorKind::InvalidData, message);
+    let loading: Loading = serde_json::from_reader(input)
+        .map_err(|e| invalid(format!("Not a saved snapshot: {}", e)))?;
+    if loading.version > VERSION {
+        return Err(invalid(format!(
+            "Saved by a newer version of libjdb (format {}, this reads up to {})",
+            loading.version, VERSION
+        )));
+    }
+    if loading.kind != T::KIND {
+        return Err(invalid(format!(
+            "A saved {}, not a {}",
+            loading.kind,
+            T::KIND
+        )));
+    }
+    T::deserialize(loading.data).map_err(|e| invalid(format!("Not a saved {}: {}", T::KIND, e)))
+}
+
+pub fn save_file<T: Snapshot, P: AsRef<Path>>(snapshot: &T, path: P) -> Result<()> {
+    let mut out = BufWriter::new(File::create(path)?);
+    save(snapshot, &mut out)?;
+    out.flush()
+}
+
+// Saves to the artifact called 'name' in 'sink', returning where it ended up
+pub fn save_to<T: Snapshot, S: OutputSink + ?Sized>(
+    snapshot: &T,
+    sink: &S,
+    name: &str,
+) -> Result<String> {
+    write_to(sink, name, |out| save(snapshot, out))
+}
+
+pub fn load_file<T: Snapshot, P: AsRef<Path>>(path: P) -> Result<T> {
+    load(BufReader::new(File::open(path)?))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, UNIX_EPOCH};
+
+    use super::{load, save};
+    use crate::hprof::{ClassCurve, HeapTimeline, SuspectHistory, TimelineDump};
+    use crate::hprof::{PathEdge, PathObject, Reference, RootKind, RootPath};
+    use crate::snapshot::{FrameInfo, Histogram, HistogramEntry, MonitorInfo, ThreadDump};
+    use crate::snapshot::{ThreadInfo, ThreadStack, ThreadState};
+
+    #[test]
+    fn thread_dump() {
+        let lock = MonitorInfo {
+            object: 0x42,
+            class_name: "com.example.Cache".to_string(),
+        };
+        let dump = ThreadDump::new(
+            UNIX_EPOCH + Duration::from_millis(1_700_000_000_123),
+            vec![ThreadInfo {
+                stack: ThreadStack {
+                    thread_id: 1,
+                    name: "worker-\u{e9}".to_string(),
+                    is_virtual: false,
+                    frames: vec![FrameInfo {
+                        class_name: "com.example.Cache".to_string(),
+                        method_name: "load".to_string(),
+                        line_number: Some(10),
+                    }],
+                    omitted_frames: 2,
+                },
+                state: ThreadState::Monitor,
+                suspended: true,
+                owned_monitors: vec![],
+                contended_monitor: Some(lock),
+            }],
+            true,
+            Duration::from_millis(12),
+        );
+        let mut saved = vec![];
+        save(&dump, &mut saved).unwrap();
+        let loaded: ThreadDump = load(&saved[..]).unwrap();
+        assert_eq!(loaded, dump);
+        assert_eq!(
+            loaded.stacks()[0].frames_hash(),
+            dump.stacks()[0].frames_hash()
+        );
+    }
+
+    #[test]
+    fn histogram() {
+        let histogram = Histogram::new(vec![
+            HistogramEntry {
+                class_name: "byte[]".to_string(),
+                instances: 3,
+                shallow_bytes: Some(4096),
+            },
+            HistogramEntry {
+                class_name: "com.example.Session".to_string(),
+                instances: 9,
+                shallow_bytes: None,
+            },
+        ]);
+        let mut saved = vec![];
+        save(&histogram, &mut saved).unwrap();
+        assert_eq!(load::<Histogram, _>(&saved[..]).unwrap(), histogram);
+
+        let error = load::<ThreadDump, _>(&saved[..]).unwrap_err();
+        assert_eq!(error.kind(), std::io::ErrorKind::InvalidData);
+        assert_eq!(error.to_string(), "A saved histogram, not a thread_dump");
+    }
+
+    #[test]
+    fn envelope() {
+        let mut saved = vec![];
+        save(&Histogram::new(vec![]), &mut saved).unwrap();
+        assert_eq!(
+            String::from_utf8(saved).unwrap(),
+            r#"{"version":1,"kind":"histogram","data":{"entries":[]}}"#
+        );
+
+        let newer = r#"{"version":2,"kind":"histogram","data":{"entries":[]}}"#;
+        let error = load::<Histogram, _>(newer.as_bytes()).unwrap_err();
+        assert_eq!(error.kind(), std::io::ErrorKind::InvalidData);
+        assert!(error.to_string().contains("newer version"), "{}", error);
+
+        // From before there was an envelope
+        let error = load::<Histogram, _>(r#"{"entries":[]}"#.as_bytes()).unwrap_err();
+        assert_eq!(error.kind(), std::io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn heap_timeline() {
+        let timeline = HeapTimeline {
+            dumps: vec![
+                TimelineDump {
+                    time: UNIX_EPOCH + Duration::from_secs(1_700_000_000),
+                    objects: 1000,
+                    shallow_bytes: 64_000,
+                },
+                TimelineDump {
+                    time: UNIX_EPOCH + Duration::from_secs(1_700_003_600),
+                    objects: 1500,
+                    shallow_bytes: 96_000,
+                },
+            ],
+            classes: vec![ClassCurve {
+                class_name: "com.example.Session".to_string(),
+                instances: vec![10, 20],
+                shallow_bytes: vec![320, 640],
+            }],
+            suspects: vec![SuspectHistory {
+                path: "class com.example.Cache.sessions".to_string(),
+                class_name: "java.util.HashMap".to_string(),
+                retained_bytes: vec![None, Some(4096)],
+            }],
+        };
+        let mut saved = vec![];
+        save(&timeline, &mut saved).unwrap();
+        let loaded: HeapTimeline = load(&saved[..]).unwrap();
+        assert_eq!(loaded, timeline);
+        assert_eq!(loaded.steadily_growing().count(), 1);
+    }
+
+    #[test]
+    fn root_path() {
+        let object = |object_id, class_name: &str| PathObject {
+            object_id,
+            class_name: class_name.to_string(),
+            shallow_bytes: 16,
+            retained_bytes: Some(1024),
+        };
+        let path = RootPath {
+            root: object(0x10, "class com.example.Cache"),
+            root_kind: RootKind::StickyClass,
+            held_by: None,
+            edges: vec![
+                PathEdge {
+                    reference: Reference::StaticField("sessions".to_string()),
+                    to: object(0x20, "java.util.ArrayList"),
+                },
+                PathEdge {
+                    reference: Reference::ArrayElement(3),
+                    to: object(0x30, "com.example.Session"),
+                },
+            ],
+        };
+        let mut saved = vec![];
+        save(&path, &mut saved).unwrap();
+        assert_eq!(load::<RootPath, _>(&saved[..]).unwrap(), path);
+    }
+}
@@ -0,0 +1,264 @@
+//
+// Scripted diagnostics collection. A script is a JSON file listing the steps to run against a
+// target, so that the same data can be collected repeatably by people who don't want to write
+// Rust. For example:
+//
+//   {
+//     "steps": [
+//       { "op": "attach", "address": "myhost:8000" },
+//       { "op": "label_thread", "name": "worker-17", "label": "stuck" },
+//       { "op": "source_links", "template": "https://github.com/ex/app/blob/main/{file}#L{line}",
+//         "packages": ["com.example"] },
+//       { "op": "suspend" },
+//       { "op": "dump_stacks", "output": "stacks-{timestamp}.txt" },
+//       { "op": "dump_stacks", "group": true },
+//       { "op": "resume" },
+//       { "op": "histogram", "limit": 50 },
+//       { "op": "html_report", "output": "report-{timestamp}.html" },
+//       { "op": "detach" }
+//     ]
+//   }
+//
+// Steps which produce output write it to stdout, unless 'output' names a file ('-' also means
+// stdout). '{timestamp}' in a file name is replaced with the time the step ran, in seconds since
+// the epoch. Set 'append' to add to an existing file rather than replacing it. Set 'group' on
+// dump_stacks to write threads with the same stack once, as "N threads (...) at:", rather than
+// each thread with its state and monitors. Steps which capture stacks suspend the target while
+// they do, so they're consistent without a suspend step. Threads labelled by label_thread have
+// their labels after their names in the stacks of later steps, and once source_links has given a
+// template (see source_link.rs) the frames of later stacks are followed by links to their code.
+//
+// Files are written to the working directory unless the script is run with a sink of its own
+// (see Script::run_with_sink()), in which case each 'output' names an artifact in that sink.
+//
+
+use serde::Deserialize;
+use std::fs;
+use std::io::{Error, ErrorKind, Result};
+use std::path::Path;
+use std::thread;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::annotation::Annotations;
+use crate::jdwp::JdwpJavaVirtualMachine;
+use crate::model::JavaVirtualMachine;
+use crate::report::{self, DirectorySink, OutputSink, SinkWriter, StdoutSink};
+use crate::source_link::SourceLinks;
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Script {
+    steps: Vec<Step>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct Output {
+    output: Option<String>,
+    #[serde(default)]
+    append: bool,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(tag = "op", rename_all = "snake_case")]
+enum Step {
+    Attach {
+        address: String,
+    },
+    Suspend,
+    Resume,
+    LabelThread {
+        name: String,
+        label: String,
+    },
+    SourceLinks {
+        template: String,
+        #[serde(default)]
+        packages: Vec<String>,
+    },
+    DumpStacks {
+        #[serde(default)]
+        group: bool,
+        #[serde(flatten)]
+        output: Output,
+    },
+    Histogram {
+        limit: Option<usize>,
+        #[serde(flatten)]
+        output: Output,
+    },
+    // Stacks and a histogram as a single HTML file
+    HtmlReport {
+        title: Option<String>,
+        limit: Option<usize>,
+        #[serde(flatten)]
+        output: Output,
+    },
+    Sleep {
+        seconds: f64,
+    },
+    Detach,
+}
+
+pub fn run_script<P: AsRef<Path>>(path: P) -> Result<()> {
+    let text = fs::read_to_string(path)?;
+    Script::from_json(&text)?.run()
+}
+
+impl Script {
+    // Steps which can't be run fail here, rather than once the steps before them have run
+    pub fn from_json(text: &str) -> Result<Script> {
+        let script: Script = serde_json::from_str(text)
+            .map_err(|e| Error::new(ErrorKind::InvalidData, format!("Invalid script: {}", e)))?;
+        for (i, step) in script.steps.iter().enumerate() {
+            if let Step::Sleep { seconds } = step {
+                sleep_duration(*seconds).map_err(|e| {
+                    Error::new(e.kind(), format!("Invalid script: step {}: {}", i + 1, e))
+                })?;
+            }
+        }
+        Ok(script)
+    }
+
+    pub fn run(&self) -> Result<()> {
+        self.run_with_sink(&DirectorySink::new(""))
+    }
+
+    // Like run(), but with output which names a file going to 'sink' instead
+    pub fn run_with_sink(&self, sink: &dyn OutputSink) -> Result<()> {
+        let mut jvm: Option<JdwpJavaVirtualMachine> = None;
+        let mut annotations = Annotations::new();
+        for (i, step) in self.steps.iter().enumerate() {
+            run_step(step, &mut jvm, &mut annotations, sink).map_err(|e| {
+                Error::new(
+                    e.kind(),
+                    format!("Step {} ({:?}) failed: {}", i + 1, step, e),
+                )
+            })?;
+        }
+        Ok(())
+    }
+}
+
+fn run_step(
+    step: &Step,
+    jvm: &mut Option<JdwpJavaVirtualMachine>,
+    annotations: &mut Annotations,
+    sink: &dyn OutputSink,
+) -> Result<()> {
+    if let Step::Attach { address } = step {
+        if jvm.is_some() {
+            return Err(Error::other("Already attached"));
+        }
+        *jvm = Some(crate::attach_live(address.as_str())?);
+        return Ok(());
+    }
+    if let Step::LabelThread { name, label } = step {
+        annotations.label_thread(name, label);
+        return Ok(());
+    }
+    if let Step::SourceLinks { template, packages } = step {
+        annotations.set_source_links(Some(SourceLinks {
+            template: template.clone(),
+            packages: packages.clone(),
+        }));
+        return Ok(());
+    }
+    if let Step::Sleep { seconds } = step {
+        thread::sleep(sleep_duration(*seconds)?);
+        return Ok(());
+    }
+    if let Step::Detach = step {
+        return match jvm.take() {
+            Some(attached) => attached.dispose(),
+            None => Err(Error::new(ErrorKind::NotConnected, "Not attached to a JVM")),
+        };
+    }
+
+    let attached = jvm
+        .as_ref()
+        .ok_or_else(|| Error::new(ErrorKind::NotConnected, "Not attached to a JVM"))?;
+    match step {
+        Step::Suspend => attached.suspend(),
+        Step::Resume => attached.resume(),
+        Step::DumpStacks { group, output } => {
+            let dump = attached.capture_thread_dump()?;
+            let mut out = open_output(output, sink)?;
+            if *group {
+                report::write_stack_groups_annotated(&dump.stacks(), annotations, &mut out)?;
+            } else {
+                report::write_captured_thread_dump_annotated(&dump, annotations, &mut out)?;
+            }
+            out.finish().map(|_| ())
+        }
+        Step::Histogram { limit, output } => {
+            let histogram = attached.class_histogram()?;
+            let mut out = open_output(output, sink)?;
+            report::write_histogram(&histogram, *limit, &mut out)?;
+            out.finish().map(|_| ())
+        }
+        Step::HtmlReport {
+            title,
+            limit,
+            output,
+        } => {
+            let stacks = attached.capture_thread_dump()?.stacks();
+            let histogram = attached.class_histogram()?;
+            let mut html = report::HtmlReport::new(title.as_deref().unwrap_or("JVM report"));
+            html.set_annotations(annotations);
+            html.add_thread_dump(&stacks);
+            html.add_histogram(&histogram, *limit);
+            let mut out = open_output(output, sink)?;
+            html.write(&mut out)?;
+            out.finish().map(|_| ())
+        }
+        Step::Attach { .. }
+        | Step::LabelThread { .. }
+        | Step::SourceLinks { .. }
+        | Step::Sleep { .. }
+        | Step::Detach => unreachable!(),
+    }
+}
+
+// Negative, NaN and unimaginably long sleeps can't be done
+fn sleep_duration(seconds: f64) -> Result<Duration> {
+    Duration::try_from_secs_f64(seconds).map_err(|e| {
+        Error::new(
+            ErrorKind::InvalidData,
+            format!("Can't sleep for {} seconds: {}", seconds, e),
+        )
+    })
+}
+
+fn open_output(output: &Output, sink: &dyn OutputSink) -> Result<Box<dyn SinkWriter>> {
+    let (sink, name) = match output.output.as_deref() {
+        None | Some("-") => (&StdoutSink as &dyn OutputSink, "-"),
+        Some(name) => (sink, name),
+    };
+    let timestamp = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0);
+    let name = name.replace("{timestamp}", &timestamp.to_string());
+    if output.append {
+        sink.append(&name)
+    } else {
+        sink.create(&name)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn invalid_sleep() {
+        let script = r#"{ "steps": [ { "op": "suspend" }, { "op": "sleep", "seconds": -1 } ] }"#;
+        let err = Script::from_json(script).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidData);
+        assert!(err.to_string().contains("step 2"), "{}", err);
+        assert!(err.to_string().contains("-1 seconds"), "{}", err);
+
+        let script = r#"{ "steps": [ { "op": "sleep", "seconds": 0.5 } ] }"#;
+        assert!(Script::from_json(script).is_ok());
+    }
+}